@@ -5,7 +5,13 @@ use std::io::Write;
 use std::path::Path;
 
 fn main() {
-    let code = hsr_codegen::generate_from_yaml_file("petstore.yaml").expect("Generation failure");
+    // the proxy is exercised by `tests/proxy.rs`
+    let opts = hsr_codegen::GenerationOptions {
+        proxy: true,
+        ..Default::default()
+    };
+    let code = hsr_codegen::generate_from_yaml_file_with_options("petstore.yaml", &opts)
+        .expect("Generation failure");
 
     let out_dir = env::var("OUT_DIR").unwrap();
     let dest_path = Path::new(&out_dir).join("api.rs");
@@ -0,0 +1,41 @@
+//! The generated proxy, in front of an upstream which only answers authorized requests
+
+use hsr::actix_web::{web, App, HttpRequest, HttpResponse, HttpServer};
+use hsr::CallOptions;
+use petstore::api::{proxy, GetPet};
+use petstore::client::Client;
+
+async fn get_pet(req: HttpRequest, pet_id: web::Path<i64>) -> HttpResponse {
+    let authorized = req
+        .headers()
+        .get("authorization")
+        .map_or(false, |auth| auth == "Bearer secret");
+    if authorized {
+        HttpResponse::Ok().json(hsr::serde_json::json!({
+            "id": pet_id.into_inner(),
+            "name": "Rex",
+        }))
+    } else {
+        HttpResponse::NotFound().finish()
+    }
+}
+
+#[actix_rt::test]
+async fn forwards_end_to_end_headers() {
+    let upstream = hsr::examples::serve(|cfg| async move {
+        HttpServer::new(|| App::new().route("/pets/{pet_id}", web::get().to(get_pet)))
+            .bind(&cfg.bind_addrs()?[..])?
+            .run()
+            .await
+    });
+    let url = hsr::examples::serve(move |cfg| proxy::serve(upstream, cfg));
+    let client = Client::new(url);
+
+    let authorized = client.with(CallOptions::new().header("Authorization", "Bearer secret"));
+    match authorized.get_pet(1).await.unwrap() {
+        GetPet::Ok(pet) => assert_eq!((pet.id, pet.name.as_str()), (1, "Rex")),
+        other => panic!("unexpected response {:?}", other),
+    }
+
+    assert!(matches!(client.get_pet(1).await.unwrap(), GetPet::NotFound));
+}
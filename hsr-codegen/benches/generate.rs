@@ -0,0 +1,98 @@
+//! How long code generation takes for large specs.
+//!
+//! Compare with and without parallel generation:
+//!
+//! ```sh
+//! cargo bench -p hsr-codegen
+//! cargo bench -p hsr-codegen --features parallel
+//! ```
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+/// A spec with `n` paths, each with a path parameter, a query parameter and an
+/// inline request body, responding with one of `n` component schemas
+fn synthetic_spec(n: usize) -> String {
+    let mut spec =
+        String::from("openapi: 3.0.0\ninfo:\n  title: Synthetic\n  version: 0.1.0\npaths:\n");
+    for ix in 0..n {
+        spec.push_str(&format!(
+            r#"  /things{ix}/{{id}}:
+    put:
+      operationId: put_thing{ix}
+      parameters:
+        - name: id
+          in: path
+          required: true
+          schema:
+            type: integer
+        - name: verbose
+          in: query
+          schema:
+            type: boolean
+      requestBody:
+        required: true
+        content:
+          application/json:
+            schema:
+              type: object
+              required: [name]
+              properties:
+                name:
+                  type: string
+                tags:
+                  type: array
+                  items:
+                    type: string
+      responses:
+        '200':
+          description: Ok
+          content:
+            application/json:
+              schema:
+                $ref: '#/components/schemas/Thing{ix}'
+        '404':
+          description: Not found
+"#,
+            ix = ix
+        ));
+    }
+    spec.push_str("components:\n  schemas:\n");
+    for ix in 0..n {
+        spec.push_str(&format!(
+            r#"    Thing{ix}:
+      type: object
+      required: [id]
+      properties:
+        id:
+          type: integer
+        kind:
+          type: string
+          enum: [small, large]
+        parts:
+          type: array
+          items:
+            type: object
+            properties:
+              weight:
+                type: number
+"#,
+            ix = ix
+        ));
+    }
+    spec
+}
+
+fn generate(c: &mut Criterion) {
+    let mut group = c.benchmark_group("generate");
+    group.sample_size(10);
+    for n in [10, 100, 500].iter() {
+        let spec = synthetic_spec(*n);
+        group.bench_with_input(BenchmarkId::from_parameter(n), &spec, |b, spec| {
+            b.iter(|| hsr_codegen::generate_from_yaml_source(spec.as_bytes()).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, generate);
+criterion_main!(benches);
@@ -0,0 +1,587 @@
+//! Report the parts of a spec which the generated code does not honour.
+//!
+//! The model hsr builds from a spec (see `ir`) is written back out as an OpenAPI
+//! document, holding only what the generated code actually implements. Anything
+//! in the original spec which is missing from the re-emitted one (or emitted
+//! differently) was dropped along the way: keywords which aren't validated, like
+//! `format` or `pattern`, schemas taken to be any JSON, links given by
+//! `operationRef`, and so on.
+//!
+//! Only the paths and the component schemas are compared. References to other
+//! components (parameters, request bodies, responses...) are followed, as hsr
+//! copies them into each operation.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use openapiv3::ReferenceOr;
+use serde_json::{json, Map as JsonMap, Value};
+
+use crate::ir::{
+    analyse, ApiModel, FieldMetadata, Primitive, Representation, Response, Route, Type, TypeInner,
+    TypePath,
+};
+use crate::yaml::locate_error;
+use crate::{escape_pointer_segment, parse_openapi, Result};
+
+/// References to other components are followed no deeper than this (they may be recursive)
+const MAX_DEPTH: usize = 16;
+
+/// A part of a spec which the generated code does not honour
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dropped {
+    /// Where the part is in the spec, as a JSON pointer
+    pub pointer: String,
+    /// The part, as written in the spec
+    pub value: Value,
+    /// What the generated code honours instead, if anything
+    pub generated: Option<Value>,
+}
+
+impl fmt::Display for Dropped {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.generated {
+            Some(generated) => write!(
+                f,
+                "{}: {} is generated as {}",
+                self.pointer, self.value, generated
+            ),
+            None => write!(f, "{}: {} is ignored", self.pointer, self.value),
+        }
+    }
+}
+
+/// Report the parts of a spec file which the generated code does not honour
+pub fn audit_yaml_file(yaml: impl AsRef<Path>) -> Result<Vec<Dropped>> {
+    let f = fs::File::open(yaml)?;
+    audit_yaml_source(f)
+}
+
+/// Report the parts of a spec which the generated code does not honour. Schemas
+/// using features hsr can't model are taken to be any JSON (as when generating
+/// with `GenerationOptions::lenient`), so they are reported rather than failing.
+pub fn audit_yaml_source(mut yaml: impl std::io::Read) -> Result<Vec<Dropped>> {
+    let mut source = String::new();
+    yaml.read_to_string(&mut source)?;
+    let (api, raw) = parse_openapi(&source)?;
+    let model = analyse(&api, &raw, true).map_err(|e| locate_error(e, &source))?;
+    let emitted = emit_spec(&model);
+    let mut dropped = Vec::new();
+    for section in &["/paths", "/components/schemas"] {
+        if let Some(original) = raw.pointer(section) {
+            let original = inline_components(original, &raw, 0);
+            compare(&original, emitted.pointer(section), section, &mut dropped);
+        }
+    }
+    Ok(dropped)
+}
+
+/// Write a model back out as an OpenAPI document, holding only what the
+/// generated code honours
+pub fn emit_spec(model: &ApiModel) -> Value {
+    let emitter = Emitter { model };
+    let paths: JsonMap<_, _> = model
+        .routes
+        .iter()
+        .map(|(path, routes)| {
+            let item: JsonMap<_, _> = routes
+                .iter()
+                .map(|route| {
+                    let method = route.method().to_string().to_lowercase();
+                    (method, emitter.operation(route))
+                })
+                .collect();
+            (path.clone(), Value::Object(item))
+        })
+        .collect();
+    let mut schemas = JsonMap::new();
+    for (type_path, typ) in &model.types {
+        let name = match type_path.segments() {
+            [components, schemas, name] if components == "components" && schemas == "schemas" => {
+                name
+            }
+            _ => continue,
+        };
+        let mut schema = emitter.schema_of(typ);
+        if let (Some(extensions), Value::Object(schema)) =
+            (model.schema_extensions.get(name), &mut schema)
+        {
+            schema.extend(extensions.clone());
+        }
+        schemas.insert(name.clone(), schema);
+    }
+    json!({
+        "openapi": "3.0.3",
+        "info": model.info,
+        "paths": paths,
+        "components": { "schemas": schemas },
+    })
+}
+
+struct Emitter<'a> {
+    model: &'a ApiModel,
+}
+
+impl<'a> Emitter<'a> {
+    fn schema_at(&self, type_path: &TypePath) -> Value {
+        self.model
+            .types
+            .get(type_path)
+            .map(|typ| self.schema_of(typ))
+            .unwrap_or_else(|| json!({}))
+    }
+
+    fn schema_of(&self, typ: &ReferenceOr<Type>) -> Value {
+        match typ {
+            ReferenceOr::Reference { reference } => json!({ "$ref": reference }),
+            ReferenceOr::Item(typ) => self.schema(typ),
+        }
+    }
+
+    fn schema(&self, typ: &Type) -> Value {
+        let mut schema = JsonMap::new();
+        let mut set = |key: &str, value: Value| {
+            schema.insert(key.to_string(), value);
+        };
+        let meta = typ.meta();
+        if let Some(title) = meta.title() {
+            set("title", json!(title));
+        }
+        if let Some(description) = meta.description() {
+            set("description", json!(description));
+        }
+        if meta.nullable() {
+            set("nullable", json!(true));
+        }
+        // a nullable enum may also list `null` as a value
+        let null = if meta.nullable() {
+            vec![Value::Null]
+        } else {
+            vec![]
+        };
+        match typ.inner() {
+            TypeInner::Primitive(Primitive::String) => set("type", json!("string")),
+            TypeInner::Primitive(Primitive::I64(bounds)) => {
+                set("type", json!("integer"));
+                if let Some(min) = bounds.min {
+                    set("minimum", json!(min));
+                }
+                if let Some(max) = bounds.max {
+                    set("maximum", json!(max));
+                }
+            }
+            TypeInner::Primitive(Primitive::F64(bounds)) => {
+                set("type", json!("number"));
+                if let Some(min) = bounds.min {
+                    set("minimum", json!(min));
+                }
+                if let Some(max) = bounds.max {
+                    set("maximum", json!(max));
+                }
+            }
+            TypeInner::Primitive(Primitive::Bool) => set("type", json!("boolean")),
+            TypeInner::StringEnum(values) => {
+                set("type", json!("string"));
+                let values = values.iter().map(|v| json!(v));
+                set("enum", Value::Array(values.chain(null).collect()));
+            }
+            TypeInner::IntegerEnum(values) => {
+                set("type", json!("integer"));
+                let values = values.iter().map(|v| json!(v));
+                set("enum", Value::Array(values.chain(null).collect()));
+            }
+            TypeInner::Array(items) => {
+                set("type", json!("array"));
+                set("items", self.schema_of(items));
+            }
+            TypeInner::Map(values) => {
+                set("type", json!("object"));
+                set("additionalProperties", self.schema_of(values));
+            }
+            TypeInner::Any | TypeInner::Exotic(_) | TypeInner::Content(_) => {}
+            TypeInner::AllOf(parts) => {
+                let parts = parts.iter().map(|part| self.schema_of(part));
+                set("allOf", Value::Array(parts.collect()));
+            }
+            TypeInner::OneOf(variants) => {
+                let variants = variants.iter().map(|path| self.schema_at(path));
+                set("oneOf", Value::Array(variants.collect()));
+            }
+            TypeInner::AnyOf(alternatives) => {
+                let alternatives = alternatives.iter().map(|path| self.schema_at(path));
+                set("anyOf", Value::Array(alternatives.collect()));
+            }
+            TypeInner::Struct(strukt) => {
+                set("type", json!("object"));
+                let properties: JsonMap<_, _> = strukt
+                    .fields()
+                    .iter()
+                    .map(|(name, (_, type_path))| (name.to_string(), self.schema_at(type_path)))
+                    .collect();
+                set("properties", Value::Object(properties));
+                let required: Vec<_> = strukt
+                    .fields()
+                    .iter()
+                    .filter(|(_, (meta, _))| meta.required())
+                    .map(|(name, _)| json!(name.to_string()))
+                    .collect();
+                if !required.is_empty() {
+                    set("required", Value::Array(required));
+                }
+            }
+        }
+        Value::Object(schema)
+    }
+
+    fn operation(&self, route: &Route) -> Value {
+        let mut op = JsonMap::new();
+        let mut set = |key: &str, value: Value| {
+            op.insert(key.to_string(), value);
+        };
+        set("operationId", json!(route.operation_id().to_string()));
+        if let Some(summary) = route.summary() {
+            set("summary", json!(summary));
+        }
+        if let Some(description) = route.description() {
+            set("description", json!(description));
+        }
+        if !route.tags().is_empty() {
+            set("tags", json!(route.tags()));
+        }
+        if route.is_deprecated() {
+            set("deprecated", json!(true));
+        }
+        let mut parameters = Vec::new();
+        if let Some(params) = route.path_params() {
+            parameters.extend(params.values().map(|param| self.parameter("path", param)));
+        }
+        if let Some(params) = route.query_params() {
+            parameters.extend(params.values().map(|param| self.parameter("query", param)));
+        }
+        if !parameters.is_empty() {
+            set("parameters", Value::Array(parameters));
+        }
+        if let Some(body) = route.method().body_type() {
+            let content = self.content(body, route.body_media_types());
+            // a body is always extracted, so it is required even if the spec says not
+            set(
+                "requestBody",
+                json!({ "content": content, "required": true }),
+            );
+        }
+        let responses = route.responses();
+        let mut emitted: JsonMap<_, _> = responses
+            .with_codes
+            .iter()
+            .map(|(code, response)| (code.as_str().to_string(), self.response(response)))
+            .collect();
+        if let Some(default) = &responses.default {
+            emitted.insert("default".into(), self.response(default));
+        }
+        set("responses", Value::Object(emitted));
+        if !route.security().is_empty() {
+            set("security", json!(route.security()));
+        }
+        for (key, value) in route.extensions() {
+            set(key, value.clone());
+        }
+        Value::Object(op)
+    }
+
+    fn parameter(&self, location: &str, (meta, type_path): &(FieldMetadata, TypePath)) -> Value {
+        // the type of a parameter is defined under its name as written in the spec
+        let name = type_path.segments().last().cloned().unwrap_or_default();
+        let mut schema = self.schema_at(type_path);
+        if let (Some(default), Value::Object(schema)) = (meta.default_value(), &mut schema) {
+            schema.insert("default".into(), default.clone());
+        }
+        let mut param = json!({
+            "name": name,
+            "in": location,
+            "required": meta.required(),
+            "schema": schema,
+        });
+        if meta.allow_empty() {
+            param["allowEmptyValue"] = json!(true);
+        }
+        if meta.allow_reserved() {
+            param["allowReserved"] = json!(true);
+        }
+        match meta.delimiter() {
+            Some(' ') => param["style"] = json!("spaceDelimited"),
+            Some('|') => param["style"] = json!("pipeDelimited"),
+            _ => {}
+        }
+        param
+    }
+
+    fn content(&self, type_path: &TypePath, media_types: &[String]) -> Value {
+        if media_types.is_empty() {
+            return json!({ "application/json": { "schema": self.schema_at(type_path) } });
+        }
+        let representations = match self.model.types.get(type_path) {
+            Some(ReferenceOr::Item(typ)) => match typ.inner() {
+                TypeInner::Content(representations) => representations,
+                _ => return json!({}),
+            },
+            _ => return json!({}),
+        };
+        let content: JsonMap<_, _> = representations
+            .iter()
+            .map(|(media_type, repr)| {
+                let media = match repr {
+                    Representation::Json(path) | Representation::MergePatch(path) => {
+                        json!({ "schema": self.schema_at(path) })
+                    }
+                    Representation::Text => json!({ "schema": { "type": "string" } }),
+                    Representation::Binary => {
+                        json!({ "schema": { "type": "string", "format": "binary" } })
+                    }
+                    Representation::JsonPatch => json!({}),
+                };
+                (media_type.clone(), media)
+            })
+            .collect();
+        Value::Object(content)
+    }
+
+    fn response(&self, response: &Response) -> Value {
+        let mut emitted = json!({ "description": response.description });
+        if let Some(type_path) = &response.type_path {
+            emitted["content"] = self.content(type_path, &response.media_types);
+        }
+        if !response.links.is_empty() {
+            let links: JsonMap<_, _> = response
+                .links
+                .iter()
+                .map(|(name, link)| {
+                    let mut emitted = json!({ "parameters": link.parameters });
+                    if let Some(operation_id) = &link.operation_id {
+                        emitted["operationId"] = json!(operation_id.to_string());
+                    }
+                    if let Some(description) = &link.description {
+                        emitted["description"] = json!(description);
+                    }
+                    (name.clone(), emitted)
+                })
+                .collect();
+            emitted["links"] = Value::Object(links);
+        }
+        emitted
+    }
+}
+
+/// Copy part of the spec, replacing references to components other than
+/// schemas with what they refer to
+fn inline_components(node: &Value, root: &Value, depth: usize) -> Value {
+    match node {
+        Value::Object(obj) => {
+            if let Some(Value::String(reference)) = obj.get("$ref") {
+                let inlined = reference.starts_with("#/components/")
+                    && !reference.starts_with("#/components/schemas/");
+                if inlined && depth < MAX_DEPTH {
+                    if let Some(target) = root.pointer(&reference[1..]) {
+                        return inline_components(target, root, depth + 1);
+                    }
+                }
+            }
+            let obj = obj
+                .iter()
+                .map(|(key, value)| (key.clone(), inline_components(value, root, depth)))
+                .collect();
+            Value::Object(obj)
+        }
+        Value::Array(items) => Value::Array(
+            items
+                .iter()
+                .map(|item| inline_components(item, root, depth))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+/// Report the parts of `original` which are missing from, or different in, `emitted`.
+/// Whatever was added is fine: it is only made explicit.
+fn compare(original: &Value, emitted: Option<&Value>, pointer: &str, dropped: &mut Vec<Dropped>) {
+    let report = |dropped: &mut Vec<Dropped>, generated: Option<&Value>| {
+        dropped.push(Dropped {
+            pointer: pointer.to_string(),
+            value: original.clone(),
+            generated: generated.cloned(),
+        })
+    };
+    let child = |key: &str| format!("{}/{}", pointer, escape_pointer_segment(key));
+    let emitted = match emitted {
+        Some(emitted) => emitted,
+        // every boolean keyword of OpenAPI is false unless given
+        None if *original == Value::Bool(false) => return,
+        None => return report(dropped, None),
+    };
+    match (original, emitted) {
+        (Value::Object(original), Value::Object(emitted)) => {
+            for (key, value) in original {
+                match (key.as_str(), value, emitted.get(key)) {
+                    ("parameters", Value::Array(params), Some(Value::Array(emitted))) => {
+                        compare_parameters(params, emitted, &child(key), dropped)
+                    }
+                    _ => compare(value, emitted.get(key), &child(key), dropped),
+                }
+            }
+        }
+        // a list of values (like `enum` or `required`), in any order
+        (Value::Array(original), Value::Array(emitted)) if original.iter().all(is_scalar) => {
+            for (ix, value) in original.iter().enumerate() {
+                if !emitted.iter().any(|e| same_scalar(value, e)) {
+                    dropped.push(Dropped {
+                        pointer: child(&ix.to_string()),
+                        value: value.clone(),
+                        generated: None,
+                    })
+                }
+            }
+        }
+        (Value::Array(original), Value::Array(emitted)) => {
+            for (ix, value) in original.iter().enumerate() {
+                compare(value, emitted.get(ix), &child(&ix.to_string()), dropped)
+            }
+        }
+        (original, emitted) if is_scalar(original) && same_scalar(original, emitted) => {}
+        (_, emitted) => report(dropped, Some(emitted)),
+    }
+}
+
+/// Parameters are identified by their name and location, rather than their order
+fn compare_parameters(
+    original: &[Value],
+    emitted: &[Value],
+    pointer: &str,
+    dropped: &mut Vec<Dropped>,
+) {
+    for (ix, param) in original.iter().enumerate() {
+        let found = emitted
+            .iter()
+            .find(|e| e.get("name") == param.get("name") && e.get("in") == param.get("in"));
+        compare(param, found, &format!("{}/{}", pointer, ix), dropped)
+    }
+}
+
+fn is_scalar(value: &Value) -> bool {
+    !value.is_object() && !value.is_array()
+}
+
+/// Numbers are equal if they have the same value (`1` is `1.0`)
+fn same_scalar(left: &Value, right: &Value) -> bool {
+    match (left.as_f64(), right.as_f64()) {
+        (Some(left), Some(right)) => left == right,
+        _ => left == right,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPEC: &str = r##"
+openapi: "3.0.0"
+info: { title: Test, version: "1.0" }
+paths:
+  /pets:
+    get:
+      operationId: list_pets
+      summary: List the pets
+      parameters:
+        - $ref: "#/components/parameters/Limit"
+        - { in: query, name: tag, schema: { type: string, pattern: "^[a-z]+$" } }
+      responses:
+        "200":
+          description: Ok
+          content:
+            application/json:
+              schema:
+                type: array
+                items: { $ref: "#/components/schemas/Pet" }
+  /pets/{petId}:
+    get:
+      operationId: get_pet
+      parameters:
+        - { in: path, name: petId, required: true, schema: { type: integer } }
+      responses:
+        "200":
+          description: Ok
+          content:
+            application/json:
+              schema: { $ref: "#/components/schemas/Pet" }
+          links:
+            owner:
+              operationRef: "#/paths/~1owners~1{ownerId}/get"
+              parameters: { ownerId: "$response.body#/owner" }
+components:
+  parameters:
+    Limit:
+      in: query
+      name: limit
+      required: false
+      schema: { type: integer, minimum: 1, maximum: 100, default: 10 }
+  schemas:
+    Pet:
+      type: object
+      required: [name]
+      properties:
+        name: { type: string, minLength: 1 }
+        born: { type: string, format: date }
+        kind: { type: string, enum: [dog, cat, null], nullable: true }
+        weight: { type: number, minimum: 0.0 }
+        extra: { not: { type: string } }
+"##;
+
+    #[test]
+    fn test_audit() {
+        let dropped = audit_yaml_source(SPEC.as_bytes()).unwrap();
+        let dropped: Vec<String> = dropped.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            dropped,
+            vec![
+                "/paths/~1pets/get/parameters/1/schema/pattern: \"^[a-z]+$\" is ignored",
+                // taken to be any JSON, for the pattern
+                "/paths/~1pets/get/parameters/1/schema/type: \"string\" is ignored",
+                "/paths/~1pets~1{petId}/get/responses/200/links/owner/operationRef: \
+                 \"#/paths/~1owners~1{ownerId}/get\" is ignored",
+                "/components/schemas/Pet/properties/born/format: \"date\" is ignored",
+                "/components/schemas/Pet/properties/born/type: \"string\" is ignored",
+                "/components/schemas/Pet/properties/extra/not: {\"type\":\"string\"} is ignored",
+                "/components/schemas/Pet/properties/name/minLength: 1 is ignored",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_emit_spec() {
+        let (api, raw) = parse_openapi(SPEC).unwrap();
+        let model = analyse(&api, &raw, true).unwrap();
+        let spec = emit_spec(&model);
+        assert_eq!(spec["info"]["title"], "Test");
+        assert_eq!(
+            spec.pointer("/paths/~1pets/get/parameters/0").unwrap(),
+            &json!({
+                "name": "limit",
+                "in": "query",
+                "required": false,
+                "schema": { "type": "integer", "minimum": 1, "maximum": 100, "default": 10 },
+            })
+        );
+        assert_eq!(
+            spec.pointer("/components/schemas/Pet/properties/kind/enum")
+                .unwrap(),
+            &json!(["dog", "cat", null])
+        );
+        assert_eq!(
+            spec.pointer("/components/schemas/Pet/required").unwrap(),
+            &json!(["name"])
+        );
+        // the re-emitted spec has nothing more to drop
+        let yaml = serde_yaml::to_string(&spec).unwrap();
+        assert!(audit_yaml_source(yaml.as_bytes()).unwrap().is_empty());
+    }
+}
@@ -1,20 +1,8 @@
-use std::path::PathBuf;
+//! The CLI under its name before it was `hsr`, taking the same arguments
 
-use structopt::StructOpt;
-
-use hsr_codegen::generate_from_yaml_file;
-
-#[derive(Clone, Debug, StructOpt)]
-struct Args {
-    #[structopt(parse(from_os_str))]
-    spec: PathBuf,
-}
+#[path = "hsr.rs"]
+mod hsr;
 
 fn main() {
-    let args = Args::from_args();
-    println!("{:?}", args);
-
-    let gen = generate_from_yaml_file(args.spec).unwrap();
-
-    println!("{}", gen);
+    hsr::main()
 }
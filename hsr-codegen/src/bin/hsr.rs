@@ -0,0 +1,212 @@
+use std::path::PathBuf;
+
+use structopt::StructOpt;
+
+use hsr_codegen::{
+    audit_yaml_file, diff_yaml_files, generate_from_yaml_file,
+    generate_fuzz_targets_from_yaml_file, generate_versioned_from_yaml_files, write_if_changed,
+    GenerationOptions, Watcher,
+};
+
+#[derive(Clone, Debug, StructOpt)]
+#[structopt(name = "hsr")]
+enum Command {
+    /// Generate the API code from a spec, and print it (or write it to a file)
+    Generate {
+        #[structopt(parse(from_os_str))]
+        spec: PathBuf,
+        /// Write the code to this file, unless it is unchanged
+        #[structopt(long, short, parse(from_os_str))]
+        output: Option<PathBuf>,
+        /// Regenerate the code whenever the spec (or a file it refers to) changes
+        #[structopt(long, requires = "output")]
+        watch: bool,
+    },
+    /// Generate modules for several versions of an API, and print them
+    GenerateVersions {
+        /// Versions of the API, as `name=spec` (e.g. `v1=api-v1.yaml`)
+        #[structopt(required = true, parse(try_from_str = parse_version))]
+        versions: Vec<(String, PathBuf)>,
+    },
+    /// Serve canned example responses for every operation in a spec
+    Mock {
+        #[structopt(parse(from_os_str))]
+        spec: PathBuf,
+        /// The url to serve on
+        #[structopt(long, default_value = "http://127.0.0.1:8000")]
+        host: String,
+    },
+    /// Check that a running server conforms to a spec
+    Verify {
+        #[structopt(parse(from_os_str))]
+        spec: PathBuf,
+        /// The url of the server to check
+        #[structopt(long)]
+        base_url: String,
+    },
+    /// Report changes between two versions of a spec which would break existing clients
+    Diff {
+        #[structopt(parse(from_os_str))]
+        old: PathBuf,
+        #[structopt(parse(from_os_str))]
+        new: PathBuf,
+    },
+    /// Report the parts of a spec which the generated code does not honour
+    Audit {
+        #[structopt(parse(from_os_str))]
+        spec: PathBuf,
+    },
+    /// Write a `cargo fuzz` target for each operation, calling the `fuzz` module of
+    /// the code generated with `fuzz_targets`
+    FuzzTargets {
+        #[structopt(parse(from_os_str))]
+        spec: PathBuf,
+        /// The path of the generated code (e.g. `my_crate::api`)
+        #[structopt(long)]
+        api: String,
+        /// The directory to write the targets to
+        #[structopt(long, short, parse(from_os_str), default_value = "fuzz/fuzz_targets")]
+        output: PathBuf,
+    },
+}
+
+pub fn main() {
+    let cmd = match Command::from_iter_safe(std::env::args_os()) {
+        Ok(cmd) => cmd,
+        Err(e) => match legacy_args() {
+            Some(cmd) => cmd,
+            None => e.exit(),
+        },
+    };
+
+    match cmd {
+        Command::Generate {
+            spec,
+            output: Some(output),
+            watch: true,
+        } => {
+            Watcher::new(spec, &output, &GenerationOptions::default()).run(|result| match result {
+                Ok(true) => eprintln!("Regenerated {}", output.display()),
+                Ok(false) => eprintln!("{} is unchanged", output.display()),
+                Err(e) => eprintln!("Failed to generate code: {}", e),
+            })
+        }
+        Command::Generate { spec, output, .. } => {
+            let gen = generate_from_yaml_file(spec).unwrap();
+            match output {
+                Some(output) => {
+                    write_if_changed(output, &gen).unwrap();
+                }
+                None => println!("{}", gen),
+            }
+        }
+        Command::GenerateVersions { versions } => {
+            let versions: Vec<_> = versions
+                .iter()
+                .map(|(name, spec)| (name.as_str(), spec))
+                .collect();
+            let gen = generate_versioned_from_yaml_files(&versions, &GenerationOptions::default())
+                .unwrap();
+            println!("{}", gen);
+        }
+        Command::Mock { spec, host } => mock(spec, host),
+        Command::Verify { spec, base_url } => verify(spec, base_url),
+        Command::Diff { old, new } => {
+            let changes = diff_yaml_files(old, new).unwrap();
+            for change in &changes {
+                println!("{}", change);
+            }
+            println!("{} breaking changes", changes.len());
+            if !changes.is_empty() {
+                std::process::exit(1)
+            }
+        }
+        Command::Audit { spec } => {
+            let dropped = audit_yaml_file(spec).unwrap();
+            for item in &dropped {
+                println!("{}", item);
+            }
+            println!("{} parts of the spec are not honoured", dropped.len());
+            if !dropped.is_empty() {
+                std::process::exit(1)
+            }
+        }
+        Command::FuzzTargets { spec, api, output } => {
+            let targets = generate_fuzz_targets_from_yaml_file(spec, &api).unwrap();
+            std::fs::create_dir_all(&output).unwrap();
+            for (file_name, source) in &targets {
+                if write_if_changed(output.join(file_name), source).unwrap() {
+                    println!("Wrote {}", output.join(file_name).display());
+                }
+            }
+        }
+    }
+}
+
+/// Before there were subcommands the CLI only generated code, as `cli <spec>`.
+/// That form is still accepted, as `generate <spec>`.
+fn legacy_args() -> Option<Command> {
+    let args: Vec<_> = std::env::args_os().collect();
+    match &args[..] {
+        [_, spec] if !spec.to_string_lossy().starts_with('-') => Some(Command::Generate {
+            spec: spec.into(),
+            output: None,
+            watch: false,
+        }),
+        _ => None,
+    }
+}
+
+fn parse_version(arg: &str) -> Result<(String, PathBuf), String> {
+    let mut parts = arg.splitn(2, '=');
+    match (parts.next(), parts.next()) {
+        (Some(name), Some(spec)) if !name.is_empty() && !spec.is_empty() => {
+            Ok((name.to_string(), spec.into()))
+        }
+        _ => Err(format!("expected 'name=spec', got '{}'", arg)),
+    }
+}
+
+#[cfg(feature = "mock")]
+fn mock(spec: PathBuf, host: String) {
+    let f = std::fs::File::open(spec).unwrap();
+    let spec: serde_json::Value = serde_yaml::from_reader(f).unwrap();
+    let host: hsr::Url = host.parse().unwrap();
+    println!("Serving mock at '{}'", host);
+    let mut system = hsr::actix_rt::System::new("mock");
+    system
+        .block_on(hsr::mock::serve(spec, hsr::Config::with_host(host)))
+        .unwrap();
+}
+
+#[cfg(not(feature = "mock"))]
+fn mock(_spec: PathBuf, _host: String) {
+    eprintln!("hsr was built without the 'mock' feature");
+    std::process::exit(1)
+}
+
+#[cfg(feature = "verify")]
+fn verify(spec: PathBuf, base_url: String) {
+    let f = std::fs::File::open(spec).unwrap();
+    let spec: serde_json::Value = serde_yaml::from_reader(f).unwrap();
+    let base_url: hsr::Url = base_url.parse().unwrap();
+    let mut system = hsr::actix_rt::System::new("verify");
+    let reports = system.block_on(hsr::verify::verify(&spec, &base_url));
+    let mut failed = 0;
+    for report in &reports {
+        print!("{}", report);
+        if !report.passed() {
+            failed += 1
+        }
+    }
+    println!("{} operations, {} failed", reports.len(), failed);
+    if failed > 0 {
+        std::process::exit(1)
+    }
+}
+
+#[cfg(not(feature = "verify"))]
+fn verify(_spec: PathBuf, _base_url: String) {
+    eprintln!("hsr was built without the 'verify' feature");
+    std::process::exit(1)
+}
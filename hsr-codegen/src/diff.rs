@@ -0,0 +1,538 @@
+//! Compare two versions of a spec, and report changes which would break
+//! existing clients (or the code generated from the old spec).
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use openapiv3::ReferenceOr;
+
+use crate::ir::{
+    parse_spec, ApiModel, FieldMetadata, Ident, Primitive, Representation, Response, Route, Type,
+    TypeInner, TypeLookup, TypePath,
+};
+use crate::walk::{combine_types, lookup_type_recursive};
+use crate::{Map, Result};
+
+/// Types nested deeper than this are not compared (they are likely recursive)
+const MAX_DEPTH: usize = 16;
+
+/// A change between two versions of a spec which may break existing clients
+#[derive(Debug, Clone, PartialEq)]
+pub struct BreakingChange {
+    /// Where the change was found, e.g. `GET /pets/{petId}: response 200: /name`
+    pub location: String,
+    pub description: String,
+}
+
+impl fmt::Display for BreakingChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.description)
+    }
+}
+
+/// Which way data flows. Widening a type is fine for data the server receives,
+/// but breaks clients when it appears in data the server sends (and vice-versa).
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Direction {
+    Request,
+    Response,
+}
+
+/// Report the breaking changes between two spec files
+pub fn diff_yaml_files(
+    old: impl AsRef<Path>,
+    new: impl AsRef<Path>,
+) -> Result<Vec<BreakingChange>> {
+    let old = fs::File::open(old)?;
+    let new = fs::File::open(new)?;
+    diff_yaml_sources(old, new)
+}
+
+/// Report the breaking changes between two specs
+pub fn diff_yaml_sources(
+    old: impl std::io::Read,
+    new: impl std::io::Read,
+) -> Result<Vec<BreakingChange>> {
+    let old = parse_spec(old)?;
+    let new = parse_spec(new)?;
+    let mut differ = Differ {
+        old: &old,
+        new: &new,
+        changes: Vec::new(),
+    };
+    differ.diff_components();
+    differ.diff_routes();
+    Ok(differ.changes)
+}
+
+struct Differ<'a> {
+    old: &'a ApiModel,
+    new: &'a ApiModel,
+    changes: Vec<BreakingChange>,
+}
+
+impl<'a> Differ<'a> {
+    fn report(&mut self, location: &str, description: String) {
+        self.changes.push(BreakingChange {
+            location: location.to_string(),
+            description,
+        })
+    }
+
+    fn diff_components(&mut self) {
+        let (old_spec, new_spec) = (self.old, self.new);
+        let component = |path: &TypePath| path.0.len() == 3 && path.0[0] == "components";
+        for path in old_spec.types.keys().filter(|path| component(path)) {
+            if !new_spec.types.contains_key(path) {
+                let location = format!("#/components/schemas/{}", path.0[2]);
+                self.report(&location, "schema removed".into())
+            }
+        }
+    }
+
+    fn diff_routes(&mut self) {
+        let (old_spec, new_spec) = (self.old, self.new);
+        for (path, routes) in &old_spec.routes {
+            for old_route in routes {
+                let location = format!("{} {}", old_route.method(), path);
+                match new_spec.find_route(path, old_route.method()) {
+                    Some(new_route) => self.diff_route(&location, old_route, new_route),
+                    None => self.report(&location, "route removed".into()),
+                }
+            }
+        }
+    }
+
+    fn diff_route(&mut self, location: &str, old: &Route, new: &Route) {
+        if old.operation_id() != new.operation_id() {
+            self.report(
+                location,
+                format!(
+                    "operationId changed from '{}' to '{}'",
+                    old.operation_id(),
+                    new.operation_id()
+                ),
+            )
+        }
+        self.diff_params(
+            &format!("{}: path", location),
+            old.path_params(),
+            new.path_params(),
+        );
+        self.diff_params(
+            &format!("{}: query", location),
+            old.query_params(),
+            new.query_params(),
+        );
+
+        let body_location = format!("{}: request body", location);
+        match (old.method().body_type(), new.method().body_type()) {
+            (Some(old_ty), Some(new_ty)) => {
+                self.diff_type_paths(&body_location, old_ty, new_ty, Direction::Request)
+            }
+            (None, Some(_)) => self.report(&body_location, "request body added".into()),
+            _ => {}
+        }
+
+        match (old.websocket(), new.websocket()) {
+            (Some(old_ty), Some(new_ty)) => {
+                let ws_location = format!("{}: websocket message", location);
+                // messages flow both ways
+                self.diff_type_paths(&ws_location, old_ty, new_ty, Direction::Request);
+                self.diff_type_paths(&ws_location, old_ty, new_ty, Direction::Response);
+            }
+            (Some(_), None) => self.report(location, "no longer a websocket".into()),
+            (None, Some(_)) => self.report(location, "now a websocket".into()),
+            (None, None) => {}
+        }
+
+        let (old_resps, new_resps) = (old.responses(), new.responses());
+        for (code, old_resp) in &old_resps.with_codes {
+            let resp_location = format!("{}: response {}", location, code.as_u16());
+            match new_resps.with_codes.get(code) {
+                Some(new_resp) => self.diff_response(&resp_location, old_resp, new_resp),
+                None => self.report(&resp_location, "response removed".into()),
+            }
+        }
+        for code in new_resps.with_codes.keys() {
+            if !old_resps.with_codes.contains_key(code) {
+                let resp_location = format!("{}: response {}", location, code.as_u16());
+                self.report(&resp_location, "response added".into())
+            }
+        }
+        let resp_location = format!("{}: default response", location);
+        match (&old_resps.default, &new_resps.default) {
+            (Some(old_resp), Some(new_resp)) => {
+                self.diff_response(&resp_location, old_resp, new_resp)
+            }
+            (Some(_), None) => self.report(&resp_location, "response removed".into()),
+            (None, Some(_)) => self.report(&resp_location, "response added".into()),
+            (None, None) => {}
+        }
+    }
+
+    fn diff_params(
+        &mut self,
+        location: &str,
+        old: Option<&Map<Ident, (FieldMetadata, TypePath)>>,
+        new: Option<&Map<Ident, (FieldMetadata, TypePath)>>,
+    ) {
+        let empty = Map::new();
+        let (old, new) = (old.unwrap_or(&empty), new.unwrap_or(&empty));
+        for (name, (new_meta, new_ty)) in new {
+            let param_location = format!("{} parameter '{}'", location, name);
+            match old.get(name) {
+                Some((old_meta, old_ty)) => {
+                    if new_meta.required && !old_meta.required {
+                        self.report(&param_location, "parameter is now required".into())
+                    }
+                    self.diff_type_paths(&param_location, old_ty, new_ty, Direction::Request)
+                }
+                None if new_meta.required => {
+                    self.report(&param_location, "new required parameter".into())
+                }
+                None => {}
+            }
+        }
+    }
+
+    fn diff_response(&mut self, location: &str, old: &Response, new: &Response) {
+        match (&old.type_path, &new.type_path) {
+            (Some(old_ty), Some(new_ty)) => {
+                self.diff_type_paths(location, old_ty, new_ty, Direction::Response)
+            }
+            (Some(_), None) => self.report(location, "response body removed".into()),
+            (None, Some(_)) => self.report(location, "response body added".into()),
+            (None, None) => {}
+        }
+    }
+
+    fn diff_type_paths(&mut self, location: &str, old: &TypePath, new: &TypePath, dir: Direction) {
+        let (old_spec, new_spec) = (self.old, self.new);
+        if let (Some(old_ty), Some(new_ty)) = (old_spec.types.get(old), new_spec.types.get(new)) {
+            self.diff_types(location, "", old_ty, new_ty, dir, 0)
+        }
+    }
+
+    fn diff_types(
+        &mut self,
+        location: &str,
+        pointer: &str,
+        old: &ReferenceOr<Type>,
+        new: &ReferenceOr<Type>,
+        dir: Direction,
+        depth: usize,
+    ) {
+        if depth > MAX_DEPTH {
+            return;
+        }
+        let (old_spec, new_spec) = (self.old, self.new);
+        let (old, new) = match (
+            lookup_type_recursive(old, &old_spec.types),
+            lookup_type_recursive(new, &new_spec.types),
+        ) {
+            (Ok(old), Ok(new)) => (old, new),
+            _ => return,
+        };
+        let here = if pointer.is_empty() {
+            location.to_string()
+        } else {
+            format!("{}: {}", location, pointer)
+        };
+
+        match dir {
+            Direction::Request if old.meta.nullable && !new.meta.nullable => {
+                self.report(&here, "no longer nullable".into())
+            }
+            Direction::Response if !old.meta.nullable && new.meta.nullable => {
+                self.report(&here, "now nullable".into())
+            }
+            _ => {}
+        }
+
+        match (&old.typ, &new.typ) {
+            (TypeInner::Primitive(old_prim), TypeInner::Primitive(new_prim)) => {
+                if !same_primitive(old_prim, new_prim) {
+                    self.report(
+                        &here,
+                        format!("type changed from {} to {}", old_prim, new_prim),
+                    )
+                }
+            }
+            (TypeInner::StringEnum(old_vars), TypeInner::StringEnum(new_vars)) => {
+                let (from, to, what) = match dir {
+                    Direction::Request => (old_vars, new_vars, "removed"),
+                    Direction::Response => (new_vars, old_vars, "added"),
+                };
+                for var in from.iter().filter(|var| !to.contains(var)) {
+                    self.report(&here, format!("enum value '{}' {}", var, what))
+                }
+            }
+            (TypeInner::IntegerEnum(old_values), TypeInner::IntegerEnum(new_values)) => {
+                let (from, to, what) = match dir {
+                    Direction::Request => (old_values, new_values, "removed"),
+                    Direction::Response => (new_values, old_values, "added"),
+                };
+                for value in from.iter().filter(|value| !to.contains(value)) {
+                    self.report(&here, format!("enum value {} {}", value, what))
+                }
+            }
+            (TypeInner::Array(old_inner), TypeInner::Array(new_inner)) => {
+                let pointer = format!("{}/items", pointer);
+                self.diff_types(location, &pointer, old_inner, new_inner, dir, depth + 1)
+            }
+            (TypeInner::Map(old_inner), TypeInner::Map(new_inner)) => {
+                let pointer = format!("{}/additionalProperties", pointer);
+                self.diff_types(location, &pointer, old_inner, new_inner, dir, depth + 1)
+            }
+            (TypeInner::Any, TypeInner::Any) | (TypeInner::Exotic(_), TypeInner::Exotic(_)) => {}
+            (TypeInner::OneOf(old_vars), TypeInner::OneOf(new_vars))
+            | (TypeInner::AnyOf(old_vars), TypeInner::AnyOf(new_vars)) => {
+                let narrowed = match dir {
+                    Direction::Request => new_vars.len() < old_vars.len(),
+                    Direction::Response => new_vars.len() > old_vars.len(),
+                };
+                if narrowed {
+                    self.report(
+                        &here,
+                        format!(
+                            "number of {} variants changed from {} to {}",
+                            describe(&old.typ),
+                            old_vars.len(),
+                            new_vars.len()
+                        ),
+                    )
+                }
+            }
+            (TypeInner::Content(old_reprs), TypeInner::Content(new_reprs)) => {
+                for (media_type, old_repr) in old_reprs {
+                    match (old_repr, new_reprs.get(media_type)) {
+                        (_, None) => {
+                            self.report(&here, format!("media type '{}' removed", media_type))
+                        }
+                        (Representation::Json(old_ty), Some(Representation::Json(new_ty)))
+                        | (
+                            Representation::MergePatch(old_ty),
+                            Some(Representation::MergePatch(new_ty)),
+                        ) => {
+                            if let (Some(old_ty), Some(new_ty)) =
+                                (old_spec.types.get(old_ty), new_spec.types.get(new_ty))
+                            {
+                                let pointer = format!("{}/{}", pointer, media_type);
+                                self.diff_types(location, &pointer, old_ty, new_ty, dir, depth + 1)
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            (TypeInner::Struct(_), _)
+            | (TypeInner::AllOf(_), _)
+            | (_, TypeInner::Struct(_))
+            | (_, TypeInner::AllOf(_)) => match (
+                as_struct(&old.typ, &old_spec.types),
+                as_struct(&new.typ, &new_spec.types),
+            ) {
+                (Some(old_fields), Some(new_fields)) => {
+                    self.diff_fields(location, pointer, &old_fields, &new_fields, dir, depth)
+                }
+                _ => self.report(
+                    &here,
+                    format!(
+                        "type changed from {} to {}",
+                        describe(&old.typ),
+                        describe(&new.typ)
+                    ),
+                ),
+            },
+            (old_typ, new_typ) => self.report(
+                &here,
+                format!(
+                    "type changed from {} to {}",
+                    describe(old_typ),
+                    describe(new_typ)
+                ),
+            ),
+        }
+    }
+
+    fn diff_fields(
+        &mut self,
+        location: &str,
+        pointer: &str,
+        old: &Map<Ident, (FieldMetadata, TypePath)>,
+        new: &Map<Ident, (FieldMetadata, TypePath)>,
+        dir: Direction,
+        depth: usize,
+    ) {
+        let (old_spec, new_spec) = (self.old, self.new);
+        let here = if pointer.is_empty() {
+            location.to_string()
+        } else {
+            format!("{}: {}", location, pointer)
+        };
+        for (name, (old_meta, old_ty)) in old {
+            let field_pointer = format!("{}/{}", pointer, name);
+            match new.get(name) {
+                Some((new_meta, new_ty)) => {
+                    match dir {
+                        Direction::Request if new_meta.required && !old_meta.required => {
+                            self.report(&here, format!("field '{}' is now required", name))
+                        }
+                        Direction::Response if old_meta.required && !new_meta.required => {
+                            self.report(&here, format!("field '{}' is no longer required", name))
+                        }
+                        _ => {}
+                    }
+                    if let (Some(old_ty), Some(new_ty)) =
+                        (old_spec.types.get(old_ty), new_spec.types.get(new_ty))
+                    {
+                        self.diff_types(location, &field_pointer, old_ty, new_ty, dir, depth + 1)
+                    }
+                }
+                None if dir == Direction::Response && old_meta.required => {
+                    self.report(&here, format!("required field '{}' removed", name))
+                }
+                None => {}
+            }
+        }
+        if dir == Direction::Request {
+            for (name, (new_meta, _)) in new {
+                if new_meta.required && !old.contains_key(name) {
+                    self.report(&here, format!("new required field '{}'", name))
+                }
+            }
+        }
+    }
+}
+
+/// Primitives are the same if they have the same rust type (bounds are ignored)
+fn same_primitive(old: &Primitive, new: &Primitive) -> bool {
+    std::mem::discriminant(old) == std::mem::discriminant(new)
+}
+
+/// Get the fields of a struct-like type
+fn as_struct(
+    typ: &TypeInner,
+    lookup: &TypeLookup,
+) -> Option<Map<Ident, (FieldMetadata, TypePath)>> {
+    match typ {
+        TypeInner::Struct(strukt) => Some(strukt.fields.clone()),
+        TypeInner::AllOf(parts) => combine_types(parts, lookup, &TypePath::default())
+            .ok()
+            .map(|s| s.fields),
+        _ => None,
+    }
+}
+
+fn describe(typ: &TypeInner) -> String {
+    match typ {
+        TypeInner::Primitive(prim) => prim.to_string(),
+        TypeInner::StringEnum(_) => "enum".into(),
+        TypeInner::IntegerEnum(_) => "integer enum".into(),
+        TypeInner::Array(_) => "array".into(),
+        TypeInner::Map(_) => "map".into(),
+        TypeInner::Any | TypeInner::Exotic(_) => "any".into(),
+        TypeInner::AllOf(_) | TypeInner::Struct(_) => "object".into(),
+        TypeInner::OneOf(_) => "oneOf".into(),
+        TypeInner::AnyOf(_) => "anyOf".into(),
+        TypeInner::Content(_) => "content".into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OLD: &str = r##"
+openapi: "3.0.0"
+info: { title: Test, version: "1.0" }
+paths:
+  /pets:
+    get:
+      operationId: list_pets
+      responses:
+        "200":
+          description: Ok
+          content:
+            application/json:
+              schema:
+                type: array
+                items: { $ref: "#/components/schemas/Pet" }
+    post:
+      operationId: create_pet
+      requestBody:
+        content:
+          application/json:
+            schema: { $ref: "#/components/schemas/Pet" }
+      responses:
+        "201": { description: Created }
+  /pets/{petId}:
+    delete:
+      operationId: delete_pet
+      parameters:
+        - { in: path, name: petId, required: true, schema: { type: integer } }
+      responses:
+        "204": { description: Deleted }
+components:
+  schemas:
+    Pet:
+      type: object
+      required: [name]
+      properties:
+        name: { type: string }
+        kind: { type: string, enum: [dog, cat] }
+"##;
+
+    const NEW: &str = r##"
+openapi: "3.0.0"
+info: { title: Test, version: "2.0" }
+paths:
+  /pets:
+    get:
+      operationId: list_pets
+      responses:
+        "200":
+          description: Ok
+          content:
+            application/json:
+              schema:
+                type: array
+                items: { $ref: "#/components/schemas/Pet" }
+    post:
+      operationId: create_pet
+      requestBody:
+        content:
+          application/json:
+            schema: { $ref: "#/components/schemas/Pet" }
+      responses:
+        "201": { description: Created }
+components:
+  schemas:
+    Pet:
+      type: object
+      required: [name, age]
+      properties:
+        name: { type: string }
+        age: { type: integer }
+        kind: { type: string, enum: [dog] }
+"##;
+
+    #[test]
+    fn test_diff() {
+        let changes = diff_yaml_sources(OLD.as_bytes(), NEW.as_bytes()).unwrap();
+        let changes: Vec<String> = changes.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            changes,
+            vec![
+                "POST /pets: request body: /kind: enum value 'cat' removed",
+                "POST /pets: request body: new required field 'age'",
+                "DELETE /pets/{petId}: route removed",
+            ]
+        );
+        assert!(diff_yaml_sources(OLD.as_bytes(), OLD.as_bytes())
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,223 @@
+//! Example calls of the operations, put together from the `example`s and `examples`
+//! of the spec, and the tests which make them (see `GenerationOptions::example_tests`).
+//!
+//! Each example of a JSON response makes a call. A named example (in `examples`)
+//! takes the examples of the parameters and of the request body with the same
+//! name, or else their `example`. An `example` of a response is named after its
+//! status, and takes the `example`s of the parameters and of the request body.
+//! A call missing the value of a required parameter, or of the request body, is
+//! left out, as are the responses of `default` or a range of statuses.
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use serde_json::Value;
+
+use crate::route::Route;
+use crate::{Error, Map, Result};
+
+/// References are only followed this many times, in case of cycles
+const MAX_HOPS: usize = 8;
+
+/// An example call of an operation
+#[derive(Debug, Clone, PartialEq)]
+pub struct Example {
+    /// The name of the example, or the status of its response if it has none
+    pub name: String,
+    /// The values of the parameters which have one, by name
+    pub params: Map<String, Value>,
+    /// The request body, if the operation has one
+    pub body: Option<Value>,
+    /// The status of the response
+    pub status: u16,
+    /// The body of the response
+    pub response: Value,
+}
+
+/// Follow a local `$ref` (if any) to the node it points at
+fn resolve<'a>(node: &'a Value, raw: &'a Value) -> Option<&'a Value> {
+    let mut node = node;
+    for _ in 0..MAX_HOPS {
+        match node.get("$ref").and_then(Value::as_str) {
+            Some(reference) => node = raw.pointer(reference.trim_start_matches('#'))?,
+            None => return Some(node),
+        }
+    }
+    None
+}
+
+/// The value of the example called `name` of a parameter or media type, or
+/// else of its `example`
+fn example_value(node: &Value, name: Option<&str>, raw: &Value) -> Option<Value> {
+    let named = name
+        .and_then(|name| node.get("examples")?.get(name))
+        .and_then(|example| resolve(example, raw)?.get("value"));
+    named.or_else(|| node.get("example")).cloned()
+}
+
+/// The examples of the operation at `pointer` of the raw spec
+pub(crate) fn operation_examples(raw: &Value, pointer: &str) -> Vec<Example> {
+    let op = match raw.pointer(pointer) {
+        Some(op) => op,
+        None => return Vec::new(),
+    };
+    let params: Vec<&Value> = op
+        .get("parameters")
+        .and_then(Value::as_array)
+        .map(|params| params.iter().filter_map(|p| resolve(p, raw)).collect())
+        .unwrap_or_default();
+    let body = op.get("requestBody").and_then(|body| resolve(body, raw));
+    let body_content = body.and_then(|body| body.pointer("/content/application~1json"));
+    let responses = op
+        .get("responses")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flatten();
+
+    let mut examples = Vec::new();
+    for (status, response) in responses {
+        let status: u16 = match status.parse() {
+            Ok(status) => status,
+            Err(_) => continue,
+        };
+        let content = match resolve(response, raw)
+            .and_then(|response| response.pointer("/content/application~1json"))
+        {
+            Some(content) => content,
+            None => continue,
+        };
+        // (the name of the call, the name of its examples, the response)
+        let mut calls: Vec<(String, Option<&str>, &Value)> = Vec::new();
+        match content.get("examples").and_then(Value::as_object) {
+            Some(named) => {
+                for (name, example) in named {
+                    if let Some(value) = resolve(example, raw).and_then(|ex| ex.get("value")) {
+                        calls.push((name.clone(), Some(name.as_str()), value));
+                    }
+                }
+            }
+            None => {
+                if let Some(value) = content.get("example") {
+                    calls.push((status.to_string(), None, value));
+                }
+            }
+        }
+        'calls: for (name, example_name, response) in calls {
+            let mut values = Map::new();
+            for param in &params {
+                let param_name = match param.get("name").and_then(Value::as_str) {
+                    Some(param_name) => param_name,
+                    None => continue 'calls,
+                };
+                match example_value(param, example_name, raw) {
+                    Some(value) => {
+                        values.insert(param_name.to_string(), value);
+                    }
+                    None if param.get("required") == Some(&Value::Bool(true)) => continue 'calls,
+                    None => {}
+                }
+            }
+            let body = match (body, body_content) {
+                (None, _) => None,
+                (Some(_), Some(content)) => match example_value(content, example_name, raw) {
+                    Some(value) => Some(value),
+                    None => continue,
+                },
+                (Some(_), None) => continue,
+            };
+            examples.push(Example {
+                name,
+                params: values,
+                body,
+                status,
+                response: response.clone(),
+            });
+        }
+    }
+    examples
+}
+
+/// Whether the example calls of a route can be made by the generated client
+fn testable(route: &Route) -> bool {
+    !route.examples().is_empty()
+        && route.websocket().is_none()
+        && route.body_media_types().is_empty()
+        && route.offered_media_types().is_empty()
+}
+
+/// A test for each operation with examples, making their calls with the client
+/// against the API made by `make_api`, which must be a path
+pub(crate) fn generate_example_tests(
+    routes: &Map<String, Vec<Route>>,
+    make_api: &str,
+) -> Result<TokenStream> {
+    let make_api: syn::Path = syn::parse_str(make_api).map_err(|_| {
+        Error::Validation(format!(
+            "Bad path '{}' of the API of the example tests",
+            make_api
+        ))
+    })?;
+    let tests = routes
+        .values()
+        .flatten()
+        .filter(|route| testable(route))
+        .map(|route| {
+            let opid = route.operation_id();
+            let calls = route.examples().iter().map(|example| {
+                let call = format!("{}/{}", opid, example.name);
+                let path_names = route
+                    .path_params()
+                    .into_iter()
+                    .flat_map(|params| params.keys());
+                let query_names = route
+                    .query_params()
+                    .into_iter()
+                    .flat_map(|params| params.keys());
+                let mut args: Vec<TokenStream> = path_names
+                    .chain(query_names)
+                    .map(|name| {
+                        // optional parameters without an example are left out
+                        let value = example
+                            .params
+                            .get(&name.to_string())
+                            .unwrap_or(&Value::Null)
+                            .to_string();
+                        quote! { hsr::examples::value(#value) }
+                    })
+                    .collect();
+                if let Some(body) = &example.body {
+                    let body = body.to_string();
+                    args.push(quote! { hsr::examples::value(#body) });
+                }
+                let status = example.status;
+                let response = example.response.to_string();
+                quote! {
+                    let result = client.#opid(#(#args),*).await;
+                    hsr::examples::check(#call, result, #status, #response);
+                }
+            });
+            let test_name = crate::ident(format!("{}_examples", opid));
+            let allow_case = crate::allow_non_snake_case(Some(opid.as_str()));
+            quote! {
+                #[test]
+                #allow_case
+                fn #test_name() {
+                    let url = hsr::examples::serve(|cfg| server::serve(#make_api(), cfg));
+                    let mut system = hsr::actix_rt::System::new("examples");
+                    system.block_on(async move {
+                        let client = client::Client::new(url);
+                        #(#calls)*
+                    });
+                }
+            }
+        });
+    Ok(quote! {
+        /// Tests making the example calls of the spec, checking the responses
+        /// against the examples
+        #[cfg(test)]
+        mod example_tests {
+            use super::*;
+
+            #(#tests)*
+        }
+    })
+}
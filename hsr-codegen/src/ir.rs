@@ -0,0 +1,88 @@
+//! The intermediate representation of a spec, as analysed by hsr.
+//!
+//! This is what the code generator works from. It is exposed so that other tools
+//! (custom generators, documentation renderers, linters...) can reuse the analysis
+//! without going through the generated code.
+//!
+//! Every type is identified by the `TypePath` of the place in the spec where it
+//! was defined, and can be found in `ApiModel::types`.
+
+use std::fs;
+use std::path::Path;
+
+use openapiv3::{OpenAPI, ReferenceOr};
+
+pub use crate::examples::Example;
+pub use crate::route::{
+    CachePolicy, Idempotency, Pagination, RateLimit, RateLimitKey, Response, ResponseLink,
+    Responses, Route, Timeout,
+};
+pub use crate::walk::{
+    Bounds, Primitive, Representation, Struct, Type, TypeInner, TypeLookup, UnknownFields,
+};
+pub use crate::{
+    Extensions, FieldMetadata, Ident, Method, MethodWithBody, MethodWithoutBody, PathSegment,
+    RoutePath, TypeMetadata, TypeName, TypePath, Visibility,
+};
+
+use crate::{api_trait_name, component_schema_extensions, walk, Map, Result};
+
+/// A spec, analysed
+#[derive(Debug, Clone)]
+pub struct ApiModel {
+    /// The name of the API trait that would be generated
+    pub trait_name: TypeName,
+    /// The title, version (and so on) of the API
+    pub info: openapiv3::Info,
+    /// Every type defined in the spec
+    pub types: TypeLookup,
+    /// The routes of each path, keyed by the path as written in the spec
+    pub routes: Map<String, Vec<Route>>,
+    /// The vendor extensions (`x-*`) of the component schemas which have any,
+    /// keyed by the name of the schema as written in the spec
+    pub schema_extensions: Map<String, Extensions>,
+}
+
+impl ApiModel {
+    /// Follow a reference (if any) to the type it points at
+    pub fn resolve<'a>(&'a self, typ: &'a ReferenceOr<Type>) -> Result<&'a Type> {
+        walk::lookup_type_recursive(typ, &self.types)
+    }
+
+    /// Find the route for a path and method
+    pub fn find_route(&self, path: &str, method: &Method) -> Option<&Route> {
+        self.routes.get(path).and_then(|routes| {
+            routes
+                .iter()
+                .find(|route| route.method().to_string() == method.to_string())
+        })
+    }
+}
+
+/// Analyse a spec. Schemas using features we can't model are an error, as when
+/// generating code without `GenerationOptions::lenient`.
+pub fn parse_spec(mut yaml: impl std::io::Read) -> Result<ApiModel> {
+    let mut source = String::new();
+    yaml.read_to_string(&mut source)?;
+    let (api, raw) = crate::parse_openapi(&source)?;
+    analyse(&api, &raw, false).map_err(|e| crate::yaml::locate_error(e, &source))
+}
+
+/// Analyse a parsed spec (and its raw document). If `lenient`, schemas we
+/// can't model are taken to be any JSON.
+pub(crate) fn analyse(api: &OpenAPI, raw: &serde_json::Value, lenient: bool) -> Result<ApiModel> {
+    let (types, routes) = walk::walk_api(api, raw, lenient)?;
+    Ok(ApiModel {
+        trait_name: api_trait_name(api)?,
+        info: api.info.clone(),
+        types,
+        routes,
+        schema_extensions: component_schema_extensions(raw),
+    })
+}
+
+/// Analyse a spec file
+pub fn parse_spec_file(yaml: impl AsRef<Path>) -> Result<ApiModel> {
+    let f = fs::File::open(yaml)?;
+    parse_spec(f)
+}
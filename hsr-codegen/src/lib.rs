@@ -10,9 +10,10 @@ use std::str::FromStr;
 use actix_http::http::StatusCode;
 use derive_more::{Deref, Display};
 use either::Either;
-use heck::{CamelCase, MixedCase, SnakeCase};
+use heck::{CamelCase, MixedCase, ShoutySnakeCase, SnakeCase};
 use indexmap::{IndexMap as Map, IndexSet as Set};
 use log::{debug, info};
+use once_cell::sync::Lazy;
 use openapiv3::{
     AnySchema, ObjectType, OpenAPI, ReferenceOr, Schema, SchemaData, SchemaKind,
     StatusCode as ApiStatusCode, Type as ApiType,
@@ -28,19 +29,198 @@ macro_rules! invalid {
     );
 }
 
+macro_rules! unsupported {
+    ($($arg:tt)+) => (
+        return Err(Error::Unsupported(format!($($arg)+)))
+    );
+}
+
+mod audit;
+mod diff;
+mod examples;
+#[cfg(feature = "fetch")]
+mod fetch;
+pub mod ir;
+mod loadtest;
+mod plugin;
 mod route;
+mod versions;
 mod walk;
+mod watch;
+mod yaml;
 
+pub use audit::{audit_yaml_file, audit_yaml_source, emit_spec, Dropped};
+pub use diff::{diff_yaml_files, diff_yaml_sources, BreakingChange};
+#[cfg(feature = "fetch")]
+pub use fetch::{
+    fetch_spec, generate_from_url, generate_from_url_with_options, verify_sha256, FetchOptions,
+};
+pub use plugin::CodegenPlugin;
 use route::Route;
+pub use versions::generate_versioned_from_yaml_files;
+pub use watch::{referenced_files, write_if_changed, Watcher};
 
 const SWAGGER_UI_TEMPLATE: &'static str = include_str!("../ui-template.html");
 
+// Compiled once, these are hit for every path, parameter and field of the spec
+static IDENT_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new("^([[:alpha:]]|_)([[:alnum:]]|_)*$").unwrap());
+static REFERENCE_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new("^#/components/schemas/([[:alnum:]]+)$").unwrap());
+// "An alpha optionally followed by any of (alpha, number or _)"
+static PATH_LITERAL_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new("^[[:alpha:]]([[:alnum:]]|_)*$").unwrap());
+static PATH_PARAM_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"^\{([[:alpha:]]([[:alnum:]]|_)*)\}$"#).unwrap());
+
 fn ident(s: impl fmt::Display) -> QIdent {
     QIdent::new(&s.to_string(), proc_macro2::Span::call_site())
 }
 
+/// `#[allow(non_snake_case)]`, if any of the names (kept as the spec has them)
+/// isn't snake case, so that the user's crate builds without warnings
+fn allow_non_snake_case<'a>(names: impl IntoIterator<Item = &'a str>) -> Option<TokenStream> {
+    if names.into_iter().any(|name| name.to_snake_case() != name) {
+        Some(quote! { #[allow(non_snake_case)] })
+    } else {
+        None
+    }
+}
+
 type SchemaLookup = Map<String, ReferenceOr<Schema>>;
 
+/// Vendor extensions (`x-...` keys) attached to a node of the spec.
+/// `openapiv3` does not keep these around, so we read them from the raw document.
+pub type Extensions = Map<String, serde_json::Value>;
+
+/// Escape a single segment of a JSON pointer (RFC 6901)
+fn escape_pointer_segment(segment: &str) -> String {
+    segment.replace('~', "~0").replace('/', "~1")
+}
+
+/// Collect the vendor extensions of the node found at `pointer` in the raw spec
+fn extensions_at(raw: &serde_json::Value, pointer: &str) -> Extensions {
+    raw.pointer(pointer)
+        .and_then(|node| node.as_object())
+        .map(|obj| {
+            obj.iter()
+                .filter(|(key, _)| key.starts_with("x-"))
+                .map(|(key, val)| (key.clone(), val.clone()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// The vendor extensions of each component schema, keyed by the name of the schema
+fn component_schema_extensions(raw: &serde_json::Value) -> Map<String, Extensions> {
+    raw.pointer("/components/schemas")
+        .and_then(|schemas| schemas.as_object())
+        .map(|schemas| {
+            schemas
+                .keys()
+                .map(|name| {
+                    let pointer = format!("/components/schemas/{}", escape_pointer_segment(name));
+                    (name.clone(), extensions_at(raw, &pointer))
+                })
+                .filter(|(_, extensions)| !extensions.is_empty())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Whether the node at `pointer` in the raw spec is marked `x-internal: true`
+fn is_internal(raw: &serde_json::Value, pointer: &str) -> bool {
+    raw.pointer(pointer)
+        .and_then(|node| node.get("x-internal"))
+        .and_then(|internal| internal.as_bool())
+        .unwrap_or(false)
+}
+
+/// Add the names of the component schemas referred to in `node` to `names`
+fn schema_refs(node: &serde_json::Value, names: &mut Set<String>) {
+    match node {
+        serde_json::Value::Object(map) => {
+            let name = map
+                .get("$ref")
+                .and_then(|reference| reference.as_str())
+                .and_then(|reference| reference.strip_prefix("#/components/schemas/"));
+            if let Some(name) = name {
+                names.insert(name.replace("~1", "/").replace("~0", "~"));
+            }
+            map.values().for_each(|value| schema_refs(value, names));
+        }
+        serde_json::Value::Array(items) => items.iter().for_each(|item| schema_refs(item, names)),
+        _ => {}
+    }
+}
+
+/// The spec without the operations marked `x-internal`, nor the component schemas
+/// marked `x-internal` which nothing left refers to, for serving to the outside world
+fn public_spec(api: &OpenAPI, raw: &serde_json::Value) -> OpenAPI {
+    let mut api = api.clone();
+    api.paths.retain(|path, item| {
+        let item = match item {
+            ReferenceOr::Item(item) => item,
+            ReferenceOr::Reference { .. } => return true,
+        };
+        let mut operations = [
+            ("get", &mut item.get),
+            ("put", &mut item.put),
+            ("post", &mut item.post),
+            ("delete", &mut item.delete),
+            ("options", &mut item.options),
+            ("head", &mut item.head),
+            ("patch", &mut item.patch),
+            ("trace", &mut item.trace),
+        ];
+        let mut remaining = 0;
+        for (method, operation) in operations.iter_mut() {
+            if operation.is_none() {
+                continue;
+            }
+            let pointer = format!("/paths/{}/{}", escape_pointer_segment(path), method);
+            if is_internal(raw, &pointer) {
+                **operation = None;
+            } else {
+                remaining += 1;
+            }
+        }
+        remaining > 0
+    });
+    let mut internal = match api.components.as_mut() {
+        Some(components) => {
+            let (internal, public) = std::mem::take(&mut components.schemas)
+                .into_iter()
+                .partition(|(name, _)| {
+                    let pointer = format!("/components/schemas/{}", escape_pointer_segment(name));
+                    is_internal(raw, &pointer)
+                });
+            components.schemas = public;
+            internal
+        }
+        None => return api,
+    };
+    // an internal schema stays while the rest of the spec (or another schema which
+    // stays) refers to it
+    let mut used = Set::new();
+    schema_refs(&serde_json::to_value(&api).unwrap_or_default(), &mut used);
+    let mut checked = 0;
+    while checked < used.len() {
+        let name = used[checked].clone();
+        checked += 1;
+        if let Some(schema) = internal.remove(&name) {
+            schema_refs(
+                &serde_json::to_value(&schema).unwrap_or_default(),
+                &mut used,
+            );
+            if let Some(components) = api.components.as_mut() {
+                components.schemas.insert(name, schema);
+            }
+        }
+    }
+    api
+}
+
 #[derive(Debug, Error)]
 pub enum Error {
     #[error("IO Error: {}", _0)]
@@ -53,10 +233,346 @@ pub enum Error {
     BadReference(String),
     #[error("OpenAPI validation failed: {}", _0)]
     Validation(String),
+    #[error("{} not supported", _0)]
+    Unsupported(String),
+    #[error("Failed to fetch spec: {}", _0)]
+    Fetch(String),
+    /// The spec doesn't have the SHA-256 it is pinned to
+    #[error("Spec has SHA-256 {}, expected {}", actual, expected)]
+    Checksum { expected: String, actual: String },
+    /// An error found at a node of the spec, given by its JSON pointer
+    #[error("{} (at '{}')", _1, _0)]
+    At(String, Box<Error>),
+    /// An error found at a node of the spec, given by its line and column in the source
+    #[error("{} (line {}, column {})", _2, _0, _1)]
+    Located(usize, usize, Box<Error>),
+}
+
+impl Error {
+    /// Attach the JSON pointer of the node of the spec at fault, unless the
+    /// error already has a (more specific) one
+    pub(crate) fn at(self, pointer: &str) -> Self {
+        match self {
+            Error::At(..) | Error::Located(..) => self,
+            _ if pointer.is_empty() => self,
+            err => Error::At(pointer.to_string(), Box::new(err)),
+        }
+    }
+
+    /// The JSON pointer of the node of the spec at fault, if known
+    pub fn pointer(&self) -> Option<&str> {
+        match self {
+            Error::At(pointer, _) => Some(pointer),
+            Error::Located(_, _, err) => err.pointer(),
+            _ => None,
+        }
+    }
+
+    /// The line and column (both from 1) in the source of the spec at fault, if known
+    pub fn location(&self) -> Option<(usize, usize)> {
+        match self {
+            Error::Located(line, column, _) => Some((*line, *column)),
+            Error::Yaml(err) => err.location().map(|loc| (loc.line(), loc.column())),
+            _ => None,
+        }
+    }
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Options controlling code generation
+#[derive(Debug, Clone, Default)]
+pub struct GenerationOptions {
+    /// Derive `proptest_derive::Arbitrary` for the generated types (under `cfg(test)`),
+    /// respecting numeric bounds declared in the spec, and generate serde round-trip
+    /// property tests for the component types. The crate including the generated
+    /// code needs `proptest` and `proptest-derive` as dev-dependencies.
+    pub arbitrary: bool,
+    /// Implement `hsr::fake::Sample` for the generated structs and enums, making
+    /// random values which fit their schemas: for seeding mock servers, tests and
+    /// load generators. Unlike `arbitrary`, this needs no dependencies.
+    pub samples: bool,
+    /// Plugins emitting extra code alongside the standard output
+    pub plugins: Vec<std::sync::Arc<dyn CodegenPlugin>>,
+    /// Generate `server::Stub`, an implementation of the API trait in which every
+    /// operation is `todo!()`, so a new project compiles and serves straight away
+    pub stub: bool,
+    /// The web framework the generated server is built on
+    pub backend: Backend,
+    /// Generate `server::lambda_handler`, to run the API as an AWS Lambda function.
+    /// Requires the `lambda` feature of `hsr`.
+    pub lambda: bool,
+    /// Generate `proxy::serve`, which serves the API by forwarding each request to
+    /// an upstream server, checking both the request and the response against the spec.
+    /// Only supported by the actix backend.
+    pub proxy: bool,
+    /// Pass an `hsr::RequestContext` as the first argument of every API method,
+    /// giving access to the headers, peer address and extensions of the request.
+    /// Only supported by the actix backend.
+    pub request_context: bool,
+    /// Call the `authorize` method of the API trait before every operation, with an
+    /// `hsr::authz::AuthzInput` describing the call. Only supported by the actix backend.
+    pub authorization: bool,
+    /// Shared state (a metrics registry, a feature flag client...) passed to every API
+    /// method as an `actix_web::web::Data`, after the request context. A `State`
+    /// struct is generated holding them, to be given to `server::serve` along with
+    /// the API. Only supported by the actix backend.
+    pub shared_state: Vec<SharedState>,
+    /// Send error responses (4xx and 5xx) as `application/problem+json` documents,
+    /// see `hsr::Problem`. Only supported by the actix backend.
+    pub problem_json: bool,
+    /// Leave the operations marked `x-internal: true` out of the generated client
+    pub public_client: bool,
+    /// How the spec served at `/spec.json` is included in the generated code
+    pub spec: SpecEmbedding,
+    /// Generate `serde_json::Value` (with a warning) for schemas using features hsr
+    /// can't model, such as `not`, rather than failing
+    pub lenient: bool,
+    /// Generate an `allOf` which refers to other schemas as a struct with a
+    /// `#[serde(flatten)]` field for each of them, rather than a copy of their fields
+    pub flatten_all_of: bool,
+    /// Reject objects with fields their schema doesn't declare, rather than ignoring
+    /// them (`#[serde(deny_unknown_fields)]`). A schema can also ask for this itself,
+    /// with `additionalProperties: false`. Structs with flattened fields (see
+    /// `flatten_all_of`) still ignore them, as serde can't tell them apart.
+    pub deny_unknown_fields: bool,
+    /// Keep the fields of an object its schema doesn't declare, rather than dropping
+    /// them, in an `extra` map (see `map_type`) field of every generated struct,
+    /// so they survive a round trip. A schema can also ask for this itself, with
+    /// `additionalProperties: true`.
+    pub extra_fields: bool,
+    /// The type of maps: objects without properties whose `additionalProperties` is
+    /// a schema, and the `extra` fields. The fields of structs always keep the order
+    /// the spec declares them in.
+    pub map_type: MapType,
+    /// String enums accept their values in any (ASCII) case, but always send them as
+    /// the spec has them. A schema can also ask for this with `x-hsr-case-insensitive: true`.
+    pub case_insensitive_enums: bool,
+    /// Number fields also accept strings holding a number (`"42"`), as some APIs send
+    /// them (see `hsr::lenient`). A schema can also ask for this with `x-hsr-lenient: true`.
+    pub lenient_numbers: bool,
+    /// API methods return `Result<_, Self::Error>`, with an error type of the API's
+    /// choosing, which `map_error` turns into a response. Only supported by the actix backend.
+    pub fallible: bool,
+    /// Serve the title, version and base path of the spec at `GET /version`
+    pub version_route: bool,
+    /// Answer `HEAD` requests for each path with a `GET` operation (and no `HEAD`
+    /// operation of its own), with the `GET` operation, leaving out the body but
+    /// keeping its `Content-Length`. The axum backend always does.
+    pub auto_head: bool,
+    /// The actix server holds the API as a `DynApi` (an `Arc<dyn Api>`), and its
+    /// handlers aren't generic over the API, so they are compiled once rather than
+    /// for the API type in the crate serving it. This keeps build times down for
+    /// specs with hundreds of operations, at the cost of a virtual call per request.
+    /// Not supported with `fallible`, or by the axum backend.
+    pub dyn_dispatch: bool,
+    /// Generate a `#[cfg(test)]` test for each operation with examples in the spec,
+    /// making the example calls with the client against a server of the API made by
+    /// the function at this path (e.g. `crate::tests::example_api`), and checking
+    /// the responses are the examples (see `hsr::examples`). Only supported by the
+    /// actix backend.
+    pub example_tests: Option<String>,
+    /// Generate a `loadtest` module, whose `run` calls every operation at a steady
+    /// rate with the client, with random inputs, and reports the latencies of each
+    /// (see `hsr::loadtest`). Needs `samples`.
+    pub load_test: bool,
+    /// Generate a `fuzz` module, with an entry point for each operation feeding
+    /// arbitrary bytes to the extraction of its parameters and body, for `cargo fuzz`
+    /// targets (see `generate_fuzz_targets_from_yaml_file`). Requires the `fuzz`
+    /// feature of `hsr`.
+    pub fuzz_targets: bool,
+}
+
+/// State shared by the API methods, see `GenerationOptions::shared_state`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SharedState {
+    /// The name of the argument (and of the field of `State`)
+    pub name: String,
+    /// The path of the type of the state, e.g. `crate::metrics::Registry`
+    pub type_path: String,
+}
+
+impl SharedState {
+    pub fn new(name: impl Into<String>, type_path: impl Into<String>) -> Self {
+        SharedState {
+            name: name.into(),
+            type_path: type_path.into(),
+        }
+    }
+
+    /// The name of the argument and the type of the state, failing if either is bad
+    fn parse(&self) -> Result<(Ident, TokenStream)> {
+        let name = Ident::from_str(&self.name)?;
+        let ty: syn::Type = syn::parse_str(&self.type_path).map_err(|_| {
+            Error::Validation(format!("Bad type '{}' of shared state", self.type_path))
+        })?;
+        Ok((name, quote! { #ty }))
+    }
+}
+
+/// The names and types of the shared state
+fn shared_state(opts: &GenerationOptions) -> Result<Vec<(Ident, TokenStream)>> {
+    opts.shared_state
+        .iter()
+        .map(|state| state.parse())
+        .collect()
+}
+
+/// How the generated actix handlers take the API: as a type parameter `A` (with
+/// `bounds`), or as the `DynApi` with `dyn_dispatch`. Gives the type parameter,
+/// the turbofish naming the handlers, and the type of the API.
+fn handler_api(
+    opts: &GenerationOptions,
+    bounds: TokenStream,
+) -> (Option<TokenStream>, Option<TokenStream>, TokenStream) {
+    if opts.dyn_dispatch {
+        (None, None, quote! { DynApi })
+    } else {
+        (
+            Some(quote! { <A: #bounds> }),
+            Some(quote! { ::<A> }),
+            quote! { A },
+        )
+    }
+}
+
+/// The names of the variables of the generated handlers (and the API methods),
+/// which shared state can't take
+const RESERVED_STATE_NAMES: &[&str] = &[
+    "self",
+    "ctx",
+    "data",
+    "req",
+    "path",
+    "query",
+    "body",
+    "payload",
+    "accept",
+    "socket",
+    "result",
+    "status",
+    "content_type",
+];
+
+/// Check the shared state can be passed to every API method
+fn check_shared_state(opts: &GenerationOptions, routes: &Map<String, Vec<Route>>) -> Result<()> {
+    if opts.shared_state.is_empty() {
+        return Ok(());
+    }
+    if opts.backend == Backend::Axum {
+        invalid!("Shared state is not supported by the axum backend");
+    }
+    let mut names = Set::new();
+    for state in &opts.shared_state {
+        state.parse()?;
+        if RESERVED_STATE_NAMES.contains(&state.name.as_str()) {
+            invalid!("Shared state can't be named '{}'", state.name);
+        }
+        if !names.insert(state.name.as_str()) {
+            invalid!("Shared state '{}' given twice", state.name);
+        }
+    }
+    for route in routes.values().flatten() {
+        if let Some(param) = route
+            .param_names()
+            .find(|param| names.contains(param.as_str()))
+        {
+            invalid!(
+                "Shared state '{}' clashes with a parameter of '{}'",
+                param,
+                route.operation_id()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// How the spec is included in the generated code, see `hsr::spec`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SpecEmbedding {
+    /// Embed the spec as a string (the default)
+    Plain,
+    /// Embed the spec gzipped, to be decompressed when first needed
+    Gzip,
+    /// Read the spec from a file at runtime, when first needed. The file must
+    /// hold the spec as JSON, see `generate_json_spec`.
+    File(String),
+    /// Leave the spec out. Neither `/spec.json` nor `/ui.html` are served,
+    /// and response validation is unavailable.
+    Omit,
+}
+
+impl Default for SpecEmbedding {
+    fn default() -> Self {
+        SpecEmbedding::Plain
+    }
+}
+
+impl SpecEmbedding {
+    /// The `hsr::spec::SpecSource` of the spec
+    fn source(&self, json_spec: &str) -> Result<TokenStream> {
+        let source = match self {
+            SpecEmbedding::Plain => quote! { hsr::spec::SpecSource::Plain(#json_spec) },
+            SpecEmbedding::Gzip => {
+                use std::io::Write;
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+                encoder.write_all(json_spec.as_bytes())?;
+                let gzipped = proc_macro2::Literal::byte_string(&encoder.finish()?);
+                quote! { hsr::spec::SpecSource::Gzip(#gzipped) }
+            }
+            SpecEmbedding::File(path) => quote! { hsr::spec::SpecSource::File(#path) },
+            SpecEmbedding::Omit => quote! { hsr::spec::SpecSource::Omitted },
+        };
+        Ok(source)
+    }
+}
+
+/// The type of the maps in the generated code, see `GenerationOptions::map_type`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapType {
+    /// `IndexMap`, keeping the order of the keys in the JSON (the default)
+    IndexMap,
+    /// `BTreeMap`, with the keys sorted, for consumers which need canonical JSON
+    BTreeMap,
+    /// `HashMap`, in no particular order
+    HashMap,
+}
+
+impl Default for MapType {
+    fn default() -> Self {
+        MapType::IndexMap
+    }
+}
+
+impl MapType {
+    fn tokens(&self) -> TokenStream {
+        match self {
+            MapType::IndexMap => quote! { hsr::indexmap::IndexMap },
+            MapType::BTreeMap => quote! { std::collections::BTreeMap },
+            MapType::HashMap => quote! { std::collections::HashMap },
+        }
+    }
+}
+
+/// The web framework the generated server is built on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// `actix-web` (the default)
+    Actix,
+    /// `axum`. Requires the `axum` feature of `hsr`. The API trait is then `Send`,
+    /// so implementations use `#[hsr::async_trait::async_trait]` (without `?Send`),
+    /// and the server must be run within a `tokio` runtime. Websockets and
+    /// response validation are not supported.
+    Axum,
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        Backend::Actix
+    }
+}
+
 /// Unwrap the reference, or fail
 /// TODO get rid of this
 fn unwrap_ref<T>(item: &ReferenceOr<T>) -> Result<&T> {
@@ -66,22 +582,32 @@ fn unwrap_ref<T>(item: &ReferenceOr<T>) -> Result<&T> {
     }
 }
 
-/// Fetch reference target via a lookup
+/// Fetch reference target via a lookup (of the components of one kind,
+/// keyed by name, so `#/components/parameters/Limit` is found as `Limit`)
 fn dereference<'a, T>(
     refr: &'a ReferenceOr<T>,
     lookup: &'a Map<String, ReferenceOr<T>>,
 ) -> Result<&'a T> {
     match refr {
-        ReferenceOr::Reference { reference } => lookup
-            .get(reference)
+        ReferenceOr::Reference { reference } => reference
+            .rsplit('/')
+            .next()
+            .map(|name| name.replace("~1", "/").replace("~0", "~"))
+            .and_then(|name| lookup.get(&name))
             .ok_or_else(|| Error::BadReference(reference.to_string()))
             .and_then(|refr| dereference(refr, lookup)),
         ReferenceOr::Item(item) => Ok(item),
     }
 }
 
-fn api_trait_name(api: &OpenAPI) -> TypeName {
-    TypeName::from_str(&format!("{}Api", api.info.title.to_camel_case())).unwrap()
+fn api_trait_name(api: &OpenAPI) -> Result<TypeName> {
+    TypeName::from_str(&format!("{}Api", api.info.title.to_camel_case())).map_err(|_| {
+        Error::Validation(format!(
+            "Can't name the API trait after the title '{}'",
+            api.info.title
+        ))
+        .at("/info/title")
+    })
 }
 
 #[derive(Debug, Clone, Copy, derive_more::Display)]
@@ -99,7 +625,7 @@ enum RawMethod {
 /// Separately represents methods which CANNOT take a body (GET, HEAD, OPTIONS, TRACE)
 /// and those which MAY take a body (POST, PATCH, PUT, DELETE)
 #[derive(Debug, Clone)]
-enum Method {
+pub enum Method {
     WithoutBody(MethodWithoutBody),
     WithBody {
         method: MethodWithBody,
@@ -156,7 +682,8 @@ impl Method {
         Ok(meth)
     }
 
-    fn body_type(&self) -> Option<&TypePath> {
+    /// The type of the request body, if any
+    pub fn body_type(&self) -> Option<&TypePath> {
         match self {
             Method::WithoutBody(_)
             | Method::WithBody {
@@ -171,7 +698,7 @@ impl Method {
 }
 
 #[derive(Debug, Clone, Copy)]
-enum MethodWithoutBody {
+pub enum MethodWithoutBody {
     Get,
     Head,
     Options,
@@ -203,7 +730,7 @@ impl fmt::Display for MethodWithBody {
 }
 
 #[derive(Debug, Clone, Copy)]
-enum MethodWithBody {
+pub enum MethodWithBody {
     Post,
     Delete,
     Put,
@@ -214,15 +741,14 @@ enum MethodWithBody {
 ///
 /// Do not construct directly, instead use str.parse
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Display, Deref)]
-struct Ident(String);
+pub struct Ident(String);
 
 impl FromStr for Ident {
     type Err = Error;
     fn from_str(val: &str) -> Result<Self> {
         // Check the string is a valid identifier
         // We do not enforce any particular case
-        let ident_re = Regex::new("^([[:alpha:]]|_)([[:alnum:]]|_)*$").unwrap();
-        if ident_re.is_match(val) {
+        if IDENT_RE.is_match(val) {
             Ok(Ident(val.to_string()))
         } else {
             invalid!("Bad identifier '{}' (not a valid Rust identifier)", val)
@@ -240,21 +766,49 @@ impl quote::ToTokens for Ident {
 /// An ApiPath represents a nested location within the OpenAPI object.
 /// It can be used to keep track of where resources (particularly type
 /// definitions) are located.
+///
+/// Types are named after the path, which doesn't always follow the layout of
+/// the spec (e.g. the items of an array are under `array`), so the JSON pointer
+/// of the location is kept alongside, for error messages.
 #[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
 pub(crate) struct ApiPath {
     path: Vec<String>,
+    pointer: String,
 }
 
 impl ApiPath {
-    fn push(mut self, s: impl Into<String>) -> Self {
+    /// Descend to a child node, named as in the spec
+    fn push(self, s: impl Into<String>) -> Self {
+        let s = s.into();
+        let pointer = format!("/{}", escape_pointer_segment(&s));
+        self.push_name(s).push_pointer(&pointer)
+    }
+
+    /// Extend the path used to name types, without moving within the spec
+    fn push_name(mut self, s: impl Into<String>) -> Self {
         self.path.push(s.into());
         self
     }
+
+    /// Move within the spec by a relative JSON pointer (e.g. `/items`),
+    /// without extending the path used to name types
+    fn push_pointer(mut self, pointer: &str) -> Self {
+        self.pointer.push_str(pointer);
+        self
+    }
+
+    /// The JSON pointer of the location within the spec
+    fn pointer(&self) -> &str {
+        &self.pointer
+    }
 }
 
 impl From<TypePath> for ApiPath {
     fn from(path: TypePath) -> Self {
-        Self { path: path.0 }
+        Self {
+            path: path.0,
+            pointer: String::new(),
+        }
     }
 }
 
@@ -270,7 +824,7 @@ impl std::fmt::Display for ApiPath {
 /// with the TypeLookup map, and to generate a canonical name for a type.
 /// Once created, it is intended to be read-only
 #[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord)]
-pub(crate) struct TypePath(Vec<String>);
+pub struct TypePath(Vec<String>);
 
 impl From<ApiPath> for TypePath {
     fn from(path: ApiPath) -> Self {
@@ -280,8 +834,7 @@ impl From<ApiPath> for TypePath {
 
 impl TypePath {
     pub(crate) fn from_reference(refr: &str) -> Result<Self> {
-        let rx = Regex::new("^#/components/schemas/([[:alnum:]]+)$").unwrap();
-        let cap = rx
+        let cap = REFERENCE_RE
             .captures(refr)
             .ok_or_else(|| Error::BadReference(refr.into()))?;
         let name = cap.get(1).unwrap();
@@ -293,9 +846,15 @@ impl TypePath {
         Ok(Self(path))
     }
 
-    // Turn an TypePath into a TypeName, which generally
-    // will be the name actually used for a type definition
-    pub(crate) fn canonicalize(&self) -> TypeName {
+    /// The location of the definition within the spec, e.g. `["components", "schemas", "Pet"]`
+    pub fn segments(&self) -> &[String] {
+        &self.0
+    }
+
+    /// Turn an TypePath into a TypeName, which generally
+    /// will be the name actually used for a type definition.
+    /// Fails if the path doesn't make a valid type name
+    pub fn canonicalize(&self) -> Result<TypeName> {
         let parts: Vec<&str> = self.0.iter().map(String::as_str).collect();
         let parts = match &parts[..] {
             // if it is from 'components', strip out not-useful components path
@@ -308,7 +867,7 @@ impl TypePath {
             rest => rest,
         };
         let joined = parts.join(" ");
-        TypeName::from_str(&joined.to_camel_case()).unwrap()
+        TypeName::from_str(&joined.to_camel_case())
     }
 }
 
@@ -316,13 +875,14 @@ impl TypePath {
 ///
 /// Do not construct directly, instead use `new`
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Display, Deref)]
-struct TypeName(String);
+pub struct TypeName(String);
 
 impl FromStr for TypeName {
     type Err = Error;
     fn from_str(val: &str) -> Result<Self> {
         let camel = val.to_camel_case();
-        if val == camel {
+        let starts_alpha = val.chars().next().map_or(false, char::is_alphabetic);
+        if val == camel && starts_alpha {
             Ok(TypeName(camel))
         } else {
             invalid!("Bad type name '{}', must be ClassCase", val)
@@ -338,14 +898,16 @@ impl quote::ToTokens for TypeName {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
-enum PathSegment {
+pub enum PathSegment {
     Literal(String),
     Parameter(String),
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
-pub(crate) struct RoutePath {
+pub struct RoutePath {
     segments: Vec<PathSegment>,
+    /// The path ends with a `/`, which is kept: `/pets/` isn't `/pets`
+    trailing_slash: bool,
 }
 
 impl fmt::Display for RoutePath {
@@ -362,6 +924,9 @@ impl fmt::Display for RoutePath {
                 }
             }
         }
+        if self.trailing_slash {
+            path.push('/');
+        }
         write!(f, "{}", path)
     }
 }
@@ -369,31 +934,62 @@ impl fmt::Display for RoutePath {
 impl RoutePath {
     /// Check a path is well-formed and break it into its respective `PathSegment`s
     fn analyse(path: &str) -> Result<RoutePath> {
-        // "An alpha optionally followed by any of (alpha, number or _)"
-        let literal_re = Regex::new("^[[:alpha:]]([[:alnum:]]|_)*$").unwrap();
-        let param_re = Regex::new(r#"^\{([[:alpha:]]([[:alnum:]]|_)*)\}$"#).unwrap();
-
         if !path.starts_with('/') {
             invalid!("Bad path '{}' (must start with '/')", path);
         }
 
+        // a trailing slash is part of the path (see `hsr::normalize`)
+        let (trimmed, trailing_slash) = match path.strip_suffix('/') {
+            Some(trimmed) if !trimmed.is_empty() => (trimmed, true),
+            _ => (path, false),
+        };
         let mut segments = Vec::new();
 
         let mut dupe_params = Set::new();
-        for segment in path.split('/').skip(1) {
-            if literal_re.is_match(segment) {
+        for segment in trimmed.split('/').skip(1) {
+            if PATH_LITERAL_RE.is_match(segment) {
                 segments.push(PathSegment::Literal(segment.to_string()))
-            } else if let Some(seg) = param_re.captures(segment) {
+            } else if let Some(seg) = PATH_PARAM_RE.captures(segment) {
                 let param = seg.get(1).unwrap().as_str().to_string();
                 if !dupe_params.insert(param.clone()) {
-                    invalid!("Duplicate parameter in path '{}'", path);
+                    invalid!("Duplicate parameter '{}' in path '{}'", param, path);
                 }
                 segments.push(PathSegment::Parameter(param))
             } else {
-                invalid!("Bad path '{}'", path);
+                invalid!("Bad path '{}' (bad segment '{}')", path, segment);
+            }
+        }
+        Ok(RoutePath {
+            segments,
+            trailing_slash,
+        })
+    }
+
+    pub fn segments(&self) -> &[PathSegment] {
+        &self.segments
+    }
+
+    /// Whether the path ends with a `/`
+    pub fn trailing_slash(&self) -> bool {
+        self.trailing_slash
+    }
+
+    /// Render the path in the syntax understood by axum, e.g. `/pets/:petId`
+    fn to_axum(&self) -> String {
+        if self.segments.is_empty() {
+            return "/".into();
+        }
+        let mut path = String::new();
+        for segment in &self.segments {
+            match segment {
+                PathSegment::Literal(p) => path.push_str(&format!("/{}", p)),
+                PathSegment::Parameter(p) => path.push_str(&format!("/:{}", p)),
             }
         }
-        Ok(RoutePath { segments })
+        if self.trailing_slash {
+            path.push('/');
+        }
+        path
     }
 
     fn path_args(&self) -> impl Iterator<Item = &str> {
@@ -408,7 +1004,7 @@ impl RoutePath {
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
-pub(crate) enum Visibility {
+pub enum Visibility {
     Public,
     Private,
 }
@@ -430,18 +1026,18 @@ impl Default for Visibility {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
-pub(crate) struct TypeMetadata {
+pub struct TypeMetadata {
     title: Option<String>,
     description: Option<String>,
     nullable: bool,
     visibility: Visibility,
+    /// A string enum accepts its values in any case (`x-hsr-case-insensitive`)
+    case_insensitive: bool,
+    /// A number also accepts strings holding one (`x-hsr-lenient`)
+    lenient: bool,
 }
 
 impl TypeMetadata {
-    fn with_visibility(self, visibility: Visibility) -> Self {
-        Self { visibility, ..self }
-    }
-
     fn with_description(self, description: String) -> Self {
         Self {
             description: Some(description),
@@ -449,7 +1045,31 @@ impl TypeMetadata {
         }
     }
 
-    fn description(&self) -> Option<TokenStream> {
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    pub fn nullable(&self) -> bool {
+        self.nullable
+    }
+
+    pub fn visibility(&self) -> Visibility {
+        self.visibility
+    }
+
+    pub fn case_insensitive(&self) -> bool {
+        self.case_insensitive
+    }
+
+    pub fn lenient(&self) -> bool {
+        self.lenient
+    }
+
+    fn description_doc(&self) -> Option<TokenStream> {
         self.description.as_ref().map(|s| {
             quote! {
                 #[doc = #s]
@@ -458,27 +1078,89 @@ impl TypeMetadata {
     }
 }
 
-impl From<openapiv3::SchemaData> for TypeMetadata {
-    fn from(from: openapiv3::SchemaData) -> Self {
+impl From<&openapiv3::SchemaData> for TypeMetadata {
+    fn from(from: &openapiv3::SchemaData) -> Self {
         Self {
-            title: from.title,
-            description: from.description,
+            title: from.title.clone(),
+            description: from.description.clone(),
             nullable: from.nullable,
             visibility: Visibility::Public,
+            case_insensitive: false,
+            lenient: false,
         }
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
-pub(crate) struct FieldMetadata {
+pub struct FieldMetadata {
     description: Option<String>,
     required: bool,
+    default: Option<serde_json::Value>,
+    allow_empty: bool,
+    allow_reserved: bool,
+    delimiter: Option<char>,
 }
 
 impl FieldMetadata {
     fn with_required(self, required: bool) -> Self {
         Self { required, ..self }
     }
+
+    fn with_default(self, default: Option<serde_json::Value>) -> Self {
+        Self { default, ..self }
+    }
+
+    fn with_allow_empty(self, allow_empty: bool) -> Self {
+        Self {
+            allow_empty,
+            ..self
+        }
+    }
+
+    fn with_allow_reserved(self, allow_reserved: bool) -> Self {
+        Self {
+            allow_reserved,
+            ..self
+        }
+    }
+
+    fn with_delimiter(self, delimiter: Option<char>) -> Self {
+        Self { delimiter, ..self }
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    pub fn required(&self) -> bool {
+        self.required
+    }
+
+    /// The value used when the field is missing
+    pub fn default_value(&self) -> Option<&serde_json::Value> {
+        self.default.as_ref()
+    }
+
+    /// Whether the field (a query parameter) may be given an empty value
+    pub fn allow_empty(&self) -> bool {
+        self.allow_empty
+    }
+
+    /// Whether the client sends the reserved characters of the field (a query parameter) unencoded
+    pub fn allow_reserved(&self) -> bool {
+        self.allow_reserved
+    }
+
+    /// The delimiter between the items of a (`spaceDelimited` or `pipeDelimited`)
+    /// array query parameter
+    pub fn delimiter(&self) -> Option<char> {
+        self.delimiter
+    }
+
+    /// Whether the field may be missing, with no default to fall back on
+    pub fn optional(&self) -> bool {
+        !self.required && self.default.is_none()
+    }
 }
 
 pub(crate) fn variant_from_status_code(code: &StatusCode) -> Ident {
@@ -494,9 +1176,29 @@ fn doc_comment(msg: impl AsRef<str>) -> TokenStream {
     }
 }
 
-fn get_derive_tokens() -> TokenStream {
+fn get_derive_tokens(opts: &GenerationOptions) -> TokenStream {
+    let arbitrary = arbitrary_derive_tokens(opts);
     quote! {
         # [derive(Debug, Clone, PartialEq, hsr::Serialize, hsr::Deserialize)]
+        #arbitrary
+    }
+}
+
+fn arbitrary_derive_tokens(opts: &GenerationOptions) -> Option<TokenStream> {
+    if opts.arbitrary {
+        Some(quote! {
+            #[cfg_attr(test, derive(proptest_derive::Arbitrary))]
+        })
+    } else {
+        None
+    }
+}
+
+/// Axum requires handler futures to be `Send`, actix does not
+fn async_trait_attr(opts: &GenerationOptions) -> TokenStream {
+    match opts.backend {
+        Backend::Actix => quote! { #[hsr::async_trait::async_trait(?Send)] },
+        Backend::Axum => quote! { #[hsr::async_trait::async_trait] },
     }
 }
 
@@ -504,110 +1206,938 @@ fn generate_rust_interface(
     routes: &Map<String, Vec<Route>>,
     title: &str,
     trait_name: &TypeName,
-) -> TokenStream {
+    opts: &GenerationOptions,
+) -> Result<TokenStream> {
     let mut methods = TokenStream::new();
     let descr = doc_comment(format!("Api generated from '{}' spec", title));
     for (_, route_methods) in routes {
         for route in route_methods {
-            methods.extend(route.generate_api_signature());
+            methods.extend(route.generate_api_signature(opts)?);
         }
     }
-    quote! {
+    // errors of the application, which the API methods may return
+    let error_hooks = if opts.fallible {
+        let problem_json = opts.problem_json;
+        Some(quote! {
+            /// The errors of the application (database failures etc)
+            type Error: std::fmt::Debug + 'static;
+
+            /// Turn an error returned by an operation into a response. By default,
+            /// the error is logged and the response is a 500 Internal Server Error.
+            fn map_error(&self, err: Self::Error, op: hsr::OperationMeta) -> HttpResponse {
+                hsr::dispatch::internal_error(&err, op, #problem_json)
+            }
+        })
+    } else {
+        None
+    };
+    // the responses to requests the API has no operation for
+    let fallbacks = if opts.backend == Backend::Actix {
+        Some(quote! {
+            /// Answer a request for a path the API doesn't have. By default, the
+            /// response is a 404 problem document.
+            fn not_found(&self, req: &HttpRequest) -> HttpResponse {
+                hsr::dispatch::not_found(req)
+            }
+
+            /// Answer a request with a method its path doesn't support, given the
+            /// methods which are. By default, the response is a 405 problem document,
+            /// with an `Allow` header.
+            fn method_not_allowed(&self, req: &HttpRequest, allowed: &[&'static str]) -> HttpResponse {
+                hsr::dispatch::method_not_allowed(req, allowed)
+            }
+        })
+    } else {
+        None
+    };
+    // the policy enforcement point, which has no default: every call goes through it
+    let authorize_hook = if opts.authorization {
+        Some(quote! {
+            /// Decide whether a call of an operation may go ahead, before it is
+            /// dispatched. A denied call gets a 401 or 403 problem document.
+            async fn authorize(&self, input: hsr::authz::AuthzInput) -> Result<(), hsr::authz::Denied>;
+        })
+    } else {
+        None
+    };
+    let audit_hook = if routes.values().flatten().any(Route::is_audited) {
+        Some(quote! {
+            /// Record a call of an operation marked `x-hsr-audit`, once it has its
+            /// response. By default, the event is logged.
+            async fn audit(&self, event: hsr::audit::AuditEvent) {
+                hsr::audit::log(&event)
+            }
+        })
+    } else {
+        None
+    };
+    let async_trait = async_trait_attr(opts);
+    let dyn_api = if opts.dyn_dispatch {
+        Some(quote! {
+            /// The API, as the server holds it: handlers aren't generic over its type
+            pub type DynApi = std::sync::Arc<dyn #trait_name>;
+        })
+    } else {
+        None
+    };
+    let state = if opts.shared_state.is_empty() {
+        None
+    } else {
+        let (names, types): (Vec<_>, Vec<_>) = shared_state(opts)?.into_iter().unzip();
+        Some(quote! {
+            /// The shared state passed to the API methods, registered on the app by
+            /// `server::serve`. Clones share the state.
+            #[derive(Clone)]
+            pub struct State {
+                #(pub #names: AxData<#types>,)*
+            }
+
+            impl State {
+                pub fn new(#(#names: #types),*) -> Self {
+                    State {
+                        #(#names: AxData::new(#names),)*
+                    }
+                }
+            }
+        })
+    };
+    Ok(quote! {
         #descr
-        #[hsr::async_trait::async_trait(?Send)]
+        #async_trait
         pub trait #trait_name: 'static + Send + Sync {
+            #error_hooks
+
+            /// Called by `server::serve` before the server starts, e.g. to warm caches
+            /// or spawn background jobs
+            async fn on_start(&self) {}
+
+            /// Called by `server::serve` once the server has shut down, e.g. to flush state
+            async fn on_shutdown(&self) {}
+
+            /// Register background tasks, which `server::serve` runs while the server
+            /// is running, see `hsr::tasks`
+            fn schedule(&self, _tasks: &mut hsr::tasks::Tasks) {}
+
+            #fallbacks
+
+            #authorize_hook
+
+            #audit_hook
+
             #methods
         }
-    }
+
+        #dyn_api
+
+        #state
+    })
 }
 
 fn generate_rust_dispatchers(
-    routes: &Map<String, Vec<Route>>,
+    routes: &[&Route],
     trait_name: &TypeName,
-) -> TokenStream {
-    let mut dispatchers = TokenStream::new();
-    for (_api_path, route_methods) in routes {
-        for route in route_methods {
-            dispatchers.extend(route.generate_dispatcher(trait_name));
+    opts: &GenerationOptions,
+) -> Result<TokenStream> {
+    generate_each(routes, |route| match opts.backend {
+        Backend::Actix => route.generate_dispatcher(trait_name, opts),
+        Backend::Axum => route.generate_axum_handler(trait_name),
+    })
+}
+
+/// Generate the code for each item and concatenate it, keeping the order of the items.
+/// With the `parallel` feature the items are generated in parallel, which pays off
+/// for specs with hundreds of paths and schemas.
+pub(crate) fn generate_each<T, F>(items: &[T], generate: F) -> Result<TokenStream>
+where
+    T: Sync,
+    F: Fn(&T) -> Result<TokenStream> + Sync,
+{
+    #[cfg(feature = "parallel")]
+    {
+        use rayon::prelude::*;
+        // TokenStreams can't be sent between threads, so they travel as source
+        let sources = items
+            .par_iter()
+            .map(|item| generate(item).map(|tokens| tokens.to_string()))
+            .collect::<Result<Vec<_>>>()?;
+        let mut tokens = TokenStream::new();
+        for source in sources {
+            let item: TokenStream = source
+                .parse()
+                .map_err(|e: proc_macro2::LexError| Error::BadCodegen(format!("{:?}", e)))?;
+            tokens.extend(item);
+        }
+        Ok(tokens)
+    }
+    #[cfg(not(feature = "parallel"))]
+    {
+        let mut tokens = TokenStream::new();
+        for item in items {
+            tokens.extend(generate(item)?);
         }
+        Ok(tokens)
     }
-    quote! {#dispatchers}
 }
 
-fn generate_rust_server(routemap: &Map<String, Vec<Route>>, trait_name: &TypeName) -> TokenStream {
-    let resources: Vec<_> = routemap
-        .iter()
-        .map(|(path, routes)| {
-            let (meth, opid): (Vec<_>, Vec<_>) = routes
-                .iter()
-                .map(|route| {
-                    (
-                        ident(route.method().to_string().to_snake_case()),
-                        route.operation_id(),
-                    )
-                })
-                .unzip();
-            quote! {
-                web::resource(#path)
-                    #(.route(web::#meth().to(#opid::<A>)))*
+fn generate_rust_server(
+    routemap: &Map<String, Vec<Route>>,
+    trait_name: &TypeName,
+    dispatchers: TokenStream,
+    opts: &GenerationOptions,
+) -> Result<TokenStream> {
+    let stub = if opts.stub {
+        let methods = routemap
+            .values()
+            .flatten()
+            .map(|route| route.generate_stub_method(opts))
+            .collect::<Result<Vec<_>>>()?;
+        let async_trait = async_trait_attr(opts);
+        let error_type = if opts.fallible {
+            Some(quote! { type Error = std::convert::Infallible; })
+        } else {
+            None
+        };
+        let authorize = if opts.authorization {
+            Some(quote! {
+                async fn authorize(&self, input: hsr::authz::AuthzInput) -> Result<(), hsr::authz::Denied> {
+                    Ok(())
+                }
+            })
+        } else {
+            None
+        };
+        Some(quote! {
+            /// A placeholder implementation of the API, in which every operation
+            /// panics with `todo!()` (and every call is authorized). Replace the
+            /// operations one at a time.
+            #[derive(Debug, Clone, Copy, Default)]
+            pub struct Stub;
+
+            #[allow(unused_variables)]
+            #async_trait
+            impl #trait_name for Stub {
+                #error_type
+                #authorize
+                #(#methods)*
             }
         })
-        .collect();
+    } else {
+        None
+    };
 
-    let server = quote! {
-        #[allow(dead_code)]
-        pub mod server {
-            use super::*;
+    let serve = match opts.backend {
+        Backend::Actix => generate_actix_serve(routemap, trait_name, opts)?,
+        Backend::Axum => generate_axum_serve(routemap, trait_name, opts)?,
+    };
+    // the checks of the generated handlers, which are kept for overriding ones
+    let overridden = if opts.backend == Backend::Actix {
+        let checks = routemap
+            .values()
+            .flatten()
+            .map(|route| route.generate_override_checks(trait_name, opts))
+            .collect::<Result<Vec<_>>>()?;
+        Some(quote! {
+            /// What the generated handlers run around the API, run around the
+            /// handlers overriding them instead
+            pub mod overridden {
+                #[allow(unused_imports)]
+                use super::*;
 
-            fn configure_hsr<A: #trait_name>(cfg: &mut actix_web::web::ServiceConfig) {
-                cfg #(.service(#resources))*;
+                #(#checks)*
             }
+        })
+    } else {
+        None
+    };
 
-            /// Serve the API on a given host.
-            /// Once started, the server blocks indefinitely.
-            pub async fn serve<A: #trait_name>(api: A, cfg: hsr::Config) -> std::io::Result<()> {
-                // We register the user-supplied Api as a Data item.
-                // You might think it would be cleaner to generate out API trait
-                // to not take "self" at all (only inherent impls) and then just
-                // have Actix call those functions directly, like `.to(Api::func)`.
-                // However we also want a way for the user to pass in arbitrary state to
-                // handlers, so we kill two birds with one stone by stashing the Api
-                // as data, pulling then it back out upon each request and calling
-                // the handler as a method
-                let api = AxData::new(api);
+    Ok(quote! {
+        #[allow(dead_code)]
+        pub mod server {
+            use super::*;
 
-                let server = HttpServer::new(move || {
+            /// The handlers of the operations, which the server routes requests to.
+            /// Replace one with `hsr::Config::override_handler`.
+            pub mod handlers {
+                #[allow(unused_imports)]
+                use super::*;
+
+                #dispatchers
+
+                #overridden
+            }
+
+            #stub
+
+            #serve
+        }
+    })
+}
+
+fn generate_actix_serve(
+    routemap: &Map<String, Vec<Route>>,
+    trait_name: &TypeName,
+    opts: &GenerationOptions,
+) -> Result<TokenStream> {
+    let paths: Vec<_> = routemap.keys().collect();
+    let (generics, turbofish, api_ty) = handler_api(opts, quote! { #trait_name });
+    // with `dyn_dispatch`, the API is boxed up before anything else sees it
+    let hold_api = if opts.dyn_dispatch {
+        quote! {
+            let api: DynApi = std::sync::Arc::new(api);
+            let api = AxData::new(api);
+        }
+    } else {
+        quote! { let api = AxData::new(api); }
+    };
+    let resources: Vec<_> = routemap
+        .iter()
+        .map(|(path, routes)| {
+            // the operation of each method served, `HEAD` being answered by `GET`
+            // unless the spec says otherwise
+            let mut served: Vec<_> = routes
+                .iter()
+                .map(|route| (route.method().to_string(), route))
+                .collect();
+            let get = served.iter().find(|(meth, _)| meth == "GET").map(|(_, route)| *route);
+            if let (true, Some(get)) = (opts.auto_head, get) {
+                if !served.iter().any(|(meth, _)| meth == "HEAD") {
+                    served.push(("HEAD".into(), get));
+                }
+            }
+            let (meth, opid): (Vec<_>, Vec<_>) = served
+                .iter()
+                .map(|(meth, route)| (ident(meth.to_snake_case()), route.operation_id()))
+                .unzip();
+            let http_meth = served.iter().map(|(meth, _)| ident(meth));
+            let override_meth = http_meth.clone();
+            let allowed = served.iter().map(|(meth, _)| meth);
+            let opid_str: Vec<_> = opid.iter().map(|opid| opid.to_string()).collect();
+            let operations = quote! {
+                &[#((hsr::actix_http::http::Method::#http_meth, #opid_str)),*]
+            };
+            let rate_limits: Vec<_> = served
+                .iter()
+                .filter_map(|(meth, route)| {
+                    route.rate_limit().map(|limit| {
+                        let meth = ident(meth);
+                        let opid = route.operation_id().to_string();
+                        quote! { .limit(hsr::actix_http::http::Method::#meth, #opid, #limit) }
+                    })
+                })
+                .collect();
+            let rate_limiter = if rate_limits.is_empty() {
+                None
+            } else {
+                Some(quote! {
+                    .wrap(hsr::rate_limit::RateLimiter::new(rate_limit_store.clone())#(#rate_limits)*)
+                })
+            };
+            let idempotent: Vec<_> = routes
+                .iter()
+                .filter_map(|route| {
+                    route.idempotency().map(|idempotency| {
+                        let meth = ident(route.method());
+                        let opid = route.operation_id().to_string();
+                        quote! { .operation(hsr::actix_http::http::Method::#meth, #opid, #idempotency) }
+                    })
+                })
+                .collect();
+            let idempotency_keys = if idempotent.is_empty() {
+                None
+            } else {
+                Some(quote! {
+                    .wrap(hsr::idempotency::IdempotencyKeys::new(idempotency_store.clone())#(#idempotent)*)
+                })
+            };
+            let cache_policies: Vec<_> = served
+                .iter()
+                .filter_map(|(meth, route)| {
+                    route.cache().map(|policy| {
+                        let meth = ident(meth);
+                        quote! { .policy(hsr::actix_http::http::Method::#meth, #policy) }
+                    })
+                })
+                .collect();
+            let cache_headers = if cache_policies.is_empty() {
+                None
+            } else {
+                Some(quote! {
+                    .wrap(hsr::cache::CacheHeaders::new()#(#cache_policies)*)
+                })
+            };
+            quote! {
+                web::resource(#path)
+                    .app_data(hsr::dispatch::json_config())
+                    #(.route(overrides.route(#opid_str, web::#meth(), |route| route.to(handlers::#opid #turbofish))))*
+                    .default_service(web::route().to(|data: AxData<#api_ty>, req: HttpRequest| async move {
+                        data.method_not_allowed(&req, &[#(#allowed),*])
+                    }))
+                    .wrap(overrides.checks()#(.operation(
+                        hsr::actix_http::http::Method::#override_meth,
+                        #opid_str,
+                        handlers::overridden::#opid #turbofish,
+                    ))*)
+                    .wrap(Condition::new(
+                        validation != hsr::validate::ResponseValidation::Off,
+                        hsr::validate::ValidateResponses::new(&spec, #path, validation),
+                    ))
+                    #cache_headers
+                    #idempotency_keys
+                    #rate_limiter
+                    .wrap(hsr::access_log::LogOperations::new(#operations))
+                    .wrap(hsr::report::ErrorReports::new(error_reporter.clone(), #operations))
+                    .wrap(hsr::otel::Tracing::new(#operations))
+            }
+        })
+        .collect();
+
+    let version_route = if opts.version_route {
+        Some(quote! { API_INFO.configure(cfg); })
+    } else {
+        None
+    };
+    // the shared state is registered alongside the API
+    let state_names: Vec<_> = shared_state(opts)?
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect();
+    let state_arg_opt = if state_names.is_empty() {
+        None
+    } else {
+        Some(quote! { state: State, })
+    };
+    let lambda = if opts.lambda {
+        Some(quote! {
+            /// Run the API as an AWS Lambda function, until the lambda runtime shuts down.
+            /// This must be called from within a `tokio` (1.x) runtime.
+            pub async fn lambda_handler<A: #trait_name>(
+                api: A,
+                #state_arg_opt
+            ) -> Result<(), hsr::lambda::Error> {
+                #hold_api
+                api.on_start().await;
+                let rate_limit_store: std::sync::Arc<dyn hsr::rate_limit::RateLimitStore> =
+                    std::sync::Arc::new(hsr::rate_limit::InMemoryStore::default());
+                let idempotency_store: std::sync::Arc<dyn hsr::idempotency::IdempotencyStore> =
+                    std::sync::Arc::new(hsr::idempotency::InMemoryStore::default());
+                let handler_api = api.clone();
+                let result = hsr::lambda::run(move || {
                     App::new()
-                        .app_data(api.clone())
+                        .app_data(handler_api)
+                        #(.app_data(state.#state_names.clone()))*
+                        .default_service(web::route().to(not_found #turbofish))
                         .wrap(Logger::default())
-                        .configure(|cfg| hsr::configure_spec(cfg, JSON_SPEC, UI_TEMPLATE))
-                        .configure(configure_hsr::<A>)
-                });
+                        .wrap(hsr::request_id::RequestIds)
+                        .configure(configure_spec)
+                        .configure(|cfg| {
+                            configure_hsr #turbofish(
+                                cfg,
+                                hsr::validate::ResponseValidation::Off,
+                                rate_limit_store.clone(),
+                                idempotency_store.clone(),
+                                None,
+                                &hsr::dispatch::Overrides::default(),
+                            )
+                        })
+                })
+                .await;
+                api.on_shutdown().await;
+                result
+            }
+        })
+    } else {
+        None
+    };
 
-                // Bind to socket
-                let server = if let Some(ssl) = cfg.ssl {
-                    server.bind_openssl((cfg.host.host_str().unwrap(), cfg.host.port().unwrap()), ssl)
-                } else {
-                    server.bind((cfg.host.host_str().unwrap(), cfg.host.port().unwrap()))
-                }?;
+    Ok(quote! {
+        /// Serve the spec and the ui, with the `ui` feature of `hsr`
+        #[allow(unused_variables)]
+        pub(super) fn configure_spec(cfg: &mut actix_web::web::ServiceConfig) {
+            hsr::if_ui! {
+                JSON_SPEC.configure(cfg, UI_TEMPLATE);
+            }
+        }
 
-                // run!
-                server.run().await
+        fn configure_hsr #generics(
+            cfg: &mut actix_web::web::ServiceConfig,
+            validation: hsr::validate::ResponseValidation,
+            rate_limit_store: std::sync::Arc<dyn hsr::rate_limit::RateLimitStore>,
+            idempotency_store: std::sync::Arc<dyn hsr::idempotency::IdempotencyStore>,
+            error_reporter: Option<std::sync::Arc<dyn hsr::report::ErrorReporter>>,
+            overrides: &hsr::dispatch::Overrides,
+        ) {
+            use hsr::actix_web::middleware::Condition;
+            let spec = validation_spec().for_validation(validation);
+            cfg #(.service(#resources))*;
+            #version_route
+        }
+
+        /// Answers requests for paths the API doesn't have
+        async fn not_found #generics(data: AxData<#api_ty>, req: HttpRequest) -> HttpResponse {
+            data.not_found(&req)
+        }
+
+        /// Serve the API on a given host.
+        /// Once started, the server blocks indefinitely.
+        pub async fn serve<A: #trait_name>(
+            api: A,
+            #state_arg_opt
+            cfg: hsr::Config,
+        ) -> std::io::Result<()> {
+            cfg.overrides.check(OPERATIONS)?;
+            // We register the user-supplied Api as a Data item.
+            // You might think it would be cleaner to generate out API trait
+            // to not take "self" at all (only inherent impls) and then just
+            // have Actix call those functions directly, like `.to(Api::func)`.
+            // However we also want a way for the user to pass in arbitrary state to
+            // handlers, so we kill two birds with one stone by stashing the Api
+            // as data, pulling then it back out upon each request and calling
+            // the handler as a method
+            #hold_api
+            let validation = cfg.response_validation;
+            let access_log = cfg.access_log;
+            let rate_limit_store = cfg.rate_limit_store;
+            let idempotency_store = cfg.idempotency_store;
+            let error_reporter = cfg.error_reporter;
+            let overrides = cfg.overrides;
+            let compression = hsr::compression::CompressionPolicy::new(cfg.compression);
+            let trusted_proxies = cfg.trusted_proxies;
+            let hsts = cfg.hsts;
+            let security_headers = cfg.security_headers;
+            let trailing_slash = cfg.trailing_slash;
+            let tls = cfg.ssl.is_some();
+
+            let handler_api = api.clone();
+            let server = HttpServer::new(move || {
+                App::new()
+                    .app_data(handler_api.clone())
+                    #(.app_data(state.#state_names.clone()))*
+                    .default_service(web::route().to(not_found #turbofish))
+                    .wrap(hsr::normalize::TrailingSlashes::new(trailing_slash, &[#(#paths),*]))
+                    .wrap(hsr::actix_web::middleware::Condition::new(
+                        trailing_slash != hsr::normalize::TrailingSlash::Strict,
+                        hsr::actix_web::middleware::NormalizePath::default(),
+                    ))
+                    // the structured access log replaces the default one
+                    .wrap(hsr::actix_web::middleware::Condition::new(
+                        access_log.is_none(),
+                        Logger::default(),
+                    ))
+                    .wrap(hsr::actix_web::middleware::Condition::new(
+                        access_log.is_some(),
+                        access_log.clone().unwrap_or_default(),
+                    ))
+                    .wrap(hsr::request_id::RequestIds)
+                    .wrap(hsr::https::HstsHeaders::new(hsts.clone()))
+                    .wrap(hsr::security_headers::AddSecurityHeaders::new(security_headers.clone()))
+                    .wrap(hsr::forwarded::ForwardedHeaders::new(trusted_proxies.clone()))
+                    .wrap(compression.clone())
+                    .wrap(hsr::actix_web::middleware::Compress::default())
+                    .configure(configure_spec)
+                    .configure(|cfg| {
+                        configure_hsr #turbofish(
+                            cfg,
+                            validation,
+                            rate_limit_store.clone(),
+                            idempotency_store.clone(),
+                            error_reporter.clone(),
+                            &overrides,
+                        )
+                    })
+            });
+
+            // Bind to sockets, unless some were inherited (see `hsr::listen`)
+            let addrs = cfg.bind_addrs()?;
+            let listeners = hsr::listen::inherited()?;
+            let server = if let Some(ssl) = cfg.ssl {
+                match listeners.into_iter().next() {
+                    Some(listener) => server.listen_openssl(listener, ssl),
+                    None => server.bind_openssl(&addrs[..], ssl),
+                }
+            } else if listeners.is_empty() {
+                server.bind(&addrs[..])
+            } else {
+                listeners
+                    .into_iter()
+                    .try_fold(server, |server, listener| server.listen(listener))
+            }?;
+            // plain HTTP is redirected to HTTPS
+            let redirect = match cfg.redirect_http {
+                Some(port) if tls => Some(hsr::https::redirect_server(&cfg.host, port)?),
+                _ => None,
+            };
+
+            // run!
+            api.on_start().await;
+            let mut tasks = hsr::tasks::Tasks::new();
+            api.schedule(&mut tasks);
+            let tasks = tasks.start();
+            let result = server.run().await;
+            if let Some(redirect) = redirect {
+                redirect.stop(true).await;
             }
+            tasks.stop().await;
+            api.on_shutdown().await;
+            result
+        }
+
+        /// Prepare the API to be served under a prefix, alongside other APIs, with
+        /// `hsr::compose::Composite`. Response validation and the stores are taken from
+        /// `cfg`; the other settings (such as the access log) from the config the composite
+        /// is served with.
+        pub fn mount<A: #trait_name>(
+            api: A,
+            #state_arg_opt
+            cfg: &hsr::Config,
+        ) -> hsr::compose::Mount {
+            #hold_api
+            let validation = cfg.response_validation;
+            let rate_limit_store = cfg.rate_limit_store.clone();
+            let idempotency_store = cfg.idempotency_store.clone();
+            let error_reporter = cfg.error_reporter.clone();
+            let overrides = cfg.overrides.clone();
+            hsr::compose::Mount::new(API_TITLE, move |prefix, service_config| {
+                let rate_limit_store = rate_limit_store.clone();
+                let idempotency_store = idempotency_store.clone();
+                let error_reporter = error_reporter.clone();
+                service_config.service(
+                    web::scope(prefix)
+                        .register_data(api.clone())
+                        #(.register_data(state.#state_names.clone()))*
+                        .default_service(web::route().to(not_found #turbofish))
+                        .configure(configure_spec)
+                        .configure(|cfg| {
+                            configure_hsr #turbofish(
+                                cfg,
+                                validation,
+                                rate_limit_store,
+                                idempotency_store,
+                                error_reporter,
+                                &overrides,
+                            )
+                        }),
+                );
+            })
         }
+
+        #lambda
+    })
+}
+
+fn generate_axum_serve(
+    routemap: &Map<String, Vec<Route>>,
+    trait_name: &TypeName,
+    opts: &GenerationOptions,
+) -> Result<TokenStream> {
+    let routes: Vec<_> = routemap
+        .iter()
+        .map(|(path, routes)| {
+            let path = RoutePath::analyse(path)?.to_axum();
+            let (first, rest) = match routes.split_first() {
+                Some(split) => split,
+                None => invalid!("Path '{}' has no operations", path),
+            };
+            let first_meth = ident(first.method().to_string().to_snake_case());
+            let first_opid = first.operation_id();
+            let (meth, opid): (Vec<_>, Vec<_>) = rest
+                .iter()
+                .map(|route| {
+                    (
+                        ident(route.method().to_string().to_snake_case()),
+                        route.operation_id(),
+                    )
+                })
+                .unzip();
+            Ok(quote! {
+                .route(#path, routing::#first_meth(handlers::#first_opid::<A>)#(.#meth(handlers::#opid::<A>))*)
+            })
+        })
+        .collect::<Result<_>>()?;
+
+    let version_route = if opts.version_route {
+        Some(quote! {
+            .route("/version", routing::get(|| async { hsr::axum::Json(API_INFO) }))
+        })
+    } else {
+        None
+    };
+    let lambda = if opts.lambda {
+        Some(quote! {
+            /// Run the API as an AWS Lambda function, until the lambda runtime shuts down.
+            pub async fn lambda_handler<A: #trait_name>(api: A) -> Result<(), hsr::lambda::Error> {
+                let api = std::sync::Arc::new(api);
+                api.on_start().await;
+                let result = hsr::lambda_http::run(shared_router(api.clone())).await;
+                api.on_shutdown().await;
+                result
+            }
+        })
+    } else {
+        None
     };
-    server
+
+    Ok(quote! {
+        /// Build an axum `Router` serving the API, and the spec and ui
+        pub fn router<A: #trait_name>(api: A) -> hsr::axum::Router {
+            shared_router(std::sync::Arc::new(api))
+        }
+
+        /// Build the `Router` around an API which is also used elsewhere
+        fn shared_router<A: #trait_name>(api: std::sync::Arc<A>) -> hsr::axum::Router {
+            use hsr::axum::routing;
+            let router = hsr::axum::Router::new();
+            hsr::if_ui! {
+                let router = match JSON_SPEC.get() {
+                    Some(spec) => router
+                        .route("/spec.json", routing::get(move || async move {
+                            ([(hsr::axum::http::header::CONTENT_TYPE, "application/json")], spec)
+                        }))
+                        .route("/ui.html", routing::get(|| async {
+                            hsr::axum::response::Html(UI_TEMPLATE)
+                        })),
+                    None => router,
+                };
+            }
+            router
+                #(#routes)*
+                #version_route
+                .with_state(api)
+        }
+
+        /// Serve the API on a given host.
+        /// Once started, the server blocks indefinitely.
+        ///
+        /// This must be run within a `tokio` (1.x) runtime.
+        pub async fn serve<A: #trait_name>(api: A, cfg: hsr::Config) -> std::io::Result<()> {
+            use std::io::{Error, ErrorKind};
+            if cfg.ssl.is_some() {
+                return Err(Error::new(ErrorKind::Other, "TLS is not supported by the axum backend"));
+            }
+            if !cfg.also_bind.is_empty() {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    "Binding several addresses is not supported by the axum backend",
+                ));
+            }
+            if !cfg.overrides.is_empty() {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    "Overriding handlers is not supported by the axum backend",
+                ));
+            }
+            let addr = cfg
+                .host
+                .socket_addrs(|| None)?
+                .into_iter()
+                .next()
+                .ok_or_else(|| Error::new(ErrorKind::Other, "Could not resolve host"))?;
+            let api = std::sync::Arc::new(api);
+            api.on_start().await;
+            let result = hsr::axum::Server::bind(&addr)
+                .serve(shared_router(api.clone()).into_make_service())
+                .await
+                .map_err(|e| Error::new(ErrorKind::Other, e));
+            api.on_shutdown().await;
+            result
+        }
+
+        #lambda
+    })
+}
+
+/// The `paths` module, with a URL builder for each operation
+fn generate_rust_paths(
+    routes: &Map<String, Vec<Route>>,
+    opts: &GenerationOptions,
+) -> Result<TokenStream> {
+    let builders = routes
+        .values()
+        .flatten()
+        .filter(|route| !(opts.public_client && route.is_internal()))
+        .map(Route::generate_url_builder)
+        .collect::<Result<Vec<_>>>()?;
+    Ok(quote! {
+        /// Typed builders for the URLs of the operations
+        #[allow(dead_code)]
+        pub mod paths {
+            #(#builders)*
+        }
+    })
+}
+
+/// The `fuzz` module, with an entry point for fuzzing the extraction of the requests
+/// of each operation
+fn generate_rust_fuzz(routes: &Map<String, Vec<Route>>) -> Result<TokenStream> {
+    let targets = routes
+        .values()
+        .flatten()
+        .map(Route::generate_fuzz_target)
+        .collect::<Result<Vec<_>>>()?;
+    Ok(quote! {
+        /// Entry points for `cargo fuzz` targets, feeding arbitrary bytes to the
+        /// extraction of the parameters and bodies of the operations
+        #[allow(dead_code)]
+        pub mod fuzz {
+            use super::*;
+
+            #(#targets)*
+        }
+    })
+}
+
+/// As `generate_fuzz_targets_from_yaml_source`, reading the spec from a file
+pub fn generate_fuzz_targets_from_yaml_file(
+    yaml: impl AsRef<Path>,
+    api: &str,
+) -> Result<Vec<(String, String)>> {
+    let f = fs::File::open(yaml)?;
+    generate_fuzz_targets_from_yaml_source(f, api)
+}
+
+/// The `cargo fuzz` targets of a spec, as (file name, source), each calling the
+/// entry point of an operation in the `fuzz` module of the generated code, at the
+/// path `api` (e.g. `my_crate::api`). See `GenerationOptions::fuzz_targets`.
+pub fn generate_fuzz_targets_from_yaml_source(
+    yaml: impl std::io::Read,
+    api: &str,
+) -> Result<Vec<(String, String)>> {
+    if syn::parse_str::<syn::Path>(api).is_err() {
+        invalid!("Bad path '{}' of the generated code", api);
+    }
+    let model = ir::parse_spec(yaml)?;
+    let targets = model
+        .routes
+        .values()
+        .flatten()
+        .filter(|route| route.websocket().is_none())
+        .map(|route| {
+            let opid = route.operation_id();
+            let source = format!(
+                "#![no_main]\n\nlibfuzzer_sys::fuzz_target!(|data: &[u8]| {}::fuzz::{}(data));\n",
+                api, opid
+            );
+            (format!("{}.rs", opid), source)
+        })
+        .collect();
+    Ok(targets)
+}
+
+/// The `links` module, with a constructor for each link to another operation, building
+/// the URL of the target from its path parameters
+fn generate_rust_links(
+    routes: &Map<String, Vec<Route>>,
+    opts: &GenerationOptions,
+) -> Result<TokenStream> {
+    let included = |route: &&Route| !(opts.public_client && route.is_internal());
+    let mut modules = Vec::new();
+    for route in routes.values().flatten().filter(included) {
+        let mut constructors = Vec::new();
+        for (name, link) in route.links()? {
+            let target_id = match &link.operation_id {
+                Some(target_id) => target_id,
+                // TODO follow operationRef
+                None => continue,
+            };
+            let target = match routes
+                .values()
+                .flatten()
+                .find(|r| r.operation_id() == target_id)
+            {
+                Some(target) => target,
+                None => invalid!(
+                    "Link '{}' of operation '{}' refers to unknown operation '{}'",
+                    name,
+                    route.operation_id(),
+                    target_id
+                ),
+            };
+            if !included(&target) {
+                continue;
+            }
+            let fn_name: Ident = name.to_snake_case().parse()?;
+            let (path_names, path_types) = target.path_args()?;
+            let target_module = target.url_module_name();
+            let mut doc = format!("The `{}` link, to `{}`", name, target_id);
+            if let Some(description) = &link.description {
+                doc.push_str(&format!(": {}", description));
+            }
+            for (param, expr) in &link.parameters {
+                doc.push_str(&format!("\n\n`{}` is `{}`", param, expr));
+            }
+            let doc = doc_comment(doc);
+            let allow_case = allow_non_snake_case(path_names.iter().map(|name| name.as_str()));
+            constructors.push(quote! {
+                #doc
+                #allow_case
+                pub fn #fn_name(
+                    base: &Url,
+                    #(#path_names: #path_types),*
+                ) -> Result<Url, hsr::path::DotSegment> {
+                    paths::#target_module::url(base, #(#path_names),*)
+                }
+            });
+        }
+        if !constructors.is_empty() {
+            let module = route.url_module_name();
+            modules.push(quote! {
+                pub mod #module {
+                    use super::super::*;
+
+                    #(#constructors)*
+                }
+            });
+        }
+    }
+    if modules.is_empty() {
+        return Ok(TokenStream::new());
+    }
+    Ok(quote! {
+        /// The URLs of the operations linked from the responses of each operation
+        #[allow(dead_code)]
+        pub mod links {
+            #(#modules)*
+        }
+    })
 }
 
-fn generate_rust_client(routes: &Map<String, Vec<Route>>) -> TokenStream {
+fn generate_rust_client(
+    routes: &Map<String, Vec<Route>>,
+    trait_name: &TypeName,
+    servers: TokenStream,
+    opts: &GenerationOptions,
+) -> Result<TokenStream> {
     let mut method_impls = TokenStream::new();
+    let mut decoders = TokenStream::new();
+    let mut trait_methods = TokenStream::new();
+    let mut trait_impls = TokenStream::new();
+    let mut in_process_impls = TokenStream::new();
+    // the in-process client passes the shared state, like the server
+    let (state_field_opt, state_arg_opt, state_opt, clone_state_opt) =
+        if opts.shared_state.is_empty() {
+            (None, None, None, None)
+        } else {
+            (
+                Some(quote! { state: State, }),
+                Some(quote! { , state: State }),
+                Some(quote! { state, }),
+                Some(quote! { state: self.state.clone(), }),
+            )
+        };
     for (_, route_methods) in routes {
         for route in route_methods {
-            method_impls.extend(route.generate_client_impl());
+            if opts.public_client && route.is_internal() {
+                continue;
+            }
+            method_impls.extend(route.generate_client_impl()?);
+            method_impls.extend(route.generate_pagination_client_impl()?);
+            decoders.extend(route.generate_client_decoder()?);
+            trait_methods.extend(route.generate_client_trait_method()?);
+            trait_impls.extend(route.generate_client_trait_impl()?);
+            in_process_impls.extend(route.generate_in_process_impl(opts)?);
         }
     }
 
-    quote! {
+    Ok(quote! {
         #[allow(dead_code)]
         #[allow(unused_imports)]
         pub mod client {
@@ -618,9 +2148,16 @@ fn generate_rust_client(routes: &Map<String, Vec<Route>>) -> TokenStream {
             use hsr::futures::future::{err as fut_err, ok as fut_ok};
             use hsr::serde_urlencoded;
 
+            #[derive(Clone)]
             pub struct Client {
                 domain: Url,
                 inner: ActixClient,
+                request_id: Option<String>,
+                options: hsr::CallOptions,
+                cache: Option<hsr::cache::ResponseCache>,
+                circuit_breaker: Option<hsr::circuit::CircuitBreaker>,
+                cassette: Option<hsr::cassette::Cassette>,
+                observer: Option<std::sync::Arc<dyn hsr::observe::ClientObserver>>,
             }
 
             impl Client {
@@ -628,214 +2165,3410 @@ fn generate_rust_client(routes: &Map<String, Vec<Route>>) -> TokenStream {
                 pub fn new(domain: Url) -> Self {
                     Client {
                         domain: domain,
-                        inner: ActixClient::new()
+                        inner: ActixClient::new(),
+                        request_id: None,
+                        options: hsr::CallOptions::default(),
+                        cache: None,
+                        circuit_breaker: None,
+                        cassette: None,
+                        observer: None,
                     }
                 }
 
-                #method_impls
-            }
-        }
-    }
-}
+                /// A client which sends `id` as the `X-Request-Id` of its requests.
+                /// Use it to pass on the id of the request being handled.
+                pub fn with_request_id(&self, id: impl std::fmt::Display) -> Self {
+                    Client {
+                        request_id: Some(id.to_string()),
+                        ..self.clone()
+                    }
+                }
 
-pub fn generate_from_yaml_file(yaml: impl AsRef<Path>) -> Result<String> {
-    // TODO add generate_from_json_file
-    let f = fs::File::open(yaml)?;
-    generate_from_yaml_source(f)
-}
+                /// A client which answers the operations with an `x-hsr-cache` policy from
+                /// `cache` while the responses are fresh. Clones share the cache.
+                pub fn with_cache(&self, cache: hsr::cache::ResponseCache) -> Self {
+                    Client {
+                        cache: Some(cache),
+                        ..self.clone()
+                    }
+                }
 
-pub fn generate_from_yaml_source(mut yaml: impl std::io::Read) -> Result<String> {
-    // Read the yaml file into an OpenAPI struct
-    let mut openapi_source = String::new();
-    yaml.read_to_string(&mut openapi_source)?;
-    let api: OpenAPI = serde_yaml::from_str(&openapi_source)?;
+                /// A client which fails its calls with `ClientError::CircuitOpen`, without
+                /// making them, while `breaker` is open. Clones share the breaker.
+                pub fn with_circuit_breaker(&self, breaker: hsr::circuit::CircuitBreaker) -> Self {
+                    Client {
+                        circuit_breaker: Some(breaker),
+                        ..self.clone()
+                    }
+                }
 
-    // pull out various sections of the OpenAPI object which will be useful
-    // let components = api.components.take().unwrap_or_default();
-    // let schema_lookup = components.schemas;
-    // let response_lookup = components.responses;
-    // let parameters_lookup = components.parameters;
-    // let req_body_lookup = components.request_bodies;
+                /// A client which records its calls to `cassette`, or replays them from it
+                /// instead of calling the server
+                pub fn with_cassette(&self, cassette: hsr::cassette::Cassette) -> Self {
+                    Client {
+                        cassette: Some(cassette),
+                        ..self.clone()
+                    }
+                }
+
+                /// A client which tells `observer` about its calls, e.g. to collect metrics
+                pub fn with_observer(
+                    &self,
+                    observer: impl hsr::observe::ClientObserver + 'static,
+                ) -> Self {
+                    Client {
+                        observer: Some(std::sync::Arc::new(observer)),
+                        ..self.clone()
+                    }
+                }
 
-    // Generate the spec as json. This will be embedded in the binary
-    let json_spec = serde_json::to_string(&api).expect("Bad api serialization");
+                /// A client which also applies `options` (extra headers, query parameters,
+                /// a timeout...) to all its calls, after those it already had
+                pub fn with(&self, options: hsr::CallOptions) -> Self {
+                    Client {
+                        options: self.options.clone().merge(options),
+                        ..self.clone()
+                    }
+                }
 
-    let trait_name = api_trait_name(&api);
+                /// A client with its own pool of connections, with the given settings
+                pub fn with_connection_options(&self, options: &hsr::ConnectionOptions) -> Self {
+                    Client {
+                        inner: options.build(),
+                        ..self.clone()
+                    }
+                }
 
-    // Walk the API to collect types and routes
-    debug!("Gather types");
-    let (type_lookup, routes) = walk::walk_api(&api)?;
+                /// A client sending its requests to `url` instead
+                pub fn with_base_url(&self, url: Url) -> Self {
+                    Client {
+                        domain: url,
+                        ..self.clone()
+                    }
+                }
 
-    // Generate type definitions
-    debug!("Generate API types");
-    let rust_api_types = walk::generate_rust_types(&type_lookup)?;
+                #method_impls
+            }
 
-    // Response types are slightly special cases (they need to implement Responder
-    debug!("Generate response types");
-    let rust_response_types: Vec<_> = routes
-        .values()
-        .map(|routes| routes.iter().map(|route| route.generate_return_type()))
-        .flatten()
-        .collect();
+            /// The operations of the API, as made by a client. Unlike `Client` this
+            /// may be a trait object, so that an application can be given the real
+            /// client, a mock, or an in-process implementation at runtime.
+            #[hsr::async_trait::async_trait(?Send)]
+            pub trait DynClient {
+                #trait_methods
+            }
 
-    debug!("Generate API trait");
-    let rust_trait = generate_rust_interface(&routes, &api.info.title, &trait_name);
+            #[hsr::async_trait::async_trait(?Send)]
+            impl DynClient for Client {
+                #trait_impls
+            }
 
-    debug!("Generate dispatchers");
-    let rust_dispatchers = generate_rust_dispatchers(&routes, &trait_name);
+            /// Calls the operations of an implementation of the API directly, rather than
+            /// over HTTP, e.g. for fast integration tests, or to deploy services together.
+            /// Requests and responses are still put through their wire formats (and
+            /// content negotiation), so they are checked as they would be by the server
+            /// and the client.
+            pub struct InProcess<A> {
+                api: std::sync::Arc<A>,
+                #state_field_opt
+            }
 
-    debug!("Generate server");
-    let rust_server = generate_rust_server(&routes, &trait_name);
+            impl<A> InProcess<A> {
+                pub fn new(api: A #state_arg_opt) -> Self {
+                    InProcess {
+                        api: std::sync::Arc::new(api),
+                        #state_opt
+                    }
+                }
 
-    debug!("Generate client");
-    let rust_client = generate_rust_client(&routes);
+                /// The implementation of the API
+                pub fn api(&self) -> &A {
+                    &self.api
+                }
+            }
 
-    let code = quote! {
-        #[allow(dead_code)]
+            impl<A> Clone for InProcess<A> {
+                fn clone(&self) -> Self {
+                    InProcess {
+                        api: self.api.clone(),
+                        #clone_state_opt
+                    }
+                }
+            }
 
-        // Dump the spec and the ui template in the source file, for serving ui
-        const JSON_SPEC: &'static str = #json_spec;
-        const UI_TEMPLATE: &'static str = #SWAGGER_UI_TEMPLATE;
+            #[hsr::async_trait::async_trait(?Send)]
+            impl<A: #trait_name> DynClient for InProcess<A> {
+                #in_process_impls
+            }
 
-        mod __imports {
-            pub use hsr::HasStatusCode;
-            pub use hsr::actix_web::{
-                self, App, HttpServer, HttpRequest, HttpResponse, Responder, Either as AxEither,
-                Error as ActixError,
-                web::{self, Json as AxJson, Query as AxQuery, Path as AxPath, Data as AxData, ServiceConfig},
-                dev::HttpResponseBuilder,
-                middleware::Logger
-            };
-            pub use hsr::url::Url;
-            pub use hsr::actix_http::http::{StatusCode};
-            pub use hsr::futures::future::{Future, FutureExt, TryFutureExt, Ready, ok as fut_ok};
-            pub use hsr::serde_json::Value as JsonValue;
+            /// Decoding responses which have already been read
+            mod decode {
+                use super::*;
 
-            // macros re-exported from `serde-derive`
-            pub use hsr::{Serialize, Deserialize};
+                #decoders
+            }
+
+            #servers
         }
-        #[allow(dead_code)]
-        use __imports::*;
+    })
+}
 
-        // Type definitions
-        #rust_api_types
-        #(#rust_response_types)*
-        // Interface definition
-        #rust_trait
-        // Dispatcher definitions
-        #rust_dispatchers
-        // Server
-        #rust_server
-        // Client
+fn generate_rust_proxy(routemap: &Map<String, Vec<Route>>) -> Result<TokenStream> {
+    let handlers = routemap
+        .values()
+        .flatten()
+        .map(|route| route.generate_proxy_handler())
+        .collect::<Result<Vec<_>>>()?;
+    let resources: Vec<_> = routemap
+        .iter()
+        .map(|(path, routes)| {
+            let (meth, opid): (Vec<_>, Vec<_>) = routes
+                .iter()
+                .filter(|route| route.websocket().is_none())
+                .map(|route| {
+                    (
+                        ident(route.method().to_string().to_snake_case()),
+                        route.operation_id(),
+                    )
+                })
+                .unzip();
+            quote! {
+                web::resource(#path)
+                    #(.route(web::#meth().to(#opid)))*
+            }
+        })
+        .collect();
+
+    Ok(quote! {
+        /// A proxy which enforces the spec in front of another server. Requests which
+        /// do not match the spec are rejected, valid requests are forwarded upstream
+        /// with the generated client, and responses which do not match the spec
+        /// are replaced with `502 Bad Gateway`.
+        #[allow(dead_code)]
+        pub mod proxy {
+            use super::*;
+
+            #(#handlers)*
+
+            fn configure_proxy(cfg: &mut actix_web::web::ServiceConfig) {
+                cfg #(.service(#resources))*;
+            }
+
+            /// Serve the proxy on a given host, forwarding to `upstream`.
+            /// Once started, the server blocks indefinitely.
+            pub async fn serve(upstream: Url, cfg: hsr::Config) -> std::io::Result<()> {
+                let server = HttpServer::new(move || {
+                    App::new()
+                        // The client is not thread-safe, so each worker gets its own
+                        .data(client::Client::new(upstream.clone()))
+                        .wrap(Logger::default())
+                        .wrap(hsr::request_id::RequestIds)
+                        .configure(server::configure_spec)
+                        .configure(configure_proxy)
+                });
+
+                // Bind to sockets, unless some were inherited (see `hsr::listen`)
+                let addrs = cfg.bind_addrs()?;
+                let listeners = hsr::listen::inherited()?;
+                let server = if let Some(ssl) = cfg.ssl {
+                    match listeners.into_iter().next() {
+                        Some(listener) => server.listen_openssl(listener, ssl),
+                        None => server.bind_openssl(&addrs[..], ssl),
+                    }
+                } else if listeners.is_empty() {
+                    server.bind(&addrs[..])
+                } else {
+                    listeners
+                        .into_iter()
+                        .try_fold(server, |server, listener| server.listen(listener))
+                }?;
+
+                // run!
+                server.run().await
+            }
+        }
+    })
+}
+
+/// The `OPERATIONS` table, describing each operation, and the lookup function
+fn generate_rust_operations(routes: &Map<String, Vec<Route>>) -> TokenStream {
+    let operations = routes.iter().flat_map(|(path, routes)| {
+        routes.iter().map(move |route| {
+            let operation_id = route.operation_id().to_string();
+            let method = route.method().to_string();
+            let tags = route.tags();
+            let deprecated = route.is_deprecated();
+            let security = route.security().iter().map(|requirement| {
+                let schemes = requirement.iter().map(|(scheme, scopes)| {
+                    quote! {
+                        hsr::operation::SecurityRequirement {
+                            scheme: #scheme,
+                            scopes: &[#(#scopes),*],
+                        }
+                    }
+                });
+                quote! { &[#(#schemes),*] }
+            });
+            quote! {
+                hsr::OperationMeta {
+                    operation_id: #operation_id,
+                    method: #method,
+                    path: #path,
+                    tags: &[#(#tags),*],
+                    deprecated: #deprecated,
+                    security: &[#(#security),*],
+                }
+            }
+        })
+    });
+    quote! {
+        /// Every operation of the API, in the order of the spec
+        #[allow(dead_code)]
+        pub static OPERATIONS: &[hsr::OperationMeta] = &[#(#operations),*];
+
+        /// Find an operation by its operation id
+        #[allow(dead_code)]
+        pub fn operation(operation_id: &str) -> Option<&'static hsr::OperationMeta> {
+            hsr::operation::find(OPERATIONS, operation_id)
+        }
+    }
+}
+
+/// The vendor extensions of the operations and component schemas, as JSON constants
+fn generate_rust_extensions(model: &ir::ApiModel) -> TokenStream {
+    fn to_json(extensions: &Extensions) -> String {
+        let map: serde_json::Map<_, _> = extensions
+            .iter()
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        serde_json::Value::Object(map).to_string()
+    }
+
+    let operations: Vec<_> = model
+        .routes
+        .values()
+        .flatten()
+        .filter(|route| !route.extensions().is_empty())
+        .map(|route| {
+            let name = ident(route.operation_id().to_shouty_snake_case());
+            let doc = doc_comment(format!(
+                "The `x-*` extensions of `{}`",
+                route.operation_id()
+            ));
+            let json = to_json(route.extensions());
+            quote! {
+                #doc
+                pub const #name: &str = #json;
+            }
+        })
+        .collect();
+    let schemas: Vec<_> = model
+        .schema_extensions
+        .iter()
+        .map(|(schema, extensions)| {
+            let name = ident(schema.to_shouty_snake_case());
+            let doc = doc_comment(format!("The `x-*` extensions of schema `{}`", schema));
+            let json = to_json(extensions);
+            quote! {
+                #doc
+                pub const #name: &str = #json;
+            }
+        })
+        .collect();
+    if operations.is_empty() && schemas.is_empty() {
+        return TokenStream::new();
+    }
+    quote! {
+        /// The vendor extensions (`x-*`) found in the spec, as JSON,
+        /// e.g. `hsr::serde_json::from_str(extensions::operations::GET_PET)`
+        #[allow(dead_code)]
+        pub mod extensions {
+            pub mod operations {
+                #(#operations)*
+            }
+            pub mod schemas {
+                #(#schemas)*
+            }
+        }
+    }
+}
+
+pub fn generate_from_yaml_file(yaml: impl AsRef<Path>) -> Result<String> {
+    generate_from_yaml_file_with_options(yaml, &GenerationOptions::default())
+}
+
+pub fn generate_from_yaml_file_with_options(
+    yaml: impl AsRef<Path>,
+    opts: &GenerationOptions,
+) -> Result<String> {
+    // TODO add generate_from_json_file
+    let f = fs::File::open(yaml)?;
+    generate_from_yaml_source_with_options(f, opts)
+}
+
+/// The path of the first server of the spec, without a trailing slash. A server
+/// URL may be relative (`/v1`) or absolute (`https://example.com/v1`).
+fn base_path(raw: &serde_json::Value) -> String {
+    let url = match raw.pointer("/servers/0") {
+        Some(server) => default_server_url(server),
+        None => return String::new(),
+    };
+    let url = url.as_str();
+    let path = match url.find("://") {
+        Some(ix) => {
+            let rest = &url[ix + 3..];
+            rest.find('/').map(|ix| &rest[ix..]).unwrap_or_default()
+        }
+        None => url,
+    };
+    path.trim_end_matches('/').to_string()
+}
+
+/// The URL of a server (from the raw spec), with its variables at their defaults
+fn default_server_url(server: &serde_json::Value) -> String {
+    let mut url = server["url"].as_str().unwrap_or_default().to_string();
+    let variables = server.get("variables").and_then(|vars| vars.as_object());
+    for (name, variable) in variables.into_iter().flatten() {
+        if let Some(default) = variable["default"].as_str() {
+            url = url.replace(&format!("{{{}}}", name), default);
+        }
+    }
+    url
+}
+
+/// The names of the variables of a server URL template, in order
+/// (`https://{region}.example.com` has `region`)
+fn server_url_variables(url: &str) -> Vec<&str> {
+    url.split('{')
+        .skip(1)
+        .filter_map(|part| part.find('}').map(|end| &part[..end]))
+        .collect()
+}
+
+/// An `Environment` enum with a variant for each server of the spec with an absolute
+/// URL, named after its description (e.g. `Staging` for "Staging"), or its position
+/// if that doesn't make a name. An absolute URL which doesn't parse is an error.
+fn generate_environments(raw: &serde_json::Value) -> Result<TokenStream> {
+    let servers = raw["servers"]
+        .as_array()
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let mut variants = Vec::new();
+    let mut names = Set::new();
+    for (ix, server) in servers.iter().enumerate() {
+        let url = default_server_url(server);
+        if !url.contains("://") {
+            continue;
+        }
+        if let Err(e) = url::Url::parse(&url) {
+            invalid!("Bad server URL '{}': {}", url, e)
+        }
+        let description = server["description"].as_str();
+        let name = description
+            .and_then(|descr| descr.to_camel_case().parse::<Ident>().ok())
+            .filter(|name| !names.contains(&name.to_string()))
+            .map(|name| name.to_string())
+            .unwrap_or_else(|| format!("Server{}", ix));
+        names.insert(name.clone());
+        let doc = match description {
+            Some(description) => format!("{} (`{}`)", description, url),
+            None => format!("`{}`", url),
+        };
+        variants.push((ident(name), doc, url, description.unwrap_or_default()));
+    }
+    if variants.is_empty() {
+        return Ok(TokenStream::new());
+    }
+    let default = &variants[0].0;
+    let defs = variants
+        .iter()
+        .map(|(name, doc, _, _)| quote! { #[doc = #doc] #name });
+    let urls = variants
+        .iter()
+        .map(|(name, _, url, _)| quote! { Environment::#name => #url });
+    let descriptions = variants
+        .iter()
+        .map(|(name, _, _, description)| quote! { Environment::#name => #description });
+    let all = variants
+        .iter()
+        .map(|(name, _, _, _)| quote! { Environment::#name });
+    Ok(quote! {
+        /// The servers of the spec
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum Environment {
+            #(#defs,)*
+        }
+
+        impl Default for Environment {
+            fn default() -> Self {
+                Environment::#default
+            }
+        }
+
+        impl Environment {
+            /// Every server, in the order of the spec
+            pub const ALL: &'static [Environment] = &[#(#all),*];
+
+            /// The URL of the server (with any variables at their defaults). The URLs
+            /// were parsed when this code was generated, so this doesn't panic.
+            pub fn url(&self) -> Url {
+                let url = match self {
+                    #(#urls,)*
+                };
+                Url::parse(url).expect("bad server URL")
+            }
+
+            /// The description of the server (empty if it has none)
+            pub fn description(&self) -> &'static str {
+                match self {
+                    #(#descriptions,)*
+                }
+            }
+        }
+
+        impl Client {
+            /// A client of one of the servers of the spec
+            pub fn for_environment(environment: Environment) -> Self {
+                Client::new(environment.url())
+            }
+        }
+    })
+}
+
+/// A struct holding the values of the variables of the first server of the spec,
+/// for a client to build its URL from. A variable with an `enum` is given an
+/// enum type. Nothing is generated unless the server URL is absolute, with variables.
+fn generate_server_variables(raw: &serde_json::Value) -> Result<TokenStream> {
+    let server = match raw.pointer("/servers/0") {
+        Some(server) => server,
+        None => return Ok(TokenStream::new()),
+    };
+    let url = server["url"].as_str().unwrap_or_default();
+    let variables = match server.get("variables").and_then(|vars| vars.as_object()) {
+        Some(variables) if !variables.is_empty() && url.contains("://") => variables,
+        _ => return Ok(TokenStream::new()),
+    };
+    let mut args = Vec::new();
+    for name in server_url_variables(url) {
+        if !variables.contains_key(name) {
+            return Err(
+                Error::Validation(format!("Server variable '{}' is not defined", name))
+                    .at("/servers/0/url"),
+            );
+        }
+        args.push(ident(name.to_snake_case()));
+    }
+    let template = server_url_variables(url)
+        .iter()
+        .fold(url.to_string(), |url, name| {
+            url.replacen(&format!("{{{}}}", name), "{}", 1)
+        });
+
+    let mut types = Vec::new();
+    let mut fields = Vec::new();
+    let mut defaults = Vec::new();
+    for (name, variable) in variables {
+        let pointer = format!("/servers/0/variables/{}", escape_pointer_segment(name));
+        let default = variable["default"].as_str().ok_or_else(|| {
+            Error::Validation(format!("Server variable '{}' has no default", name)).at(&pointer)
+        })?;
+        let field = ident(name.to_snake_case());
+        let docs = variable["description"].as_str().map(doc_comment);
+        let values: Vec<&str> = variable
+            .get("enum")
+            .and_then(|values| values.as_array())
+            .into_iter()
+            .flatten()
+            .filter_map(|value| value.as_str())
+            .collect();
+        if values.is_empty() {
+            fields.push(quote! { #docs pub #field: String });
+            defaults.push(quote! { #field: #default.into() });
+            continue;
+        }
+        let default_ix = match values.iter().position(|v| *v == default) {
+            Some(ix) => ix,
+            None => {
+                return Err(Error::Validation(format!(
+                    "The default of server variable '{}' is not one of its values",
+                    name
+                ))
+                .at(&pointer))
+            }
+        };
+        let typ = ident(format!("Server{}", name.to_camel_case()));
+        let variants = values
+            .iter()
+            .map(|value| value.to_camel_case().parse::<Ident>())
+            .collect::<Result<Vec<_>>>()
+            .map_err(|e| e.at(&pointer))?;
+        let default_variant = &variants[default_ix];
+        let arms = variants
+            .iter()
+            .zip(&values)
+            .map(|(variant, value)| quote! { #typ::#variant => #value });
+        let type_doc = format!("The values of the server variable `{}`", name);
+        types.push(quote! {
+            #[doc = #type_doc]
+            #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+            pub enum #typ {
+                #(#variants),*
+            }
+
+            impl Default for #typ {
+                fn default() -> Self {
+                    #typ::#default_variant
+                }
+            }
+
+            impl std::fmt::Display for #typ {
+                fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                    f.write_str(match self {
+                        #(#arms),*
+                    })
+                }
+            }
+        });
+        fields.push(quote! { #docs pub #field: #typ });
+        defaults.push(quote! { #field: #typ::#default_variant });
+    }
+
+    let struct_doc = format!("The variables of the server URL `{}`", url);
+    Ok(quote! {
+        #(#types)*
+
+        #[doc = #struct_doc]
+        #[derive(Debug, Clone, PartialEq)]
+        pub struct ServerVariables {
+            #(#fields,)*
+        }
+
+        impl Default for ServerVariables {
+            fn default() -> Self {
+                ServerVariables {
+                    #(#defaults,)*
+                }
+            }
+        }
+
+        impl ServerVariables {
+            /// The URL of the server, with these variables
+            pub fn url(&self) -> Result<Url, hsr::url::ParseError> {
+                Url::parse(&format!(#template, #(self.#args),*))
+            }
+        }
+
+        impl Client {
+            /// A client of the server given by the spec, with these variables
+            pub fn for_server(variables: &ServerVariables) -> Result<Self, hsr::url::ParseError> {
+                Ok(Client::new(variables.url()?))
+            }
+        }
+    })
+}
+
+/// Parse a spec, also giving the raw document (to pick up what `openapiv3` doesn't
+/// model, like vendor extensions). `openapiv3` can't parse the `null` value of a
+/// nullable enum, so it only gets to see the other values.
+pub(crate) fn parse_openapi(source: &str) -> Result<(OpenAPI, serde_json::Value)> {
+    let parse = || -> Result<(OpenAPI, serde_json::Value)> {
+        let mut doc: serde_yaml::Value = serde_yaml::from_str(source)?;
+        yaml::apply_merge_keys(&mut doc, "")?;
+        let raw = serde_yaml::from_value(doc.clone())?;
+        strip_null_enum_values(&mut doc);
+        match serde_yaml::from_value(doc.clone()) {
+            Ok(api) => Ok((api, raw)),
+            Err(e) => {
+                let doc = serde_yaml::from_value(doc)?;
+                let pointer = yaml::find_invalid_node(&doc).unwrap_or_default();
+                Err(Error::from(e).at(&pointer))
+            }
+        }
+    };
+    parse().map_err(|e| yaml::locate_error(e, source))
+}
+
+fn strip_null_enum_values(node: &mut serde_yaml::Value) {
+    match node {
+        serde_yaml::Value::Mapping(map) => {
+            for (key, value) in map.iter_mut() {
+                match value {
+                    serde_yaml::Value::Sequence(values) if key.as_str() == Some("enum") => {
+                        values.retain(|value| !value.is_null())
+                    }
+                    value => strip_null_enum_values(value),
+                }
+            }
+        }
+        serde_yaml::Value::Sequence(items) => items.iter_mut().for_each(strip_null_enum_values),
+        _ => {}
+    }
+}
+
+/// The spec as served at `/spec.json`, without the parts marked `x-internal`.
+/// Write this to a file to serve it with `SpecEmbedding::File`.
+pub fn generate_json_spec(mut yaml: impl std::io::Read) -> Result<String> {
+    let mut source = String::new();
+    yaml.read_to_string(&mut source)?;
+    let (api, raw) = parse_openapi(&source)?;
+    serde_json::to_string(&public_spec(&api, &raw))
+        .map_err(|e| Error::BadCodegen(format!("Failed to serialize the spec: {}", e)))
+}
+
+pub fn generate_from_yaml_source(yaml: impl std::io::Read) -> Result<String> {
+    generate_from_yaml_source_with_options(yaml, &GenerationOptions::default())
+}
+
+pub fn generate_from_yaml_source_with_options(
+    mut yaml: impl std::io::Read,
+    opts: &GenerationOptions,
+) -> Result<String> {
+    let mut openapi_source = String::new();
+    yaml.read_to_string(&mut openapi_source)?;
+    let code = generate_tokens(&openapi_source, opts, &Set::new())
+        .map_err(|e| yaml::locate_error(e, &openapi_source))?;
+    format_code(code)
+}
+
+/// Generate the code for a spec, except for the component schemas named in
+/// `shared_types`, which are imported from a sibling `shared` module instead
+pub(crate) fn generate_tokens(
+    openapi_source: &str,
+    opts: &GenerationOptions,
+    shared_types: &Set<String>,
+) -> Result<TokenStream> {
+    // Read the yaml file into an OpenAPI struct, keeping the raw document too
+    let (api, raw) = parse_openapi(openapi_source)?;
+
+    // pull out various sections of the OpenAPI object which will be useful
+    // let components = api.components.take().unwrap_or_default();
+    // let schema_lookup = components.schemas;
+    // let response_lookup = components.responses;
+    // let parameters_lookup = components.parameters;
+    // let req_body_lookup = components.request_bodies;
+
+    // Generate the spec as json. This will be embedded in the binary (unless
+    // configured otherwise), without the parts marked `x-internal`
+    let json_spec = serde_json::to_string(&public_spec(&api, &raw))
+        .map_err(|e| Error::BadCodegen(format!("Failed to serialize the spec: {}", e)))?;
+    let spec_source = opts.spec.source(&json_spec)?;
+    // Responses are checked against the whole spec, internal parts included, which is
+    // embedded separately if it differs (gzipped, when the public one is read from a file)
+    let full_spec = serde_json::to_string(&api)
+        .map_err(|e| Error::BadCodegen(format!("Failed to serialize the spec: {}", e)))?;
+    let full_spec_opt = if full_spec == json_spec {
+        None
+    } else {
+        let source = match &opts.spec {
+            SpecEmbedding::File(_) => SpecEmbedding::Gzip.source(&full_spec)?,
+            embedding => embedding.source(&full_spec)?,
+        };
+        Some(quote! {
+            /// The whole spec, with the parts marked `x-internal`, to check responses against
+            #[allow(dead_code)]
+            static FULL_SPEC: hsr::spec::EmbeddedSpec =
+                hsr::spec::EmbeddedSpec::new(hsr::spec_source!(#source));
+        })
+    };
+    let validation_spec = if full_spec_opt.is_some() {
+        ident("FULL_SPEC")
+    } else {
+        ident("JSON_SPEC")
+    };
+
+    let trait_name = api_trait_name(&api)?;
+
+    // Walk the API to collect types and routes
+    debug!("Gather types");
+    let (type_lookup, routes) = walk::walk_api(&api, &raw, opts.lenient)?;
+    let has_websockets = routes.values().flatten().any(|r| r.websocket().is_some());
+    if opts.backend == Backend::Axum && has_websockets {
+        invalid!("Websocket routes are not supported by the axum backend");
+    }
+    if opts.backend == Backend::Axum && opts.proxy {
+        invalid!("Proxy mode is not supported by the axum backend");
+    }
+    if opts.backend == Backend::Axum && opts.request_context {
+        invalid!("Request context is not supported by the axum backend");
+    }
+    if opts.backend == Backend::Axum && opts.authorization {
+        invalid!("Authorization is not supported by the axum backend");
+    }
+    check_shared_state(opts, &routes)?;
+    let has_rate_limits = routes.values().flatten().any(|r| r.rate_limit().is_some());
+    if opts.backend == Backend::Axum && has_rate_limits {
+        invalid!("Rate limits are not supported by the axum backend");
+    }
+    let has_idempotency = routes.values().flatten().any(|r| r.idempotency().is_some());
+    if opts.backend == Backend::Axum && has_idempotency {
+        invalid!("Idempotency keys are not supported by the axum backend");
+    }
+    let has_cache_policies = routes.values().flatten().any(|r| r.cache().is_some());
+    if opts.backend == Backend::Axum && has_cache_policies {
+        invalid!("Cache policies are not supported by the axum backend");
+    }
+    let has_audits = routes.values().flatten().any(Route::is_audited);
+    if opts.backend == Backend::Axum && has_audits {
+        invalid!("Audit trails are not supported by the axum backend");
+    }
+    if opts.backend == Backend::Axum && opts.problem_json {
+        invalid!("Problem documents are not supported by the axum backend");
+    }
+    if opts.backend == Backend::Axum && opts.fallible {
+        invalid!("Fallible API methods are not supported by the axum backend");
+    }
+    let hooks = [
+        "on_start",
+        "on_shutdown",
+        "schedule",
+        "not_found",
+        "method_not_allowed",
+        "authorize",
+        "audit",
+    ];
+    if let Some(route) = routes
+        .values()
+        .flatten()
+        .find(|route| hooks.contains(&route.operation_id().as_str()))
+    {
+        invalid!(
+            "Operation '{}' clashes with a provided method of the API trait",
+            route.operation_id()
+        );
+    }
+    if opts.deny_unknown_fields && opts.extra_fields {
+        invalid!(
+            "Unknown fields can't be both denied ('deny_unknown_fields') and kept ('extra_fields')"
+        );
+    }
+    if let Some(make_api) = &opts.example_tests {
+        if opts.backend == Backend::Axum {
+            invalid!("Example tests are not supported by the axum backend");
+        }
+        if !opts.shared_state.is_empty() {
+            invalid!("Example tests can't be generated for an API with shared state");
+        }
+        if syn::parse_str::<syn::Path>(make_api).is_err() {
+            invalid!("Bad path '{}' of the API of the example tests", make_api);
+        }
+    }
+    if opts.dyn_dispatch && (opts.fallible || opts.backend == Backend::Axum) {
+        invalid!("'dyn_dispatch' can't be combined with 'fallible', or the axum backend");
+    }
+    if opts.load_test && !opts.samples {
+        invalid!("The load test makes its inputs with the sample values of the types ('samples')");
+    }
+    if opts.version_route && routes.contains_key("/version") {
+        invalid!("The spec has a '/version' path, so it can't be served by 'version_route'");
+    }
+    let has_negotiation = routes
+        .values()
+        .flatten()
+        .any(|r| !r.body_media_types().is_empty() || !r.offered_media_types().is_empty());
+    if opts.backend == Backend::Axum && has_negotiation {
+        invalid!("Content negotiation is not supported by the axum backend");
+    }
+    let has_internal = routes.values().flatten().any(|r| r.is_internal());
+    if opts.proxy && opts.public_client && has_internal {
+        invalid!("The proxy forwards requests with the client, so can't be combined with 'public_client'");
+    }
+    let model = ir::ApiModel {
+        trait_name: trait_name.clone(),
+        info: api.info.clone(),
+        types: type_lookup,
+        routes,
+        schema_extensions: component_schema_extensions(&raw),
+    };
+    let (type_lookup, routes) = (&model.types, &model.routes);
+
+    // Generate type definitions
+    debug!("Generate API types");
+    let rust_api_types = if shared_types.is_empty() {
+        walk::generate_rust_types(type_lookup, opts)?
+    } else {
+        let is_shared = |path: &TypePath| versions::is_shared_type(path, shared_types);
+        let names = type_lookup
+            .keys()
+            .filter(|path| is_shared(path))
+            .map(TypePath::canonicalize)
+            .collect::<Result<Vec<_>>>()?;
+        let local_types =
+            walk::generate_rust_types_where(type_lookup, opts, &|path| !is_shared(path))?;
+        quote! {
+            #[allow(unused_imports)]
+            pub use super::shared::{#(#names),*};
+            #local_types
+        }
+    };
+
+    // Response types are slightly special cases (they need to implement Responder
+    debug!("Generate response types");
+    let all_routes: Vec<&Route> = routes.values().flatten().collect();
+    let rust_response_types = generate_each(&all_routes, |route| route.generate_return_type(opts))?;
+
+    debug!("Generate API trait");
+    let rust_trait = generate_rust_interface(routes, &api.info.title, &trait_name, opts)?;
+
+    debug!("Generate dispatchers");
+    let rust_dispatchers = generate_rust_dispatchers(&all_routes, &trait_name, opts)?;
+
+    debug!("Generate server");
+    let rust_server = generate_rust_server(routes, &trait_name, rust_dispatchers, opts)?;
+
+    debug!("Generate URL builders");
+    let rust_paths = generate_rust_paths(routes, opts)?;
+
+    debug!("Generate links");
+    let rust_links = generate_rust_links(routes, opts)?;
+
+    debug!("Generate client");
+    let servers = generate_server_variables(&raw)?;
+    let environments = generate_environments(&raw)?;
+    let rust_client =
+        generate_rust_client(routes, &trait_name, quote! { #servers #environments }, opts)?;
+
+    let rust_proxy = if opts.proxy {
+        debug!("Generate proxy");
+        Some(generate_rust_proxy(routes)?)
+    } else {
+        None
+    };
+
+    debug!("Generate operation metadata");
+    let rust_operations = generate_rust_operations(routes);
+
+    let rust_example_tests = match &opts.example_tests {
+        Some(make_api) => {
+            debug!("Generate example tests");
+            Some(examples::generate_example_tests(routes, make_api)?)
+        }
+        None => None,
+    };
+
+    let rust_load_test = if opts.load_test {
+        debug!("Generate load test");
+        Some(loadtest::generate_load_test(routes, type_lookup)?)
+    } else {
+        None
+    };
+
+    let rust_fuzz = if opts.fuzz_targets {
+        debug!("Generate fuzz targets");
+        Some(generate_rust_fuzz(routes)?)
+    } else {
+        None
+    };
+
+    debug!("Generate extension constants");
+    let rust_extensions = generate_rust_extensions(&model);
+
+    debug!("Run plugins");
+    let plugin_items = plugin::run_plugins(&opts.plugins, &model)?;
+
+    let api_title = &api.info.title;
+    let api_version = &api.info.version;
+    let base_path = base_path(&raw);
+    let code = quote! {
+        /// The spec, as served at `/spec.json`
+        #[allow(dead_code)]
+        pub static JSON_SPEC: hsr::spec::EmbeddedSpec =
+            hsr::spec::EmbeddedSpec::new(hsr::spec_source!(#spec_source));
+        #full_spec_opt
+        /// The spec to check responses against
+        #[allow(dead_code)]
+        fn validation_spec() -> &'static hsr::spec::EmbeddedSpec {
+            &#validation_spec
+        }
+        // Dump the ui template in the source file, for serving ui
+        // (left out without the `ui` feature of `hsr`)
+        hsr::if_ui! {
+            const UI_TEMPLATE: &'static str = #SWAGGER_UI_TEMPLATE;
+        }
+        /// The title of the API (`info.title` of the spec)
+        #[allow(dead_code)]
+        pub const API_TITLE: &'static str = #api_title;
+        /// The version of the API (`info.version` of the spec)
+        #[allow(dead_code)]
+        pub const API_VERSION: &'static str = #api_version;
+        /// The path of the first server of the spec (empty if there is none)
+        #[allow(dead_code)]
+        pub const BASE_PATH: &'static str = #base_path;
+        /// The title, version and base path of the API together
+        #[allow(dead_code)]
+        pub const API_INFO: hsr::spec::ApiInfo = hsr::spec::ApiInfo {
+            title: API_TITLE,
+            version: API_VERSION,
+            base_path: BASE_PATH,
+        };
+
+        mod __imports {
+            pub use hsr::HasStatusCode;
+            pub use hsr::actix_web::{
+                self, App, HttpServer, HttpRequest, HttpResponse, Responder, Either as AxEither,
+                Error as ActixError,
+                web::{self, Json as AxJson, Query as AxQuery, Data as AxData, ServiceConfig},
+                dev::HttpResponseBuilder,
+                middleware::Logger
+            };
+            pub use hsr::url::Url;
+            pub use hsr::actix_http::http::{StatusCode};
+            pub use hsr::futures::future::{Future, FutureExt, TryFutureExt, Ready, ok as fut_ok};
+            pub use hsr::serde_json::Value as JsonValue;
+
+            // macros re-exported from `serde-derive`
+            pub use hsr::{Serialize, Deserialize};
+        }
+        #[allow(unused_imports)]
+        use __imports::*;
+
+        // Type definitions
+        #rust_api_types
+        #rust_response_types
+        // Interface definition
+        #rust_trait
+        // Server
+        #rust_server
+        // URL builders
+        #rust_paths
+        // Links
+        #rust_links
+        // Client
         #rust_client
+        // Proxy
+        #rust_proxy
+        // Operation metadata
+        #rust_operations
+        // Vendor extensions
+        #rust_extensions
+        // Example tests
+        #rust_example_tests
+        // Load test
+        #rust_load_test
+        // Fuzz targets
+        #rust_fuzz
+        // Plugins
+        #plugin_items
     };
+    Ok(code)
+}
+
+/// Turn the generated tokens into source code, prettified if enabled
+fn format_code(code: TokenStream) -> Result<String> {
     let code = code.to_string();
-    #[cfg(feature = "pretty")]
+    #[cfg(any(feature = "pretty", feature = "rustfmt"))]
     {
         debug!("Prettify");
         prettify_code(code)
     }
-    #[cfg(not(feature = "pretty"))]
-    {
-        Ok(code)
+    #[cfg(not(any(feature = "pretty", feature = "rustfmt")))]
+    {
+        Ok(code)
+    }
+}
+
+/// Format the code with `prettyplease`.
+#[cfg(all(feature = "pretty", not(feature = "rustfmt")))]
+pub fn prettify_code(input: String) -> Result<String> {
+    let file = syn::parse_file(&input).map_err(|e| Error::BadCodegen(e.to_string()))?;
+    Ok(prettyplease::unparse(&file))
+}
+
+/// Run the code through `rustfmt`.
+#[cfg(feature = "rustfmt")]
+pub fn prettify_code(input: String) -> Result<String> {
+    let mut buf = Vec::new();
+    {
+        let mut config = rustfmt_nightly::Config::default();
+        config.set().emit_mode(rustfmt_nightly::EmitMode::Stdout);
+        config.set().edition(rustfmt_nightly::Edition::Edition2018);
+        let mut session = rustfmt_nightly::Session::new(config, Some(&mut buf));
+        session
+            .format(rustfmt_nightly::Input::Text(input))
+            .map_err(|e| Error::BadCodegen(e.to_string()))?;
+    }
+    if buf.is_empty() {
+        return Err(Error::BadCodegen("empty buffer".to_string()));
+    }
+    let mut s = String::from_utf8(buf).unwrap();
+    // TODO no idea why this is necessary but... it is
+    if s.starts_with("stdin:\n\n") {
+        s = s.split_off(8);
+    }
+    Ok(s)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The code without whitespace or trailing commas, which differ with the
+    /// formatting (the generated code is only formatted with `pretty` or `rustfmt`)
+    fn flat(code: &str) -> String {
+        let code: String = code.split_whitespace().collect();
+        code.replace(",)", ")")
+            .replace(",]", "]")
+            .replace(",}", "}")
+    }
+
+    /// Whether the generated `code` contains `snippet`, whatever the layout
+    fn has(code: &str, snippet: &str) -> bool {
+        flat(code).contains(&flat(snippet))
+    }
+
+    /// How many times `snippet` occurs in the generated `code`
+    fn count(code: &str, snippet: &str) -> usize {
+        flat(code).matches(&flat(snippet)).count()
+    }
+
+    /// Where `snippet` first occurs in the generated `code`, to compare with others
+    fn find(code: &str, snippet: &str) -> Option<usize> {
+        flat(code).find(&flat(snippet))
+    }
+
+    #[test]
+    fn test_snake_casify() {
+        assert_eq!("/a/b/c".to_snake_case(), "a_b_c");
+        assert_eq!(
+            "/All/ThisIs/justFine".to_snake_case(),
+            "all_this_is_just_fine"
+        );
+        assert_eq!("/{someId}".to_snake_case(), "some_id");
+        assert_eq!(
+            "/123_abc{xyz\\!\"£$%^}/456 asdf".to_snake_case(),
+            "123_abc_xyz_456_asdf"
+        )
+    }
+
+    #[test]
+    fn test_valid_identifier() {
+        assert!(Ident::from_str("x").is_ok());
+        assert!(Ident::from_str("_").is_ok());
+        assert!(Ident::from_str("x1").is_ok());
+        assert!(Ident::from_str("x1_23_aB").is_ok());
+
+        assert!(Ident::from_str("").is_err());
+        assert!(Ident::from_str("1abc").is_err());
+        assert!(Ident::from_str("abc!").is_err());
+    }
+
+    #[test]
+    fn test_analyse_path() {
+        use PathSegment::*;
+
+        // Should fail
+        assert!(RoutePath::analyse("").is_err());
+        assert!(RoutePath::analyse("a").is_err());
+        assert!(RoutePath::analyse("/a//").is_err());
+        assert!(RoutePath::analyse("/a{").is_err());
+        assert!(RoutePath::analyse("/a{}").is_err());
+        assert!(RoutePath::analyse("/{}a").is_err());
+        assert!(RoutePath::analyse("/{a}a").is_err());
+        assert!(RoutePath::analyse("/ a").is_err());
+        assert!(RoutePath::analyse("/1").is_err());
+        assert!(RoutePath::analyse("/a//b").is_err());
+
+        assert!(RoutePath::analyse("/a").is_ok());
+        assert!(RoutePath::analyse("/a/b/c").is_ok());
+        assert!(RoutePath::analyse("/a/a/a").is_ok());
+        assert!(RoutePath::analyse("/a1/b2/c3").is_ok());
+        assert!(RoutePath::analyse("/a/").unwrap().trailing_slash());
+        assert!(!RoutePath::analyse("/a").unwrap().trailing_slash());
+        assert_eq!(
+            RoutePath::analyse("/a/{b}/").unwrap().to_string(),
+            "/a/{b}/"
+        );
+
+        assert!(RoutePath::analyse("/{a1}").is_ok());
+        assert!(RoutePath::analyse("/{a1}/b2/{c3}").is_ok());
+        assert!(RoutePath::analyse("/{a1B2c3}").is_ok());
+        assert!(RoutePath::analyse("/{a1_b2_c3}").is_ok());
+
+        // duplicate param
+        assert!(RoutePath::analyse("/{a}/{b}/{a}").is_err());
+
+        assert_eq!(
+            RoutePath::analyse("/{a_1}/{b2C3}/a/b").unwrap(),
+            RoutePath {
+                segments: vec![
+                    Parameter("a_1".into()),
+                    Parameter("b2C3".into()),
+                    Literal("a".into()),
+                    Literal("b".into())
+                ],
+                trailing_slash: false,
+            }
+        );
+    }
+
+    #[test]
+    fn test_path_parameters_match_the_path() {
+        let spec = |params: &str| {
+            format!(
+                r#"
+openapi: 3.0.0
+info:
+  title: Pets
+  version: 0.1.0
+paths:
+  /pets/{{petId}}/toys/{{toyId}}:
+    get:
+      operationId: get_toy
+      parameters:{}
+      responses:
+        '200':
+          description: Ok
+"#,
+                params
+            )
+        };
+        let param = |name: &str| {
+            format!(
+                "\n        - name: {}\n          in: path\n          required: true\n          schema:\n            type: string",
+                name
+            )
+        };
+        let error = |params: String| ir::parse_spec(spec(&params).as_bytes()).unwrap_err();
+
+        assert!(ir::parse_spec(spec(&(param("petId") + &param("toyId"))).as_bytes()).is_ok());
+
+        let err = error(param("petId"));
+        assert_eq!(
+            err.pointer(),
+            Some("/paths/~1pets~1{petId}~1toys~1{toyId}/get/parameters")
+        );
+        assert!(err.to_string().contains("'toyId'"), "{}", err);
+
+        let err = error(param("petId") + &param("toyId") + &param("petId"));
+        assert_eq!(
+            err.pointer(),
+            Some("/paths/~1pets~1{petId}~1toys~1{toyId}/get/parameters/2")
+        );
+        assert!(err.to_string().contains("declared twice"), "{}", err);
+
+        let err = error(param("petid") + &param("toyId"));
+        assert!(err.to_string().contains("did you mean 'petId'?"), "{}", err);
+
+        let err = RoutePath::analyse("/pets/{id}/{id}").unwrap_err();
+        assert!(err.to_string().contains("'id'"), "{}", err);
+    }
+
+    #[test]
+    fn test_query_parameter_defaults() {
+        let spec = |default: &str| {
+            format!(
+                r#"
+openapi: 3.0.0
+info:
+  title: Pets
+  version: 0.1.0
+paths:
+  /pets:
+    get:
+      operationId: list_pets
+      parameters:
+        - name: limit
+          in: query
+          schema:
+            type: integer
+            default: {}
+      responses:
+        '200':
+          description: Ok
+"#,
+                default
+            )
+        };
+        let model = ir::parse_spec(spec("20").as_bytes()).unwrap();
+        let route = &model.routes["/pets"][0];
+        let (meta, _) = &route.query_params().unwrap()[&"limit".parse::<Ident>().unwrap()];
+        assert_eq!(meta.default_value(), Some(&serde_json::json!(20)));
+        assert!(!meta.optional());
+
+        let code = generate_from_yaml_source(spec("20").as_bytes()).unwrap();
+        assert!(has(&code, "is_list_pets_query_limit_default"), "{}", code);
+
+        let err = ir::parse_spec(spec("lots").as_bytes()).unwrap_err();
+        assert_eq!(err.pointer(), Some("/paths/~1pets/get/parameters/0"));
+        assert!(err.to_string().contains("does not match"), "{}", err);
+    }
+
+    #[test]
+    fn test_query_parameter_flags() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Files
+  version: 0.1.0
+paths:
+  /files:
+    get:
+      operationId: list_files
+      parameters:
+        - name: verbose
+          in: query
+          allowEmptyValue: true
+          schema:
+            type: boolean
+        - name: dir
+          in: query
+          allowReserved: true
+          schema:
+            type: string
+        - name: ids
+          in: query
+          style: pipeDelimited
+          schema:
+            type: array
+            items:
+              type: integer
+      responses:
+        '200':
+          description: Ok
+"#;
+        let model = ir::parse_spec(yaml.as_bytes()).unwrap();
+        let params = model.routes["/files"][0].query_params().unwrap();
+        let (verbose, _) = &params[&"verbose".parse::<Ident>().unwrap()];
+        let (dir, _) = &params[&"dir".parse::<Ident>().unwrap()];
+        assert!(verbose.allow_empty() && !verbose.allow_reserved());
+        assert!(dir.allow_reserved() && !dir.allow_empty());
+        let (ids, _) = &params[&"ids".parse::<Ident>().unwrap()];
+        assert_eq!(ids.delimiter(), Some('|'));
+        assert_eq!(dir.delimiter(), None);
+
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        assert!(has(&code, "fn list_files_query_verbose_empty"), "{}", code);
+        assert!(has(&code, "allow_reserved"), "{}", code);
+        assert!(
+            has(&code, "fn deserialize_list_files_query_ids_delimited"),
+            "{}",
+            code
+        );
+
+        // only arrays can be delimited
+        let yaml = yaml.replace(
+            "type: array\n            items:\n              type: integer",
+            "type: integer",
+        );
+        let err = generate_from_yaml_source(yaml.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("Only array"), "{}", err);
+    }
+
+    #[test]
+    fn test_content_negotiation() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Reports
+  version: 0.1.0
+paths:
+  /report:
+    post:
+      operationId: make_report
+      requestBody:
+        content:
+          application/json:
+            schema:
+              type: object
+              properties:
+                title:
+                  type: string
+          text/plain: {}
+      responses:
+        '200':
+          description: Ok
+          content:
+            application/json:
+              schema:
+                type: array
+                items:
+                  type: string
+            text/csv: {}
+            application/pdf: {}
+"#;
+        let model = ir::parse_spec(yaml.as_bytes()).unwrap();
+        let route = &model.routes["/report"][0];
+        assert_eq!(
+            route.body_media_types(),
+            &["application/json", "text/plain"]
+        );
+        assert_eq!(
+            route.offered_media_types(),
+            vec!["application/json", "text/csv", "application/pdf"]
+        );
+        let response = &route.responses().with_codes[&StatusCode::OK];
+        let reprs = match &model.types[response.type_path.as_ref().unwrap()] {
+            ReferenceOr::Item(ir::Type {
+                typ: ir::TypeInner::Content(reprs),
+                ..
+            }) => reprs,
+            other => panic!("not content: {:?}", other),
+        };
+        assert!(matches!(
+            reprs["application/json"],
+            ir::Representation::Json(_)
+        ));
+        assert_eq!(reprs["text/csv"], ir::Representation::Text);
+        assert_eq!(reprs["application/pdf"], ir::Representation::Binary);
+
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        assert!(has(&code, "Representations for"), "{}", code);
+        assert!(has(&code, "Negotiated"), "{}", code);
+        // the PDF may be streamed
+        assert!(route.has_binary_response());
+        assert!(has(&code, "fn make_report_stream"), "{}", code);
+
+        // variants are named after their media types, which mustn't clash
+        let yaml = yaml.replace("application/pdf", "text/CSV");
+        let err = generate_from_yaml_source(yaml.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("clashes"), "{}", err);
+    }
+
+    #[test]
+    fn test_patch_bodies() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Pets
+  version: 0.1.0
+paths:
+  /pets/{id}:
+    patch:
+      operationId: update_pet
+      parameters:
+        - name: id
+          in: path
+          required: true
+          schema:
+            type: integer
+      requestBody:
+        content:
+          application/merge-patch+json:
+            schema:
+              $ref: '#/components/schemas/Pet'
+          application/json-patch+json:
+            schema:
+              type: array
+              items:
+                type: object
+      responses:
+        '200':
+          description: Ok
+components:
+  schemas:
+    Pet:
+      type: object
+      properties:
+        name:
+          type: string
+"#;
+        let model = ir::parse_spec(yaml.as_bytes()).unwrap();
+        let route = &model.routes["/pets/{id}"][0];
+        let body = route.method().body_type().unwrap();
+        let reprs = match &model.types[body] {
+            ReferenceOr::Item(ir::Type {
+                typ: ir::TypeInner::Content(reprs),
+                ..
+            }) => reprs,
+            other => panic!("not content: {:?}", other),
+        };
+        assert!(matches!(
+            reprs["application/merge-patch+json"],
+            ir::Representation::MergePatch(_)
+        ));
+        assert_eq!(
+            reprs["application/json-patch+json"],
+            ir::Representation::JsonPatch
+        );
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        assert!(has(&code, "enum UpdatePetRequestBody"), "{}", code);
+        assert!(has(&code, "ApplicationMergePatchJson"), "{}", code);
+        assert!(has(&code, "PatchOp"), "{}", code);
+
+        // a lone patch is used as it is
+        let yaml = yaml.replace(
+            "          application/json-patch+json:
+            schema:
+              type: array
+              items:
+                type: object
+",
+            "",
+        );
+        let model = ir::parse_spec(yaml.as_bytes()).unwrap();
+        let body = model.routes["/pets/{id}"][0].method().body_type().unwrap();
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        assert!(!has(&code, "enum UpdatePetRequestBody"), "{}", code);
+        assert!(
+            has(
+                &code,
+                &format!("type {} = hsr::patch::Patch<", body.canonicalize().unwrap())
+            ),
+            "{}",
+            code
+        );
+    }
+
+    #[test]
+    fn test_public_spec() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Internal
+  version: 0.1.0
+paths:
+  /public:
+    get:
+      operationId: public
+      responses:
+        '200':
+          description: Ok
+    post:
+      operationId: hidden
+      x-internal: true
+      responses:
+        '200':
+          description: Ok
+  /admin:
+    get:
+      operationId: admin
+      x-internal: true
+      responses:
+        '200':
+          description: Ok
+components:
+  schemas:
+    Visible:
+      type: string
+    Secret:
+      type: string
+      x-internal: true
+"#;
+        let api: OpenAPI = serde_yaml::from_str(yaml).unwrap();
+        let raw: serde_json::Value = serde_yaml::from_str(yaml).unwrap();
+        let public = public_spec(&api, &raw);
+        let paths: Vec<_> = public.paths.keys().collect();
+        assert_eq!(paths, vec!["/public"]);
+        match &public.paths["/public"] {
+            ReferenceOr::Item(item) => {
+                assert!(item.get.is_some());
+                assert!(item.post.is_none());
+            }
+            _ => panic!("expected a path item"),
+        }
+        let schemas: Vec<_> = public.components.unwrap().schemas.keys().cloned().collect();
+        assert_eq!(schemas, vec!["Visible"]);
+    }
+
+    #[test]
+    fn test_public_spec_keeps_referenced_internal_schemas() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Internal
+  version: 0.1.0
+paths:
+  /public:
+    get:
+      operationId: public
+      responses:
+        '200':
+          description: Ok
+          content:
+            application/json:
+              schema:
+                $ref: '#/components/schemas/Shared'
+  /admin:
+    get:
+      operationId: admin
+      x-internal: true
+      responses:
+        '200':
+          description: Ok
+          content:
+            application/json:
+              schema:
+                $ref: '#/components/schemas/AdminOnly'
+components:
+  schemas:
+    Shared:
+      type: object
+      x-internal: true
+      properties:
+        part:
+          $ref: '#/components/schemas/Part'
+    Part:
+      type: string
+      x-internal: true
+    AdminOnly:
+      type: string
+      x-internal: true
+"#;
+        let api: OpenAPI = serde_yaml::from_str(yaml).unwrap();
+        let raw: serde_json::Value = serde_yaml::from_str(yaml).unwrap();
+        let public = public_spec(&api, &raw);
+        let mut schemas: Vec<_> = public.components.unwrap().schemas.keys().cloned().collect();
+        schemas.sort();
+        assert_eq!(schemas, vec!["Part", "Shared"]);
+
+        // responses are checked against the whole spec
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        assert!(
+            has(&code, "static FULL_SPEC: hsr::spec::EmbeddedSpec"),
+            "{}",
+            code
+        );
+        assert!(has(&code, "&FULL_SPEC"), "{}", code);
+        assert!(
+            has(&code, "validation_spec().for_validation(validation)"),
+            "{}",
+            code
+        );
+    }
+
+    #[test]
+    fn test_spec_embedding() {
+        use std::io::Read;
+        let json = r#"{"openapi":"3.0.0"}"#;
+        let source = SpecEmbedding::Gzip.source(json).unwrap().to_string();
+        assert!(source.starts_with("hsr :: spec :: SpecSource :: Gzip (b\""));
+        // decompresses to the spec again
+        let gzipped: syn::LitByteStr = syn::parse_str(
+            source
+                .trim_start_matches("hsr :: spec :: SpecSource :: Gzip (")
+                .trim_end_matches(')'),
+        )
+        .unwrap();
+        let mut unzipped = String::new();
+        flate2::read::GzDecoder::new(&gzipped.value()[..])
+            .read_to_string(&mut unzipped)
+            .unwrap();
+        assert_eq!(unzipped, json);
+
+        let source = SpecEmbedding::Omit.source(json).unwrap().to_string();
+        assert_eq!(source, "hsr :: spec :: SpecSource :: Omitted");
+    }
+
+    #[test]
+    fn test_trailing_slash() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Pets
+  version: 0.1.0
+paths:
+  /pets/:
+    get:
+      operationId: list_pets
+      responses:
+        '200':
+          description: The pets
+  /pets/{id}:
+    get:
+      operationId: get_pet
+      parameters:
+        - in: path
+          name: id
+          required: true
+          schema:
+            type: integer
+      responses:
+        '200':
+          description: The pet
+"#;
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        // the slash is kept, by the server and the client
+        assert!(has(&code, r#"web::resource("/pets/")"#), "{}", code);
+        assert!(has(&code, r#""/pets/","#), "{}", code);
+        assert!(
+            has(
+                &code,
+                r#"TrailingSlashes::new(trailing_slash, &["/pets/", "/pets/{id}"])"#
+            ),
+            "{}",
+            code
+        );
+    }
+
+    #[test]
+    fn test_auto_head() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Pets
+  version: 0.1.0
+paths:
+  /pets:
+    get:
+      operationId: list_pets
+      responses:
+        '200':
+          description: The pets
+    post:
+      operationId: add_pet
+      responses:
+        '201':
+          description: Added
+  /pets/{id}:
+    get:
+      operationId: get_pet
+      parameters:
+        - in: path
+          name: id
+          required: true
+          schema:
+            type: integer
+      responses:
+        '200':
+          description: The pet
+    head:
+      operationId: pet_exists
+      parameters:
+        - in: path
+          name: id
+          required: true
+          schema:
+            type: integer
+      responses:
+        '200':
+          description: The pet exists
+"#;
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        assert!(!has(&code, r#""list_pets", web::head()"#), "{}", code);
+
+        let opts = GenerationOptions {
+            auto_head: true,
+            ..Default::default()
+        };
+        let code = generate_from_yaml_source_with_options(yaml.as_bytes(), &opts).unwrap();
+        assert!(has(&code, r#""list_pets", web::head()"#), "{}", code);
+        assert!(has(&code, r#"&["GET", "POST", "HEAD"]"#), "{}", code);
+        assert!(
+            has(
+                &code,
+                r#"(hsr::actix_http::http::Method::HEAD, "list_pets")"#
+            ),
+            "{}",
+            code
+        );
+        // a HEAD operation of the spec's own is left alone
+        assert!(has(&code, r#""pet_exists", web::head()"#), "{}", code);
+        assert!(!has(&code, r#""get_pet", web::head()"#), "{}", code);
+    }
+
+    #[test]
+    fn test_dyn_dispatch() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Pets
+  version: 0.1.0
+paths:
+  /pets/{id}:
+    get:
+      operationId: get_pet
+      parameters:
+        - in: path
+          name: id
+          required: true
+          schema:
+            type: integer
+      responses:
+        '200':
+          description: The pet
+"#;
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        assert!(!has(&code, "DynApi"), "{}", code);
+        assert!(has(&code, "route.to(handlers::get_pet::<A>)"), "{}", code);
+
+        let opts = GenerationOptions {
+            dyn_dispatch: true,
+            ..Default::default()
+        };
+        let code = generate_from_yaml_source_with_options(yaml.as_bytes(), &opts).unwrap();
+        assert!(
+            has(&code, "pub type DynApi = std::sync::Arc<dyn PetsApi>;"),
+            "{}",
+            code
+        );
+        // the handlers are compiled once
+        assert!(has(&code, "data: AxData<DynApi>,"), "{}", code);
+        assert!(has(&code, "route.to(handlers::get_pet)"), "{}", code);
+        assert!(has(&code, "fn configure_hsr("), "{}", code);
+        assert!(!has(&code, "::<A>"), "{}", code);
+        assert!(
+            has(&code, "let api: DynApi = std::sync::Arc::new(api);"),
+            "{}",
+            code
+        );
+
+        let opts = GenerationOptions {
+            dyn_dispatch: true,
+            fallible: true,
+            ..Default::default()
+        };
+        assert!(generate_from_yaml_source_with_options(yaml.as_bytes(), &opts).is_err());
+    }
+
+    #[test]
+    fn test_overridable_handlers() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Files
+  version: 0.1.0
+paths:
+  /files/{name}:
+    put:
+      operationId: upload_file
+      parameters:
+        - in: path
+          name: name
+          required: true
+          schema:
+            type: string
+      responses:
+        '201':
+          description: Uploaded
+"#;
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        assert!(has(&code, "pub mod handlers"), "{}", code);
+        assert!(has(&code, "pub async fn upload_file<A"), "{}", code);
+        // which the public handler takes
+        assert!(has(&code, "pub struct UploadFilePath"), "{}", code);
+        assert!(
+            has(&code, r#"overrides.route("upload_file", web::put(),"#),
+            "{}",
+            code
+        );
+        assert!(has(&code, "cfg.overrides.check(OPERATIONS)?;"), "{}", code);
+        // an overriding handler is still checked as the generated one is
+        assert!(has(&code, "pub mod overridden"), "{}", code);
+        assert!(
+            has(&code, "pub async fn upload_file<A: FilesApi + Send + Sync>(req: hsr::actix_web::dev::ServiceRequest, next: hsr::dispatch::Next)"),
+            "{}",
+            code
+        );
+        assert!(
+            has(
+                &code,
+                r#".wrap(overrides.checks().operation(hsr::actix_http::http::Method::PUT, "upload_file", handlers::overridden::upload_file::<A>))"#
+            ),
+            "{}",
+            code
+        );
+        assert!(
+            has(
+                &code,
+                r#"hsr::dispatch::guard("upload_file", None, next(req))"#
+            ),
+            "{}",
+            code
+        );
+    }
+
+    #[test]
+    fn test_access_log_wraps_the_app() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Pets
+  version: 0.1.0
+paths:
+  /pets:
+    get:
+      operationId: list_pets
+      responses:
+        '200':
+          description: Ok
+"#;
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        // the resources only note their operations...
+        assert!(
+            has(
+                &code,
+                r#".wrap(hsr::access_log::LogOperations::new(&[(hsr::actix_http::http::Method::GET, "list_pets")]))"#
+            ),
+            "{}",
+            code
+        );
+        // ...for the log around the app, which also sees unknown paths and the spec
+        let app_log = "access_log.is_some(), access_log.clone().unwrap_or_default())";
+        let app = find(&code, "App::new()").unwrap();
+        let not_found = find(&code, ".default_service(web::route().to(not_found::<A>))").unwrap();
+        let log = find(&code, app_log).unwrap();
+        assert!(app < not_found && not_found < log, "{}", code);
+        assert!(
+            !has(&code, "access_log: Option<hsr::access_log::AccessLog>"),
+            "{}",
+            code
+        );
+    }
+
+    #[test]
+    fn test_ui_is_feature_gated() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Pets
+  version: 0.1.0
+paths:
+  /pets:
+    get:
+      operationId: list_pets
+      responses:
+        '200':
+          description: The pets
+"#;
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        // the spec, the template and what serves them are left to the `ui` feature of hsr
+        assert!(has(&code, "hsr::spec_source!("), "{}", code);
+        let gate = find(&code, "hsr::if_ui!").unwrap();
+        assert!(gate < find(&code, "const UI_TEMPLATE").unwrap(), "{}", code);
+        assert!(has(&code, ".configure(configure_spec)"), "{}", code);
+        assert!(
+            !has(&code, "JSON_SPEC.configure(cfg, UI_TEMPLATE))"),
+            "{}",
+            code
+        );
+    }
+
+    #[test]
+    fn test_unsupported_features_are_errors() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Unsupported
+  version: 0.1.0
+paths:
+  /pets:
+    get:
+      operationId: list_pets
+      parameters:
+        - name: X-Trace
+          in: header
+          schema:
+            type: string
+      responses:
+        '200':
+          description: Ok
+"#;
+        match ir::parse_spec(yaml.as_bytes()) {
+            Err(Error::Located(11, 11, err)) => match *err {
+                Error::At(pointer, inner) => {
+                    assert_eq!(pointer, "/paths/~1pets/get/parameters/0");
+                    match *inner {
+                        Error::Unsupported(feature) => assert_eq!(feature, "Header parameters"),
+                        other => panic!("expected Unsupported, got {:?}", other),
+                    }
+                }
+                other => panic!("expected a pointer, got {:?}", other),
+            },
+            other => panic!("expected a located error, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn test_errors_point_into_schemas() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Broken
+  version: 0.1.0
+paths: {}
+components:
+  schemas:
+    Pet:
+      type: object
+      properties:
+        tags:
+          type: array
+          items:
+            type: string
+            pattern: '^[a-z]+$'
+"#;
+        let err = ir::parse_spec(yaml.as_bytes()).unwrap_err();
+        assert_eq!(
+            err.pointer(),
+            Some("/components/schemas/Pet/properties/tags/items")
+        );
+        assert_eq!(
+            err.to_string(),
+            "String patterns not supported (at '/components/schemas/Pet/properties/tags/items') \
+             (line 15, column 13)"
+        );
     }
-}
 
-/// Run the code through `rustfmt`.
-#[cfg(feature = "pretty")]
-pub fn prettify_code(input: String) -> Result<String> {
-    let mut buf = Vec::new();
-    {
-        let mut config = rustfmt_nightly::Config::default();
-        config.set().emit_mode(rustfmt_nightly::EmitMode::Stdout);
-        config.set().edition(rustfmt_nightly::Edition::Edition2018);
-        let mut session = rustfmt_nightly::Session::new(config, Some(&mut buf));
-        session
-            .format(rustfmt_nightly::Input::Text(input))
-            .map_err(|e| Error::BadCodegen(e.to_string()))?;
+    #[test]
+    fn test_lenient_schemas() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Exotic
+  version: 0.1.0
+paths: {}
+components:
+  schemas:
+    NotAString:
+      not:
+        type: string
+    Pet:
+      type: object
+      properties:
+        name:
+          type: string
+        tags:
+          type: array
+          items:
+            type: string
+            pattern: '^[a-z]+$'
+"#;
+        let err = ir::parse_spec(yaml.as_bytes()).unwrap_err();
+        assert_eq!(err.pointer(), Some("/components/schemas/NotAString"));
+        assert_eq!(
+            err.to_string(),
+            "'not' schemas not supported (at '/components/schemas/NotAString') \
+             (line 10, column 7)"
+        );
+
+        let opts = GenerationOptions {
+            lenient: true,
+            ..Default::default()
+        };
+        let code = generate_from_yaml_source_with_options(yaml.as_bytes(), &opts).unwrap();
+        // the rest of the spec is generated as usual
+        assert!(has(&code, "struct Pet"), "{}", code);
+        assert!(has(&code, "'not' schemas not supported"), "{}", code);
+        assert!(has(&code, "String patterns not supported"), "{}", code);
     }
-    if buf.is_empty() {
-        return Err(Error::BadCodegen("empty buffer".to_string()));
+
+    #[test]
+    fn test_all_of_extensions() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Pets
+  version: 0.1.0
+paths: {}
+components:
+  schemas:
+    NewPet:
+      type: object
+      properties:
+        name:
+          type: string
+    Pet:
+      allOf:
+        - $ref: '#/components/schemas/NewPet'
+        - properties:
+            id:
+              type: integer
+    Dog:
+      allOf:
+        - $ref: '#/components/schemas/Pet'
+        - description: A pet which barks
+"#;
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        assert!(!has(&code, "flatten"), "{}", code);
+
+        let opts = GenerationOptions {
+            flatten_all_of: true,
+            ..Default::default()
+        };
+        let code = generate_from_yaml_source_with_options(yaml.as_bytes(), &opts).unwrap();
+        assert!(has(&code, "flatten"), "{}", code);
+        assert!(has(&code, "new_pet"), "{}", code);
+
+        let yaml = yaml.replace("            id:", "            new_pet:");
+        let err = generate_from_yaml_source_with_options(yaml.as_bytes(), &opts).unwrap_err();
+        assert!(err.to_string().contains("clashes"), "{}", err);
     }
-    let mut s = String::from_utf8(buf).unwrap();
-    // TODO no idea why this is necessary but... it is
-    if s.starts_with("stdin:\n\n") {
-        s = s.split_off(8);
+
+    #[test]
+    fn test_deny_unknown_fields() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Pets
+  version: 0.1.0
+paths:
+  /pets:
+    get:
+      operationId: list_pets
+      parameters:
+        - in: query
+          name: limit
+          schema:
+            type: integer
+      responses:
+        '200':
+          description: The pets
+components:
+  schemas:
+    Pet:
+      type: object
+      properties:
+        name:
+          type: string
+    Owner:
+      type: object
+      additionalProperties: false
+      properties:
+        name:
+          type: string
+"#;
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        assert_eq!(count(&code, "deny_unknown_fields"), 1, "{}", code);
+        let model = ir::parse_spec(yaml.as_bytes()).unwrap();
+        let owner = TypePath::from_reference("#/components/schemas/Owner").unwrap();
+        let owner = model.resolve(&model.types[&owner]).unwrap();
+        assert!(matches!(
+            owner.inner(),
+            ir::TypeInner::Struct(strukt) if strukt.unknown_fields() == ir::UnknownFields::Deny
+        ));
+
+        // the query parameters are left alone
+        let opts = GenerationOptions {
+            deny_unknown_fields: true,
+            ..Default::default()
+        };
+        let code = generate_from_yaml_source_with_options(yaml.as_bytes(), &opts).unwrap();
+        assert_eq!(count(&code, "deny_unknown_fields"), 2, "{}", code);
+
+        let yaml = yaml.replace(
+            "additionalProperties: false",
+            "additionalProperties: {type: integer}",
+        );
+        let err = generate_from_yaml_source(yaml.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("Additional properties"), "{}", err);
     }
-    Ok(s)
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_extra_fields() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Pets
+  version: 0.1.0
+paths: {}
+components:
+  schemas:
+    Pet:
+      type: object
+      properties:
+        name:
+          type: string
+    Owner:
+      type: object
+      additionalProperties: true
+      properties:
+        name:
+          type: string
+"#;
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        assert_eq!(count(&code, "pub extra:"), 1, "{}", code);
+        assert!(
+            has(&code, "hsr::indexmap::IndexMap<String, JsonValue>"),
+            "{}",
+            code
+        );
+
+        let opts = GenerationOptions {
+            extra_fields: true,
+            ..Default::default()
+        };
+        let code = generate_from_yaml_source_with_options(yaml.as_bytes(), &opts).unwrap();
+        assert_eq!(count(&code, "pub extra:"), 2, "{}", code);
+
+        let clash = yaml.replace("        name:", "        extra:");
+        let err = generate_from_yaml_source_with_options(clash.as_bytes(), &opts).unwrap_err();
+        assert!(err.to_string().contains("clashes"), "{}", err);
+
+        let opts = GenerationOptions {
+            deny_unknown_fields: true,
+            ..opts
+        };
+        assert!(generate_from_yaml_source_with_options(yaml.as_bytes(), &opts).is_err());
+    }
 
     #[test]
-    fn test_snake_casify() {
-        assert_eq!("/a/b/c".to_snake_case(), "a_b_c");
+    fn test_map_types() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Pets
+  version: 0.1.0
+paths: {}
+components:
+  schemas:
+    Labels:
+      type: object
+      additionalProperties:
+        type: string
+    Pet:
+      type: object
+      additionalProperties: true
+      properties:
+        zebra:
+          type: string
+        labels:
+          $ref: '#/components/schemas/Labels'
+        apple:
+          type: object
+          additionalProperties:
+            type: integer
+"#;
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
         assert_eq!(
-            "/All/ThisIs/justFine".to_snake_case(),
-            "all_this_is_just_fine"
+            count(&code, "hsr::indexmap::IndexMap<String,"),
+            3,
+            "{}",
+            code
         );
-        assert_eq!("/{someId}".to_snake_case(), "some_id");
+        // the fields are in the order of the spec
+        let zebra = find(&code, "pub zebra:").unwrap();
+        let labels = find(&code, "pub labels:").unwrap();
+        let apple = find(&code, "pub apple:").unwrap();
+        assert!(zebra < labels && labels < apple, "{}", code);
+
+        let opts = GenerationOptions {
+            map_type: MapType::BTreeMap,
+            ..Default::default()
+        };
+        let code = generate_from_yaml_source_with_options(yaml.as_bytes(), &opts).unwrap();
         assert_eq!(
-            "/123_abc{xyz\\!\"£$%^}/456 asdf".to_snake_case(),
-            "123_abc_xyz_456_asdf"
-        )
+            count(&code, "std::collections::BTreeMap<String,"),
+            3,
+            "{}",
+            code
+        );
+        assert!(!has(&code, "hsr::indexmap"), "{}", code);
     }
 
     #[test]
-    fn test_valid_identifier() {
-        assert!(Ident::from_str("x").is_ok());
-        assert!(Ident::from_str("_").is_ok());
-        assert!(Ident::from_str("x1").is_ok());
-        assert!(Ident::from_str("x1_23_aB").is_ok());
+    fn test_samples() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Pets
+  version: 0.1.0
+paths: {}
+components:
+  schemas:
+    Kind:
+      type: string
+      enum: [cat, dog]
+    Pet:
+      type: object
+      required: [name, age]
+      properties:
+        name:
+          type: string
+        age:
+          type: integer
+          minimum: 0
+          maximum: 30
+        kind:
+          $ref: '#/components/schemas/Kind'
+        friends:
+          type: array
+          items:
+            $ref: '#/components/schemas/Pet'
+"#;
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        assert!(!has(&code, "hsr::fake"), "{}", code);
 
-        assert!(Ident::from_str("").is_err());
-        assert!(Ident::from_str("1abc").is_err());
-        assert!(Ident::from_str("abc!").is_err());
+        let opts = GenerationOptions {
+            samples: true,
+            ..Default::default()
+        };
+        let code = generate_from_yaml_source_with_options(yaml.as_bytes(), &opts).unwrap();
+        assert!(has(&code, "impl hsr::fake::Sample for Pet"), "{}", code);
+        assert!(has(&code, "impl hsr::fake::Sample for Kind"), "{}", code);
+        assert!(has(&code, "rng.below(2)"), "{}", code);
+        // within the bounds, and optional fields may be missing
+        assert!(
+            has(&code, "age: rng.int_in(Some(0i64), Some(30i64))"),
+            "{}",
+            code
+        );
+        assert!(has(&code, "kind: rng.maybe("), "{}", code);
+        assert!(has(&code, "rng.nested("), "{}", code);
     }
 
     #[test]
-    fn test_analyse_path() {
-        use PathSegment::*;
+    fn test_example_tests() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Pets
+  version: 0.1.0
+paths:
+  /pets/{id}:
+    get:
+      operationId: get_pet
+      parameters:
+        - in: path
+          name: id
+          required: true
+          schema:
+            type: integer
+          examples:
+            rex:
+              value: 1
+            missing:
+              value: 2
+        - in: query
+          name: verbose
+          schema:
+            type: boolean
+      responses:
+        '200':
+          description: The pet
+          content:
+            application/json:
+              schema:
+                type: object
+                properties:
+                  name:
+                    type: string
+              examples:
+                rex:
+                  value:
+                    name: Rex
+        '404':
+          description: No such pet
+          content:
+            application/json:
+              schema:
+                type: string
+              examples:
+                missing:
+                  $ref: '#/components/examples/Missing'
+  /pets:
+    post:
+      operationId: add_pet
+      requestBody:
+        content:
+          application/json:
+            schema:
+              type: string
+      responses:
+        '201':
+          description: Added
+          content:
+            application/json:
+              schema:
+                type: string
+              example: added
+components:
+  examples:
+    Missing:
+      value: no such pet
+"#;
+        let model = ir::parse_spec(yaml.as_bytes()).unwrap();
+        let examples = model.routes["/pets/{id}"][0].examples();
+        assert_eq!(examples.len(), 2);
+        assert_eq!(examples[0].name, "rex");
+        assert_eq!(examples[0].params["id"], 1);
+        assert_eq!(examples[1].status, 404);
+        assert_eq!(examples[1].response, "no such pet");
+        // without an example of the body, the call can't be made
+        assert!(model.routes["/pets"][0].examples().is_empty());
 
-        // Should fail
-        assert!(RoutePath::analyse("").is_err());
-        assert!(RoutePath::analyse("a").is_err());
-        assert!(RoutePath::analyse("/a/").is_err());
-        assert!(RoutePath::analyse("/a/b/c/").is_err());
-        assert!(RoutePath::analyse("/a{").is_err());
-        assert!(RoutePath::analyse("/a{}").is_err());
-        assert!(RoutePath::analyse("/{}a").is_err());
-        assert!(RoutePath::analyse("/{a}a").is_err());
-        assert!(RoutePath::analyse("/ a").is_err());
-        assert!(RoutePath::analyse("/1").is_err());
-        assert!(RoutePath::analyse("/a//b").is_err());
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        assert!(!has(&code, "example_tests"), "{}", code);
+        let opts = GenerationOptions {
+            example_tests: Some("crate::tests::example_api".into()),
+            ..Default::default()
+        };
+        let code = generate_from_yaml_source_with_options(yaml.as_bytes(), &opts).unwrap();
+        assert!(has(&code, "fn get_pet_examples()"), "{}", code);
+        assert!(!has(&code, "fn add_pet_examples()"), "{}", code);
+        assert!(
+            has(&code, "server::serve(crate::tests::example_api(), cfg)"),
+            "{}",
+            code
+        );
+        assert!(has(&code, "\"get_pet/missing\""), "{}", code);
 
-        assert!(RoutePath::analyse("/a").is_ok());
-        assert!(RoutePath::analyse("/a/b/c").is_ok());
-        assert!(RoutePath::analyse("/a/a/a").is_ok());
-        assert!(RoutePath::analyse("/a1/b2/c3").is_ok());
+        let opts = GenerationOptions {
+            example_tests: Some("not a path".into()),
+            ..Default::default()
+        };
+        assert!(generate_from_yaml_source_with_options(yaml.as_bytes(), &opts).is_err());
+    }
 
-        assert!(RoutePath::analyse("/{a1}").is_ok());
-        assert!(RoutePath::analyse("/{a1}/b2/{c3}").is_ok());
-        assert!(RoutePath::analyse("/{a1B2c3}").is_ok());
-        assert!(RoutePath::analyse("/{a1_b2_c3}").is_ok());
+    #[test]
+    fn test_load_test() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Pets
+  version: 0.1.0
+paths:
+  /pets/{id}:
+    get:
+      operationId: get_pet
+      parameters:
+        - in: path
+          name: id
+          required: true
+          schema:
+            type: integer
+            minimum: 1
+        - in: query
+          name: verbose
+          schema:
+            type: boolean
+      responses:
+        '200':
+          description: The pet
+  /pets:
+    post:
+      operationId: add_pet
+      requestBody:
+        content:
+          application/json:
+            schema:
+              type: string
+      responses:
+        '201':
+          description: Added
+"#;
+        let opts = GenerationOptions {
+            load_test: true,
+            ..Default::default()
+        };
+        // the inputs are made by the sample impls
+        assert!(generate_from_yaml_source_with_options(yaml.as_bytes(), &opts).is_err());
 
-        // duplicate param
-        assert!(RoutePath::analyse("/{a}/{b}/{a}").is_err());
+        let opts = GenerationOptions {
+            load_test: true,
+            samples: true,
+            ..Default::default()
+        };
+        let code = generate_from_yaml_source_with_options(yaml.as_bytes(), &opts).unwrap();
+        assert!(has(&code, "pub mod loadtest"), "{}", code);
+        assert!(has(&code, "hsr::loadtest::drive(\"get_pet\""), "{}", code);
+        assert!(has(&code, "hsr::loadtest::drive(\"add_pet\""), "{}", code);
+        assert!(has(&code, "rng.int_in(Some(1i64), None)"), "{}", code);
+        assert!(has(&code, "rng.maybe(|rng| <"), "{}", code);
+    }
+
+    #[test]
+    fn test_routes_without_parameters() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Pets
+  version: 0.1.0
+paths:
+  /pets:
+    get:
+      operationId: list_pets
+      responses:
+        '200':
+          description: The pets
+  /pets/search:
+    get:
+      operationId: search_pets
+      parameters:
+        - in: query
+          name: name
+          schema:
+            type: string
+      responses:
+        '200':
+          description: The pets
+"#;
+        let model = ir::parse_spec(yaml.as_bytes()).unwrap();
+        assert!(model.routes["/pets"][0].query_params().is_none());
+        assert!(model.routes["/pets/search"][0].query_params().is_some());
+
+        // only the operation with parameters has a type and an extractor for them
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        assert!(!has(&code, "ListPetsQuery"), "{}", code);
+        assert!(!has(&code, "ListPetsPath"), "{}", code);
+        assert!(has(&code, "SearchPetsQuery"), "{}", code);
+        assert_eq!(count(&code, "AxQuery<"), 1, "{}", code);
+        assert_eq!(count(&code, "round_trip_query("), 1, "{}", code);
+        assert!(!has(&code, "hsr::path::Path<"), "{}", code);
+    }
+
+    #[test]
+    fn test_fuzz_targets() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Pets
+  version: 0.1.0
+paths:
+  /pets/{id}:
+    put:
+      operationId: update_pet
+      parameters:
+        - in: path
+          name: id
+          required: true
+          schema:
+            type: integer
+        - in: query
+          name: dry_run
+          schema:
+            type: boolean
+      requestBody:
+        content:
+          application/json:
+            schema:
+              type: string
+      responses:
+        '200':
+          description: Updated
+  /pets:
+    get:
+      operationId: list_pets
+      responses:
+        '200':
+          description: The pets
+"#;
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        assert!(!has(&code, "hsr::fuzz"), "{}", code);
+        let opts = GenerationOptions {
+            fuzz_targets: true,
+            ..Default::default()
+        };
+        let code = generate_from_yaml_source_with_options(yaml.as_bytes(), &opts).unwrap();
+        assert!(has(&code, "pub fn update_pet(data: &[u8])"), "{}", code);
+        assert!(has(&code, "pub fn list_pets(data: &[u8])"), "{}", code);
+        assert!(has(&code, "(&[\"id\"])"), "{}", code);
+        assert!(has(&code, "input.query::<"), "{}", code);
+        assert!(has(&code, "input.json::<"), "{}", code);
+
+        let targets = generate_fuzz_targets_from_yaml_source(yaml.as_bytes(), "pets::api").unwrap();
+        assert_eq!(targets.len(), 2);
+        assert_eq!(targets[0].0, "update_pet.rs");
+        assert!(targets[0]
+            .1
+            .contains("fuzz_target!(|data: &[u8]| pets::api::fuzz::update_pet(data));"));
+        assert!(generate_fuzz_targets_from_yaml_source(yaml.as_bytes(), "not a path").is_err());
+    }
+
+    #[test]
+    fn test_non_snake_case_names() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Pets
+  version: 0.1.0
+paths:
+  /pets/{petId}:
+    get:
+      operationId: getPet
+      parameters:
+        - in: path
+          name: petId
+          required: true
+          schema:
+            type: integer
+      responses:
+        '200':
+          description: The pet
+          content:
+            application/json:
+              schema:
+                $ref: '#/components/schemas/Pet'
+  /pets:
+    get:
+      operationId: list_pets
+      responses:
+        '200':
+          description: The pets
+components:
+  schemas:
+    Pet:
+      type: object
+      properties:
+        petName:
+          type: string
+        age:
+          type: integer
+"#;
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        assert!(!has(&code, "unsafe"), "{}", code);
+        assert!(has(&code, "#[allow(unused_imports)]"), "{}", code);
+        // the allows are only where the names of the spec aren't snake case
+        let code_flat = flat(&code);
+        let allowed: Vec<_> = code_flat
+            .split("#[allow(non_snake_case)]")
+            .skip(1)
+            .map(|after| after.split(&[',', ';', '{'][..]).next().unwrap())
+            .collect();
+        assert!(
+            allowed.iter().any(|item| has(item, "fn getPet(")),
+            "{}",
+            code
+        );
+        assert!(
+            allowed.iter().any(|item| has(item, "pub petName:")),
+            "{}",
+            code
+        );
+        assert!(
+            !allowed.iter().any(|item| has(item, "list_pets")),
+            "{}",
+            code
+        );
+        assert!(!allowed.iter().any(|item| has(item, "age:")), "{}", code);
+    }
+
+    #[test]
+    fn test_case_insensitive_enums() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Enums
+  version: 0.1.0
+paths: {}
+components:
+  schemas:
+    Size:
+      type: string
+      enum: [small, LARGE]
+      x-hsr-case-insensitive: true
+    Color:
+      type: string
+      enum: [red, green]
+"#;
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        assert!(has(&code, "Deserialize<'de> for Size"), "{}", code);
+        assert!(
+            has(&code, r#"value.eq_ignore_ascii_case("LARGE")"#),
+            "{}",
+            code
+        );
+        // serialized as in the spec
+        assert!(has(&code, r#"#[serde(rename = "LARGE")]"#), "{}", code);
+        assert!(!has(&code, "Deserialize<'de> for Color"), "{}", code);
+
+        let opts = GenerationOptions {
+            case_insensitive_enums: true,
+            ..Default::default()
+        };
+        let code = generate_from_yaml_source_with_options(yaml.as_bytes(), &opts).unwrap();
+        assert!(has(&code, "Deserialize<'de> for Color"), "{}", code);
 
+        let yaml = yaml.replace("enum: [red, green]", "x-hsr-case-insensitive: true");
+        let err = generate_from_yaml_source(yaml.as_bytes()).unwrap_err();
         assert_eq!(
-            RoutePath::analyse("/{a_1}/{b2C3}/a/b").unwrap(),
-            RoutePath {
-                segments: vec![
-                    Parameter("a_1".into()),
-                    Parameter("b2C3".into()),
-                    Literal("a".into()),
-                    Literal("b".into())
-                ]
+            err.pointer(),
+            Some("/components/schemas/Color/x-hsr-case-insensitive")
+        );
+    }
+
+    #[test]
+    fn test_lenient_numbers() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Numbers
+  version: 0.1.0
+paths: {}
+components:
+  schemas:
+    Age:
+      type: integer
+      x-hsr-lenient: true
+    Pet:
+      type: object
+      required: [age]
+      properties:
+        age:
+          $ref: '#/components/schemas/Age'
+        weight:
+          type: number
+"#;
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        assert!(
+            has(
+                &code,
+                r#"#[serde(deserialize_with = "hsr::lenient::number")]"#
+            ),
+            "{}",
+            code
+        );
+        assert!(!has(&code, "hsr::lenient::optional_number"), "{}", code);
+
+        let opts = GenerationOptions {
+            lenient_numbers: true,
+            ..Default::default()
+        };
+        let code = generate_from_yaml_source_with_options(yaml.as_bytes(), &opts).unwrap();
+        assert!(
+            has(
+                &code,
+                r#"deserialize_with = "hsr::lenient::optional_number""#
+            ),
+            "{}",
+            code
+        );
+
+        let yaml = yaml.replace("type: integer", "type: string");
+        let err = generate_from_yaml_source(yaml.as_bytes()).unwrap_err();
+        assert_eq!(err.pointer(), Some("/components/schemas/Age/x-hsr-lenient"));
+    }
+
+    #[test]
+    fn test_integer_and_nullable_enums() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Enums
+  version: 0.1.0
+paths: {}
+components:
+  schemas:
+    Level:
+      type: integer
+      enum: [1, 2, -1]
+    Size:
+      type: string
+      enum: [small, large, null]
+    Shirt:
+      type: object
+      required: [size]
+      properties:
+        size:
+          $ref: '#/components/schemas/Size'
+"#;
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        assert!(has(&code, "enum Level"), "{}", code);
+        assert!(has(&code, "VMinus1"), "{}", code);
+        assert!(has(&code, "serialize_i64"), "{}", code);
+        assert!(has(&code, "enum SizeOpt"), "{}", code);
+        assert!(has(&code, "Option<SizeOpt>"), "{}", code);
+
+        let model = ir::parse_spec(yaml.as_bytes()).unwrap();
+        let size = TypePath::from_reference("#/components/schemas/Size").unwrap();
+        let size = model.resolve(&model.types[&size]).unwrap();
+        assert!(size.meta().nullable);
+        assert!(matches!(size.inner(), ir::TypeInner::StringEnum(values) if values.len() == 2));
+
+        let yaml = yaml.replace("[1, 2, -1]", "[1, two]");
+        let err = generate_from_yaml_source(yaml.as_bytes()).unwrap_err();
+        assert_eq!(err.pointer(), Some("/components/schemas/Level"));
+    }
+
+    #[test]
+    fn test_return_types_are_errors() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Pets
+  version: 0.1.0
+paths:
+  /pets/{id}:
+    get:
+      operationId: get_pet
+      parameters:
+        - name: id
+          in: path
+          required: true
+          schema:
+            type: integer
+      responses:
+        '200':
+          description: The pet
+        '404':
+          description: No such pet
+"#;
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        assert!(has(&code, "fmt::Display for GetPet"), "{}", code);
+        assert!(has(&code, "\"No such pet\""), "{}", code);
+        assert!(has(&code, "error::Error for GetPet"), "{}", code);
+        assert!(has(&code, "ResponseError for GetPet"), "{}", code);
+    }
+
+    #[test]
+    fn test_fallible_methods() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Pets
+  version: 0.1.0
+paths:
+  /pets:
+    get:
+      operationId: list_pets
+      responses:
+        '200':
+          description: The pets
+"#;
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        assert!(!has(&code, "map_error"), "{}", code);
+
+        let opts = GenerationOptions {
+            fallible: true,
+            stub: true,
+            ..Default::default()
+        };
+        let code = generate_from_yaml_source_with_options(yaml.as_bytes(), &opts).unwrap();
+        assert!(has(&code, "type Error"), "{}", code);
+        assert!(has(&code, "Self::Error"), "{}", code);
+        assert!(has(&code, "map_error"), "{}", code);
+        assert!(has(&code, "Infallible"), "{}", code);
+
+        let opts = GenerationOptions {
+            backend: Backend::Axum,
+            ..opts
+        };
+        let err = generate_from_yaml_source_with_options(yaml.as_bytes(), &opts).unwrap_err();
+        assert!(err.to_string().contains("axum"), "{}", err);
+    }
+
+    #[test]
+    fn test_proxy() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Pets
+  version: 0.1.0
+paths:
+  /pets/{id}:
+    get:
+      operationId: get_pet
+      parameters:
+        - name: id
+          in: path
+          required: true
+          schema:
+            type: integer
+      responses:
+        '200':
+          description: The pet
+          content:
+            application/json:
+              schema:
+                type: string
+"#;
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        assert!(!has(&code, "pub mod proxy"), "{}", code);
+
+        let opts = GenerationOptions {
+            proxy: true,
+            ..Default::default()
+        };
+        let code = generate_from_yaml_source_with_options(yaml.as_bytes(), &opts).unwrap();
+        assert!(has(&code, "pub mod proxy"), "{}", code);
+        assert!(
+            has(&code, ".with(hsr::CallOptions::forwarding(req.headers()))"),
+            "{}",
+            code
+        );
+        assert!(has(&code, "client.get_pet(id,).await"), "{}", code);
+        assert!(
+            has(
+                &code,
+                "web::resource(\"/pets/{id}\").route(web::get().to(get_pet))"
+            ),
+            "{}",
+            code
+        );
+
+        let opts = GenerationOptions {
+            backend: Backend::Axum,
+            ..opts
+        };
+        let err = generate_from_yaml_source_with_options(yaml.as_bytes(), &opts).unwrap_err();
+        assert!(err.to_string().contains("axum"), "{}", err);
+    }
+
+    #[cfg(all(feature = "pretty", not(feature = "rustfmt")))]
+    #[test]
+    fn test_pretty() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Pets
+  version: 0.1.0
+paths: {}
+components:
+  schemas:
+    Pet:
+      type: object
+      required:
+        - name
+      properties:
+        name:
+          type: string
+"#;
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        assert!(
+            code.contains("pub struct Pet {\n    pub name: PetName,\n}"),
+            "{}",
+            code
+        );
+        // formatting is stable
+        assert_eq!(prettify_code(code.clone()).unwrap(), code);
+
+        let err = prettify_code("fn {".into()).unwrap_err();
+        assert!(matches!(err, Error::BadCodegen(_)), "{}", err);
+    }
+
+    #[test]
+    fn test_plugins() {
+        #[derive(Debug)]
+        struct Describe;
+
+        impl CodegenPlugin for Describe {
+            fn on_component_type(
+                &self,
+                name: &TypeName,
+                _typ: &ir::Type,
+                _model: &ir::ApiModel,
+            ) -> TokenStream {
+                quote! { impl crate::Described for #name {} }
+            }
+
+            fn on_route(
+                &self,
+                path: &str,
+                route: &ir::Route,
+                _model: &ir::ApiModel,
+            ) -> TokenStream {
+                let opid = route.operation_id().to_string().to_uppercase();
+                let name = quote::format_ident!("{}_PATH", opid);
+                quote! { pub const #name: &str = #path; }
+            }
+
+            fn extra_items(&self, model: &ir::ApiModel) -> TokenStream {
+                let title = &model.info.title;
+                quote! { pub const DESCRIBED: &str = #title; }
             }
+        }
+
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Pets
+  version: 0.1.0
+paths:
+  /pets:
+    get:
+      operationId: list_pets
+      responses:
+        '200':
+          description: The pets
+          content:
+            application/json:
+              schema:
+                type: array
+                items:
+                  $ref: '#/components/schemas/Pet'
+components:
+  schemas:
+    Pet:
+      type: object
+      properties:
+        name:
+          type: string
+"#;
+        let opts = GenerationOptions {
+            plugins: vec![std::sync::Arc::new(Describe)],
+            ..Default::default()
+        };
+        let code = generate_from_yaml_source_with_options(yaml.as_bytes(), &opts).unwrap();
+        assert!(has(&code, "impl crate::Described for Pet {}"), "{}", code);
+        // only component schemas are passed to `on_component_type`
+        assert_eq!(count(&code, "impl crate::Described for"), 1, "{}", code);
+        assert!(
+            has(&code, "pub const LIST_PETS_PATH: &str = \"/pets\";"),
+            "{}",
+            code
+        );
+        assert!(
+            has(&code, "pub const DESCRIBED: &str = \"Pets\";"),
+            "{}",
+            code
+        );
+    }
+
+    #[test]
+    fn test_shared_state() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Pets
+  version: 0.1.0
+paths:
+  /pets:
+    get:
+      operationId: list_pets
+      parameters:
+        - name: limit
+          in: query
+          schema:
+            type: integer
+      responses:
+        '200':
+          description: The pets
+"#;
+        let opts = GenerationOptions {
+            shared_state: vec![
+                SharedState::new("metrics", "crate::metrics::Registry"),
+                SharedState::new("flags", "std::sync::Arc<dyn crate::Flags>"),
+            ],
+            ..Default::default()
+        };
+        let code = generate_from_yaml_source_with_options(yaml.as_bytes(), &opts).unwrap();
+        assert!(has(&code, "pub struct State"), "{}", code);
+        assert!(
+            has(&code, "metrics: AxData<crate::metrics::Registry>"),
+            "{}",
+            code
+        );
+        assert!(has(&code, "state: State"), "{}", code);
+        assert!(has(&code, "app_data(state.flags.clone())"), "{}", code);
+
+        let bad = |state: SharedState| {
+            let opts = GenerationOptions {
+                shared_state: vec![state],
+                ..Default::default()
+            };
+            generate_from_yaml_source_with_options(yaml.as_bytes(), &opts)
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(bad(SharedState::new("limit", "u32")).contains("clashes"));
+        assert!(bad(SharedState::new("payload", "u32")).contains("can't be named"));
+        assert!(bad(SharedState::new("flags", "not a type")).contains("Bad type"));
+        assert!(bad(SharedState::new("1st", "u32")).contains("Bad identifier"));
+
+        let opts = GenerationOptions {
+            backend: Backend::Axum,
+            ..opts
+        };
+        let err = generate_from_yaml_source_with_options(yaml.as_bytes(), &opts).unwrap_err();
+        assert!(err.to_string().contains("axum"), "{}", err);
+    }
+
+    #[test]
+    fn test_lifecycle_hooks() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Pets
+  version: 0.1.0
+paths:
+  /pets:
+    get:
+      operationId: list_pets
+      responses:
+        '200':
+          description: The pets
+"#;
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        assert!(has(&code, "async fn on_start(&self) {}"), "{}", code);
+        assert!(has(&code, "api.on_shutdown().await"), "{}", code);
+        assert!(has(&code, "api.schedule(&mut tasks)"), "{}", code);
+
+        let yaml = yaml.replace("list_pets", "on_start");
+        let err = generate_from_yaml_source(yaml.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("provided method"), "{}", err);
+    }
+
+    #[test]
+    fn test_fallbacks() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Pets
+  version: 0.1.0
+paths:
+  /pets:
+    get:
+      operationId: list_pets
+      responses:
+        '200':
+          description: The pets
+    post:
+      operationId: create_pet
+      responses:
+        '201':
+          description: Created
+"#;
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        assert!(
+            has(&code, r#"data.method_not_allowed(&req, &["GET", "POST"])"#),
+            "{}",
+            code
+        );
+        assert!(
+            has(&code, ".default_service(web::route().to(not_found::<A>))"),
+            "{}",
+            code
+        );
+
+        let yaml = yaml.replace("create_pet", "not_found");
+        let err = generate_from_yaml_source(yaml.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("provided method"), "{}", err);
+    }
+
+    #[test]
+    fn test_timeouts() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Reports
+  version: 0.1.0
+paths:
+  /reports:
+    post:
+      operationId: create_report
+      x-hsr-timeout: 2.5
+      responses:
+        '200':
+          description: The report
+"#;
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        assert!(
+            has(&code, "Some(std::time::Duration::from_millis(2500))"),
+            "{}",
+            code
+        );
+        assert!(has(&code, "req.timeout("), "{}", code);
+
+        let model = ir::parse_spec(yaml.as_bytes()).unwrap();
+        let route = &model.routes["/reports"][0];
+        assert_eq!(route.timeout(), Some(ir::Timeout { millis: 2500 }));
+
+        let yaml = yaml.replace("2.5", "soon");
+        let err = generate_from_yaml_source(yaml.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("x-hsr-timeout"), "{}", err);
+    }
+
+    #[test]
+    fn test_audit() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Pets
+  version: 0.1.0
+paths:
+  /pets/{id}:
+    delete:
+      operationId: delete_pet
+      x-hsr-audit: true
+      parameters:
+        - in: path
+          name: id
+          required: true
+          schema:
+            type: integer
+      responses:
+        '204':
+          description: Deleted
+"#;
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        assert!(
+            has(
+                &code,
+                "async fn audit(&self, event: hsr::audit::AuditEvent)"
+            ),
+            "{}",
+            code
+        );
+        assert!(
+            has(
+                &code,
+                r#"hsr::audit::Audit::start("delete_pet", &req, &(&*path,))"#
+            ),
+            "{}",
+            code
+        );
+        let model = ir::parse_spec(yaml.as_bytes()).unwrap();
+        assert!(model.routes["/pets/{id}"][0].is_audited());
+
+        let plain = yaml.replace("x-hsr-audit: true", "x-hsr-audit: false");
+        let code = generate_from_yaml_source(plain.as_bytes()).unwrap();
+        assert!(!has(&code, "hsr::audit"), "{}", code);
+
+        let yaml = yaml.replace("x-hsr-audit: true", "x-hsr-audit: yes please");
+        let err = generate_from_yaml_source(yaml.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("x-hsr-audit"), "{}", err);
+    }
+
+    #[test]
+    fn test_authorization() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Pets
+  version: 0.1.0
+paths:
+  /pets/{id}:
+    put:
+      operationId: update_pet
+      tags: [pets]
+      security:
+        - oauth: [pets:write]
+      parameters:
+        - in: path
+          name: id
+          required: true
+          schema:
+            type: integer
+      requestBody:
+        required: true
+        content:
+          application/json:
+            schema:
+              type: string
+      responses:
+        '204':
+          description: Updated
+"#;
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        assert!(!has(&code, "hsr::authz"), "{}", code);
+
+        let opts = GenerationOptions {
+            authorization: true,
+            stub: true,
+            ..Default::default()
+        };
+        let code = generate_from_yaml_source_with_options(yaml.as_bytes(), &opts).unwrap();
+        assert!(
+            has(
+                &code,
+                "async fn authorize(&self, input: hsr::authz::AuthzInput)"
+            ),
+            "{}",
+            code
         );
+        assert!(has(&code, "hsr::authz::AuthzInput::new("), "{}", code);
+        assert!(has(&code, ".with_params(&*path)"), "{}", code);
+        assert!(has(&code, ".with_body(&body)"), "{}", code);
+        assert!(has(&code, "data.authorize(authz).await"), "{}", code);
+
+        let opts = GenerationOptions {
+            backend: Backend::Axum,
+            stub: false,
+            ..opts
+        };
+        let err = generate_from_yaml_source_with_options(yaml.as_bytes(), &opts).unwrap_err();
+        assert!(err.to_string().contains("axum"), "{}", err);
+    }
+
+    #[test]
+    fn test_api_constants() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Pets
+  version: 1.2.0
+servers:
+  - url: https://pets.example.com/v1/
+paths:
+  /pets:
+    get:
+      operationId: list_pets
+      responses:
+        '200':
+          description: The pets
+"#;
+        let (_, raw) = parse_openapi(yaml).unwrap();
+        assert_eq!(base_path(&raw), "/v1");
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        assert!(has(&code, "API_VERSION"), "{}", code);
+        assert!(has(&code, "\"1.2.0\""), "{}", code);
+        assert!(has(&code, "\"/v1\""), "{}", code);
+        assert!(!has(&code, "\"/version\""), "{}", code);
+
+        let opts = GenerationOptions {
+            version_route: true,
+            ..Default::default()
+        };
+        let code = generate_from_yaml_source_with_options(yaml.as_bytes(), &opts).unwrap();
+        assert!(has(&code, "API_INFO.configure"), "{}", code);
+
+        let yaml = yaml.replace("/pets:", "/version:");
+        let err = generate_from_yaml_source_with_options(yaml.as_bytes(), &opts).unwrap_err();
+        assert!(err.to_string().contains("/version"), "{}", err);
+
+        for (url, path) in &[("/api/", "/api"), ("http://localhost:8000", ""), ("/", "")] {
+            let yaml = format!(
+                "openapi: 3.0.0\ninfo: {{title: t, version: v}}\nservers: [{{url: '{}'}}]\npaths: {{}}",
+                url
+            );
+            let (_, raw) = parse_openapi(&yaml).unwrap();
+            assert_eq!(base_path(&raw), *path);
+        }
+    }
+
+    #[test]
+    fn test_server_variables() {
+        let yaml = r#"
+openapi: 3.0.0
+info: { title: Pets, version: 1.0.0 }
+servers:
+  - url: https://{region}.pets.example.com/{basePath}
+    variables:
+      region:
+        default: eu-west
+        enum: [us-east, eu-west]
+        description: Where the data is kept
+      basePath:
+        default: v2
+paths:
+  /pets:
+    get:
+      operationId: list_pets
+      responses:
+        '200':
+          description: The pets
+"#;
+        let (_, raw) = parse_openapi(yaml).unwrap();
+        assert_eq!(base_path(&raw), "/v2");
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        assert!(has(&code, "pub struct ServerVariables"), "{}", code);
+        assert!(has(&code, "pub enum ServerRegion"), "{}", code);
+        assert!(has(&code, "EuWest"), "{}", code);
+        assert!(has(&code, "\"eu-west\""), "{}", code);
+        assert!(has(&code, "\"v2\""), "{}", code);
+        assert!(has(&code, "\"https://{}.pets.example.com/{}\""), "{}", code);
+        assert!(has(&code, "fn for_server"), "{}", code);
+
+        let bad = yaml.replace("default: eu-west", "default: ap-south");
+        let err = generate_from_yaml_source(bad.as_bytes()).unwrap_err();
+        assert_eq!(err.pointer(), Some("/servers/0/variables/region"));
+        let bad = yaml.replace("{basePath}", "{version}");
+        let err = generate_from_yaml_source(bad.as_bytes()).unwrap_err();
+        assert_eq!(err.pointer(), Some("/servers/0/url"));
+
+        // a relative URL can't be built by the client
+        let relative = yaml.replace("https://{region}.pets.example.com/", "/");
+        let (_, raw) = parse_openapi(&relative).unwrap();
+        assert_eq!(base_path(&raw), "/v2");
+        let code = generate_from_yaml_source(relative.as_bytes()).unwrap();
+        assert!(!has(&code, "ServerVariables"), "{}", code);
+    }
+
+    #[test]
+    fn test_client_asks_for_compressed_responses() {
+        let yaml = r#"
+openapi: 3.0.0
+info: { title: Pets, version: 1.0.0 }
+paths:
+  /pets:
+    get:
+      operationId: list_pets
+      responses:
+        '200':
+          description: The pets
+"#;
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        // after the call options, so that an `Accept-Encoding` set there wins
+        let options = find(&code, "req = self.options.apply(req);").unwrap();
+        let compressed = find(&code, "req = hsr::compression::accept_compressed(req);").unwrap();
+        assert!(options < compressed, "{}", code);
+    }
+
+    #[test]
+    fn test_cache_scope() {
+        let spec = |policy: &str| {
+            format!(
+                r#"
+openapi: 3.0.0
+info: {{ title: Pets, version: 1.0.0 }}
+paths:
+  /pets:
+    get:
+      operationId: list_pets
+      x-hsr-cache: {}
+      responses:
+        '200':
+          description: The pets
+"#,
+                policy
+            )
+        };
+        let generate = |policy| generate_from_yaml_source(spec(policy).as_bytes());
+        let policy = "hsr::cache::CachePolicy::max_age(std::time::Duration::from_secs(60))";
+
+        let code = generate("{ max-age: 60 }").unwrap();
+        assert!(has(&code, &format!("{})", policy)), "{}", code);
+        let code = generate("{ max-age: 60, private: true }").unwrap();
+        assert!(has(&code, &format!("{}.private()", policy)), "{}", code);
+        let code = generate("{ max-age: 60, public: true }").unwrap();
+        assert!(has(&code, &format!("{}.public()", policy)), "{}", code);
+        assert!(generate("{ max-age: 60, public: true, private: true }").is_err());
+    }
+
+    #[test]
+    fn test_environments() {
+        let yaml = r#"
+openapi: 3.0.0
+info: { title: Pets, version: 1.0.0 }
+servers:
+  - url: https://pets.example.com/v1
+    description: Production
+  - url: https://{stage}.pets.example.com/v1
+    description: Staging
+    variables:
+      stage: { default: staging }
+  - url: /v1
+    description: Wherever the spec is served
+  - url: http://localhost:8000/v1
+paths:
+  /pets:
+    get:
+      operationId: list_pets
+      responses:
+        '200':
+          description: The pets
+"#;
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        assert!(has(&code, "fn with_base_url"), "{}", code);
+        assert!(has(&code, "fn with_connection_options"), "{}", code);
+        assert!(has(&code, "fn with_circuit_breaker"), "{}", code);
+        assert!(has(&code, "breaker.check()?"), "{}", code);
+        assert!(has(&code, "fn with_cassette"), "{}", code);
+        assert!(has(&code, "fn with_observer"), "{}", code);
+        assert!(has(&code, "pub trait DynClient"), "{}", code);
+        assert!(has(&code, "impl DynClient for Client"), "{}", code);
+        assert!(has(&code, "DynClient for InProcess"), "{}", code);
+        assert!(has(&code, "hsr::dispatch::guard("), "{}", code);
+        assert!(has(&code, "cassette.play(&request)?"), "{}", code);
+        assert!(has(&code, "pub enum Environment"), "{}", code);
+        assert!(has(&code, "Production"), "{}", code);
+        assert!(
+            has(&code, "\"https://staging.pets.example.com/v1\""),
+            "{}",
+            code
+        );
+        assert!(has(&code, "Server3"), "{}", code);
+        assert!(!has(&code, "WhereverTheSpecIsServed"), "{}", code);
+        assert!(has(&code, "fn for_environment"), "{}", code);
+
+        let bad = yaml.replace("http://localhost:8000/v1", "http://localhost:80000/v1");
+        let err = generate_from_yaml_source(bad.as_bytes()).unwrap_err();
+        assert!(err.to_string().contains("localhost:80000"), "{}", err);
+
+        let yaml = yaml.replace("servers:", "x-servers:");
+        let code = generate_from_yaml_source(yaml.as_bytes()).unwrap();
+        assert!(!has(&code, "Environment"), "{}", code);
+        assert!(has(&code, "fn with_base_url"), "{}", code);
+    }
+
+    #[test]
+    fn test_component_schema_extensions() {
+        let raw = serde_json::json!({"components": {"schemas": {
+            "Plain": {"type": "string"},
+            "Tagged": {"type": "string", "x-owner": "billing", "x-pii": true},
+        }}});
+        let extensions = component_schema_extensions(&raw);
+        assert_eq!(extensions.len(), 1);
+        assert_eq!(extensions["Tagged"]["x-owner"], "billing");
+        assert_eq!(extensions["Tagged"]["x-pii"], true);
     }
 
     // #[test]
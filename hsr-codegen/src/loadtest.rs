@@ -0,0 +1,100 @@
+//! The load test (see `GenerationOptions::load_test`): a `loadtest` module whose
+//! `run` drives every operation the client can call, with random inputs made by
+//! the `Sample` impls of the types (see `GenerationOptions::samples`).
+
+use proc_macro2::TokenStream;
+use quote::quote;
+
+use crate::route::Route;
+use crate::walk::{lookup_type_recursive, sample_expr, TypeInner, TypeLookup};
+use crate::{Map, Result};
+
+/// Whether the load test can call the operation with random inputs. The client
+/// can't make websocket calls, and bodies of several representations have no
+/// random values.
+fn loadable(route: &Route, lookup: &TypeLookup) -> Result<bool> {
+    if route.websocket().is_some()
+        || !route.body_media_types().is_empty()
+        || !route.offered_media_types().is_empty()
+    {
+        return Ok(false);
+    }
+    match route.method().body_type().and_then(|path| lookup.get(path)) {
+        Some(body) => Ok(!matches!(
+            lookup_type_recursive(body, lookup)?.typ,
+            TypeInner::Content(_)
+        )),
+        None => Ok(true),
+    }
+}
+
+/// The expression making the arguments of a call of the client, from `rng`.
+/// The arguments are in the order of `Route::client_params`.
+fn call_args(route: &Route, lookup: &TypeLookup) -> Result<Vec<TokenStream>> {
+    let mut args = Vec::new();
+    for (_, (_, type_path)) in route.path_params().into_iter().flatten() {
+        args.push(sample_expr(type_path, lookup)?);
+    }
+    for (_, (meta, type_path)) in route.query_params().into_iter().flatten() {
+        let value = sample_expr(type_path, lookup)?;
+        if meta.optional() {
+            args.push(quote! { rng.maybe(|rng| #value) });
+        } else {
+            args.push(value);
+        }
+    }
+    if let Some(body) = route.method().body_type() {
+        args.push(sample_expr(body, lookup)?);
+    }
+    Ok(args)
+}
+
+pub(crate) fn generate_load_test(
+    routes: &Map<String, Vec<Route>>,
+    lookup: &TypeLookup,
+) -> Result<TokenStream> {
+    let mut runs = Vec::new();
+    for route in routes.values().flatten() {
+        if !loadable(route, lookup)? {
+            continue;
+        }
+        let opid = route.operation_id();
+        let opid_str = opid.to_string();
+        let ix = runs.len() as u64;
+        let args = call_args(route, lookup)?;
+        let rng = if args.is_empty() {
+            quote! { _rng }
+        } else {
+            quote! { rng }
+        };
+        runs.push(quote! {
+            if opts.includes(#opid_str) {
+                runs.push(
+                    hsr::loadtest::drive(#opid_str, &opts, opts.rng(#ix), |#rng| {
+                        client.#opid(#(#args),*)
+                    })
+                    .boxed_local(),
+                );
+            }
+        });
+    }
+    Ok(quote! {
+        /// A load test of the API, calling its operations with random inputs
+        /// (see `hsr::loadtest`)
+        #[allow(dead_code)]
+        pub mod loadtest {
+            use super::*;
+
+            /// Call each operation `opts.rps` times a second for `opts.duration`
+            /// (all at once), against the server at `url`
+            #[allow(unused_mut, unused_variables)]
+            pub async fn run(url: Url, opts: hsr::loadtest::LoadOptions) -> hsr::loadtest::Report {
+                let client = client::Client::new(url);
+                let mut runs: Vec<hsr::futures::future::LocalBoxFuture<hsr::loadtest::OperationStats>> =
+                    Vec::new();
+                #(#runs)*
+                hsr::loadtest::Report::new(hsr::futures::future::join_all(runs).await)
+            }
+        }
+    })
+}
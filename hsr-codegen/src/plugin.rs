@@ -0,0 +1,63 @@
+//! Hooks for emitting custom code alongside the standard output
+
+use std::fmt;
+use std::sync::Arc;
+
+use proc_macro2::TokenStream;
+
+use crate::ir::{ApiModel, Route, Type, TypeName};
+use crate::Result;
+
+/// A plugin which emits extra items (impls, constants, annotations...) into the
+/// generated code. Register plugins with `GenerationOptions::plugins`.
+///
+/// Items are emitted at the top level of the generated code, so can refer
+/// to the generated types directly. All hooks default to emitting nothing.
+/// Plugins are shared between the threads generating the code, with the
+/// `parallel` feature.
+pub trait CodegenPlugin: fmt::Debug + Send + Sync {
+    /// Called for each type defined in `#/components/schemas`.
+    /// `name` is the name of the generated type.
+    fn on_component_type(&self, _name: &TypeName, _typ: &Type, _model: &ApiModel) -> TokenStream {
+        TokenStream::new()
+    }
+
+    /// Called for each route. `path` is the path as written in the spec.
+    fn on_route(&self, _path: &str, _route: &Route, _model: &ApiModel) -> TokenStream {
+        TokenStream::new()
+    }
+
+    /// Called once, after everything else has been generated
+    fn extra_items(&self, _model: &ApiModel) -> TokenStream {
+        TokenStream::new()
+    }
+}
+
+/// Run every plugin over the model, collecting their output
+pub(crate) fn run_plugins(
+    plugins: &[Arc<dyn CodegenPlugin>],
+    model: &ApiModel,
+) -> Result<TokenStream> {
+    let mut items = TokenStream::new();
+    for plugin in plugins {
+        for (path, typ) in &model.types {
+            let is_component = match path.segments() {
+                [components, schemas, _] => components == "components" && schemas == "schemas",
+                _ => false,
+            };
+            if !is_component {
+                continue;
+            }
+            if let Ok(typ) = model.resolve(typ) {
+                items.extend(plugin.on_component_type(&path.canonicalize()?, typ, model));
+            }
+        }
+        for (path, routes) in &model.routes {
+            for route in routes {
+                items.extend(plugin.on_route(path, route, model));
+            }
+        }
+        items.extend(plugin.extra_items(model));
+    }
+    Ok(items)
+}
@@ -4,32 +4,106 @@ use openapiv3::{ReferenceOr, StatusCode as ApiStatusCode};
 use proc_macro2::TokenStream;
 use quote::quote;
 
-use std::collections::HashMap;
 use std::convert::TryFrom;
 use std::hash::Hash;
 use std::ops::Deref;
 
+use crate::examples::Example;
 use crate::walk::{generate_enum_def, Type, Variant};
 use crate::*;
 
 // Just the bits of the Responses that the Route needs to know about
 #[derive(Debug, Clone)]
-pub(crate) struct Responses {
+pub struct Responses {
     pub with_codes: Map<StatusCode, Response>,
     pub default: Option<Response>,
 }
 
 #[derive(Debug, Clone)]
-pub(crate) struct Response {
+pub struct Response {
     pub description: String,
     pub type_path: Option<TypePath>,
+    /// The media types of a body with more than one (or one which isn't JSON),
+    /// whose type is an enum over its representations. Empty for a JSON body.
+    pub media_types: Vec<String>,
+    /// The links to related operations, by name
+    pub links: Map<String, ResponseLink>,
+}
+
+impl Response {
+    /// The description, followed by a list of the links
+    pub(crate) fn documentation(&self) -> String {
+        let mut doc = self.description.clone();
+        if !self.links.is_empty() {
+            doc.push_str("\n\nLinks:\n");
+        }
+        for (name, link) in &self.links {
+            doc.push_str(&format!("\n* `{}`", name));
+            if let Some(target) = &link.operation_id {
+                doc.push_str(&format!(" (to `{}`)", target));
+            }
+            if let Some(description) = &link.description {
+                doc.push_str(&format!(": {}", description));
+            }
+        }
+        doc
+    }
+}
+
+/// A link from a response to a related operation
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResponseLink {
+    pub description: Option<String>,
+    /// The target of the link, unless it is given by `operationRef`
+    pub operation_id: Option<Ident>,
+    /// The parameters of the target, by name, as runtime expressions
+    /// (e.g. `$response.body#/id`) or constants
+    pub parameters: Map<String, String>,
+}
+
+impl ResponseLink {
+    pub(crate) fn from_spec(link: &openapiv3::Link) -> Result<Self> {
+        // read the link as JSON, rather than depend on how `openapiv3` models it
+        let raw = serde_json::to_value(link)
+            .map_err(|e| Error::BadCodegen(format!("Failed to read link: {}", e)))?;
+        let description = raw
+            .get("description")
+            .and_then(|d| d.as_str())
+            .map(String::from);
+        let operation_id = raw
+            .get("operationId")
+            .and_then(|id| id.as_str())
+            .map(str::parse)
+            .transpose()?;
+        let parameters = raw
+            .get("parameters")
+            .and_then(|params| params.as_object())
+            .map(|params| {
+                params
+                    .iter()
+                    .map(|(name, value)| {
+                        let value = match value {
+                            serde_json::Value::String(expr) => expr.clone(),
+                            other => other.to_string(),
+                        };
+                        (name.clone(), value)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        Ok(ResponseLink {
+            description,
+            operation_id,
+            parameters,
+        })
+    }
 }
 
 /// Route contains all the information necessary to contruct the API
 ///
 /// If it has been constructed, the route is logically sound
 #[derive(Debug, Clone, derive_more::Constructor)]
-pub(crate) struct Route {
+pub struct Route {
     summary: Option<String>,
     description: Option<String>,
     operation_id: Ident,
@@ -37,18 +111,429 @@ pub(crate) struct Route {
     path: RoutePath,
     path_params: Option<(TypePath, Map<Ident, (FieldMetadata, TypePath)>)>,
     query_params: Option<(TypePath, Map<Ident, (FieldMetadata, TypePath)>)>,
+    /// The media types of a request body with more than one (see `Response::media_types`)
+    body_media_types: Vec<String>,
     responses: Responses,
+    /// If set, the route upgrades to a websocket carrying messages of this type
+    websocket: Option<TypePath>,
+    rate_limit: Option<RateLimit>,
+    pagination: Option<Pagination>,
+    idempotency: Option<Idempotency>,
+    cache: Option<CachePolicy>,
+    timeout: Option<Timeout>,
+    /// Marked `x-hsr-audit`, so each call is passed to the `audit` method of the API
+    audited: bool,
+    /// Marked `x-internal`, so left out of the served spec
+    internal: bool,
+    /// All the vendor extensions (`x-*`) of the operation
+    extensions: Extensions,
+    tags: Vec<String>,
+    deprecated: bool,
+    /// The security requirements of the operation, or the default ones of the spec
+    security: Vec<openapiv3::SecurityRequirement>,
+    /// The example calls of the operation
+    examples: Vec<Example>,
+}
+
+/// How to walk the pages of a list operation, declared with `x-hsr-pagination`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pagination {
+    /// The query parameter which selects the page
+    pub param: Ident,
+    /// The field of the response holding the items of the page
+    pub items: Ident,
+    /// The field of the response holding the `param` of the next page. If unset,
+    /// `param` is a page number, counted up from `first_page`
+    pub next: Option<Ident>,
+    pub first_page: i64,
+    /// The status of a page of results
+    pub(crate) status: StatusCode,
+    pub(crate) item_type: TypePath,
+    /// Whether the items field is an `Option<Vec<_>>`
+    pub(crate) items_optional: bool,
+}
+
+/// A rate limit declared with the `x-hsr-rate-limit` extension
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimit {
+    pub requests: u32,
+    pub per_seconds: u64,
+    pub key: RateLimitKey,
+}
+
+/// What a rate limit is keyed by
+#[derive(Debug, Clone, PartialEq)]
+pub enum RateLimitKey {
+    Ip,
+    /// An API key, found in the named header
+    Header(String),
+}
+
+impl RateLimit {
+    /// Parse the value of the extension
+    pub(crate) fn from_extension(ext: &serde_json::Value, route_path: &RoutePath) -> Result<Self> {
+        let positive = |field: &str| match ext.get(field).and_then(|v| v.as_u64()) {
+            Some(n) if n > 0 => Ok(n),
+            _ => invalid!(
+                "'x-hsr-rate-limit' must have a positive integer '{}' (path: '{}')",
+                field,
+                route_path
+            ),
+        };
+        let requests = positive("requests")?;
+        if requests > u64::from(u32::MAX) {
+            invalid!(
+                "'x-hsr-rate-limit' allows too many requests (path: '{}')",
+                route_path
+            )
+        }
+        let per_seconds = positive("per")?;
+        let header = ext
+            .get("header")
+            .and_then(|h| h.as_str())
+            .unwrap_or("X-Api-Key");
+        let key = match ext.get("key").and_then(|k| k.as_str()) {
+            None | Some("ip") => RateLimitKey::Ip,
+            Some("api-key") => RateLimitKey::Header(header.to_string()),
+            Some(other) => invalid!(
+                "Unknown 'x-hsr-rate-limit' key '{}', expected 'ip' or 'api-key' (path: '{}')",
+                other,
+                route_path
+            ),
+        };
+        Ok(RateLimit {
+            requests: requests as u32,
+            per_seconds,
+            key,
+        })
+    }
+}
+
+impl quote::ToTokens for RateLimit {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        let requests = proc_macro2::Literal::u32_unsuffixed(self.requests);
+        let per_seconds = proc_macro2::Literal::u64_unsuffixed(self.per_seconds);
+        let key = match &self.key {
+            RateLimitKey::Ip => quote! { hsr::rate_limit::RateLimitKey::Ip },
+            RateLimitKey::Header(name) => {
+                quote! { hsr::rate_limit::RateLimitKey::Header(#name.to_string()) }
+            }
+        };
+        tokens.extend(quote! {
+            hsr::rate_limit::RateLimit::new(
+                #requests,
+                std::time::Duration::from_secs(#per_seconds),
+                #key,
+            )
+        })
+    }
+}
+
+/// Idempotency key handling declared with the `x-hsr-idempotent` extension
+#[derive(Debug, Clone, PartialEq)]
+pub struct Idempotency {
+    /// Whether requests without a key are rejected
+    pub required: bool,
+    /// How long responses are recorded for
+    pub ttl_seconds: u64,
+}
+
+impl Idempotency {
+    /// Parse the value of the extension, either `true` or an object
+    /// with optional `required` and `ttl` fields
+    pub(crate) fn from_extension(
+        ext: &serde_json::Value,
+        route_path: &RoutePath,
+    ) -> Result<Option<Self>> {
+        let mut idempotency = Idempotency {
+            required: true,
+            ttl_seconds: 24 * 60 * 60,
+        };
+        match ext {
+            serde_json::Value::Bool(false) => return Ok(None),
+            serde_json::Value::Bool(true) => {}
+            serde_json::Value::Object(fields) => {
+                if let Some(required) = fields.get("required") {
+                    match required.as_bool() {
+                        Some(required) => idempotency.required = required,
+                        None => invalid!(
+                            "'x-hsr-idempotent' 'required' must be a boolean (path: '{}')",
+                            route_path
+                        ),
+                    }
+                }
+                if let Some(ttl) = fields.get("ttl") {
+                    match ttl.as_u64() {
+                        Some(ttl) if ttl > 0 => idempotency.ttl_seconds = ttl,
+                        _ => invalid!(
+                            "'x-hsr-idempotent' 'ttl' must be a positive integer (path: '{}')",
+                            route_path
+                        ),
+                    }
+                }
+            }
+            _ => invalid!(
+                "'x-hsr-idempotent' must be a boolean or an object (path: '{}')",
+                route_path
+            ),
+        }
+        Ok(Some(idempotency))
+    }
+}
+
+impl quote::ToTokens for Idempotency {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        let required = self.required;
+        let ttl_seconds = proc_macro2::Literal::u64_unsuffixed(self.ttl_seconds);
+        tokens.extend(quote! {
+            hsr::idempotency::Idempotency::new(
+                #required,
+                std::time::Duration::from_secs(#ttl_seconds),
+            )
+        })
+    }
+}
+
+/// A handler timeout declared with the `x-hsr-timeout` extension, in seconds
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Timeout {
+    pub millis: u64,
+}
+
+impl Timeout {
+    /// Parse the value of the extension, a positive number of seconds
+    pub(crate) fn from_extension(ext: &serde_json::Value, route_path: &RoutePath) -> Result<Self> {
+        match ext.as_f64() {
+            Some(seconds) if seconds > 0.0 && seconds * 1000.0 < u64::MAX as f64 => Ok(Timeout {
+                millis: ((seconds * 1000.0).round() as u64).max(1),
+            }),
+            _ => invalid!(
+                "'x-hsr-timeout' must be a positive number of seconds (path: '{}')",
+                route_path
+            ),
+        }
+    }
+}
+
+impl quote::ToTokens for Timeout {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        let millis = proc_macro2::Literal::u64_unsuffixed(self.millis);
+        tokens.extend(quote! { std::time::Duration::from_millis(#millis) })
+    }
+}
+
+/// A cache policy declared with the `x-hsr-cache` extension
+#[derive(Debug, Clone, PartialEq)]
+pub struct CachePolicy {
+    /// How long responses stay fresh. `None` means they must not be stored.
+    pub max_age_seconds: Option<u64>,
+    pub private: bool,
+    pub public: bool,
+}
+
+impl CachePolicy {
+    /// Parse the value of the extension
+    pub(crate) fn from_extension(
+        ext: &serde_json::Value,
+        method: &Method,
+        route_path: &RoutePath,
+    ) -> Result<Self> {
+        match method {
+            Method::WithoutBody(MethodWithoutBody::Get)
+            | Method::WithoutBody(MethodWithoutBody::Head) => {}
+            _ => invalid!(
+                "'x-hsr-cache' only applies to GET and HEAD operations (path: '{}')",
+                route_path
+            ),
+        }
+        let flag = |field: &str| match ext.get(field) {
+            None => Ok(false),
+            Some(serde_json::Value::Bool(b)) => Ok(*b),
+            Some(_) => invalid!(
+                "'x-hsr-cache' '{}' must be a boolean (path: '{}')",
+                field,
+                route_path
+            ),
+        };
+        let no_store = flag("no-store")?;
+        let private = flag("private")?;
+        let public = flag("public")?;
+        if private && public {
+            invalid!(
+                "'x-hsr-cache' can't be both 'private' and 'public' (path: '{}')",
+                route_path
+            )
+        }
+        let max_age = ext.get("max-age").map(|age| age.as_u64());
+        let max_age_seconds = match (no_store, max_age) {
+            (true, None) => None,
+            (false, Some(Some(age))) => Some(age),
+            _ => invalid!(
+                "'x-hsr-cache' must have either an integer 'max-age' or 'no-store: true' (path: '{}')",
+                route_path
+            ),
+        };
+        Ok(CachePolicy {
+            max_age_seconds,
+            private,
+            public,
+        })
+    }
+}
+
+impl quote::ToTokens for CachePolicy {
+    fn to_tokens(&self, tokens: &mut TokenStream) {
+        let policy = match self.max_age_seconds {
+            None => quote! { hsr::cache::CachePolicy::no_store() },
+            Some(age) => {
+                let age = proc_macro2::Literal::u64_unsuffixed(age);
+                quote! { hsr::cache::CachePolicy::max_age(std::time::Duration::from_secs(#age)) }
+            }
+        };
+        let scope = if self.private {
+            Some(quote! { .private() })
+        } else if self.public {
+            Some(quote! { .public() })
+        } else {
+            None
+        };
+        tokens.extend(quote! { #policy #scope })
+    }
 }
 
 impl Route {
-    pub(crate) fn method(&self) -> &Method {
+    pub fn summary(&self) -> Option<&str> {
+        self.summary.as_deref()
+    }
+
+    pub fn description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    pub fn method(&self) -> &Method {
         &self.method
     }
 
-    pub(crate) fn operation_id(&self) -> &Ident {
+    pub fn operation_id(&self) -> &Ident {
         &self.operation_id
     }
 
+    pub fn path(&self) -> &RoutePath {
+        &self.path
+    }
+
+    pub fn path_params(&self) -> Option<&Map<Ident, (FieldMetadata, TypePath)>> {
+        self.path_params.as_ref().map(|(_, params)| params)
+    }
+
+    pub fn query_params(&self) -> Option<&Map<Ident, (FieldMetadata, TypePath)>> {
+        self.query_params.as_ref().map(|(_, params)| params)
+    }
+
+    pub fn responses(&self) -> &Responses {
+        &self.responses
+    }
+
+    /// The media types of a request body which is negotiated by its `Content-Type`.
+    /// Empty if the body is plain JSON (or there is no body).
+    pub fn body_media_types(&self) -> &[String] {
+        &self.body_media_types
+    }
+
+    /// Whether a successful response may have a body which is neither JSON nor text
+    pub(crate) fn has_binary_response(&self) -> bool {
+        self.responses
+            .with_codes
+            .iter()
+            .filter(|(code, _)| code.is_success())
+            .flat_map(|(_, response)| &response.media_types)
+            .any(|media_type| {
+                !crate::walk::is_json_media_type(media_type) && !media_type.starts_with("text/")
+            })
+    }
+
+    /// The media types which the responses are negotiated between, in the order
+    /// of the spec. Empty if every response body is plain JSON.
+    pub fn offered_media_types(&self) -> Vec<&str> {
+        let mut offered = Vec::new();
+        for resp in self
+            .responses
+            .with_codes
+            .values()
+            .chain(self.responses.default.iter())
+        {
+            for media_type in &resp.media_types {
+                if !offered.contains(&media_type.as_str()) {
+                    offered.push(media_type.as_str())
+                }
+            }
+        }
+        offered
+    }
+
+    pub fn websocket(&self) -> Option<&TypePath> {
+        self.websocket.as_ref()
+    }
+
+    /// The pagination scheme declared with `x-hsr-pagination`, if any
+    pub fn pagination(&self) -> Option<&Pagination> {
+        self.pagination.as_ref()
+    }
+
+    /// The rate limit declared with `x-hsr-rate-limit`, if any
+    pub fn rate_limit(&self) -> Option<&RateLimit> {
+        self.rate_limit.as_ref()
+    }
+
+    /// The idempotency key handling declared with `x-hsr-idempotent`, if any
+    pub fn idempotency(&self) -> Option<&Idempotency> {
+        self.idempotency.as_ref()
+    }
+
+    /// The cache policy declared with `x-hsr-cache`, if any
+    pub fn cache(&self) -> Option<&CachePolicy> {
+        self.cache.as_ref()
+    }
+
+    /// The handler timeout declared with `x-hsr-timeout`, if any
+    pub fn timeout(&self) -> Option<Timeout> {
+        self.timeout
+    }
+
+    /// Whether the operation is marked `x-hsr-audit: true`
+    pub fn is_audited(&self) -> bool {
+        self.audited
+    }
+
+    /// Whether the operation is marked `x-internal: true`
+    pub fn is_internal(&self) -> bool {
+        self.internal
+    }
+
+    /// The example calls of the operation, put together from the examples of the spec
+    pub fn examples(&self) -> &[Example] {
+        &self.examples
+    }
+
+    /// The vendor extensions (`x-*`) of the operation, including those hsr understands
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
+    pub fn is_deprecated(&self) -> bool {
+        self.deprecated
+    }
+
+    /// The alternative security requirements of the operation (any one will do).
+    /// Inherited from the top level of the spec, unless the operation overrides them.
+    pub fn security(&self) -> &[openapiv3::SecurityRequirement] {
+        &self.security
+    }
+
     fn return_ty_name(&self) -> TypeName {
         TypeName::from_str(&self.operation_id.deref().to_camel_case()).unwrap()
     }
@@ -64,7 +549,11 @@ impl Route {
 
     /// The name of the return type. If none are found, returns '()'.
     /// If both Success and Error types exist, will be a Result type
-    pub(crate) fn generate_return_type(&self) -> TokenStream {
+    pub(crate) fn generate_return_type(&self, opts: &GenerationOptions) -> Result<TokenStream> {
+        if self.websocket.is_some() {
+            // websocket handlers don't return a response
+            return Ok(TokenStream::new());
+        }
         let enum_name = self.return_ty_name();
         let variants: Vec<_> = self
             .responses
@@ -72,15 +561,19 @@ impl Route {
             .iter()
             .map(|(code, resp)| {
                 Variant::new(variant_from_status_code(code))
-                    .description(resp.description.clone())
+                    .description(resp.documentation())
                     .type_path(resp.type_path.clone())
+                    .status(code.as_u16())
             })
             .collect();
-        let default_variant = self.responses.default.as_ref().map(|dflt| {
-            Variant::new("Default".parse().unwrap())
-                .description(dflt.description.clone())
-                .type_path(dflt.type_path.clone())
-        });
+        let default_variant = match &self.responses.default {
+            Some(dflt) => Some(
+                Variant::new("Default".parse()?)
+                    .description(dflt.documentation())
+                    .type_path(dflt.type_path.clone()),
+            ),
+            None => None,
+        };
         let meta = TypeMetadata::default()
             .with_description(format!("Returned from operation '{}'", self.operation_id));
         let enum_def = generate_enum_def(
@@ -89,110 +582,153 @@ impl Route {
             &variants,
             default_variant.as_ref(),
             false,
-        );
+            opts,
+        )?;
 
-        let status_matches = {
-            let mut status_matches: Vec<_> = self
-                .responses
-                .with_codes
-                .iter()
-                .map(|(code, response)| {
-                    let var_name = variant_from_status_code(code);
-                    let code_lit = proc_macro2::Literal::u16_unsuffixed(code.as_u16());
-                    match response.type_path {
-                        Some(_) => quote! {
-                            #var_name(_) => StatusCode::from_u16(#code_lit).unwrap()
-                        },
-                        None => quote! {
-                            #var_name => StatusCode::from_u16(#code_lit).unwrap()
-                        },
-                    }
-                })
-                .collect();
-            if let Some(_) = self.responses.default.as_ref() {
-                status_matches.push(
-                    // TODO print warning on bad code
-                    quote! {
-                        Default { status_code, .. } => {
-                            StatusCode::from_u16(*status_code)
-                                .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR)
-                        }
-                    },
-                )
+        // negotiated bodies know their own media type
+        let reply = |resp: &Response| {
+            if resp.media_types.is_empty() {
+                quote! { hsr::dispatch::Reply::json }
+            } else {
+                quote! { hsr::dispatch::Reply::content }
             }
-            status_matches
         };
-
-        let response_match_arms = {
-            let mut response_match_arms: Vec<_> = variants
-                .iter()
-                .map(
-                    |Variant {
-                         name, type_path, ..
-                     }| match type_path {
-                        Some(_) => {
-                            quote! {
-                                #name(inner) => {
-                                    HttpResponseBuilder::new(status_code).json(inner)
-                                }
-                            }
-                        }
-                        None => {
-                            quote! {
-                                #name => {
-                                    HttpResponseBuilder::new(status_code).finish()
-                                }
-                            }
-                        }
+        let mut reply_arms: Vec<_> = variants
+            .iter()
+            .zip(self.responses.with_codes.values())
+            .map(
+                |(
+                    Variant {
+                        name, type_path, ..
                     },
-                )
-                .collect();
-            if let Some(dflt) = &self.responses.default {
-                match dflt.type_path {
-                    None => response_match_arms.push(quote! {
-                        Default { .. } => HttpResponseBuilder::new(status_code).finish()
-                    }),
-                    Some(_) => response_match_arms.push(quote! {
-                        Default { body, .. } => HttpResponseBuilder::new(status_code).json(body)
-                    }),
-                }
+                    resp,
+                )| {
+                    let reply = reply(resp);
+                    match type_path {
+                        Some(_) => quote! { #name(inner) => #reply(&inner) },
+                        None => quote! { #name => hsr::dispatch::Reply::empty() },
+                    }
+                },
+            )
+            .collect();
+        if let Some(dflt) = &self.responses.default {
+            let reply = reply(dflt);
+            match dflt.type_path {
+                None => reply_arms.push(quote! {
+                    Default { .. } => hsr::dispatch::Reply::empty()
+                }),
+                Some(_) => reply_arms.push(quote! {
+                    Default { body, .. } => #reply(&body)
+                }),
             }
-            response_match_arms
+        }
+
+        // errors are displayed as their status and the description of the response
+        let mut descr_arms: Vec<_> = variants
+            .iter()
+            .zip(self.responses.with_codes.values())
+            .map(|(Variant { name, .. }, resp)| {
+                let descr = resp.description.trim();
+                quote! { #name { .. } => #descr }
+            })
+            .collect();
+        if let Some(dflt) = &self.responses.default {
+            let descr = dflt.description.trim();
+            descr_arms.push(quote! { Default { .. } => #descr });
+        }
+
+        let problem_json = opts.problem_json;
+        let axum_impl = match opts.backend {
+            Backend::Axum => Some(quote! {
+                impl hsr::axum::response::IntoResponse for #enum_name {
+                    fn into_response(self) -> hsr::axum::response::Response {
+                        hsr::dispatch::into_axum_response(self)
+                    }
+                }
+            }),
+            Backend::Actix => None,
         };
 
-        quote! {
+        Ok(quote! {
 
+            #[derive(HasStatusCode)]
             #enum_def
 
-            impl HasStatusCode for #enum_name {
-                fn status_code(&self) -> StatusCode {
+            impl hsr::dispatch::IntoReply for #enum_name {
+                fn into_reply(self) -> hsr::dispatch::Reply {
                     use #enum_name::*;
                     match self {
-                        #(#status_matches,)*
+                        #(#reply_arms,)*
                     }
                 }
             }
 
             impl Responder for #enum_name {
                 type Error = std::convert::Infallible;
-                type Future = Ready<Result<HttpResponse, <Self as Responder>::Error>>;
+                type Future = hsr::dispatch::Responded;
                 fn respond_to(self, _req: &HttpRequest) -> Self::Future {
+                    hsr::dispatch::respond(self, #problem_json)
+                }
+            }
+
+            impl std::fmt::Display for #enum_name {
+                fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
                     use #enum_name::*;
-                    let status_code = self.status_code();
-                    let resp = match self {
-                        #(#response_match_arms)*
+                    let description = match self {
+                        #(#descr_arms,)*
                     };
-                    fut_ok(resp)
+                    hsr::dispatch::fmt_reply(HasStatusCode::status_code(self), description, f)
                 }
             }
 
-        }
+            impl std::error::Error for #enum_name {}
+
+            impl hsr::actix_web::ResponseError for #enum_name {
+                fn status_code(&self) -> StatusCode {
+                    HasStatusCode::status_code(self)
+                }
+
+                fn error_response(&self) -> HttpResponse {
+                    hsr::dispatch::to_response(self.clone(), #problem_json)
+                }
+            }
+
+            #axum_impl
+        })
+    }
+
+    /// Generate the trait method declaration for the Route
+    pub(crate) fn generate_api_signature(&self, opts: &GenerationOptions) -> Result<TokenStream> {
+        let docs = self.documentation();
+        let signature = self.signature(opts)?;
+        Ok(quote! {
+            #docs
+            #signature;
+        })
+    }
+
+    /// Generate a placeholder implementation of the trait method
+    pub(crate) fn generate_stub_method(&self, opts: &GenerationOptions) -> Result<TokenStream> {
+        let signature = self.signature(opts)?;
+        let msg = format!("operation '{}' is not implemented", self.operation_id);
+        Ok(quote! {
+            #signature {
+                todo!(#msg)
+            }
+        })
     }
 
-    /// Generate the function signature compatible with the Route
-    pub(crate) fn generate_api_signature(&self) -> TokenStream {
+    /// The function signature compatible with the Route
+    fn signature(&self, opts: &GenerationOptions) -> Result<TokenStream> {
         let opid = &self.operation_id;
+        let allow_case = self.allow_non_snake_case();
         let api_return_ty = self.return_ty_name();
+        let ctx_arg_opt = if opts.request_context {
+            Some(quote! { ctx: hsr::RequestContext, })
+        } else {
+            None
+        };
+        let (state_args, _) = state_args(opts)?;
 
         let paths: Vec<_> = self
             .path_params
@@ -201,14 +737,21 @@ impl Route {
                 params
                     .iter()
                     .map(|(id, (meta, ty))| {
-                        assert!(meta.required, "path params are always required");
-                        let type_name = ty.canonicalize();
-                        quote! {
-                            #id: #type_name
+                        if !meta.required {
+                            invalid!(
+                                "Path parameter '{}' of '{}' must be required",
+                                id,
+                                self.operation_id
+                            );
                         }
+                        let type_name = ty.canonicalize()?;
+                        Ok(quote! {
+                            #id: #type_name
+                        })
                     })
-                    .collect()
+                    .collect::<Result<_>>()
             })
+            .transpose()?
             .unwrap_or(Vec::new());
 
         let queries: Vec<_> = self
@@ -218,32 +761,54 @@ impl Route {
                 params
                     .iter()
                     .map(|(id, (meta, ty))| {
-                        let type_name = ty.canonicalize();
-                        if meta.required {
-                            quote! {
+                        let type_name = ty.canonicalize()?;
+                        if !meta.optional() {
+                            Ok(quote! {
                                 #id: #type_name
-                            }
+                            })
                         } else {
-                            quote! {
+                            Ok(quote! {
                                 #id: Option<#type_name>
-                            }
+                            })
                         }
                     })
-                    .collect()
+                    .collect::<Result<_>>()
             })
+            .transpose()?
             .unwrap_or(Vec::new());
 
-        let body_arg_opt = self.method.body_type().map(|body_ty| {
-            let body_ty = body_ty.canonicalize();
-            let name = ident("payload");
-            Some(quote! { #name: #body_ty, })
-        });
-        let docs = self.documentation();
-        // define the trait method which the user must implement
-        quote! {
-            #docs
-            async fn #opid(&self, #(#paths,)* #(#queries,)* #body_arg_opt) -> #api_return_ty;
+        let body_arg_opt = self
+            .method
+            .body_type()
+            .map(|body_ty| -> Result<_> {
+                let body_ty = body_ty.canonicalize()?;
+                let name = ident("payload");
+                Ok(quote! { #name: #body_ty, })
+            })
+            .transpose()?;
+        // the media type the response must be in, chosen by the `Accept` header
+        let accept_arg_opt = if self.offered_media_types().is_empty() {
+            None
+        } else {
+            Some(quote! { accept: &'static str, })
+        };
+        if let Some(ref message_ty) = self.websocket {
+            let message_ty = message_ty.canonicalize()?;
+            return Ok(quote! {
+                #allow_case
+                async fn #opid(&self, #ctx_arg_opt #(#state_args,)* #(#paths,)* #(#queries,)* socket: hsr::ws::WebSocket<#message_ty>)
+            });
         }
+        let return_ty = if opts.fallible {
+            quote! { Result<#api_return_ty, Self::Error> }
+        } else {
+            quote! { #api_return_ty }
+        };
+        // define the trait method which the user must implement
+        Ok(quote! {
+            #allow_case
+            async fn #opid(&self, #ctx_arg_opt #(#state_args,)* #(#paths,)* #(#queries,)* #body_arg_opt #accept_arg_opt) -> #return_ty
+        })
     }
 
     /// Generate the client implementation.
@@ -251,137 +816,236 @@ impl Route {
     /// It takes a bit of care to build up this code. Unfortunately we can't just implement
     /// the API trait because we have to be able to return connection errors etc
     /// Which requires a `Result` type.
-    pub(crate) fn generate_client_impl(&self) -> TokenStream {
+    pub(crate) fn generate_client_impl(&self) -> Result<TokenStream> {
+        if self.websocket.is_some() {
+            // TODO generate a websocket client
+            return Ok(TokenStream::new());
+        }
         let opid = &self.operation_id;
+        let allow_case = self.allow_non_snake_case();
         let result_type = self.return_ty_name();
 
-        // build useful path and query iterators
-        let (path_names, path_types) = self
-            .path_params
-            .as_ref()
-            .map(|(_, params)| {
-                params
-                    .iter()
-                    .map(|(id, (_meta, ty))| (id, ty.canonicalize()))
-                    .unzip()
-            })
-            .unwrap_or((Vec::new(), Vec::new()));
+        let (params, _) = self.client_params()?;
+        let (path_names, _) = self.path_args()?;
 
-        let query_name_type_pairs = self
+        // template the code to add query parameters to the url, if necessary
+        let add_query_string_to_url = self
             .query_params
             .as_ref()
-            .map(|(_, params)| {
-                params
+            .map(|(type_path, params)| -> Result<_> {
+                let type_name = type_path.canonicalize()?;
+                let fields = params.iter().map(|(id, _)| id);
+                let reserved: Vec<String> = params
                     .iter()
-                    .map(|(id, (meta, ty))| {
-                        let type_name = ty.canonicalize();
-                        if meta.required {
-                            quote! {
-                                #id: #type_name
-                            }
-                        } else {
-                            quote! {
-                                #id: Option<#type_name>
-                            }
-                        }
+                    .filter(|(_, (meta, _))| meta.allow_reserved())
+                    .map(|(id, _)| id.to_string())
+                    .collect();
+                let allow_reserved = if reserved.is_empty() {
+                    None
+                } else {
+                    Some(quote! {
+                        let qs = hsr::query::allow_reserved(&qs, &[#(#reserved),*]);
                     })
-                    .collect()
+                };
+                Ok(quote! {
+                    {
+                        // construct and instance of our query param type
+                        // then url-encode into the string
+                        let qstyp = #type_name {
+                            #(#fields,)*
+                        };
+                        let qs = serde_urlencoded::to_string(qstyp).unwrap();
+                        #allow_reserved
+                        url.set_query(Some(&qs));
+                    }
+                })
             })
-            .unwrap_or(Vec::new());
+            .transpose()?;
 
-        // template the code to add query parameters to the url, if necessary
-        let add_query_string_to_url = self.query_params.as_ref().map(|(type_path, params)| {
-            let type_name = type_path.canonicalize();
-            let fields = params.iter().map(|(id, _)| id);
-            quote! {
-                {
-                    // construct and instance of our query param type
-                    // then url-encode into the string
-                    let qstyp = #type_name {
-                        #(#fields,)*
-                    };
-                    let qs = serde_urlencoded::to_string(qstyp).unwrap();
-                    url.set_query(Some(&qs));
+        // if there is a payload in the body, make sure to add it (as json,
+        // unless it has representations of its own)
+        let (encode_body_opt, send_request) = match self.method.body_type() {
+            None => (None, quote! {.send()}),
+            Some(_) if !self.body_media_types.is_empty() => (
+                Some(quote! {
+                    let content_type = hsr::negotiate::Representations::media_type(&payload);
+                    let body = hsr::negotiate::Representations::to_body(&payload)
+                        .map_err(hsr::actix_web::error::ErrorInternalServerError)?;
+                }),
+                quote! { .content_type(content_type).send_body(body) },
+            ),
+            Some(_) => (None, quote! { .send_json(&payload) }),
+        };
+
+        // the caller may choose which of the negotiated media types to ask for
+        let accept_header_opt = if self.offered_media_types().is_empty() {
+            None
+        } else {
+            Some(quote! {
+                if let Some(accept) = accept {
+                    req = req.header(hsr::actix_http::http::header::ACCEPT, accept);
                 }
-            }
-        });
+            })
+        };
 
-        // if there is a payload in the body, make sure to add it (as json)
-        let (body_arg_opt, send_request) = match self.method.body_type() {
-            None => (None, quote! {.send()}),
-            Some(ref body_type_path) => {
-                let body_name = body_type_path.canonicalize();
-                (
-                    Some(quote! { payload: #body_name, }),
-                    quote! { .send_json(&payload) },
-                )
+        // the timeout of the handler is the default timeout of the call
+        let default_timeout_opt = self.timeout.map(|timeout| {
+            quote! {
+                req = req.timeout(#timeout);
             }
-        };
+        });
 
         let method = ident(&self.method);
-        let path_template = self.path.to_string();
+        let url_module = self.url_module_name();
 
-        // We will need to deserialize the response based on the status code
-        // Build up the match arms that will do so
-        let resp_match_arms = {
-            let mut resp_match_arms: Vec<_> = self
+        // operations using idempotency keys get one, unless the caller chose their own
+        let idempotency_key = self.idempotency.as_ref().map(|_| {
+            quote! {
+                if !self.options.has_idempotency_key() {
+                    req = req.header(hsr::IDEMPOTENCY_KEY_HEADER, hsr::idempotency::generate_key());
+                }
+            }
+        });
+
+        // GET operations with a cache policy may be answered from the client's cache.
+        // The cache doesn't know about media types, so negotiated operations aren't cached.
+        let cacheable = match (&self.method, &self.cache) {
+            _ if !self.offered_media_types().is_empty() => false,
+            (
+                Method::WithoutBody(MethodWithoutBody::Get),
+                Some(CachePolicy {
+                    max_age_seconds: Some(_),
+                    ..
+                }),
+            ) => true,
+            _ => false,
+        };
+        let (cache_lookup, cache_store) = if cacheable {
+            let success: Vec<_> = self
                 .responses
                 .with_codes
                 .iter()
-                .map(|(code, response)| {
+                .filter(|(code, _)| code.is_success())
+                .collect();
+            let status_codes: Vec<_> = success
+                .iter()
+                .map(|(code, _)| proc_macro2::Literal::u16_unsuffixed(code.as_u16()))
+                .collect();
+            let decode_arms: Vec<_> = success
+                .iter()
+                .map(|(code, response)| {
+                    let status_code_literal = proc_macro2::Literal::u16_unsuffixed(code.as_u16());
+                    let variant = variant_from_status_code(code);
+                    match &response.type_path {
+                        Some(type_path) => {
+                            let decode = decode_body(
+                                response,
+                                type_path,
+                                quote! { #result_type::#variant(body) },
+                            )?;
+                            Ok(quote! {
+                                #status_code_literal => Some(#decode),
+                            })
+                        }
+                        None => Ok(quote! {
+                            #status_code_literal => Some(Result::Ok(#result_type::#variant)),
+                        }),
+                    }
+                })
+                .collect::<Result<_>>()?;
+            let decode = quote! {
+                match status {
+                    #(#decode_arms)*
+                    _ => None,
+                }
+            };
+            let lookup = quote! {
+                // responses are kept for the credentials and `Vary` headers of the request
+                let cache_key = hsr::cache::CacheKey::new(url.as_str(), req.headers());
+                if let Some(cache) = &self.cache {
+                    if let Some((status, body)) = cache.get(&cache_key) {
+                        if let Some(result) = #decode {
+                            return result;
+                        }
+                    }
+                }
+            };
+            let store = quote! {
+                if let Some(cache) = &self.cache {
+                    let status = resp.status().as_u16();
+                    let cacheable = match status {
+                        #(#status_codes)|* => true,
+                        _ => false,
+                    };
+                    if let (true, Some(ttl)) = (cacheable, hsr::cache::freshness(resp.headers())) {
+                        let body = resp.body().await.map_err(ActixError::from)?;
+                        cache.put(&cache_key, resp.headers(), status, body.clone(), ttl);
+                        if let Some(result) = #decode {
+                            return result;
+                        }
+                    }
+                }
+            };
+            (Some(lookup), Some(store))
+        } else {
+            (None, None)
+        };
+
+        // We will need to deserialize the response based on the status code
+        // Build up the match arms that will do so
+        let resp_match_arms = {
+            let mut resp_match_arms: Vec<_> = self
+                .responses
+                .with_codes
+                .iter()
+                .map(|(code, response)| {
                     let status_code_literal = proc_macro2::Literal::u16_unsuffixed(code.as_u16());
                     let variant = variant_from_status_code(code);
                     match &response.type_path {
                         Some(type_path) => {
                             // there is a payload associated with the response type
                             // so attempt to deserialize it
-                            let type_name = type_path.canonicalize();
-                            quote! {
-                                #status_code_literal => {
-                                    match resp
-                                        .json::<#type_name>()
-                                        .await {
-                                            Ok(body) => Result::Ok(#result_type::#variant(body)),
-                                            Err(e) => Result::Err(ClientError::Actix(e.into()))
-                                        }
-                                }
-                            }
+                            let decode = decode_response(
+                                response,
+                                type_path,
+                                quote! { #result_type::#variant(body) },
+                            )?;
+                            Ok(quote! {
+                                #status_code_literal => #decode
+                            })
                         }
                         None => {
                             // There is no payload with this response, just return the bare variant
                             // TODO: Check the payload is empty?
-                            quote! {
+                            Ok(quote! {
                                 #status_code_literal => {
                                     // could check body is empty here?
                                     Result::Ok(#result_type::#variant)
                                 }
-                            }
+                            })
                         }
                     }
                 })
-                .collect();
+                .collect::<Result<_>>()?;
 
             // we have done the 'expected' matches. Now, what if we get an unknown status code?
             // Depends on whether we have a 'default' response
             let fallthough_match = match &self.responses.default {
                 None => quote! {
-                    _ => Result::Err(ClientError::BadStatus(resp.status()))
+                    _ => Result::Err(ClientError::unexpected(&mut resp).await)
                 },
                 Some(dflt) => match &dflt.type_path {
                     None => quote! {
                         status_code => Result::Ok(#result_type::Default { status_code })
                     },
                     Some(type_path) => {
-                        let type_name = type_path.canonicalize();
+                        let decode = decode_response(
+                            dflt,
+                            type_path,
+                            quote! { #result_type::Default { status_code, body } },
+                        )?;
                         quote! {
-                            status_code => {
-                                match resp
-                                    .json::<#type_name>()
-                                    .await {
-                                        Ok(body) => Result::Ok(#result_type::Default { status_code, body }),
-                                        Err(e) => Result::Err(ClientError::Actix(e.into()))
-                                    }
-                            }
+                            status_code => #decode
                         }
                     }
                 },
@@ -390,32 +1054,700 @@ impl Route {
             resp_match_arms
         };
 
+        // With a cassette, calls are replayed from (or recorded to) a file, decoding
+        // the response from its body
+        let cassette_body = match self.method.body_type() {
+            None => quote! { None },
+            Some(_) if !self.body_media_types.is_empty() => quote! {
+                hsr::negotiate::Representations::to_body(&payload)
+                    .ok()
+                    .map(|body| String::from_utf8_lossy(&body).into_owned().into())
+            },
+            Some(_) => quote! { hsr::serde_json::to_value(&payload).ok() },
+        };
+        let opid_str = self.operation_id.to_string();
+        let cassette_replay = quote! {
+            let recording = match &self.cassette {
+                None => None,
+                Some(cassette) => {
+                    let request = hsr::cassette::Request::new(#opid_str, &url, #cassette_body);
+                    if cassette.is_replaying() {
+                        let response = cassette.play(&request)?;
+                        let status = response.status;
+                        let content_type = response.content_type.as_str();
+                        let body = response.body();
+                        return decode::#opid(status, content_type, &body);
+                    }
+                    Some((cassette, request))
+                }
+            };
+        };
+        let cassette_record = quote! {
+            if let Some((cassette, request)) = recording {
+                let status = resp.status().as_u16();
+                let content_type = hsr::negotiate::content_type(resp.headers()).to_string();
+                let body = resp.body().await.map_err(ActixError::from)?;
+                let response = hsr::cassette::Response::new(status, &content_type, &body);
+                cassette.record_response(request, response)?;
+                let content_type = content_type.as_str();
+                return decode::#opid(status, content_type, &body);
+            }
+        };
+
+        // Binary bodies may also be streamed, rather than read into memory
+        let stream_method = if self.has_binary_response() {
+            let stream_opid = ident(format!("{}_stream", opid));
+            let doc = format!(
+                "As `{}`, giving the body of a successful response as it arrives",
+                opid
+            );
+            Some(quote! {
+                #[doc = #doc]
+                #[allow(unused_mut)]
+                #allow_case
+                pub async fn #stream_opid(
+                    &self,
+                    #(#params,)*
+                ) -> Result<hsr::download::Download, ClientError>
+                {
+                    let mut url = paths::#url_module::url(&self.domain, #(#path_names),*)?;
+                    #add_query_string_to_url
+                    self.options.extend_url(&mut url);
+
+                    if let Some(breaker) = &self.circuit_breaker {
+                        breaker.check()?;
+                    }
+
+                    let mut req = self.inner.request(Method::#method, url.as_str());
+                    if let Some(id) = &self.request_id {
+                        req = req.header(hsr::request_id::REQUEST_ID_HEADER, id.as_str());
+                    }
+                    #default_timeout_opt
+                    req = self.options.apply(req);
+                    req = hsr::compression::accept_compressed(req);
+                    #idempotency_key
+                    #accept_header_opt
+                    #encode_body_opt
+                    let req = hsr::otel::inject_context(req);
+                    let observation = hsr::observe::Observation::start(
+                        &self.observer, #opid_str, Method::#method, &url,
+                    );
+                    let resp = req #send_request .await;
+                    if let Some(observation) = observation {
+                        observation.finish(&resp);
+                    }
+                    if let Some(breaker) = &self.circuit_breaker {
+                        breaker.record(matches!(&resp, Ok(resp) if !resp.status().is_server_error()));
+                    }
+                    let mut resp = resp.map_err(ActixError::from)?;
+                    if resp.status().is_success() {
+                        Ok(hsr::download::Download::new(resp))
+                    } else {
+                        Err(ClientError::unexpected(&mut resp).await)
+                    }
+                }
+            })
+        } else {
+            None
+        };
+
         // Finally we can piece everything together
-        quote! {
-            #[allow(unused_mut)]
+        Ok(quote! {
+            #[allow(unused_mut, unused_variables)]
+            #allow_case
             pub async fn #opid(
                 &self,
-                #(#path_names: #path_types,)*
-                #(#query_name_type_pairs,)*
-                #body_arg_opt
+                #(#params,)*
             ) -> Result<#result_type, ClientError>
             {
-                // Build up our request path
-                let path = format!(#path_template, #(#path_names = #path_names,)*);
-                let mut url = self.domain.join(&path).unwrap();
+                let mut url = paths::#url_module::url(&self.domain, #(#path_names),*)?;
                 #add_query_string_to_url
+                self.options.extend_url(&mut url);
+                #cassette_replay
 
-                let mut resp = self.inner
-                    .request(Method::#method, url.as_str())
+                let mut req = self.inner.request(Method::#method, url.as_str());
+                if let Some(id) = &self.request_id {
+                    req = req.header(hsr::request_id::REQUEST_ID_HEADER, id.as_str());
+                }
+                #default_timeout_opt
+                req = self.options.apply(req);
+                req = hsr::compression::accept_compressed(req);
+                #idempotency_key
+                #accept_header_opt
+                #cache_lookup
+
+                if let Some(breaker) = &self.circuit_breaker {
+                    breaker.check()?;
+                }
+
+                #encode_body_opt
+                let req = hsr::otel::inject_context(req);
+                let observation = hsr::observe::Observation::start(
+                    &self.observer, #opid_str, Method::#method, &url,
+                );
+                let resp = req
                     // Send, giving a future containing an HttpResponse
                     #send_request
-                    .await.map_err(ActixError::from)?;
+                    .await;
+                if let Some(observation) = observation {
+                    observation.finish(&resp);
+                }
+                if let Some(breaker) = &self.circuit_breaker {
+                    breaker.record(matches!(&resp, Ok(resp) if !resp.status().is_server_error()));
+                }
+                let mut resp = resp.map_err(ActixError::from)?;
+                #cassette_record
+                #cache_store
                 // We match on the status type to handle the return correctly
                 match resp.status().as_u16() {
                     #(#resp_match_arms)*
                 }
             }
+
+            #stream_method
+        })
+    }
+
+    /// Generate the function decoding a response to the operation which has already
+    /// been read, for the client (in the `client::decode` module)
+    pub(crate) fn generate_client_decoder(&self) -> Result<TokenStream> {
+        if self.websocket.is_some() {
+            return Ok(TokenStream::new());
+        }
+        let opid = &self.operation_id;
+        let allow_case = self.allow_non_snake_case();
+        let result_type = self.return_ty_name();
+        let match_arms = {
+            let mut match_arms: Vec<_> = self
+                .responses
+                .with_codes
+                .iter()
+                .map(|(code, response)| {
+                    let status_code_literal = proc_macro2::Literal::u16_unsuffixed(code.as_u16());
+                    let variant = variant_from_status_code(code);
+                    match &response.type_path {
+                        Some(type_path) => {
+                            let decode = decode_body(
+                                response,
+                                type_path,
+                                quote! { #result_type::#variant(body) },
+                            )?;
+                            Ok(quote! { #status_code_literal => #decode, })
+                        }
+                        None => Ok(quote! {
+                            #status_code_literal => Result::Ok(#result_type::#variant),
+                        }),
+                    }
+                })
+                .collect::<Result<_>>()?;
+            match_arms.push(match &self.responses.default {
+                None => quote! {
+                    status => Result::Err(ClientError::unexpected_body(status, content_type, &body))
+                },
+                Some(dflt) => match &dflt.type_path {
+                    None => quote! {
+                        status_code => Result::Ok(#result_type::Default { status_code })
+                    },
+                    Some(type_path) => {
+                        let decode = decode_body(
+                            dflt,
+                            type_path,
+                            quote! { #result_type::Default { status_code, body } },
+                        )?;
+                        quote! { status_code => #decode }
+                    }
+                },
+            });
+            match_arms
+        };
+        Ok(quote! {
+            #[allow(unused_variables)]
+            #allow_case
+            pub(super) fn #opid(
+                status: u16,
+                content_type: &str,
+                body: &[u8],
+            ) -> Result<#result_type, ClientError> {
+                match status {
+                    #(#match_arms)*
+                }
+            }
+        })
+    }
+
+    /// The implementation of the operation in the `DynClient` trait by `InProcess`,
+    /// which calls the API directly. The request and response are put through
+    /// their wire formats, as they would be by the client and the dispatcher.
+    pub(crate) fn generate_in_process_impl(&self, opts: &GenerationOptions) -> Result<TokenStream> {
+        if self.websocket.is_some() {
+            return Ok(TokenStream::new());
+        }
+        let opid = &self.operation_id;
+        let allow_case = self.allow_non_snake_case();
+        let opid_str = opid.to_string();
+        let result_type = self.return_ty_name();
+        let method = ident(&self.method);
+        let url_module = self.url_module_name();
+        let (params, _) = self.client_params()?;
+        let (path_names, _) = self.path_args()?;
+
+        let offered = self.offered_media_types();
+        let (negotiate_opt, accept_opt) = if offered.is_empty() {
+            (None, None)
+        } else {
+            (
+                Some(quote! {
+                    const OFFERED: &[&str] = &[#(#offered),*];
+                    let accept = hsr::negotiate::Accept::new(accept)
+                        .negotiate(OFFERED)
+                        .ok_or_else(|| ClientError::from(hsr::negotiate::not_acceptable(OFFERED)))?;
+                }),
+                Some(quote! { accept }),
+            )
+        };
+        let (query_round_trip_opt, query_names) = match &self.query_params {
+            None => (None, Vec::new()),
+            Some((type_path, params)) => {
+                let type_name = type_path.canonicalize()?;
+                let fields: Vec<_> = params.keys().collect();
+                let round_trip = quote! {
+                    let #type_name { #(#fields),* } =
+                        hsr::in_process::round_trip_query(&#type_name { #(#fields),* })?;
+                };
+                (Some(round_trip), fields)
+            }
+        };
+        let (body_round_trip_opt, payload_opt) = match self.method.body_type() {
+            None => (None, None),
+            Some(_) if !self.body_media_types.is_empty() => (
+                Some(quote! {
+                    let payload = hsr::in_process::round_trip_content(&payload)?;
+                }),
+                Some(quote! { payload }),
+            ),
+            Some(_) => (
+                Some(quote! {
+                    let payload = hsr::in_process::round_trip_json(&payload)?;
+                }),
+                Some(quote! { payload }),
+            ),
+        };
+        let ctx_opt = if opts.request_context {
+            Some(quote! {
+                hsr::in_process::context(
+                    Method::#method,
+                    &paths::#url_module::url(
+                        &hsr::in_process::base_url(),
+                        #(#path_names.clone()),*
+                    )?,
+                ),
+            })
+        } else {
+            None
+        };
+        let (_, state_names) = state_args(opts)?;
+        let problem_json = opts.problem_json;
+        let to_response = if opts.fallible {
+            quote! {
+                match result {
+                    Ok(resp) => hsr::dispatch::to_response(resp, #problem_json),
+                    Err(e) => self.api.map_error(e, *operation(#opid_str).unwrap()),
+                }
+            }
+        } else {
+            quote! { hsr::dispatch::to_response(result, #problem_json) }
+        };
+        // a panic or a timeout gives the same response as from the dispatcher
+        let timeout = match self.timeout {
+            Some(timeout) => quote! { Some(#timeout) },
+            None => quote! { None },
+        };
+        let args = path_names
+            .iter()
+            .map(|name| quote! { #name })
+            .chain(query_names.iter().map(|name| quote! { #name }))
+            .chain(payload_opt)
+            .chain(accept_opt);
+        Ok(quote! {
+            #[allow(unused_variables)]
+            #allow_case
+            async fn #opid(&self, #(#params),*) -> Result<#result_type, ClientError> {
+                #negotiate_opt
+                #query_round_trip_opt
+                #body_round_trip_opt
+                let handler = self
+                    .api
+                    .#opid(#ctx_opt #(self.state.#state_names.clone(),)* #(#args),*);
+                let resp = match hsr::dispatch::guard(#opid_str, #timeout, handler).await {
+                    Ok(result) => #to_response,
+                    Err(e) => e.as_response_error().error_response(),
+                };
+                let (status, content_type, body) = hsr::in_process::into_parts(resp);
+                decode::#opid(status, &content_type, &body)
+            }
+        })
+    }
+
+    /// The parameters of the client method (which follow the parameters of the
+    /// operation), and their names
+    fn client_params(&self) -> Result<(Vec<TokenStream>, Vec<TokenStream>)> {
+        let mut params = Vec::new();
+        let mut names = Vec::new();
+        let (path_names, path_types) = self.path_args()?;
+        for (name, ty) in path_names.iter().zip(&path_types) {
+            params.push(quote! { #name: #ty });
+            names.push(quote! { #name });
+        }
+        for (id, (meta, ty)) in self.query_params.iter().flat_map(|(_, query)| query) {
+            let type_name = ty.canonicalize()?;
+            if meta.optional() {
+                params.push(quote! { #id: Option<#type_name> });
+            } else {
+                params.push(quote! { #id: #type_name });
+            }
+            names.push(quote! { #id });
+        }
+        if let Some(body_type_path) = self.method.body_type() {
+            let body_name = body_type_path.canonicalize()?;
+            params.push(quote! { payload: #body_name });
+            names.push(quote! { payload });
+        }
+        if !self.offered_media_types().is_empty() {
+            params.push(quote! { accept: Option<&str> });
+            names.push(quote! { accept });
+        }
+        Ok((params, names))
+    }
+
+    /// The declaration of the operation in the `DynClient` trait
+    pub(crate) fn generate_client_trait_method(&self) -> Result<TokenStream> {
+        if self.websocket.is_some() {
+            return Ok(TokenStream::new());
+        }
+        let opid = &self.operation_id;
+        let result_type = self.return_ty_name();
+        let docs = self.documentation();
+        let (params, _) = self.client_params()?;
+        let allow_case = self.allow_non_snake_case();
+        Ok(quote! {
+            #docs
+            #allow_case
+            async fn #opid(&self, #(#params),*) -> Result<#result_type, ClientError>;
+        })
+    }
+
+    /// The implementation of the operation in the `DynClient` trait, by the client
+    pub(crate) fn generate_client_trait_impl(&self) -> Result<TokenStream> {
+        if self.websocket.is_some() {
+            return Ok(TokenStream::new());
+        }
+        let opid = &self.operation_id;
+        let allow_case = self.allow_non_snake_case();
+        let result_type = self.return_ty_name();
+        let (params, names) = self.client_params()?;
+        Ok(quote! {
+            #allow_case
+            async fn #opid(&self, #(#params),*) -> Result<#result_type, ClientError> {
+                Client::#opid(self, #(#names),*).await
+            }
+        })
+    }
+
+    /// The names and types of the path parameters, in order
+    pub(crate) fn path_args(&self) -> Result<(Vec<&Ident>, Vec<TypeName>)> {
+        let mut names = Vec::new();
+        let mut types = Vec::new();
+        for (id, (_meta, ty)) in self.path_params.iter().flat_map(|(_, params)| params) {
+            names.push(id);
+            types.push(ty.canonicalize()?);
+        }
+        Ok((names, types))
+    }
+
+    /// The names of the path and query parameters
+    pub(crate) fn param_names(&self) -> impl Iterator<Item = &Ident> {
+        self.path_params
+            .iter()
+            .chain(&self.query_params)
+            .flat_map(|(_, params)| params.keys())
+    }
+
+    /// `#[allow(non_snake_case)]` for the functions named after the operation, or
+    /// taking its parameters, if any of those names (from the spec) isn't snake case
+    pub(crate) fn allow_non_snake_case(&self) -> Option<TokenStream> {
+        crate::allow_non_snake_case(
+            std::iter::once(self.operation_id.as_str())
+                .chain(self.param_names().map(|name| name.as_str())),
+        )
+    }
+
+    /// The name of the module holding the URL builder (and links) of the operation
+    pub(crate) fn url_module_name(&self) -> QIdent {
+        ident(self.operation_id.to_string().to_snake_case())
+    }
+
+    /// The links of every response, by name
+    pub(crate) fn links(&self) -> Result<Map<&str, &ResponseLink>> {
+        let mut links = Map::new();
+        let responses = self
+            .responses
+            .with_codes
+            .values()
+            .chain(self.responses.default.as_ref());
+        for response in responses {
+            for (name, link) in &response.links {
+                match links.insert(name.as_str(), link) {
+                    Some(prev) if prev.operation_id != link.operation_id => invalid!(
+                        "Link '{}' of operation '{}' has different targets",
+                        name,
+                        self.operation_id
+                    ),
+                    _ => {}
+                }
+            }
+        }
+        Ok(links)
+    }
+
+    /// Generate `paths::<operation>::url`, building the URL of the operation from a
+    /// base URL and its path parameters
+    pub(crate) fn generate_url_builder(&self) -> Result<TokenStream> {
+        let module = self.url_module_name();
+        let allow_case = self.allow_non_snake_case();
+        let (path_names, path_types) = self.path_args()?;
+        let path_template = self.path.to_string();
+        let mut doc = format!(
+            "The URL of `{}` (`{} {}`), relative to the root of `base`",
+            self.operation_id, self.method, path_template
+        );
+        if !path_names.is_empty() {
+            doc.push_str(". Fails if a path parameter is `.` or `..`");
+        }
+        let doc = doc_comment(doc);
+        Ok(quote! {
+            pub mod #module {
+                use super::super::*;
+
+                #doc
+                #allow_case
+                pub fn url(
+                    base: &Url,
+                    #(#path_names: #path_types),*
+                ) -> Result<Url, hsr::path::DotSegment> {
+                    // escape the parameters
+                    let path = format!(
+                        #path_template,
+                        #(#path_names = hsr::path::encode_segment(&#path_names.to_string())?,)*
+                    );
+                    Ok(base.join(&path).unwrap())
+                }
+            }
+        })
+    }
+
+    /// Generate `fuzz::<operation>`, extracting the parameters and body of the
+    /// operation from arbitrary bytes, as the server does
+    pub(crate) fn generate_fuzz_target(&self) -> Result<TokenStream> {
+        if self.websocket.is_some() {
+            return Ok(TokenStream::new());
+        }
+        let opid = &self.operation_id;
+        let allow_case = self.allow_non_snake_case();
+        let doc = doc_comment(format!(
+            "Extract the parameters and body of `{}` from arbitrary bytes (see `hsr::fuzz::Input`)",
+            opid
+        ));
+        let path_count = self
+            .path_params
+            .as_ref()
+            .map(|(_, params)| params.len())
+            .unwrap_or_default();
+        let path_opt = match &self.path_params {
+            Some((type_path, params)) => {
+                let name = type_path.canonicalize()?;
+                let names = params.keys().map(|id| id.to_string());
+                Some(quote! { let _ = input.path::<#name>(&[#(#names),*]); })
+            }
+            None => None,
+        };
+        let query_opt = match &self.query_params {
+            Some((type_path, _)) => {
+                let name = type_path.canonicalize()?;
+                Some(quote! { let _ = input.query::<#name>(); })
+            }
+            None => None,
+        };
+        let body_opt = match self.method.body_type() {
+            Some(body_type) => {
+                let name = body_type.canonicalize()?;
+                if self.body_media_types.is_empty() {
+                    Some(quote! { let _ = input.json::<#name>(); })
+                } else {
+                    Some(quote! { let _ = input.representations::<#name>(); })
+                }
+            }
+            None => None,
+        };
+        // an operation without parameters or a body has nothing to extract
+        let input = if path_opt.is_none() && query_opt.is_none() && body_opt.is_none() {
+            ident("_input")
+        } else {
+            ident("input")
+        };
+        Ok(quote! {
+            #doc
+            #allow_case
+            pub fn #opid(data: &[u8]) {
+                let #input = hsr::fuzz::Input::new(data, #path_count);
+                #path_opt
+                #query_opt
+                #body_opt
+            }
+        })
+    }
+
+    /// Generate `<operation>_pages` and `<operation>_items` client methods, which
+    /// stream every page (or item) of a paginated operation
+    pub(crate) fn generate_pagination_client_impl(&self) -> Result<TokenStream> {
+        let pagination = match &self.pagination {
+            Some(pagination) => pagination,
+            None => return Ok(TokenStream::new()),
+        };
+        let opid = &self.operation_id;
+        let allow_case = self.allow_non_snake_case();
+        let pages_fn = ident(format!("{}_pages", opid));
+        let items_fn = ident(format!("{}_items", opid));
+        let result_type = self.return_ty_name();
+        let page_variant = variant_from_status_code(&pagination.status);
+        let page_type = match self
+            .responses
+            .with_codes
+            .get(&pagination.status)
+            .and_then(|response| response.type_path.as_ref())
+        {
+            Some(type_path) => type_path.canonicalize()?,
+            None => invalid!(
+                "The {} response of '{}' has no page of items",
+                pagination.status,
+                opid
+            ),
+        };
+        let item_type = pagination.item_type.canonicalize()?;
+        let param = &pagination.param;
+        let items = &pagination.items;
+
+        // The arguments of the operation, apart from the page
+        let mut args = Vec::new();
+        let mut arg_names = Vec::new();
+        let mut call_args = Vec::new();
+        let params = self
+            .path_params
+            .iter()
+            .chain(self.query_params.iter())
+            .flat_map(|(_, params)| params.iter());
+        for (id, (meta, ty)) in params {
+            if id == param {
+                call_args.push(quote! { cursor });
+                continue;
+            }
+            let type_name = ty.canonicalize()?;
+            let is_query = self
+                .query_params
+                .as_ref()
+                .map(|(_, query)| query.contains_key(id))
+                .unwrap_or(false);
+            if is_query && meta.optional() {
+                args.push(quote! { #id: Option<#type_name> });
+            } else {
+                args.push(quote! { #id: #type_name });
+            }
+            arg_names.push(quote! { #id });
+            call_args.push(quote! { #id });
         }
+        if let Some(body_ty) = self.method.body_type() {
+            let body_ty = body_ty.canonicalize()?;
+            args.push(quote! { payload: #body_ty });
+            arg_names.push(quote! { payload });
+            call_args.push(quote! { payload });
+        }
+        if !self.offered_media_types().is_empty() {
+            // the pages themselves are JSON, leave the negotiation to the server
+            call_args.push(quote! { None });
+        }
+
+        let (item_iter, items_len) = if pagination.items_optional {
+            (
+                quote! { page.#items.into_iter().flatten() },
+                quote! { page.#items.as_ref().map(|items| items.len()).unwrap_or(0) },
+            )
+        } else {
+            (
+                quote! { page.#items.into_iter() },
+                quote! { page.#items.len() },
+            )
+        };
+        // The state is the value of `param` for the next page, or `None` when done
+        let (initial, next_state) = match &pagination.next {
+            Some(next) => (
+                quote! { Some(None) },
+                quote! { page.#next.clone().map(Some) },
+            ),
+            None => {
+                let first = pagination.first_page;
+                (
+                    quote! { Some(Some(#first)) },
+                    quote! {
+                        if #items_len == 0 {
+                            None
+                        } else {
+                            cursor.map(|n| Some(n + 1))
+                        }
+                    },
+                )
+            }
+        };
+
+        Ok(quote! {
+            /// Stream every page of results, following the pagination of the operation
+            #allow_case
+            pub fn #pages_fn<'a>(
+                &'a self,
+                #(#args,)*
+            ) -> impl hsr::futures::Stream<Item = Result<#page_type, ClientError>> + 'a {
+                hsr::futures::stream::unfold(#initial, move |state| {
+                    #(let #arg_names = #arg_names.clone();)*
+                    async move {
+                        let cursor = state?;
+                        match self.#opid(#(#call_args),*).await {
+                            Ok(#result_type::#page_variant(page)) => {
+                                let next = #next_state;
+                                Some((Ok(page), next))
+                            }
+                            Ok(other) => {
+                                Some((Err(ClientError::BadStatus(other.status_code())), None))
+                            }
+                            Err(e) => Some((Err(e), None)),
+                        }
+                    }
+                })
+            }
+
+            /// Stream every item of every page of results
+            #allow_case
+            pub fn #items_fn<'a>(
+                &'a self,
+                #(#args,)*
+            ) -> impl hsr::futures::Stream<Item = Result<#item_type, ClientError>> + 'a {
+                use hsr::futures::StreamExt;
+                self.#pages_fn(#(#arg_names),*).flat_map(|page| {
+                    let items: Vec<Result<#item_type, ClientError>> = match page {
+                        Ok(page) => #item_iter.map(Ok).collect(),
+                        Err(e) => vec![Err(e)],
+                    };
+                    hsr::futures::stream::iter(items)
+                })
+            }
+        })
     }
 
     /// If there are multitple difference error types, construct an
@@ -424,103 +1756,645 @@ impl Route {
     /// interface function in a shim that translates the signature into a form
     /// that Actix expects.
     ///
-    /// Specifically, we generate a function that accepts Path, Query and Json types,
-    /// extracts the values from these types, calls the API function with the values,
+    /// Specifically, we generate a function that accepts Path, Query and Json types
+    /// (only those the route has parameters or a body for), extracts the values
+    /// from these types, calls the API function with the values,
     /// and wraps the resulting Future3 type to return a Future1 with corresponding Ok
     /// and Error types.
-    pub(crate) fn generate_dispatcher(&self, trait_name: &TypeName) -> TokenStream {
-        // XXX this function is a total mess, there must be a better way to do it.
-        // After all, it seems we have got the API signatures right/OK?
+    pub(crate) fn generate_dispatcher(
+        &self,
+        trait_name: &TypeName,
+        opts: &GenerationOptions,
+    ) -> Result<TokenStream> {
+        let opid = &self.operation_id;
+        let allow_case = self.allow_non_snake_case();
+        let opid_str = opid.to_string();
+        let ctx_opt = if opts.request_context {
+            Some(quote! { hsr::RequestContext::from(req), })
+        } else {
+            None
+        };
+        let (state_args, state_names) = state_args(opts)?;
+        let (generics, _, api_ty) = handler_api(opts, quote! { #trait_name + Send + Sync });
+        let HandlerArgs {
+            path_arg_opt,
+            path_destructure_opt,
+            path_param_fields,
+            query_arg_opt,
+            query_destructure_opt,
+            query_param_fields,
+            body_arg_opt,
+            body_ident_opt,
+        } = self.handler_args()?;
+
+        // the typed parameters, which are described to `authorize` and `audit`
+        let mut param_refs = Vec::new();
+        if path_arg_opt.is_some() {
+            param_refs.push(quote! { &*path });
+        }
+        if query_arg_opt.is_some() {
+            param_refs.push(quote! { &*query });
+        }
+        let authz_opt = if opts.authorization {
+            let with_body_opt = body_ident_opt
+                .as_ref()
+                .map(|body| quote! { .with_body(&#body) });
+            Some(quote! {
+                let authz = hsr::authz::AuthzInput::new(*operation(#opid_str).unwrap(), &req)
+                    #(.with_params(#param_refs))*
+                    #with_body_opt;
+            })
+        } else {
+            None
+        };
+
+        if self.websocket.is_some() {
+            let authorize_opt = authz_opt.as_ref().map(|authz| {
+                quote! {
+                    #authz
+                    data.authorize(authz).await?;
+                }
+            });
+            // Perform the handshake and hand the socket over to the handler,
+            // which runs in the background for the lifetime of the connection
+            return Ok(quote! {
+                #allow_case
+                pub async fn #opid #generics(
+                    data: AxData<#api_ty>,
+                    #(#state_args,)*
+                    req: HttpRequest,
+                    #path_arg_opt
+                    #query_arg_opt
+                    payload: web::Payload,
+                ) -> Result<HttpResponse, ActixError> {
+                    #authorize_opt
+                    #path_destructure_opt
+                    #query_destructure_opt
+                    let (resp, socket) = hsr::ws::upgrade(&req, payload)?;
+                    hsr::actix_rt::spawn(async move {
+                        let handler = data.#opid(
+                            #ctx_opt
+                            #(#state_names,)*
+                            #(#path_param_fields,)*
+                            #(#query_param_fields,)*
+                            socket
+                        );
+                        // a panic is logged, and closes the connection
+                        let _ = hsr::dispatch::guard(#opid_str, None, handler).await;
+                    });
+                    Ok(resp)
+                }
+            });
+        }
+
+        let return_ty = self.return_ty_name();
+        let req_arg_opt = if ctx_opt.is_some() || self.audited || opts.authorization {
+            Some(quote! { req: HttpRequest, })
+        } else {
+            None
+        };
+        // errors of the application are turned into responses by the API
+        let (return_ty, handle_result) = if opts.fallible {
+            (
+                quote! { AxEither<#return_ty, HttpResponse> },
+                quote! {
+                    match result {
+                        Ok(resp) => AxEither::A(resp),
+                        Err(e) => AxEither::B(data.map_error(e, *operation(#opid_str).unwrap())),
+                    }
+                },
+            )
+        } else {
+            (quote! { #return_ty }, quote! { result })
+        };
+        // a panic in the handler gives a 500, and so does a timeout (with a 504)
+        let timeout = match self.timeout {
+            Some(timeout) => quote! { Some(#timeout) },
+            None => quote! { None },
+        };
+        // a call the API doesn't authorize is refused, without running the handler
+        let (authorize_opt, guarded) = if authz_opt.is_some() {
+            let authorize = quote! {
+                #authz_opt
+                let authorized = data.authorize(authz).await;
+            };
+            let guarded = quote! {
+                match authorized {
+                    Ok(()) => hsr::dispatch::guard(#opid_str, #timeout, handler).await,
+                    Err(denied) => Err(denied.into()),
+                }
+            };
+            (Some(authorize), guarded)
+        } else {
+            let guarded = quote! { hsr::dispatch::guard(#opid_str, #timeout, handler).await };
+            (None, guarded)
+        };
+        // an audited call is passed to the API once it has its response
+        let (audit_start_opt, run_handler) = if self.audited {
+            let mut params = param_refs.clone();
+            if let Some(body) = &body_ident_opt {
+                params.push(quote! { &#body });
+            }
+            let ok_status = if opts.fallible {
+                quote! {
+                    Ok(AxEither::A(resp)) => hsr::HasStatusCode::status_code(resp),
+                    Ok(AxEither::B(resp)) => resp.status(),
+                }
+            } else {
+                quote! { Ok(resp) => hsr::HasStatusCode::status_code(resp), }
+            };
+            let audit_start = quote! {
+                let audit = hsr::audit::Audit::start(#opid_str, &req, &(#(#params,)*));
+            };
+            let run_handler = quote! {
+                let result = #guarded.map(|result| #handle_result);
+                let status = match &result {
+                    #ok_status
+                    Err(e) => e.as_response_error().status_code(),
+                };
+                data.audit(audit.finish(status)).await;
+                result
+            };
+            (Some(audit_start), run_handler)
+        } else {
+            let run_handler = quote! {
+                let result = #guarded?;
+                Ok(#handle_result)
+            };
+            (None, run_handler)
+        };
+
+        let offered = self.offered_media_types();
+        if !offered.is_empty() {
+            let body_comma_opt = body_ident_opt.map(|body| quote! { #body, });
+            // pick the media type of the response before calling the handler,
+            // so that we can refuse the request if none are acceptable
+            return Ok(quote! {
+                #allow_case
+                pub async fn #opid #generics(
+                    data: AxData<#api_ty>,
+                    #(#state_args,)*
+                    #req_arg_opt
+                    accept: hsr::negotiate::Accept,
+                    #path_arg_opt
+                    #query_arg_opt
+                    #body_arg_opt
+                ) -> Result<#return_ty, ActixError> {
+                    const OFFERED: &[&str] = &[#(#offered),*];
+                    let accept = match accept.negotiate(OFFERED) {
+                        Some(accept) => accept,
+                        None => return Err(hsr::negotiate::not_acceptable(OFFERED)),
+                    };
+                    #audit_start_opt
+                    #authorize_opt
+                    #path_destructure_opt
+                    #query_destructure_opt
+                    let handler = data.#opid(
+                        #ctx_opt
+                        #(#state_names,)*
+                        #(#path_param_fields,)*
+                        #(#query_param_fields,)*
+                        #body_comma_opt
+                        accept
+                    );
+                    #run_handler
+                }
+            });
+        }
+
+        let code = quote! {
+            // define the 'top level' function which is called directly by actix
+            #allow_case
+            pub async fn #opid #generics(
+                data: AxData<#api_ty>,
+                #(#state_args,)*
+                #req_arg_opt
+                #path_arg_opt
+                #query_arg_opt
+                #body_arg_opt
+            ) -> Result<#return_ty, ActixError> {
+                #audit_start_opt
+                #authorize_opt
+                // destructure path and query parameters into variables, if any
+                #path_destructure_opt
+                #query_destructure_opt
+                // call our API handler function with requisite arguments
+                let handler = data.#opid(
+                    #ctx_opt
+                    #(#state_names,)*
+                    #(#path_param_fields,)*
+                    #(#query_param_fields,)*
+                    #body_ident_opt
+                );
+                #run_handler
+            }
+        };
+        Ok(code)
+    }
+
+    /// Generate what is run around the handler overriding the generated one (see
+    /// `hsr::Config::override_handler`), as the generated handler runs it around the
+    /// API: the authorization and auditing of the call, its timeout, and catching
+    /// a panic. The typed path and query parameters are read for the authorization
+    /// and auditing, but the body is left to the overriding handler.
+    pub(crate) fn generate_override_checks(
+        &self,
+        trait_name: &TypeName,
+        opts: &GenerationOptions,
+    ) -> Result<TokenStream> {
         let opid = &self.operation_id;
+        let allow_case = self.allow_non_snake_case();
+        let opid_str = opid.to_string();
+        let (generics, _, api_ty) = handler_api(opts, quote! { #trait_name + Send + Sync });
+        let checked = self.audited || opts.authorization;
 
+        let mut params = Vec::new();
+        let mut extract = Vec::new();
+        if checked {
+            if let Some((name, _)) = &self.path_params {
+                let name = name.canonicalize()?;
+                extract.push(quote! {
+                    let path = <hsr::path::Path<#name> as hsr::actix_web::FromRequest>::extract(req.request()).await?;
+                });
+                params.push(quote! { &*path });
+            }
+            if let Some((name, _)) = &self.query_params {
+                let name = name.canonicalize()?;
+                extract.push(quote! {
+                    let query = <AxQuery<#name> as hsr::actix_web::FromRequest>::extract(req.request()).await?;
+                });
+                params.push(quote! { &*query });
+            }
+        }
+        let data_opt = if checked {
+            Some(quote! {
+                let data = req.app_data::<#api_ty>().ok_or_else(|| {
+                    hsr::actix_web::error::ErrorInternalServerError("The API isn't registered")
+                })?;
+            })
+        } else {
+            None
+        };
+        let timeout = match self.timeout {
+            Some(timeout) => quote! { Some(#timeout) },
+            None => quote! { None },
+        };
+        let guarded = quote! {
+            hsr::dispatch::guard(#opid_str, #timeout, next(req)).await.and_then(|res| res)
+        };
+        let (authorize_opt, guarded) = if opts.authorization {
+            let authorize = quote! {
+                let authz = hsr::authz::AuthzInput::new(*operation(#opid_str).unwrap(), req.request())
+                    #(.with_params(#params))*;
+                let authorized = data.authorize(authz).await;
+            };
+            let guarded = quote! {
+                match authorized {
+                    Ok(()) => #guarded,
+                    Err(denied) => Ok(req.error_response(denied)),
+                }
+            };
+            (Some(authorize), guarded)
+        } else {
+            (None, guarded)
+        };
+        let (audit_start_opt, audit_finish_opt) = if self.audited {
+            (
+                Some(quote! {
+                    let audit = hsr::audit::Audit::start(#opid_str, req.request(), &(#(#params,)*));
+                }),
+                Some(quote! {
+                    let status = match &result {
+                        Ok(res) => res.status(),
+                        Err(e) => e.as_response_error().status_code(),
+                    };
+                    data.audit(audit.finish(status)).await;
+                }),
+            )
+        } else {
+            (None, None)
+        };
+        Ok(quote! {
+            #allow_case
+            pub async fn #opid #generics(
+                req: hsr::actix_web::dev::ServiceRequest,
+                next: hsr::dispatch::Next,
+            ) -> Result<hsr::actix_web::dev::ServiceResponse, ActixError> {
+                // the request mustn't be held on to (e.g. cloned) past `next`, which
+                // needs it to itself to hand its body to the handler
+                #data_opt
+                #(#extract)*
+                #audit_start_opt
+                #authorize_opt
+                let result = #guarded;
+                #audit_finish_opt
+                result
+            }
+        })
+    }
+
+    /// The typed arguments of an actix handler for the route,
+    /// and the code to unpack them into the arguments of the API function
+    fn handler_args(&self) -> Result<HandlerArgs> {
         // path args handling
-        let path_param_fields = &self
+        let path_param_fields = self
             .path_params
             .as_ref()
-            .map(|(_, params)| params.keys().collect::<Vec<_>>())
+            .map(|(_, params)| params.keys().cloned().collect::<Vec<_>>())
             .unwrap_or_default();
-        let (path_arg_opt, path_destructure_opt) = {
-            self.path_params.as_ref().map(|(name, _params)| {
-                let name = name.canonicalize();
+        let (path_arg_opt, path_destructure_opt) = self
+            .path_params
+            .as_ref()
+            .map(|(name, _params)| -> Result<_> {
+                let name = name.canonicalize()?;
                 let path_destructure = quote! {
                     let #name { #(#path_param_fields),* } = path.into_inner();
                 };
                 let path_arg = quote! {
-                    path: AxPath<#name>,
+                    path: hsr::path::Path<#name>,
                 };
-                (Some(path_arg), Some(path_destructure))
+                Ok((Some(path_arg), Some(path_destructure)))
             })
-        }
-        .unwrap_or((None, None));
+            .transpose()?
+            .unwrap_or((None, None));
 
         // query args handling
-        let query_param_fields = &self
+        let query_param_fields = self
             .query_params
             .as_ref()
-            .map(|(_, params)| params.keys().collect::<Vec<_>>())
+            .map(|(_, params)| params.keys().cloned().collect::<Vec<_>>())
             .unwrap_or_default();
-
-        let (query_arg_opt, query_destructure_opt) = {
-            self.query_params.as_ref().map(|(name, _params)| {
-                let name = name.canonicalize();
+        let (query_arg_opt, query_destructure_opt) = self
+            .query_params
+            .as_ref()
+            .map(|(name, _params)| -> Result<_> {
+                let name = name.canonicalize()?;
                 let query_destructure = quote! {
                     let #name { #(#query_param_fields),* } = query.into_inner();
                 };
                 let query_arg = quote! {
                     query: AxQuery<#name>,
                 };
-                (Some(query_arg), Some(query_destructure))
+                Ok((Some(query_arg), Some(query_destructure)))
+            })
+            .transpose()?
+            .unwrap_or((None, None));
+
+        let (body_arg_opt, body_ident_opt) = self
+            .method
+            .body_type()
+            .map(TypePath::canonicalize)
+            .transpose()?
+            .map(|body_ty| {
+                let body_arg = if self.body_media_types.is_empty() {
+                    quote! { AxJson(body): AxJson<#body_ty>, }
+                } else {
+                    quote! { hsr::negotiate::Negotiated(body): hsr::negotiate::Negotiated<#body_ty>, }
+                };
+                (Some(body_arg), Some(ident("body")))
             })
+            .unwrap_or((None, None));
+
+        Ok(HandlerArgs {
+            path_arg_opt,
+            path_destructure_opt,
+            path_param_fields,
+            query_arg_opt,
+            query_destructure_opt,
+            query_param_fields,
+            body_arg_opt,
+            body_ident_opt,
+        })
+    }
+
+    /// Generate an actix handler which forwards the (typed, and so validated)
+    /// request upstream using the generated client, and returns the response
+    pub(crate) fn generate_proxy_handler(&self) -> Result<TokenStream> {
+        if self.websocket.is_some() {
+            // there is no websocket client to forward with
+            return Ok(TokenStream::new());
         }
-        .unwrap_or((None, None));
+        let opid = &self.operation_id;
+        let allow_case = self.allow_non_snake_case();
+        let return_ty = self.return_ty_name();
+        let HandlerArgs {
+            path_arg_opt,
+            path_destructure_opt,
+            path_param_fields,
+            query_arg_opt,
+            query_destructure_opt,
+            query_param_fields,
+            body_arg_opt,
+            body_ident_opt,
+        } = self.handler_args()?;
+        // forward the `Accept` header, for the upstream to negotiate with
+        let accept_opt = if self.offered_media_types().is_empty() {
+            None
+        } else {
+            Some(quote! {
+                ,
+                req.headers()
+                    .get(hsr::actix_http::http::header::ACCEPT)
+                    .and_then(|accept| accept.to_str().ok())
+            })
+        };
+        Ok(quote! {
+            #allow_case
+            async fn #opid(
+                client: AxData<client::Client>,
+                req: HttpRequest,
+                #path_arg_opt
+                #query_arg_opt
+                #body_arg_opt
+            ) -> AxEither<#return_ty, HttpResponse> {
+                #path_destructure_opt
+                #query_destructure_opt
+                // pass the request id and end-to-end headers upstream
+                let request_id = req.extensions().get::<hsr::request_id::RequestId>().cloned();
+                let client = match request_id {
+                    Some(id) => client.with_request_id(id),
+                    None => client.get_ref().clone(),
+                }
+                .with(hsr::CallOptions::forwarding(req.headers()));
+                let resp = client.#opid(
+                    #(#path_param_fields,)*
+                    #(#query_param_fields,)*
+                    #body_ident_opt
+                    #accept_opt
+                ).await;
+                match resp {
+                    Ok(resp) => AxEither::A(resp),
+                    Err(e) => AxEither::B(HttpResponse::BadGateway().body(e.to_string())),
+                }
+            }
+        })
+    }
 
+    /// Generate a handler for the route which can be registered with an axum `Router`.
+    /// It does the same job as the actix dispatcher.
+    pub(crate) fn generate_axum_handler(&self, trait_name: &TypeName) -> Result<TokenStream> {
+        let opid = &self.operation_id;
+        let allow_case = self.allow_non_snake_case();
+        let return_ty = self.return_ty_name();
+        let path_param_fields = self
+            .path_params
+            .as_ref()
+            .map(|(_, params)| params.keys().collect::<Vec<_>>())
+            .unwrap_or_default();
+        let query_param_fields = self
+            .query_params
+            .as_ref()
+            .map(|(_, params)| params.keys().collect::<Vec<_>>())
+            .unwrap_or_default();
+        let (path_arg_opt, path_destructure_opt) = self
+            .path_params
+            .as_ref()
+            .map(|(name, _)| -> Result<_> {
+                let name = name.canonicalize()?;
+                Ok((
+                    Some(
+                        quote! { hsr::axum::extract::Path(path): hsr::axum::extract::Path<#name>, },
+                    ),
+                    Some(quote! { let #name { #(#path_param_fields),* } = path; }),
+                ))
+            })
+            .transpose()?
+            .unwrap_or((None, None));
+        let (query_arg_opt, query_destructure_opt) = self
+            .query_params
+            .as_ref()
+            .map(|(name, _)| -> Result<_> {
+                let name = name.canonicalize()?;
+                Ok((
+                    Some(quote! { hsr::axum::extract::Query(query): hsr::axum::extract::Query<#name>, }),
+                    Some(quote! { let #name { #(#query_param_fields),* } = query; }),
+                ))
+            })
+            .transpose()?
+            .unwrap_or((None, None));
         let (body_arg_opt, body_ident_opt) = self
             .method
             .body_type()
             .map(TypePath::canonicalize)
+            .transpose()?
             .map(|body_ty| {
                 (
-                    Some(quote! { AxJson(body): AxJson<#body_ty>, }),
+                    Some(quote! { hsr::axum::Json(body): hsr::axum::Json<#body_ty>, }),
                     Some(ident("body")),
                 )
             })
             .unwrap_or((None, None));
 
-        let return_ty = self.return_ty_name();
-
-        let code = quote! {
-            // define the 'top level' function which is called directly by actix
-            async fn #opid<A: #trait_name + Send + Sync>(
-                data: AxData<A>,
+        Ok(quote! {
+            #allow_case
+            pub async fn #opid<A: #trait_name>(
+                hsr::axum::extract::State(api): hsr::axum::extract::State<std::sync::Arc<A>>,
                 #path_arg_opt
                 #query_arg_opt
                 #body_arg_opt
             ) -> #return_ty {
-
-                // destructure path and query parameters into variables, if any
                 #path_destructure_opt
                 #query_destructure_opt
-                // call our API handler function with requisite arguments
-                data.#opid(
+                api.#opid(
                     #(#path_param_fields,)*
                     #(#query_param_fields,)*
                     #body_ident_opt
                 ).await
             }
-        };
-        code
+        })
+    }
+}
+
+/// The shared state arguments of the API methods (and of the handlers calling them),
+/// and their names
+fn state_args(opts: &GenerationOptions) -> Result<(Vec<TokenStream>, Vec<Ident>)> {
+    Ok(shared_state(opts)?
+        .into_iter()
+        .map(|(name, ty)| (quote! { #name: AxData<#ty> }, name))
+        .unzip())
+}
+
+/// Client code deserializing the body of a response, then wrapping it (as `body`)
+/// with `wrap`. Negotiated bodies are decoded according to their `Content-Type`.
+fn decode_response(
+    response: &Response,
+    type_path: &TypePath,
+    wrap: TokenStream,
+) -> Result<TokenStream> {
+    let type_name = type_path.canonicalize()?;
+    if response.media_types.is_empty() {
+        return Ok(quote! {
+            {
+                match resp
+                    .json::<#type_name>()
+                    .await {
+                        Ok(body) => Result::Ok(#wrap),
+                        Err(e) => Result::Err(ClientError::Actix(e.into()))
+                    }
+            }
+        });
     }
+    Ok(quote! {
+        {
+            match resp.body().await {
+                Ok(body) => match <#type_name as hsr::negotiate::Representations>::from_body(
+                    hsr::negotiate::content_type(resp.headers()),
+                    &body,
+                ) {
+                    Ok(body) => Result::Ok(#wrap),
+                    Err(e) => Result::Err(ClientError::Actix(e.into())),
+                },
+                Err(e) => Result::Err(ClientError::Actix(e.into())),
+            }
+        }
+    })
+}
+
+/// As `decode_response`, for a body which has already been read into `body`
+/// (with `content_type`)
+fn decode_body(
+    response: &Response,
+    type_path: &TypePath,
+    wrap: TokenStream,
+) -> Result<TokenStream> {
+    let type_name = type_path.canonicalize()?;
+    if response.media_types.is_empty() {
+        return Ok(quote! {
+            match hsr::serde_json::from_slice::<#type_name>(&body) {
+                Ok(body) => Result::Ok(#wrap),
+                Err(e) => Result::Err(ClientError::Actix(e.into())),
+            }
+        });
+    }
+    Ok(quote! {
+        match <#type_name as hsr::negotiate::Representations>::from_body(content_type, &body) {
+            Ok(body) => Result::Ok(#wrap),
+            Err(e) => Result::Err(ClientError::Actix(e.into())),
+        }
+    })
+}
+
+/// Pieces of an actix handler, see `Route::handler_args`
+struct HandlerArgs {
+    path_arg_opt: Option<TokenStream>,
+    path_destructure_opt: Option<TokenStream>,
+    path_param_fields: Vec<Ident>,
+    query_arg_opt: Option<TokenStream>,
+    query_destructure_opt: Option<TokenStream>,
+    query_param_fields: Vec<Ident>,
+    body_arg_opt: Option<TokenStream>,
+    body_ident_opt: Option<QIdent>,
 }
 
 #[derive(Debug, Clone, derive_more::Constructor, derive_more::Deref)]
-struct Counter<A: PartialEq + Eq + Hash>(HashMap<A, usize>);
+struct Counter<A: PartialEq + Eq + Hash>(Map<A, usize>);
 
 impl<A: PartialEq + Eq + Hash> std::iter::FromIterator<A> for Counter<A> {
     fn from_iter<T>(iter: T) -> Self
     where
         T: IntoIterator<Item = A>,
     {
-        let mut counter = Counter(HashMap::new());
+        let mut counter = Counter(Map::new());
         for item in iter {
             let ct = counter.0.entry(item).or_insert(0);
             *ct += 1
@@ -0,0 +1,313 @@
+//! Generating several versions of an API side by side.
+//!
+//! Each version gets its own module, as if generated alone. Component schemas which
+//! are identical in every version (and only refer to other such schemas) are generated
+//! once, in a `shared` module, so that values can be passed between versions.
+
+use std::fs;
+use std::path::Path;
+
+use heck::CamelCase;
+use proc_macro2::TokenStream;
+use quote::quote;
+
+use crate::*;
+
+/// Generate a module for each version of an API, named by the first member of each
+/// pair (e.g. `("v1", "api-v1.yaml")`), and a `serve` function serving each version
+/// under its own prefix (e.g. `/v1`).
+pub fn generate_versioned_from_yaml_files<P: AsRef<Path>>(
+    versions: &[(&str, P)],
+    opts: &GenerationOptions,
+) -> Result<String> {
+    if versions.is_empty() {
+        invalid!("No API versions given");
+    }
+    if opts.backend == Backend::Axum {
+        invalid!("Versioned APIs are not supported by the axum backend");
+    }
+    if !opts.shared_state.is_empty() {
+        invalid!("Shared state is not supported for versioned APIs");
+    }
+    let version_re = Regex::new("^[[:alpha:]]([[:alnum:]]|_)*$").unwrap();
+    let mut sources = Vec::new();
+    for (version, path) in versions {
+        if !version_re.is_match(version) {
+            invalid!("Bad API version name '{}'", version);
+        }
+        if sources.iter().any(|(v, _)| v == version) {
+            invalid!("API version '{}' given twice", version);
+        }
+        sources.push((*version, fs::read_to_string(path)?));
+    }
+
+    let apis = sources
+        .iter()
+        .map(|(_, source)| parse_openapi(source))
+        .collect::<Result<Vec<_>>>()?;
+    let raws: Vec<_> = apis.iter().map(|(_, raw)| raw.clone()).collect();
+    let shared = shared_schemas(&raws);
+
+    let shared_types = if shared.is_empty() {
+        TokenStream::new()
+    } else {
+        let (api, raw) = &apis[0];
+        let (type_lookup, _) = walk::walk_api(api, raw, opts.lenient)?;
+        walk::generate_rust_types_where(&type_lookup, opts, &|path| is_shared_type(path, &shared))?
+    };
+
+    let mut modules = Vec::new();
+    let mut mounts = Vec::new();
+    let mut type_params = Vec::new();
+    let mut args = Vec::new();
+    for ((version, source), (api, _)) in sources.iter().zip(&apis) {
+        let code =
+            generate_tokens(source, opts, &shared).map_err(|e| yaml::locate_error(e, source))?;
+        let module = ident(version);
+        let trait_name = api_trait_name(api)?;
+        let type_param = ident(version.to_camel_case());
+        let arg = ident(format!("{}_api", version));
+        let prefix = format!("/{}", version);
+        modules.push(quote! {
+            pub mod #module {
+                #code
+            }
+        });
+        mounts.push(quote! {
+            .mount(#prefix, #module::server::mount(#arg, &cfg))
+        });
+        type_params.push(quote! { #type_param: #module::#trait_name });
+        args.push(quote! { #arg: #type_param });
+    }
+
+    let code = quote! {
+        /// Types which are identical in every version of the API
+        #[allow(dead_code)]
+        pub mod shared {
+            #[allow(unused_imports)]
+            use hsr::serde_json::Value as JsonValue;
+
+            #shared_types
+        }
+
+        #(#modules)*
+
+        /// Serve every version of the API, each under its own prefix.
+        /// Once started, the server blocks indefinitely.
+        pub async fn serve<#(#type_params),*>(
+            #(#args,)*
+            cfg: hsr::Config,
+        ) -> std::io::Result<()> {
+            hsr::compose::Composite::new()
+                #(#mounts)*
+                .serve(cfg)
+                .await
+        }
+    };
+    format_code(code)
+}
+
+/// Whether a type is (part of) one of the shared component schemas
+pub(crate) fn is_shared_type(path: &TypePath, shared: &Set<String>) -> bool {
+    match &path.0[..] {
+        [components, schemas, name, ..] => {
+            components == "components" && schemas == "schemas" && shared.contains(name)
+        }
+        _ => false,
+    }
+}
+
+/// The names of the component schemas which are the same in every spec, and which
+/// only refer to other such schemas
+fn shared_schemas(raws: &[serde_json::Value]) -> Set<String> {
+    let schemas: Vec<_> = raws
+        .iter()
+        .map(|raw| {
+            raw.pointer("/components/schemas")
+                .and_then(|s| s.as_object())
+        })
+        .collect();
+    let first = match schemas.first() {
+        Some(Some(first)) => first,
+        _ => return Set::new(),
+    };
+    let mut shared: Set<String> = first
+        .iter()
+        .filter(|(name, schema)| {
+            schemas[1..]
+                .iter()
+                .all(|other| other.and_then(|o| o.get(name.as_str())) == Some(*schema))
+        })
+        .map(|(name, _)| name.clone())
+        .collect();
+    // A schema referring to a type which differs between versions can't be shared
+    loop {
+        let unshareable: Vec<String> = shared
+            .iter()
+            .filter(|name| {
+                let mut refs = Vec::new();
+                collect_refs(&first[name.as_str()], &mut refs);
+                refs.iter().any(|r| !shared.contains(r))
+            })
+            .cloned()
+            .collect();
+        if unshareable.is_empty() {
+            return shared;
+        }
+        for name in unshareable {
+            shared.shift_remove(&name);
+        }
+    }
+}
+
+/// The names of the component schemas referenced within `value`
+fn collect_refs(value: &serde_json::Value, refs: &mut Vec<String>) {
+    match value {
+        serde_json::Value::Object(map) => {
+            for (key, value) in map {
+                match (key.as_str(), value.as_str()) {
+                    ("$ref", Some(reference)) => {
+                        let name = reference.trim_start_matches("#/components/schemas/");
+                        refs.push(name.to_string())
+                    }
+                    _ => collect_refs(value, refs),
+                }
+            }
+        }
+        serde_json::Value::Array(values) => {
+            for value in values {
+                collect_refs(value, refs)
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use tempdir::TempDir;
+
+    const SPEC: &str = r#"
+openapi: 3.0.0
+info:
+  title: Pets
+  version: VERSION
+paths:
+  /pets:
+    get:
+      operationId: list_pets
+      responses:
+        '200':
+          description: The pets
+          content:
+            application/json:
+              schema:
+                type: array
+                items:
+                  $ref: '#/components/schemas/Pet'
+components:
+  schemas:
+    Pet:
+      type: object
+      properties:
+        name:
+          type: string
+    Owner:
+      type: object
+      properties:
+        OWNER_FIELD:
+          type: string
+"#;
+
+    /// The code without whitespace, which differs with the formatting
+    fn flat(code: &str) -> String {
+        code.split_whitespace().collect()
+    }
+
+    #[test]
+    fn generate_versions() {
+        let dir = TempDir::new("hsr-versions").unwrap();
+        let v1 = dir.path().join("v1.yaml");
+        let v2 = dir.path().join("v2.yaml");
+        fs::write(
+            &v1,
+            SPEC.replace("VERSION", "1.0.0")
+                .replace("OWNER_FIELD", "name"),
+        )
+        .unwrap();
+        fs::write(
+            &v2,
+            SPEC.replace("VERSION", "2.0.0")
+                .replace("OWNER_FIELD", "full_name"),
+        )
+        .unwrap();
+        let opts = GenerationOptions::default();
+
+        let code =
+            flat(&generate_versioned_from_yaml_files(&[("v1", &v1), ("v2", &v2)], &opts).unwrap());
+        assert!(code.contains("pubmodshared{"), "{}", code);
+        assert!(code.contains("pubmodv1{"), "{}", code);
+        assert!(code.contains("pubmodv2{"), "{}", code);
+        // the pet is shared, the owner differs
+        assert_eq!(code.matches("pubstructPet{").count(), 1, "{}", code);
+        assert_eq!(code.matches("pubstructOwner{").count(), 2, "{}", code);
+        assert_eq!(
+            code.matches("pubusesuper::shared::{Pet,PetName}").count(),
+            2,
+            "{}",
+            code
+        );
+        assert!(
+            code.contains(".mount(\"/v1\",v1::server::mount(v1_api,&cfg))"),
+            "{}",
+            code
+        );
+        assert!(
+            code.contains(".mount(\"/v2\",v2::server::mount(v2_api,&cfg))"),
+            "{}",
+            code
+        );
+
+        // a single version shares everything with itself
+        let code = flat(&generate_versioned_from_yaml_files(&[("v1", &v1)], &opts).unwrap());
+        assert_eq!(code.matches("pubstructOwner{").count(), 1, "{}", code);
+
+        let err = |versions: &[(&str, &std::path::PathBuf)], opts: &GenerationOptions| {
+            generate_versioned_from_yaml_files(versions, opts)
+                .unwrap_err()
+                .to_string()
+        };
+        assert!(err(&[], &opts).contains("No API versions"));
+        assert!(err(&[("1", &v1)], &opts).contains("Bad API version name"));
+        assert!(err(&[("v1", &v1), ("v1", &v2)], &opts).contains("given twice"));
+        let axum = GenerationOptions {
+            backend: Backend::Axum,
+            ..Default::default()
+        };
+        assert!(err(&[("v1", &v1)], &axum).contains("axum"));
+        let missing = dir.path().join("v3.yaml");
+        assert!(generate_versioned_from_yaml_files(&[("v3", &missing)], &opts).is_err());
+    }
+
+    #[test]
+    fn find_shared_schemas() {
+        let v1 = json!({"components": {"schemas": {
+            "Same": {"type": "string"},
+            "Changed": {"type": "string"},
+            "RefersToSame": {"type": "array", "items": {"$ref": "#/components/schemas/Same"}},
+            "RefersToChanged": {"type": "array", "items": {"$ref": "#/components/schemas/Changed"}},
+            "Removed": {"type": "string"},
+        }}});
+        let v2 = json!({"components": {"schemas": {
+            "Same": {"type": "string"},
+            "Changed": {"type": "integer"},
+            "RefersToSame": {"type": "array", "items": {"$ref": "#/components/schemas/Same"}},
+            "RefersToChanged": {"type": "array", "items": {"$ref": "#/components/schemas/Changed"}},
+        }}});
+        let mut shared: Vec<_> = shared_schemas(&[v1, v2]).into_iter().collect();
+        shared.sort();
+        assert_eq!(shared, vec!["RefersToSame", "Same"]);
+    }
+}
@@ -1,33 +1,39 @@
-use heck::CamelCase;
+use heck::{CamelCase, SnakeCase};
 use indexmap::{IndexMap as Map, IndexSet as Set};
 use log::debug;
 use openapiv3::{
     AdditionalProperties, AnySchema, Components, ObjectType, OpenAPI, Operation, Parameter,
-    ParameterSchemaOrContent, ReferenceOr, Schema, SchemaData, SchemaKind,
-    StatusCode as ApiStatusCode, Type as ApiType,
+    ParameterSchemaOrContent, QueryStyle, ReferenceOr, Schema, SchemaData, SchemaKind,
+    SecurityRequirement, StatusCode as ApiStatusCode, Type as ApiType,
 };
 use proc_macro2::TokenStream;
-use quote::quote;
+use quote::{quote, ToTokens};
 use regex::Regex;
 
+use std::borrow::Borrow;
 use std::collections::BTreeMap;
 use std::convert::TryFrom;
 use std::fmt;
 use std::ops::Deref;
 
 use crate::{
-    dereference, doc_comment, get_derive_tokens, unwrap_ref, variant_from_status_code, ApiPath,
-    Error, FieldMetadata, Ident, Method, MethodWithBody, MethodWithoutBody, RawMethod, Result,
-    RoutePath, SchemaLookup, StatusCode, TypeMetadata, TypeName, TypePath, Visibility,
+    arbitrary_derive_tokens, dereference, doc_comment, escape_pointer_segment, extensions_at,
+    get_derive_tokens, unwrap_ref, variant_from_status_code, ApiPath, Error, Extensions,
+    FieldMetadata, GenerationOptions, Ident, Method, MethodWithBody, MethodWithoutBody, RawMethod,
+    Result, RoutePath, SchemaLookup, StatusCode, TypeMetadata, TypeName, TypePath,
 };
 
-use crate::route::{validate_routes, Response, Responses, Route};
+use crate::route::{
+    validate_routes, CachePolicy, Idempotency, Pagination, RateLimit, Response, ResponseLink,
+    Responses, Route, Timeout,
+};
 
 use proc_macro2::Ident as QIdent;
 
-pub(crate) type TypeLookup = BTreeMap<TypePath, ReferenceOr<Type>>;
+/// Every type found in the spec, keyed by where it was defined
+pub type TypeLookup = BTreeMap<TypePath, ReferenceOr<Type>>;
 
-fn lookup_type_recursive<'a>(
+pub(crate) fn lookup_type_recursive<'a>(
     item: &'a ReferenceOr<Type>,
     lookup: &'a TypeLookup,
 ) -> Result<&'a Type> {
@@ -44,9 +50,22 @@ fn lookup_type_recursive<'a>(
 }
 
 #[derive(Clone, PartialEq)]
-pub(crate) struct Type {
-    meta: TypeMetadata,
-    typ: TypeInner,
+pub struct Type {
+    pub(crate) meta: TypeMetadata,
+    pub(crate) typ: TypeInner,
+    /// The JSON pointer of the schema in the spec, if it comes from one
+    pub(crate) pointer: String,
+}
+
+impl Type {
+    /// The JSON pointer of the schema defining the type, if any
+    pub fn pointer(&self) -> Option<&str> {
+        if self.pointer.is_empty() {
+            None
+        } else {
+            Some(&self.pointer)
+        }
+    }
 }
 
 impl fmt::Debug for Type {
@@ -56,17 +75,24 @@ impl fmt::Debug for Type {
 }
 
 #[derive(Debug, Clone, PartialEq, derive_more::Display)]
-enum Primitive {
+pub enum Primitive {
     #[display(fmt = "String")]
     String,
     #[display(fmt = "f64")]
-    F64,
+    F64(Bounds<f64>),
     #[display(fmt = "i64")]
-    I64,
+    I64(Bounds<i64>),
     #[display(fmt = "bool")]
     Bool,
 }
 
+/// The (inclusive) range of values a number may take
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Bounds<T> {
+    pub min: Option<T>,
+    pub max: Option<T>,
+}
+
 /// Represent a variant of an enum
 #[derive(Debug, Clone)]
 pub(crate) struct Variant {
@@ -74,6 +100,7 @@ pub(crate) struct Variant {
     pub description: Option<String>,
     pub type_path: Option<TypePath>,
     pub rename: Option<String>,
+    pub status: Option<u16>,
 }
 
 impl Variant {
@@ -83,6 +110,7 @@ impl Variant {
             description: None,
             type_path: None,
             rename: None,
+            status: None,
         }
     }
 
@@ -103,10 +131,17 @@ impl Variant {
             ..self
         }
     }
-}
 
-impl quote::ToTokens for Variant {
-    fn to_tokens(&self, tokens: &mut TokenStream) {
+    /// The http status code of the variant, for use with `#[derive(HasStatusCode)]`
+    pub(crate) fn status(self, status: u16) -> Self {
+        Self {
+            status: Some(status),
+            ..self
+        }
+    }
+
+    /// The definition of the variant, within its enum
+    fn tokens(&self) -> Result<TokenStream> {
         let descr = self.description.as_ref().map(doc_comment);
         let name = &self.name;
         let rename = self.rename.as_ref().map(|name| {
@@ -114,12 +149,19 @@ impl quote::ToTokens for Variant {
                 #[serde(rename = #name)]
             }
         });
+        let status = self.status.map(|code| {
+            let code = proc_macro2::Literal::u16_unsuffixed(code);
+            quote! {
+                #[status(#code)]
+            }
+        });
         let tok = match self.type_path.as_ref() {
             Some(path) => {
-                let varty = path.canonicalize();
+                let varty = path.canonicalize()?;
                 quote! {
                     #descr
                     #rename
+                    #status
                     #name(#varty)
                 }
             }
@@ -127,44 +169,107 @@ impl quote::ToTokens for Variant {
                 quote! {
                     #descr
                     #rename
+                    #status
                     #name
                 }
             }
         };
-        tokens.extend(tok);
+        Ok(tok)
     }
 }
 
 #[derive(Debug, Clone, PartialEq)]
-enum TypeInner {
+pub enum TypeInner {
     // primitives
     Primitive(Primitive),
     // String that can only take set values
     StringEnum(Vec<String>),
+    // Integer that can only take set values
+    IntegerEnum(Vec<i64>),
     // An array of of some inner type
     Array(Box<ReferenceOr<Type>>),
+    // An object whose values are all of some inner type (`additionalProperties: <schema>`)
+    Map(Box<ReferenceOr<Type>>),
     // Any type. Could be anything! Probably a user-error
     Any,
     AllOf(Vec<ReferenceOr<Type>>),
     OneOf(Vec<TypePath>),
+    // Like a OneOf, but a value may match more than one alternative (the first wins)
+    AnyOf(Vec<TypePath>),
     Struct(Struct),
+    // A body with more than one media type (or one which isn't JSON), by media type
+    Content(Map<String, Representation>),
+    // A schema using a feature we can't model (which is named), taken to be any JSON
+    Exotic(String),
+}
+
+/// How a body of some media type is represented
+#[derive(Debug, Clone, PartialEq)]
+pub enum Representation {
+    /// JSON, deserialized into a type
+    Json(TypePath),
+    /// Text (`text/*`), as a `String`
+    Text,
+    /// Anything else, as bytes
+    Binary,
+    /// A JSON merge patch of a type, as `hsr::patch::Patch<T>`
+    MergePatch(TypePath),
+    /// A JSON patch, as `Vec<hsr::patch::PatchOp>`
+    JsonPatch,
+}
+
+impl Type {
+    pub fn meta(&self) -> &TypeMetadata {
+        &self.meta
+    }
+
+    pub fn inner(&self) -> &TypeInner {
+        &self.typ
+    }
 }
 
 impl TypeInner {
     /// Attach metadata
     fn with_meta(self, meta: TypeMetadata) -> Type {
-        Type { meta, typ: self }
+        Type {
+            meta,
+            typ: self,
+            pointer: String::new(),
+        }
     }
 }
 
 #[derive(Clone, Debug, PartialEq)]
-struct Struct {
+pub struct Struct {
     // each field must carry some struct-specific metadata
     // (on top of metadata attached to the type)
-    fields: Map<Ident, (FieldMetadata, TypePath)>,
+    pub(crate) fields: Map<Ident, (FieldMetadata, TypePath)>,
+    pub(crate) unknown_fields: UnknownFields,
+}
+
+/// What a struct does with the fields of an object which it doesn't declare
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnknownFields {
+    /// Whatever `GenerationOptions::deny_unknown_fields` and `extra_fields` say
+    Default,
+    /// Reject them, as asked by `additionalProperties: false`
+    Deny,
+    /// Keep them in an `extra` map, as asked by `additionalProperties: true`
+    Capture,
+    /// Ignore them, as for parameters (query strings often carry extra ones)
+    Ignore,
 }
 
 impl Struct {
+    /// The fields of the struct, with the location of the type of each
+    pub fn fields(&self) -> &Map<Ident, (FieldMetadata, TypePath)> {
+        &self.fields
+    }
+
+    pub fn unknown_fields(&self) -> UnknownFields {
+        self.unknown_fields
+    }
+
     /// Build a struct from an object-like OpenApi type
     /// We look recursively inside the object definition
     /// and nested schema definitions are added to the index
@@ -174,19 +279,62 @@ impl Struct {
         type_index: &mut TypeLookup,
     ) -> Result<Self> {
         let mut fields = Map::new();
-        let required_args: Set<String> = obj.required().iter().cloned().collect();
+        let required_args: Set<&str> = obj.required().iter().map(String::as_str).collect();
         for (name, schemaref) in obj.properties() {
-            let schemaref = schemaref.clone().unbox();
-            let path = path.clone().push(name);
-            let ty = build_type_recursive(&schemaref, path.clone(), type_index)?;
-            let type_path = TypePath::from(path);
-            assert!(type_index.insert(type_path.clone(), ty.clone()).is_none());
-            let meta = FieldMetadata::default().with_required(required_args.contains(name));
-            if let Some(_) = fields.insert(name.parse()?, (meta, type_path)) {
+            let path = path
+                .clone()
+                .push_name(name)
+                .push_pointer(&format!("/properties/{}", escape_pointer_segment(name)));
+            let ty = build_type_recursive(schemaref, path.clone(), type_index)?;
+            insert_type(type_index, path.clone(), ty)?;
+            let meta =
+                FieldMetadata::default().with_required(required_args.contains(name.as_str()));
+            let field = name.parse().map_err(|e: Error| e.at(path.pointer()))?;
+            if let Some(_) = fields.insert(field, (meta, TypePath::from(path))) {
                 invalid!("Duplicate field name: '{}'", name);
             }
         }
-        Ok(Self { fields })
+        let unknown_fields = match obj.additional_properties() {
+            Some(AdditionalProperties::Any(false)) => UnknownFields::Deny,
+            Some(AdditionalProperties::Any(true)) => UnknownFields::Capture,
+            _ => UnknownFields::Default,
+        };
+        Ok(Self {
+            fields,
+            unknown_fields,
+        })
+    }
+}
+
+/// Whether we can model the `additionalProperties` of an object: `true` or `false`
+/// are, and so is a schema if the object has no properties (it is then a map)
+fn check_additional_properties<T: ObjectLike>(obj: &T) -> Result<()> {
+    match obj.additional_properties() {
+        Some(AdditionalProperties::Schema(_)) if !obj.properties().is_empty() => {
+            unsupported!("Additional properties schemas alongside properties")
+        }
+        _ => Ok(()),
+    }
+}
+
+/// The type of the values of an object without properties whose `additionalProperties`
+/// is a schema, which is then a map
+fn build_map_type<T: ObjectLike>(
+    obj: &T,
+    path: &ApiPath,
+    type_index: &mut TypeLookup,
+) -> Result<Option<TypeInner>> {
+    match obj.additional_properties() {
+        Some(AdditionalProperties::Schema(schema)) if obj.properties().is_empty() => {
+            let path = path
+                .clone()
+                .push_name("map")
+                .push_pointer("/additionalProperties");
+            let innerty = build_type_recursive(&**schema, path.clone(), type_index)?;
+            insert_type(type_index, path, innerty.clone())?;
+            Ok(Some(TypeInner::Map(Box::new(innerty))))
+        }
+        _ => Ok(None),
     }
 }
 
@@ -217,32 +365,200 @@ macro_rules! impl_objlike {
 impl_objlike!(ObjectType);
 impl_objlike!(AnySchema);
 
-pub(crate) fn walk_api(api: &OpenAPI) -> Result<(TypeLookup, Map<String, Vec<Route>>)> {
-    if !api.security.is_empty() {
-        todo!("Security not supported")
+/// Add a type to the lookup, failing if the location is taken or doesn't make a
+/// valid type name
+fn insert_type(type_index: &mut TypeLookup, path: ApiPath, typ: ReferenceOr<Type>) -> Result<()> {
+    let pointer = path.pointer().to_string();
+    let path = TypePath::from(path);
+    if let Err(e) = path.canonicalize() {
+        return Err(e.at(&pointer));
     }
+    if type_index.contains_key(&path) {
+        return Err(
+            Error::Validation(format!("Type '{}' defined twice", ApiPath::from(path))).at(&pointer),
+        );
+    }
+    type_index.insert(path, typ);
+    Ok(())
+}
+
+/// Walk the spec, collecting its types and routes. Unless `lenient`, schemas
+/// using features we can't model are an error.
+pub(crate) fn walk_api(
+    api: &OpenAPI,
+    raw: &serde_json::Value,
+    lenient: bool,
+) -> Result<(TypeLookup, Map<String, Vec<Route>>)> {
     let mut type_index = TypeLookup::new();
     let dummy = Default::default();
     let components = api.components.as_ref().unwrap_or(&dummy);
     walk_component_schemas(&components.schemas, &mut type_index)?;
-    let routes = walk_paths(&api.paths, &mut type_index, &components)?;
+    let routes = walk_paths(&api.paths, raw, &api.security, &mut type_index, &components)?;
     validate_routes(&routes)?;
+    check_all_of_types(&mut type_index)?;
+    for typ in type_index.values_mut() {
+        if let ReferenceOr::Item(typ) = typ {
+            read_raw_schema(typ, raw)?;
+        }
+    }
+    check_exotic_types(&type_index, lenient)?;
     Ok((type_index, routes))
 }
 
+/// Check that the parts of each `allOf` can be combined. This can only be done once
+/// every type has been seen. Those which can't (but are otherwise valid) are exotic.
+fn check_all_of_types(type_index: &mut TypeLookup) -> Result<()> {
+    let mut exotic = Vec::new();
+    for (type_path, typ) in type_index.iter() {
+        if let ReferenceOr::Item(typ) = typ {
+            if let TypeInner::AllOf(parts) = &typ.typ {
+                match combine_types(parts, type_index, type_path) {
+                    Ok(_) => {}
+                    Err(Error::Unsupported(feature)) => exotic.push((type_path.clone(), feature)),
+                    Err(e) => return Err(e.at(&typ.pointer)),
+                }
+            }
+        }
+    }
+    for (type_path, feature) in exotic {
+        if let Some(ReferenceOr::Item(typ)) = type_index.get_mut(&type_path) {
+            typ.typ = TypeInner::Exotic(feature);
+        }
+    }
+    Ok(())
+}
+
+fn is_number(typ: &TypeInner) -> bool {
+    matches!(
+        typ,
+        TypeInner::Primitive(Primitive::I64(_)) | TypeInner::Primitive(Primitive::F64(_))
+    )
+}
+
+/// `openapiv3` ignores `not` and integer enums, and can't parse the `null`
+/// of a nullable enum (see `parse_openapi`), so we look for them in the raw spec.
+/// A schema using `not` is exotic, whatever else it says.
+fn read_raw_schema(typ: &mut Type, raw: &serde_json::Value) -> Result<()> {
+    let schema = match raw.pointer(&typ.pointer) {
+        Some(schema) if !typ.pointer.is_empty() => Some(schema),
+        _ => None,
+    };
+    if schema.and_then(|schema| schema.get("not")).is_some() {
+        typ.typ = TypeInner::Exotic("'not' schemas".into());
+        return Ok(());
+    }
+    if let Some(values) = schema
+        .and_then(|schema| schema.get("enum"))
+        .and_then(|values| values.as_array())
+    {
+        if values.iter().any(|value| value.is_null()) {
+            typ.meta.nullable = true;
+        }
+        // openapiv3 can't parse an integer schema with a bad value, and
+        // takes it for any JSON
+        let integer = matches!(typ.typ, TypeInner::Primitive(Primitive::I64(_)))
+            || schema.and_then(|schema| schema.get("type")) == Some(&"integer".into());
+        if integer {
+            let values = values
+                .iter()
+                .filter(|value| !value.is_null())
+                .map(|value| match value.as_i64() {
+                    Some(value) => Ok(value),
+                    None => invalid!("Enum value {} is not an integer", value),
+                })
+                .collect::<Result<Vec<_>>>()
+                .map_err(|e| e.at(&typ.pointer))?;
+            if !values.is_empty() {
+                typ.typ = TypeInner::IntegerEnum(values);
+            }
+        }
+    }
+    match schema.and_then(|schema| schema.get("x-hsr-case-insensitive")) {
+        None => {}
+        Some(serde_json::Value::Bool(flag)) if matches!(typ.typ, TypeInner::StringEnum(_)) => {
+            typ.meta.case_insensitive = *flag
+        }
+        Some(_) => {
+            return Err(Error::Validation(
+                "'x-hsr-case-insensitive' must be a boolean, on a string enum".into(),
+            )
+            .at(&format!("{}/x-hsr-case-insensitive", typ.pointer)))
+        }
+    }
+    match schema.and_then(|schema| schema.get("x-hsr-lenient")) {
+        None => {}
+        Some(serde_json::Value::Bool(flag)) if is_number(&typ.typ) => typ.meta.lenient = *flag,
+        Some(_) => {
+            return Err(
+                Error::Validation("'x-hsr-lenient' must be a boolean, on a number".into())
+                    .at(&format!("{}/x-hsr-lenient", typ.pointer)),
+            )
+        }
+    }
+    // inline parts are not in the lookup
+    let parts: Vec<&mut ReferenceOr<Type>> = match &mut typ.typ {
+        TypeInner::AllOf(parts) => parts.iter_mut().collect(),
+        TypeInner::Array(inner) | TypeInner::Map(inner) => vec![&mut **inner],
+        _ => Vec::new(),
+    };
+    for part in parts {
+        if let ReferenceOr::Item(part) = part {
+            read_raw_schema(part, raw)?
+        }
+    }
+    Ok(())
+}
+
+/// Schemas we can't model are an error, unless we are lenient. Then they
+/// are `serde_json::Value`s, with a warning.
+fn check_exotic_types(type_index: &TypeLookup, lenient: bool) -> Result<()> {
+    fn find_exotic<'a>(typ: &'a Type, found: &mut Vec<(&'a str, &'a str)>) {
+        match &typ.typ {
+            TypeInner::Exotic(feature) => found.push((typ.pointer.as_str(), feature.as_str())),
+            TypeInner::AllOf(parts) => {
+                for part in parts {
+                    if let ReferenceOr::Item(part) = part {
+                        find_exotic(part, found)
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    let mut found = Vec::new();
+    for typ in type_index.values() {
+        if let ReferenceOr::Item(typ) = typ {
+            find_exotic(typ, &mut found)
+        }
+    }
+    for (pointer, feature) in found {
+        if !lenient {
+            return Err(Error::Unsupported(feature.to_string()).at(pointer));
+        }
+        log::warn!(
+            "{} not supported (at '{}'), generating a JSON value instead",
+            feature,
+            pointer
+        );
+    }
+    Ok(())
+}
+
 fn walk_component_schemas(schema_lookup: &SchemaLookup, type_index: &mut TypeLookup) -> Result<()> {
     let path = ApiPath::default().push("components").push("schemas");
     // gather types defined in components
     for (name, schema) in schema_lookup {
         let path = path.clone().push(name);
-        let typ = build_type_recursive(&schema, path.clone(), type_index)?;
-        assert!(type_index.insert(TypePath::from(path), typ).is_none());
+        let typ = build_type_recursive(schema, path.clone(), type_index)?;
+        insert_type(type_index, path, typ)?;
     }
     Ok(())
 }
 
 fn walk_paths(
     paths: &openapiv3::Paths,
+    raw: &serde_json::Value,
+    default_security: &[SecurityRequirement],
     type_index: &mut TypeLookup,
     components: &Components,
 ) -> Result<Map<String, Vec<Route>>> {
@@ -250,26 +566,43 @@ fn walk_paths(
     let api_path = ApiPath::default().push("paths");
     for (path, ref_or_item) in paths {
         let api_path = api_path.clone().push(path);
-        let route_path = RoutePath::analyse(path)?;
+        let at_path = |e: Error| e.at(api_path.pointer());
+        let route_path = RoutePath::analyse(path).map_err(at_path)?;
 
         debug!("Gathering types for path: {:?}", path);
         // TODO lookup rather than unwrap
-        let pathitem = unwrap_ref(&ref_or_item)?;
+        let pathitem = unwrap_ref(&ref_or_item).map_err(at_path)?;
 
         if !pathitem.parameters.is_empty() {
-            todo!("Path-level paraters are not supported")
+            return Err(Error::Unsupported("Path-level parameters".into()).at(api_path.pointer()));
         }
 
         apply_over_operations(pathitem, |op, method| {
-            let api_path = api_path.clone().push(method.to_string());
+            let api_path = api_path
+                .clone()
+                .push_name(method.to_string())
+                .push_pointer(&format!("/{}", method.to_string().to_lowercase()));
+            let pointer = api_path.pointer().to_string();
+            let extensions = extensions_at(raw, &pointer);
+            // `security: []` on an operation removes the default requirements
+            let security = if raw.pointer(&format!("{}/security", pointer)).is_some() {
+                &op.security[..]
+            } else {
+                default_security
+            };
+            let examples = crate::examples::operation_examples(raw, &pointer);
             let route = walk_operation(
                 op,
                 method,
                 api_path.clone(),
                 &route_path,
+                &extensions,
+                security,
+                examples,
                 type_index,
                 components,
-            )?;
+            )
+            .map_err(|e| e.at(&pointer))?;
             routes.entry(path.clone()).or_default().push(route);
             Ok(())
         })?;
@@ -315,6 +648,9 @@ fn walk_operation(
     method: RawMethod,
     path: ApiPath,
     route_path: &RoutePath,
+    extensions: &Extensions,
+    security: &[SecurityRequirement],
+    examples: Vec<crate::examples::Example>,
     type_index: &mut TypeLookup,
     components: &Components,
 ) -> Result<Route> {
@@ -322,14 +658,24 @@ fn walk_operation(
 
     use Parameter::*;
 
-    if !op.security.is_empty() {
-        todo!("Security not supported")
-    }
+    let op_pointer = path.pointer().to_string();
+    // the pointer of a member of the operation
+    let member = |name: &str| format!("{}/{}", op_pointer, name);
 
-    let (operation_id, path) = match op.operation_id {
-        Some(ref op) => op.parse().map(|opid| (opid, path.push(op))),
+    let (operation_id, path): (Ident, _) = match op.operation_id {
+        Some(ref op) => op
+            .parse()
+            .map(|opid| (opid, path.push_name(op)))
+            .map_err(|e: Error| e.at(&member("operationId"))),
         None => invalid!("Missing operationId for '{}'", route_path),
     }?;
+    // the operation id also names the return type
+    if operation_id.to_camel_case().parse::<TypeName>().is_err() {
+        return Err(
+            Error::Validation(format!("Bad operationId '{}'", operation_id))
+                .at(&member("operationId")),
+        );
+    }
 
     // A LOT of work goes into getting the path and query parameters correct!
 
@@ -339,64 +685,121 @@ fn walk_operation(
     let mut expected_route_params: Set<&str> = route_path.path_args().collect();
     let mut duplicate_param_name_check = Set::new();
 
-    for param in &op.parameters {
-        // for each parameter we gather the type but we also need to
-        // collect the Queries and Paths to make the parent Query and Path types
-        let param = dereference(param, &components.parameters)?;
-
-        let parameter_data = match param {
-            Path { parameter_data, .. }
-            | Query { parameter_data, .. }
-            | Header { parameter_data, .. }
-            | Cookie { parameter_data, .. } => parameter_data,
-        };
+    for (ix, param) in op.parameters.iter().enumerate() {
+        let param_pointer = member(&format!("parameters/{}", ix));
+        let mut walk_param = || -> Result<()> {
+            // for each parameter we gather the type but we also need to
+            // collect the Queries and Paths to make the parent Query and Path types
+            let param = dereference(param, &components.parameters)?;
+
+            let parameter_data = match param {
+                Path { parameter_data, .. }
+                | Query { parameter_data, .. }
+                | Header { parameter_data, .. }
+                | Cookie { parameter_data, .. } => parameter_data,
+            };
 
-        // We use macros here and below to cut down on duplication between path and query params
-        macro_rules! build_param_type {
-            ($params: ident, $path: expr) => {
-                if !duplicate_param_name_check.insert(&parameter_data.name) {
-                    invalid!("Duplicated parameter '{}'", parameter_data.name)
-                }
-                let path = path.clone().push($path).push(&parameter_data.name);
-                let name: Ident = parameter_data.name.parse()?;
-                let meta = FieldMetadata::default().with_required(parameter_data.required);
-                $params.insert(name, (meta, TypePath::from(path.clone())));
-                match &parameter_data.format {
-                    ParameterSchemaOrContent::Schema(schema) => {
-                        let typ = build_type_recursive(&schema, path.clone(), type_index)?;
-                        assert!(type_index.insert(TypePath::from(path), typ).is_none());
+            // We use macros here and below to cut down on duplication between path and query params
+            macro_rules! build_param_type {
+                ($params: ident, $path: expr, $with_default: expr, $meta: expr) => {
+                    if !duplicate_param_name_check.insert(&parameter_data.name) {
+                        invalid!("Duplicated parameter '{}'", parameter_data.name)
                     }
-                    ParameterSchemaOrContent::Content(_) => todo!(),
-                }
-            };
-        }
+                    let path = path
+                        .clone()
+                        .push_name($path)
+                        .push_name(&parameter_data.name)
+                        .push_pointer(&format!("/parameters/{}/schema", ix));
+                    let name: Ident = parameter_data.name.parse()?;
+                    let schema = match &parameter_data.format {
+                        ParameterSchemaOrContent::Schema(schema) => schema,
+                        ParameterSchemaOrContent::Content(_) => {
+                            unsupported!("Parameters with 'content'")
+                        }
+                    };
+                    let (without_default, default) = match schema {
+                        ReferenceOr::Item(schema) if $with_default => split_default(schema)?,
+                        _ => (None, None),
+                    };
+                    let typ = match without_default {
+                        Some(schema) => build_type_recursive(
+                            &ReferenceOr::Item(schema),
+                            path.clone(),
+                            type_index,
+                        )?,
+                        None => build_type_recursive(schema, path.clone(), type_index)?,
+                    };
+                    let meta = $meta
+                        .with_required(parameter_data.required)
+                        .with_default(default);
+                    $params.insert(name, (meta, TypePath::from(path.clone())));
+                    insert_type(type_index, path, typ)?;
+                };
+            }
 
-        match param {
-            Path { .. } => {
-                if !expected_route_params.remove(parameter_data.name.as_str()) {
-                    invalid!("path parameter '{}' not found in path", parameter_data.name)
+            match param {
+                Path { .. } => {
+                    let name = parameter_data.name.as_str();
+                    if !expected_route_params.remove(name) {
+                        if route_path.path_args().any(|arg| arg == name) {
+                            invalid!("Path parameter '{}' is declared twice", name)
+                        }
+                        let hint = route_path
+                            .path_args()
+                            .find(|arg| arg.eq_ignore_ascii_case(name))
+                            .map(|arg| format!(" (did you mean '{}'?)", arg))
+                            .unwrap_or_default();
+                        invalid!(
+                            "Path parameter '{}' does not appear in the path '{}'{}",
+                            name,
+                            route_path,
+                            hint
+                        )
+                    }
+                    if !parameter_data.required {
+                        invalid!(
+                            "Path parameter '{}' must be 'required'",
+                            parameter_data.name
+                        )
+                    }
+                    build_param_type!(path_params, "path", false, FieldMetadata::default());
                 }
-                if !parameter_data.required {
-                    invalid!(
-                        "Path parameter '{}' must be 'required'",
-                        parameter_data.name
-                    )
+                Query {
+                    allow_reserved,
+                    allow_empty_value,
+                    style,
+                    ..
+                } => {
+                    let delimiter = match style {
+                        QueryStyle::SpaceDelimited => Some(' '),
+                        QueryStyle::PipeDelimited => Some('|'),
+                        QueryStyle::Form | QueryStyle::DeepObject => None,
+                    };
+                    let meta = FieldMetadata::default()
+                        .with_allow_empty(allow_empty_value.unwrap_or(false))
+                        .with_allow_reserved(*allow_reserved)
+                        .with_delimiter(delimiter);
+                    build_param_type!(query_params, "query", true, meta);
                 }
-                build_param_type!(path_params, "path");
-            }
-            Query { .. } => {
-                build_param_type!(query_params, "query");
-            }
-            Header { .. } => todo!(),
-            Cookie { .. } => todo!(),
+                Header { .. } => unsupported!("Header parameters"),
+                Cookie { .. } => unsupported!("Cookie parameters"),
+            };
+            Ok(())
         };
+        walk_param().map_err(|e| e.at(&param_pointer))?;
     }
 
     if !expected_route_params.is_empty() {
-        invalid!(
-            "Not enough path parameters specified (Missing: {:?})",
-            expected_route_params
-        )
+        let missing: Vec<_> = expected_route_params
+            .iter()
+            .map(|param| format!("'{}'", param))
+            .collect();
+        return Err(Error::Validation(format!(
+            "Path parameter(s) {} of '{}' missing from the parameters",
+            missing.join(", "),
+            route_path
+        ))
+        .at(&member("parameters")));
     }
 
     macro_rules! type_from_params {
@@ -405,16 +808,16 @@ fn walk_operation(
                 None
             } else {
                 // construct a path param type, if any
-                // This will be used as an Extractor in actix-web
+                // This will be used as an Extractor in actix-web, by the
+                // (public) handlers, so it is public too
                 let typ = TypeInner::Struct(Struct {
                     fields: $params.clone(),
+                    unknown_fields: UnknownFields::Ignore,
                 })
-                .with_meta(TypeMetadata::default().with_visibility(Visibility::Private));
-                let type_path = TypePath::from(path.clone().push($path));
-                let exists = type_index
-                    .insert(type_path.clone(), ReferenceOr::Item(typ))
-                    .is_some();
-                assert!(!exists);
+                .with_meta(TypeMetadata::default());
+                let path = path.clone().push_name($path);
+                let type_path = TypePath::from(path.clone());
+                insert_type(type_index, path, ReferenceOr::Item(typ))?;
                 Some((type_path, $params))
             }
         };
@@ -423,22 +826,134 @@ fn walk_operation(
     let path_params = type_from_params!(path_params, "path");
     let query_params = type_from_params!(query_params, "query");
 
-    let body_path: Option<TypePath> = op
+    let (body_path, body_media_types) = op
         .request_body
         .as_ref()
-        .map::<Result<Option<TypePath>>, _>(|reqbody| {
-            let path = path.clone().push("request_body");
+        .map(|reqbody| {
+            let path = path
+                .clone()
+                .push_name("request_body")
+                .push_pointer("/requestBody");
             let reqbody = dereference(reqbody, &components.request_bodies)?;
-            let path: Option<TypePath> = walk_contents(&reqbody.content, path.clone(), type_index)?;
-            Ok(path)
+            walk_contents(&reqbody.content, path.clone(), type_index)
         })
         .transpose()?
-        .flatten();
+        .unwrap_or_default();
+
+    // A websocket route carries the schema of its messages in the extension
+    let websocket: Option<TypePath> = extensions
+        .get("x-hsr-websocket")
+        .map::<Result<TypePath>, _>(|ext| {
+            if let RawMethod::Get = method {
+            } else {
+                invalid!(
+                    "'x-hsr-websocket' is only valid on GET (path: '{}')",
+                    route_path
+                )
+            }
+            let message = match ext.get("message") {
+                Some(message) => message,
+                None => invalid!(
+                    "'x-hsr-websocket' must specify a 'message' schema (path: '{}')",
+                    route_path
+                ),
+            };
+            let schema: ReferenceOr<Schema> = serde_json::from_value(message.clone())
+                .map_err(|e| Error::Validation(format!("Bad websocket message schema: {}", e)))?;
+            let path = path
+                .clone()
+                .push_name("websocket")
+                .push_pointer("/x-hsr-websocket/message");
+            let typ = build_type_recursive(&schema, path.clone(), type_index)?;
+            insert_type(type_index, path.clone(), typ)?;
+            Ok(path.into())
+        })
+        .transpose()
+        .map_err(|e| e.at(&member("x-hsr-websocket")))?;
+
+    let rate_limit = extensions
+        .get("x-hsr-rate-limit")
+        .map(|ext| RateLimit::from_extension(ext, route_path))
+        .transpose()
+        .map_err(|e| e.at(&member("x-hsr-rate-limit")))?;
+
+    let idempotency = match extensions.get("x-hsr-idempotent") {
+        Some(ext) => Idempotency::from_extension(ext, route_path)
+            .map_err(|e| e.at(&member("x-hsr-idempotent")))?,
+        None => None,
+    };
 
     let method = Method::from_raw(method, body_path)?;
 
+    let cache = extensions
+        .get("x-hsr-cache")
+        .map(|ext| CachePolicy::from_extension(ext, &method, route_path))
+        .transpose()
+        .map_err(|e| e.at(&member("x-hsr-cache")))?;
+
+    let timeout = extensions
+        .get("x-hsr-timeout")
+        .map(|ext| {
+            if websocket.is_some() {
+                invalid!(
+                    "'x-hsr-timeout' doesn't apply to websockets (path: '{}')",
+                    route_path
+                )
+            }
+            Timeout::from_extension(ext, route_path)
+        })
+        .transpose()
+        .map_err(|e| e.at(&member("x-hsr-timeout")))?;
+
+    let audited = match extensions.get("x-hsr-audit") {
+        None => false,
+        Some(serde_json::Value::Bool(audited)) => {
+            if *audited && websocket.is_some() {
+                return Err(Error::Validation(format!(
+                    "'x-hsr-audit' doesn't apply to websockets (path: '{}')",
+                    route_path
+                ))
+                .at(&member("x-hsr-audit")));
+            }
+            *audited
+        }
+        Some(_) => {
+            return Err(Error::Validation(format!(
+                "'x-hsr-audit' must be a boolean (path: '{}')",
+                route_path
+            ))
+            .at(&member("x-hsr-audit")))
+        }
+    };
+
+    let internal = match extensions.get("x-internal") {
+        None => false,
+        Some(serde_json::Value::Bool(internal)) => *internal,
+        Some(_) => {
+            return Err(Error::Validation(format!(
+                "'x-internal' must be a boolean (path: '{}')",
+                route_path
+            ))
+            .at(&member("x-internal")))
+        }
+    };
+
     let responses = walk_responses(&op.responses, path, type_index, components)?;
 
+    let pagination = extensions
+        .get("x-hsr-pagination")
+        .map(|ext| {
+            walk_pagination(
+                ext,
+                route_path,
+                query_params.as_ref().map(|(_, params)| params),
+                &responses,
+                type_index,
+            )
+        })
+        .transpose()
+        .map_err(|e| e.at(&member("x-hsr-pagination")))?;
+
     let route = Route::new(
         op.summary.clone(),
         op.description.clone(),
@@ -447,38 +962,322 @@ fn walk_operation(
         route_path.clone(),
         path_params,
         query_params,
+        body_media_types,
         responses,
+        websocket,
+        rate_limit,
+        pagination,
+        idempotency,
+        cache,
+        timeout,
+        audited,
+        internal,
+        extensions.clone(),
+        op.tags.clone(),
+        op.deprecated,
+        security.to_vec(),
+        examples,
     );
 
     Ok(route)
 }
 
+/// Check an `x-hsr-pagination` declaration against the operation,
+/// and find the types involved
+fn walk_pagination(
+    ext: &serde_json::Value,
+    route_path: &RoutePath,
+    query_params: Option<&Map<Ident, (FieldMetadata, TypePath)>>,
+    responses: &Responses,
+    type_index: &TypeLookup,
+) -> Result<Pagination> {
+    let field = |name: &str| -> Result<Option<Ident>> {
+        match ext.get(name) {
+            None => Ok(None),
+            Some(serde_json::Value::String(s)) => Ok(Some(s.parse()?)),
+            Some(_) => invalid!(
+                "'x-hsr-pagination' field '{}' must be a string (path: '{}')",
+                name,
+                route_path
+            ),
+        }
+    };
+    let (param, items) = match (field("param")?, field("items")?) {
+        (Some(param), Some(items)) => (param, items),
+        _ => invalid!(
+            "'x-hsr-pagination' must name the 'param' and 'items' fields (path: '{}')",
+            route_path
+        ),
+    };
+    let next = field("next")?;
+    let first_page = match ext.get("first") {
+        None => 1,
+        Some(first) => match first.as_i64() {
+            Some(first) => first,
+            None => invalid!("'x-hsr-pagination' 'first' must be an integer"),
+        },
+    };
+
+    // The page is selected by an optional query parameter
+    let (param_meta, param_type) = match query_params.and_then(|params| params.get(&param)) {
+        Some(param) => param,
+        None => invalid!(
+            "Pagination parameter '{}' is not a query parameter (path: '{}')",
+            param,
+            route_path
+        ),
+    };
+    if !param_meta.optional() {
+        invalid!(
+            "Pagination parameter '{}' must not be required or have a default (path: '{}')",
+            param,
+            route_path
+        )
+    }
+
+    // The page is the first successful response with a body
+    let (status, page_path) = match responses
+        .with_codes
+        .iter()
+        .filter(|(code, _)| code.is_success())
+        .find_map(|(code, resp)| resp.type_path.as_ref().map(|path| (*code, path)))
+    {
+        Some(page) => page,
+        None => invalid!(
+            "Paginated operation has no response body (path: '{}')",
+            route_path
+        ),
+    };
+    let (_, page_type) = resolve_type_path(page_path, type_index)?;
+    let page_fields = match &page_type.typ {
+        TypeInner::Struct(strukt) => &strukt.fields,
+        _ => invalid!(
+            "Paginated response must be an object (path: '{}')",
+            route_path
+        ),
+    };
+
+    let (items_path, items_type, items_optional) =
+        pagination_field(page_fields, &items, route_path, type_index)?;
+    let item_type = match items_type.typ {
+        TypeInner::Array(_) => TypePath::from(ApiPath::from(items_path).push("array")),
+        _ => invalid!(
+            "Pagination items field '{}' must be an array (path: '{}')",
+            items,
+            route_path
+        ),
+    };
+
+    let (_, param_type) = resolve_type_path(param_type, type_index)?;
+    match &next {
+        Some(next) => {
+            let (_, next_type, next_optional) =
+                pagination_field(page_fields, next, route_path, type_index)?;
+            if next_type.typ != param_type.typ {
+                invalid!(
+                    "Pagination field '{}' must have the same type as parameter '{}' (path: '{}')",
+                    next,
+                    param,
+                    route_path
+                )
+            }
+            if !next_optional {
+                invalid!(
+                    "Pagination field '{}' must be optional, to mark the last page (path: '{}')",
+                    next,
+                    route_path
+                )
+            }
+        }
+        None => {
+            if let TypeInner::Primitive(Primitive::I64(_)) = param_type.typ {
+            } else {
+                invalid!(
+                    "Pagination parameter '{}' must be an integer page number, \
+                     or 'next' must be given (path: '{}')",
+                    param,
+                    route_path
+                )
+            }
+        }
+    }
+
+    Ok(Pagination {
+        param,
+        items,
+        next,
+        first_page,
+        status,
+        item_type,
+        items_optional,
+    })
+}
+
+/// Take the `default` of a query parameter out of its schema, checking that it is
+/// a value of the schema. The default is applied by the query type when the
+/// parameter is missing, so the type of the parameter itself has none.
+fn split_default(schema: &Schema) -> Result<(Option<Schema>, Option<serde_json::Value>)> {
+    let default = match &schema.schema_data.default {
+        Some(default) => default,
+        None => return Ok((None, None)),
+    };
+    let valid = match &schema.schema_kind {
+        _ if default.is_null() => schema.schema_data.nullable,
+        SchemaKind::Type(ApiType::String(strty)) => match default.as_str() {
+            Some(value) => {
+                strty.enumeration.is_empty() || strty.enumeration.iter().any(|var| var == value)
+            }
+            None => false,
+        },
+        SchemaKind::Type(ApiType::Integer(_)) => default.is_i64(),
+        SchemaKind::Type(ApiType::Number(_)) => default.is_number(),
+        SchemaKind::Type(ApiType::Boolean {}) => default.is_boolean(),
+        SchemaKind::Type(ApiType::Array(_)) => default.is_array(),
+        SchemaKind::Type(ApiType::Object(_)) => default.is_object(),
+        _ => true,
+    };
+    if !valid {
+        invalid!("Default value {} does not match the schema", default)
+    }
+    let mut without_default = schema.clone();
+    without_default.schema_data.default = None;
+    Ok((Some(without_default), Some(default.clone())))
+}
+
+/// Find a field of a page of results, and whether it is optional
+fn pagination_field<'a>(
+    fields: &Map<Ident, (FieldMetadata, TypePath)>,
+    name: &Ident,
+    route_path: &RoutePath,
+    lookup: &'a TypeLookup,
+) -> Result<(TypePath, &'a Type, bool)> {
+    match fields.get(name) {
+        Some((meta, path)) => {
+            let (path, typ) = resolve_type_path(path, lookup)?;
+            let optional = !meta.required || typ.meta.nullable;
+            Ok((path, typ, optional))
+        }
+        None => invalid!(
+            "Paginated response has no field '{}' (path: '{}')",
+            name,
+            route_path
+        ),
+    }
+}
+
+/// Follow references from the type at `path` to its definition
+fn resolve_type_path<'a>(path: &TypePath, lookup: &'a TypeLookup) -> Result<(TypePath, &'a Type)> {
+    match lookup.get(path) {
+        Some(ReferenceOr::Reference { reference }) => {
+            resolve_type_path(&TypePath::from_reference(reference)?, lookup)
+        }
+        Some(ReferenceOr::Item(typ)) => Ok((path.clone(), typ)),
+        None => Err(Error::BadReference(ApiPath::from(path.clone()).to_string())),
+    }
+}
+
 fn walk_contents(
     content: &Map<String, openapiv3::MediaType>,
     path: ApiPath,
     type_index: &mut TypeLookup,
-) -> Result<Option<TypePath>> {
-    if content.len() > 1 {
-        todo!("Can't have more than one content type");
+) -> Result<(Option<TypePath>, Vec<String>)> {
+    // A single JSON body is (de)serialized directly
+    let mut contents = content.iter();
+    match (contents.next(), contents.next()) {
+        (None, _) => return Ok((None, Vec::new())),
+        (Some((contentty, mediaty)), None) if contentty == "application/json" => {
+            return Ok((
+                walk_json_content(contentty, mediaty, path, type_index)?,
+                Vec::new(),
+            ))
+        }
+        _ => {}
     }
-    content
-        .iter()
-        .next()
-        .and_then(|(contentty, mediaty)| {
-            if contentty != "application/json" {
-                todo!("Content other than application/json not supported")
-            }
-            mediaty.schema.as_ref().map(|schema| {
-                let typ = build_type_recursive(schema, path.clone(), type_index)?;
-                assert!(type_index
-                    .insert(TypePath::from(path.clone()), typ)
-                    .is_none());
-                Ok(path.into())
-            })
+
+    // Otherwise it is one of several representations, negotiated by media type
+    let mut representations = Map::new();
+    let mut variants = Set::new();
+    for (contentty, mediaty) in content {
+        let at_content = |e: Error| {
+            e.at(&format!(
+                "{}/content/{}",
+                path.pointer(),
+                escape_pointer_segment(contentty)
+            ))
+        };
+        let variant = media_type_variant(contentty).map_err(at_content)?;
+        if !variants.insert(variant.clone()) {
+            return Err(at_content(Error::Validation(format!(
+                "Media type '{}' clashes with another (both would be variant '{}')",
+                contentty, variant
+            ))));
+        }
+        let json_type = |type_index: &mut TypeLookup| -> Result<TypePath> {
+            let path = path.clone().push_name(&variant.to_string());
+            match walk_json_content(contentty, mediaty, path.clone(), type_index)? {
+                Some(type_path) => Ok(type_path),
+                None => {
+                    // JSON without a schema could be anything
+                    let typ = TypeInner::Any.with_meta(TypeMetadata::default());
+                    insert_type(type_index, path.clone(), ReferenceOr::Item(typ))?;
+                    Ok(path.into())
+                }
+            }
+        };
+        let repr = if contentty.eq_ignore_ascii_case("application/merge-patch+json") {
+            Representation::MergePatch(json_type(type_index)?)
+        } else if contentty.eq_ignore_ascii_case("application/json-patch+json") {
+            // the schema can only describe the standard operations
+            Representation::JsonPatch
+        } else if is_json_media_type(contentty) {
+            Representation::Json(json_type(type_index)?)
+        } else if contentty.starts_with("text/") {
+            Representation::Text
+        } else {
+            Representation::Binary
+        };
+        representations.insert(contentty.clone(), repr);
+    }
+    let media_types = representations.keys().cloned().collect();
+    let typ = TypeInner::Content(representations).with_meta(TypeMetadata::default());
+    insert_type(type_index, path.clone(), ReferenceOr::Item(typ))?;
+    Ok((Some(path.into()), media_types))
+}
+
+fn walk_json_content(
+    contentty: &str,
+    mediaty: &openapiv3::MediaType,
+    path: ApiPath,
+    type_index: &mut TypeLookup,
+) -> Result<Option<TypePath>> {
+    let path = path.push_pointer(&format!(
+        "/content/{}/schema",
+        escape_pointer_segment(contentty)
+    ));
+    mediaty
+        .schema
+        .as_ref()
+        .map(|schema| {
+            let typ = build_type_recursive(schema, path.clone(), type_index)?;
+            insert_type(type_index, path.clone(), typ)?;
+            Ok(path.into())
         })
         .transpose()
 }
 
+pub(crate) fn is_json_media_type(media_type: &str) -> bool {
+    media_type == "application/json" || media_type.ends_with("+json")
+}
+
+/// The name of the variant for a media type, e.g. `TextCsv` for `text/csv`
+pub(crate) fn media_type_variant(media_type: &str) -> Result<TypeName> {
+    match media_type.to_camel_case().as_str() {
+        // `*/*`
+        "" => "Any".parse(),
+        variant => variant.parse(),
+    }
+}
+
 fn walk_responses(
     resps: &openapiv3::Responses,
     path: ApiPath,
@@ -489,28 +1288,46 @@ fn walk_responses(
         .responses
         .iter()
         .map(|(code, resp)| {
+            let pointer = match code {
+                ApiStatusCode::Code(v) => format!("/responses/{}", v),
+                ApiStatusCode::Range(v) => format!("/responses/{}XX", v),
+            };
+            let path = path.clone().push_pointer(&pointer);
             let code = match code {
                 ApiStatusCode::Code(v) => StatusCode::from_u16(*v)
                     .map_err(|_| Error::Validation(format!("Unknown status code '{}'", v))),
-                ApiStatusCode::Range(v) => invalid!("Status code ranges not supported '{}'", v),
-            }?;
+                ApiStatusCode::Range(v) => {
+                    Err(Error::Unsupported(format!("Status code range '{}XX'", v)))
+                }
+            }
+            .map_err(|e| e.at(path.pointer()))?;
             let resp = dereference(resp, &components.responses)?;
             walk_response(
                 resp,
-                path.clone().push(code.as_u16().to_string()),
+                path.push_name(code.as_u16().to_string()),
                 type_index,
+                components,
             )
             .map(|pth| (code, pth))
         })
         .collect::<Result<_>>()?;
 
+    if resps.responses.is_empty() && resps.default.is_none() {
+        return Err(
+            Error::Validation("No responses".into()).at(&format!("{}/responses", path.pointer()))
+        );
+    }
+
     let default = resps
         .default
         .as_ref()
         .map::<Result<Response>, _>(|dflt| {
             let resp = dereference(dflt, &components.responses)?;
-            let path = path.clone().push("default");
-            walk_response(&resp, path, type_index)
+            let path = path
+                .clone()
+                .push_name("default")
+                .push_pointer("/responses/default");
+            walk_response(&resp, path, type_index, components)
         })
         .transpose()?;
 
@@ -524,17 +1341,35 @@ fn walk_response(
     resp: &openapiv3::Response,
     path: ApiPath,
     type_index: &mut TypeLookup,
+    components: &Components,
+) -> Result<Response> {
+    let pointer = path.pointer().to_string();
+    walk_response_at(resp, path, type_index, components).map_err(|e| e.at(&pointer))
+}
+
+fn walk_response_at(
+    resp: &openapiv3::Response,
+    path: ApiPath,
+    type_index: &mut TypeLookup,
+    components: &Components,
 ) -> Result<Response> {
     if !resp.headers.is_empty() {
-        todo!("response headers not supported")
+        unsupported!("Response headers")
     }
-    if !resp.links.is_empty() {
-        todo!("response links not supported")
-    }
-    let type_path = walk_contents(&resp.content, path, type_index)?;
+    let links = resp
+        .links
+        .iter()
+        .map(|(name, link)| {
+            let link = dereference(link, &components.links)?;
+            ResponseLink::from_spec(link).map(|link| (name.clone(), link))
+        })
+        .collect::<Result<_>>()?;
+    let (type_path, media_types) = walk_contents(&resp.content, path, type_index)?;
     Ok(Response {
         type_path,
+        media_types,
         description: resp.description.clone(),
+        links,
     })
 }
 
@@ -544,37 +1379,63 @@ fn walk_response(
 // dereference them later. However, sometimes we will need to
 // recursively build an inner type (e.g. for arrays), at which point the outer-type will
 // need to add the inner-type to the registry to make sure it can use it
-fn build_type_recursive(
-    ref_or_schema: &ReferenceOr<Schema>,
+//
+// The schema is borrowed (whether or not it is boxed) rather than cloned, as
+// cloning a schema means cloning everything nested inside it
+fn build_type_recursive<S: Borrow<Schema>>(
+    ref_or_schema: &ReferenceOr<S>,
+    path: ApiPath,
+    type_index: &mut TypeLookup,
+) -> Result<ReferenceOr<Type>> {
+    let pointer = path.pointer().to_string();
+    let mut typ = match (build_type(ref_or_schema, path, type_index), ref_or_schema) {
+        (Ok(typ), _) => typ,
+        // Still some JSON, even if we can't model it. Whether that's
+        // good enough is decided once the walk is done.
+        (Err(Error::Unsupported(feature)), ReferenceOr::Item(schema)) => {
+            let schema: &Schema = schema.borrow();
+            let meta = (&schema.schema_data).into();
+            ReferenceOr::Item(TypeInner::Exotic(feature).with_meta(meta))
+        }
+        (Err(e), _) => return Err(e.at(&pointer)),
+    };
+    if let ReferenceOr::Item(typ) = &mut typ {
+        typ.pointer = pointer;
+    }
+    Ok(typ)
+}
+
+fn build_type<S: Borrow<Schema>>(
+    ref_or_schema: &ReferenceOr<S>,
     path: ApiPath,
     type_index: &mut TypeLookup,
 ) -> Result<ReferenceOr<Type>> {
-    let schema = match ref_or_schema {
+    let schema: &Schema = match ref_or_schema {
         ReferenceOr::Reference { reference } => {
             return Ok(ReferenceOr::Reference {
                 reference: reference.clone(),
             })
         }
-        ReferenceOr::Item(item) => item,
+        ReferenceOr::Item(item) => item.borrow(),
     };
-    let meta = schema.schema_data.clone();
+    let meta = &schema.schema_data;
 
     if let Some(_) = meta.default {
-        todo!("Default values not supported (location: '{}')", path)
+        unsupported!("Default values")
     }
 
     if let Some(_) = meta.discriminator {
-        todo!("Discriminator values not supported (location: '{}')", path)
+        unsupported!("Discriminators")
     }
 
     let ty = match &schema.schema_kind {
         SchemaKind::Type(ty) => ty,
         SchemaKind::Any(obj) => {
-            if let Some(_) = obj.additional_properties() {
-                todo!("Additional properties not supported (location: '{}')", path)
-            }
+            check_additional_properties(obj)?;
 
-            let inner = if obj.properties.is_empty() {
+            let inner = if let Some(map) = build_map_type(obj, &path, type_index)? {
+                map
+            } else if obj.properties.is_empty() {
                 TypeInner::Any
             } else {
                 TypeInner::Struct(Struct::from_objlike_recursive(obj, path, type_index)?)
@@ -586,7 +1447,10 @@ fn build_type_recursive(
                 .iter()
                 .enumerate()
                 .map(|(ix, schema)| {
-                    let path = path.clone().push(format!("AllOf_{}", ix));
+                    let path = path
+                        .clone()
+                        .push_name(format!("AllOf_{}", ix))
+                        .push_pointer(&format!("/allOf/{}", ix));
                     // Note that we do NOT automatically add the sub-types to
                     // the registry as they may not be needed
                     build_type_recursive(schema, path, type_index)
@@ -600,22 +1464,31 @@ fn build_type_recursive(
             ));
         }
         SchemaKind::AnyOf { any_of: schemas } | SchemaKind::OneOf { one_of: schemas } => {
+            let keyword = match &schema.schema_kind {
+                SchemaKind::AnyOf { .. } => "anyOf",
+                _ => "oneOf",
+            };
+            if schemas.is_empty() {
+                invalid!("Empty '{}'", keyword)
+            }
             let oneof_types = schemas
                 .iter()
                 .enumerate()
                 .map(|(ix, schema)| {
-                    let path = path.clone().push(format!("OneOf_{}", ix));
+                    let path = path
+                        .clone()
+                        .push_name(format!("OneOf_{}", ix))
+                        .push_pointer(&format!("/{}/{}", keyword, ix));
                     let innerty = build_type_recursive(schema, path.clone(), type_index)?;
-                    let type_path = TypePath::from(path);
-                    assert!(type_index
-                        .insert(type_path.clone(), innerty.clone())
-                        .is_none());
-                    Ok(type_path)
+                    insert_type(type_index, path.clone(), innerty)?;
+                    Ok(TypePath::from(path))
                 })
                 .collect::<Result<Vec<_>>>()?;
-            return Ok(ReferenceOr::Item(
-                TypeInner::OneOf(oneof_types).with_meta(meta.into()),
-            ));
+            let inner = match &schema.schema_kind {
+                SchemaKind::AnyOf { .. } => TypeInner::AnyOf(oneof_types),
+                _ => TypeInner::OneOf(oneof_types),
+            };
+            return Ok(ReferenceOr::Item(inner.with_meta(meta.into())));
         }
     };
     let typ = match ty {
@@ -624,11 +1497,11 @@ fn build_type_recursive(
         // handle the primitives in a straightforward way
         ApiType::String(strty) => {
             if !strty.format.is_empty() {
-                todo!("String formats not supported (location: '{}')", path)
+                unsupported!("String formats")
             }
 
             if let Some(_) = strty.pattern {
-                todo!("String patterns not supported (location: '{}')", path)
+                unsupported!("String patterns")
             }
 
             if !strty.enumeration.is_empty() {
@@ -637,25 +1510,35 @@ fn build_type_recursive(
                 TypeInner::Primitive(Primitive::String)
             }
         }
-        ApiType::Number(_) => TypeInner::Primitive(Primitive::F64),
-        ApiType::Integer(_) => TypeInner::Primitive(Primitive::I64),
+        ApiType::Number(num) => TypeInner::Primitive(Primitive::F64(Bounds {
+            min: num.minimum,
+            max: num.maximum,
+        })),
+        ApiType::Integer(int) => {
+            // Exclusive bounds can be made inclusive for integers
+            let min = int
+                .minimum
+                .map(|min| if int.exclusive_minimum { min + 1 } else { min });
+            let max = int
+                .maximum
+                .map(|max| if int.exclusive_maximum { max - 1 } else { max });
+            TypeInner::Primitive(Primitive::I64(Bounds { min, max }))
+        }
         ApiType::Boolean {} => TypeInner::Primitive(Primitive::Bool),
         ApiType::Array(arr) => {
             // build the inner-type
-            let items = arr.items.clone().unbox();
-            let path = path.clone().push("array");
-            let innerty = build_type_recursive(&items, path.clone(), type_index)?;
+            let path = path.clone().push_name("array").push_pointer("/items");
+            let innerty = build_type_recursive(&arr.items, path.clone(), type_index)?;
             // add inner type to the registry
-            assert!(type_index
-                .insert(TypePath::from(path), innerty.clone())
-                .is_none());
+            insert_type(type_index, path, innerty.clone())?;
             TypeInner::Array(Box::new(innerty))
         }
         ApiType::Object(obj) => {
-            if let Some(_) = obj.additional_properties() {
-                todo!("Additional properties not supported")
+            check_additional_properties(obj)?;
+            match build_map_type(obj, &path, type_index)? {
+                Some(map) => map,
+                None => TypeInner::Struct(Struct::from_objlike_recursive(obj, path, type_index)?),
             }
-            TypeInner::Struct(Struct::from_objlike_recursive(obj, path, type_index)?)
         }
     };
     Ok(ReferenceOr::Item(typ.with_meta(meta.into())))
@@ -663,27 +1546,417 @@ fn build_type_recursive(
 
 /// Generate code that defines a `struct` or `type` alias for each object found
 /// in the OpenAPI definition
-pub(crate) fn generate_rust_types(types: &TypeLookup) -> Result<TokenStream> {
-    let mut tokens = TokenStream::new();
-    for (typepath, typ) in types {
-        let def = generate_rust_type(typepath, typ, types)?;
-        tokens.extend(def);
+pub(crate) fn generate_rust_types(
+    types: &TypeLookup,
+    opts: &GenerationOptions,
+) -> Result<TokenStream> {
+    generate_rust_types_where(types, opts, &|_| true)
+}
+
+/// Generate the types for which `include` is true. References to the
+/// other types are still resolved, by name.
+pub(crate) fn generate_rust_types_where(
+    types: &TypeLookup,
+    opts: &GenerationOptions,
+    include: &dyn Fn(&TypePath) -> bool,
+) -> Result<TokenStream> {
+    let duplicates = duplicate_types(types, include);
+    let included: Vec<_> = types
+        .iter()
+        .filter(|(typepath, _)| include(typepath))
+        .collect();
+    let mut tokens = crate::generate_each(&included, |&(typepath, typ)| {
+        match (duplicates.get(typepath), typ) {
+            (Some(original), ReferenceOr::Item(typ)) => {
+                let name = typepath.canonicalize()?;
+                let original = original.canonicalize()?;
+                let visibility = typ.meta.visibility;
+                Ok(quote! {
+                    // Same shape as the original, so we share its definition
+                    #visibility type #name = #original;
+                })
+            }
+            _ => generate_rust_type(typepath, typ, types, opts),
+        }
+    })?;
+    if opts.arbitrary {
+        tokens.extend(generate_roundtrip_tests(types, include)?);
     }
     Ok(tokens)
 }
 
+/// Find the inline types which have the same structure as a type defined before
+/// them, mapped to that type. Only one definition is generated for each shape,
+/// the duplicates are aliases for it. Component schemas are named in the spec,
+/// so they are always defined, but inline types may be aliases for them.
+pub(crate) fn duplicate_types(
+    types: &TypeLookup,
+    include: &dyn Fn(&TypePath) -> bool,
+) -> BTreeMap<TypePath, TypePath> {
+    let mut shapes = Shapes::new(types);
+    let mut originals: Map<usize, &TypePath> = Map::new();
+    let mut duplicates = BTreeMap::new();
+    for (type_path, typ) in types.iter().filter(|(type_path, _)| include(type_path)) {
+        // only these generate a definition of their own, the rest are aliases anyway
+        let defines = match typ {
+            ReferenceOr::Item(typ) => matches!(
+                typ.typ,
+                TypeInner::Struct(_)
+                    | TypeInner::StringEnum(_)
+                    | TypeInner::IntegerEnum(_)
+                    | TypeInner::OneOf(_)
+                    | TypeInner::AnyOf(_)
+                    | TypeInner::AllOf(_)
+            ),
+            ReferenceOr::Reference { .. } => false,
+        };
+        if !defines {
+            continue;
+        }
+        let shape = shapes.of_path(type_path);
+        match originals.get(&shape) {
+            Some(original) if !is_component_schema(type_path) => {
+                duplicates.insert(type_path.clone(), (*original).clone());
+            }
+            Some(_) => {}
+            None => {
+                originals.insert(shape, type_path);
+            }
+        }
+    }
+    duplicates
+}
+
+fn is_component_schema(type_path: &TypePath) -> bool {
+    match &type_path.0[..] {
+        [components, schemas, _] => components == "components" && schemas == "schemas",
+        _ => false,
+    }
+}
+
+/// Hash-consing of types: types with the same structure (including their
+/// metadata) are given the same id. Ids are memoized by path, so each type
+/// is only visited once however often it is used.
+struct Shapes<'a> {
+    lookup: &'a TypeLookup,
+    ids: Map<String, usize>,
+    by_path: BTreeMap<TypePath, usize>,
+}
+
+impl<'a> Shapes<'a> {
+    fn new(lookup: &'a TypeLookup) -> Self {
+        Self {
+            lookup,
+            ids: Map::new(),
+            by_path: BTreeMap::new(),
+        }
+    }
+
+    fn intern(&mut self, key: String) -> usize {
+        let next = self.ids.len();
+        *self.ids.entry(key).or_insert(next)
+    }
+
+    fn of_path(&mut self, type_path: &TypePath) -> usize {
+        if let Some(id) = self.by_path.get(type_path) {
+            return *id;
+        }
+        let lookup = self.lookup;
+        let id = match lookup.get(type_path) {
+            Some(typ) => self.of_type(typ),
+            // never the same as anything else
+            None => self.intern(format!("missing {}", ApiPath::from(type_path.clone()))),
+        };
+        self.by_path.insert(type_path.clone(), id);
+        id
+    }
+
+    fn of_type(&mut self, typ: &ReferenceOr<Type>) -> usize {
+        use TypeInner as T;
+        // References are followed by name, so recursive types are fine
+        let typ = match typ {
+            ReferenceOr::Reference { reference } => {
+                return self.intern(format!("ref {}", reference))
+            }
+            ReferenceOr::Item(typ) => typ,
+        };
+        let inner = match &typ.typ {
+            T::Primitive(prim) => format!("{:?}", prim),
+            T::StringEnum(variants) => format!("enum {:?}", variants),
+            T::IntegerEnum(values) => format!("integer enum {:?}", values),
+            T::Array(inner) => format!("array {}", self.of_type(inner)),
+            T::Map(inner) => format!("map {}", self.of_type(inner)),
+            T::Any | T::Exotic(_) => "any".into(),
+            T::AllOf(parts) => {
+                let parts: Vec<_> = parts.iter().map(|part| self.of_type(part)).collect();
+                format!("allOf {:?}", parts)
+            }
+            T::OneOf(variants) => {
+                let variants: Vec<_> = variants.iter().map(|var| self.of_path(var)).collect();
+                format!("oneOf {:?}", variants)
+            }
+            T::AnyOf(alternatives) => {
+                let alternatives: Vec<_> =
+                    alternatives.iter().map(|alt| self.of_path(alt)).collect();
+                format!("anyOf {:?}", alternatives)
+            }
+            T::Struct(strukt) => {
+                let fields: Vec<_> = strukt
+                    .fields
+                    .iter()
+                    .map(|(name, (meta, field_path))| (name, meta, self.of_path(field_path)))
+                    .collect();
+                format!("struct {:?} {:?}", strukt.unknown_fields, fields)
+            }
+            T::Content(reprs) => format!("content {:?}", reprs),
+        };
+        self.intern(format!("{:?} {}", typ.meta, inner))
+    }
+}
+
+/// Generate property tests checking that every component type
+/// survives a round-trip through serde
+fn generate_roundtrip_tests(
+    types: &TypeLookup,
+    include: &dyn Fn(&TypePath) -> bool,
+) -> Result<TokenStream> {
+    let tests: Vec<_> = types
+        .iter()
+        .filter(|(type_path, _)| include(type_path))
+        .filter(|(type_path, typ)| {
+            let parts: Vec<&str> = type_path.0.iter().map(String::as_str).collect();
+            // nor can it make a map, see `arbitrary_field_attr`
+            let is_map = matches!(
+                typ,
+                ReferenceOr::Item(Type {
+                    typ: TypeInner::Map(_),
+                    ..
+                })
+            );
+            match &parts[..] {
+                ["components", "schemas", _] => !is_map && !contains_untagged(typ, types, 0),
+                _ => false,
+            }
+        })
+        .map(|(type_path, _)| {
+            let name = type_path.canonicalize()?;
+            let test_name = crate::ident(format!("roundtrip_{}", name.to_snake_case()));
+            Ok(quote! {
+                #[test]
+                fn #test_name(value: #name) {
+                    let json = hsr::serde_json::to_string(&value).unwrap();
+                    let back: #name = hsr::serde_json::from_str(&json).unwrap();
+                    proptest::prop_assert_eq!(value, back);
+                }
+            })
+        })
+        .collect::<Result<_>>()?;
+    Ok(quote! {
+        #[cfg(test)]
+        mod arbitrary_roundtrip {
+            use super::*;
+
+            proptest::proptest! {
+                #(#tests)*
+            }
+        }
+    })
+}
+
+/// Untagged enums do not necessarily survive a round-trip (the first
+/// matching variant wins) so we skip types which contain them
+fn contains_untagged(typ: &ReferenceOr<Type>, lookup: &TypeLookup, depth: usize) -> bool {
+    // Assume the worst for deeply nested (probably recursive) types
+    if depth > 16 {
+        return true;
+    }
+    let typ = match lookup_type_recursive(typ, lookup) {
+        Ok(typ) => typ,
+        Err(_) => return true,
+    };
+    let check_path = |path: &TypePath| match lookup.get(path) {
+        Some(inner) => contains_untagged(inner, lookup, depth + 1),
+        None => true,
+    };
+    match &typ.typ {
+        TypeInner::OneOf(_) | TypeInner::AnyOf(_) | TypeInner::Content(_) => true,
+        TypeInner::Primitive(_)
+        | TypeInner::StringEnum(_)
+        | TypeInner::IntegerEnum(_)
+        | TypeInner::Any
+        | TypeInner::Exotic(_) => false,
+        TypeInner::Array(inner) | TypeInner::Map(inner) => {
+            contains_untagged(inner, lookup, depth + 1)
+        }
+        TypeInner::AllOf(parts) => parts
+            .iter()
+            .any(|part| contains_untagged(part, lookup, depth + 1)),
+        TypeInner::Struct(strukt) => strukt
+            .fields
+            .values()
+            .any(|(_, field_path)| check_path(field_path)),
+    }
+}
+
+/// Tell `proptest` how to generate values for fields where the
+/// default strategy would be wrong
+fn arbitrary_field_attr(field_type: &Type, required: bool) -> Option<TokenStream> {
+    let optional = field_type.meta.nullable || !required;
+    let strategy = match &field_type.typ {
+        TypeInner::Primitive(Primitive::I64(Bounds { min, max })) => {
+            if min.is_none() && max.is_none() {
+                return None;
+            }
+            let min = min.map(|v| v.to_string()).unwrap_or("std::i64::MIN".into());
+            let max = max.map(|v| v.to_string()).unwrap_or("std::i64::MAX".into());
+            format!("({})..=({})", min, max)
+        }
+        // NaN and infinity do not survive a trip through JSON
+        TypeInner::Primitive(Primitive::F64(Bounds { min, max })) => match (min, max) {
+            (Some(min), Some(max)) => format!("({:?}f64)..=({:?}f64)", min, max),
+            _ => "proptest::num::f64::NORMAL".into(),
+        },
+        TypeInner::Any | TypeInner::Exotic(_) => {
+            // Note that JsonValue is not wrapped in Option when it is nullable
+            let value = if !required && !field_type.meta.nullable {
+                "None"
+            } else {
+                "JsonValue::Null"
+            };
+            return Some(quote! {
+                #[cfg_attr(test, proptest(value = #value))]
+            });
+        }
+        // proptest can't make an `IndexMap`, and often not the values
+        TypeInner::Map(_) => {
+            return Some(quote! {
+                #[cfg_attr(test, proptest(value = "Default::default()"))]
+            });
+        }
+        _ => return None,
+    };
+    let strategy = if optional {
+        format!("proptest::option::of({})", strategy)
+    } else {
+        strategy
+    };
+    Some(quote! {
+        #[cfg_attr(test, proptest(strategy = #strategy))]
+    })
+}
+
+/// An expression making a random value of the type at `type_path` (from `rng`).
+/// The `Sample` impl of the type would do, but knows nothing of the bounds of
+/// numbers, so numbers (also those in arrays and maps) are made here.
+pub(crate) fn sample_expr(type_path: &TypePath, lookup: &TypeLookup) -> Result<TokenStream> {
+    let ref_or = lookup
+        .get(type_path)
+        .ok_or_else(|| Error::BadReference(ApiPath::from(type_path.clone()).to_string()))?;
+    let typ = lookup_type_recursive(ref_or, lookup)?;
+    let bound = |bound: Option<TokenStream>| match bound {
+        Some(bound) => quote! { Some(#bound) },
+        None => quote! { None },
+    };
+    let value = match &typ.typ {
+        TypeInner::Primitive(Primitive::I64(Bounds { min, max })) => {
+            let min = bound(min.map(|v| quote! { #v }));
+            let max = bound(max.map(|v| quote! { #v }));
+            quote! { rng.int_in(#min, #max) }
+        }
+        TypeInner::Primitive(Primitive::F64(Bounds { min, max })) => {
+            let min = bound(min.map(|v| quote! { #v }));
+            let max = bound(max.map(|v| quote! { #v }));
+            quote! { rng.float_in(#min, #max) }
+        }
+        TypeInner::Array(_) => {
+            let inner_path = TypePath::from(ApiPath::from(type_path.clone()).push("array"));
+            let inner = sample_expr(&inner_path, lookup)?;
+            quote! { rng.many(|rng| #inner) }
+        }
+        TypeInner::Map(_) => {
+            let inner_path = TypePath::from(ApiPath::from(type_path.clone()).push("map"));
+            let inner = sample_expr(&inner_path, lookup)?;
+            quote! { rng.many(|rng| (rng.string(), #inner)).into_iter().collect() }
+        }
+        _ => {
+            let name = type_path.canonicalize()?;
+            return Ok(quote! { <#name as hsr::fake::Sample>::sample(rng) });
+        }
+    };
+    if typ.meta.nullable {
+        Ok(quote! { rng.maybe(|rng| #value) })
+    } else {
+        Ok(value)
+    }
+}
+
+/// Implement `hsr::fake::Sample` for an enum, choosing any of its variants
+fn enum_sample_impl(
+    name: &TypeName,
+    variants: &[Variant],
+    lookup: &TypeLookup,
+    opts: &GenerationOptions,
+) -> Result<Option<TokenStream>> {
+    if !opts.samples {
+        return Ok(None);
+    }
+    let values = variants
+        .iter()
+        .map(|variant| {
+            let var = &variant.name;
+            match &variant.type_path {
+                Some(path) => {
+                    let value = sample_expr(path, lookup)?;
+                    Ok(quote! { Self::#var(#value) })
+                }
+                None => Ok(quote! { Self::#var }),
+            }
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let (last, values) = match values.split_last() {
+        Some(split) => split,
+        None => invalid!("Enum '{}' has no variants", name),
+    };
+    let body = if values.is_empty() {
+        quote! { #last }
+    } else {
+        let ixs = (0..values.len()).map(proc_macro2::Literal::usize_unsuffixed);
+        let len = proc_macro2::Literal::usize_unsuffixed(variants.len());
+        quote! {
+            match rng.below(#len) {
+                #(#ixs => #values,)*
+                _ => #last,
+            }
+        }
+    };
+    // a single unit variant needs no randomness
+    let rng = if values.is_empty() && variants[0].type_path.is_none() {
+        quote! { _rng }
+    } else {
+        quote! { rng }
+    };
+    Ok(Some(quote! {
+        impl hsr::fake::Sample for #name {
+            fn sample(#rng: &mut hsr::fake::Rng) -> Self {
+                #body
+            }
+        }
+    }))
+}
+
 /// Generate code that defines a `struct` or `type` alias for each object found
 /// in the OpenAPI definition
 fn generate_rust_type(
     type_path: &TypePath,
     typ: &ReferenceOr<Type>,
     lookup: &TypeLookup,
+    opts: &GenerationOptions,
 ) -> Result<TokenStream> {
     debug!("generate: {}", ApiPath::from(type_path.clone()));
-    let name = type_path.canonicalize();
+    let name = type_path.canonicalize()?;
     let def = match typ {
         ReferenceOr::Reference { reference } => {
-            let refs = TypePath::from_reference(reference)?.canonicalize();
+            let refs = TypePath::from_reference(reference)?.canonicalize()?;
             quote! {
                 // Simply alias this type to the referred type
                 type #name = #refs;
@@ -693,34 +1966,98 @@ fn generate_rust_type(
             use TypeInner as T;
             match &typ.typ {
                 T::Any => {
-                    let descr = typ.meta.description();
+                    let descr = typ.meta.description_doc();
                     quote! {
                         #descr
                         // could be 'any' valid json
                         type #name = JsonValue;
                     }
                 }
+                T::Exotic(feature) => {
+                    let descr = typ.meta.description_doc();
+                    let note = format!(" {} not supported, so could be any valid json", feature);
+                    quote! {
+                        #descr
+                        #[doc = #note]
+                        type #name = JsonValue;
+                    }
+                }
+                T::AllOf(parts)
+                    if opts.flatten_all_of
+                        && parts
+                            .iter()
+                            .any(|part| matches!(part, ReferenceOr::Reference { .. })) =>
+                {
+                    // Embed the referred types, rather than copying their fields
+                    let mut refs = Vec::new();
+                    let mut inline = Vec::new();
+                    for part in parts {
+                        match part {
+                            ReferenceOr::Reference { reference } => refs.push(reference),
+                            ReferenceOr::Item(_) => inline.push(part.clone()),
+                        }
+                    }
+                    let strukt = combine_types(&inline, lookup, type_path)?;
+                    let flattened = refs
+                        .into_iter()
+                        .map(|reference| {
+                            let ty = TypePath::from_reference(reference)?.canonicalize()?;
+                            let field: Ident = ty.to_snake_case().parse()?;
+                            if strukt.fields.contains_key(&field) {
+                                invalid!(
+                                    "Field '{}' clashes with the flattened '{}' (location: '{}')",
+                                    field,
+                                    reference,
+                                    ApiPath::from(type_path.clone())
+                                );
+                            }
+                            Ok((field, ty))
+                        })
+                        .collect::<Result<Vec<_>>>()?;
+                    generate_struct_def(
+                        &strukt, &name, type_path, &typ.meta, &flattened, lookup, opts,
+                    )?
+                }
                 T::AllOf(parts) => {
-                    let strukt = combine_types(parts, lookup)?;
+                    let strukt = combine_types(parts, lookup, type_path)?;
                     let typ =
                         ReferenceOr::Item(TypeInner::Struct(strukt).with_meta(typ.meta.clone()));
                     // Defer to struct impl
-                    generate_rust_type(type_path, &typ, lookup)?
+                    generate_rust_type(type_path, &typ, lookup, opts)?
                 }
                 T::OneOf(variants) => {
                     let variants: Vec<_> = variants
                         .iter()
                         .enumerate()
                         .map(|(ix, var)| {
-                            Variant::new(format!("V{}", ix + 1).parse().unwrap())
-                                .type_path(Some(var.clone()))
+                            Ok(Variant::new(format!("V{}", ix + 1).parse()?)
+                                .type_path(Some(var.clone())))
                         })
+                        .collect::<Result<_>>()?;
+                    let def = generate_enum_def(&name, &typ.meta, &variants, None, true, opts)?;
+                    let sample_impl = enum_sample_impl(&name, &variants, lookup, opts)?;
+                    quote! {
+                        #def
+                        #sample_impl
+                    }
+                }
+                T::AnyOf(alternatives) => {
+                    let variants: Vec<_> = any_of_variant_names(alternatives, lookup)
+                        .into_iter()
+                        .zip(alternatives)
+                        .map(|(var, alt)| Variant::new(var).type_path(Some(alt.clone())))
                         .collect();
-                    generate_enum_def(&name, &typ.meta, &variants, None, true)
+                    // serde tries the variants in order, so the first match wins
+                    let def = generate_enum_def(&name, &typ.meta, &variants, None, true, opts)?;
+                    let sample_impl = enum_sample_impl(&name, &variants, lookup, opts)?;
+                    quote! {
+                        #def
+                        #sample_impl
+                    }
                 }
                 T::Primitive(p) => {
                     let id = crate::ident(p);
-                    let descr = typ.meta.description();
+                    let descr = typ.meta.description_doc();
                     let ty = if typ.meta.nullable {
                         quote! {
                             Option<#id>
@@ -744,14 +2081,44 @@ fn generate_rust_type(
                             Ok(var)
                         })
                         .collect::<Result<_>>()?;
-                    generate_enum_def(&name, &typ.meta, &variants, None, false)
+                    let (enum_name, alias) = nullable_enum_name(&name, type_path, &typ.meta)?;
+                    let def = if opts.case_insensitive_enums || typ.meta.case_insensitive {
+                        generate_case_insensitive_enum_def(&enum_name, &typ.meta, &variants, opts)?
+                    } else {
+                        generate_enum_def(&enum_name, &typ.meta, &variants, None, false, opts)?
+                    };
+                    let sample_impl = enum_sample_impl(&enum_name, &variants, lookup, opts)?;
+                    quote! {
+                        #def
+                        #sample_impl
+                        #alias
+                    }
+                }
+                T::IntegerEnum(values) => {
+                    let (enum_name, alias) = nullable_enum_name(&name, type_path, &typ.meta)?;
+                    let def = generate_integer_enum_def(&enum_name, &typ.meta, values, opts)?;
+                    let variants = values
+                        .iter()
+                        .map(|value| Ok(Variant::new(integer_variant_name(*value)?)))
+                        .collect::<Result<Vec<_>>>()?;
+                    let sample_impl = enum_sample_impl(&enum_name, &variants, lookup, opts)?;
+                    quote! {
+                        #def
+                        #sample_impl
+                        #alias
+                    }
                 }
                 T::Array(_) => {
                     let path = ApiPath::from(type_path.clone());
                     let inner_path = TypePath::from(path.push("array"));
-                    assert!(lookup.contains_key(&inner_path));
-                    let inner_path = inner_path.canonicalize();
-                    let descr = typ.meta.description();
+                    if !lookup.contains_key(&inner_path) {
+                        return Err(Error::BadCodegen(format!(
+                            "Type '{}' is missing",
+                            ApiPath::from(inner_path)
+                        )));
+                    }
+                    let inner_path = inner_path.canonicalize()?;
+                    let descr = typ.meta.description_doc();
                     if typ.meta.nullable {
                         quote! {
                             #descr
@@ -764,9 +2131,34 @@ fn generate_rust_type(
                         }
                     }
                 }
+                T::Map(_) => {
+                    let path = ApiPath::from(type_path.clone());
+                    let inner_path = TypePath::from(path.push("map"));
+                    if !lookup.contains_key(&inner_path) {
+                        return Err(Error::BadCodegen(format!(
+                            "Type '{}' is missing",
+                            ApiPath::from(inner_path)
+                        )));
+                    }
+                    let inner_path = inner_path.canonicalize()?;
+                    let map = opts.map_type.tokens();
+                    let descr = typ.meta.description_doc();
+                    if typ.meta.nullable {
+                        quote! {
+                            #descr
+                            type #name = Option<#map<String, #inner_path>>;
+                        }
+                    } else {
+                        quote! {
+                            #descr
+                            type #name = #map<String, #inner_path>;
+                        }
+                    }
+                }
                 T::Struct(strukt) => {
-                    generate_struct_def(strukt, &name, type_path, &typ.meta, lookup)?
+                    generate_struct_def(strukt, &name, type_path, &typ.meta, &[], lookup, opts)?
                 }
+                T::Content(reprs) => generate_content_def(reprs, &name, &typ.meta)?,
             }
         }
     };
@@ -778,15 +2170,30 @@ fn generate_struct_def(
     name: &TypeName,
     type_path: &TypePath,
     meta: &TypeMetadata,
+    flattened: &[(Ident, TypeName)],
     lookup: &TypeLookup,
+    opts: &GenerationOptions,
 ) -> Result<TokenStream> {
     let fieldnames: Vec<_> = strukt.fields.iter().map(|(field, _)| field).collect();
+    let flattened_names: Vec<_> = flattened.iter().map(|(field, _)| field).collect();
+    // fields holding other structs, whose fields are serialized as if they were ours
+    let mut flattened: Vec<_> = flattened
+        .iter()
+        .map(|(field, ty)| {
+            quote! {
+                #[serde(flatten)]
+                pub #field: #ty
+            }
+        })
+        .collect();
     let visibility = meta.visibility;
-    let descr = meta.description();
+    let descr = meta.description_doc();
+    let mut field_attrs: Vec<TokenStream> = Vec::new();
+    let mut helper_fns: Vec<TokenStream> = Vec::new();
     let fields: Vec<TokenStream> = strukt
         .fields
         .iter()
-        .map(|(_field, (meta, field_type_path))| {
+        .map(|(field, (meta, field_type_path))| {
             // Tricky bit. The field may be 'not required', from POV of the struct
             // but also the type itself may be nullable. This is supposed to represent
             // how in javascript an object key may be 'missing', or it may be 'null'
@@ -800,16 +2207,233 @@ fn generate_struct_def(
             let field_type = lookup_type_recursive(ref_or, lookup)?; // this one can
             let required = meta.required;
             let nullable = field_type.meta.nullable;
-            let field_type_name = field_type_path.canonicalize();
-            let def = if nullable || (required && !nullable) {
-                quote! {#field_type_name}
+            let field_type_name = field_type_path.canonicalize()?;
+            // Fields with a default take it when missing, and are left out
+            // when they hold it. Serde wants functions for both.
+            let default_fn = format!("{}_{}_default", name, field).to_snake_case();
+            let defaulted = meta.default.as_ref().map(|value| {
+                let json = value.to_string();
+                let is_default_fn = format!("is_{}", default_fn);
+                let (default_id, is_default_id) =
+                    (crate::ident(&default_fn), crate::ident(&is_default_fn));
+                helper_fns.push(quote! {
+                    fn #default_id() -> #field_type_name {
+                        // checked against the schema during codegen
+                        hsr::serde_json::from_str(#json).unwrap()
+                    }
+                    fn #is_default_id(value: &#field_type_name) -> bool {
+                        *value == #default_id()
+                    }
+                });
+                quote! { #[serde(default = #default_fn, skip_serializing_if = #is_default_fn)] }
+            });
+            // Fields which aren't required may be missing, and are left out
+            // (rather than sent as `null`) when they are not nullable
+            let serde_attr = match (required, nullable) {
+                (true, _) => None,
+                (false, _) if defaulted.is_some() => defaulted,
+                (false, true) => Some(quote! { #[serde(default)] }),
+                (false, false) => Some(quote! {
+                    #[serde(default, skip_serializing_if = "Option::is_none")]
+                }),
+            };
+            let arbitrary_attr = if opts.arbitrary {
+                arbitrary_field_attr(field_type, !meta.optional())
             } else {
+                None
+            };
+            let is_option = nullable || meta.optional();
+            let def = if is_option && !nullable {
                 quote! {Option<#field_type_name>}
+            } else {
+                quote! {#field_type_name}
+            };
+            // Delimited arrays are (de)serialized as a single string
+            let delimited_attr = match (meta.delimiter, &field_type.typ) {
+                (None, _) => None,
+                (Some(delimiter), TypeInner::Array(_)) => {
+                    let delimited_fn = format!("{}_{}_delimited", name, field).to_snake_case();
+                    let de_fn = format!("deserialize_{}", delimited_fn);
+                    let ser_fn = format!("serialize_{}", delimited_fn);
+                    let (de_id, ser_id) = (crate::ident(&de_fn), crate::ident(&ser_fn));
+                    let (de_body, ser_body) = if is_option {
+                        (
+                            quote! { hsr::query::deserialize_delimited(de, #delimiter).map(Some) },
+                            quote! {
+                                match value {
+                                    Some(items) => hsr::query::serialize_delimited(items, #delimiter, ser),
+                                    None => hsr::serde::Serializer::serialize_none(ser),
+                                }
+                            },
+                        )
+                    } else {
+                        (
+                            quote! { hsr::query::deserialize_delimited(de, #delimiter) },
+                            quote! { hsr::query::serialize_delimited(value, #delimiter, ser) },
+                        )
+                    };
+                    helper_fns.push(quote! {
+                        fn #de_id<'de, D: hsr::serde::Deserializer<'de>>(
+                            de: D,
+                        ) -> Result<#def, D::Error> {
+                            #de_body
+                        }
+                        fn #ser_id<S: hsr::serde::Serializer>(
+                            value: &#def,
+                            ser: S,
+                        ) -> Result<S::Ok, S::Error> {
+                            #ser_body
+                        }
+                    });
+                    Some(quote! { #[serde(deserialize_with = #de_fn, serialize_with = #ser_fn)] })
+                }
+                (Some(_), _) => invalid!(
+                    "Only array query parameters can be delimited ('{}' of '{}')",
+                    field,
+                    name
+                ),
+            };
+            // An empty value of a boolean is `true`, of a string is an empty string,
+            // of a delimited array is an empty array. For anything else the field
+            // is taken to be missing.
+            let empty_attr = match &field_type.typ {
+                TypeInner::Primitive(Primitive::String)
+                | TypeInner::Any
+                | TypeInner::Exotic(_)
+                | TypeInner::Array(_)
+                | TypeInner::Map(_) => None,
+                _ if !meta.allow_empty => None,
+                typ => {
+                    let empty = match (typ, is_option, &meta.default) {
+                        (TypeInner::Primitive(Primitive::Bool), true, _) => {
+                            quote! { Some(Some(true)) }
+                        }
+                        (TypeInner::Primitive(Primitive::Bool), false, _) => quote! { Some(true) },
+                        (_, _, Some(_)) => {
+                            let default_id = crate::ident(&default_fn);
+                            quote! { Some(#default_id()) }
+                        }
+                        (_, true, None) => quote! { Some(None) },
+                        (_, false, None) => quote! { None },
+                    };
+                    let empty_fn = format!("{}_{}_empty", name, field).to_snake_case();
+                    let empty_id = crate::ident(&empty_fn);
+                    helper_fns.push(quote! {
+                        fn #empty_id<'de, D: hsr::serde::Deserializer<'de>>(
+                            de: D,
+                        ) -> Result<#def, D::Error> {
+                            hsr::query::allow_empty(de, || #empty)
+                        }
+                    });
+                    Some(quote! { #[serde(deserialize_with = #empty_fn)] })
+                }
+            };
+            // numbers sent as strings are taken (unless something else reads the field)
+            let lenient_attr = match (&delimited_attr, &empty_attr) {
+                (None, None)
+                    if is_number(&field_type.typ)
+                        && (opts.lenient_numbers || field_type.meta.lenient) =>
+                {
+                    let lenient_fn = if is_option {
+                        "hsr::lenient::optional_number"
+                    } else {
+                        "hsr::lenient::number"
+                    };
+                    Some(quote! { #[serde(deserialize_with = #lenient_fn)] })
+                }
+                _ => None,
             };
+            let allow_case = crate::allow_non_snake_case(Some(field.as_str()));
+            field_attrs.push(
+                quote! { #serde_attr #delimited_attr #empty_attr #lenient_attr #arbitrary_attr #allow_case },
+            );
             Ok(def)
         })
         .collect::<Result<_>>()?;
-    let derives = get_derive_tokens();
+    let derives = get_derive_tokens(opts);
+    let (deny_unknown, capture_unknown) = match strukt.unknown_fields {
+        UnknownFields::Default => (opts.deny_unknown_fields, opts.extra_fields),
+        UnknownFields::Deny => (true, false),
+        UnknownFields::Capture => (false, true),
+        UnknownFields::Ignore => (false, false),
+    };
+    // the fields nobody declared are kept in a map, flattened after everything else
+    // (so only those the other flattened structs don't take are left for it)
+    if capture_unknown {
+        let extra: Ident = "extra".parse()?;
+        if strukt.fields.contains_key(&extra) {
+            invalid!(
+                "Field 'extra' of '{}' clashes with the map of unknown fields",
+                ApiPath::from(type_path.clone())
+            );
+        }
+        let arbitrary_attr = if opts.arbitrary {
+            Some(quote! { #[cfg_attr(test, proptest(value = "Default::default()"))] })
+        } else {
+            None
+        };
+        let map = opts.map_type.tokens();
+        flattened.push(quote! {
+            #[serde(flatten)]
+            #arbitrary_attr
+            pub extra: #map<String, JsonValue>
+        });
+    }
+    let sample_impl = if opts.samples {
+        let samples = strukt
+            .fields
+            .values()
+            .map(|(meta, field_type_path)| {
+                let value = sample_expr(field_type_path, lookup)?;
+                let ref_or = lookup.get(field_type_path).unwrap();
+                let nullable = lookup_type_recursive(ref_or, lookup)?.meta.nullable;
+                if meta.optional() && !nullable {
+                    Ok(quote! { rng.maybe(|rng| #value) })
+                } else {
+                    Ok(value)
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let extra = if capture_unknown {
+            Some(quote! { extra: Default::default(), })
+        } else {
+            None
+        };
+        let rng = if samples.is_empty() && flattened_names.is_empty() {
+            quote! { _rng }
+        } else {
+            quote! { rng }
+        };
+        let struct_name = match (&meta.title, meta.nullable) {
+            (Some(title), _) => title.parse::<Ident>()?.to_token_stream(),
+            (None, true) => TypePath::from(ApiPath::from(type_path.clone()).push("opt"))
+                .canonicalize()?
+                .to_token_stream(),
+            (None, false) => name.to_token_stream(),
+        };
+        Some(quote! {
+            impl hsr::fake::Sample for #struct_name {
+                fn sample(rng: &mut hsr::fake::Rng) -> Self {
+                    rng.nested(|#rng| Self {
+                        #(#fieldnames: #samples,)*
+                        #(#flattened_names: hsr::fake::Sample::sample(rng),)*
+                        #extra
+                    })
+                }
+            }
+        })
+    } else {
+        None
+    };
+    // serde can't tell the fields of flattened structs from unknown ones
+    let derives = if deny_unknown && flattened.is_empty() {
+        quote! {
+            #derives
+            #[serde(deny_unknown_fields)]
+        }
+    } else {
+        derives
+    };
     // Another tricky bit. We have to create 'some' type with the
     // canonical name, either concrete struct or alias, so that it can be
     // referenced from elsewhere. But we also need want to potentially
@@ -822,18 +2446,20 @@ fn generate_struct_def(
                 #descr
                 #derives
                 #visibility struct #name {
-                    #(pub #fieldnames: #fields),*
+                    #(#field_attrs pub #fieldnames: #fields,)*
+                    #(#flattened,)*
                 }
             }
         }
         (None, true) => {
             let new_path = TypePath::from(ApiPath::from(type_path.clone()).push("opt"));
-            let new_name = new_path.canonicalize();
+            let new_name = new_path.canonicalize()?;
             quote! {
                 #descr
                 #derives
                 #visibility struct #new_name {
-                    #(pub #fieldnames: #fields),*
+                    #(#field_attrs pub #fieldnames: #fields,)*
+                    #(#flattened,)*
                 }
                 #visibility type #name = Option<#new_name>;
             }
@@ -844,7 +2470,8 @@ fn generate_struct_def(
                 #descr
                 #derives
                 #visibility struct #new_name {
-                    #(pub #fieldnames: #fields),*
+                    #(#field_attrs pub #fieldnames: #fields,)*
+                    #(#flattened,)*
                 }
                 // This alias is not visible because we prefer to use new_name
                 type #name = #new_name;
@@ -856,28 +2483,152 @@ fn generate_struct_def(
                 #descr
                 #derives
                 #visibility struct #new_name {
-                    #(pub #fieldnames: #fields),*
+                    #(#field_attrs pub #fieldnames: #fields,)*
+                    #(#flattened,)*
                 }
                 #visibility type #name = Option<#new_name>;
             }
         }
     };
-    Ok(tokens)
+    Ok(quote! {
+        #tokens
+        #sample_impl
+        #(#helper_fns)*
+    })
 }
 
 /// TODO If there are multiple different error types, construct an
 /// enum to hold them all. If there is only one or none, don't bother.
+/// A body with several representations is an (untagged) enum of them, which
+/// knows how to (de)serialize each according to its media type
+fn generate_content_def(
+    reprs: &Map<String, Representation>,
+    name: &TypeName,
+    meta: &TypeMetadata,
+) -> Result<TokenStream> {
+    // A body which is only a patch needs no enum, the patch types have
+    // representations of their own
+    let descr = meta.description_doc();
+    if let (1, Some(repr)) = (reprs.len(), reprs.values().next()) {
+        match repr {
+            Representation::MergePatch(type_path) => {
+                let patched = type_path.canonicalize()?;
+                return Ok(quote! {
+                    #descr
+                    type #name = hsr::patch::Patch<#patched>;
+                });
+            }
+            Representation::JsonPatch => {
+                return Ok(quote! {
+                    #descr
+                    type #name = Vec<hsr::patch::PatchOp>;
+                });
+            }
+            _ => {}
+        }
+    }
+
+    let mut variants = Vec::new();
+    let mut media_type_arms = Vec::new();
+    let mut to_body_arms = Vec::new();
+    let mut from_body_checks = Vec::new();
+    for (media_type, repr) in reprs {
+        let variant = crate::ident(media_type_variant(media_type)?);
+        let (ty, to_body, from_body) = match repr {
+            Representation::Json(type_path) => {
+                let ty = type_path.canonicalize()?;
+                (
+                    quote! { #ty },
+                    quote! { hsr::serde_json::to_vec(body) },
+                    quote! { hsr::serde_json::from_slice(body)? },
+                )
+            }
+            Representation::MergePatch(type_path) => {
+                let patched = type_path.canonicalize()?;
+                (
+                    quote! { hsr::patch::Patch<#patched> },
+                    quote! { hsr::serde_json::to_vec(body) },
+                    quote! { hsr::serde_json::from_slice(body)? },
+                )
+            }
+            Representation::JsonPatch => (
+                quote! { Vec<hsr::patch::PatchOp> },
+                quote! { hsr::serde_json::to_vec(body) },
+                quote! { hsr::serde_json::from_slice(body)? },
+            ),
+            Representation::Text => (
+                quote! { String },
+                quote! { Ok(body.clone().into_bytes()) },
+                quote! { String::from_utf8(body.to_vec())? },
+            ),
+            Representation::Binary => (
+                quote! { Vec<u8> },
+                quote! { Ok(body.clone()) },
+                quote! { body.to_vec() },
+            ),
+        };
+        variants.push(quote! { #variant(#ty) });
+        media_type_arms.push(quote! { #name::#variant(_) => #media_type });
+        to_body_arms.push(quote! { #name::#variant(body) => #to_body });
+        from_body_checks.push(quote! {
+            if hsr::negotiate::is_media_type(content_type, #media_type) {
+                return Ok(#name::#variant(#from_body));
+            }
+        });
+    }
+    let media_types = reprs.keys();
+    // not `get_derive_tokens`, as raw bodies and patches have no `Arbitrary` impls
+    let visibility = meta.visibility;
+    Ok(quote! {
+        #descr
+        #[derive(Debug, Clone, PartialEq, hsr::Serialize, hsr::Deserialize)]
+        #[serde(untagged)]
+        #visibility enum #name {
+            #(#variants,)*
+        }
+
+        impl hsr::negotiate::Representations for #name {
+            const MEDIA_TYPES: &'static [&'static str] = &[#(#media_types),*];
+
+            fn media_type(&self) -> &'static str {
+                match self {
+                    #(#media_type_arms,)*
+                }
+            }
+
+            fn to_body(&self) -> hsr::serde_json::Result<Vec<u8>> {
+                match self {
+                    #(#to_body_arms,)*
+                }
+            }
+
+            fn from_body(
+                content_type: &str,
+                body: &[u8],
+            ) -> Result<Self, hsr::negotiate::BodyError> {
+                #(#from_body_checks)*
+                Err(hsr::negotiate::BodyError::UnsupportedMediaType(content_type.to_string()))
+            }
+        }
+    })
+}
+
 pub(crate) fn generate_enum_def(
     name: &TypeName,
     meta: &TypeMetadata,
     variants: &[Variant],
     dflt: Option<&Variant>,
     untagged: bool,
-) -> TokenStream {
+    opts: &GenerationOptions,
+) -> Result<TokenStream> {
     if variants.is_empty() && dflt.is_none() {
-        // Should not be able to get here (?)
-        panic!("Enum '{}' has no variants", name);
+        // operations without responses and empty 'oneOf's are rejected by the walk
+        invalid!("Enum '{}' has no variants", name);
     }
+    let variants = variants
+        .iter()
+        .map(Variant::tokens)
+        .collect::<Result<Vec<_>>>()?;
 
     // should serde do untagged serialization?
     // (The answer should be 'no', unless it is a OneOf/AnyOf type)
@@ -887,27 +2638,35 @@ pub(crate) fn generate_enum_def(
         None
     };
 
-    // Special-case the default variant
-    let default = dflt.map(|variant| {
-        let docs = variant.description.as_ref().map(doc_comment);
-        match &variant.type_path {
-            None => quote! {Default { status_code: u16 }},
-            Some(path) => {
-                let varty = path.canonicalize();
-                quote! {
-                    #docs
-                    Default {
-                        status_code: u16,
-                        body: #varty
-                    }
+    // Special-case the default variant (only found in return types),
+    // which holds its status code
+    let default = match dflt {
+        None => None,
+        Some(variant) => {
+            let docs = variant.description.as_ref().map(doc_comment);
+            match &variant.type_path {
+                None => Some(quote! {
+                    #[status(field = "status_code")]
+                    Default { status_code: u16 }
+                }),
+                Some(path) => {
+                    let varty = path.canonicalize()?;
+                    Some(quote! {
+                        #docs
+                        #[status(field = "status_code")]
+                        Default {
+                            status_code: u16,
+                            body: #varty
+                        }
+                    })
                 }
             }
         }
-    });
-    let derives = get_derive_tokens();
+    };
+    let derives = get_derive_tokens(opts);
     let visibility = meta.visibility;
-    let descr = meta.description();
-    quote! {
+    let descr = meta.description_doc();
+    Ok(quote! {
         #descr
         #derives
         #serde_tag
@@ -915,32 +2674,217 @@ pub(crate) fn generate_enum_def(
             #(#variants,)*
             #default
         }
+    })
+}
+
+/// A string enum which accepts its values in any (ASCII) case, sending them as they
+/// are (see `GenerationOptions::case_insensitive_enums`). The variants are renamed
+/// to their values.
+fn generate_case_insensitive_enum_def(
+    name: &TypeName,
+    meta: &TypeMetadata,
+    variants: &[Variant],
+    opts: &GenerationOptions,
+) -> Result<TokenStream> {
+    let arbitrary = arbitrary_derive_tokens(opts);
+    let visibility = meta.visibility;
+    let descr = meta.description_doc();
+    let names = variants.iter().map(|var| &var.name);
+    let values: Vec<&str> = variants
+        .iter()
+        .map(|var| var.rename.as_deref().unwrap_or(var.name.as_str()))
+        .collect();
+    let variants = variants
+        .iter()
+        .map(Variant::tokens)
+        .collect::<Result<Vec<_>>>()?;
+    Ok(quote! {
+        #descr
+        #[derive(Debug, Clone, PartialEq, hsr::Serialize)]
+        #arbitrary
+        #visibility enum #name {
+            #(#variants,)*
+        }
+
+        impl<'de> hsr::serde::Deserialize<'de> for #name {
+            fn deserialize<D: hsr::serde::Deserializer<'de>>(de: D) -> Result<Self, D::Error> {
+                const VALUES: &[&str] = &[#(#values),*];
+                let value = <String as hsr::serde::Deserialize>::deserialize(de)?;
+                #(
+                    if value.eq_ignore_ascii_case(#values) {
+                        return Ok(#name::#names);
+                    }
+                )*
+                Err(hsr::serde::de::Error::unknown_variant(&value, VALUES))
+            }
+        }
+    })
+}
+
+/// A nullable enum is defined under another name, so that its own name can be
+/// an alias for an `Option` of it (like a nullable struct)
+fn nullable_enum_name(
+    name: &TypeName,
+    type_path: &TypePath,
+    meta: &TypeMetadata,
+) -> Result<(TypeName, Option<TokenStream>)> {
+    if !meta.nullable {
+        return Ok((name.clone(), None));
+    }
+    let inner = TypePath::from(ApiPath::from(type_path.clone()).push("opt")).canonicalize()?;
+    let visibility = meta.visibility;
+    let alias = quote! {
+        #visibility type #name = Option<#inner>;
+    };
+    Ok((inner, Some(alias)))
+}
+
+/// The variant of an integer enum for `value`
+fn integer_variant_name(value: i64) -> Result<Ident> {
+    if value < 0 {
+        format!("VMinus{}", -i128::from(value)).parse()
+    } else {
+        format!("V{}", value).parse()
+    }
+}
+
+/// An integer enum has a variant for each value, which is also its discriminant.
+/// Serde only (de)serializes enums by name, so we do it by value ourselves.
+fn generate_integer_enum_def(
+    name: &TypeName,
+    meta: &TypeMetadata,
+    values: &[i64],
+    opts: &GenerationOptions,
+) -> Result<TokenStream> {
+    let variants = values
+        .iter()
+        .map(|value| integer_variant_name(*value))
+        .collect::<Result<Vec<Ident>>>()?;
+    let expected = values
+        .iter()
+        .map(|value| value.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    // parsed, so that negative values are a minus sign and a literal
+    let values: Vec<TokenStream> = values
+        .iter()
+        .map(|value| value.to_string().parse().unwrap())
+        .collect();
+    let arbitrary = arbitrary_derive_tokens(opts);
+    let visibility = meta.visibility;
+    let descr = meta.description_doc();
+    Ok(quote! {
+        #descr
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        #arbitrary
+        #[repr(i64)]
+        #visibility enum #name {
+            #(#variants = #values,)*
+        }
+
+        impl #name {
+            /// The value, as it is sent
+            pub fn value(self) -> i64 {
+                self as i64
+            }
+        }
+
+        impl hsr::serde::Serialize for #name {
+            fn serialize<S: hsr::serde::Serializer>(
+                &self,
+                ser: S,
+            ) -> std::result::Result<S::Ok, S::Error> {
+                ser.serialize_i64(self.value())
+            }
+        }
+
+        impl<'de> hsr::serde::Deserialize<'de> for #name {
+            fn deserialize<D: hsr::serde::Deserializer<'de>>(
+                de: D,
+            ) -> std::result::Result<Self, D::Error> {
+                match <i64 as hsr::serde::Deserialize>::deserialize(de)? {
+                    #(#values => Ok(#name::#variants),)*
+                    value => Err(hsr::serde::de::Error::custom(format!(
+                        "unexpected value {}, expected one of {}",
+                        value, #expected
+                    ))),
+                }
+            }
+        }
+    })
+}
+
+/// Name the variants of an `anyOf` after their alternatives: the schema referred
+/// to, or the kind of primitive (giving e.g. `String` and `Number` variants).
+/// If that doesn't give distinct names, they are numbered like `oneOf` variants.
+fn any_of_variant_names(alternatives: &[TypePath], lookup: &TypeLookup) -> Vec<Ident> {
+    let numbered = || {
+        (1..=alternatives.len())
+            .map(|ix| format!("V{}", ix).parse().unwrap())
+            .collect()
+    };
+    let mut names: Vec<Ident> = Vec::new();
+    for alt in alternatives {
+        let name = match lookup.get(alt) {
+            Some(ReferenceOr::Reference { reference }) => match TypePath::from_reference(reference)
+            {
+                Ok(path) => match path.canonicalize().and_then(|name| name.parse()) {
+                    Ok(name) => name,
+                    Err(_) => return numbered(),
+                },
+                Err(_) => return numbered(),
+            },
+            Some(ReferenceOr::Item(typ)) => {
+                let kind = match &typ.typ {
+                    TypeInner::Primitive(Primitive::String) => "String",
+                    TypeInner::Primitive(Primitive::I64(_)) => "Integer",
+                    TypeInner::Primitive(Primitive::F64(_)) => "Number",
+                    TypeInner::Primitive(Primitive::Bool) => "Boolean",
+                    TypeInner::Array(_) => "Array",
+                    _ => return numbered(),
+                };
+                kind.parse().unwrap()
+            }
+            None => return numbered(),
+        };
+        if names.contains(&name) {
+            return numbered();
+        }
+        names.push(name);
     }
+    names
 }
 
-fn combine_types(parts: &[ReferenceOr<Type>], lookup: &TypeLookup) -> Result<Struct> {
+pub(crate) fn combine_types(
+    parts: &[ReferenceOr<Type>],
+    lookup: &TypeLookup,
+    type_path: &TypePath,
+) -> Result<Struct> {
+    let location = ApiPath::from(type_path.clone());
     // We do the combination in a simplistic way: assume parent types are structs,
     // and add all the fields into a new struct. Reject duplicates
     let mut base = Map::new();
     for part in parts.iter() {
         let typ = lookup_type_recursive(part, lookup)?;
-        match &typ.typ {
-            TypeInner::Struct(strukt) => {
-                for (field, required) in &strukt.fields {
-                    if let Some(_) = base.insert(field, required) {
-                        // duplicate field
-                        invalid!("Duplicate field '{}'", field);
-                    }
-                }
+        let fields = match &typ.typ {
+            TypeInner::Struct(strukt) => strukt.fields.clone(),
+            // e.g. a `$ref` to another `allOf`
+            TypeInner::AllOf(parts) => combine_types(parts, lookup, type_path)?.fields,
+            // a part without properties (e.g. just a description) adds no fields
+            TypeInner::Any => continue,
+            _ => unsupported!("Non-struct allOf combinations (type '{}')", location),
+        };
+        for (field, required) in fields {
+            if base.contains_key(&field) {
+                // duplicate field
+                invalid!("Duplicate field '{}' (location: '{}')", field, location);
             }
-            _ => todo!("Non-struct allOf combinations are not supported"),
+            base.insert(field, required);
         }
     }
     Ok(Struct {
-        fields: base
-            .into_iter()
-            .map(|(n, m)| (n.clone(), m.clone()))
-            .collect(),
+        fields: base,
+        unknown_fields: UnknownFields::Default,
     })
 }
 
@@ -955,16 +2899,225 @@ mod tests {
         // let yaml = "../examples/petstore/petstore.yaml";
         let yaml = fs::read_to_string(yaml).unwrap();
         let api: OpenAPI = serde_yaml::from_str(&yaml).unwrap();
-        let (types, _routes) = walk_api(&api).unwrap();
+        let raw: serde_json::Value = serde_yaml::from_str(&yaml).unwrap();
+        let (types, _routes) = walk_api(&api, &raw, false).unwrap();
 
         #[allow(unused_mut)]
-        let mut code = generate_rust_types(&types).unwrap().to_string();
+        let mut code = generate_rust_types(&types, &Default::default())
+            .unwrap()
+            .to_string();
 
-        #[cfg(feature = "rustfmt")]
+        #[cfg(any(feature = "pretty", feature = "rustfmt"))]
         {
             code = crate::prettify_code(code).unwrap();
         }
         println!("{}", code);
         panic!()
     }
+
+    #[test]
+    fn test_duplicate_types() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: Shapes
+  version: 0.1.0
+paths:
+  /a:
+    get:
+      operationId: get_a
+      responses:
+        '200':
+          description: Ok
+          content:
+            application/json:
+              schema:
+                type: object
+                properties:
+                  id:
+                    type: integer
+  /b:
+    get:
+      operationId: get_b
+      responses:
+        '200':
+          description: Ok
+          content:
+            application/json:
+              schema:
+                type: object
+                properties:
+                  id:
+                    type: integer
+  /c:
+    get:
+      operationId: get_c
+      responses:
+        '200':
+          description: Ok
+          content:
+            application/json:
+              schema:
+                type: object
+                properties:
+                  id:
+                    type: string
+components:
+  schemas:
+    Thing:
+      type: object
+      properties:
+        id:
+          type: string
+    Other:
+      type: object
+      properties:
+        id:
+          type: string
+"#;
+        let api: OpenAPI = serde_yaml::from_str(yaml).unwrap();
+        let raw: serde_json::Value = serde_yaml::from_str(yaml).unwrap();
+        let (types, _routes) = walk_api(&api, &raw, false).unwrap();
+        let duplicates: Vec<_> = duplicate_types(&types, &|_| true)
+            .into_iter()
+            .map(|(dup, original)| {
+                (
+                    dup.canonicalize().unwrap(),
+                    original.canonicalize().unwrap(),
+                )
+            })
+            .map(|(dup, original)| (dup.to_string(), original.to_string()))
+            .collect();
+        // components are kept, even if they look the same
+        assert_eq!(
+            duplicates,
+            vec![
+                ("GetB200".to_string(), "GetA200".to_string()),
+                ("GetC200".to_string(), "Other".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_borrowed_schemas() {
+        let schema: Schema = serde_yaml::from_str(
+            r#"
+title: Pet
+description: A pet
+type: object
+properties:
+  tags:
+    type: array
+    items:
+      type: string
+"#,
+        )
+        .unwrap();
+        let path = || {
+            ApiPath::default()
+                .push("components")
+                .push("schemas")
+                .push("Pet")
+        };
+
+        // properties and items are boxed schemas, components aren't, and both build alike
+        let mut unboxed_index = TypeLookup::new();
+        let unboxed = ReferenceOr::Item(schema.clone());
+        let unboxed = build_type_recursive(&unboxed, path(), &mut unboxed_index).unwrap();
+        let mut boxed_index = TypeLookup::new();
+        let boxed = ReferenceOr::Item(Box::new(schema));
+        let boxed = build_type_recursive(&boxed, path(), &mut boxed_index).unwrap();
+        assert_eq!(boxed, unboxed);
+        assert_eq!(boxed_index, unboxed_index);
+
+        let pet = match &unboxed {
+            ReferenceOr::Item(pet) => pet,
+            ReferenceOr::Reference { .. } => panic!("Pet is a reference"),
+        };
+        assert_eq!(pet.meta().title(), Some("Pet"));
+        assert_eq!(pet.meta().description(), Some("A pet"));
+        let names: Vec<_> = unboxed_index
+            .keys()
+            .map(|path| path.canonicalize().unwrap().to_string())
+            .collect();
+        assert_eq!(names, vec!["PetTags", "PetTagsArray"]);
+
+        let reference = ReferenceOr::<Box<Schema>>::Reference {
+            reference: "#/components/schemas/Pet".into(),
+        };
+        let built = build_type_recursive(&reference, path(), &mut TypeLookup::new()).unwrap();
+        assert!(matches!(built, ReferenceOr::Reference { .. }));
+        assert!(TypePath::from_reference("#/components/schemas/Pet").is_ok());
+        assert!(TypePath::from_reference("#/components/responses/Pet").is_err());
+    }
+
+    #[test]
+    fn test_any_of_variant_names() {
+        let yaml = r#"
+openapi: 3.0.0
+info:
+  title: AnyOf
+  version: 0.1.0
+paths: {}
+components:
+  schemas:
+    Cat:
+      type: object
+      properties:
+        meows:
+          type: boolean
+    Dog:
+      type: object
+      properties:
+        barks:
+          type: boolean
+    Pet:
+      anyOf:
+        - $ref: '#/components/schemas/Cat'
+        - $ref: '#/components/schemas/Dog'
+    Amount:
+      anyOf:
+        - type: integer
+        - type: number
+        - type: string
+    Label:
+      anyOf:
+        - type: string
+        - type: string
+          maxLength: 10
+"#;
+        let api: OpenAPI = serde_yaml::from_str(yaml).unwrap();
+        let raw: serde_json::Value = serde_yaml::from_str(yaml).unwrap();
+        let (types, _routes) = walk_api(&api, &raw, false).unwrap();
+        let names = |schema: &str| {
+            let path = TypePath::from_reference(&format!("#/components/schemas/{}", schema));
+            match &types[&path.unwrap()] {
+                ReferenceOr::Item(Type {
+                    typ: TypeInner::AnyOf(alternatives),
+                    ..
+                }) => any_of_variant_names(alternatives, &types)
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>(),
+                other => panic!("not anyOf: {:?}", other),
+            }
+        };
+        assert_eq!(names("Pet"), vec!["Cat", "Dog"]);
+        assert_eq!(names("Amount"), vec!["Integer", "Number", "String"]);
+        // not distinct
+        assert_eq!(names("Label"), vec!["V1", "V2"]);
+    }
+
+    #[test]
+    fn test_generation_errors() {
+        // a path which doesn't make a type name is an error, not a panic
+        let path = TypePath::from_reference("#/components/schemas/1Pet").unwrap();
+        assert!(path.canonicalize().is_err());
+        let name: TypeName = "Empty".parse().unwrap();
+        let meta = TypeMetadata::default();
+        let opts = GenerationOptions::default();
+        assert!(generate_enum_def(&name, &meta, &[], None, false, &opts).is_err());
+        let variant = Variant::new("Pet".parse().unwrap()).type_path(Some(path));
+        assert!(generate_enum_def(&name, &meta, &[variant], None, true, &opts).is_err());
+    }
 }
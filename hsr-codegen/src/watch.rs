@@ -0,0 +1,232 @@
+//! Regenerate code when a spec changes.
+//!
+//! A `Watcher` polls the spec, and any files it refers to with an external
+//! `$ref`, and regenerates the code whenever one of them changes. The output
+//! file is only rewritten if the generated code is different, so that saving
+//! the spec without changing what it means doesn't set off a cargo rebuild.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use crate::{generate_from_yaml_file_with_options, GenerationOptions, Map, Result, Set};
+
+/// How often the watched files are checked, unless given
+const DEFAULT_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Write a file, unless it already has the given contents. Gives whether it was written.
+pub fn write_if_changed(path: impl AsRef<Path>, contents: &str) -> io::Result<bool> {
+    let path = path.as_ref();
+    match fs::read(path) {
+        Ok(existing) if existing == contents.as_bytes() => Ok(false),
+        _ => {
+            fs::write(path, contents)?;
+            Ok(true)
+        }
+    }
+}
+
+/// The spec, and the files it refers to (directly or not) with an external `$ref`.
+/// Files which don't exist (yet) are included, so they are picked up when created.
+pub fn referenced_files(spec: impl AsRef<Path>) -> Vec<PathBuf> {
+    let mut files = Set::new();
+    let mut pending = vec![spec.as_ref().to_path_buf()];
+    while let Some(file) = pending.pop() {
+        if !files.insert(file.clone()) {
+            continue;
+        }
+        let doc: serde_yaml::Value = match fs::read_to_string(&file)
+            .ok()
+            .and_then(|source| serde_yaml::from_str(&source).ok())
+        {
+            Some(doc) => doc,
+            None => continue,
+        };
+        let dir = file.parent().unwrap_or_else(|| Path::new(""));
+        let mut refs = Vec::new();
+        external_refs(&doc, &mut refs);
+        pending.extend(refs.into_iter().map(|r| dir.join(r)));
+    }
+    files.into_iter().collect()
+}
+
+/// The files named by the external `$ref`s of a document (`other.yaml#/Pet`), other than URLs
+fn external_refs(node: &serde_yaml::Value, refs: &mut Vec<String>) {
+    match node {
+        serde_yaml::Value::Mapping(map) => {
+            for (key, value) in map {
+                match (key.as_str(), value.as_str()) {
+                    (Some("$ref"), Some(reference)) => {
+                        let file = reference.split('#').next().unwrap_or_default();
+                        if !file.is_empty() && !file.contains("://") {
+                            refs.push(file.to_string())
+                        }
+                    }
+                    _ => external_refs(value, refs),
+                }
+            }
+        }
+        serde_yaml::Value::Sequence(items) => {
+            items.iter().for_each(|item| external_refs(item, refs))
+        }
+        _ => {}
+    }
+}
+
+/// Regenerates the code for a spec whenever it (or a file it refers to) changes
+#[derive(Debug, Clone)]
+pub struct Watcher {
+    spec: PathBuf,
+    output: PathBuf,
+    opts: GenerationOptions,
+    interval: Duration,
+    /// A hash of the contents of each watched file, or `None` if it is missing
+    seen: Map<PathBuf, Option<u64>>,
+}
+
+impl Watcher {
+    /// Watch a spec, writing the generated code to `output`
+    pub fn new(spec: impl AsRef<Path>, output: impl AsRef<Path>, opts: &GenerationOptions) -> Self {
+        Self {
+            spec: spec.as_ref().to_path_buf(),
+            output: output.as_ref().to_path_buf(),
+            opts: opts.clone(),
+            interval: DEFAULT_INTERVAL,
+            seen: Map::new(),
+        }
+    }
+
+    /// Check the files this often (every half second by default)
+    pub fn with_interval(self, interval: Duration) -> Self {
+        Self { interval, ..self }
+    }
+
+    /// The files being watched, as of the last regeneration
+    pub fn watched_files(&self) -> impl Iterator<Item = &Path> {
+        self.seen.keys().map(PathBuf::as_path)
+    }
+
+    /// Regenerate the code if any watched file changed since the last call (or
+    /// this is the first). Gives `None` if nothing changed, or else whether the
+    /// output was rewritten.
+    pub fn poll(&mut self) -> Option<Result<bool>> {
+        let changed = self.seen.is_empty()
+            || self
+                .seen
+                .iter()
+                .any(|(file, hash)| content_hash(file) != *hash);
+        if !changed {
+            return None;
+        }
+        // the spec may have gained or lost references
+        self.seen = referenced_files(&self.spec)
+            .into_iter()
+            .map(|file| {
+                let hash = content_hash(&file);
+                (file, hash)
+            })
+            .collect();
+        let result = generate_from_yaml_file_with_options(&self.spec, &self.opts)
+            .and_then(|code| Ok(write_if_changed(&self.output, &code)?));
+        Some(result)
+    }
+
+    /// Regenerate the code whenever a watched file changes, forever, passing
+    /// the outcome of each regeneration to `report`
+    pub fn run(mut self, mut report: impl FnMut(Result<bool>)) -> ! {
+        loop {
+            if let Some(result) = self.poll() {
+                report(result)
+            }
+            thread::sleep(self.interval)
+        }
+    }
+}
+
+fn content_hash(file: &Path) -> Option<u64> {
+    let contents = fs::read(file).ok()?;
+    let mut hasher = DefaultHasher::new();
+    contents.hash(&mut hasher);
+    Some(hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    const SPEC: &str = r##"
+openapi: "3.0.0"
+info: { title: Test, version: "1.0" }
+paths:
+  /pets:
+    get:
+      operationId: list_pets
+      responses:
+        "200": { description: Ok }
+"##;
+
+    #[test]
+    fn test_watcher() {
+        let dir = TempDir::new("hsr-watch").unwrap();
+        let spec = dir.path().join("api.yaml");
+        let output = dir.path().join("api.rs");
+        fs::write(&spec, SPEC).unwrap();
+        let mut watcher = Watcher::new(&spec, &output, &GenerationOptions::default());
+
+        assert!(watcher.poll().unwrap().unwrap());
+        let code = fs::read_to_string(&output).unwrap();
+        assert!(code.contains("list_pets"));
+        assert!(watcher.poll().is_none());
+
+        // saving without changes is not a change
+        fs::write(&spec, SPEC).unwrap();
+        assert!(watcher.poll().is_none());
+
+        // a change which doesn't change the code leaves the output alone
+        fs::write(&spec, format!("{}# a comment\n", SPEC)).unwrap();
+        assert!(!watcher.poll().unwrap().unwrap());
+
+        fs::write(&spec, SPEC.replace("list_pets", "get_pets")).unwrap();
+        assert!(watcher.poll().unwrap().unwrap());
+        assert!(fs::read_to_string(&output).unwrap().contains("get_pets"));
+
+        // an invalid spec is reported, and watched until it is fixed
+        fs::write(&spec, "openapi: [").unwrap();
+        assert!(watcher.poll().unwrap().is_err());
+        assert!(watcher.poll().is_none());
+        fs::write(&spec, SPEC).unwrap();
+        assert!(watcher.poll().unwrap().unwrap());
+    }
+
+    #[test]
+    fn test_referenced_files() {
+        let dir = TempDir::new("hsr-watch").unwrap();
+        let spec = dir.path().join("api.yaml");
+        let pets = dir.path().join("schemas/pets.yaml");
+        fs::create_dir(dir.path().join("schemas")).unwrap();
+        fs::write(
+            &spec,
+            r##"
+paths:
+  /pets:
+    get:
+      responses:
+        "200": { $ref: "schemas/pets.yaml#/Ok" }
+        default: { $ref: "#/components/responses/Error" }
+        "404": { $ref: "https://example.com/errors.yaml#/NotFound" }
+"##,
+        )
+        .unwrap();
+        fs::write(&pets, "Ok: { $ref: 'toys.yaml#/Ok' }").unwrap();
+        let files = referenced_files(&spec);
+        assert_eq!(
+            files,
+            vec![spec, pets, dir.path().join("schemas/toys.yaml")]
+        );
+    }
+}
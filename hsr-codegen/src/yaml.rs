@@ -0,0 +1,416 @@
+//! Reading specs written in YAML.
+//!
+//! `serde_yaml` resolves anchors and aliases, but leaves merge keys
+//! (`<<: *defaults`) as ordinary keys, so they are applied here. Errors are
+//! located in the source: an error found at a node of the spec (see `Error::at`)
+//! is given the line and column where the node was written.
+
+use openapiv3::{Operation, Parameter, PathItem, ReferenceOr, RequestBody, Response, Schema};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::Marker;
+
+use crate::{escape_pointer_segment, Error, Map, Result};
+
+/// The keys of a path item which are operations
+const METHODS: &[&str] = &[
+    "get", "put", "post", "delete", "options", "head", "patch", "trace",
+];
+
+/// Apply the merge keys of a document. The entries of the merged mapping (or
+/// mappings, the first taking precedence) are added to the mapping containing
+/// the key, unless it has them already.
+pub(crate) fn apply_merge_keys(node: &mut serde_yaml::Value, pointer: &str) -> Result<()> {
+    match node {
+        serde_yaml::Value::Mapping(map) => {
+            // merged mappings may have merge keys of their own
+            for (key, value) in map.iter_mut() {
+                let key = key.as_str().unwrap_or_default();
+                apply_merge_keys(
+                    value,
+                    &format!("{}/{}", pointer, escape_pointer_segment(key)),
+                )?;
+            }
+            let merge_key = serde_yaml::Value::String("<<".into());
+            if !map.contains_key(&merge_key) {
+                return Ok(());
+            }
+            let mut merged = serde_yaml::Mapping::new();
+            let mut source = None;
+            for (key, value) in std::mem::take(map) {
+                if key == merge_key {
+                    source = Some(value);
+                } else {
+                    merged.insert(key, value);
+                }
+            }
+            let sources = match source {
+                Some(serde_yaml::Value::Mapping(source)) => vec![source],
+                Some(serde_yaml::Value::Sequence(items)) => items
+                    .into_iter()
+                    .map(|item| match item {
+                        serde_yaml::Value::Mapping(source) => Ok(source),
+                        _ => Err(bad_merge(pointer)),
+                    })
+                    .collect::<Result<_>>()?,
+                _ => return Err(bad_merge(pointer)),
+            };
+            for source in sources {
+                for (key, value) in source {
+                    if !merged.contains_key(&key) {
+                        merged.insert(key, value);
+                    }
+                }
+            }
+            *map = merged;
+        }
+        serde_yaml::Value::Sequence(items) => {
+            for (ix, item) in items.iter_mut().enumerate() {
+                apply_merge_keys(item, &format!("{}/{}", pointer, ix))?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn bad_merge(pointer: &str) -> Error {
+    Error::Validation("The merge key '<<' must be given a mapping, or a list of mappings".into())
+        .at(&format!("{}/<<", pointer))
+}
+
+/// Give an error the line and column of the node of the spec at fault, if known
+pub(crate) fn locate_error(err: Error, source: &str) -> Error {
+    let pointer = match &err {
+        Error::At(pointer, _) => pointer.clone(),
+        _ => return err,
+    };
+    let positions = match node_positions(source) {
+        Some(positions) => positions,
+        None => return err,
+    };
+    // nodes brought in by a merge key are found at the mapping merging them
+    let mut prefix = pointer.as_str();
+    loop {
+        if let Some(&(line, column)) = positions.get(prefix) {
+            return Error::Located(line, column, Box::new(err));
+        }
+        match prefix.rfind('/') {
+            Some(ix) => prefix = &prefix[..ix],
+            None => return err,
+        }
+    }
+}
+
+/// The line and column (both from 1) of every node of a YAML document, by JSON pointer
+fn node_positions(source: &str) -> Option<Map<String, (usize, usize)>> {
+    let mut locator = Locator {
+        positions: Map::new(),
+        stack: Vec::new(),
+    };
+    Parser::new(source.chars()).load(&mut locator, false).ok()?;
+    Some(locator.positions)
+}
+
+struct Locator {
+    positions: Map<String, (usize, usize)>,
+    stack: Vec<Collection>,
+}
+
+enum Collection {
+    Mapping {
+        pointer: String,
+        /// The key of the value to come, once it has been read
+        key: Option<String>,
+    },
+    Sequence {
+        pointer: String,
+        index: usize,
+    },
+}
+
+impl Locator {
+    /// The pointer of the node to come, or `None` if it is a key
+    fn next_pointer(&mut self, event: &Event) -> Option<String> {
+        match self.stack.last_mut() {
+            None => Some(String::new()),
+            Some(Collection::Mapping { pointer, key }) => match key {
+                Some(key) => Some(format!("{}/{}", pointer, escape_pointer_segment(key))),
+                None => {
+                    *key = match event {
+                        Event::Scalar(key, ..) => Some(key.clone()),
+                        _ => Some(String::new()),
+                    };
+                    None
+                }
+            },
+            Some(Collection::Sequence { pointer, index }) => Some(format!("{}/{}", pointer, index)),
+        }
+    }
+
+    /// A node of the collection being read is complete
+    fn complete(&mut self) {
+        match self.stack.last_mut() {
+            Some(Collection::Mapping { key, .. }) => *key = None,
+            Some(Collection::Sequence { index, .. }) => *index += 1,
+            None => {}
+        }
+    }
+}
+
+impl MarkedEventReceiver for Locator {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        match event {
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.stack.pop();
+                self.complete();
+                return;
+            }
+            Event::Scalar(..)
+            | Event::Alias(_)
+            | Event::MappingStart(_)
+            | Event::SequenceStart(_) => {}
+            _ => return,
+        }
+        // a block mapping is only known to start once its first key has been
+        // read, so it is marked after that key: it starts at the key instead
+        if let (Event::Scalar(..), Some(Collection::Mapping { pointer, key: None })) =
+            (&event, self.stack.last())
+        {
+            let start = (mark.line(), mark.col() + 1);
+            if let Some(position) = self.positions.get_mut(pointer) {
+                *position = start.min(*position);
+            }
+        }
+        let pointer = match self.next_pointer(&event) {
+            Some(pointer) => {
+                self.positions
+                    .entry(pointer.clone())
+                    .or_insert((mark.line(), mark.col() + 1));
+                pointer
+            }
+            // a key which is a collection (which OpenAPI has no use for) is
+            // read like a value, so that its end is found
+            None => match event {
+                Event::MappingStart(_) | Event::SequenceStart(_) => String::new(),
+                _ => return,
+            },
+        };
+        match event {
+            Event::MappingStart(_) => self.stack.push(Collection::Mapping { pointer, key: None }),
+            Event::SequenceStart(_) => self.stack.push(Collection::Sequence { pointer, index: 0 }),
+            _ => self.complete(),
+        }
+    }
+}
+
+/// Find the (deepest) node of a spec which `openapiv3` can't parse. Its errors
+/// don't say where they are.
+pub(crate) fn find_invalid_node(doc: &Value) -> Option<String> {
+    let child = |pointer: &str, key: &str| format!("{}/{}", pointer, escape_pointer_segment(key));
+    for (path, item) in entries(doc.get("paths")) {
+        let pointer = child("/paths", path);
+        if parses::<ReferenceOr<PathItem>>(item) {
+            continue;
+        }
+        for (key, value) in entries(Some(item)) {
+            let pointer = child(&pointer, key);
+            if key == "parameters" {
+                if let Some(found) = find_in_parameters(value, &pointer) {
+                    return Some(found);
+                }
+            } else if METHODS.contains(&key.as_str()) && !parses::<Operation>(value) {
+                return Some(find_in_operation(value, &pointer));
+            }
+        }
+        return Some(pointer);
+    }
+    for (name, schema) in entries(doc.pointer("/components/schemas")) {
+        if !parses::<ReferenceOr<Schema>>(schema) {
+            return Some(find_in_schema(schema, &child("/components/schemas", name)));
+        }
+    }
+    for (name, param) in entries(doc.pointer("/components/parameters")) {
+        if !parses::<ReferenceOr<Parameter>>(param) {
+            let pointer = child("/components/parameters", name);
+            return Some(find_in_parameter(param, &pointer));
+        }
+    }
+    for (name, body) in entries(doc.pointer("/components/requestBodies")) {
+        if !parses::<ReferenceOr<RequestBody>>(body) {
+            let pointer = child("/components/requestBodies", name);
+            return Some(find_in_content(body, &pointer).unwrap_or(pointer));
+        }
+    }
+    for (name, response) in entries(doc.pointer("/components/responses")) {
+        if !parses::<ReferenceOr<Response>>(response) {
+            let pointer = child("/components/responses", name);
+            return Some(find_in_content(response, &pointer).unwrap_or(pointer));
+        }
+    }
+    None
+}
+
+fn parses<T: DeserializeOwned>(node: &Value) -> bool {
+    serde_json::from_value::<T>(node.clone()).is_ok()
+}
+
+fn entries(node: Option<&Value>) -> impl Iterator<Item = (&String, &Value)> {
+    node.and_then(Value::as_object).into_iter().flatten()
+}
+
+fn find_in_operation(op: &Value, pointer: &str) -> String {
+    if let Some(found) = op
+        .get("parameters")
+        .and_then(|params| find_in_parameters(params, &format!("{}/parameters", pointer)))
+    {
+        return found;
+    }
+    if let Some(body) = op.get("requestBody") {
+        let body_pointer = format!("{}/requestBody", pointer);
+        if !parses::<ReferenceOr<RequestBody>>(body) {
+            return find_in_content(body, &body_pointer).unwrap_or(body_pointer);
+        }
+    }
+    for (status, response) in entries(op.get("responses")) {
+        let response_pointer = format!("{}/responses/{}", pointer, status);
+        if !parses::<ReferenceOr<Response>>(response) {
+            return find_in_content(response, &response_pointer).unwrap_or(response_pointer);
+        }
+    }
+    pointer.to_string()
+}
+
+fn find_in_parameters(params: &Value, pointer: &str) -> Option<String> {
+    let params = params.as_array()?;
+    params.iter().enumerate().find_map(|(ix, param)| {
+        if parses::<ReferenceOr<Parameter>>(param) {
+            None
+        } else {
+            Some(find_in_parameter(param, &format!("{}/{}", pointer, ix)))
+        }
+    })
+}
+
+fn find_in_parameter(param: &Value, pointer: &str) -> String {
+    match param.get("schema") {
+        Some(schema) if !parses::<ReferenceOr<Schema>>(schema) => {
+            find_in_schema(schema, &format!("{}/schema", pointer))
+        }
+        _ => pointer.to_string(),
+    }
+}
+
+fn find_in_content(node: &Value, pointer: &str) -> Option<String> {
+    entries(node.get("content")).find_map(|(media_type, media)| {
+        let schema = media.get("schema")?;
+        if parses::<ReferenceOr<Schema>>(schema) {
+            return None;
+        }
+        let pointer = format!(
+            "{}/content/{}/schema",
+            pointer,
+            escape_pointer_segment(media_type)
+        );
+        Some(find_in_schema(schema, &pointer))
+    })
+}
+
+/// The deepest subschema which doesn't parse, or the schema itself
+fn find_in_schema(schema: &Value, pointer: &str) -> String {
+    let mut children = Vec::new();
+    for (name, property) in entries(schema.get("properties")) {
+        children.push((
+            format!("{}/properties/{}", pointer, escape_pointer_segment(name)),
+            property,
+        ));
+    }
+    for key in &["items", "not", "additionalProperties"] {
+        match schema.get(*key) {
+            Some(child) if child.is_object() => {
+                children.push((format!("{}/{}", pointer, key), child))
+            }
+            _ => {}
+        }
+    }
+    for key in &["allOf", "oneOf", "anyOf"] {
+        let parts = schema.get(*key).and_then(Value::as_array);
+        for (ix, part) in parts.into_iter().flatten().enumerate() {
+            children.push((format!("{}/{}/{}", pointer, key, ix), part));
+        }
+    }
+    children
+        .into_iter()
+        .find(|(_, child)| !parses::<ReferenceOr<Schema>>(child))
+        .map(|(pointer, child)| find_in_schema(child, &pointer))
+        .unwrap_or_else(|| pointer.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::parse_spec;
+
+    #[test]
+    fn test_merge_keys() {
+        let mut doc: serde_yaml::Value = serde_yaml::from_str(
+            r#"
+base: &base { a: 1, b: 2 }
+other: &other { b: 3, c: 4 }
+one: { <<: *base, a: 0 }
+many: { <<: [*base, *other] }
+nested: { <<: { <<: *other, d: 5 } }
+"#,
+        )
+        .unwrap();
+        apply_merge_keys(&mut doc, "").unwrap();
+        let expected: serde_yaml::Value = serde_yaml::from_str(
+            r#"
+base: { a: 1, b: 2 }
+other: { b: 3, c: 4 }
+one: { a: 0, b: 2 }
+many: { a: 1, b: 2, c: 4 }
+nested: { d: 5, b: 3, c: 4 }
+"#,
+        )
+        .unwrap();
+        assert_eq!(doc, expected);
+    }
+
+    #[test]
+    fn test_error_locations() {
+        let spec = r#"openapi: "3.0.0"
+info: { title: Test, version: "1.0" }
+paths: {}
+components:
+  schemas:
+    Pet:
+      <<: 3
+"#;
+        let err = parse_spec(spec.as_bytes()).unwrap_err();
+        assert_eq!(err.pointer(), Some("/components/schemas/Pet/<<"));
+        assert_eq!(err.location(), Some((7, 11)));
+
+        // openapiv3 doesn't say where it failed, so we look for it
+        let spec = r#"openapi: "3.0.0"
+info: { title: Test, version: "1.0" }
+paths: {}
+components:
+  schemas:
+    Pet:
+      type: object
+      properties:
+        name:
+          type: string
+          nullable: maybe
+"#;
+        let err = parse_spec(spec.as_bytes()).unwrap_err();
+        assert_eq!(
+            err.pointer(),
+            Some("/components/schemas/Pet/properties/name")
+        );
+        assert_eq!(err.location(), Some((10, 11)));
+        assert!(err.to_string().ends_with("(line 10, column 11)"));
+    }
+}
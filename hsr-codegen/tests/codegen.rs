@@ -122,7 +122,7 @@ fn build_types_simple() {
         }
     }
     .to_string();
-    #[cfg(feature = "rustfmt")]
+    #[cfg(any(feature = "pretty", feature = "rustfmt"))]
     {
         expect = hsr_codegen::prettify_code(expect).unwrap();
     }
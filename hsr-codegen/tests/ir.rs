@@ -0,0 +1,78 @@
+//! The analysis of a spec, as other tools see it through `hsr_codegen::ir`
+
+use hsr_codegen::ir::{self, Method, MethodWithBody, MethodWithoutBody, Primitive, TypeInner};
+
+fn petstore() -> ir::ApiModel {
+    ir::parse_spec_file("../examples/petstore/petstore.yaml").unwrap()
+}
+
+#[test]
+fn routes() {
+    let model = petstore();
+    assert_eq!(model.trait_name.to_string(), "PetstoreApi");
+    assert_eq!(model.info.title, "Petstore");
+    let paths: Vec<_> = model.routes.keys().map(String::as_str).collect();
+    assert_eq!(paths, vec!["/pets", "/pets/{pet_id}"]);
+
+    let get = Method::WithoutBody(MethodWithoutBody::Get);
+    let route = model.find_route("/pets/{pet_id}", &get).unwrap();
+    assert_eq!(route.operation_id().to_string(), "get_pet");
+    assert_eq!(route.summary(), Some("Info for a specific pet"));
+    assert_eq!(route.tags(), &["pets".to_string()]);
+    let (meta, _) = &route.path_params().unwrap()[0];
+    assert!(meta.required());
+
+    let codes: Vec<_> = route
+        .responses()
+        .with_codes
+        .keys()
+        .map(|code| code.as_u16())
+        .collect();
+    assert_eq!(codes, vec![200, 404]);
+    // the body is an alias of the Pet schema
+    let ok = route.responses().with_codes.values().next().unwrap();
+    let body = ok.type_path.as_ref().unwrap();
+    assert_eq!(body.canonicalize().unwrap().to_string(), "GetPet200");
+    let pet = model.resolve(&model.types[body]).unwrap();
+    assert_eq!(
+        pet.meta().description(),
+        Some("A cat or a dog or a mouse or a rabbit")
+    );
+    assert!(route.responses().default.is_some());
+
+    let put = Method::WithBody {
+        method: MethodWithBody::Put,
+        body_type: None,
+    };
+    assert!(model.find_route("/pets/{pet_id}", &put).is_none());
+}
+
+#[test]
+fn types() {
+    let model = petstore();
+    let (_, pet) = model
+        .types
+        .iter()
+        .find(|(path, _)| path.segments() == ["components", "schemas", "Pet"])
+        .unwrap();
+    let pet = model.resolve(pet).unwrap();
+    assert_eq!(
+        pet.meta().description(),
+        Some("A cat or a dog or a mouse or a rabbit")
+    );
+    let fields = match pet.inner() {
+        TypeInner::Struct(pet) => pet.fields(),
+        other => panic!("Pet is a {:?}", other),
+    };
+    let names: Vec<_> = fields.keys().map(|name| name.to_string()).collect();
+    assert_eq!(names, vec!["id", "name", "tag"]);
+
+    let (meta, id) = &fields[0];
+    assert!(meta.required());
+    assert!(!fields[2].0.required());
+    let id = model.resolve(&model.types[id]).unwrap();
+    assert!(matches!(
+        id.inner(),
+        TypeInner::Primitive(Primitive::I64(_))
+    ));
+}
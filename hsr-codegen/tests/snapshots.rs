@@ -0,0 +1,75 @@
+//! Golden-file tests of the code generated for each of the example specs.
+//!
+//! The generated code is formatted and compared against `tests/snapshots/<name>.snap`.
+//! A missing snapshot is a failure. After an intentional change to the generated code
+//! (or to add a spec), rerun with `UPDATE_SNAPSHOTS=1` to write the snapshots, and
+//! check the diff in review.
+
+use std::fs;
+use std::path::Path;
+
+use yansi::Paint;
+
+const SPECS: &[(&str, &str)] = &[
+    ("bench", "../examples/bench/bench.yaml"),
+    ("petstore", "../examples/petstore/petstore.yaml"),
+    (
+        "petstore_expanded",
+        "../examples/petstore-expanded/petstore-expanded.yaml",
+    ),
+    ("quickstart", "../examples/quickstart/quickstart.yaml"),
+    ("tutorial", "../examples/tutorial/spec.yaml"),
+    ("test_spec", "../test/test-spec.yaml"),
+];
+
+fn assert_diff(left: &str, right: &str) {
+    use diff::Result::*;
+    if left == right {
+        return;
+    }
+    for d in diff::lines(left, right) {
+        match d {
+            Left(l) => println!("{}", Paint::red(format!("- {}", l))),
+            Right(r) => println!("{}", Paint::green(format!("+ {}", r))),
+            Both(l, _) => println!("= {}", l),
+        }
+    }
+    panic!("Bad diff")
+}
+
+fn generate(spec: &str) -> String {
+    let code = hsr_codegen::generate_from_yaml_file(spec).unwrap();
+    let file = syn::parse_file(&code).unwrap();
+    prettyplease::unparse(&file)
+}
+
+#[test]
+fn snapshots() {
+    let update = std::env::var_os("UPDATE_SNAPSHOTS").is_some();
+    for (name, spec) in SPECS {
+        let code = generate(spec);
+        let snapshot = Path::new("tests/snapshots").join(format!("{}.snap", name));
+        if update {
+            fs::create_dir_all(snapshot.parent().unwrap()).unwrap();
+            fs::write(&snapshot, &code).unwrap();
+            continue;
+        }
+        println!("Checking snapshot '{}'", name);
+        let expect = fs::read_to_string(&snapshot).unwrap_or_else(|_| {
+            panic!(
+                "Missing snapshot {}, rerun with UPDATE_SNAPSHOTS=1 to write it",
+                snapshot.display()
+            )
+        });
+        assert_diff(&expect, &code);
+    }
+}
+
+#[test]
+fn deterministic() {
+    for (_, spec) in SPECS {
+        let first = hsr_codegen::generate_from_yaml_file(spec).unwrap();
+        let second = hsr_codegen::generate_from_yaml_file(spec).unwrap();
+        assert_eq!(first, second, "Output for '{}' is not reproducible", spec);
+    }
+}
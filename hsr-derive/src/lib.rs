@@ -0,0 +1,160 @@
+//! Derive macros for hsr. Use them through the re-exports in `hsr`.
+
+extern crate proc_macro;
+
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::{
+    parse_macro_input, Attribute, Data, DeriveInput, Error, Fields, Ident, Lit, Meta, NestedMeta,
+};
+
+/// Derive `hsr::HasStatusCode`.
+///
+/// The status is given by a `#[status(...)]` attribute, on each variant of an enum or
+/// on the type itself (where it serves as the default for variants without one).
+/// The attribute takes one of:
+///
+/// * the name of a `StatusCode` constant, e.g. `#[status(NOT_FOUND)]`
+/// * a numeric code, e.g. `#[status(404)]`
+/// * a `u16` field holding the code, e.g. `#[status(field = "status_code")]`.
+///   Codes which are not valid become `500 Internal Server Error`.
+#[proc_macro_derive(HasStatusCode, attributes(status))]
+pub fn derive_has_status_code(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(&input)
+        .unwrap_or_else(|e| e.to_compile_error())
+        .into()
+}
+
+enum Status {
+    Code(TokenStream),
+    Field(Ident),
+}
+
+impl Status {
+    /// The expression giving the status code, assuming any field is bound by name
+    fn to_expr(&self) -> TokenStream {
+        match self {
+            Status::Code(code) => code.clone(),
+            Status::Field(field) => quote! {
+                ::hsr::actix_http::http::StatusCode::from_u16(*#field)
+                    .unwrap_or(::hsr::actix_http::http::StatusCode::INTERNAL_SERVER_ERROR)
+            },
+        }
+    }
+}
+
+fn parse_status(attrs: &[Attribute]) -> syn::Result<Option<Status>> {
+    let mut status = None;
+    for attr in attrs.iter().filter(|attr| attr.path.is_ident("status")) {
+        if status.is_some() {
+            return Err(Error::new(attr.span(), "duplicate #[status] attribute"));
+        }
+        let list = match attr.parse_meta()? {
+            Meta::List(list) if list.nested.len() == 1 => list,
+            meta => return Err(Error::new(meta.span(), "expected #[status(...)]")),
+        };
+        let parsed = match &list.nested[0] {
+            NestedMeta::Meta(Meta::Path(path)) => match path.get_ident() {
+                Some(ident) => Status::Code(quote! { ::hsr::actix_http::http::StatusCode::#ident }),
+                None => return Err(Error::new(path.span(), "expected a StatusCode constant")),
+            },
+            NestedMeta::Lit(Lit::Int(lit)) => {
+                let code: u16 = lit.base10_parse()?;
+                if code < 100 || code > 999 {
+                    return Err(Error::new(lit.span(), "invalid status code"));
+                }
+                Status::Code(quote! {
+                    ::hsr::actix_http::http::StatusCode::from_u16(#code).unwrap()
+                })
+            }
+            NestedMeta::Meta(Meta::NameValue(nv)) if nv.path.is_ident("field") => match &nv.lit {
+                Lit::Str(field) => Status::Field(field.parse()?),
+                lit => return Err(Error::new(lit.span(), "expected a field name")),
+            },
+            nested => {
+                return Err(Error::new(
+                    nested.span(),
+                    "expected a StatusCode constant, a status code or `field = \"...\"`",
+                ))
+            }
+        };
+        status = Some(parsed);
+    }
+    Ok(status)
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<TokenStream> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let default = parse_status(&input.attrs)?;
+
+    let body = match &input.data {
+        Data::Struct(_) => match &default {
+            Some(Status::Code(code)) => code.clone(),
+            Some(Status::Field(field)) => {
+                let expr = Status::Field(field.clone()).to_expr();
+                quote! {
+                    let #name { #field, .. } = self;
+                    #expr
+                }
+            }
+            None => return Err(Error::new(name.span(), "missing #[status(...)] attribute")),
+        },
+        Data::Enum(data) => {
+            let mut arms = Vec::new();
+            for variant in &data.variants {
+                let vname = &variant.ident;
+                let status = match (parse_status(&variant.attrs)?, &default) {
+                    (Some(status), _) => status,
+                    (None, Some(Status::Code(code))) => Status::Code(code.clone()),
+                    (None, Some(Status::Field(field))) => Status::Field(field.clone()),
+                    (None, None) => {
+                        return Err(Error::new(
+                            variant.span(),
+                            "missing #[status(...)] attribute",
+                        ))
+                    }
+                };
+                let pattern = match (&status, &variant.fields) {
+                    (Status::Field(field), Fields::Named(_)) => {
+                        quote! { Self::#vname { #field, .. } }
+                    }
+                    (Status::Field(field), _) => {
+                        return Err(Error::new(
+                            field.span(),
+                            "`field` requires a variant with named fields",
+                        ))
+                    }
+                    (Status::Code(_), _) => quote! { Self::#vname { .. } },
+                };
+                let expr = status.to_expr();
+                arms.push(quote! { #pattern => #expr });
+            }
+            if arms.is_empty() {
+                quote! { match *self {} }
+            } else {
+                quote! {
+                    match self {
+                        #(#arms,)*
+                    }
+                }
+            }
+        }
+        Data::Union(_) => {
+            return Err(Error::new(
+                name.span(),
+                "HasStatusCode cannot be derived for unions",
+            ))
+        }
+    };
+
+    Ok(quote! {
+        impl #impl_generics ::hsr::HasStatusCode for #name #ty_generics #where_clause {
+            fn status_code(&self) -> ::hsr::actix_http::http::StatusCode {
+                #body
+            }
+        }
+    })
+}
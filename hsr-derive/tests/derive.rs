@@ -0,0 +1,64 @@
+use hsr::actix_http::http::StatusCode;
+use hsr::HasStatusCode;
+
+#[allow(dead_code)]
+#[derive(HasStatusCode)]
+#[status(BAD_REQUEST)]
+enum Failure {
+    #[status(404)]
+    NotFound,
+    Invalid(String),
+    #[status(field = "code")]
+    Other {
+        code: u16,
+        message: String,
+    },
+}
+
+#[derive(HasStatusCode)]
+#[status(CREATED)]
+struct Created;
+
+#[derive(HasStatusCode)]
+#[status(field = "status")]
+struct Custom {
+    status: u16,
+}
+
+#[derive(HasStatusCode)]
+enum Never {}
+
+#[test]
+fn enum_statuses() {
+    assert_eq!(Failure::NotFound.status_code(), StatusCode::NOT_FOUND);
+    // the status of the type is the default for its variants
+    assert_eq!(
+        Failure::Invalid("name".into()).status_code(),
+        StatusCode::BAD_REQUEST
+    );
+    let other = Failure::Other {
+        code: 409,
+        message: "conflict".into(),
+    };
+    assert_eq!(other.status_code(), StatusCode::CONFLICT);
+}
+
+#[test]
+fn struct_statuses() {
+    assert_eq!(Created.status_code(), StatusCode::CREATED);
+    assert_eq!(
+        Custom { status: 418 }.status_code(),
+        StatusCode::IM_A_TEAPOT
+    );
+    // codes which are not valid are server errors
+    assert_eq!(
+        Custom { status: 42 }.status_code(),
+        StatusCode::INTERNAL_SERVER_ERROR
+    );
+    let _: fn(&Never) -> StatusCode = Never::status_code;
+}
+
+#[test]
+fn compile_errors() {
+    trybuild::TestCases::new().compile_fail("tests/ui/*.rs");
+}
@@ -0,0 +1,13 @@
+use hsr::HasStatusCode;
+
+#[derive(HasStatusCode)]
+#[status(1000)]
+struct TooBig;
+
+#[derive(HasStatusCode)]
+enum Tuple {
+    #[status(field = "code")]
+    Other(u16),
+}
+
+fn main() {}
@@ -0,0 +1,10 @@
+use hsr::HasStatusCode;
+
+#[derive(HasStatusCode)]
+enum Failure {
+    #[status(404)]
+    NotFound,
+    Invalid(String),
+}
+
+fn main() {}
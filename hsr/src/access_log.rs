@@ -0,0 +1,304 @@
+//! Structured access logging.
+//!
+//! Each request is logged as a single line of JSON (at `info` level, with target
+//! `hsr::access_log`), for consumption by log aggregators:
+//!
+//! ```text
+//! {"ts":1589371200123,"method":"GET","path":"/pets/1","operation_id":"getPet","status":200,"latency_ms":0.412,"request_id":"..."}
+//! ```
+//!
+//! Sensitive headers are redacted if request headers are logged.
+//!
+//! `AccessLog` is installed around the whole app, so that every request is logged,
+//! including those for paths the API doesn't have. The generated resources tell it
+//! the operation of their requests with `LogOperations`.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use actix_http::http::{HeaderMap, Method};
+use actix_service::{Service, Transform};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::{Error as ActixError, HttpMessage};
+use futures::future::{ok, FutureExt, LocalBoxFuture, Ready};
+use serde_json::{json, Map, Value};
+
+use crate::request_id::{RequestId, REQUEST_ID_HEADER};
+
+const REDACTED: &str = "[redacted]";
+
+/// Configuration for the access log, and the middleware which writes it.
+///
+/// Set `Config::access_log` to replace the default `actix` logger with this.
+#[derive(Debug, Clone)]
+pub struct AccessLog {
+    log_headers: bool,
+    redact: Arc<Vec<String>>,
+}
+
+impl Default for AccessLog {
+    fn default() -> Self {
+        Self {
+            log_headers: false,
+            redact: Arc::new(
+                [
+                    "authorization",
+                    "proxy-authorization",
+                    "cookie",
+                    "set-cookie",
+                ]
+                .iter()
+                .map(|h| h.to_string())
+                .collect(),
+            ),
+        }
+    }
+}
+
+impl AccessLog {
+    /// Log requests without headers, redacting `Authorization`, `Proxy-Authorization`,
+    /// `Cookie` and `Set-Cookie` if headers are enabled later
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Include the request headers in the log
+    pub fn log_headers(mut self, log_headers: bool) -> Self {
+        self.log_headers = log_headers;
+        self
+    }
+
+    /// Replace the value of a header with a placeholder, when logging headers
+    pub fn redact(mut self, header: &str) -> Self {
+        Arc::make_mut(&mut self.redact).push(header.to_ascii_lowercase());
+        self
+    }
+
+    fn headers(&self, headers: &HeaderMap) -> Value {
+        let mut map = Map::new();
+        for (name, value) in headers.iter() {
+            let value = if self.redact.iter().any(|r| r == name.as_str()) {
+                REDACTED.into()
+            } else {
+                String::from_utf8_lossy(value.as_bytes()).into_owned()
+            };
+            map.insert(name.as_str().into(), Value::String(value));
+        }
+        Value::Object(map)
+    }
+}
+
+impl<S, B> Transform<S> for AccessLog
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = ActixError>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = ActixError;
+    type InitError = ();
+    type Transform = AccessLogMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AccessLogMiddleware {
+            service,
+            config: self.clone(),
+        })
+    }
+}
+
+pub struct AccessLogMiddleware<S> {
+    service: S,
+    config: AccessLog,
+}
+
+impl<S, B> Service for AccessLogMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = ActixError>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = ActixError;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let start = Instant::now();
+        let mut entry = Map::new();
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_millis() as u64)
+            .unwrap_or(0);
+        entry.insert("ts".into(), json!(ts));
+        entry.insert("method".into(), json!(req.method().as_str()));
+        entry.insert("path".into(), json!(req.path()));
+        let operation = LoggedOperation::default();
+        req.extensions_mut().insert(operation.clone());
+        if let Some(peer) = crate::forwarded::client_ip(&req) {
+            entry.insert("peer".into(), json!(peer.to_string()));
+        }
+        if self.config.log_headers {
+            entry.insert("headers".into(), self.config.headers(req.headers()));
+        }
+        let request_id = req
+            .extensions()
+            .get::<RequestId>()
+            .map(|id| id.to_string())
+            .or_else(|| {
+                req.headers()
+                    .get(REQUEST_ID_HEADER)
+                    .and_then(|id| id.to_str().ok())
+                    .map(String::from)
+            });
+
+        let fut = self.service.call(req);
+        async move {
+            let res = fut.await;
+            let latency = start.elapsed().as_secs_f64() * 1000.0;
+            match &res {
+                Ok(res) => {
+                    entry.insert("status".into(), json!(res.status().as_u16()));
+                    // a request id may have been assigned while handling the request
+                    let request_id = res
+                        .headers()
+                        .get(REQUEST_ID_HEADER)
+                        .and_then(|id| id.to_str().ok())
+                        .map(String::from)
+                        .or(request_id);
+                    if let Some(id) = request_id {
+                        entry.insert("request_id".into(), json!(id));
+                    }
+                }
+                Err(e) => {
+                    let status = e.as_response_error().status_code();
+                    entry.insert("status".into(), json!(status.as_u16()));
+                    entry.insert("error".into(), json!(e.to_string()));
+                    if let Some(id) = request_id {
+                        entry.insert("request_id".into(), json!(id));
+                    }
+                }
+            }
+            if let Some(opid) = operation.0.borrow_mut().take() {
+                entry.insert("operation_id".into(), json!(opid));
+            }
+            entry.insert("latency_ms".into(), json!(latency));
+            log::info!(target: "hsr::access_log", "{}", Value::Object(entry));
+            res
+        }
+        .boxed_local()
+    }
+}
+
+/// Where the operation of a request is noted, for the access log
+#[derive(Debug, Clone, Default)]
+struct LoggedOperation(Rc<RefCell<Option<String>>>);
+
+/// Middleware noting the operation of each request to a resource, by method, for
+/// the access log. Used by the generated code.
+#[derive(Debug, Clone)]
+pub struct LogOperations {
+    operations: Arc<Vec<(Method, String)>>,
+}
+
+impl LogOperations {
+    pub fn new(operations: &[(Method, &str)]) -> Self {
+        Self {
+            operations: Arc::new(
+                operations
+                    .iter()
+                    .map(|(method, opid)| (method.clone(), opid.to_string()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl<S, B> Transform<S> for LogOperations
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = ActixError>,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = ActixError;
+    type InitError = ();
+    type Transform = LogOperationsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(LogOperationsMiddleware {
+            service,
+            operations: self.operations.clone(),
+        })
+    }
+}
+
+pub struct LogOperationsMiddleware<S> {
+    service: S,
+    operations: Arc<Vec<(Method, String)>>,
+}
+
+impl<S, B> Service for LogOperationsMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = ActixError>,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = ActixError;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let opid = self
+            .operations
+            .iter()
+            .find(|(method, _)| method == req.method())
+            .map(|(_, opid)| opid.clone());
+        if let (Some(opid), Some(operation)) = (opid, req.extensions().get::<LoggedOperation>()) {
+            *operation.0.borrow_mut() = Some(opid);
+        }
+        self.service.call(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_http::http::{HeaderName, HeaderValue};
+
+    #[test]
+    fn redact_headers() {
+        let log = AccessLog::new().redact("X-Api-Key");
+        let mut headers = HeaderMap::new();
+        for (name, value) in &[
+            ("authorization", "Bearer secret"),
+            ("x-api-key", "secret"),
+            ("accept", "application/json"),
+        ] {
+            headers.insert(
+                HeaderName::from_static(*name),
+                HeaderValue::from_static(*value),
+            );
+        }
+        assert_eq!(
+            log.headers(&headers),
+            json!({
+                "authorization": REDACTED,
+                "x-api-key": REDACTED,
+                "accept": "application/json",
+            })
+        );
+    }
+}
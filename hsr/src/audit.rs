@@ -0,0 +1,124 @@
+//! Audit trails, for operations marked `x-hsr-audit: true`.
+//!
+//! Once such an operation has finished, the dispatcher calls the `audit` method of
+//! the API with an `AuditEvent`: the operation, who called it, a digest of its
+//! parameters and the status of the response. By default the event is logged (with
+//! the `hsr::audit` target); override `audit` to write it somewhere durable.
+//!
+//! The caller is only known if the authentication of the application (a middleware)
+//! has stored an `Actor` in the request extensions.
+
+use std::fmt::Write;
+use std::time::SystemTime;
+
+use actix_http::http::StatusCode;
+use actix_web::HttpRequest;
+use serde::Serialize;
+
+use crate::request_id::RequestId;
+
+/// Who made a request, as stored in the request extensions by authentication
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Actor(pub String);
+
+/// A call of an audited operation
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEvent {
+    pub operation_id: &'static str,
+    /// Who called the operation, if known
+    pub actor: Option<String>,
+    /// The SHA-256 (in hex) of the parameters and body of the call, as JSON. Equal
+    /// calls have equal digests, without the trail holding sensitive values.
+    pub params_digest: String,
+    pub status: u16,
+    pub request_id: Option<RequestId>,
+    /// When the call started
+    pub started: SystemTime,
+}
+
+/// An audited call in progress. Used by the generated dispatchers.
+#[derive(Debug)]
+pub struct Audit {
+    operation_id: &'static str,
+    actor: Option<String>,
+    params_digest: String,
+    request_id: Option<RequestId>,
+    started: SystemTime,
+}
+
+impl Audit {
+    /// Start auditing a call, with its (typed) parameters
+    pub fn start<P: Serialize>(operation_id: &'static str, req: &HttpRequest, params: &P) -> Self {
+        let extensions = req.extensions();
+        Self {
+            operation_id,
+            actor: extensions.get::<Actor>().map(|actor| actor.0.clone()),
+            params_digest: digest(params),
+            request_id: extensions.get::<RequestId>().cloned(),
+            started: SystemTime::now(),
+        }
+    }
+
+    /// Finish auditing a call, once it has its response
+    pub fn finish(self, status: StatusCode) -> AuditEvent {
+        AuditEvent {
+            operation_id: self.operation_id,
+            actor: self.actor,
+            params_digest: self.params_digest,
+            status: status.as_u16(),
+            request_id: self.request_id,
+            started: self.started,
+        }
+    }
+}
+
+/// The SHA-256 (in hex) of a value as JSON
+pub fn digest<P: Serialize>(params: &P) -> String {
+    let json = serde_json::to_vec(params).unwrap_or_default();
+    let mut hex = String::with_capacity(64);
+    for byte in openssl::sha::sha256(&json).iter() {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    hex
+}
+
+/// The default audit trail: a log line per call
+pub fn log(event: &AuditEvent) {
+    log::info!(
+        target: "hsr::audit",
+        "{} by {} ({}): {} [request {}]",
+        event.operation_id,
+        event.actor.as_deref().unwrap_or("anonymous"),
+        event.params_digest,
+        event.status,
+        event
+            .request_id
+            .as_ref()
+            .map(|id| id.as_str())
+            .unwrap_or("-")
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn audit_event() {
+        let req = TestRequest::default().to_http_request();
+        req.extensions_mut().insert(Actor("alice".into()));
+        let audit = Audit::start("delete_pet", &req, &(&1, &"reason"));
+        let event = audit.finish(StatusCode::NO_CONTENT);
+        assert_eq!(event.operation_id, "delete_pet");
+        assert_eq!(event.actor.as_deref(), Some("alice"));
+        assert_eq!(event.status, 204);
+        assert_eq!(event.params_digest, digest(&(1, "reason")));
+        assert_ne!(event.params_digest, digest(&(2, "reason")));
+        // sha256("null")
+        assert_eq!(
+            digest(&()),
+            "74234e98afe7498fb5daf1f36ac2d78acc339464f950703b8c019892f982b90b"
+        );
+    }
+}
@@ -0,0 +1,187 @@
+//! A policy enforcement point for the whole API.
+//!
+//! With `GenerationOptions::authorization`, the dispatcher of every operation calls
+//! the `authorize` method of the API before the operation, with an `AuthzInput`:
+//! the operation, its tags and security requirements from the spec, its parameters
+//! and the request. Role or attribute based decisions can then be made in one place,
+//! driven by the spec, rather than by checks scattered through the handlers.
+//!
+//! ```ignore
+//! async fn authorize(&self, input: hsr::authz::AuthzInput) -> Result<(), hsr::authz::Denied> {
+//!     let user = self.users.authenticate(&input.context).await.ok_or(Denied::Unauthenticated)?;
+//!     if input.has_tag("admin") && !user.is_admin {
+//!         return Err(Denied::Forbidden(format!("{} is not an admin", user.name)));
+//!     }
+//!     if !input.scopes_granted(&user.scopes()) {
+//!         return Err(Denied::Forbidden("missing scopes".into()));
+//!     }
+//!     Ok(())
+//! }
+//! ```
+//!
+//! The calls of `client::InProcess` don't go through the dispatcher, so are not
+//! authorized.
+
+use std::fmt;
+
+use actix_http::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+use crate::audit::Actor;
+use crate::operation::SecurityRequirement;
+use crate::{OperationMeta, Problem, RequestContext};
+
+/// What the policy is asked about a call of an operation
+#[derive(Debug, Clone)]
+pub struct AuthzInput {
+    pub operation_id: &'static str,
+    pub tags: &'static [&'static str],
+    /// The alternative security requirements of the operation (any one will do),
+    /// each a list of schemes with the scopes they need. Empty if the operation is
+    /// not secured.
+    pub security_scopes: &'static [&'static [SecurityRequirement]],
+    /// The path and query parameters, by name
+    pub params: Map<String, Value>,
+    /// The request body, or null if there is none
+    pub body: Value,
+    /// The request, for its headers, peer address and extensions
+    pub context: RequestContext,
+}
+
+impl AuthzInput {
+    /// The input for a call of `op`, without parameters. Used by the generated dispatchers.
+    pub fn new(op: OperationMeta, req: &HttpRequest) -> Self {
+        Self {
+            operation_id: op.operation_id,
+            tags: op.tags,
+            security_scopes: op.security,
+            params: Map::new(),
+            body: Value::Null,
+            context: RequestContext::from(req.clone()),
+        }
+    }
+
+    /// Add the fields of a (path or query) parameters struct
+    pub fn with_params<P: Serialize>(mut self, params: &P) -> Self {
+        if let Ok(Value::Object(fields)) = serde_json::to_value(params) {
+            self.params.extend(fields);
+        }
+        self
+    }
+
+    pub fn with_body<B: Serialize>(mut self, body: &B) -> Self {
+        self.body = serde_json::to_value(body).unwrap_or(Value::Null);
+        self
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.contains(&tag)
+    }
+
+    /// Whether `granted` holds every scope of one of the security requirements of
+    /// the operation (whatever their scheme). True if the operation is not secured.
+    pub fn scopes_granted(&self, granted: &[&str]) -> bool {
+        self.security_scopes.is_empty()
+            || self.security_scopes.iter().any(|requirement| {
+                requirement
+                    .iter()
+                    .flat_map(|scheme| scheme.scopes)
+                    .all(|scope| granted.contains(scope))
+            })
+    }
+
+    /// Who is calling, if the authentication of the application (a middleware) has
+    /// stored an `Actor` in the request extensions
+    pub fn actor(&self) -> Option<Actor> {
+        self.context.extensions().get::<Actor>().cloned()
+    }
+}
+
+/// Why a call was refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Denied {
+    /// The caller didn't say who they are, or couldn't prove it. Gives a 401.
+    Unauthenticated,
+    /// The caller may not make the call, for the given reason. Gives a 403; the
+    /// reason is logged, but not sent to the caller.
+    Forbidden(String),
+}
+
+impl fmt::Display for Denied {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Denied::Unauthenticated => write!(f, "unauthenticated"),
+            Denied::Forbidden(reason) => write!(f, "forbidden: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for Denied {}
+
+impl ResponseError for Denied {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Denied::Unauthenticated => StatusCode::UNAUTHORIZED,
+            Denied::Forbidden(_) => StatusCode::FORBIDDEN,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        log::info!("Call denied, {}", self);
+        Problem::new(self.status_code()).to_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[test]
+    fn authz_input() {
+        let op = OperationMeta {
+            operation_id: "deletePet",
+            method: "DELETE",
+            path: "/pets/{id}",
+            tags: &["pets", "admin"],
+            deprecated: false,
+            security: &[
+                &[SecurityRequirement {
+                    scheme: "oauth",
+                    scopes: &["pets:read", "pets:write"],
+                }],
+                &[SecurityRequirement {
+                    scheme: "apiKey",
+                    scopes: &[],
+                }],
+            ],
+        };
+        let req = TestRequest::default().to_http_request();
+        req.extensions_mut().insert(Actor("alice".into()));
+        let input = AuthzInput::new(op, &req)
+            .with_params(&serde_json::json!({ "id": 1 }))
+            .with_params(&serde_json::json!({ "reason": "gone" }))
+            .with_body(&"body");
+        assert_eq!(input.params["id"], 1);
+        assert_eq!(input.params["reason"], "gone");
+        assert_eq!(input.body, "body");
+        assert!(input.has_tag("admin"));
+        assert_eq!(input.actor(), Some(Actor("alice".into())));
+        // the API key needs no scopes
+        assert!(input.scopes_granted(&[]));
+
+        let input = AuthzInput {
+            security_scopes: &op.security[..1],
+            ..input
+        };
+        assert!(!input.scopes_granted(&["pets:read"]));
+        assert!(input.scopes_granted(&["pets:read", "pets:write"]));
+
+        let resp = Denied::Forbidden("not an admin".into()).error_response();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+        let resp = Denied::Unauthenticated.error_response();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,429 @@
+//! HTTP caching, for operations with an `x-hsr-cache` policy.
+//!
+//! On the server, `CacheHeaders` sets `Cache-Control` and `Expires` on the successful
+//! responses of those operations (unless the handler has set `Cache-Control` itself).
+//! On the client, a `ResponseCache` keeps responses for as long as their
+//! `Cache-Control: max-age` allows, so repeated calls don't hit the network.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant, SystemTime};
+
+use actix_http::http::header::{
+    HttpDate, AUTHORIZATION, CACHE_CONTROL, COOKIE, EXPIRES, PROXY_AUTHORIZATION, VARY,
+};
+use actix_http::http::{HeaderMap, HeaderName, HeaderValue, Method};
+use actix_service::{Service, Transform};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::Error as ActixError;
+use bytes::Bytes;
+use futures::future::{ok, FutureExt, LocalBoxFuture, Ready};
+
+/// How the responses to an operation may be cached
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CachePolicy {
+    /// How long responses stay fresh. `None` means they must not be stored at all.
+    pub max_age: Option<Duration>,
+    /// Only the client may cache responses, not shared caches (proxies, CDNs)
+    pub private: bool,
+    /// Shared caches may store responses even to requests with an `Authorization`
+    /// header, which they otherwise don't
+    pub public: bool,
+}
+
+impl CachePolicy {
+    /// Responses may be cached for `max_age`
+    pub fn max_age(max_age: Duration) -> Self {
+        Self {
+            max_age: Some(max_age),
+            private: false,
+            public: false,
+        }
+    }
+
+    /// Responses must not be cached
+    pub fn no_store() -> Self {
+        Self {
+            max_age: None,
+            private: false,
+            public: false,
+        }
+    }
+
+    pub fn private(mut self) -> Self {
+        self.private = true;
+        self.public = false;
+        self
+    }
+
+    pub fn public(mut self) -> Self {
+        self.public = true;
+        self.private = false;
+        self
+    }
+
+    /// The value of the `Cache-Control` header
+    pub fn cache_control(&self) -> String {
+        match self.max_age {
+            None => "no-store".into(),
+            Some(max_age) if self.private => format!("private, max-age={}", max_age.as_secs()),
+            Some(max_age) if self.public => format!("public, max-age={}", max_age.as_secs()),
+            Some(max_age) => format!("max-age={}", max_age.as_secs()),
+        }
+    }
+}
+
+/// How long a response may be cached for, according to its `Cache-Control` header
+pub fn freshness(headers: &HeaderMap) -> Option<Duration> {
+    let mut max_age = None;
+    for value in headers.get_all(CACHE_CONTROL) {
+        for directive in value.to_str().ok()?.split(',') {
+            let directive = directive.trim().to_ascii_lowercase();
+            if directive == "no-store" || directive == "no-cache" {
+                return None;
+            }
+            if let Some(secs) = directive.strip_prefix("max-age=") {
+                max_age = secs.trim_matches('"').parse().ok().map(Duration::from_secs);
+            }
+        }
+    }
+    max_age.filter(|age| *age > Duration::from_secs(0))
+}
+
+/// Middleware applying the cache policies of the operations of a resource
+#[derive(Debug, Clone, Default)]
+pub struct CacheHeaders {
+    policies: Arc<Vec<(Method, CachePolicy)>>,
+}
+
+impl CacheHeaders {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Apply a policy to an operation
+    pub fn policy(mut self, method: Method, policy: CachePolicy) -> Self {
+        Arc::make_mut(&mut self.policies).push((method, policy));
+        self
+    }
+}
+
+impl<S, B> Transform<S> for CacheHeaders
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = ActixError>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = ActixError;
+    type InitError = ();
+    type Transform = CacheHeadersMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(CacheHeadersMiddleware {
+            service,
+            policies: self.policies.clone(),
+        })
+    }
+}
+
+pub struct CacheHeadersMiddleware<S> {
+    service: S,
+    policies: Arc<Vec<(Method, CachePolicy)>>,
+}
+
+impl<S, B> Service for CacheHeadersMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = ActixError>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = ActixError;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let policy = self
+            .policies
+            .iter()
+            .find(|(method, _)| method == req.method())
+            .map(|(_, policy)| policy.clone());
+        let fut = self.service.call(req);
+        async move {
+            let mut res = fut.await?;
+            let policy = match policy {
+                Some(policy) => policy,
+                None => return Ok(res),
+            };
+            if !res.status().is_success() || res.headers().contains_key(CACHE_CONTROL) {
+                return Ok(res);
+            }
+            let headers = res.headers_mut();
+            if let Ok(value) = HeaderValue::from_str(&policy.cache_control()) {
+                headers.insert(CACHE_CONTROL, value);
+            }
+            if let Some(max_age) = policy.max_age {
+                let expires = HttpDate::from(SystemTime::now() + max_age).to_string();
+                if let Ok(value) = HeaderValue::from_str(&expires) {
+                    headers.insert(EXPIRES, value);
+                }
+            }
+            Ok(res)
+        }
+        .boxed_local()
+    }
+}
+
+/// The request headers which always tell responses apart, whatever their `Vary`
+/// header: a response for one caller's credentials is never given to another
+pub(crate) const CREDENTIAL_HEADERS: &[HeaderName] = &[AUTHORIZATION, PROXY_AUTHORIZATION, COOKIE];
+
+/// What a response is cached for: the URL of the request, and its headers, which
+/// are compared with the credentials and the `Vary` headers of cached responses
+#[derive(Debug, Clone)]
+pub struct CacheKey {
+    url: String,
+    headers: HeaderMap,
+}
+
+impl CacheKey {
+    pub fn new(url: &str, headers: &HeaderMap) -> Self {
+        Self {
+            url: url.into(),
+            headers: headers.clone(),
+        }
+    }
+
+    /// The values of the request header `name`
+    fn values(&self, name: &HeaderName) -> Vec<HeaderValue> {
+        self.headers.get_all(name).cloned().collect()
+    }
+}
+
+/// An in-memory cache of responses, shared between clones.
+/// Responses are kept by URL, for the credentials (`Authorization`,
+/// `Proxy-Authorization` and `Cookie`) and the headers named by their `Vary`
+/// header of the request they answered, so only use it for `GET` requests.
+#[derive(Debug, Clone, Default)]
+pub struct ResponseCache {
+    entries: Arc<Mutex<HashMap<String, Vec<Entry>>>>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// The request headers the response is for, with their values
+    request: Vec<(HeaderName, Vec<HeaderValue>)>,
+    status: u16,
+    body: Bytes,
+    expires: Instant,
+}
+
+impl Entry {
+    fn answers(&self, key: &CacheKey) -> bool {
+        self.request
+            .iter()
+            .all(|(name, values)| key.values(name) == *values)
+    }
+}
+
+impl ResponseCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The status and body of a fresh response to the request `key`, if any
+    pub fn get(&self, key: &CacheKey) -> Option<(u16, Bytes)> {
+        self.get_at(key, Instant::now())
+    }
+
+    /// Store a response to the request `key`, with the response `headers`, fresh for
+    /// `ttl`. A response with `Vary: *` isn't stored.
+    pub fn put(
+        &self,
+        key: &CacheKey,
+        headers: &HeaderMap,
+        status: u16,
+        body: Bytes,
+        ttl: Duration,
+    ) {
+        self.put_at(key, headers, status, body, ttl, Instant::now())
+    }
+
+    /// Forget all responses
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear()
+    }
+
+    fn get_at(&self, key: &CacheKey, now: Instant) -> Option<(u16, Bytes)> {
+        let entries = self.entries.lock().unwrap();
+        entries
+            .get(&key.url)?
+            .iter()
+            .find(|entry| entry.expires > now && entry.answers(key))
+            .map(|entry| (entry.status, entry.body.clone()))
+    }
+
+    fn put_at(
+        &self,
+        key: &CacheKey,
+        headers: &HeaderMap,
+        status: u16,
+        body: Bytes,
+        ttl: Duration,
+        now: Instant,
+    ) {
+        let mut names = CREDENTIAL_HEADERS.to_vec();
+        for value in headers.get_all(VARY) {
+            let value = match value.to_str() {
+                Ok(value) => value,
+                // the response can't be told apart from others
+                Err(_) => return,
+            };
+            for name in value
+                .split(',')
+                .map(str::trim)
+                .filter(|name| !name.is_empty())
+            {
+                match HeaderName::from_bytes(name.as_bytes()) {
+                    Ok(name) if !names.contains(&name) => names.push(name),
+                    Ok(_) => {}
+                    // including `Vary: *`, which varies with more than the headers
+                    Err(_) => return,
+                }
+            }
+        }
+        let entry = Entry {
+            request: names
+                .into_iter()
+                .map(|name| {
+                    let values = key.values(&name);
+                    (name, values)
+                })
+                .collect(),
+            status,
+            body,
+            expires: now + ttl,
+        };
+
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entries| {
+            entries.retain(|entry| entry.expires > now);
+            !entries.is_empty()
+        });
+        let entries = entries.entry(key.url.clone()).or_default();
+        entries.retain(|old| !old.answers(key));
+        entries.push(entry);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_control() {
+        let policy = CachePolicy::max_age(Duration::from_secs(60));
+        // shared caches keep the default of not storing authorized responses
+        assert_eq!(policy.cache_control(), "max-age=60");
+        assert_eq!(
+            policy.clone().private().cache_control(),
+            "private, max-age=60"
+        );
+        assert_eq!(policy.public().cache_control(), "public, max-age=60");
+        assert_eq!(CachePolicy::no_store().cache_control(), "no-store");
+    }
+
+    #[test]
+    fn parse_freshness() {
+        let fresh = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(CACHE_CONTROL, HeaderValue::from_static(value));
+            freshness(&headers)
+        };
+        assert_eq!(fresh("public, max-age=60"), Some(Duration::from_secs(60)));
+        assert_eq!(fresh("Max-Age=5"), Some(Duration::from_secs(5)));
+        assert_eq!(fresh("max-age=0"), None);
+        assert_eq!(fresh("no-store"), None);
+        assert_eq!(fresh("max-age=60, no-cache"), None);
+        assert_eq!(freshness(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn response_cache() {
+        let cache = ResponseCache::new();
+        let now = Instant::now();
+        let ttl = Duration::from_secs(10);
+        let none = HeaderMap::new();
+        let a = CacheKey::new("/a", &none);
+        cache.put_at(&a, &none, 200, Bytes::from_static(b"1"), ttl, now);
+        assert_eq!(cache.get_at(&a, now), Some((200, Bytes::from_static(b"1"))));
+        assert_eq!(cache.get_at(&CacheKey::new("/b", &none), now), None);
+        assert_eq!(cache.get_at(&a, now + ttl), None);
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(
+                HeaderName::from_static(name),
+                HeaderValue::from_static(value),
+            );
+        }
+        headers
+    }
+
+    #[test]
+    fn responses_are_kept_per_credentials() {
+        let cache = ResponseCache::new();
+        let now = Instant::now();
+        let ttl = Duration::from_secs(10);
+        let alice = CacheKey::new("/a", &headers(&[("authorization", "Bearer alice")]));
+        let bob = CacheKey::new("/a", &headers(&[("authorization", "Bearer bob")]));
+        let anonymous = CacheKey::new("/a", &HeaderMap::new());
+        cache.put_at(&alice, &HeaderMap::new(), 200, "alice".into(), ttl, now);
+        assert_eq!(cache.get_at(&alice, now), Some((200, "alice".into())));
+        assert_eq!(cache.get_at(&bob, now), None);
+        assert_eq!(cache.get_at(&anonymous, now), None);
+
+        cache.put_at(&bob, &HeaderMap::new(), 200, "bob".into(), ttl, now);
+        assert_eq!(cache.get_at(&alice, now), Some((200, "alice".into())));
+        assert_eq!(cache.get_at(&bob, now), Some((200, "bob".into())));
+
+        let cookie = CacheKey::new("/a", &headers(&[("cookie", "session=1")]));
+        assert_eq!(cache.get_at(&cookie, now), None);
+    }
+
+    #[test]
+    fn responses_vary() {
+        let cache = ResponseCache::new();
+        let now = Instant::now();
+        let ttl = Duration::from_secs(10);
+        let vary = headers(&[("vary", "Accept-Language, x-tenant")]);
+        let en = CacheKey::new("/a", &headers(&[("accept-language", "en")]));
+        let fr = CacheKey::new("/a", &headers(&[("accept-language", "fr")]));
+        cache.put_at(&en, &vary, 200, "hello".into(), ttl, now);
+        assert_eq!(cache.get_at(&en, now), Some((200, "hello".into())));
+        assert_eq!(cache.get_at(&fr, now), None);
+        let tenant = CacheKey::new(
+            "/a",
+            &headers(&[("accept-language", "en"), ("x-tenant", "acme")]),
+        );
+        assert_eq!(cache.get_at(&tenant, now), None);
+
+        // a newer response replaces the one for the same request
+        cache.put_at(&en, &vary, 200, "hi".into(), ttl, now);
+        assert_eq!(cache.get_at(&en, now), Some((200, "hi".into())));
+
+        let star = CacheKey::new("/b", &HeaderMap::new());
+        cache.put_at(&star, &headers(&[("vary", "*")]), 200, "1".into(), ttl, now);
+        assert_eq!(cache.get_at(&star, now), None);
+    }
+}
@@ -0,0 +1,231 @@
+use std::time::Duration;
+
+use actix_http::http::HeaderMap;
+use url::Url;
+
+use crate::idempotency::IDEMPOTENCY_KEY_HEADER;
+use crate::request_id::REQUEST_ID_HEADER;
+
+/// Headers which apply to a single connection, and must not be forwarded
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Headers which the generated client sets itself
+const CLIENT_SET: &[&str] = &[
+    "host",
+    "accept",
+    "accept-encoding",
+    "content-type",
+    "content-length",
+    REQUEST_ID_HEADER,
+];
+
+/// Per-call settings for the generated client.
+///
+/// Set them with `Client::with`, which returns a copy of the client using these
+/// options for all its calls, on top of any it already had:
+///
+/// ```ignore
+/// let resp = client
+///     .with(CallOptions::new().header("X-Tenant", "acme").timeout(Duration::from_secs(1)))
+///     .get_pet(1)
+///     .await?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CallOptions {
+    headers: Vec<(String, String)>,
+    query: Vec<(String, String)>,
+    timeout: Option<Duration>,
+}
+
+impl CallOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Options which pass on the end-to-end headers of a request being proxied
+    /// (e.g. `Authorization`). Hop-by-hop headers, including those named by
+    /// `Connection`, and headers which the client sets itself are left out, as are
+    /// values which aren't visible ASCII.
+    pub fn forwarding(headers: &HeaderMap) -> Self {
+        let connection: Vec<String> = headers
+            .get_all("connection")
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(|name| name.trim().to_ascii_lowercase())
+            .collect();
+        headers
+            .iter()
+            .filter(|(name, _)| {
+                let name = name.as_str();
+                !HOP_BY_HOP.contains(&name)
+                    && !CLIENT_SET.contains(&name)
+                    && !connection.iter().any(|hop| hop == name)
+            })
+            .filter_map(|(name, value)| Some((name.as_str(), value.to_str().ok()?)))
+            .fold(Self::new(), |opts, (name, value)| opts.header(name, value))
+    }
+
+    /// Send an extra header. Invalid names or values cause the call to fail.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// Send an `Idempotency-Key` header
+    pub fn idempotency_key(self, key: impl Into<String>) -> Self {
+        self.header(IDEMPOTENCY_KEY_HEADER, key)
+    }
+
+    /// Append an extra query parameter, after those of the operation
+    pub fn query(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.query.push((name.into(), value.into()));
+        self
+    }
+
+    /// Give up on the call after `timeout`, instead of the client's default
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Combine with `other`: its headers and query parameters are sent after ours,
+    /// and its timeout (if any) replaces ours
+    pub fn merge(mut self, other: CallOptions) -> Self {
+        self.headers.extend(other.headers);
+        self.query.extend(other.query);
+        self.timeout = other.timeout.or(self.timeout);
+        self
+    }
+
+    /// Whether an `Idempotency-Key` header has been set
+    pub fn has_idempotency_key(&self) -> bool {
+        self.headers
+            .iter()
+            .any(|(name, _)| name.eq_ignore_ascii_case(IDEMPOTENCY_KEY_HEADER))
+    }
+
+    /// Add the extra query parameters to `url`
+    pub fn extend_url(&self, url: &mut Url) {
+        if !self.query.is_empty() {
+            url.query_pairs_mut().extend_pairs(&self.query);
+        }
+    }
+
+    /// Add the extra headers and timeout to `req`
+    pub fn apply(&self, mut req: awc::ClientRequest) -> awc::ClientRequest {
+        for (name, value) in &self.headers {
+            req = req.header(name.as_str(), value.as_str());
+        }
+        if let Some(timeout) = self.timeout {
+            req = req.timeout(timeout);
+        }
+        req
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extra_query_params() {
+        let opts = CallOptions::new().query("a b", "c&d").query("e", "f");
+
+        let mut url = Url::parse("http://localhost/pets?limit=1").unwrap();
+        opts.extend_url(&mut url);
+        assert_eq!(url.query(), Some("limit=1&a+b=c%26d&e=f"));
+
+        let mut url = Url::parse("http://localhost/pets").unwrap();
+        CallOptions::new().extend_url(&mut url);
+        assert_eq!(url.query(), None);
+    }
+
+    #[test]
+    fn merged_options() {
+        let first = CallOptions::new()
+            .header("x-tenant", "acme")
+            .query("a", "1")
+            .timeout(Duration::from_secs(1));
+        let merged = first
+            .clone()
+            .merge(CallOptions::new().header("x-trace", "t1"));
+        assert_eq!(
+            merged.headers,
+            vec![
+                ("x-tenant".to_string(), "acme".to_string()),
+                ("x-trace".to_string(), "t1".to_string()),
+            ]
+        );
+        assert_eq!(merged.query, vec![("a".to_string(), "1".to_string())]);
+        assert_eq!(merged.timeout, Some(Duration::from_secs(1)));
+
+        let merged = first.merge(
+            CallOptions::new()
+                .query("b", "2")
+                .timeout(Duration::from_secs(5)),
+        );
+        let mut url = Url::parse("http://localhost/pets").unwrap();
+        merged.extend_url(&mut url);
+        assert_eq!(url.query(), Some("a=1&b=2"));
+        assert_eq!(merged.timeout, Some(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn forwarded_headers() {
+        use actix_http::http::header::{HeaderName, HeaderValue};
+
+        let mut headers = HeaderMap::new();
+        for (name, value) in &[
+            ("authorization", "Bearer abc"),
+            ("x-tenant", "acme"),
+            ("idempotency-key", "k1"),
+            ("connection", "keep-alive, x-hop"),
+            ("x-hop", "1"),
+            ("keep-alive", "timeout=5"),
+            ("transfer-encoding", "chunked"),
+            ("host", "proxy.example.com"),
+            ("content-type", "application/json"),
+            ("accept", "application/json"),
+            ("x-request-id", "r1"),
+        ] {
+            headers.append(
+                HeaderName::from_static(name),
+                HeaderValue::from_static(value),
+            );
+        }
+        let opts = CallOptions::forwarding(&headers);
+        let mut forwarded = opts.headers.clone();
+        forwarded.sort();
+        assert_eq!(
+            forwarded,
+            vec![
+                ("authorization".to_string(), "Bearer abc".to_string()),
+                ("idempotency-key".to_string(), "k1".to_string()),
+                ("x-tenant".to_string(), "acme".to_string()),
+            ]
+        );
+        assert!(opts.has_idempotency_key());
+    }
+
+    #[test]
+    fn idempotency_key() {
+        assert!(!CallOptions::new()
+            .header("x-other", "1")
+            .has_idempotency_key());
+        assert!(CallOptions::new()
+            .idempotency_key("abc")
+            .has_idempotency_key());
+        assert!(CallOptions::new()
+            .header("Idempotency-Key", "abc")
+            .has_idempotency_key());
+    }
+}
@@ -0,0 +1,274 @@
+//! Record the calls of the generated client to a file (a "cassette"), and replay
+//! them later, so that integration tests don't need the upstream to be running.
+//!
+//! Calls are matched by operation, path, query and body. A call made several times
+//! replays the recorded responses in order, repeating the last one once they run out,
+//! so sequences (create a pet, then list the pets) replay as they were recorded.
+//!
+//! Cassettes are JSON, with JSON bodies stored as JSON, so they can be reviewed
+//! (and edited) along with the tests which use them.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use serde_json::Value;
+use url::{Position, Url};
+
+/// Cassettes are recorded (rather than replayed) by `Cassette::from_env` if this
+/// environment variable is set
+pub const RECORD_ENV_VAR: &str = "HSR_RECORD";
+
+#[derive(Debug, thiserror::Error)]
+pub enum CassetteError {
+    #[error("No recording of {} {}", operation_id, request)]
+    NotRecorded {
+        operation_id: String,
+        request: String,
+    },
+    #[error("Failed to read or write the cassette: {}", _0)]
+    Io(#[from] io::Error),
+    #[error("Bad cassette: {}", _0)]
+    Json(#[from] serde_json::Error),
+}
+
+/// A call of the client, as far as matching recordings goes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Request {
+    pub operation_id: String,
+    /// The path and query of the URL
+    pub request: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body: Option<Value>,
+}
+
+impl Request {
+    pub fn new(operation_id: &str, url: &Url, body: Option<Value>) -> Self {
+        Self {
+            operation_id: operation_id.into(),
+            request: url[Position::BeforePath..].into(),
+            body,
+        }
+    }
+}
+
+/// A recorded response
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Response {
+    pub status: u16,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub content_type: String,
+    /// JSON bodies as JSON, other bodies as text
+    pub body: Value,
+}
+
+impl Response {
+    pub fn new(status: u16, content_type: &str, body: &[u8]) -> Self {
+        let body = if body.is_empty() {
+            Value::Null
+        } else if is_json(content_type) {
+            serde_json::from_slice(body)
+                .unwrap_or_else(|_| String::from_utf8_lossy(body).into_owned().into())
+        } else {
+            String::from_utf8_lossy(body).into_owned().into()
+        };
+        Self {
+            status,
+            content_type: content_type.into(),
+            body,
+        }
+    }
+
+    /// The body, as it was received
+    pub fn body(&self) -> Vec<u8> {
+        match &self.body {
+            Value::Null => Vec::new(),
+            Value::String(text) if !is_json(&self.content_type) => text.clone().into_bytes(),
+            json => serde_json::to_vec(json).unwrap_or_default(),
+        }
+    }
+}
+
+fn is_json(content_type: &str) -> bool {
+    let essence = content_type.split(';').next().unwrap_or_default().trim();
+    essence.is_empty()
+        || essence.eq_ignore_ascii_case("application/json")
+        || essence.to_ascii_lowercase().ends_with("+json")
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Interaction {
+    request: Request,
+    response: Response,
+}
+
+#[derive(Debug, Default)]
+struct Tape {
+    interactions: Vec<Interaction>,
+    /// Whether each interaction has been replayed
+    played: Vec<bool>,
+}
+
+/// Records calls to a file, or replays them from it. Clones share the recording.
+#[derive(Debug, Clone)]
+pub struct Cassette {
+    path: PathBuf,
+    replaying: bool,
+    tape: Arc<Mutex<Tape>>,
+}
+
+impl Cassette {
+    /// Record calls to `path`, replacing any recording there. The file is written
+    /// after each call, so it is complete even if the test fails.
+    pub fn record(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            replaying: false,
+            tape: Default::default(),
+        }
+    }
+
+    /// Replay the calls recorded in `path`
+    pub fn replay(path: impl AsRef<Path>) -> Result<Self, CassetteError> {
+        let interactions: Vec<Interaction> = serde_json::from_slice(&fs::read(&path)?)?;
+        let played = vec![false; interactions.len()];
+        Ok(Self {
+            path: path.as_ref().to_path_buf(),
+            replaying: true,
+            tape: Arc::new(Mutex::new(Tape {
+                interactions,
+                played,
+            })),
+        })
+    }
+
+    /// Record calls if `HSR_RECORD` is set, or there is no recording yet, else replay them
+    pub fn from_env(path: impl AsRef<Path>) -> Result<Self, CassetteError> {
+        if std::env::var_os(RECORD_ENV_VAR).is_some() || !path.as_ref().exists() {
+            Ok(Self::record(path))
+        } else {
+            Self::replay(path)
+        }
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        self.replaying
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The recorded response to a call: the first one not yet replayed, or else the last
+    pub fn play(&self, request: &Request) -> Result<Response, CassetteError> {
+        let mut tape = self.tape.lock().unwrap();
+        let Tape {
+            interactions,
+            played,
+        } = &mut *tape;
+        let matching: Vec<usize> = interactions
+            .iter()
+            .enumerate()
+            .filter(|(_, interaction)| interaction.request == *request)
+            .map(|(ix, _)| ix)
+            .collect();
+        let ix = match matching.iter().find(|&&ix| !played[ix]).or(matching.last()) {
+            Some(&ix) => ix,
+            None => {
+                return Err(CassetteError::NotRecorded {
+                    operation_id: request.operation_id.clone(),
+                    request: request.request.clone(),
+                })
+            }
+        };
+        played[ix] = true;
+        Ok(interactions[ix].response.clone())
+    }
+
+    /// Record the response to a call, and save the cassette
+    pub fn record_response(
+        &self,
+        request: Request,
+        response: Response,
+    ) -> Result<(), CassetteError> {
+        let mut tape = self.tape.lock().unwrap();
+        tape.interactions.push(Interaction { request, response });
+        tape.played.push(true);
+        fs::write(
+            &self.path,
+            serde_json::to_string_pretty(&tape.interactions)?,
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn request(path: &str, body: Option<Value>) -> Request {
+        let url = Url::parse("http://localhost:8000")
+            .unwrap()
+            .join(path)
+            .unwrap();
+        Request::new("list_pets", &url, body)
+    }
+
+    #[test]
+    fn record_and_replay() {
+        let path = std::env::temp_dir().join(format!("hsr-cassette-{}.json", std::process::id()));
+        let cassette = Cassette::record(&path);
+        assert!(!cassette.is_replaying());
+        let pets = request("/v1/pets?limit=2", None);
+        cassette
+            .record_response(pets.clone(), Response::new(200, "application/json", b"[]"))
+            .unwrap();
+        cassette
+            .record_response(
+                pets.clone(),
+                Response::new(200, "application/json", br#"[{"name":"Rex"}]"#),
+            )
+            .unwrap();
+        let create = request("/v1/pets", Some(json!({"name": "Rex"})));
+        cassette
+            .record_response(create.clone(), Response::new(500, "text/plain", b"oops"))
+            .unwrap();
+
+        let saved: Value = serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(saved[0]["request"]["request"], "/v1/pets?limit=2");
+        assert_eq!(saved[1]["response"]["body"], json!([{"name": "Rex"}]));
+
+        let cassette = Cassette::replay(&path).unwrap();
+        assert!(cassette.is_replaying());
+        assert_eq!(cassette.play(&pets).unwrap().body(), b"[]");
+        let second = cassette.play(&pets).unwrap();
+        assert_eq!(second.body(), br#"[{"name":"Rex"}]"#);
+        // the last recording repeats
+        assert_eq!(cassette.play(&pets).unwrap(), second);
+
+        let created = cassette.play(&create).unwrap();
+        assert_eq!(created.status, 500);
+        assert_eq!(created.body(), b"oops");
+
+        let other = request("/v1/pets", Some(json!({"name": "Tom"})));
+        assert!(matches!(
+            cassette.play(&other),
+            Err(CassetteError::NotRecorded { .. })
+        ));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn bodies() {
+        let text = Response::new(200, "text/plain", b"\"quoted\"");
+        assert_eq!(text.body, json!("\"quoted\""));
+        assert_eq!(text.body(), b"\"quoted\"");
+        let json = Response::new(200, "application/problem+json", br#"{"title":"x"}"#);
+        assert_eq!(json.body, json!({"title": "x"}));
+        let empty = Response::new(204, "", b"");
+        assert_eq!(empty.body, Value::Null);
+        assert!(empty.body().is_empty());
+    }
+}
@@ -0,0 +1,155 @@
+//! A circuit breaker for the generated client.
+//!
+//! After `failure_threshold` calls in a row fail (with a connection error or a
+//! `5xx` response), the circuit opens: calls fail straight away with
+//! `ClientError::CircuitOpen`, rather than adding to the load on an upstream which
+//! is already struggling. Once `reset_timeout` has passed, a single call is let
+//! through to probe the upstream (the circuit is half-open). If it succeeds the
+//! circuit closes again, otherwise it stays open for another `reset_timeout`.
+//!
+//! Give a client a breaker with `Client::with_circuit_breaker`. Clones of a breaker
+//! share its state, so clients (or clones of one) calling the same upstream can
+//! share a breaker.
+
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::ClientError;
+
+/// The state of a circuit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CircuitState {
+    /// Calls are made
+    Closed,
+    /// Calls fail straight away
+    Open,
+    /// A call is probing the upstream
+    HalfOpen,
+}
+
+#[derive(Debug)]
+enum State {
+    Closed { failures: u32 },
+    Open { until: Instant },
+    HalfOpen { since: Instant },
+}
+
+/// Fails calls fast while an upstream is failing
+#[derive(Debug, Clone)]
+pub struct CircuitBreaker {
+    state: Arc<Mutex<State>>,
+    failure_threshold: u32,
+    reset_timeout: Duration,
+}
+
+impl CircuitBreaker {
+    /// A breaker opening after `failure_threshold` failures in a row, for `reset_timeout`
+    pub fn new(failure_threshold: u32, reset_timeout: Duration) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(State::Closed { failures: 0 })),
+            failure_threshold: failure_threshold.max(1),
+            reset_timeout,
+        }
+    }
+
+    pub fn state(&self) -> CircuitState {
+        match *self.state.lock().unwrap() {
+            State::Closed { .. } => CircuitState::Closed,
+            State::Open { until } if Instant::now() >= until => CircuitState::HalfOpen,
+            State::Open { .. } => CircuitState::Open,
+            State::HalfOpen { .. } => CircuitState::HalfOpen,
+        }
+    }
+
+    /// Whether a call may be made now. While half-open, only the probing call may;
+    /// if it doesn't finish within `reset_timeout` (say, it was cancelled), another
+    /// call takes over the probing.
+    pub fn allow(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let probe_after = match *state {
+            State::Closed { .. } => return true,
+            State::Open { until } => until,
+            State::HalfOpen { since } => since + self.reset_timeout,
+        };
+        let now = Instant::now();
+        if now < probe_after {
+            return false;
+        }
+        *state = State::HalfOpen { since: now };
+        true
+    }
+
+    /// `Err(ClientError::CircuitOpen)` unless a call may be made now
+    pub fn check(&self) -> Result<(), ClientError> {
+        if self.allow() {
+            Ok(())
+        } else {
+            Err(ClientError::CircuitOpen)
+        }
+    }
+
+    /// Record the outcome of a call: whether the upstream answered, without a `5xx` status
+    pub fn record(&self, success: bool) {
+        let mut state = self.state.lock().unwrap();
+        *state = match (&*state, success) {
+            (_, true) => State::Closed { failures: 0 },
+            (State::Closed { failures }, false) if failures + 1 < self.failure_threshold => {
+                State::Closed {
+                    failures: failures + 1,
+                }
+            }
+            (_, false) => State::Open {
+                until: Instant::now() + self.reset_timeout,
+            },
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::sleep;
+
+    #[test]
+    fn circuit_breaker() {
+        let breaker = CircuitBreaker::new(2, Duration::from_millis(50));
+        assert!(breaker.allow());
+        breaker.record(false);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        breaker.record(true);
+        breaker.record(false);
+        assert!(breaker.allow());
+        breaker.record(false);
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(!breaker.clone().allow());
+        assert!(matches!(breaker.check(), Err(ClientError::CircuitOpen)));
+
+        // a single probe is let through, and fails
+        sleep(Duration::from_millis(60));
+        assert!(breaker.allow());
+        assert!(!breaker.allow());
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        breaker.record(false);
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(!breaker.allow());
+
+        // the next probe succeeds
+        sleep(Duration::from_millis(60));
+        assert!(breaker.allow());
+        breaker.record(true);
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert!(breaker.allow());
+    }
+
+    #[test]
+    fn abandoned_probe() {
+        let breaker = CircuitBreaker::new(1, Duration::from_millis(20));
+        breaker.record(false);
+        sleep(Duration::from_millis(30));
+        assert!(breaker.allow());
+        assert!(!breaker.allow());
+        // the probe never reports back
+        sleep(Duration::from_millis(30));
+        assert!(breaker.allow());
+    }
+}
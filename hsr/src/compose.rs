@@ -0,0 +1,199 @@
+//! Serving several generated APIs from one application.
+//!
+//! Each generated server module has a `mount` function, returning a `Mount` of the
+//! API. A `Composite` serves any number of them under distinct path prefixes,
+//! each with its own `spec.json` and `ui.html`, plus an index page linking to them:
+//!
+//! ```ignore
+//! hsr::compose::Composite::new()
+//!     .mount("/pets", petstore::server::mount(PetApi, &cfg))
+//!     .mount("/users", users::server::mount(UserApi, &cfg))
+//!     .serve(cfg)
+//!     .await
+//! ```
+
+use std::sync::Arc;
+
+use actix_web::http::header::ContentType;
+use actix_web::middleware::{Compress, Condition, Logger};
+use actix_web::web::{self, ServiceConfig};
+use actix_web::{App, HttpResponse, HttpServer};
+
+use crate::compression::CompressionPolicy;
+use crate::forwarded::ForwardedHeaders;
+use crate::https::{redirect_server, HstsHeaders};
+use crate::request_id::RequestIds;
+use crate::security_headers::AddSecurityHeaders;
+use crate::Config;
+
+/// A generated API, ready to be mounted under a prefix
+#[derive(Clone)]
+pub struct Mount {
+    title: String,
+    register: Arc<dyn Fn(&str, &mut ServiceConfig) + Send + Sync>,
+}
+
+impl Mount {
+    /// Used by the generated `mount` functions. `register` should add a scope for
+    /// the given prefix, serving the API along with its spec and UI.
+    pub fn new(
+        title: impl Into<String>,
+        register: impl Fn(&str, &mut ServiceConfig) + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            title: title.into(),
+            register: Arc::new(register),
+        }
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+}
+
+/// Several APIs, each served under its own prefix
+#[derive(Clone, Default)]
+pub struct Composite {
+    mounts: Vec<(String, Mount)>,
+}
+
+impl Composite {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Serve `api` under `prefix` (e.g. `/pets`).
+    ///
+    /// Panics if the prefix is empty or `/`, since the root is taken by the index page.
+    pub fn mount(mut self, prefix: &str, api: Mount) -> Self {
+        let prefix = prefix.trim_end_matches('/');
+        assert!(
+            prefix.starts_with('/'),
+            "API prefix '{}' must start with '/' and not be the root",
+            prefix
+        );
+        self.mounts.push((prefix.to_string(), api));
+        self
+    }
+
+    /// The HTML index page, linking to the spec and UI of each API
+    pub fn index(&self) -> String {
+        let items: String = self
+            .mounts
+            .iter()
+            .map(|(prefix, api)| {
+                format!(
+                    "<li>{title} ({prefix}): <a href=\"{prefix}/ui.html\">docs</a>, \
+                     <a href=\"{prefix}/spec.json\">spec</a></li>\n",
+                    title = escape_html(api.title()),
+                    prefix = escape_html(prefix),
+                )
+            })
+            .collect();
+        format!(
+            "<!DOCTYPE html>\n<html>\n<head><title>APIs</title></head>\n<body>\n\
+             <h1>APIs</h1>\n<ul>\n{}</ul>\n</body>\n</html>\n",
+            items
+        )
+    }
+
+    /// Add the APIs and the index page to an application, e.g. to serve them
+    /// alongside other services with `App::configure`
+    pub fn configure(&self, cfg: &mut ServiceConfig) {
+        let index = self.index();
+        cfg.route(
+            "/",
+            web::get().to(move || {
+                HttpResponse::Ok()
+                    .set(ContentType::html())
+                    .body(index.clone())
+            }),
+        );
+        for (prefix, api) in &self.mounts {
+            (api.register)(prefix, cfg);
+        }
+    }
+
+    /// Serve the APIs on the host given by `cfg`.
+    /// Once started, the server blocks indefinitely.
+    pub async fn serve(self, cfg: Config) -> std::io::Result<()> {
+        let access_log = cfg.access_log;
+        let compression = CompressionPolicy::new(cfg.compression);
+        let trusted_proxies = cfg.trusted_proxies;
+        let hsts = cfg.hsts;
+        let security_headers = cfg.security_headers;
+        let tls = cfg.ssl.is_some();
+        let server = HttpServer::new(move || {
+            App::new()
+                // the structured access log replaces the default one
+                .wrap(Condition::new(access_log.is_none(), Logger::default()))
+                .wrap(Condition::new(
+                    access_log.is_some(),
+                    access_log.clone().unwrap_or_default(),
+                ))
+                .wrap(RequestIds)
+                .wrap(HstsHeaders::new(hsts.clone()))
+                .wrap(AddSecurityHeaders::new(security_headers.clone()))
+                .wrap(ForwardedHeaders::new(trusted_proxies.clone()))
+                .wrap(compression.clone())
+                .wrap(Compress::default())
+                .configure(|cfg| self.configure(cfg))
+        });
+
+        // Bind to sockets, unless some were inherited (see `hsr::listen`)
+        let addrs = cfg.bind_addrs()?;
+        let listeners = crate::listen::inherited()?;
+        let server = if let Some(ssl) = cfg.ssl {
+            match listeners.into_iter().next() {
+                Some(listener) => server.listen_openssl(listener, ssl),
+                None => server.bind_openssl(&addrs[..], ssl),
+            }
+        } else if listeners.is_empty() {
+            server.bind(&addrs[..])
+        } else {
+            listeners
+                .into_iter()
+                .try_fold(server, |server, listener| server.listen(listener))
+        }?;
+        // plain HTTP is redirected to HTTPS
+        let redirect = match cfg.redirect_http {
+            Some(port) if tls => Some(redirect_server(&cfg.host, port)?),
+            _ => None,
+        };
+
+        // run!
+        let result = server.run().await;
+        if let Some(redirect) = redirect {
+            redirect.stop(true).await;
+        }
+        result
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_page() {
+        let composite = Composite::new()
+            .mount("/pets/", Mount::new("Pet <Store>", |_, _| ()))
+            .mount("/users", Mount::new("Users", |_, _| ()));
+        let index = composite.index();
+        assert!(index.contains("Pet &lt;Store&gt; (/pets): <a href=\"/pets/ui.html\">"));
+        assert!(index.contains("<a href=\"/users/spec.json\">spec</a>"));
+    }
+
+    #[test]
+    #[should_panic]
+    fn root_prefix() {
+        Composite::new().mount("/", Mount::new("Root", |_, _| ()));
+    }
+}
@@ -0,0 +1,223 @@
+//! Response compression and request decompression.
+//!
+//! The generated server always installs `actix`'s `Compress` middleware, and a
+//! `CompressionPolicy` inside it which decides what it is allowed to compress.
+//!
+//! With the `decompress` feature (on by default), the generated client asks for
+//! compressed responses, which `awc` decompresses as they are read.
+
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use actix_http::http::header::{ContentEncoding, ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_TYPE};
+use actix_service::{Service, Transform};
+use actix_web::dev::{BodyEncoding, ServiceRequest, ServiceResponse};
+use actix_web::error::ErrorUnsupportedMediaType;
+use actix_web::Error as ActixError;
+use futures::future::{err, ok, Either, FutureExt, LocalBoxFuture, Ready};
+
+/// Compression settings, see `Config::compression`
+#[derive(Debug, Clone)]
+pub struct CompressionConfig {
+    /// Compress responses, if the client accepts it
+    pub compress_responses: bool,
+    /// Accept request bodies compressed with gzip, deflate or brotli
+    pub decompress_requests: bool,
+    /// Content types which are never compressed, because they are already
+    /// (images, archives...). Entries ending with `/` match a whole type.
+    pub skip_content_types: Vec<String>,
+}
+
+impl Default for CompressionConfig {
+    /// Don't compress responses, accept compressed requests
+    fn default() -> Self {
+        Self {
+            compress_responses: false,
+            decompress_requests: true,
+            skip_content_types: [
+                "image/",
+                "video/",
+                "audio/",
+                "application/zip",
+                "application/gzip",
+                "application/x-gzip",
+            ]
+            .iter()
+            .map(|ct| ct.to_string())
+            .collect(),
+        }
+    }
+}
+
+impl CompressionConfig {
+    /// Compress responses, and accept compressed requests
+    pub fn enabled() -> Self {
+        Self {
+            compress_responses: true,
+            ..Self::default()
+        }
+    }
+
+    /// Never compress responses of this content type
+    pub fn skip_content_type(mut self, content_type: impl Into<String>) -> Self {
+        self.skip_content_types.push(content_type.into());
+        self
+    }
+
+    fn should_compress(&self, content_type: Option<&str>) -> bool {
+        if !self.compress_responses {
+            return false;
+        }
+        let mime = match content_type.and_then(|ct| ct.split(';').next()) {
+            Some(mime) => mime.trim().to_ascii_lowercase(),
+            None => return true,
+        };
+        !self.skip_content_types.iter().any(|skip| {
+            if skip.ends_with('/') {
+                mime.starts_with(skip.as_str())
+            } else {
+                mime == *skip
+            }
+        })
+    }
+}
+
+/// Middleware applying a `CompressionConfig`. It must be wrapped in `Compress`.
+#[derive(Debug, Clone)]
+pub struct CompressionPolicy {
+    config: Arc<CompressionConfig>,
+}
+
+impl CompressionPolicy {
+    pub fn new(config: CompressionConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+        }
+    }
+}
+
+impl<S, B> Transform<S> for CompressionPolicy
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = ActixError>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = ActixError;
+    type InitError = ();
+    type Transform = CompressionPolicyMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(CompressionPolicyMiddleware {
+            service,
+            config: self.config.clone(),
+        })
+    }
+}
+
+pub struct CompressionPolicyMiddleware<S> {
+    service: S,
+    config: Arc<CompressionConfig>,
+}
+
+impl<S, B> Service for CompressionPolicyMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = ActixError>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = ActixError;
+    type Future = Either<
+        LocalBoxFuture<'static, Result<Self::Response, Self::Error>>,
+        Ready<Result<Self::Response, Self::Error>>,
+    >;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let encoded = req
+            .headers()
+            .get(CONTENT_ENCODING)
+            .and_then(|enc| enc.to_str().ok())
+            .map(|enc| !enc.trim().eq_ignore_ascii_case("identity"))
+            .unwrap_or(false);
+        if encoded && !self.config.decompress_requests {
+            return Either::Right(err(ErrorUnsupportedMediaType(
+                "compressed request bodies are not accepted",
+            )));
+        }
+        let config = self.config.clone();
+        let fut = self.service.call(req);
+        Either::Left(
+            async move {
+                let mut res = fut.await?;
+                let compress = config.should_compress(
+                    res.headers()
+                        .get(CONTENT_TYPE)
+                        .and_then(|ct| ct.to_str().ok()),
+                );
+                if !compress {
+                    res.response_mut().encoding(ContentEncoding::Identity);
+                }
+                Ok(res)
+            }
+            .boxed_local(),
+        )
+    }
+}
+
+/// The `Accept-Encoding` of client requests, with the `decompress` feature
+pub const CLIENT_ACCEPT_ENCODING: &str = "br, gzip, deflate";
+
+/// Ask for a compressed response (with the `decompress` feature), unless the request
+/// already says which encodings it accepts
+pub fn accept_compressed(req: awc::ClientRequest) -> awc::ClientRequest {
+    if cfg!(feature = "decompress") {
+        req.set_header_if_none(ACCEPT_ENCODING, CLIENT_ACCEPT_ENCODING)
+    } else {
+        req
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skip_content_types() {
+        let config = CompressionConfig::enabled().skip_content_type("application/pdf");
+        assert!(config.should_compress(Some("application/json")));
+        assert!(config.should_compress(None));
+        assert!(!config.should_compress(Some("image/png")));
+        assert!(!config.should_compress(Some("application/zip")));
+        assert!(!config.should_compress(Some("Application/PDF; q=1")));
+        assert!(!CompressionConfig::default().should_compress(Some("application/json")));
+    }
+
+    #[actix_rt::test]
+    async fn client_accept_encoding() {
+        let accepted = |req: awc::ClientRequest| {
+            accept_compressed(req)
+                .headers()
+                .get(ACCEPT_ENCODING)
+                .map(|enc| enc.to_str().unwrap().to_string())
+        };
+        let client = awc::Client::new();
+        let expected = if cfg!(feature = "decompress") {
+            Some(CLIENT_ACCEPT_ENCODING.to_string())
+        } else {
+            None
+        };
+        assert_eq!(accepted(client.get("http://localhost/")), expected);
+        let identity = client
+            .get("http://localhost/")
+            .header(ACCEPT_ENCODING, "identity");
+        assert_eq!(accepted(identity), Some("identity".into()));
+    }
+}
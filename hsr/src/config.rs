@@ -0,0 +1,552 @@
+//! Server configuration
+
+use std::env;
+use std::net::{Ipv6Addr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use openssl::ssl::{SslAcceptor, SslAcceptorBuilder, SslFiletype, SslMethod};
+
+use crate::access_log::AccessLog;
+use crate::compression::CompressionConfig;
+use crate::dispatch::Overrides;
+use crate::forwarded::TrustedProxies;
+use crate::https::Hsts;
+use crate::idempotency::{self, IdempotencyStore};
+use crate::normalize::TrailingSlash;
+use crate::rate_limit::{InMemoryStore, RateLimitStore};
+use crate::report::ErrorReporter;
+use crate::security_headers::SecurityHeaders;
+use crate::validate::ResponseValidation;
+use crate::Url;
+
+/// Errors building a `Config`
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("Invalid value for {}: {}", _0, _1)]
+    BadValue(&'static str, String),
+    #[error("Bad host: {}", _0)]
+    BadHost(#[from] url::ParseError),
+    #[error("A TLS certificate requires a private key, and vice versa")]
+    IncompleteTls,
+    #[error("Redirecting plain HTTP to HTTPS requires a TLS certificate")]
+    RedirectWithoutTls,
+    #[error("Failed to load TLS certificate: {}", _0)]
+    Tls(#[from] openssl::error::ErrorStack),
+}
+
+pub struct Config {
+    pub host: Url,
+    /// Further addresses to listen on, e.g. `[::]:8000` alongside `0.0.0.0:8000`.
+    /// They are served the same way as `host`, with TLS if it has a certificate.
+    pub also_bind: Vec<SocketAddr>,
+    pub ssl: Option<SslAcceptorBuilder>,
+    /// Check outgoing responses against the spec. Useful during development,
+    /// e.g. `if cfg!(debug_assertions) { ResponseValidation::Fail }`
+    pub response_validation: ResponseValidation,
+    /// Write a structured access log instead of the default `actix` one
+    pub access_log: Option<AccessLog>,
+    /// Where the rate limits declared with `x-hsr-rate-limit` are tracked
+    pub rate_limit_store: Arc<dyn RateLimitStore>,
+    /// Where the responses to operations marked `x-hsr-idempotent` are recorded
+    pub idempotency_store: Arc<dyn IdempotencyStore>,
+    /// Compression of responses and requests. By default responses are not compressed.
+    pub compression: CompressionConfig,
+    /// The proxies whose forwarding headers give the client of a request, see
+    /// `hsr::forwarded`. By default, none are trusted.
+    pub trusted_proxies: TrustedProxies,
+    /// Set `Strict-Transport-Security` on the responses to HTTPS requests
+    pub hsts: Option<Hsts>,
+    /// Also listen for plain HTTP on this port, redirecting every request to HTTPS.
+    /// Ignored unless the server has a TLS certificate.
+    pub redirect_http: Option<u16>,
+    /// Set `X-Content-Type-Options`, `X-Frame-Options` and `Referrer-Policy` on the
+    /// responses, see `hsr::security_headers`
+    pub security_headers: Option<SecurityHeaders>,
+    /// Told about every `5xx` response of an operation, see `hsr::report`
+    pub error_reporter: Option<Arc<dyn ErrorReporter>>,
+    /// What to do with requests for the paths of the spec, but for a trailing
+    /// slash, see `hsr::normalize`. By default, they are not found.
+    pub trailing_slash: TrailingSlash,
+    /// The handlers replacing the generated ones of some operations
+    pub overrides: Overrides,
+}
+
+impl Config {
+    pub fn with_host(host: Url) -> Self {
+        Self {
+            host,
+            also_bind: Vec::new(),
+            ssl: None,
+            response_validation: ResponseValidation::Off,
+            access_log: None,
+            rate_limit_store: Arc::new(InMemoryStore::default()),
+            idempotency_store: Arc::new(idempotency::InMemoryStore::default()),
+            compression: CompressionConfig::default(),
+            trusted_proxies: TrustedProxies::default(),
+            hsts: None,
+            redirect_http: None,
+            security_headers: None,
+            error_reporter: None,
+            trailing_slash: TrailingSlash::Strict,
+            overrides: Overrides::default(),
+        }
+    }
+
+    pub fn compression(mut self, compression: CompressionConfig) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Believe the forwarding headers of requests from peers in the given address
+    /// ranges, like `10.0.0.0/8` or `::1`
+    pub fn trust_proxy<S: AsRef<str>>(
+        mut self,
+        cidrs: impl IntoIterator<Item = S>,
+    ) -> Result<Self, ConfigError> {
+        self.trusted_proxies = TrustedProxies::parse(cidrs)
+            .map_err(|e| ConfigError::BadValue("trusted proxies", e))?;
+        Ok(self)
+    }
+
+    pub fn hsts(mut self, hsts: Hsts) -> Self {
+        self.hsts = Some(hsts);
+        self
+    }
+
+    /// Also listen for plain HTTP on `port`, redirecting every request to HTTPS
+    pub fn redirect_http(mut self, port: u16) -> Self {
+        self.redirect_http = Some(port);
+        self
+    }
+
+    pub fn security_headers(mut self, headers: SecurityHeaders) -> Self {
+        self.security_headers = Some(headers);
+        self
+    }
+
+    pub fn error_reporter(mut self, reporter: Arc<dyn ErrorReporter>) -> Self {
+        self.error_reporter = Some(reporter);
+        self
+    }
+
+    pub fn trailing_slash(mut self, policy: TrailingSlash) -> Self {
+        self.trailing_slash = policy;
+        self
+    }
+
+    /// Handle requests for the operation `operation_id` with `handler` rather than
+    /// the generated handler (one of `server::handlers`). `handler` is given the
+    /// route of the operation to add a handler to:
+    ///
+    /// ```ignore
+    /// let config = hsr::Config::from_env()?
+    ///     .override_handler("upload_file", |route| route.to(streaming_upload::<MyApi>));
+    /// ```
+    ///
+    /// The call is still authorized, audited, timed out and guarded against panics
+    /// as it is with the generated handler, with the typed path and query parameters
+    /// (but not the body, which is left to `handler`).
+    pub fn override_handler<F>(mut self, operation_id: &'static str, handler: F) -> Self
+    where
+        F: Fn(actix_web::Route) -> actix_web::Route + Send + Sync + 'static,
+    {
+        self.overrides.insert(operation_id, handler);
+        self
+    }
+
+    /// Also listen on `addr`
+    pub fn also_bind(mut self, addr: SocketAddr) -> Self {
+        self.also_bind.push(addr);
+        self
+    }
+
+    /// The addresses to listen on: those `host` resolves to, then `also_bind`
+    pub fn bind_addrs(&self) -> std::io::Result<Vec<SocketAddr>> {
+        let mut addrs = self.host.socket_addrs(|| None)?;
+        addrs.extend(self.also_bind.iter().copied());
+        Ok(addrs)
+    }
+
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+
+    /// Configure from environment variables, see `ConfigBuilder::env`
+    pub fn from_env() -> Result<Self, ConfigError> {
+        Self::builder().env()?.build()
+    }
+}
+
+/// Build a `Config`. Unless set otherwise, the server listens on `127.0.0.1:8000`.
+///
+/// ```no_run
+/// let config = hsr::Config::builder()
+///     .host("0.0.0.0")
+///     .port(8080)
+///     .tls("cert.pem", "key.pem")
+///     .build()?;
+/// # Ok::<(), hsr::ConfigError>(())
+/// ```
+#[derive(Default, Clone)]
+pub struct ConfigBuilder {
+    host: Option<String>,
+    port: Option<u16>,
+    also_bind: Vec<SocketAddr>,
+    tls_cert: Option<PathBuf>,
+    tls_key: Option<PathBuf>,
+    response_validation: ResponseValidation,
+    access_log: Option<AccessLog>,
+    rate_limit_store: Option<Arc<dyn RateLimitStore>>,
+    idempotency_store: Option<Arc<dyn IdempotencyStore>>,
+    compression: CompressionConfig,
+    trusted_proxies: Vec<String>,
+    hsts: Option<Hsts>,
+    redirect_http: Option<u16>,
+    security_headers: Option<SecurityHeaders>,
+    error_reporter: Option<Arc<dyn ErrorReporter>>,
+    trailing_slash: TrailingSlash,
+}
+
+impl ConfigBuilder {
+    /// The host name or IP address to listen on
+    pub fn host(mut self, host: impl Into<String>) -> Self {
+        self.host = Some(host.into());
+        self
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
+
+    /// Also listen on `addr`, e.g. `[::]:8000` alongside `0.0.0.0:8000`
+    pub fn also_bind(mut self, addr: SocketAddr) -> Self {
+        self.also_bind.push(addr);
+        self
+    }
+
+    /// Serve HTTPS, with a PEM certificate chain and private key
+    pub fn tls(mut self, cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> Self {
+        self.tls_cert = Some(cert.into());
+        self.tls_key = Some(key.into());
+        self
+    }
+
+    pub fn response_validation(mut self, mode: ResponseValidation) -> Self {
+        self.response_validation = mode;
+        self
+    }
+
+    pub fn access_log(mut self, access_log: AccessLog) -> Self {
+        self.access_log = Some(access_log);
+        self
+    }
+
+    pub fn rate_limit_store(mut self, store: Arc<dyn RateLimitStore>) -> Self {
+        self.rate_limit_store = Some(store);
+        self
+    }
+
+    pub fn idempotency_store(mut self, store: Arc<dyn IdempotencyStore>) -> Self {
+        self.idempotency_store = Some(store);
+        self
+    }
+
+    pub fn compression(mut self, compression: CompressionConfig) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Believe the forwarding headers of requests from peers in the given address
+    /// ranges, like `10.0.0.0/8` or `::1`
+    pub fn trust_proxy<S: Into<String>>(mut self, cidrs: impl IntoIterator<Item = S>) -> Self {
+        self.trusted_proxies = cidrs.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn hsts(mut self, hsts: Hsts) -> Self {
+        self.hsts = Some(hsts);
+        self
+    }
+
+    /// Also listen for plain HTTP on `port`, redirecting every request to HTTPS.
+    /// Requires `tls`.
+    pub fn redirect_http(mut self, port: u16) -> Self {
+        self.redirect_http = Some(port);
+        self
+    }
+
+    pub fn security_headers(mut self, headers: SecurityHeaders) -> Self {
+        self.security_headers = Some(headers);
+        self
+    }
+
+    pub fn error_reporter(mut self, reporter: Arc<dyn ErrorReporter>) -> Self {
+        self.error_reporter = Some(reporter);
+        self
+    }
+
+    /// Redirect or merge requests for the paths of the spec, but for a trailing slash
+    pub fn trailing_slash(mut self, policy: TrailingSlash) -> Self {
+        self.trailing_slash = policy;
+        self
+    }
+
+    /// Override settings with those found in the environment:
+    ///
+    /// * `HSR_HOST`: host name or IP address to listen on
+    /// * `HSR_PORT`: port to listen on
+    /// * `HSR_ALSO_BIND`: comma-separated further addresses to listen on
+    /// * `HSR_TLS_CERT`, `HSR_TLS_KEY`: paths to a PEM certificate chain and private key
+    /// * `HSR_RESPONSE_VALIDATION`: `off`, `log` or `fail`
+    /// * `HSR_TRUSTED_PROXIES`: comma-separated address ranges of trusted proxies
+    /// * `HSR_REDIRECT_HTTP_PORT`: port to redirect plain HTTP to HTTPS from
+    /// * `HSR_TRAILING_SLASH`: `strict`, `redirect` or `merge`
+    pub fn env(mut self) -> Result<Self, ConfigError> {
+        if let Some(host) = var("HSR_HOST") {
+            self.host = Some(host);
+        }
+        if let Some(port) = var("HSR_PORT") {
+            self.port = Some(
+                port.parse()
+                    .map_err(|_| ConfigError::BadValue("HSR_PORT", port))?,
+            );
+        }
+        if let Some(addrs) = var("HSR_ALSO_BIND") {
+            self.also_bind = addrs
+                .split(',')
+                .map(|addr| addr.trim().parse())
+                .collect::<Result<_, _>>()
+                .map_err(|_| ConfigError::BadValue("HSR_ALSO_BIND", addrs.clone()))?;
+        }
+        if let Some(cert) = var("HSR_TLS_CERT") {
+            self.tls_cert = Some(cert.into());
+        }
+        if let Some(key) = var("HSR_TLS_KEY") {
+            self.tls_key = Some(key.into());
+        }
+        if let Some(mode) = var("HSR_RESPONSE_VALIDATION") {
+            self.response_validation = mode
+                .parse()
+                .map_err(|e| ConfigError::BadValue("HSR_RESPONSE_VALIDATION", e))?;
+        }
+        if let Some(proxies) = var("HSR_TRUSTED_PROXIES") {
+            TrustedProxies::parse(proxies.split(','))
+                .map_err(|e| ConfigError::BadValue("HSR_TRUSTED_PROXIES", e))?;
+            self.trusted_proxies = proxies.split(',').map(String::from).collect();
+        }
+        if let Some(port) = var("HSR_REDIRECT_HTTP_PORT") {
+            self.redirect_http = Some(
+                port.parse()
+                    .map_err(|_| ConfigError::BadValue("HSR_REDIRECT_HTTP_PORT", port))?,
+            );
+        }
+        if let Some(policy) = var("HSR_TRAILING_SLASH") {
+            self.trailing_slash = policy
+                .parse()
+                .map_err(|e| ConfigError::BadValue("HSR_TRAILING_SLASH", e))?;
+        }
+        Ok(self)
+    }
+
+    pub fn build(self) -> Result<Config, ConfigError> {
+        let ssl = match (self.tls_cert, self.tls_key) {
+            (Some(cert), Some(key)) => {
+                let mut ssl = SslAcceptor::mozilla_intermediate(SslMethod::tls())?;
+                ssl.set_private_key_file(key, SslFiletype::PEM)?;
+                ssl.set_certificate_chain_file(cert)?;
+                Some(ssl)
+            }
+            (None, None) => None,
+            _ => return Err(ConfigError::IncompleteTls),
+        };
+        if self.redirect_http.is_some() && ssl.is_none() {
+            return Err(ConfigError::RedirectWithoutTls);
+        }
+        let scheme = if ssl.is_some() { "https" } else { "http" };
+        let host = match self.host.as_deref().unwrap_or("127.0.0.1") {
+            // an IPv6 address is bracketed in a url
+            host if host.parse::<Ipv6Addr>().is_ok() => format!("[{}]", host),
+            host => host.to_string(),
+        };
+        let port = self.port.unwrap_or(8000);
+        let host = format!("{}://{}:{}", scheme, host, port).parse()?;
+        let trusted_proxies = TrustedProxies::parse(&self.trusted_proxies)
+            .map_err(|e| ConfigError::BadValue("trusted proxies", e))?;
+        Ok(Config {
+            host,
+            also_bind: self.also_bind,
+            ssl,
+            response_validation: self.response_validation,
+            access_log: self.access_log,
+            rate_limit_store: self
+                .rate_limit_store
+                .unwrap_or_else(|| Arc::new(InMemoryStore::default())),
+            idempotency_store: self
+                .idempotency_store
+                .unwrap_or_else(|| Arc::new(idempotency::InMemoryStore::default())),
+            compression: self.compression,
+            trusted_proxies,
+            hsts: self.hsts,
+            redirect_http: self.redirect_http,
+            security_headers: self.security_headers,
+            error_reporter: self.error_reporter,
+            trailing_slash: self.trailing_slash,
+            overrides: Overrides::default(),
+        })
+    }
+}
+
+/// Read an environment variable, treating empty as unset
+fn var(name: &str) -> Option<String> {
+    env::var(name).ok().filter(|v| !v.is_empty())
+}
+
+/// Command-line arguments for configuring a server, to be `#[structopt(flatten)]`ed
+/// into an application's own arguments. Each can also be set by its environment variable.
+#[cfg(feature = "structopt")]
+#[derive(Debug, Clone, structopt::StructOpt)]
+pub struct ConfigArgs {
+    /// Host name or IP address to listen on
+    #[structopt(long, env = "HSR_HOST", default_value = "127.0.0.1")]
+    pub host: String,
+    /// Port to listen on
+    #[structopt(long, env = "HSR_PORT", default_value = "8000")]
+    pub port: u16,
+    /// Further addresses to listen on
+    #[structopt(long, env = "HSR_ALSO_BIND", use_delimiter = true)]
+    pub also_bind: Vec<SocketAddr>,
+    /// PEM certificate chain, to serve HTTPS
+    #[structopt(long, env = "HSR_TLS_CERT", requires = "tls-key")]
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key, to serve HTTPS
+    #[structopt(long, env = "HSR_TLS_KEY", requires = "tls-cert")]
+    pub tls_key: Option<PathBuf>,
+    /// Check responses against the spec: off, log or fail
+    #[structopt(long, env = "HSR_RESPONSE_VALIDATION", default_value = "off")]
+    pub response_validation: ResponseValidation,
+    /// Address ranges of proxies whose forwarding headers are believed
+    #[structopt(long, env = "HSR_TRUSTED_PROXIES", use_delimiter = true)]
+    pub trusted_proxies: Vec<String>,
+    /// Port to redirect plain HTTP to HTTPS from
+    #[structopt(long, env = "HSR_REDIRECT_HTTP_PORT", requires = "tls-cert")]
+    pub redirect_http_port: Option<u16>,
+    /// Requests for paths of the spec but for a trailing slash: strict, redirect or merge
+    #[structopt(long, env = "HSR_TRAILING_SLASH", default_value = "strict")]
+    pub trailing_slash: TrailingSlash,
+}
+
+#[cfg(feature = "structopt")]
+impl ConfigArgs {
+    pub fn into_config(self) -> Result<Config, ConfigError> {
+        let mut builder = Config::builder()
+            .host(self.host)
+            .port(self.port)
+            .response_validation(self.response_validation)
+            .trust_proxy(self.trusted_proxies)
+            .trailing_slash(self.trailing_slash);
+        if let (Some(cert), Some(key)) = (self.tls_cert, self.tls_key) {
+            builder = builder.tls(cert, key);
+        }
+        for addr in self.also_bind {
+            builder = builder.also_bind(addr);
+        }
+        if let Some(port) = self.redirect_http_port {
+            builder = builder.redirect_http(port);
+        }
+        builder.build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_config() {
+        let config = Config::builder().build().unwrap();
+        assert_eq!(config.host.as_str(), "http://127.0.0.1:8000/");
+        assert!(config.ssl.is_none());
+
+        let config = Config::builder()
+            .host("0.0.0.0")
+            .port(9000)
+            .response_validation(ResponseValidation::Log)
+            .build()
+            .unwrap();
+        assert_eq!(config.host.as_str(), "http://0.0.0.0:9000/");
+        assert_eq!(config.response_validation, ResponseValidation::Log);
+        assert_eq!(config.trailing_slash, TrailingSlash::Strict);
+
+        let config = Config::builder()
+            .host("0.0.0.0")
+            .also_bind("[::]:8000".parse().unwrap())
+            .build()
+            .unwrap();
+        let addrs: Vec<_> = config
+            .bind_addrs()
+            .unwrap()
+            .iter()
+            .map(|addr| addr.to_string())
+            .collect();
+        assert_eq!(addrs, vec!["0.0.0.0:8000", "[::]:8000"]);
+
+        let config = Config::builder()
+            .trust_proxy(vec!["10.0.0.0/8", "::1"])
+            .build()
+            .unwrap();
+        assert!(config
+            .trusted_proxies
+            .is_trusted("10.1.2.3".parse().unwrap()));
+        assert!(!config
+            .trusted_proxies
+            .is_trusted("192.0.2.1".parse().unwrap()));
+        assert!(matches!(
+            Config::builder().trust_proxy(vec!["10.0.0.0/40"]).build(),
+            Err(ConfigError::BadValue(..))
+        ));
+
+        assert!(matches!(
+            Config::builder().redirect_http(8080).build(),
+            Err(ConfigError::RedirectWithoutTls)
+        ));
+
+        let mut builder = Config::builder();
+        builder.tls_cert = Some("cert.pem".into());
+        assert!(matches!(builder.build(), Err(ConfigError::IncompleteTls)));
+
+        let config = Config::builder().host("::1").build().unwrap();
+        assert_eq!(config.host.as_str(), "http://[::1]:8000/");
+        let config = Config::builder().host("[::]").port(9000).build().unwrap();
+        assert_eq!(config.host.as_str(), "http://[::]:9000/");
+    }
+
+    #[test]
+    fn config_from_env() {
+        // no other test reads these variables
+        env::set_var("HSR_HOST", "::");
+        env::set_var("HSR_PORT", "9000");
+        env::set_var("HSR_TRAILING_SLASH", "redirect");
+        env::set_var("HSR_RESPONSE_VALIDATION", "");
+        let config = Config::builder().env().unwrap().build().unwrap();
+        assert_eq!(config.host.as_str(), "http://[::]:9000/");
+        assert_eq!(config.trailing_slash, TrailingSlash::Redirect);
+        // empty is unset
+        assert_eq!(config.response_validation, ResponseValidation::Off);
+
+        env::set_var("HSR_PORT", "ninety");
+        assert!(matches!(
+            Config::builder().env(),
+            Err(ConfigError::BadValue("HSR_PORT", _))
+        ));
+        for name in &[
+            "HSR_HOST",
+            "HSR_PORT",
+            "HSR_TRAILING_SLASH",
+            "HSR_RESPONSE_VALIDATION",
+        ] {
+            env::remove_var(name);
+        }
+    }
+}
@@ -0,0 +1,96 @@
+//! Access to the underlying request from within an API handler
+
+use std::cell::Ref;
+use std::net::SocketAddr;
+
+use actix_http::http::{HeaderMap, Method, Uri};
+use actix_http::Extensions;
+use actix_web::HttpRequest;
+
+use crate::forwarded::ClientInfo;
+use crate::request_id::RequestId;
+
+/// The parts of the incoming request that aren't described by the spec:
+/// arbitrary headers, the peer address, extensions inserted by middleware etc.
+///
+/// Generated with `GenerationOptions::request_context`, in which case every API
+/// method takes a `RequestContext` as its first argument.
+#[derive(Debug, Clone)]
+pub struct RequestContext {
+    req: HttpRequest,
+}
+
+impl RequestContext {
+    /// The request method
+    pub fn method(&self) -> &Method {
+        self.req.method()
+    }
+
+    /// The request URI, as received
+    pub fn uri(&self) -> &Uri {
+        self.req.uri()
+    }
+
+    /// All of the request headers
+    pub fn headers(&self) -> &HeaderMap {
+        self.req.headers()
+    }
+
+    /// Look up a header, if it is present and valid UTF-8
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.req.headers().get(name).and_then(|v| v.to_str().ok())
+    }
+
+    /// The address of the connected peer, if known
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.req.peer_addr()
+    }
+
+    /// The address, scheme and host used by the client, taking the forwarding
+    /// headers of trusted proxies into account, see `hsr::forwarded`
+    pub fn client_info(&self) -> ClientInfo {
+        self.req
+            .extensions()
+            .get::<ClientInfo>()
+            .cloned()
+            .unwrap_or_else(|| {
+                ClientInfo::direct(
+                    self.req.peer_addr(),
+                    self.req.headers(),
+                    self.req.uri(),
+                    self.req.app_config(),
+                )
+            })
+    }
+
+    /// The address of the client, taking `Forwarded` and `X-Forwarded-For`
+    /// headers into account. These can be spoofed, so only trust this behind
+    /// a proxy which sets them; `client_info` only believes trusted proxies.
+    pub fn realip_remote_addr(&self) -> Option<String> {
+        self.req
+            .connection_info()
+            .realip_remote_addr()
+            .map(String::from)
+    }
+
+    /// The id of the request, see `hsr::request_id`
+    pub fn request_id(&self) -> Option<RequestId> {
+        self.req.extensions().get::<RequestId>().cloned()
+    }
+
+    /// Values stored in the request by middleware
+    pub fn extensions(&self) -> Ref<'_, Extensions> {
+        self.req.extensions()
+    }
+
+    /// The underlying actix request
+    pub fn http_request(&self) -> &HttpRequest {
+        &self.req
+    }
+}
+
+impl From<HttpRequest> for RequestContext {
+    fn from(req: HttpRequest) -> Self {
+        Self { req }
+    }
+}
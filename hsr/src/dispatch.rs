@@ -0,0 +1,709 @@
+//! The parts of request dispatch shared by every operation.
+//!
+//! The generated return type of each operation implements `IntoReply`, which
+//! only has to say which body (if any) goes with each variant. Turning that into
+//! an HTTP response (status, headers, problem documents, serialization errors)
+//! is done here, once, rather than in the generated code for every operation.
+//!
+//! The return types are also errors (`Display`, `std::error::Error` and actix's
+//! `ResponseError`), so that handlers can return them with `?`.
+//!
+//! With `GenerationOptions::fallible`, API methods return a `Result` with an error
+//! of the application, which the API turns into a response with `map_error`.
+//! By default that is `internal_error`.
+//!
+//! Handlers are run with `guard`, so that a panic is logged and becomes a 500
+//! response rather than taking the worker down, and an operation with an
+//! `x-hsr-timeout` is given up on after it. What went wrong is recorded on the
+//! response, for the `ErrorReporter` of the server (see `hsr::report`).
+//!
+//! Requests for paths the API doesn't have, or with methods their path doesn't
+//! support, get `not_found` and `method_not_allowed` unless the API overrides them.
+//! A JSON body which doesn't fit its schema gets a 400 problem document saying why,
+//! see `json_config`.
+//!
+//! The generated handlers are public, in `server::handlers`. The handler of an
+//! operation can be replaced (e.g. to stream its request body) with `Overrides`,
+//! keeping the rest of the generated server.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt;
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::rc::Rc;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use actix_http::http::header::{HeaderValue, ALLOW, CONTENT_TYPE};
+use actix_http::http::{Method, StatusCode};
+use actix_service::{Service, Transform};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::error::{InternalError, JsonPayloadError};
+use actix_web::web::JsonConfig;
+use actix_web::{Error as ActixError, HttpRequest, HttpResponse, Route};
+use futures::future::{ok, FutureExt, LocalBoxFuture, Ready};
+use serde::Serialize;
+
+use crate::negotiate::Representations;
+use crate::report::{self, Failure};
+use crate::{HasStatusCode, OperationMeta, Problem};
+
+/// The future returned by the `Responder` impls of the generated return types
+pub type Responded = Ready<Result<HttpResponse, Infallible>>;
+
+/// The body of a response, serialized
+#[derive(Debug)]
+pub struct Reply {
+    body: Option<serde_json::Result<Vec<u8>>>,
+    content_type: &'static str,
+}
+
+impl Reply {
+    /// A response without a body
+    pub fn empty() -> Self {
+        Self {
+            body: None,
+            content_type: "application/json",
+        }
+    }
+
+    /// A response with a JSON body
+    pub fn json<T: Serialize>(body: &T) -> Self {
+        Self {
+            body: Some(serde_json::to_vec(body)),
+            content_type: "application/json",
+        }
+    }
+
+    /// A response with one of several representations
+    pub fn content<T: Representations>(body: &T) -> Self {
+        Self {
+            body: Some(body.to_body()),
+            content_type: body.media_type(),
+        }
+    }
+
+    /// The body, if there is one and it is JSON
+    pub(crate) fn to_json(&self) -> Option<serde_json::Value> {
+        match &self.body {
+            Some(Ok(body)) => serde_json::from_slice(body).ok(),
+            _ => None,
+        }
+    }
+}
+
+/// The return type of an operation
+pub trait IntoReply: HasStatusCode {
+    fn into_reply(self) -> Reply;
+}
+
+/// Build the response to an operation. With `problem_json`, error responses
+/// are sent as `application/problem+json` documents.
+pub fn to_response<R: IntoReply>(resp: R, problem_json: bool) -> HttpResponse {
+    let status = resp.status_code();
+    let reply = resp.into_reply();
+    let body = match reply.body {
+        None => None,
+        Some(Ok(body)) => Some(body),
+        Some(Err(e)) => {
+            log::error!("Failed to serialize response: {}", e);
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    if problem_json && (status.is_client_error() || status.is_server_error()) {
+        let problem = match body {
+            None => Problem::new(status),
+            Some(body) => {
+                let body: serde_json::Value =
+                    serde_json::from_slice(&body).unwrap_or(serde_json::Value::Null);
+                Problem::from_body(status, &body)
+            }
+        };
+        return problem.to_response();
+    }
+    match body {
+        None => HttpResponse::build(status).finish(),
+        Some(body) => HttpResponse::build(status)
+            .header(CONTENT_TYPE, reply.content_type)
+            .body(body),
+    }
+}
+
+/// `Display::fmt` for the generated return types: the status, followed by the
+/// description of the response (if any), e.g. `404 Not Found: No such pet`
+pub fn fmt_reply(status: StatusCode, description: &str, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", status.as_u16())?;
+    if let Some(reason) = status.canonical_reason() {
+        write!(f, " {}", reason)?;
+    }
+    if !description.is_empty() {
+        write!(f, ": {}", description)?;
+    }
+    Ok(())
+}
+
+/// The response to an error of the application: it is logged, and the client
+/// gets a 500 without the details
+pub fn internal_error(err: &dyn fmt::Debug, op: OperationMeta, problem_json: bool) -> HttpResponse {
+    log::error!("Operation '{}' failed: {:?}", op.operation_id, err);
+    let mut resp = if problem_json {
+        Problem::new(StatusCode::INTERNAL_SERVER_ERROR).to_response()
+    } else {
+        HttpResponse::InternalServerError().finish()
+    };
+    report::record(&mut resp, Failure::Error(format!("{:?}", err)));
+    resp
+}
+
+/// Run the future of a handler, giving up on it after `timeout`, in which case the
+/// client gets a 504 Gateway Timeout problem document
+pub async fn with_timeout<F: Future>(
+    operation_id: &str,
+    timeout: Duration,
+    handler: F,
+) -> Result<F::Output, ActixError> {
+    match actix_rt::time::timeout(timeout, handler).await {
+        Ok(output) => Ok(output),
+        Err(_) => {
+            log::warn!("Operation '{}' timed out after {:?}", operation_id, timeout);
+            let mut resp = Problem::new(StatusCode::GATEWAY_TIMEOUT)
+                .with_detail(format!("The operation didn't finish within {:?}", timeout))
+                .to_response();
+            report::record(&mut resp, Failure::Timeout(timeout));
+            Err(InternalError::from_response("timed out", resp).into())
+        }
+    }
+}
+
+/// Run the future of a handler, catching a panic, which is logged along with the
+/// operation, and gives the client a 500 problem document
+pub async fn catch_panic<F: Future>(
+    operation_id: &str,
+    handler: F,
+) -> Result<F::Output, ActixError> {
+    match AssertUnwindSafe(handler).catch_unwind().await {
+        Ok(output) => Ok(output),
+        Err(panic) => {
+            let message = match panic.downcast_ref::<&str>() {
+                Some(message) => message.to_string(),
+                None => match panic.downcast_ref::<String>() {
+                    Some(message) => message.clone(),
+                    None => "Box<dyn Any>".into(),
+                },
+            };
+            log::error!("Operation '{}' panicked: {}", operation_id, message);
+            let mut resp = Problem::new(StatusCode::INTERNAL_SERVER_ERROR).to_response();
+            report::record(&mut resp, Failure::Panic(message));
+            Err(InternalError::from_response("panicked", resp).into())
+        }
+    }
+}
+
+/// Run the future of a handler as the dispatcher does: catching a panic, and giving
+/// up after the timeout of the operation, if it has one
+pub async fn guard<F: Future>(
+    operation_id: &str,
+    timeout: Option<Duration>,
+    handler: F,
+) -> Result<F::Output, ActixError> {
+    let handler = catch_panic(operation_id, handler);
+    match timeout {
+        Some(timeout) => with_timeout(operation_id, timeout, handler).await?,
+        None => handler.await,
+    }
+}
+
+/// The default response to a request for a path the API doesn't have: a 404
+/// problem document
+pub fn not_found(req: &HttpRequest) -> HttpResponse {
+    Problem::new(StatusCode::NOT_FOUND)
+        .with_detail(format!("No such path: {}", req.path()))
+        .to_response()
+}
+
+/// The default response to a request with a method its path doesn't support: a 405
+/// problem document, with an `Allow` header listing the methods which are
+pub fn method_not_allowed(req: &HttpRequest, allowed: &[&str]) -> HttpResponse {
+    let allowed = allowed.join(", ");
+    let mut resp = Problem::new(StatusCode::METHOD_NOT_ALLOWED)
+        .with_detail(format!(
+            "{} is not allowed for {}, only {}",
+            req.method(),
+            req.path(),
+            allowed
+        ))
+        .to_response();
+    if let Ok(allowed) = HeaderValue::from_str(&allowed) {
+        resp.headers_mut().insert(ALLOW, allowed);
+    }
+    resp
+}
+
+/// The handlers replacing the generated ones of some operations, by operation id.
+/// See `Config::override_handler`.
+#[derive(Clone, Default)]
+pub struct Overrides(HashMap<&'static str, Arc<dyn Fn(Route) -> Route + Send + Sync>>);
+
+impl Overrides {
+    /// Handle requests for `operation_id` with `handler`, which is given the route of
+    /// the operation (matching its method) to add a handler to, e.g.
+    /// `|route| route.to(upload_file)`
+    pub fn insert<F>(&mut self, operation_id: &'static str, handler: F)
+    where
+        F: Fn(Route) -> Route + Send + Sync + 'static,
+    {
+        self.0.insert(operation_id, Arc::new(handler));
+    }
+
+    /// The route of `operation_id`, with its overriding handler, or else the
+    /// `generated` one. Called by the generated server for each operation.
+    pub fn route(
+        &self,
+        operation_id: &str,
+        route: Route,
+        generated: impl FnOnce(Route) -> Route,
+    ) -> Route {
+        match self.0.get(operation_id) {
+            Some(handler) => handler(route),
+            None => generated(route),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Middleware running the checks of the generated handlers around the
+    /// overriding ones. Called by the generated server for each resource.
+    pub fn checks(&self) -> OverrideChecks {
+        OverrideChecks {
+            overridden: self.0.keys().copied().collect(),
+            operations: Rc::new(Vec::new()),
+        }
+    }
+
+    /// Check that every overridden operation is one of `operations` (those of the
+    /// API), as a misspelt operation id would leave the generated handler in place
+    pub fn check(&self, operations: &[OperationMeta]) -> std::io::Result<()> {
+        match self
+            .0
+            .keys()
+            .find(|id| !operations.iter().any(|op| op.operation_id == **id))
+        {
+            Some(id) => Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("No operation '{}' to override", id),
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Debug for Overrides {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
+/// The rest of the handling of a request, from a middleware
+pub type Next =
+    Box<dyn FnOnce(ServiceRequest) -> LocalBoxFuture<'static, Result<ServiceResponse, ActixError>>>;
+
+type Checks = Rc<
+    dyn Fn(ServiceRequest, Next) -> LocalBoxFuture<'static, Result<ServiceResponse, ActixError>>,
+>;
+
+/// Middleware running what the generated handler of an operation runs around the
+/// API (authorization, auditing, the timeout and catching panics) around the
+/// handler overriding it, so that overriding a handler doesn't bypass them.
+/// See `Overrides::checks`.
+#[derive(Clone)]
+pub struct OverrideChecks {
+    overridden: Vec<&'static str>,
+    operations: Rc<Vec<(Method, Checks)>>,
+}
+
+impl OverrideChecks {
+    /// Run `checks` around the handler of the operation, if it is overridden
+    pub fn operation<F, Fut>(mut self, method: Method, operation_id: &str, checks: F) -> Self
+    where
+        F: Fn(ServiceRequest, Next) -> Fut + 'static,
+        Fut: Future<Output = Result<ServiceResponse, ActixError>> + 'static,
+    {
+        if self.overridden.iter().any(|id| *id == operation_id) {
+            let checks: Checks = Rc::new(move |req, next| checks(req, next).boxed_local());
+            Rc::make_mut(&mut self.operations).push((method, checks));
+        }
+        self
+    }
+}
+
+impl<S> Transform<S> for OverrideChecks
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = ActixError> + 'static,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = ActixError;
+    type InitError = ();
+    type Transform = OverrideChecksMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(OverrideChecksMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            operations: self.operations.clone(),
+        })
+    }
+}
+
+pub struct OverrideChecksMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    operations: Rc<Vec<(Method, Checks)>>,
+}
+
+impl<S> Service for OverrideChecksMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = ActixError> + 'static,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = ActixError;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let checks = match self
+            .operations
+            .iter()
+            .find(|(method, _)| method == req.method())
+        {
+            Some((_, checks)) => checks.clone(),
+            None => return self.service.borrow_mut().call(req).boxed_local(),
+        };
+        let service = self.service.clone();
+        let next: Next = Box::new(move |req| service.borrow_mut().call(req).boxed_local());
+        checks(req, next)
+    }
+}
+
+/// The configuration of the JSON bodies of the generated resources, with `json_error`
+/// as the error handler
+pub fn json_config() -> JsonConfig {
+    JsonConfig::default().error_handler(|err, _req| json_error(err))
+}
+
+/// The error for a JSON body which can't be read: a 400 problem document (or 413 if
+/// it is too large) whose detail is the reason. An unknown field (see
+/// `GenerationOptions::deny_unknown_fields`) is also named in a `field` member.
+pub fn json_error(err: JsonPayloadError) -> ActixError {
+    let status = match err {
+        JsonPayloadError::Overflow => StatusCode::PAYLOAD_TOO_LARGE,
+        _ => StatusCode::BAD_REQUEST,
+    };
+    let mut problem = Problem::new(status).with_detail(err.to_string());
+    if let JsonPayloadError::Deserialize(e) = &err {
+        if let Some(field) = unknown_field(&e.to_string()) {
+            problem
+                .extensions
+                .insert("field".into(), serde_json::Value::String(field.into()));
+        }
+    }
+    InternalError::from_response(err, problem.to_response()).into()
+}
+
+/// The field named by serde's error for an unknown field
+fn unknown_field(message: &str) -> Option<&str> {
+    message.strip_prefix("unknown field `")?.split('`').next()
+}
+
+/// `Responder::respond_to` for the generated return types
+pub fn respond<R: IntoReply>(resp: R, problem_json: bool) -> Responded {
+    ok(to_response(resp, problem_json))
+}
+
+/// `IntoResponse::into_response` for the generated return types, with the axum backend
+#[cfg(feature = "axum")]
+pub fn into_axum_response<R: IntoReply>(resp: R) -> axum::response::Response {
+    use axum::response::IntoResponse;
+    let status = axum::http::StatusCode::from_u16(resp.status_code().as_u16()).unwrap();
+    let reply = resp.into_reply();
+    match reply.body {
+        None => status.into_response(),
+        Some(Ok(body)) => (
+            status,
+            [(axum::http::header::CONTENT_TYPE, reply.content_type)],
+            body,
+        )
+            .into_response(),
+        Some(Err(e)) => {
+            log::error!("Failed to serialize response: {}", e);
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    enum Resp {
+        Ok(Vec<u32>),
+        NotFound,
+        Invalid(String),
+        Table(Table),
+    }
+
+    enum Table {
+        Json(Vec<u32>),
+        Csv(String),
+    }
+
+    impl Representations for Table {
+        const MEDIA_TYPES: &'static [&'static str] = &["application/json", "text/csv"];
+
+        fn media_type(&self) -> &'static str {
+            match self {
+                Table::Json(_) => "application/json",
+                Table::Csv(_) => "text/csv",
+            }
+        }
+
+        fn to_body(&self) -> serde_json::Result<Vec<u8>> {
+            match self {
+                Table::Json(inner) => serde_json::to_vec(inner),
+                Table::Csv(inner) => Ok(inner.as_bytes().to_vec()),
+            }
+        }
+
+        fn from_body(
+            content_type: &str,
+            _body: &[u8],
+        ) -> Result<Self, crate::negotiate::BodyError> {
+            Err(crate::negotiate::BodyError::UnsupportedMediaType(
+                content_type.into(),
+            ))
+        }
+    }
+
+    impl HasStatusCode for Resp {
+        fn status_code(&self) -> StatusCode {
+            match self {
+                Resp::Ok(_) | Resp::Table(_) => StatusCode::OK,
+                Resp::NotFound => StatusCode::NOT_FOUND,
+                Resp::Invalid(_) => StatusCode::BAD_REQUEST,
+            }
+        }
+    }
+
+    impl IntoReply for Resp {
+        fn into_reply(self) -> Reply {
+            match self {
+                Resp::Ok(inner) => Reply::json(&inner),
+                Resp::NotFound => Reply::empty(),
+                Resp::Invalid(inner) => Reply::json(&inner),
+                Resp::Table(inner) => Reply::content(&inner),
+            }
+        }
+    }
+
+    impl fmt::Display for Resp {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            let description = match self {
+                Resp::NotFound => "No such thing",
+                _ => "",
+            };
+            fmt_reply(self.status_code(), description, f)
+        }
+    }
+
+    fn content_type(resp: &HttpResponse) -> Option<&str> {
+        resp.headers()
+            .get(CONTENT_TYPE)
+            .and_then(|ct| ct.to_str().ok())
+    }
+
+    #[test]
+    fn responses() {
+        let resp = to_response(Resp::Ok(vec![1, 2]), false);
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(content_type(&resp), Some("application/json"));
+
+        let resp = to_response(Resp::NotFound, false);
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(content_type(&resp), None);
+
+        let resp = to_response(Resp::Invalid("no".into()), false);
+        assert_eq!(content_type(&resp), Some("application/json"));
+
+        let resp = to_response(Resp::Table(Table::Csv("1,2".into())), false);
+        assert_eq!(content_type(&resp), Some("text/csv"));
+        let resp = to_response(Resp::Table(Table::Json(vec![1, 2])), false);
+        assert_eq!(content_type(&resp), Some("application/json"));
+    }
+
+    #[test]
+    fn problem_responses() {
+        // only errors are problems
+        let resp = to_response(Resp::Ok(vec![]), true);
+        assert_eq!(content_type(&resp), Some("application/json"));
+
+        for resp in vec![Resp::NotFound, Resp::Invalid("no".into())] {
+            let status = resp.status_code();
+            let resp = to_response(resp, true);
+            assert_eq!(resp.status(), status);
+            assert_eq!(content_type(&resp), Some(crate::problem::PROBLEM_JSON));
+        }
+    }
+
+    #[test]
+    fn internal_errors() {
+        let op = OperationMeta {
+            operation_id: "getThing",
+            method: "GET",
+            path: "/thing",
+            tags: &[],
+            deprecated: false,
+            security: &[],
+        };
+        let resp = internal_error(&"database down", op, false);
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(content_type(&resp), None);
+        let resp = internal_error(&"database down", op, true);
+        assert_eq!(content_type(&resp), Some(crate::problem::PROBLEM_JSON));
+    }
+
+    #[actix_rt::test]
+    async fn timeouts() {
+        let fast = with_timeout("fast", Duration::from_millis(50), async { 1 }).await;
+        assert_eq!(fast.unwrap(), 1);
+
+        let slow = actix_rt::time::delay_for(Duration::from_millis(50));
+        let err = with_timeout("slow", Duration::from_millis(5), slow)
+            .await
+            .unwrap_err();
+        let resp = err.as_response_error().error_response();
+        assert_eq!(resp.status(), StatusCode::GATEWAY_TIMEOUT);
+        assert_eq!(content_type(&resp), Some(crate::problem::PROBLEM_JSON));
+    }
+
+    #[actix_rt::test]
+    async fn panics() {
+        let fine = guard("fine", None, async { 1 }).await;
+        assert_eq!(fine.unwrap(), 1);
+
+        let err = guard("buggy", None, async { panic!("oops") })
+            .await
+            .unwrap_err();
+        let resp = err.as_response_error().error_response();
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(content_type(&resp), Some(crate::problem::PROBLEM_JSON));
+        assert_eq!(
+            resp.extensions().get::<Failure>(),
+            Some(&Failure::Panic("oops".into()))
+        );
+
+        let slow = actix_rt::time::delay_for(Duration::from_millis(50));
+        let err = guard("slow", Some(Duration::from_millis(5)), slow)
+            .await
+            .unwrap_err();
+        let resp = err.as_response_error().error_response();
+        assert_eq!(resp.status(), StatusCode::GATEWAY_TIMEOUT);
+    }
+
+    #[test]
+    fn fallbacks() {
+        let req = actix_web::test::TestRequest::default()
+            .method(actix_http::http::Method::PATCH)
+            .uri("/pets")
+            .to_http_request();
+        let resp = not_found(&req);
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert_eq!(content_type(&resp), Some(crate::problem::PROBLEM_JSON));
+
+        let resp = method_not_allowed(&req, &["GET", "POST"]);
+        assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(content_type(&resp), Some(crate::problem::PROBLEM_JSON));
+        assert_eq!(resp.headers().get(ALLOW).unwrap(), "GET, POST");
+    }
+
+    #[actix_rt::test]
+    async fn overrides() {
+        use actix_web::{test, web, App};
+
+        let mut overrides = Overrides::default();
+        overrides.insert("add_pet", |route| route.to(HttpResponse::Accepted));
+        let mut app = test::init_service(
+            App::new().service(
+                web::resource("/pets")
+                    .route(
+                        overrides
+                            .route("list_pets", web::get(), |route| route.to(HttpResponse::Ok)),
+                    )
+                    .route(overrides.route("add_pet", web::post(), |route| {
+                        route.to(HttpResponse::Created)
+                    })),
+            ),
+        )
+        .await;
+        let req = test::TestRequest::get().uri("/pets").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        let req = test::TestRequest::post().uri("/pets").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.status(), StatusCode::ACCEPTED);
+
+        let add_pet = OperationMeta {
+            operation_id: "add_pet",
+            method: "POST",
+            path: "/pets",
+            tags: &[],
+            deprecated: false,
+            security: &[],
+        };
+        assert!(overrides.check(&[add_pet]).is_ok());
+        overrides.insert("add_pets", |route| route.to(HttpResponse::Accepted));
+        assert!(overrides.check(&[add_pet]).is_err());
+    }
+
+    #[test]
+    fn json_errors() {
+        #[derive(Debug, Deserialize)]
+        #[serde(deny_unknown_fields)]
+        #[allow(dead_code)]
+        struct Pet {
+            name: String,
+        }
+
+        let e = serde_json::from_str::<Pet>(r#"{"nmae": "Rex"}"#).unwrap_err();
+        let resp = json_error(JsonPayloadError::Deserialize(e))
+            .as_response_error()
+            .error_response();
+        assert_eq!(resp.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(content_type(&resp), Some(crate::problem::PROBLEM_JSON));
+        assert_eq!(
+            unknown_field("unknown field `nmae`, expected `name` at line 1 column 7"),
+            Some("nmae")
+        );
+        assert_eq!(unknown_field("missing field `name`"), None);
+
+        let resp = json_error(JsonPayloadError::Overflow)
+            .as_response_error()
+            .error_response();
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(Resp::NotFound.to_string(), "404 Not Found: No such thing");
+        assert_eq!(Resp::Invalid("no".into()).to_string(), "400 Bad Request");
+    }
+}
@@ -0,0 +1,181 @@
+//! Streaming response bodies, for operations returning binary (and potentially large)
+//! bodies, which the generated client would otherwise read into memory whole.
+//!
+//! For each such operation the client has a `<operation>_stream` method, giving a
+//! `Download` once a successful response has arrived. A `Download` is a `Stream` of
+//! the chunks of the body, or can be written straight to a file:
+//!
+//! ```ignore
+//! let download = client.get_report_stream(id, None).await?;
+//! download
+//!     .download_to("report.pdf", |progress| println!("{:?}", progress.fraction()))
+//!     .await?;
+//! ```
+
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use actix_http::error::PayloadError;
+use actix_http::http::header::CONTENT_LENGTH;
+use bytes::Bytes;
+use futures::stream::{LocalBoxStream, Stream, StreamExt};
+
+use crate::ClientError;
+
+/// How much of a body has been downloaded
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub downloaded: u64,
+    /// The size of the body, if the server gave a `Content-Length`
+    pub total: Option<u64>,
+}
+
+impl Progress {
+    /// How much has been downloaded, between 0 and 1, if the size is known
+    pub fn fraction(&self) -> Option<f64> {
+        self.total.map(|total| match total {
+            0 => 1.0,
+            total => self.downloaded as f64 / total as f64,
+        })
+    }
+}
+
+/// The body of a successful response, as it arrives
+pub struct Download {
+    status: u16,
+    content_type: String,
+    content_length: Option<u64>,
+    body: LocalBoxStream<'static, Result<Bytes, PayloadError>>,
+}
+
+impl Download {
+    pub fn new<S>(resp: awc::ClientResponse<S>) -> Self
+    where
+        S: Stream<Item = Result<Bytes, PayloadError>> + Unpin + 'static,
+    {
+        let content_length = resp
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|len| len.to_str().ok())
+            .and_then(|len| len.parse().ok());
+        Self {
+            status: resp.status().as_u16(),
+            content_type: crate::negotiate::content_type(resp.headers()).to_string(),
+            content_length,
+            body: resp.boxed_local(),
+        }
+    }
+
+    pub fn status(&self) -> u16 {
+        self.status
+    }
+
+    /// The `Content-Type` of the body, or an empty string
+    pub fn content_type(&self) -> &str {
+        &self.content_type
+    }
+
+    /// The size of the body, if the server gave a `Content-Length`
+    pub fn content_length(&self) -> Option<u64> {
+        self.content_length
+    }
+
+    /// Write the body to `writer`, calling `progress` after each chunk. Gives the size
+    /// of the body.
+    pub async fn write_to(
+        mut self,
+        mut writer: impl Write,
+        mut progress: impl FnMut(Progress),
+    ) -> Result<u64, ClientError> {
+        let mut downloaded = 0;
+        while let Some(chunk) = self.body.next().await {
+            let chunk = chunk.map_err(actix_web::Error::from)?;
+            writer
+                .write_all(&chunk)
+                .map_err(actix_web::error::ErrorInternalServerError)?;
+            downloaded += chunk.len() as u64;
+            progress(Progress {
+                downloaded,
+                total: self.content_length,
+            });
+        }
+        writer
+            .flush()
+            .map_err(actix_web::error::ErrorInternalServerError)?;
+        Ok(downloaded)
+    }
+
+    /// Write the body to a file at `path` (replacing any file there), calling
+    /// `progress` after each chunk. Gives the size of the body.
+    pub async fn download_to(
+        self,
+        path: impl AsRef<Path>,
+        progress: impl FnMut(Progress),
+    ) -> Result<u64, ClientError> {
+        let file = File::create(path).map_err(actix_web::error::ErrorInternalServerError)?;
+        self.write_to(file, progress).await
+    }
+}
+
+impl Stream for Download {
+    type Item = Result<Bytes, ClientError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        self.body
+            .as_mut()
+            .poll_next(cx)
+            .map(|chunk| chunk.map(|chunk| chunk.map_err(|e| actix_web::Error::from(e).into())))
+    }
+}
+
+impl std::fmt::Debug for Download {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Download")
+            .field("status", &self.status)
+            .field("content_type", &self.content_type)
+            .field("content_length", &self.content_length)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progress() {
+        let progress = Progress {
+            downloaded: 25,
+            total: Some(100),
+        };
+        assert_eq!(progress.fraction(), Some(0.25));
+        let progress = Progress {
+            downloaded: 25,
+            total: None,
+        };
+        assert_eq!(progress.fraction(), None);
+    }
+
+    #[actix_rt::test]
+    async fn write_to() {
+        let resp = awc::test::TestResponse::with_header("content-type", "application/pdf")
+            .header("content-length", "11")
+            .set_payload("hello world")
+            .finish();
+        let download = Download::new(resp);
+        assert_eq!(download.content_type(), "application/pdf");
+        assert_eq!(download.content_length(), Some(11));
+        let mut seen = Vec::new();
+        let mut body = Vec::new();
+        let size = download
+            .write_to(&mut body, |progress| seen.push(progress))
+            .await
+            .unwrap();
+        assert_eq!(size, 11);
+        assert_eq!(body, b"hello world");
+        assert_eq!(seen.last().and_then(Progress::fraction), Some(1.0));
+    }
+}
@@ -0,0 +1,131 @@
+//! Support for the example tests, which check an implementation of an API against
+//! the examples of its spec.
+//!
+//! With `GenerationOptions::example_tests`, a test is generated for each operation
+//! with examples. It serves the API in the background, makes each example call
+//! with the generated client, and checks that the response has the status and the
+//! body (as JSON) of the example. The examples of the spec become executable
+//! documentation of the contract.
+
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use url::Url;
+
+use crate::dispatch::IntoReply;
+use crate::validate::ResponseValidation;
+use crate::{Config, HasStatusCode};
+
+/// How long to wait for the server to listen
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Serve an API in the background, on a free port of localhost, returning its URL
+/// once it is listening. `serve` is given the config of the server, and is called
+/// on a thread (and an actix system) of its own, so the API needn't be `Send`.
+/// The server logs the responses which don't conform to the spec.
+pub fn serve<F, S>(serve: F) -> Url
+where
+    F: FnOnce(Config) -> S + Send + 'static,
+    S: Future<Output = io::Result<()>> + 'static,
+{
+    let port = TcpListener::bind(("127.0.0.1", 0))
+        .and_then(|listener| listener.local_addr())
+        .expect("no free port for the example server")
+        .port();
+    let url: Url = format!("http://127.0.0.1:{}", port).parse().unwrap();
+    let mut cfg = Config::with_host(url.clone());
+    cfg.response_validation = ResponseValidation::Log;
+    std::thread::spawn(move || {
+        let mut system = actix_rt::System::new("examples");
+        if let Err(e) = system.block_on(serve(cfg)) {
+            log::error!("The example server failed: {}", e);
+        }
+    });
+    let step = Duration::from_millis(10);
+    let mut waited = Duration::from_secs(0);
+    while TcpStream::connect(("127.0.0.1", port)).is_err() {
+        if waited > STARTUP_TIMEOUT {
+            panic!("The example server didn't start");
+        }
+        std::thread::sleep(step);
+        waited += step;
+    }
+    url
+}
+
+/// An example value (as JSON) of a parameter or body, as its type. Panics if the
+/// example doesn't fit.
+pub fn value<T: DeserializeOwned>(json: &str) -> T {
+    serde_json::from_str(json)
+        .unwrap_or_else(|e| panic!("Example value {} doesn't fit its schema: {}", json, e))
+}
+
+/// Check the result of the example `call` (`operationId/name`) against the status
+/// and body (as JSON) of the example response. Panics if they differ.
+pub fn check<R, E>(call: &str, result: Result<R, E>, status: u16, body: &str)
+where
+    R: IntoReply + fmt::Debug,
+    E: fmt::Debug,
+{
+    let resp = match result {
+        Ok(resp) => resp,
+        Err(e) => panic!("Example '{}' failed: {:?}", call, e),
+    };
+    assert_eq!(
+        resp.status_code().as_u16(),
+        status,
+        "Example '{}' answered {:?}",
+        call,
+        resp
+    );
+    let expected: Value = value(body);
+    let actual = resp.into_reply().to_json();
+    assert_eq!(
+        actual.as_ref(),
+        Some(&expected),
+        "Example '{}' answered another body",
+        call
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dispatch::Reply;
+    use actix_http::http::StatusCode;
+
+    #[derive(Debug)]
+    struct Found(Value);
+
+    impl HasStatusCode for Found {
+        fn status_code(&self) -> StatusCode {
+            StatusCode::OK
+        }
+    }
+
+    impl IntoReply for Found {
+        fn into_reply(self) -> Reply {
+            Reply::json(&self.0)
+        }
+    }
+
+    #[test]
+    fn check_examples() {
+        let found: Result<_, ()> = Ok(Found(serde_json::json!({ "id": 1, "name": "Rex" })));
+        check("get_pet/rex", found, 200, r#"{"name": "Rex", "id": 1}"#);
+        assert_eq!(value::<Option<i64>>("null"), None);
+        assert_eq!(value::<Vec<String>>(r#"["a"]"#), vec!["a".to_string()]);
+    }
+
+    #[test]
+    #[should_panic(expected = "another body")]
+    fn check_example_body() {
+        let found: Result<_, ()> = Ok(Found(serde_json::json!({ "id": 2 })));
+        check("get_pet/rex", found, 200, r#"{"id": 1}"#);
+    }
+}
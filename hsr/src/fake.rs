@@ -0,0 +1,256 @@
+//! Random values of the generated types, for seeding mock servers, tests and load
+//! generators.
+//!
+//! With `GenerationOptions::samples`, the generated structs and enums implement
+//! `Sample`, making values which fit their schemas: within the bounds of numbers,
+//! one of the values of enums, with or without the fields which aren't required,
+//! `null` only where the schema allows it. hsr doesn't model string formats or
+//! patterns, so strings are short random words.
+//!
+//! ```ignore
+//! let pet: api::Pet = hsr::fake::sample();
+//! // the same values every time
+//! let mut rng = hsr::fake::Rng::seeded(42);
+//! let pets: Vec<api::Pet> = (0..100).map(|_| hsr::fake::Sample::sample(&mut rng)).collect();
+//! ```
+
+use std::collections::{BTreeMap, HashMap};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use indexmap::IndexMap;
+use serde_json::Value;
+
+/// Nested values are left out (optional fields are missing, arrays are empty)
+/// below this depth, so that recursive types have an end
+const MAX_DEPTH: usize = 4;
+
+/// Arrays and maps have up to this many items
+const MAX_ITEMS: usize = 3;
+
+/// Numbers without a bound in the spec stay within this far of zero (or their
+/// other bound), to be of some use
+const UNBOUNDED: i64 = 1_000_000;
+
+const LETTERS: &[u8] = b"abcdefghijklmnopqrstuvwxyz";
+
+/// A small, fast random number generator (SplitMix64). Not for cryptography.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+    depth: usize,
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Rng {
+    /// A generator seeded from the clock
+    pub fn new() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|since| since.as_nanos() as u64)
+            .unwrap_or_default();
+        Self::seeded(nanos)
+    }
+
+    /// A generator making the same values for the same seed
+    pub fn seeded(seed: u64) -> Self {
+        Self {
+            state: seed,
+            depth: 0,
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..n`, for `n > 0`
+    pub fn below(&mut self, n: usize) -> usize {
+        (self.next_u64() % n as u64) as usize
+    }
+
+    pub fn coin(&mut self) -> bool {
+        self.next_u64() & 1 == 1
+    }
+
+    /// An integer within the (inclusive) bounds, if any
+    pub fn int_in(&mut self, min: Option<i64>, max: Option<i64>) -> i64 {
+        let (min, max) = match (min, max) {
+            (Some(min), Some(max)) => (min, max),
+            (Some(min), None) => (min, min.saturating_add(UNBOUNDED)),
+            (None, Some(max)) => (max.saturating_sub(UNBOUNDED), max),
+            (None, None) => (-UNBOUNDED, UNBOUNDED),
+        };
+        if max <= min {
+            return min;
+        }
+        let span = (i128::from(max) - i128::from(min) + 1) as u128;
+        (i128::from(min) + (u128::from(self.next_u64()) % span) as i128) as i64
+    }
+
+    /// A number within the (inclusive) bounds, if any
+    pub fn float_in(&mut self, min: Option<f64>, max: Option<f64>) -> f64 {
+        let unbounded = UNBOUNDED as f64;
+        let (min, max) = match (min, max) {
+            (Some(min), Some(max)) => (min, max),
+            (Some(min), None) => (min, min + unbounded),
+            (None, Some(max)) => (max - unbounded, max),
+            (None, None) => (-unbounded, unbounded),
+        };
+        if max <= min {
+            return min;
+        }
+        // 53 random bits make a fraction in [0, 1]
+        let fraction = (self.next_u64() >> 11) as f64 / ((1u64 << 53) - 1) as f64;
+        (min + fraction * (max - min)).min(max)
+    }
+
+    /// A short lowercase word
+    pub fn string(&mut self) -> String {
+        let len = 3 + self.below(6);
+        (0..len)
+            .map(|_| LETTERS[self.below(LETTERS.len())] as char)
+            .collect()
+    }
+
+    /// Maybe a value, made by `f`. Never one when deeply nested.
+    pub fn maybe<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> Option<T> {
+        if self.depth < MAX_DEPTH && self.coin() {
+            Some(f(self))
+        } else {
+            None
+        }
+    }
+
+    /// A few values made by `f`. None when deeply nested.
+    pub fn many<T>(&mut self, mut f: impl FnMut(&mut Self) -> T) -> Vec<T> {
+        let len = if self.depth < MAX_DEPTH {
+            self.below(MAX_ITEMS + 1)
+        } else {
+            0
+        };
+        (0..len).map(|_| f(self)).collect()
+    }
+
+    /// The value made by `f`, one level deeper. Used by the generated structs.
+    pub fn nested<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        self.depth += 1;
+        let value = f(self);
+        self.depth -= 1;
+        value
+    }
+}
+
+/// A type of which random values can be made
+pub trait Sample: Sized {
+    fn sample(rng: &mut Rng) -> Self;
+}
+
+/// A random value, from a generator seeded from the clock
+pub fn sample<T: Sample>() -> T {
+    T::sample(&mut Rng::new())
+}
+
+impl Sample for bool {
+    fn sample(rng: &mut Rng) -> Self {
+        rng.coin()
+    }
+}
+
+impl Sample for i64 {
+    fn sample(rng: &mut Rng) -> Self {
+        rng.int_in(None, None)
+    }
+}
+
+impl Sample for f64 {
+    fn sample(rng: &mut Rng) -> Self {
+        rng.float_in(None, None)
+    }
+}
+
+impl Sample for String {
+    fn sample(rng: &mut Rng) -> Self {
+        rng.string()
+    }
+}
+
+/// Any JSON will do, so `null` does
+impl Sample for Value {
+    fn sample(_rng: &mut Rng) -> Self {
+        Value::Null
+    }
+}
+
+impl<T: Sample> Sample for Option<T> {
+    fn sample(rng: &mut Rng) -> Self {
+        rng.maybe(T::sample)
+    }
+}
+
+impl<T: Sample> Sample for Vec<T> {
+    fn sample(rng: &mut Rng) -> Self {
+        rng.many(T::sample)
+    }
+}
+
+impl<T: Sample> Sample for IndexMap<String, T> {
+    fn sample(rng: &mut Rng) -> Self {
+        rng.many(|rng| (rng.string(), T::sample(rng)))
+            .into_iter()
+            .collect()
+    }
+}
+
+impl<T: Sample> Sample for BTreeMap<String, T> {
+    fn sample(rng: &mut Rng) -> Self {
+        rng.many(|rng| (rng.string(), T::sample(rng)))
+            .into_iter()
+            .collect()
+    }
+}
+
+impl<T: Sample> Sample for HashMap<String, T> {
+    fn sample(rng: &mut Rng) -> Self {
+        rng.many(|rng| (rng.string(), T::sample(rng)))
+            .into_iter()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn samples() {
+        let mut rng = Rng::seeded(7);
+        for _ in 0..1000 {
+            let n = rng.int_in(Some(-3), Some(3));
+            assert!((-3..=3).contains(&n));
+            let x = rng.float_in(Some(0.5), Some(1.5));
+            assert!((0.5..=1.5).contains(&x));
+            assert!(rng.int_in(Some(i64::MAX - 1), None) >= i64::MAX - 1);
+        }
+        assert_eq!(rng.int_in(Some(i64::MIN), Some(i64::MIN)), i64::MIN);
+
+        // the same seed makes the same values
+        let words: Vec<String> = Sample::sample(&mut Rng::seeded(1));
+        assert_eq!(words, Vec::<String>::sample(&mut Rng::seeded(1)));
+
+        // deeply nested values are left out
+        let mut rng = Rng::seeded(7);
+        let deep: Vec<Vec<Vec<Vec<Vec<bool>>>>> = (0..20)
+            .flat_map(|_| rng.nested(|rng| rng.nested(|rng| rng.nested(|rng| Vec::sample(rng)))))
+            .collect();
+        assert!(deep.iter().flatten().all(|inner| inner.is_empty()));
+    }
+}
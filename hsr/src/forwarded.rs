@@ -0,0 +1,519 @@
+//! The client of a request, as seen through trusted reverse proxies and load balancers.
+//!
+//! Behind a proxy the peer of every request is the proxy, and the client's address,
+//! scheme and host are only known from the `Forwarded` (or `X-Forwarded-For`,
+//! `X-Forwarded-Proto` and `X-Forwarded-Host`) headers it sets. Anyone can set those
+//! headers, so they are only believed when the peer is one of the proxies given to
+//! `Config::trust_proxy`.
+//!
+//! The `ForwardedHeaders` middleware (installed by the generated server) stores the
+//! `ClientInfo` of each request in its extensions. It is used by the rate limiter and
+//! the access log, and handlers can find it with `RequestContext::client_info`.
+
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+use std::task::{Context, Poll};
+
+use actix_http::http::header::{HeaderMap, HOST};
+use actix_http::http::Uri;
+use actix_service::{Service, Transform};
+use actix_web::dev::{AppConfig, ServiceRequest, ServiceResponse};
+use actix_web::{Error as ActixError, HttpMessage};
+use futures::future::{ok, Ready};
+
+const FORWARDED: &str = "forwarded";
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+const X_FORWARDED_HOST: &str = "x-forwarded-host";
+
+/// A range of addresses, like `10.0.0.0/8` or `2001:db8::/32`. A bare address is a
+/// range of one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix_len: u8,
+}
+
+impl Cidr {
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        match (self.addr, addr) {
+            (IpAddr::V4(net), IpAddr::V4(addr)) => prefix_matches(
+                u32::from(net).into(),
+                u32::from(addr).into(),
+                32,
+                self.prefix_len,
+            ),
+            (IpAddr::V6(net), IpAddr::V6(addr)) => {
+                prefix_matches(u128::from(net), u128::from(addr), 128, self.prefix_len)
+            }
+            // an IPv4 peer of a dual-stack socket
+            (IpAddr::V4(_), IpAddr::V6(addr)) => match addr.to_ipv4() {
+                Some(v4) if addr.segments()[..6] == [0, 0, 0, 0, 0, 0xffff] => {
+                    self.contains(IpAddr::V4(v4))
+                }
+                _ => false,
+            },
+            (IpAddr::V6(_), IpAddr::V4(_)) => false,
+        }
+    }
+}
+
+fn prefix_matches(net: u128, addr: u128, bits: u8, prefix_len: u8) -> bool {
+    let shift = bits - prefix_len;
+    shift == bits || net >> shift == addr >> shift
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bad = || format!("Bad address range '{}'", s);
+        let (addr, prefix_len) = match s.find('/') {
+            Some(ix) => (&s[..ix], Some(&s[ix + 1..])),
+            None => (s, None),
+        };
+        let addr: IpAddr = addr.trim().parse().map_err(|_| bad())?;
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(len) => len.trim().parse().map_err(|_| bad())?,
+            None => max,
+        };
+        if prefix_len > max {
+            return Err(bad());
+        }
+        Ok(Cidr { addr, prefix_len })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix_len)
+    }
+}
+
+/// The proxies whose forwarding headers are believed. By default, none are.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TrustedProxies(Vec<Cidr>);
+
+impl TrustedProxies {
+    /// Trust the proxies in the given address ranges
+    pub fn new(cidrs: impl IntoIterator<Item = Cidr>) -> Self {
+        TrustedProxies(cidrs.into_iter().collect())
+    }
+
+    /// Parse address ranges, like `10.0.0.0/8` or `::1`
+    pub fn parse<S: AsRef<str>>(cidrs: impl IntoIterator<Item = S>) -> Result<Self, String> {
+        cidrs
+            .into_iter()
+            .map(|cidr| cidr.as_ref().parse())
+            .collect::<Result<_, _>>()
+            .map(TrustedProxies)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn is_trusted(&self, addr: IpAddr) -> bool {
+        self.0.iter().any(|cidr| cidr.contains(addr))
+    }
+
+    /// Find the client of a request with the given peer and headers. `direct` is
+    /// the scheme and host of the request as received.
+    fn client_info(
+        &self,
+        peer: Option<IpAddr>,
+        headers: &HeaderMap,
+        direct: ClientInfo,
+    ) -> ClientInfo {
+        let peer = match peer {
+            Some(peer) if self.is_trusted(peer) => peer,
+            _ => return direct,
+        };
+        let hops = Forwarded::from_headers(headers).hops;
+        // walk back along the chain of proxies, to the first one not trusted. The
+        // element we stop at, and those after it, were set by trusted proxies.
+        let mut ip = peer;
+        let mut settled = hops.len();
+        for (ix, hop) in hops.iter().enumerate().rev() {
+            settled = ix;
+            match hop.node.as_deref().and_then(parse_node) {
+                Some(addr) => {
+                    ip = addr;
+                    if !self.is_trusted(addr) {
+                        break;
+                    }
+                }
+                // an obfuscated or unknown address hides the rest of the chain
+                None => break,
+            }
+        }
+        // the scheme and host as seen by the trusted proxy nearest the client
+        let trusted = hops.get(settled..).unwrap_or_default();
+        let scheme = trusted
+            .iter()
+            .filter_map(|hop| hop.proto.as_ref())
+            .find(|proto| proto.eq_ignore_ascii_case("http") || proto.eq_ignore_ascii_case("https"))
+            .map(|proto| proto.to_ascii_lowercase())
+            .unwrap_or(direct.scheme);
+        let host = trusted
+            .iter()
+            .filter_map(|hop| hop.host.clone())
+            .find(|host| !host.is_empty())
+            .unwrap_or(direct.host);
+        ClientInfo {
+            ip: Some(ip),
+            scheme,
+            host,
+        }
+    }
+}
+
+/// The client of a request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientInfo {
+    /// The address of the client, if known
+    pub ip: Option<IpAddr>,
+    /// The scheme the client used, `http` or `https`
+    pub scheme: String,
+    /// The host (and port) the client asked for
+    pub host: String,
+}
+
+impl ClientInfo {
+    /// The client of a request as received, not believing any forwarding headers
+    pub fn direct(
+        peer: Option<SocketAddr>,
+        headers: &HeaderMap,
+        uri: &Uri,
+        config: &AppConfig,
+    ) -> Self {
+        let host = headers
+            .get(HOST)
+            .and_then(|host| host.to_str().ok())
+            .map(String::from)
+            .or_else(|| uri.authority().map(|authority| authority.to_string()))
+            .unwrap_or_else(|| config.host().to_string());
+        let scheme = if config.secure() { "https" } else { "http" };
+        ClientInfo {
+            ip: peer.map(|peer| peer.ip()),
+            scheme: scheme.into(),
+            host,
+        }
+    }
+
+    /// The base URL the client used, e.g. to build links and redirects
+    pub fn base_url(&self) -> Option<url::Url> {
+        format!("{}://{}/", self.scheme, self.host).parse().ok()
+    }
+}
+
+/// The address of the client of a request, as found by `ForwardedHeaders`, or else
+/// the address of the peer
+pub fn client_ip(req: &ServiceRequest) -> Option<IpAddr> {
+    req.extensions()
+        .get::<ClientInfo>()
+        .map(|info| info.ip)
+        .unwrap_or_else(|| req.peer_addr().map(|peer| peer.ip()))
+}
+
+/// An element of the forwarding headers, added by one proxy
+#[derive(Debug, Default)]
+struct Hop {
+    /// The address the proxy received the request from
+    node: Option<String>,
+    proto: Option<String>,
+    host: Option<String>,
+}
+
+/// The forwarding headers of a request
+#[derive(Debug, Default)]
+struct Forwarded {
+    /// The client and each proxy but the last, in order
+    hops: Vec<Hop>,
+}
+
+impl Forwarded {
+    /// Read the `Forwarded` header, or else the `X-Forwarded-*` ones
+    fn from_headers(headers: &HeaderMap) -> Self {
+        let values = |name: &str| {
+            headers
+                .get_all(name)
+                .filter_map(|value| value.to_str().ok())
+                .flat_map(|value| value.split(','))
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+                .collect::<Vec<_>>()
+        };
+        let elements = values(FORWARDED);
+        if !elements.is_empty() {
+            let hops = elements
+                .iter()
+                .map(|element| {
+                    let mut hop = Hop::default();
+                    for pair in element.split(';') {
+                        let mut pair = pair.splitn(2, '=');
+                        let key = pair.next().unwrap_or_default().trim();
+                        let value = pair.next().unwrap_or_default().trim().trim_matches('"');
+                        if key.eq_ignore_ascii_case("for") {
+                            hop.node = Some(value.to_string());
+                        } else if key.eq_ignore_ascii_case("proto") {
+                            hop.proto = Some(value.to_string());
+                        } else if key.eq_ignore_ascii_case("host") {
+                            hop.host = Some(value.to_string());
+                        }
+                    }
+                    hop
+                })
+                .collect();
+            return Forwarded { hops };
+        }
+        // proxies append to each header, so line their values up from the end
+        let (nodes, protos, hosts) = (
+            values(X_FORWARDED_FOR),
+            values(X_FORWARDED_PROTO),
+            values(X_FORWARDED_HOST),
+        );
+        let len = nodes.len().max(protos.len()).max(hosts.len());
+        let at = |values: &[String], ix: usize| {
+            (ix + values.len())
+                .checked_sub(len)
+                .map(|ix| values[ix].clone())
+        };
+        let hops = (0..len)
+            .map(|ix| Hop {
+                node: at(&nodes, ix),
+                proto: at(&protos, ix),
+                host: at(&hosts, ix),
+            })
+            .collect();
+        Forwarded { hops }
+    }
+}
+
+/// Parse a forwarded address, which may be quoted, bracketed and have a port
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+    if let Ok(addr) = node.parse::<IpAddr>() {
+        return Some(addr);
+    }
+    if let Ok(addr) = node.parse::<SocketAddr>() {
+        return Some(addr.ip());
+    }
+    // `[2001:db8::1]` without a port, or `1.2.3.4:_hidden`
+    let host = if node.starts_with('[') {
+        node[1..].split(']').next()?
+    } else {
+        node.split(':').next()?
+    };
+    host.parse().ok()
+}
+
+/// Middleware which finds the client of each request, stored in the request extensions
+#[derive(Debug, Clone, Default)]
+pub struct ForwardedHeaders {
+    trusted: TrustedProxies,
+}
+
+impl ForwardedHeaders {
+    pub fn new(trusted: TrustedProxies) -> Self {
+        Self { trusted }
+    }
+}
+
+impl<S, B> Transform<S> for ForwardedHeaders
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = ActixError>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = ActixError;
+    type InitError = ();
+    type Transform = ForwardedHeadersMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(ForwardedHeadersMiddleware {
+            service,
+            trusted: self.trusted.clone(),
+        })
+    }
+}
+
+pub struct ForwardedHeadersMiddleware<S> {
+    service: S,
+    trusted: TrustedProxies,
+}
+
+impl<S, B> Service for ForwardedHeadersMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = ActixError>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = ActixError;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let peer = req.peer_addr();
+        let direct = ClientInfo::direct(peer, req.headers(), req.uri(), req.app_config());
+        let info = self
+            .trusted
+            .client_info(peer.map(|peer| peer.ip()), req.headers(), direct);
+        req.extensions_mut().insert(info);
+        self.service.call(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_http::http::HeaderValue;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(
+                actix_http::http::HeaderName::from_static(name),
+                HeaderValue::from_static(value),
+            );
+        }
+        headers
+    }
+
+    fn direct() -> ClientInfo {
+        ClientInfo {
+            ip: None,
+            scheme: "http".into(),
+            host: "internal:8000".into(),
+        }
+    }
+
+    #[test]
+    fn cidrs() {
+        let net: Cidr = "10.0.0.0/8".parse().unwrap();
+        assert!(net.contains("10.1.2.3".parse().unwrap()));
+        assert!(net.contains("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!net.contains("11.0.0.1".parse().unwrap()));
+        let one: Cidr = "::1".parse().unwrap();
+        assert_eq!(one.to_string(), "::1/128");
+        assert!(one.contains("::1".parse().unwrap()));
+        assert!(!one.contains("::2".parse().unwrap()));
+        let all: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(all.contains("192.0.2.1".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("localhost".parse::<Cidr>().is_err());
+    }
+
+    #[test]
+    fn forwarded_for() {
+        let trusted = TrustedProxies::parse(&["10.0.0.0/8"]).unwrap();
+        let proxy = "10.0.0.1".parse().ok();
+        let headers = headers(&[
+            ("x-forwarded-for", "192.0.2.1, 198.51.100.7, 10.0.0.2"),
+            ("x-forwarded-proto", "https"),
+            ("x-forwarded-host", "api.example.com"),
+        ]);
+        let info = trusted.client_info(proxy, &headers, direct());
+        // the last address not of a trusted proxy
+        assert_eq!(info.ip, "198.51.100.7".parse().ok());
+        assert_eq!(info.scheme, "https");
+        assert_eq!(
+            info.base_url().unwrap().as_str(),
+            "https://api.example.com/"
+        );
+
+        // from an untrusted peer, the headers are ignored
+        let stranger = "203.0.113.9".parse().ok();
+        let info = trusted.client_info(
+            stranger,
+            &headers,
+            ClientInfo {
+                ip: stranger,
+                ..direct()
+            },
+        );
+        assert_eq!(info.ip, stranger);
+        assert_eq!(info.scheme, "http");
+        assert_eq!(info.host, "internal:8000");
+        let info = TrustedProxies::default().client_info(proxy, &headers, direct());
+        assert_eq!(info, direct());
+    }
+
+    #[test]
+    fn forwarded() {
+        let trusted = TrustedProxies::parse(&["10.0.0.1"]).unwrap();
+        let proxy = "10.0.0.1".parse().ok();
+        let headers = headers(&[
+            (
+                "forwarded",
+                r#"for="[2001:db8:cafe::17]:4711";proto=https;host=example.com"#,
+            ),
+            ("x-forwarded-for", "192.0.2.1"),
+        ]);
+        let info = trusted.client_info(proxy, &headers, direct());
+        assert_eq!(info.ip, "2001:db8:cafe::17".parse().ok());
+        assert_eq!(info.scheme, "https");
+        assert_eq!(info.host, "example.com");
+
+        // an obfuscated client leaves the proxy as the client
+        let headers = self::headers(&[("forwarded", "for=_hidden, for=10.0.0.1")]);
+        let info = trusted.client_info(proxy, &headers, direct());
+        assert_eq!(info.ip, proxy);
+    }
+
+    #[test]
+    fn spoofed_proto_and_host() {
+        let trusted = TrustedProxies::parse(&["10.0.0.0/8"]).unwrap();
+        let proxy = "10.0.0.1".parse().ok();
+
+        // the client sent its own element, which the trusted proxy appended to
+        let headers = headers(&[(
+            "forwarded",
+            "for=192.0.2.66;proto=https;host=evil, for=198.51.100.7;proto=http;host=api.example.com",
+        )]);
+        let info = trusted.client_info(proxy, &headers, direct());
+        assert_eq!(info.ip, "198.51.100.7".parse().ok());
+        assert_eq!(info.scheme, "http");
+        assert_eq!(info.host, "api.example.com");
+
+        // ...from behind a further trusted proxy, which doesn't say
+        let headers = self::headers(&[(
+            "forwarded",
+            "proto=https;host=evil, for=198.51.100.7;proto=http;host=api.example.com, for=10.0.0.2",
+        )]);
+        let info = trusted.client_info(proxy, &headers, direct());
+        assert_eq!(info.ip, "198.51.100.7".parse().ok());
+        assert_eq!(info.scheme, "http");
+        assert_eq!(info.host, "api.example.com");
+
+        // and the same with the X-Forwarded-* headers
+        let headers = self::headers(&[
+            ("x-forwarded-for", "192.0.2.66, 198.51.100.7"),
+            ("x-forwarded-proto", "https, http"),
+            ("x-forwarded-host", "evil, api.example.com"),
+        ]);
+        let info = trusted.client_info(proxy, &headers, direct());
+        assert_eq!(info.ip, "198.51.100.7".parse().ok());
+        assert_eq!(info.scheme, "http");
+        assert_eq!(info.host, "api.example.com");
+
+        // a proxy which doesn't set them leaves those of the request as received
+        let headers = self::headers(&[("forwarded", "for=198.51.100.7, for=10.0.0.2;proto=https")]);
+        let info = trusted.client_info(proxy, &headers, direct());
+        assert_eq!(info.scheme, "https");
+        let headers = self::headers(&[("forwarded", "proto=https;host=evil, for=198.51.100.7")]);
+        let info = trusted.client_info(proxy, &headers, direct());
+        assert_eq!(info.scheme, "http");
+        assert_eq!(info.host, "internal:8000");
+    }
+}
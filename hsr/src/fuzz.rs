@@ -0,0 +1,108 @@
+//! Fuzzing the extraction of requests, to find the inputs which make the generated
+//! (de)serialization code panic before anyone else does.
+//!
+//! With `GenerationOptions::fuzz_targets` (and the `fuzz` feature of `hsr`), a `fuzz`
+//! module is generated with a function for each operation, which takes arbitrary
+//! bytes as an `Input` and extracts the path parameters, the query parameters and
+//! the body of the operation from them, as the server does. `hsr fuzz-targets`
+//! writes a `cargo fuzz` target calling each:
+//!
+//! ```ignore
+//! #![no_main]
+//! libfuzzer_sys::fuzz_target!(|data: &[u8]| my_crate::api::fuzz::add_pet(data));
+//! ```
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::negotiate::Representations;
+
+/// The parts of a request, made from arbitrary bytes. The body is what follows the
+/// first NUL byte. Before it, each line is the (percent-encoded) value of a path
+/// parameter, and the rest is the query string.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Input<'a> {
+    pub path: Vec<&'a str>,
+    pub query: &'a str,
+    pub body: &'a [u8],
+}
+
+impl<'a> Input<'a> {
+    /// Split `data` into the values of `path_params` path parameters, the query
+    /// string and the body. Text which isn't UTF-8 is cut short.
+    pub fn new(data: &'a [u8], path_params: usize) -> Self {
+        let (head, body) = match data.iter().position(|byte| *byte == 0) {
+            Some(ix) => (&data[..ix], &data[ix + 1..]),
+            None => (data, &data[data.len()..]),
+        };
+        let head = match std::str::from_utf8(head) {
+            Ok(head) => head,
+            Err(e) => std::str::from_utf8(&head[..e.valid_up_to()]).unwrap(),
+        };
+        let mut lines = head.splitn(path_params + 1, '\n');
+        let path = (0..path_params)
+            .map(|_| lines.next().unwrap_or_default())
+            .collect();
+        let query = lines.next().unwrap_or_default();
+        Self { path, query, body }
+    }
+
+    /// The path parameters called `names`, as the server extracts them
+    pub fn path<T: DeserializeOwned>(&self, names: &[&str]) -> Option<T> {
+        crate::path::from_params(names.iter().copied().zip(self.path.iter().copied())).ok()
+    }
+
+    /// The query parameters, as the server extracts them. They are serialized
+    /// again, as the client does.
+    pub fn query<T: DeserializeOwned + Serialize>(&self) -> Option<T> {
+        let params: T = serde_urlencoded::from_str(self.query).ok()?;
+        let _ = serde_urlencoded::to_string(&params);
+        Some(params)
+    }
+
+    /// The JSON body, serialized again
+    pub fn json<T: DeserializeOwned + Serialize>(&self) -> Option<T> {
+        let body: T = serde_json::from_slice(self.body).ok()?;
+        let _ = serde_json::to_vec(&body);
+        Some(body)
+    }
+
+    /// The body in the first of its representations which it makes sense as,
+    /// serialized again
+    pub fn representations<T: Representations>(&self) -> Option<T> {
+        let body = T::MEDIA_TYPES
+            .iter()
+            .find_map(|media_type| T::from_body(media_type, self.body).ok())?;
+        let _ = body.to_body();
+        Some(body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Deserialize, Serialize)]
+    struct Params {
+        id: i64,
+        name: String,
+    }
+
+    #[test]
+    fn fuzz_input() {
+        let input = Input::new(b"12\nRex%2F1\nverbose=true\0{\"id\": 1}", 2);
+        assert_eq!(input.path, vec!["12", "Rex%2F1"]);
+        assert_eq!(input.query, "verbose=true");
+        assert_eq!(input.body, b"{\"id\": 1}");
+        let params: Params = input.path(&["id", "name"]).unwrap();
+        assert_eq!(params.name, "Rex/1");
+        assert_eq!(input.json::<serde_json::Value>().unwrap()["id"], 1);
+        assert!(input.query::<Params>().is_none());
+
+        // anything goes
+        let input = Input::new(b"\xff\xfe\n\0", 3);
+        assert_eq!(input.path, vec!["", "", ""]);
+        assert!(input.path::<Params>(&["id", "name"]).is_none());
+        assert!(input.json::<Params>().is_none());
+    }
+}
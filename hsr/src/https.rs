@@ -0,0 +1,253 @@
+//! Safe defaults for servers which terminate TLS themselves.
+//!
+//! With `Config::hsts`, the `HstsHeaders` middleware (installed by the generated
+//! server) sets `Strict-Transport-Security` on the responses to HTTPS requests, so
+//! browsers stop trying plain HTTP. With `Config::redirect_http`, the server also
+//! listens for plain HTTP on another port, answering every request with a redirect
+//! to the same path over HTTPS.
+
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use actix_http::http::header::{HeaderValue, HOST, LOCATION, STRICT_TRANSPORT_SECURITY};
+use actix_http::http::{Method, StatusCode};
+use actix_service::{Service, Transform};
+use actix_web::dev::{Server, ServiceRequest, ServiceResponse};
+use actix_web::{
+    web, App, Error as ActixError, HttpMessage, HttpRequest, HttpResponse, HttpServer,
+};
+use futures::future::{ok, FutureExt, LocalBoxFuture, Ready};
+use url::{Host, Url};
+
+use crate::forwarded::ClientInfo;
+
+/// A `Strict-Transport-Security` policy
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hsts {
+    /// How long browsers should only use HTTPS
+    pub max_age: Duration,
+    /// The policy also covers subdomains
+    pub include_subdomains: bool,
+    /// Ask to be included in browsers' preload lists
+    pub preload: bool,
+}
+
+impl Default for Hsts {
+    /// Two years, as required for preloading, without subdomains
+    fn default() -> Self {
+        Self::max_age(Duration::from_secs(2 * 365 * 24 * 60 * 60))
+    }
+}
+
+impl Hsts {
+    pub fn max_age(max_age: Duration) -> Self {
+        Self {
+            max_age,
+            include_subdomains: false,
+            preload: false,
+        }
+    }
+
+    pub fn include_subdomains(mut self) -> Self {
+        self.include_subdomains = true;
+        self
+    }
+
+    pub fn preload(mut self) -> Self {
+        self.preload = true;
+        self
+    }
+
+    /// The value of the `Strict-Transport-Security` header
+    pub fn header_value(&self) -> String {
+        let mut value = format!("max-age={}", self.max_age.as_secs());
+        if self.include_subdomains {
+            value.push_str("; includeSubDomains");
+        }
+        if self.preload {
+            value.push_str("; preload");
+        }
+        value
+    }
+}
+
+/// Middleware which sets `Strict-Transport-Security` on the responses to HTTPS
+/// requests (unless the handler has set it), if there is a policy
+#[derive(Debug, Clone, Default)]
+pub struct HstsHeaders {
+    policy: Option<Hsts>,
+}
+
+impl HstsHeaders {
+    pub fn new(policy: Option<Hsts>) -> Self {
+        Self { policy }
+    }
+}
+
+impl<S, B> Transform<S> for HstsHeaders
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = ActixError>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = ActixError;
+    type InitError = ();
+    type Transform = HstsHeadersMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        let header = self
+            .policy
+            .as_ref()
+            .and_then(|policy| HeaderValue::from_str(&policy.header_value()).ok());
+        ok(HstsHeadersMiddleware { service, header })
+    }
+}
+
+pub struct HstsHeadersMiddleware<S> {
+    service: S,
+    header: Option<HeaderValue>,
+}
+
+impl<S, B> Service for HstsHeadersMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = ActixError>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = ActixError;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        // behind a trusted proxy, it is the client's scheme which counts
+        let secure = req
+            .extensions()
+            .get::<ClientInfo>()
+            .map(|info| info.scheme == "https")
+            .unwrap_or_else(|| req.app_config().secure());
+        let header = self.header.clone().filter(|_| secure);
+        let fut = self.service.call(req);
+        async move {
+            let mut res = fut.await?;
+            if let Some(header) = header {
+                if !res.headers().contains_key(STRICT_TRANSPORT_SECURITY) {
+                    res.headers_mut().insert(STRICT_TRANSPORT_SECURITY, header);
+                }
+            }
+            Ok(res)
+        }
+        .boxed_local()
+    }
+}
+
+/// Where to redirect a plain HTTP request, given the HTTPS URL of the server. The
+/// host asked for is kept, so a server listening on all interfaces redirects to
+/// the name it was reached by.
+pub fn redirect_location(https: &Url, host: Option<&str>, path_and_query: &str) -> Option<Url> {
+    let mut location = https.clone();
+    let mut parts = path_and_query.splitn(2, '?');
+    location.set_path(parts.next().unwrap_or("/"));
+    location.set_query(parts.next());
+    if let Some(host) = host {
+        // drop the port of the plain HTTP listener
+        let name = if host.starts_with('[') {
+            host.split(']').next().map(|name| format!("{}]", name))
+        } else {
+            host.split(':').next().map(String::from)
+        };
+        if let Some(name) = name.filter(|name| !name.is_empty()) {
+            location.set_host(Some(&name)).ok()?;
+        }
+    }
+    Some(location)
+}
+
+async fn redirect(req: HttpRequest, https: web::Data<Url>) -> HttpResponse {
+    let host = req.headers().get(HOST).and_then(|host| host.to_str().ok());
+    let path_and_query = req
+        .uri()
+        .path_and_query()
+        .map(|path| path.as_str())
+        .unwrap_or("/");
+    match redirect_location(&https, host, path_and_query) {
+        Some(location) => {
+            // other methods must not be turned into a GET
+            let status = if req.method() == Method::GET || req.method() == Method::HEAD {
+                StatusCode::MOVED_PERMANENTLY
+            } else {
+                StatusCode::PERMANENT_REDIRECT
+            };
+            HttpResponse::build(status)
+                .header(LOCATION, location.as_str())
+                .finish()
+        }
+        None => HttpResponse::BadRequest().finish(),
+    }
+}
+
+/// Start listening for plain HTTP on `port` of the host of `https` (the HTTPS URL
+/// of the server), redirecting every request there. The returned server is stopped
+/// with `Server::stop`.
+pub fn redirect_server(https: &Url, port: u16) -> std::io::Result<Server> {
+    // `host_str` brackets an IPv6 address, which can't be bound
+    let host = match https.host() {
+        Some(Host::Ipv6(addr)) => addr.to_string(),
+        Some(host) => host.to_string(),
+        None => "127.0.0.1".to_string(),
+    };
+    let https = https.clone();
+    let server = HttpServer::new(move || {
+        App::new()
+            .data(https.clone())
+            .default_service(web::route().to(redirect))
+    })
+    .bind((host.as_str(), port))?;
+    Ok(server.run())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn header_value() {
+        let hsts = Hsts::max_age(Duration::from_secs(3600));
+        assert_eq!(hsts.header_value(), "max-age=3600");
+        let hsts = Hsts::default().include_subdomains().preload();
+        assert_eq!(
+            hsts.header_value(),
+            "max-age=63072000; includeSubDomains; preload"
+        );
+    }
+
+    #[test]
+    fn locations() {
+        let https: Url = "https://0.0.0.0:8443/".parse().unwrap();
+        let location = redirect_location(&https, Some("example.com:8080"), "/pets?limit=2");
+        assert_eq!(
+            location.unwrap().as_str(),
+            "https://example.com:8443/pets?limit=2"
+        );
+        let location = redirect_location(&https, Some("[::1]:8080"), "/");
+        assert_eq!(location.unwrap().as_str(), "https://[::1]:8443/");
+        let location = redirect_location(&https, None, "/pets");
+        assert_eq!(location.unwrap().as_str(), "https://0.0.0.0:8443/pets");
+
+        // the path can't take the redirect elsewhere
+        let location = redirect_location(&https, None, "//evil.com/pets");
+        assert_eq!(location.unwrap().host_str(), Some("0.0.0.0"));
+
+        // the default port is left out
+        let https: Url = "https://example.com:443/".parse().unwrap();
+        let location = redirect_location(&https, Some("example.com"), "/");
+        assert_eq!(location.unwrap().as_str(), "https://example.com/");
+    }
+}
@@ -0,0 +1,615 @@
+//! Idempotency keys, for operations marked with `x-hsr-idempotent`.
+//!
+//! The first request with a given `Idempotency-Key` is handled as usual, and its
+//! response is recorded. Repeats of the request get the recorded response back
+//! (with an `Idempotent-Replayed: true` header) instead of being handled again,
+//! and repeats which arrive while the first is still being handled are rejected
+//! with `409 Conflict`. Server errors (5xx) are not recorded, so they can be retried,
+//! and neither are the requests which are dropped before they have a response.
+//!
+//! Keys belong to the caller, as told by its credentials (the `Authorization`,
+//! `Proxy-Authorization` and `Cookie` headers), so one caller can't replay another's
+//! responses. Reusing a key for a different request (another path, query or body)
+//! is rejected with `422 Unprocessable Entity`. Cookies set and the `X-Request-Id`
+//! of the first response are not replayed. Request bodies are read to tell requests
+//! apart, up to `IdempotencyKeys::max_body`; larger ones get `413 Payload Too Large`.
+//!
+//! Responses live in an `IdempotencyStore`. The default `InMemoryStore` is local to
+//! the process; implement the trait on top of e.g. Redis to share keys between replicas.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use actix_http::http::header::{CONTENT_LENGTH, SET_COOKIE};
+use actix_http::http::{HeaderName, HeaderValue, Method, StatusCode};
+use actix_service::{Service, Transform};
+use actix_web::dev::{Body, ResponseBody, ServiceRequest, ServiceResponse};
+use actix_web::{Error as ActixError, HttpMessage, HttpResponse};
+use bytes::{Bytes, BytesMut};
+use futures::future::{ok, FutureExt, LocalBoxFuture, Ready};
+use futures::StreamExt;
+use sha2::{Digest, Sha256};
+
+use crate::cache::CREDENTIAL_HEADERS;
+use crate::request_id::REQUEST_ID_HEADER;
+
+/// The `Idempotency-Key` request header
+pub const IDEMPOTENCY_KEY_HEADER: &str = "idempotency-key";
+
+/// Set on responses which are replays of a recorded response
+pub const REPLAYED_HEADER: &str = "idempotent-replayed";
+
+/// Incoming keys longer than this are rejected
+const MAX_KEY_LEN: usize = 255;
+
+/// The default for `IdempotencyKeys::max_body`: the default limit of actix-web's
+/// `PayloadConfig`, the largest body the generated handlers accept
+pub const DEFAULT_MAX_BODY: usize = 262_144;
+
+/// A fresh key, as sent by the generated client
+pub fn generate_key() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+/// A digest of `parts` which is the same in every build, so that replicas sharing
+/// a store agree
+fn digest<'a>(parts: impl IntoIterator<Item = &'a [u8]>) -> String {
+    let mut hasher = Sha256::new();
+    for part in parts {
+        hasher.update((part.len() as u64).to_be_bytes());
+        hasher.update(part);
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// What tells a request apart from another with the same key: its method, path,
+/// query and body
+fn fingerprint(req: &ServiceRequest, body: &[u8]) -> String {
+    digest(vec![
+        req.method().as_str().as_bytes(),
+        req.path().as_bytes(),
+        req.query_string().as_bytes(),
+        body,
+    ])
+}
+
+/// Who the keys of a request belong to, as told by its credentials
+fn caller(req: &ServiceRequest) -> String {
+    digest(CREDENTIAL_HEADERS.iter().flat_map(|name| {
+        req.headers()
+            .get_all(name)
+            .flat_map(move |value| vec![name.as_str().as_bytes(), value.as_bytes()])
+    }))
+}
+
+/// Read the body of `req`, and put it back for the handler. `None` if it is longer
+/// than `limit`.
+async fn read_body(req: &mut ServiceRequest, limit: usize) -> Result<Option<Bytes>, ActixError> {
+    let length = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|length| length.to_str().ok())
+        .and_then(|length| length.parse::<usize>().ok());
+    if length.map_or(false, |length| length > limit) {
+        return Ok(None);
+    }
+    let mut payload = req.take_payload();
+    let mut body = BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk?;
+        if body.len() + chunk.len() > limit {
+            return Ok(None);
+        }
+        body.extend_from_slice(&chunk);
+    }
+    let body = body.freeze();
+    let (_, mut payload) = actix_http::h1::Payload::create(true);
+    payload.unread_data(body.clone());
+    req.set_payload(payload.into());
+    Ok(Some(body))
+}
+
+/// How an operation uses idempotency keys
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Idempotency {
+    /// Reject requests without a key with `400 Bad Request`.
+    /// Otherwise they are handled normally, without being recorded.
+    pub required: bool,
+    /// How long responses are kept for
+    pub ttl: Duration,
+}
+
+impl Idempotency {
+    pub fn new(required: bool, ttl: Duration) -> Self {
+        Self { required, ttl }
+    }
+}
+
+/// A recorded response
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CachedResponse {
+    pub status: u16,
+    pub headers: Vec<(String, Vec<u8>)>,
+    pub body: Vec<u8>,
+}
+
+impl CachedResponse {
+    /// Record a response. Streaming bodies can't be recorded.
+    fn from_response(res: &ServiceResponse) -> Option<Self> {
+        let body: &[u8] = match res.response().body() {
+            ResponseBody::Body(Body::Bytes(bytes)) | ResponseBody::Other(Body::Bytes(bytes)) => {
+                bytes
+            }
+            ResponseBody::Body(Body::None)
+            | ResponseBody::Body(Body::Empty)
+            | ResponseBody::Other(Body::None)
+            | ResponseBody::Other(Body::Empty) => b"",
+            _ => return None,
+        };
+        // the cookies and request id are the first caller's
+        let headers = res
+            .headers()
+            .iter()
+            .filter(|(name, _)| **name != SET_COOKIE && name.as_str() != REQUEST_ID_HEADER)
+            .map(|(name, value)| (name.as_str().to_string(), value.as_bytes().to_vec()))
+            .collect();
+        Some(CachedResponse {
+            status: res.status().as_u16(),
+            headers,
+            body: body.to_vec(),
+        })
+    }
+
+    fn to_response(&self) -> HttpResponse {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let mut builder = HttpResponse::build(status);
+        for (name, value) in &self.headers {
+            if let (Ok(name), Ok(value)) = (
+                HeaderName::from_bytes(name.as_bytes()),
+                HeaderValue::from_bytes(value),
+            ) {
+                builder.header(name, value);
+            }
+        }
+        builder
+            .header(REPLAYED_HEADER, "true")
+            .body(self.body.clone())
+    }
+}
+
+/// The state of a key, when a request claims it
+#[derive(Debug, Clone, PartialEq)]
+pub enum Claim {
+    /// The key is new, and is now in progress
+    New,
+    /// Another request with the key is being handled
+    InProgress,
+    /// The key has been used, with this response
+    Done(CachedResponse),
+    /// The key has been used for a different request
+    Mismatch,
+}
+
+/// Storage for recorded responses
+#[async_trait::async_trait]
+pub trait IdempotencyStore: Send + Sync + 'static {
+    /// Claim `key` for a request, for up to `ttl`. A key claimed with another
+    /// `fingerprint` is a `Claim::Mismatch`.
+    async fn begin(&self, key: &str, fingerprint: &str, ttl: Duration) -> Claim;
+    /// Record the response to the request which claimed `key`
+    async fn complete(&self, key: &str, response: CachedResponse, ttl: Duration);
+    /// Release `key` without a response, so that the request can be retried
+    async fn abandon(&self, key: &str);
+}
+
+/// An in-process `IdempotencyStore`
+#[derive(Debug, Default)]
+pub struct InMemoryStore {
+    entries: Mutex<HashMap<String, Entry>>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    fingerprint: String,
+    response: Option<CachedResponse>,
+    expires: Instant,
+}
+
+impl InMemoryStore {
+    fn claim(&self, key: &str, fingerprint: &str, ttl: Duration, now: Instant) -> Claim {
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, entry| entry.expires > now);
+        match entries.get(key) {
+            Some(entry) if entry.fingerprint != fingerprint => Claim::Mismatch,
+            Some(Entry {
+                response: Some(response),
+                ..
+            }) => Claim::Done(response.clone()),
+            Some(Entry { response: None, .. }) => Claim::InProgress,
+            None => {
+                entries.insert(
+                    key.to_string(),
+                    Entry {
+                        fingerprint: fingerprint.to_string(),
+                        response: None,
+                        expires: now + ttl,
+                    },
+                );
+                Claim::New
+            }
+        }
+    }
+
+    fn record(&self, key: &str, response: CachedResponse, ttl: Duration, now: Instant) {
+        let mut entries = self.entries.lock().unwrap();
+        // the key may have been abandoned in the meantime
+        if let Some(entry) = entries.get_mut(key) {
+            entry.response = Some(response);
+            entry.expires = now + ttl;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl IdempotencyStore for InMemoryStore {
+    async fn begin(&self, key: &str, fingerprint: &str, ttl: Duration) -> Claim {
+        self.claim(key, fingerprint, ttl, Instant::now())
+    }
+
+    async fn complete(&self, key: &str, response: CachedResponse, ttl: Duration) {
+        self.record(key, response, ttl, Instant::now())
+    }
+
+    async fn abandon(&self, key: &str) {
+        self.entries.lock().unwrap().remove(key);
+    }
+}
+
+/// A key claimed by a request. If the request is dropped before it has a response
+/// (e.g. because the client went away), the key is abandoned rather than left in
+/// progress until it expires.
+struct Claimed {
+    store: Arc<dyn IdempotencyStore>,
+    key: String,
+    settled: bool,
+}
+
+impl Claimed {
+    fn new(store: Arc<dyn IdempotencyStore>, key: String) -> Self {
+        Self {
+            store,
+            key,
+            settled: false,
+        }
+    }
+
+    /// Record the response to the request, or abandon the key without one
+    async fn settle(mut self, response: Option<CachedResponse>, ttl: Duration) {
+        match response {
+            Some(response) => self.store.complete(&self.key, response, ttl).await,
+            None => self.store.abandon(&self.key).await,
+        }
+        self.settled = true;
+    }
+}
+
+impl Drop for Claimed {
+    fn drop(&mut self) {
+        if !self.settled {
+            let store = self.store.clone();
+            let key = std::mem::take(&mut self.key);
+            actix_rt::spawn(async move { store.abandon(&key).await });
+        }
+    }
+}
+
+/// Middleware applying idempotency keys to the operations of a resource
+#[derive(Clone)]
+pub struct IdempotencyKeys {
+    store: Arc<dyn IdempotencyStore>,
+    operations: Arc<Vec<(Method, String, Idempotency)>>,
+    max_body: usize,
+}
+
+impl IdempotencyKeys {
+    pub fn new(store: Arc<dyn IdempotencyStore>) -> Self {
+        Self {
+            store,
+            operations: Arc::new(Vec::new()),
+            max_body: DEFAULT_MAX_BODY,
+        }
+    }
+
+    /// Reject requests with a key and a body longer than `limit` bytes, rather than
+    /// reading them into memory. Defaults to `DEFAULT_MAX_BODY`.
+    pub fn max_body(mut self, limit: usize) -> Self {
+        self.max_body = limit;
+        self
+    }
+
+    /// Apply idempotency keys to an operation
+    pub fn operation(
+        mut self,
+        method: Method,
+        operation_id: &str,
+        idempotency: Idempotency,
+    ) -> Self {
+        Arc::make_mut(&mut self.operations).push((method, operation_id.to_string(), idempotency));
+        self
+    }
+}
+
+impl<S> Transform<S> for IdempotencyKeys
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = ActixError> + 'static,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = ActixError;
+    type InitError = ();
+    type Transform = IdempotencyKeysMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(IdempotencyKeysMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            store: self.store.clone(),
+            operations: self.operations.clone(),
+            max_body: self.max_body,
+        })
+    }
+}
+
+pub struct IdempotencyKeysMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    store: Arc<dyn IdempotencyStore>,
+    operations: Arc<Vec<(Method, String, Idempotency)>>,
+    max_body: usize,
+}
+
+impl<S> Service for IdempotencyKeysMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = ActixError> + 'static,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = ActixError;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let (opid, idempotency) = match self
+            .operations
+            .iter()
+            .find(|(method, _, _)| method == req.method())
+        {
+            Some((_, opid, idempotency)) => (opid.clone(), idempotency.clone()),
+            None => return self.service.borrow_mut().call(req).boxed_local(),
+        };
+        let key = match req.headers().get(IDEMPOTENCY_KEY_HEADER) {
+            Some(value) => match value.to_str() {
+                Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LEN => key.to_string(),
+                _ => {
+                    let resp = HttpResponse::BadRequest().body("Bad Idempotency-Key header");
+                    return ok(req.into_response(resp)).boxed_local();
+                }
+            },
+            None if idempotency.required => {
+                let resp = HttpResponse::BadRequest().body("Missing Idempotency-Key header");
+                return ok(req.into_response(resp)).boxed_local();
+            }
+            None => return self.service.borrow_mut().call(req).boxed_local(),
+        };
+        let key = format!("{}:{}:{}", caller(&req), opid, key);
+        let store = self.store.clone();
+        let service = self.service.clone();
+        let max_body = self.max_body;
+        async move {
+            let mut req = req;
+            let body = match read_body(&mut req, max_body).await? {
+                Some(body) => body,
+                None => {
+                    let resp = HttpResponse::PayloadTooLarge().body("Request body too large");
+                    return Ok(req.into_response(resp));
+                }
+            };
+            let fingerprint = fingerprint(&req, &body);
+            match store.begin(&key, &fingerprint, idempotency.ttl).await {
+                Claim::Done(response) => Ok(req.into_response(response.to_response())),
+                Claim::InProgress => {
+                    let resp = HttpResponse::Conflict()
+                        .body("A request with this Idempotency-Key is in progress");
+                    Ok(req.into_response(resp))
+                }
+                Claim::Mismatch => {
+                    let resp = HttpResponse::UnprocessableEntity()
+                        .body("The Idempotency-Key was used for a different request");
+                    Ok(req.into_response(resp))
+                }
+                Claim::New => {
+                    let claimed = Claimed::new(store, key);
+                    // don't hold the borrow across the await
+                    let fut = service.borrow_mut().call(req);
+                    let res = fut.await;
+                    let recorded = res
+                        .as_ref()
+                        .ok()
+                        .filter(|res| !res.status().is_server_error())
+                        .and_then(CachedResponse::from_response);
+                    claimed.settle(recorded, idempotency.ttl).await;
+                    res
+                }
+            }
+        }
+        .boxed_local()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App};
+
+    #[test]
+    fn claim_keys() {
+        let store = InMemoryStore::default();
+        let ttl = Duration::from_secs(10);
+        let start = Instant::now();
+        let response = CachedResponse {
+            status: 201,
+            headers: vec![("content-type".into(), b"application/json".to_vec())],
+            body: b"{}".to_vec(),
+        };
+
+        assert_eq!(store.claim("a", "1", ttl, start), Claim::New);
+        assert_eq!(store.claim("a", "1", ttl, start), Claim::InProgress);
+        store.record("a", response.clone(), ttl, start);
+        assert_eq!(store.claim("a", "1", ttl, start), Claim::Done(response));
+        // for another request
+        assert_eq!(store.claim("a", "2", ttl, start), Claim::Mismatch);
+        // other keys are independent
+        assert_eq!(store.claim("b", "2", ttl, start), Claim::New);
+        // expired
+        assert_eq!(store.claim("a", "2", ttl, start + ttl), Claim::New);
+    }
+
+    #[actix_rt::test]
+    async fn replay_responses() {
+        let store: Arc<dyn IdempotencyStore> = Arc::new(InMemoryStore::default());
+        let keys = IdempotencyKeys::new(store).operation(
+            Method::POST,
+            "pay",
+            Idempotency::new(true, Duration::from_secs(60)),
+        );
+        let calls = Arc::new(Mutex::new(0));
+        let counter = calls.clone();
+        let mut app =
+            test::init_service(App::new().service(web::resource("/pay").wrap(keys).route(
+                web::post().to(move |body: Bytes| {
+                    *counter.lock().unwrap() += 1;
+                    HttpResponse::Created()
+                        .header(SET_COOKIE, "session=1")
+                        .header(REQUEST_ID_HEADER, "first")
+                        .body(body)
+                }),
+            )))
+            .await;
+        let pay = |key: &str, who: &str, body: &'static str| {
+            test::TestRequest::post()
+                .uri("/pay")
+                .header(IDEMPOTENCY_KEY_HEADER, key)
+                .header("authorization", who)
+                .set_payload(body)
+                .to_request()
+        };
+
+        let resp = test::call_service(&mut app, pay("k", "alice", "10")).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        let resp = test::call_service(&mut app, pay("k", "alice", "10")).await;
+        assert_eq!(resp.status(), StatusCode::CREATED);
+        assert_eq!(resp.headers().get(REPLAYED_HEADER).unwrap(), "true");
+        assert!(resp.headers().get(SET_COOKIE).is_none());
+        assert!(resp.headers().get(REQUEST_ID_HEADER).is_none());
+        assert_eq!(test::read_body(resp).await, Bytes::from_static(b"10"));
+        assert_eq!(*calls.lock().unwrap(), 1);
+
+        // the key can't be reused for another payment
+        let resp = test::call_service(&mut app, pay("k", "alice", "20")).await;
+        assert_eq!(resp.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(*calls.lock().unwrap(), 1);
+
+        // another caller's key is their own
+        let resp = test::call_service(&mut app, pay("k", "bob", "20")).await;
+        assert!(resp.headers().get(REPLAYED_HEADER).is_none());
+        assert_eq!(test::read_body(resp).await, Bytes::from_static(b"20"));
+        assert_eq!(*calls.lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn stable_digests() {
+        let req = test::TestRequest::post()
+            .uri("/pay?x=1")
+            .header("authorization", "alice")
+            .to_srv_request();
+        assert_eq!(
+            fingerprint(&req, b"10"),
+            "5bfdc8a213b383a821d5c2b711076f25027c981674b864914b6f15f930d756f2"
+        );
+        assert_eq!(
+            caller(&req),
+            "cf6fdd36d3afa4eca132d6de8859b6eb2e02815381ac5b6892e241db9f876e07"
+        );
+        // the parts can't run into each other
+        assert_ne!(
+            digest(vec![&b"ab"[..], b"c"]),
+            digest(vec![&b"a"[..], b"bc"])
+        );
+    }
+
+    #[actix_rt::test]
+    async fn large_bodies() {
+        let store: Arc<dyn IdempotencyStore> = Arc::new(InMemoryStore::default());
+        let keys = IdempotencyKeys::new(store)
+            .operation(
+                Method::POST,
+                "pay",
+                Idempotency::new(false, Duration::from_secs(60)),
+            )
+            .max_body(4);
+        let mut app = test::init_service(
+            App::new().service(
+                web::resource("/pay")
+                    .wrap(keys)
+                    .route(web::post().to(|body: Bytes| HttpResponse::Ok().body(body))),
+            ),
+        )
+        .await;
+        let pay = |key: Option<&str>, body: &'static str| {
+            let mut req = test::TestRequest::post().uri("/pay").set_payload(body);
+            if let Some(key) = key {
+                req = req.header(IDEMPOTENCY_KEY_HEADER, key);
+            }
+            req.to_request()
+        };
+
+        let resp = test::call_service(&mut app, pay(Some("k"), "1234")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(test::read_body(resp).await, Bytes::from_static(b"1234"));
+        let resp = test::call_service(&mut app, pay(Some("k2"), "12345")).await;
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        // without a key, the body is left to the handler
+        let resp = test::call_service(&mut app, pay(None, "12345")).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+    }
+
+    #[actix_rt::test]
+    async fn dropped_requests_abandon_their_key() {
+        let store = Arc::new(InMemoryStore::default());
+        let ttl = Duration::from_secs(10);
+        let response = CachedResponse {
+            status: 200,
+            headers: vec![],
+            body: vec![],
+        };
+        assert_eq!(store.begin("a", "1", ttl).await, Claim::New);
+        drop(Claimed::new(store.clone(), "a".into()));
+        actix_rt::time::delay_for(Duration::from_millis(1)).await;
+        assert_eq!(store.begin("a", "1", ttl).await, Claim::New);
+
+        // but a settled key is kept
+        Claimed::new(store.clone(), "a".into())
+            .settle(Some(response.clone()), ttl)
+            .await;
+        actix_rt::time::delay_for(Duration::from_millis(1)).await;
+        assert_eq!(store.begin("a", "1", ttl).await, Claim::Done(response));
+    }
+}
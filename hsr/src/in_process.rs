@@ -0,0 +1,108 @@
+//! Helpers for the generated `client::InProcess`, which calls an implementation of
+//! the API directly rather than over HTTP.
+//!
+//! The arguments of a call are put through their wire formats (the query string, and
+//! JSON or another representation for the body) as they would be by the client and
+//! the server, so that a value which couldn't be sent fails the same way in tests.
+//! Likewise the response is serialized as by the server and decoded as by the client.
+
+use actix_http::http::Method;
+use actix_web::dev::{Body, ResponseBody};
+use actix_web::error::ErrorBadRequest;
+use actix_web::HttpResponse;
+use bytes::Bytes;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use url::Url;
+
+use crate::negotiate::Representations;
+use crate::{ClientError, RequestContext};
+
+/// The base URL of in-process calls, as seen by `RequestContext::uri`
+pub fn base_url() -> Url {
+    "http://localhost/".parse().unwrap()
+}
+
+/// The context of an in-process call: a request without headers
+pub fn context(method: Method, url: &Url) -> RequestContext {
+    actix_web::test::TestRequest::default()
+        .method(method)
+        .uri(&url[url::Position::BeforePath..])
+        .to_http_request()
+        .into()
+}
+
+/// Put query parameters through a query string, as the client and server do
+pub fn round_trip_query<T: Serialize + DeserializeOwned>(query: &T) -> Result<T, ClientError> {
+    let query = serde_urlencoded::to_string(query).map_err(ErrorBadRequest)?;
+    Ok(serde_urlencoded::from_str(&query).map_err(ErrorBadRequest)?)
+}
+
+/// Put a body through JSON, as the client and server do
+pub fn round_trip_json<T: Serialize + DeserializeOwned>(body: &T) -> Result<T, ClientError> {
+    let json = serde_json::to_vec(body).map_err(ErrorBadRequest)?;
+    Ok(serde_json::from_slice(&json).map_err(ErrorBadRequest)?)
+}
+
+/// Put a body through its representation, as the client and server do
+pub fn round_trip_content<T: Representations>(body: &T) -> Result<T, ClientError> {
+    let bytes = body.to_body().map_err(ErrorBadRequest)?;
+    Ok(T::from_body(body.media_type(), &bytes).map_err(ErrorBadRequest)?)
+}
+
+/// The status, `Content-Type` (or an empty string) and body of a response
+pub fn into_parts(mut resp: HttpResponse) -> (u16, String, Bytes) {
+    let status = resp.status().as_u16();
+    let content_type = crate::negotiate::content_type(resp.headers()).to_string();
+    let body = match resp.take_body() {
+        ResponseBody::Body(Body::Bytes(bytes)) | ResponseBody::Other(Body::Bytes(bytes)) => bytes,
+        _ => Bytes::new(),
+    };
+    (status, content_type, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Query {
+        name: String,
+        limit: Option<i64>,
+    }
+
+    #[test]
+    fn round_trips() {
+        let query = Query {
+            name: "a&b=c".into(),
+            limit: None,
+        };
+        assert_eq!(round_trip_query(&query).unwrap(), query);
+        assert_eq!(round_trip_json(&query).unwrap(), query);
+        // NaN can't be sent as JSON
+        assert!(round_trip_json(&std::f64::NAN).is_err());
+    }
+
+    #[test]
+    fn response_parts() {
+        let resp = HttpResponse::NotFound()
+            .content_type("application/json")
+            .body("[1]");
+        let (status, content_type, body) = into_parts(resp);
+        assert_eq!(status, 404);
+        assert_eq!(content_type, "application/json");
+        assert_eq!(body, Bytes::from_static(b"[1]"));
+        assert_eq!(
+            into_parts(HttpResponse::NoContent().finish()).2,
+            Bytes::new()
+        );
+    }
+
+    #[test]
+    fn request_context() {
+        let url = base_url().join("/pets/1?full=true").unwrap();
+        let ctx = context(Method::DELETE, &url);
+        assert_eq!(*ctx.method(), Method::DELETE);
+        assert_eq!(ctx.uri().to_string(), "/pets/1?full=true");
+    }
+}
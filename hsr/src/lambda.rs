@@ -0,0 +1,232 @@
+//! Run an actix-web app as an AWS Lambda function, behind API Gateway or a
+//! function URL.
+//!
+//! `lambda_http` runs on `tokio` 1.x, while actix runs on its own runtime. So the
+//! app is run on a dedicated thread, and each event is handed over to it and
+//! called in-process (no sockets involved). Events are handled one at a time,
+//! which is how Lambda delivers them anyway.
+
+use actix_http::http::{HeaderName, HeaderValue, Method, StatusCode, Uri};
+use actix_http::Request;
+use actix_service::{IntoServiceFactory, Service, ServiceFactory};
+use actix_web::dev::{AppConfig, MessageBody, ServiceRequest, ServiceResponse};
+use actix_web::{App, Error as ActixError};
+use bytes::{Bytes, BytesMut};
+use futures::channel::{mpsc, oneshot};
+use futures::StreamExt;
+
+pub use lambda_http::Error;
+
+type Headers = Vec<(HeaderName, HeaderValue)>;
+
+/// A lambda event, converted for handing over to the actix thread
+struct Job {
+    method: Method,
+    uri: Uri,
+    headers: Headers,
+    body: Bytes,
+    respond: oneshot::Sender<(StatusCode, Headers, Bytes)>,
+}
+
+impl Job {
+    fn to_request(&self) -> Request {
+        let (_, mut payload) = actix_http::h1::Payload::create(true);
+        payload.unread_data(self.body.clone());
+        let mut req = Request::with_payload(payload.into());
+        let head = req.head_mut();
+        head.method = self.method.clone();
+        head.uri = self.uri.clone();
+        for (name, value) in &self.headers {
+            head.headers.append(name.clone(), value.clone());
+        }
+        req
+    }
+}
+
+/// The response to a request the app failed to answer
+fn internal_error() -> (StatusCode, Headers, Bytes) {
+    (StatusCode::INTERNAL_SERVER_ERROR, Vec::new(), Bytes::new())
+}
+
+/// Call the app with a request, reading the whole response. An error from the app
+/// (or from reading the body of its response) gives a 500.
+async fn call<S, B>(app: &mut S, req: Request) -> (StatusCode, Headers, Bytes)
+where
+    S: Service<Request = Request, Response = ServiceResponse<B>, Error = ActixError>,
+    B: MessageBody,
+{
+    let ready = futures::future::poll_fn(|cx| app.poll_ready(cx)).await;
+    let result = match ready {
+        Ok(()) => app.call(req).await,
+        Err(e) => Err(e),
+    };
+    let mut resp = match result {
+        Ok(resp) => resp,
+        Err(e) => {
+            log::error!("The app failed to answer a lambda event: {}", e);
+            return internal_error();
+        }
+    };
+    let status = resp.status();
+    let headers = resp
+        .headers()
+        .iter()
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+    let mut stream = resp.take_body();
+    let mut body = BytesMut::new();
+    while let Some(chunk) = stream.next().await {
+        match chunk {
+            Ok(chunk) => body.extend_from_slice(&chunk),
+            Err(e) => {
+                log::error!("Failed to read the response to a lambda event: {}", e);
+                return internal_error();
+            }
+        }
+    }
+    (status, headers, body.freeze())
+}
+
+/// Serve lambda events with the app built by `factory`, until the lambda runtime shuts down.
+/// This must be called from within a `tokio` 1.x runtime.
+pub async fn run<F, T, B>(factory: F) -> Result<(), Error>
+where
+    F: FnOnce() -> App<T, B> + Send + 'static,
+    B: MessageBody + 'static,
+    T: ServiceFactory<
+            Config = (),
+            Request = ServiceRequest,
+            Response = ServiceResponse<B>,
+            Error = ActixError,
+            InitError = (),
+        > + 'static,
+{
+    let (tx, mut rx) = mpsc::unbounded::<Job>();
+
+    std::thread::spawn(move || {
+        let mut system = actix_rt::System::new("lambda");
+        system.block_on(async move {
+            let app = factory()
+                .into_factory()
+                .new_service(AppConfig::default())
+                .await;
+            let mut app = match app {
+                Ok(app) => Some(app),
+                Err(()) => {
+                    log::error!("Failed to start the app for lambda events");
+                    None
+                }
+            };
+            while let Some(job) = rx.next().await {
+                let resp = match &mut app {
+                    Some(app) => call(app, job.to_request()).await,
+                    None => internal_error(),
+                };
+                let _ = job.respond.send(resp);
+            }
+        })
+    });
+
+    lambda_http::run(lambda_http::service_fn(
+        move |event: lambda_http::Request| {
+            let tx = tx.clone();
+            async move {
+                let (parts, body) = event.into_parts();
+                let (respond, response) = oneshot::channel();
+                let job = Job {
+                    method: parts.method,
+                    uri: parts.uri,
+                    headers: parts
+                        .headers
+                        .iter()
+                        .map(|(name, value)| (name.clone(), value.clone()))
+                        .collect(),
+                    body: Bytes::from(body.to_vec()),
+                    respond,
+                };
+                tx.unbounded_send(job)
+                    .map_err(|_| Error::from("actix app has shut down"))?;
+                let (status, headers, body) = response.await?;
+                let mut resp = lambda_http::Response::builder().status(status);
+                for (name, value) in headers {
+                    resp = resp.header(name, value);
+                }
+                let resp = resp.body(lambda_http::Body::from(body.to_vec()))?;
+                Ok::<_, Error>(resp)
+            }
+        },
+    ))
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{error, web, HttpResponse};
+
+    fn request(method: Method, uri: &str, body: &str) -> Request {
+        let (respond, _) = oneshot::channel();
+        let job = Job {
+            method,
+            uri: uri.parse().unwrap(),
+            headers: vec![(
+                HeaderName::from_static("content-type"),
+                HeaderValue::from_static("text/plain"),
+            )],
+            body: Bytes::from(body.to_string()),
+            respond,
+        };
+        job.to_request()
+    }
+
+    #[actix_rt::test]
+    async fn events_round_trip() {
+        let app = App::new()
+            .route(
+                "/echo",
+                web::post().to(|req: actix_web::HttpRequest, body: Bytes| async move {
+                    let query = req.query_string().to_string();
+                    HttpResponse::Created().header("x-query", query).body(body)
+                }),
+            )
+            .route(
+                "/fail",
+                web::get().to(|| async { Err::<HttpResponse, _>(error::ErrorBadRequest("no")) }),
+            )
+            .wrap_fn(|req, srv| {
+                let fail = req.path() == "/broken";
+                let resp = srv.call(req);
+                async move {
+                    if fail {
+                        Err(error::ErrorBadRequest("broken"))
+                    } else {
+                        resp.await
+                    }
+                }
+            });
+        let mut app = app
+            .into_factory()
+            .new_service(AppConfig::default())
+            .await
+            .unwrap();
+
+        let echo = request(Method::POST, "/echo?page=2", "hello");
+        let (status, headers, body) = call(&mut app, echo).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert!(headers
+            .iter()
+            .any(|(name, value)| name == "x-query" && value == "page=2"));
+        assert_eq!(body, Bytes::from_static(b"hello"));
+
+        // an error response of a handler is kept
+        let fail = request(Method::GET, "/fail", "");
+        let (status, _, _) = call(&mut app, fail).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        // an error from the app itself gives a 500
+        let broken = request(Method::GET, "/broken", "");
+        let (status, _, body) = call(&mut app, broken).await;
+        assert_eq!(status, StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(body.is_empty());
+    }
+}
@@ -0,0 +1,94 @@
+//! Tolerant parsing of sloppy APIs, which send numbers as strings (`"42"`).
+//!
+//! With `GenerationOptions::lenient_numbers` (or `x-hsr-lenient: true` on the schema
+//! of a number), the generated fields deserialize with these functions, accepting
+//! both `42` and `"42"`. They are always serialized as numbers.
+
+use std::fmt::Display;
+use std::str::FromStr;
+
+use serde::de::{Deserialize, Deserializer, Error};
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum NumberOrString<T> {
+    Number(T),
+    String(String),
+}
+
+impl<T> NumberOrString<T>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    fn into_number<E: Error>(self) -> Result<T, E> {
+        match self {
+            NumberOrString::Number(number) => Ok(number),
+            NumberOrString::String(s) => s
+                .trim()
+                .parse()
+                .map_err(|e| E::custom(format!("bad number '{}': {}", s, e))),
+        }
+    }
+}
+
+/// A number, or a string holding one
+pub fn number<'de, D, T>(de: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + FromStr,
+    T::Err: Display,
+{
+    NumberOrString::deserialize(de)?.into_number()
+}
+
+/// A number, or a string holding one, or `null`
+pub fn optional_number<'de, D, T>(de: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + FromStr,
+    T::Err: Display,
+{
+    match Option::<NumberOrString<T>>::deserialize(de)? {
+        Some(number) => number.into_number().map(Some),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, PartialEq, Deserialize, Serialize)]
+    struct Pet {
+        #[serde(deserialize_with = "number")]
+        age: i64,
+        #[serde(default, deserialize_with = "optional_number")]
+        weight: Option<f64>,
+    }
+
+    #[test]
+    fn lenient_numbers() {
+        let pet: Pet = serde_json::from_str(r#"{"age": "3", "weight": " 4.5"}"#).unwrap();
+        assert_eq!(
+            pet,
+            Pet {
+                age: 3,
+                weight: Some(4.5)
+            }
+        );
+        let pet: Pet = serde_json::from_str(r#"{"age": 3, "weight": null}"#).unwrap();
+        assert_eq!(pet.weight, None);
+        let pet: Pet = serde_json::from_str(r#"{"age": 3}"#).unwrap();
+        assert_eq!(pet.weight, None);
+        // sent as numbers
+        assert_eq!(
+            serde_json::to_string(&pet).unwrap(),
+            r#"{"age":3,"weight":null}"#
+        );
+
+        let err = serde_json::from_str::<Pet>(r#"{"age": "three"}"#).unwrap_err();
+        assert!(err.to_string().contains("bad number 'three'"), "{}", err);
+        assert!(serde_json::from_str::<Pet>(r#"{"age": true}"#).is_err());
+    }
+}
@@ -14,18 +14,88 @@ pub use actix_web;
 pub use async_trait;
 pub use awc;
 pub use futures;
+pub use indexmap;
+pub use serde;
 pub use serde_json;
 pub use serde_urlencoded;
 pub use url;
 
 pub use openssl;
 
+#[cfg(feature = "axum")]
+pub use axum;
+#[cfg(feature = "lambda")]
+pub use lambda_http;
+#[cfg(feature = "opentelemetry")]
+pub use opentelemetry;
+
 pub use url::Url;
 
+pub mod access_log;
+pub mod audit;
+pub mod authz;
+pub mod cache;
+mod call;
+pub mod cassette;
+pub mod circuit;
+pub mod compose;
+pub mod compression;
+mod config;
+mod context;
+pub mod dispatch;
+pub mod download;
+pub mod examples;
+pub mod fake;
+pub mod forwarded;
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
+pub mod https;
+pub mod idempotency;
+pub mod in_process;
+#[cfg(feature = "lambda")]
+pub mod lambda;
+pub mod lenient;
+pub mod listen;
+pub mod loadtest;
+pub mod mock;
+pub mod negotiate;
+pub mod normalize;
+pub mod observe;
+pub mod operation;
+pub mod otel;
+pub mod patch;
+pub mod path;
+mod pool;
+pub mod problem;
+pub mod query;
+pub mod rate_limit;
+pub mod report;
+pub mod request_id;
+pub mod security_headers;
+pub mod spec;
+pub mod tasks;
+pub mod validate;
+pub mod verify;
+pub mod ws;
+
+pub use call::CallOptions;
+#[cfg(feature = "structopt")]
+pub use config::ConfigArgs;
+pub use config::{Config, ConfigBuilder, ConfigError};
+pub use context::RequestContext;
+pub use idempotency::IDEMPOTENCY_KEY_HEADER;
+pub use operation::OperationMeta;
+pub use pool::ConnectionOptions;
+pub use problem::Problem;
+
 // We re-export this type as it is used in all the trait functions
 use actix_http::http::StatusCode;
 use actix_web::{Error as ActixError, HttpResponse};
 
+/// Derive `HasStatusCode` from `#[status(NOT_FOUND)]`-style attributes,
+/// see the `hsr_derive` docs for details
+pub use hsr_derive::HasStatusCode;
+
 /// Associate an http status code with a type. Defaults to 501 Internal Server Error
 pub trait HasStatusCode {
     /// The http status code associated with the type
@@ -43,8 +113,55 @@ pub enum ClientError {
     BadStatus(StatusCode),
     #[error("Actix error: {}", _0)]
     Actix(#[from] ActixError),
+    #[error("Problem: {}", _0)]
+    Problem(Problem),
+    /// The circuit breaker of the client is open, so the call was not made
+    #[error("Circuit open: the upstream is failing")]
+    CircuitOpen,
+    #[error("Cassette error: {}", _0)]
+    Cassette(#[from] cassette::CassetteError),
+    /// A path parameter can't be sent, so the call was not made
+    #[error("Bad path parameter: {}", _0)]
+    Path(#[from] path::DotSegment),
+}
+
+impl ClientError {
+    /// The error for a response with a status code that the spec doesn't describe.
+    /// Problem documents (`application/problem+json`) are parsed into `ClientError::Problem`.
+    pub async fn unexpected<S>(resp: &mut awc::ClientResponse<S>) -> Self
+    where
+        S: futures::Stream<Item = Result<bytes::Bytes, actix_http::error::PayloadError>> + Unpin,
+    {
+        use actix_http::HttpMessage;
+        let is_problem = resp
+            .headers()
+            .get(actix_http::http::header::CONTENT_TYPE)
+            .and_then(|ct| ct.to_str().ok())
+            .map(Problem::is_problem_content_type)
+            .unwrap_or(false);
+        if is_problem {
+            if let Ok(problem) = resp.json::<Problem>().await {
+                return ClientError::Problem(problem);
+            }
+        }
+        ClientError::BadStatus(resp.status())
+    }
+
+    /// As `unexpected`, for a response which has already been read
+    pub fn unexpected_body(status: u16, content_type: &str, body: &[u8]) -> Self {
+        if Problem::is_problem_content_type(content_type) {
+            if let Ok(problem) = serde_json::from_slice::<Problem>(body) {
+                return ClientError::Problem(problem);
+            }
+        }
+        StatusCode::from_u16(status)
+            .map(ClientError::BadStatus)
+            .unwrap_or_else(|e| ClientError::Actix(actix_http::error::ErrorBadGateway(e)))
+    }
 }
 
+/// Serve the spec at `/spec.json`, and the ui at `/ui.html`
+#[cfg(feature = "ui")]
 pub fn configure_spec(
     cfg: &mut actix_web::web::ServiceConfig,
     spec: &'static str,
@@ -62,14 +179,3 @@ pub fn configure_spec(
         actix_web::web::get().to(move || HttpResponse::Ok().set(ContentType::html()).body(ui)),
     );
 }
-
-pub struct Config {
-    pub host: Url,
-    pub ssl: Option<openssl::ssl::SslAcceptorBuilder>,
-}
-
-impl Config {
-    pub fn with_host(host: Url) -> Self {
-        Self { host, ssl: None }
-    }
-}
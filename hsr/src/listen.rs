@@ -0,0 +1,42 @@
+//! Socket activation, for zero-downtime reloads during development.
+//!
+//! With the `listenfd` feature, the generated server listens on the sockets it was
+//! started with by `systemfd` (or systemd), if there are any, rather than binding
+//! its own. Run it with
+//!
+//! ```text
+//! systemfd --no-pid -s http::8000 -- cargo watch -x run
+//! ```
+//!
+//! and `cargo watch` restarts it on every change while `systemfd` holds the socket
+//! open, so no connection is refused in between.
+
+use std::io;
+use std::net::TcpListener;
+
+/// The sockets the process was started with, if any. Always empty without the
+/// `listenfd` feature.
+pub fn inherited() -> io::Result<Vec<TcpListener>> {
+    #[cfg(feature = "listenfd")]
+    {
+        let mut fds = listenfd::ListenFd::from_env();
+        (0..fds.len())
+            .filter_map(|ix| fds.take_tcp_listener(ix).transpose())
+            .collect()
+    }
+    #[cfg(not(feature = "listenfd"))]
+    {
+        Ok(Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nothing_inherited() {
+        // the tests aren't run by systemfd
+        assert!(inherited().unwrap().is_empty());
+    }
+}
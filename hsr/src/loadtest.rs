@@ -0,0 +1,301 @@
+//! Load tests: performance smoke tests of the operations of an API.
+//!
+//! With `GenerationOptions::load_test`, a `loadtest` module is generated, whose
+//! `run` calls each operation (that the client can make) at a steady rate, with
+//! random inputs made by `hsr::fake`, against a running server. The `Report` has
+//! the latency percentiles and the statuses of each operation.
+//!
+//! ```ignore
+//! #[derive(structopt::StructOpt)]
+//! struct Args {
+//!     #[structopt(flatten)]
+//!     load: hsr::loadtest::LoadArgs,
+//! }
+//!
+//! #[actix_rt::main]
+//! async fn main() {
+//!     let Args { load } = Args::from_args();
+//!     let report = api::loadtest::run(load.url.clone(), load.options()).await;
+//!     println!("{}", report);
+//! }
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use futures::future::{self, Either};
+use futures::stream::{FuturesUnordered, StreamExt};
+
+use crate::fake::Rng;
+use crate::HasStatusCode;
+
+/// How hard, and for how long, to load the server
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoadOptions {
+    /// Calls a second of each operation
+    pub rps: u32,
+    /// How long to call each operation for
+    pub duration: Duration,
+    /// Seed of the random inputs, to make the same calls every time
+    pub seed: Option<u64>,
+    /// The operations (by operation id) to call. All of them, if empty.
+    pub operations: Vec<String>,
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        Self {
+            rps: 10,
+            duration: Duration::from_secs(10),
+            seed: None,
+            operations: Vec::new(),
+        }
+    }
+}
+
+impl LoadOptions {
+    /// Whether to call the operation
+    pub fn includes(&self, operation_id: &str) -> bool {
+        self.operations.is_empty() || self.operations.iter().any(|op| op == operation_id)
+    }
+
+    /// The generator of the inputs of the `ix`th operation
+    pub fn rng(&self, ix: u64) -> Rng {
+        match self.seed {
+            Some(seed) => Rng::seeded(seed.wrapping_add(ix)),
+            None => Rng::new(),
+        }
+    }
+}
+
+/// Command-line arguments for a load test, to be `#[structopt(flatten)]`ed into an
+/// application's own arguments
+#[cfg(feature = "structopt")]
+#[derive(Debug, Clone, structopt::StructOpt)]
+pub struct LoadArgs {
+    /// URL of the server to load
+    #[structopt(long, default_value = "http://127.0.0.1:8000")]
+    pub url: crate::Url,
+    /// Calls a second of each operation
+    #[structopt(long, default_value = "10")]
+    pub rps: u32,
+    /// Seconds to call each operation for
+    #[structopt(long, default_value = "10")]
+    pub duration: u64,
+    /// Seed of the random inputs, to make the same calls every time
+    #[structopt(long)]
+    pub seed: Option<u64>,
+    /// Operations to call (all of them by default)
+    #[structopt(long = "operation", use_delimiter = true)]
+    pub operations: Vec<String>,
+}
+
+#[cfg(feature = "structopt")]
+impl LoadArgs {
+    pub fn options(&self) -> LoadOptions {
+        LoadOptions {
+            rps: self.rps,
+            duration: Duration::from_secs(self.duration),
+            seed: self.seed,
+            operations: self.operations.clone(),
+        }
+    }
+}
+
+/// What came of the calls of an operation
+#[derive(Debug, Clone, Default)]
+pub struct OperationStats {
+    pub operation_id: String,
+    /// How long the calls which got a response took, from the fastest
+    pub latencies: Vec<Duration>,
+    /// How many responses had each status
+    pub statuses: BTreeMap<u16, usize>,
+    /// Calls which got no (sensible) response
+    pub errors: usize,
+}
+
+impl OperationStats {
+    pub fn new(operation_id: &str) -> Self {
+        Self {
+            operation_id: operation_id.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn calls(&self) -> usize {
+        self.latencies.len() + self.errors
+    }
+
+    /// The latency which `p` percent of the responses were as quick as
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        if self.latencies.is_empty() {
+            return None;
+        }
+        let rank = (p / 100.0 * self.latencies.len() as f64).ceil() as usize;
+        let ix = rank.max(1).min(self.latencies.len()) - 1;
+        Some(self.latencies[ix])
+    }
+
+    fn record<E: fmt::Debug>(&mut self, latency: Duration, status: Result<u16, E>) {
+        match status {
+            Ok(status) => {
+                let ix = self.latencies.partition_point(|l| *l <= latency);
+                self.latencies.insert(ix, latency);
+                *self.statuses.entry(status).or_default() += 1;
+            }
+            Err(e) => {
+                log::debug!("Call of {} failed: {:?}", self.operation_id, e);
+                self.errors += 1;
+            }
+        }
+    }
+}
+
+fn millis(latency: Option<Duration>) -> String {
+    match latency {
+        Some(latency) => format!("{:.1}ms", latency.as_secs_f64() * 1000.0),
+        None => "-".into(),
+    }
+}
+
+impl fmt::Display for OperationStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let statuses: Vec<String> = self
+            .statuses
+            .iter()
+            .map(|(status, count)| format!("{}x{}", count, status))
+            .collect();
+        write!(
+            f,
+            "{:<30} {:>6} {:>6} {:>9} {:>9} {:>9} {:>9}  {}",
+            self.operation_id,
+            self.calls(),
+            self.errors,
+            millis(self.percentile(50.0)),
+            millis(self.percentile(90.0)),
+            millis(self.percentile(99.0)),
+            millis(self.latencies.last().copied()),
+            statuses.join(" ")
+        )
+    }
+}
+
+/// The outcome of a load test
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+    pub operations: Vec<OperationStats>,
+}
+
+impl Report {
+    pub fn new(operations: Vec<OperationStats>) -> Self {
+        Self { operations }
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<30} {:>6} {:>6} {:>9} {:>9} {:>9} {:>9}  statuses",
+            "operation", "calls", "errors", "p50", "p90", "p99", "max"
+        )?;
+        for stats in &self.operations {
+            writeln!(f, "{}", stats)?;
+        }
+        Ok(())
+    }
+}
+
+/// Make calls of an operation, `opts.rps` a second for `opts.duration`, without
+/// waiting for the previous ones to finish. `call` makes a call with random
+/// inputs from `rng`. Used by the generated load test.
+pub async fn drive<F, Fut, R, E>(
+    operation_id: &str,
+    opts: &LoadOptions,
+    mut rng: Rng,
+    mut call: F,
+) -> OperationStats
+where
+    F: FnMut(&mut Rng) -> Fut,
+    Fut: Future<Output = Result<R, E>>,
+    R: HasStatusCode,
+    E: fmt::Debug,
+{
+    let mut stats = OperationStats::new(operation_id);
+    let period = Duration::from_secs_f64(1.0 / f64::from(opts.rps.max(1)));
+    let deadline = Instant::now() + opts.duration;
+    let mut ticks = actix_rt::time::interval(period);
+    let mut in_flight = FuturesUnordered::new();
+    while Instant::now() < deadline {
+        // record the calls which finish before it is time for the next one
+        let tick = ticks.tick();
+        futures::pin_mut!(tick);
+        loop {
+            if in_flight.is_empty() {
+                tick.as_mut().await;
+                break;
+            }
+            match future::select(tick.as_mut(), in_flight.next()).await {
+                Either::Left(_) => break,
+                Either::Right((Some((latency, status)), _)) => stats.record(latency, status),
+                Either::Right((None, _)) => {}
+            }
+        }
+        let started = Instant::now();
+        let response = call(&mut rng);
+        in_flight.push(async move {
+            let status = response.await.map(|resp| resp.status_code().as_u16());
+            (started.elapsed(), status)
+        });
+    }
+    while let Some((latency, status)) = in_flight.next().await {
+        stats.record(latency, status);
+    }
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_http::http::StatusCode;
+
+    struct Created;
+
+    impl HasStatusCode for Created {
+        fn status_code(&self) -> StatusCode {
+            StatusCode::CREATED
+        }
+    }
+
+    #[actix_rt::test]
+    async fn drive_load() {
+        let opts = LoadOptions {
+            rps: 100,
+            duration: Duration::from_millis(200),
+            seed: Some(1),
+            operations: vec!["addPet".into()],
+        };
+        assert!(opts.includes("addPet") && !opts.includes("deletePet"));
+        let stats = drive("addPet", &opts, opts.rng(0), |rng| {
+            let fail = rng.below(4) == 0;
+            async move {
+                actix_rt::time::delay_for(Duration::from_millis(5)).await;
+                if fail {
+                    Err("refused")
+                } else {
+                    Ok(Created)
+                }
+            }
+        })
+        .await;
+        assert!(stats.calls() > 5, "{:?}", stats);
+        assert!(stats.errors > 0, "{:?}", stats);
+        assert_eq!(stats.statuses[&201], stats.latencies.len());
+        let p50 = stats.percentile(50.0).unwrap();
+        assert!(p50 >= Duration::from_millis(5));
+        assert!(p50 <= stats.percentile(99.0).unwrap());
+        assert!(Report::new(vec![stats]).to_string().contains("addPet"));
+    }
+}
@@ -0,0 +1,296 @@
+//! A mock server, which answers every operation in a spec with canned example
+//! responses, so that clients can be developed before the real server exists.
+//!
+//! Responses use the `example`/`examples` declared in the spec. Where there are none,
+//! an example is synthesized from the response schema.
+
+use actix_http::http::{Method, StatusCode};
+use actix_web::{web, App, HttpResponse, HttpServer};
+use serde_json::{json, Value};
+
+use crate::Config;
+
+/// Schemas nested deeper than this are not synthesized (they are likely recursive)
+const MAX_DEPTH: usize = 8;
+
+static NULL: Value = Value::Null;
+
+const METHODS: &[&str] = &[
+    "get", "put", "post", "delete", "options", "head", "patch", "trace",
+];
+
+/// A canned response for an operation
+#[derive(Debug, Clone, PartialEq)]
+pub struct MockResponse {
+    pub status: StatusCode,
+    pub body: Option<Value>,
+}
+
+impl MockResponse {
+    fn to_response(&self) -> HttpResponse {
+        match &self.body {
+            Some(body) => HttpResponse::build(self.status).json(body),
+            None => HttpResponse::build(self.status).finish(),
+        }
+    }
+}
+
+/// A canned response for each operation of a path
+#[derive(Debug, Clone)]
+pub struct MockRoute {
+    pub path: String,
+    pub operations: Vec<(Method, MockResponse)>,
+}
+
+/// Follow a local `$ref` (if any) to the node it points at
+pub fn resolve<'a>(node: &'a Value, spec: &'a Value) -> &'a Value {
+    let mut node = node;
+    // bound the number of hops in case of reference cycles
+    for _ in 0..MAX_DEPTH {
+        match node.get("$ref").and_then(Value::as_str) {
+            Some(reference) => match spec.pointer(reference.trim_start_matches('#')) {
+                Some(target) => node = target,
+                None => return &NULL,
+            },
+            None => return node,
+        }
+    }
+    node
+}
+
+/// Build an example value which conforms to `schema`
+pub fn example_for_schema(schema: &Value, spec: &Value) -> Value {
+    example_for_schema_at_depth(schema, spec, 0)
+}
+
+fn example_for_schema_at_depth(schema: &Value, spec: &Value, depth: usize) -> Value {
+    if depth > MAX_DEPTH {
+        return Value::Null;
+    }
+    let schema = resolve(schema, spec);
+    if let Some(example) = schema.get("example") {
+        return example.clone();
+    }
+    if let Some(default) = schema.get("default") {
+        return default.clone();
+    }
+    if let Some(first) = schema
+        .get("enum")
+        .and_then(Value::as_array)
+        .and_then(|e| e.first())
+    {
+        return first.clone();
+    }
+    for key in &["oneOf", "anyOf"] {
+        if let Some(first) = schema
+            .get(*key)
+            .and_then(Value::as_array)
+            .and_then(|e| e.first())
+        {
+            return example_for_schema_at_depth(first, spec, depth + 1);
+        }
+    }
+    if let Some(parts) = schema.get("allOf").and_then(Value::as_array) {
+        let mut combined = serde_json::Map::new();
+        for part in parts {
+            if let Value::Object(obj) = example_for_schema_at_depth(part, spec, depth + 1) {
+                combined.extend(obj)
+            }
+        }
+        return Value::Object(combined);
+    }
+    match schema.get("type").and_then(Value::as_str) {
+        Some("string") => json!("string"),
+        Some("integer") => json!(0),
+        Some("number") => json!(0.0),
+        Some("boolean") => json!(false),
+        Some("array") => match schema.get("items") {
+            Some(items) => json!([example_for_schema_at_depth(items, spec, depth + 1)]),
+            None => json!([]),
+        },
+        Some("object") | None => match schema.get("properties").and_then(Value::as_object) {
+            Some(props) => Value::Object(
+                props
+                    .iter()
+                    .map(|(name, prop)| {
+                        (
+                            name.clone(),
+                            example_for_schema_at_depth(prop, spec, depth + 1),
+                        )
+                    })
+                    .collect(),
+            ),
+            None => json!({}),
+        },
+        Some(_) => Value::Null,
+    }
+}
+
+/// Pick an example out of a 'Media Type' object
+pub fn example_for_media_type(media: &Value, spec: &Value) -> Value {
+    if let Some(example) = media.get("example") {
+        return example.clone();
+    }
+    if let Some(example) = media
+        .get("examples")
+        .and_then(Value::as_object)
+        .and_then(|examples| examples.values().next())
+        .and_then(|example| resolve(example, spec).get("value"))
+    {
+        return example.clone();
+    }
+    match media.get("schema") {
+        Some(schema) => example_for_schema(schema, spec),
+        None => Value::Null,
+    }
+}
+
+/// Choose the response we will mock for an operation.
+/// We prefer the lowest 2XX code, then the default, then whatever comes first.
+fn choose_response(responses: &serde_json::Map<String, Value>) -> Option<(StatusCode, &Value)> {
+    let mut codes: Vec<(StatusCode, &Value)> = responses
+        .iter()
+        .filter_map(|(code, resp)| {
+            let code = code.parse::<u16>().ok()?;
+            StatusCode::from_u16(code).ok().map(|code| (code, resp))
+        })
+        .collect();
+    codes.sort_by_key(|(code, _)| code.as_u16());
+    codes
+        .iter()
+        .find(|(code, _)| code.is_success())
+        .cloned()
+        .or_else(|| responses.get("default").map(|resp| (StatusCode::OK, resp)))
+        .or_else(|| codes.first().cloned())
+}
+
+/// Build the canned response for an operation
+pub fn mock_response(operation: &Value, spec: &Value) -> MockResponse {
+    let (status, response) = match operation
+        .get("responses")
+        .and_then(Value::as_object)
+        .and_then(choose_response)
+    {
+        Some(found) => found,
+        None => {
+            return MockResponse {
+                status: StatusCode::NOT_IMPLEMENTED,
+                body: None,
+            }
+        }
+    };
+    let body = resolve(response, spec)
+        .get("content")
+        .and_then(|content| content.get("application/json"))
+        .map(|media| example_for_media_type(media, spec));
+    MockResponse { status, body }
+}
+
+/// Work out the canned responses for every operation in the spec
+pub fn mock_routes(spec: &Value) -> Vec<MockRoute> {
+    let paths = match spec.get("paths").and_then(Value::as_object) {
+        Some(paths) => paths,
+        None => return Vec::new(),
+    };
+    paths
+        .iter()
+        .map(|(path, item)| {
+            let item = resolve(item, spec);
+            let operations = METHODS
+                .iter()
+                .filter_map(|method| {
+                    let op = item.get(*method)?;
+                    let method = Method::from_bytes(method.to_uppercase().as_bytes()).ok()?;
+                    Some((method, mock_response(op, spec)))
+                })
+                .collect();
+            MockRoute {
+                path: path.clone(),
+                operations,
+            }
+        })
+        .collect()
+}
+
+/// Register a route for every mocked operation
+pub fn configure_mock(cfg: &mut web::ServiceConfig, routes: &[MockRoute]) {
+    for route in routes {
+        let mut resource = web::resource(route.path.as_str());
+        for (method, mock) in &route.operations {
+            let mock = mock.clone();
+            resource = resource.route(web::method(method.clone()).to(move || mock.to_response()));
+        }
+        cfg.service(resource);
+    }
+}
+
+/// Serve mocked responses for every operation in the spec.
+/// Once started, the server blocks indefinitely.
+pub async fn serve(spec: Value, cfg: Config) -> std::io::Result<()> {
+    let routes = mock_routes(&spec);
+    let server = HttpServer::new(move || {
+        App::new()
+            .wrap(actix_web::middleware::Logger::default())
+            .configure(|cfg| configure_mock(cfg, &routes))
+    });
+    let addrs = cfg.bind_addrs()?;
+    let server = if let Some(ssl) = cfg.ssl {
+        server.bind_openssl(&addrs[..], ssl)
+    } else {
+        server.bind(&addrs[..])
+    }?;
+    server.run().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_example_for_schema() {
+        let spec = json!({
+            "components": {
+                "schemas": {
+                    "Pet": {
+                        "properties": {
+                            "id": { "type": "integer" },
+                            "name": { "type": "string", "example": "Rex" },
+                            "kind": { "type": "string", "enum": ["dog", "cat"] }
+                        }
+                    }
+                }
+            }
+        });
+        let schema = json!({
+            "type": "array",
+            "items": { "$ref": "#/components/schemas/Pet" }
+        });
+        assert_eq!(
+            example_for_schema(&schema, &spec),
+            json!([{ "id": 0, "name": "Rex", "kind": "dog" }])
+        );
+    }
+
+    #[test]
+    fn test_mock_response() {
+        let spec = json!({});
+        let op = json!({
+            "responses": {
+                "404": { "description": "Not found" },
+                "201": {
+                    "description": "Created",
+                    "content": {
+                        "application/json": { "example": { "id": 3 } }
+                    }
+                }
+            }
+        });
+        assert_eq!(
+            mock_response(&op, &spec),
+            MockResponse {
+                status: StatusCode::CREATED,
+                body: Some(json!({ "id": 3 }))
+            }
+        );
+    }
+}
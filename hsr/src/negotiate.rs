@@ -0,0 +1,224 @@
+//! Content negotiation, for bodies with more than one media type.
+//!
+//! A body which the spec gives several media types (or a single one which isn't
+//! JSON) is generated as an enum over its representations, implementing
+//! `Representations`. The generated server picks the representation of a request
+//! body from its `Content-Type`, and negotiates the representation of the response
+//! with the `Accept` header, answering `406 Not Acceptable` if there is none.
+
+use actix_http::http::header::{ACCEPT, CONTENT_TYPE};
+use actix_http::http::HeaderMap;
+use actix_http::Payload;
+use actix_web::error::{ErrorBadRequest, ErrorNotAcceptable, ErrorUnsupportedMediaType};
+use actix_web::{Error as ActixError, FromRequest, HttpRequest};
+use bytes::Bytes;
+use futures::future::{ok, FutureExt, LocalBoxFuture, Ready};
+
+/// A body with a representation for each of its media types
+pub trait Representations: Sized {
+    /// The media types, in the order of the spec
+    const MEDIA_TYPES: &'static [&'static str];
+
+    /// The media type of this representation
+    fn media_type(&self) -> &'static str;
+
+    /// Serialize the body
+    fn to_body(&self) -> serde_json::Result<Vec<u8>>;
+
+    /// Deserialize a body with the given `Content-Type`
+    fn from_body(content_type: &str, body: &[u8]) -> Result<Self, BodyError>;
+}
+
+/// Why a body couldn't be deserialized
+#[derive(Debug, thiserror::Error)]
+pub enum BodyError {
+    #[error("Unsupported media type '{0}'")]
+    UnsupportedMediaType(String),
+    #[error("Invalid JSON: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Invalid text: {0}")]
+    Text(#[from] std::string::FromUtf8Error),
+}
+
+impl From<BodyError> for ActixError {
+    fn from(e: BodyError) -> Self {
+        match e {
+            BodyError::UnsupportedMediaType(_) => ErrorUnsupportedMediaType(e),
+            _ => ErrorBadRequest(e),
+        }
+    }
+}
+
+/// Whether a `Content-Type` is of a media type of the spec (which may be a range, like `text/*`)
+pub fn is_media_type(content_type: &str, media_type: &str) -> bool {
+    let content_type = essence(content_type);
+    match media_type {
+        "*/*" => !content_type.is_empty(),
+        range if range.ends_with("/*") => {
+            let main = &range[..range.len() - 1];
+            content_type.len() > main.len() && content_type[..main.len()].eq_ignore_ascii_case(main)
+        }
+        media_type => content_type.eq_ignore_ascii_case(media_type),
+    }
+}
+
+/// The `Content-Type` of a message, or an empty string
+pub fn content_type(headers: &HeaderMap) -> &str {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|ct| ct.to_str().ok())
+        .unwrap_or_default()
+}
+
+/// The media type, without parameters
+fn essence(content_type: &str) -> &str {
+    content_type.split(';').next().unwrap_or_default().trim()
+}
+
+/// The `Accept` header of a request
+#[derive(Debug, Clone, PartialEq)]
+pub struct Accept {
+    /// The media ranges, with their quality
+    ranges: Vec<(String, f32)>,
+}
+
+impl Accept {
+    /// Parse an `Accept` header. Without one, anything is acceptable.
+    pub fn new(header: Option<&str>) -> Self {
+        let ranges = header
+            .unwrap_or("*/*")
+            .split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                let media_range = parts.next()?.trim().to_ascii_lowercase();
+                if media_range.is_empty() {
+                    return None;
+                }
+                let quality = parts
+                    .map(str::trim)
+                    .filter(|param| param.starts_with("q="))
+                    .find_map(|param| param[2..].parse().ok())
+                    .unwrap_or(1.0);
+                Some((media_range, quality))
+            })
+            .collect();
+        Self { ranges }
+    }
+
+    /// The quality of a media type, given by the most specific range it is in
+    fn quality(&self, media_type: &str) -> f32 {
+        let media_type = media_type.to_ascii_lowercase();
+        let main = media_type.split('/').next().unwrap_or_default();
+        self.ranges
+            .iter()
+            .filter_map(|(range, quality)| {
+                let specificity = if *range == media_type {
+                    2
+                } else if range.ends_with("/*") && range[..range.len() - 2] == *main {
+                    1
+                } else if range == "*/*" {
+                    0
+                } else {
+                    return None;
+                };
+                Some((specificity, *quality))
+            })
+            .max_by_key(|(specificity, _)| *specificity)
+            .map(|(_, quality)| quality)
+            .unwrap_or(0.0)
+    }
+
+    /// The most acceptable of the offered media types (the first, of those equally
+    /// acceptable), if any are acceptable at all
+    pub fn negotiate(&self, offered: &[&'static str]) -> Option<&'static str> {
+        let mut best: Option<(&'static str, f32)> = None;
+        for &media_type in offered {
+            let quality = self.quality(media_type);
+            if quality > 0.0 && best.map(|(_, best)| quality > best).unwrap_or(true) {
+                best = Some((media_type, quality));
+            }
+        }
+        best.map(|(media_type, _)| media_type)
+    }
+}
+
+impl FromRequest for Accept {
+    type Error = ActixError;
+    type Future = Ready<Result<Self, ActixError>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        let header = req.headers().get(ACCEPT).and_then(|h| h.to_str().ok());
+        ok(Accept::new(header))
+    }
+}
+
+/// The error for a request accepting none of the offered media types
+pub fn not_acceptable(offered: &[&str]) -> ActixError {
+    ErrorNotAcceptable(format!(
+        "Acceptable media types are: {}",
+        offered.join(", ")
+    ))
+}
+
+/// Extract a request body with more than one representation,
+/// choosing the representation by the `Content-Type` of the request
+#[derive(Debug, Clone, PartialEq)]
+pub struct Negotiated<T>(pub T);
+
+impl<T: Representations + 'static> FromRequest for Negotiated<T> {
+    type Error = ActixError;
+    type Future = LocalBoxFuture<'static, Result<Self, ActixError>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        let content_type = content_type(req.headers()).to_string();
+        let body = Bytes::from_request(req, payload);
+        async move {
+            let body = body.await?;
+            let body = T::from_body(&content_type, &body)?;
+            Ok(Negotiated(body))
+        }
+        .boxed_local()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OFFERED: &[&str] = &["application/json", "text/csv"];
+
+    #[test]
+    fn negotiation() {
+        let negotiate = |accept: Option<&str>| Accept::new(accept).negotiate(OFFERED);
+        assert_eq!(negotiate(None), Some("application/json"));
+        assert_eq!(negotiate(Some("*/*")), Some("application/json"));
+        assert_eq!(negotiate(Some("text/csv")), Some("text/csv"));
+        assert_eq!(negotiate(Some("text/*")), Some("text/csv"));
+        assert_eq!(
+            negotiate(Some("application/json;q=0.5, text/csv")),
+            Some("text/csv")
+        );
+        assert_eq!(
+            negotiate(Some("text/*;q=0.1, application/*;q=0.2")),
+            Some("application/json")
+        );
+        assert_eq!(
+            negotiate(Some("*/*, text/csv;q=0")),
+            Some("application/json")
+        );
+        assert_eq!(negotiate(Some("image/png")), None);
+    }
+
+    #[test]
+    fn media_types() {
+        assert!(is_media_type("application/json", "application/json"));
+        assert!(is_media_type("Text/CSV; charset=utf-8", "text/csv"));
+        assert!(is_media_type("text/plain", "text/*"));
+        assert!(is_media_type("image/png", "*/*"));
+        assert!(!is_media_type("text/plain", "text/csv"));
+        assert!(!is_media_type("", "*/*"));
+        assert!(!is_media_type("textual/plain", "text/*"));
+    }
+}
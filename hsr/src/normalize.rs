@@ -0,0 +1,190 @@
+//! What to do with requests whose path differs from one of the spec only by a
+//! trailing slash (`/pets/` for `/pets`, or the other way round).
+//!
+//! With `Config::trailing_slash` other than `Strict`, the generated server merges
+//! repeated slashes with `actix_web`'s `NormalizePath`, and the `TrailingSlashes`
+//! middleware then redirects such requests to the path of the spec, or serves them
+//! as if they had been made to it. The paths of the spec keep their trailing
+//! slashes, so `/pets/` may be an operation of its own.
+
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use actix_http::http::header::LOCATION;
+use actix_http::http::uri::{PathAndQuery, Uri};
+use actix_service::{Service, Transform};
+use actix_web::dev::{ResourceDef, ServiceRequest, ServiceResponse};
+use actix_web::{Error as ActixError, HttpResponse};
+use futures::future::{ok, Either, Ready};
+
+/// The trailing slash policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrailingSlash {
+    /// Only the paths of the spec are served (the default)
+    Strict,
+    /// Redirect (`308 Permanent Redirect`) to the path of the spec
+    Redirect,
+    /// Serve the request as if it had been made to the path of the spec
+    Merge,
+}
+
+impl Default for TrailingSlash {
+    fn default() -> Self {
+        TrailingSlash::Strict
+    }
+}
+
+impl std::str::FromStr for TrailingSlash {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "strict" => Ok(TrailingSlash::Strict),
+            "redirect" => Ok(TrailingSlash::Redirect),
+            "merge" => Ok(TrailingSlash::Merge),
+            other => Err(format!(
+                "unknown trailing slash policy '{}' (expected strict, redirect or merge)",
+                other
+            )),
+        }
+    }
+}
+
+/// The path of the spec (out of `paths`) which `path` is, but for a trailing slash,
+/// if `path` isn't one already
+fn spec_path(path: &str, paths: &[ResourceDef]) -> Option<String> {
+    let is_spec_path = |path: &str| paths.iter().any(|def| def.is_match(path));
+    if is_spec_path(path) {
+        return None;
+    }
+    let other = match path.strip_suffix('/') {
+        Some("") => return None,
+        Some(trimmed) => trimmed.to_string(),
+        None => format!("{}/", path),
+    };
+    Some(other).filter(|other| is_spec_path(other))
+}
+
+/// Middleware applying the trailing slash policy to requests for the (templated)
+/// paths of the spec. Installed by the generated server.
+#[derive(Clone)]
+pub struct TrailingSlashes {
+    policy: TrailingSlash,
+    paths: Rc<Vec<ResourceDef>>,
+}
+
+impl TrailingSlashes {
+    pub fn new(policy: TrailingSlash, paths: &[&str]) -> Self {
+        Self {
+            policy,
+            paths: Rc::new(paths.iter().map(|path| ResourceDef::new(path)).collect()),
+        }
+    }
+}
+
+impl<S> Transform<S> for TrailingSlashes
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = ActixError>,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = ActixError;
+    type InitError = ();
+    type Transform = TrailingSlashesMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(TrailingSlashesMiddleware {
+            service,
+            policy: self.policy,
+            paths: self.paths.clone(),
+        })
+    }
+}
+
+pub struct TrailingSlashesMiddleware<S> {
+    service: S,
+    policy: TrailingSlash,
+    paths: Rc<Vec<ResourceDef>>,
+}
+
+impl<S> Service for TrailingSlashesMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = ActixError>,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = ActixError;
+    type Future = Either<S::Future, Ready<Result<Self::Response, Self::Error>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: ServiceRequest) -> Self::Future {
+        let path = match self.policy {
+            TrailingSlash::Strict => None,
+            _ => spec_path(req.path(), &self.paths),
+        };
+        let path = match path {
+            Some(path) => path,
+            None => return Either::Left(self.service.call(req)),
+        };
+        let path_and_query = match req.query_string() {
+            "" => path,
+            query => format!("{}?{}", path, query),
+        };
+        if self.policy == TrailingSlash::Redirect {
+            let resp = HttpResponse::PermanentRedirect()
+                .header(LOCATION, path_and_query)
+                .finish();
+            return Either::Right(ok(req.into_response(resp)));
+        }
+        let mut parts = req.head().uri.clone().into_parts();
+        parts.path_and_query = path_and_query.parse::<PathAndQuery>().ok();
+        if let Ok(uri) = Uri::from_parts(parts) {
+            req.match_info_mut().get_mut().update(&uri);
+            req.head_mut().uri = uri;
+        }
+        Either::Left(self.service.call(req))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App};
+
+    #[actix_rt::test]
+    async fn trailing_slashes() {
+        let paths = ["/pets", "/pets/{id}/toys/"];
+        for (policy, status) in &[
+            (TrailingSlash::Strict, 404),
+            (TrailingSlash::Redirect, 308),
+            (TrailingSlash::Merge, 200),
+        ] {
+            let mut app = test::init_service(
+                App::new()
+                    .route("/pets", web::get().to(HttpResponse::Ok))
+                    .route("/pets/{id}/toys/", web::get().to(HttpResponse::Ok))
+                    .wrap(TrailingSlashes::new(*policy, &paths)),
+            )
+            .await;
+            let req = test::TestRequest::get().uri("/pets/?limit=2").to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status().as_u16(), *status, "{:?}", policy);
+            if *policy == TrailingSlash::Redirect {
+                assert_eq!(resp.headers().get(LOCATION).unwrap(), "/pets?limit=2");
+            }
+            let req = test::TestRequest::get().uri("/pets/1/toys").to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status().as_u16(), *status, "{:?}", policy);
+
+            // the paths of the spec are served as they are
+            let req = test::TestRequest::get().uri("/pets").to_request();
+            let resp = test::call_service(&mut app, req).await;
+            assert_eq!(resp.status().as_u16(), 200);
+        }
+        assert_eq!("Merge".parse(), Ok(TrailingSlash::Merge));
+        assert!("sometimes".parse::<TrailingSlash>().is_err());
+    }
+}
@@ -0,0 +1,232 @@
+//! Hooks for watching the calls of the generated client, to collect metrics (latency
+//! and status per operation) or record traces without wrapping every method.
+//!
+//! Give a client a `ClientObserver` with `Client::with_observer`. It is told about
+//! each call sent over the network; calls answered from the cache or a cassette, or
+//! refused by the circuit breaker, are not observed.
+//!
+//! `LogObserver` logs each call. With the `opentelemetry` feature, `OtelObserver`
+//! records a client span for each call, as a child of the current context.
+
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+use actix_http::http::Method;
+use awc::error::SendRequestError;
+use url::Url;
+
+/// A call made by the client
+#[derive(Debug, Clone)]
+pub struct ClientCall {
+    pub operation_id: &'static str,
+    pub method: Method,
+    /// The host (and port, if not the default) called
+    pub host: String,
+    /// When the request was sent
+    pub started: SystemTime,
+}
+
+/// How a call ended
+#[derive(Debug, Clone)]
+pub struct CallOutcome {
+    /// The status of the response, unless the call failed
+    pub status: Option<u16>,
+    /// Why the call failed, if it did
+    pub error: Option<String>,
+    /// How long the response took to arrive (not counting reading its body)
+    pub elapsed: Duration,
+}
+
+impl CallOutcome {
+    /// Whether the call failed, or the server gave a `5xx` status
+    pub fn is_failure(&self) -> bool {
+        self.status.map(|status| status >= 500).unwrap_or(true)
+    }
+}
+
+/// Watches the calls of a client
+pub trait ClientObserver: Send + Sync {
+    /// A call is about to be sent
+    fn on_start(&self, _call: &ClientCall) {}
+
+    /// A call has had its response (or failed)
+    fn on_finish(&self, call: &ClientCall, outcome: &CallOutcome);
+}
+
+/// An observation of a call in progress. Used by the generated client.
+pub struct Observation {
+    observer: Arc<dyn ClientObserver>,
+    call: ClientCall,
+    started: Instant,
+}
+
+impl Observation {
+    /// Start observing a call, if there is an observer
+    pub fn start(
+        observer: &Option<Arc<dyn ClientObserver>>,
+        operation_id: &'static str,
+        method: Method,
+        url: &Url,
+    ) -> Option<Self> {
+        let observer = observer.as_ref()?.clone();
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{}:{}", host, port),
+            (host, None) => host.unwrap_or_default().to_string(),
+            (None, Some(_)) => String::new(),
+        };
+        let call = ClientCall {
+            operation_id,
+            method,
+            host,
+            started: SystemTime::now(),
+        };
+        observer.on_start(&call);
+        Some(Self {
+            observer,
+            call,
+            started: Instant::now(),
+        })
+    }
+
+    /// Finish observing a call, once it has had its response (or failed)
+    pub fn finish<S>(self, result: &Result<awc::ClientResponse<S>, SendRequestError>) {
+        let outcome = match result {
+            Ok(resp) => CallOutcome {
+                status: Some(resp.status().as_u16()),
+                error: None,
+                elapsed: self.started.elapsed(),
+            },
+            Err(e) => CallOutcome {
+                status: None,
+                error: Some(e.to_string()),
+                elapsed: self.started.elapsed(),
+            },
+        };
+        self.observer.on_finish(&self.call, &outcome)
+    }
+}
+
+/// Logs each call: at `info` level, or `warn` if it failed
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LogObserver;
+
+impl ClientObserver for LogObserver {
+    fn on_finish(&self, call: &ClientCall, outcome: &CallOutcome) {
+        let level = if outcome.is_failure() {
+            log::Level::Warn
+        } else {
+            log::Level::Info
+        };
+        let result = match (&outcome.status, &outcome.error) {
+            (Some(status), _) => status.to_string(),
+            (None, Some(error)) => error.clone(),
+            (None, None) => "failed".into(),
+        };
+        log::log!(
+            level,
+            "{} ({} {}): {} in {:?}",
+            call.operation_id,
+            call.method,
+            call.host,
+            result,
+            outcome.elapsed
+        );
+    }
+}
+
+/// Records a client span for each call, with the `opentelemetry` feature
+#[cfg(feature = "opentelemetry")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OtelObserver;
+
+#[cfg(feature = "opentelemetry")]
+impl ClientObserver for OtelObserver {
+    fn on_finish(&self, call: &ClientCall, outcome: &CallOutcome) {
+        use opentelemetry::trace::{Span, SpanKind, StatusCode, Tracer};
+        use opentelemetry::{global, KeyValue};
+
+        let tracer = global::tracer("hsr");
+        let mut attributes = vec![
+            KeyValue::new("http.method", call.method.to_string()),
+            KeyValue::new("net.peer.name", call.host.clone()),
+        ];
+        if let Some(status) = outcome.status {
+            attributes.push(KeyValue::new("http.status_code", status as i64));
+        }
+        let mut span = tracer
+            .span_builder(call.operation_id)
+            .with_kind(SpanKind::Client)
+            .with_start_time(call.started)
+            .with_attributes(attributes)
+            .start(&tracer);
+        if outcome.is_failure() {
+            let message = match (&outcome.status, &outcome.error) {
+                (_, Some(error)) => error.clone(),
+                (Some(status), None) => status.to_string(),
+                (None, None) => String::new(),
+            };
+            span.set_status(StatusCode::Error, message);
+        }
+        span.end_with_timestamp(call.started + outcome.elapsed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    impl ClientObserver for Recorder {
+        fn on_start(&self, call: &ClientCall) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("start {}", call.operation_id));
+        }
+
+        fn on_finish(&self, call: &ClientCall, outcome: &CallOutcome) {
+            self.0.lock().unwrap().push(format!(
+                "finish {} {} {:?} {}",
+                call.operation_id,
+                call.host,
+                outcome.status,
+                outcome.is_failure()
+            ));
+        }
+    }
+
+    #[actix_rt::test]
+    async fn observe_calls() {
+        let recorder = Arc::new(Recorder::default());
+        let observer: Option<Arc<dyn ClientObserver>> = Some(recorder.clone());
+        assert!(Observation::start(
+            &None,
+            "list_pets",
+            Method::GET,
+            &"http://a/".parse().unwrap()
+        )
+        .is_none());
+
+        let url: Url = "http://127.0.0.1:1/pets".parse().unwrap();
+        let observation = Observation::start(&observer, "list_pets", Method::GET, &url).unwrap();
+        // nothing listens on port 1
+        let result = awc::Client::new().get(url.as_str()).send().await;
+        observation.finish(&result);
+
+        let observation = Observation::start(&observer, "get_pet", Method::GET, &url).unwrap();
+        observation.finish(&Ok(awc::test::TestResponse::default().finish()));
+
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            vec![
+                "start list_pets",
+                "finish list_pets 127.0.0.1:1 None true",
+                "start get_pet",
+                "finish get_pet 127.0.0.1:1 Some(200) false",
+            ]
+        );
+    }
+}
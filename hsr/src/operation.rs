@@ -0,0 +1,95 @@
+//! Metadata about the operations of an API.
+//!
+//! The generated code has an `OPERATIONS` table, with an entry for each operation
+//! of the spec, and an `operation` function to look one up by its operation id.
+//! Middleware, metrics and audit logging can use it to introspect the API at
+//! runtime, without parsing the spec:
+//!
+//! ```ignore
+//! if let Some(op) = api::operation("deletePet") {
+//!     assert_eq!(op.method(), hsr::actix_http::http::Method::DELETE);
+//! }
+//! ```
+
+use actix_http::http::Method;
+
+/// One security scheme of a security requirement, with the scopes it needs
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SecurityRequirement {
+    /// The name of the scheme in `components.securitySchemes`
+    pub scheme: &'static str,
+    pub scopes: &'static [&'static str],
+}
+
+/// An operation of the API, as declared in the spec
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OperationMeta {
+    pub operation_id: &'static str,
+    /// The method, in upper case
+    pub method: &'static str,
+    /// The path template, e.g. `/pets/{id}`
+    pub path: &'static str,
+    pub tags: &'static [&'static str],
+    pub deprecated: bool,
+    /// The alternative ways of satisfying the security requirements of the
+    /// operation (any one will do), each a list of schemes which must all be
+    /// satisfied. Empty if the operation is not secured.
+    ///
+    /// hsr does not enforce these, it is up to the application to do so.
+    pub security: &'static [&'static [SecurityRequirement]],
+}
+
+impl OperationMeta {
+    /// The method, as an `http::Method`
+    pub fn method(&self) -> Method {
+        Method::from_bytes(self.method.as_bytes()).expect("Bad method")
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.contains(&tag)
+    }
+}
+
+/// Find an operation by its operation id. Used by the generated `operation` function.
+pub fn find(
+    operations: &'static [OperationMeta],
+    operation_id: &str,
+) -> Option<&'static OperationMeta> {
+    operations.iter().find(|op| op.operation_id == operation_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static OPERATIONS: &[OperationMeta] = &[
+        OperationMeta {
+            operation_id: "getPet",
+            method: "GET",
+            path: "/pets/{id}",
+            tags: &["pets"],
+            deprecated: false,
+            security: &[],
+        },
+        OperationMeta {
+            operation_id: "deletePet",
+            method: "DELETE",
+            path: "/pets/{id}",
+            tags: &["pets", "admin"],
+            deprecated: true,
+            security: &[&[SecurityRequirement {
+                scheme: "oauth",
+                scopes: &["pets:write"],
+            }]],
+        },
+    ];
+
+    #[test]
+    fn find_operations() {
+        let op = find(OPERATIONS, "deletePet").unwrap();
+        assert_eq!(op.method(), Method::DELETE);
+        assert!(op.has_tag("admin"));
+        assert_eq!(op.security[0][0].scopes, &["pets:write"]);
+        assert!(find(OPERATIONS, "nothing").is_none());
+    }
+}
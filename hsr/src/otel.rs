@@ -0,0 +1,237 @@
+//! OpenTelemetry distributed tracing, with the `opentelemetry` feature.
+//!
+//! The generated server continues the trace context found in the W3C `traceparent`
+//! (or whatever the globally registered propagator understands) of each request, and
+//! records a span per operation. Within a handler the span is the current context, so
+//! calls made with the generated client carry it on to the next service.
+//!
+//! Register a propagator and tracer provider as usual, e.g.
+//! `opentelemetry::global::set_text_map_propagator(TraceContextPropagator::new())`.
+//! Without the feature, everything here does nothing.
+
+use std::task::{Context, Poll};
+
+use actix_http::http::Method;
+use actix_service::{Service, Transform};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::Error as ActixError;
+use futures::future::{ok, FutureExt, LocalBoxFuture, Ready};
+use std::sync::Arc;
+
+/// Middleware recording a span for each request to a resource
+#[derive(Debug, Clone, Default)]
+pub struct Tracing {
+    operations: Arc<Vec<(Method, String)>>,
+}
+
+impl Tracing {
+    /// Trace a resource, naming spans by the operation id of each method
+    pub fn new(operations: &[(Method, &str)]) -> Self {
+        Self {
+            operations: Arc::new(
+                operations
+                    .iter()
+                    .map(|(method, opid)| (method.clone(), opid.to_string()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl<S, B> Transform<S> for Tracing
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = ActixError>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = ActixError;
+    type InitError = ();
+    type Transform = TracingMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(TracingMiddleware {
+            service,
+            operations: self.operations.clone(),
+        })
+    }
+}
+
+pub struct TracingMiddleware<S> {
+    service: S,
+    #[cfg_attr(not(feature = "opentelemetry"), allow(dead_code))]
+    operations: Arc<Vec<(Method, String)>>,
+}
+
+impl<S, B> Service for TracingMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = ActixError>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = ActixError;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    #[cfg(not(feature = "opentelemetry"))]
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        self.service.call(req).boxed_local()
+    }
+
+    #[cfg(feature = "opentelemetry")]
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        use opentelemetry::trace::{
+            FutureExt as _, Span, SpanKind, StatusCode, TraceContextExt, Tracer,
+        };
+        use opentelemetry::{global, KeyValue};
+
+        let parent = global::get_text_map_propagator(|propagator| {
+            propagator.extract(&HeaderExtractor(req.headers()))
+        });
+        let name = self
+            .operations
+            .iter()
+            .find(|(method, _)| method == req.method())
+            .map(|(_, opid)| opid.clone())
+            .unwrap_or_else(|| format!("{} {}", req.method(), req.path()));
+        let tracer = global::tracer("hsr");
+        let span = tracer
+            .span_builder(name)
+            .with_kind(SpanKind::Server)
+            .with_attributes(vec![
+                KeyValue::new("http.method", req.method().to_string()),
+                KeyValue::new("http.target", req.uri().to_string()),
+            ])
+            .start_with_context(&tracer, &parent);
+        let cx = parent.with_span(span);
+
+        // Polling within the context makes the span current inside the handler
+        let fut = self.service.call(req).with_context(cx.clone());
+        async move {
+            let res = fut.await;
+            let span = cx.span();
+            match &res {
+                Ok(res) => {
+                    let status = res.status();
+                    span.set_attribute(KeyValue::new("http.status_code", status.as_u16() as i64));
+                    if status.is_server_error() {
+                        span.set_status(StatusCode::Error, status.to_string());
+                    }
+                }
+                Err(e) => span.set_status(StatusCode::Error, e.to_string()),
+            }
+            span.end();
+            res
+        }
+        .boxed_local()
+    }
+}
+
+/// Add the current trace context to the headers of an outgoing request.
+/// Used by the generated client.
+pub fn inject_context(req: awc::ClientRequest) -> awc::ClientRequest {
+    #[cfg(feature = "opentelemetry")]
+    {
+        let mut injector = HeaderInjector(Vec::new());
+        opentelemetry::global::get_text_map_propagator(|propagator| {
+            propagator.inject_context(&opentelemetry::Context::current(), &mut injector)
+        });
+        injector
+            .0
+            .into_iter()
+            .fold(req, |req, (name, value)| req.header(name.as_str(), value))
+    }
+    #[cfg(not(feature = "opentelemetry"))]
+    {
+        req
+    }
+}
+
+#[cfg(feature = "opentelemetry")]
+struct HeaderExtractor<'a>(&'a actix_http::http::HeaderMap);
+
+#[cfg(feature = "opentelemetry")]
+impl<'a> opentelemetry::propagation::Extractor for HeaderExtractor<'a> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+#[cfg(feature = "opentelemetry")]
+struct HeaderInjector(Vec<(String, String)>);
+
+#[cfg(feature = "opentelemetry")]
+impl opentelemetry::propagation::Injector for HeaderInjector {
+    fn set(&mut self, key: &str, value: String) {
+        self.0.push((key.to_string(), value));
+    }
+}
+
+#[cfg(all(test, feature = "opentelemetry"))]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App, HttpResponse};
+    use opentelemetry::global;
+    use opentelemetry::sdk::propagation::TraceContextPropagator;
+    use opentelemetry::sdk::trace::TracerProvider;
+
+    const TRACE_ID: &str = "0af7651916cd43dd8448eb211c80319c";
+    const PARENT_ID: &str = "b7ad6b7169203331";
+
+    /// Answers with the `traceparent` a call made by the handler would carry
+    async fn outgoing() -> HttpResponse {
+        let req = inject_context(awc::Client::new().get("http://upstream/pets"));
+        let traceparent = req.headers().get("traceparent").cloned();
+        match traceparent {
+            Some(traceparent) => HttpResponse::Ok().body(traceparent.to_str().unwrap().to_string()),
+            None => HttpResponse::NotFound().finish(),
+        }
+    }
+
+    #[actix_rt::test]
+    async fn spans_continue_the_trace() {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        global::set_tracer_provider(TracerProvider::builder().build());
+        let mut app = test::init_service(
+            App::new().service(
+                web::resource("/pets")
+                    .wrap(Tracing::new(&[(Method::GET, "list_pets")]))
+                    .route(web::get().to(outgoing)),
+            ),
+        )
+        .await;
+
+        let req = test::TestRequest::get()
+            .uri("/pets")
+            .header("traceparent", format!("00-{}-{}-01", TRACE_ID, PARENT_ID))
+            .to_request();
+        let body = test::read_response(&mut app, req).await;
+        let traceparent = std::str::from_utf8(&body).unwrap();
+        // the span of the operation is current in the handler, as a child of the caller's
+        let parts: Vec<_> = traceparent.split('-').collect();
+        assert_eq!(parts.len(), 4, "{}", traceparent);
+        assert_eq!(parts[1], TRACE_ID);
+        assert_ne!(parts[2], PARENT_ID);
+        assert_eq!(parts[3], "01");
+
+        // without a trace to continue, a new one is started
+        let req = test::TestRequest::get().uri("/pets").to_request();
+        let body = test::read_response(&mut app, req).await;
+        let traceparent = std::str::from_utf8(&body).unwrap();
+        let parts: Vec<_> = traceparent.split('-').collect();
+        assert_eq!(parts.len(), 4, "{}", traceparent);
+        assert_ne!(parts[1], TRACE_ID);
+        assert_ne!(parts[1], "0".repeat(32));
+    }
+}
@@ -0,0 +1,360 @@
+//! JSON patches, for `PATCH` operations.
+//!
+//! A body of media type `application/merge-patch+json` ([RFC 7386]) is generated as
+//! `Patch<T>`, where `T` is the type of its schema: any part of a `T`, with `null`
+//! removing a field. A body of media type `application/json-patch+json` ([RFC 6902])
+//! is generated as `Vec<PatchOp>`. Either can be applied to the value being patched.
+//!
+//! Both implement `Representations`, so they are sent and received with their own
+//! media types, like any other negotiated body (see `hsr::negotiate`).
+//!
+//! [RFC 7386]: https://tools.ietf.org/html/rfc7386
+//! [RFC 6902]: https://tools.ietf.org/html/rfc6902
+
+use std::marker::PhantomData;
+
+use actix_web::error::{ErrorConflict, ErrorUnprocessableEntity};
+use actix_web::Error as ActixError;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::negotiate::{is_media_type, BodyError, Representations};
+
+/// The media type of a JSON merge patch
+pub const MERGE_PATCH: &str = "application/merge-patch+json";
+
+/// The media type of a JSON patch
+pub const JSON_PATCH: &str = "application/json-patch+json";
+
+/// A JSON merge patch of a `T`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Patch<T> {
+    value: Value,
+    #[serde(skip)]
+    patched: PhantomData<fn() -> T>,
+}
+
+impl<T> Patch<T> {
+    /// A merge patch, as a JSON document
+    pub fn new(value: Value) -> Self {
+        Self {
+            value,
+            patched: PhantomData,
+        }
+    }
+
+    /// The patch, as a JSON document
+    pub fn as_value(&self) -> &Value {
+        &self.value
+    }
+
+    pub fn into_value(self) -> Value {
+        self.value
+    }
+
+    /// Apply the patch to a JSON document
+    pub fn apply_to_value(&self, target: &mut Value) {
+        merge(target, &self.value)
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> Patch<T> {
+    /// Apply the patch, giving the patched value. Fails if the result isn't a `T`.
+    pub fn apply(&self, target: &T) -> Result<T, PatchError> {
+        let mut doc = serde_json::to_value(target)?;
+        self.apply_to_value(&mut doc);
+        Ok(serde_json::from_value(doc)?)
+    }
+}
+
+impl<T> Representations for Patch<T> {
+    const MEDIA_TYPES: &'static [&'static str] = &[MERGE_PATCH];
+
+    fn media_type(&self) -> &'static str {
+        MERGE_PATCH
+    }
+
+    fn to_body(&self) -> serde_json::Result<Vec<u8>> {
+        serde_json::to_vec(&self.value)
+    }
+
+    fn from_body(content_type: &str, body: &[u8]) -> Result<Self, BodyError> {
+        if !is_media_type(content_type, MERGE_PATCH) {
+            return Err(BodyError::UnsupportedMediaType(content_type.to_string()));
+        }
+        Ok(Self::new(serde_json::from_slice(body)?))
+    }
+}
+
+/// Merge a patch into a JSON document, as described by RFC 7386
+pub fn merge(target: &mut Value, patch: &Value) {
+    let patch = match patch {
+        Value::Object(patch) => patch,
+        _ => {
+            *target = patch.clone();
+            return;
+        }
+    };
+    if !target.is_object() {
+        *target = Value::Object(Default::default());
+    }
+    let target = target.as_object_mut().unwrap();
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(key);
+        } else {
+            merge(target.entry(key.as_str()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+/// An operation of a JSON patch. Paths are JSON pointers.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum PatchOp {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: Value },
+}
+
+impl Representations for Vec<PatchOp> {
+    const MEDIA_TYPES: &'static [&'static str] = &[JSON_PATCH];
+
+    fn media_type(&self) -> &'static str {
+        JSON_PATCH
+    }
+
+    fn to_body(&self) -> serde_json::Result<Vec<u8>> {
+        serde_json::to_vec(self)
+    }
+
+    fn from_body(content_type: &str, body: &[u8]) -> Result<Self, BodyError> {
+        if !is_media_type(content_type, JSON_PATCH) {
+            return Err(BodyError::UnsupportedMediaType(content_type.to_string()));
+        }
+        Ok(serde_json::from_slice(body)?)
+    }
+}
+
+/// Why a patch couldn't be applied
+#[derive(Debug, thiserror::Error)]
+pub enum PatchError {
+    #[error("No value at '{0}'")]
+    NoSuchPath(String),
+    #[error("Test of '{0}' failed")]
+    TestFailed(String),
+    #[error("Cannot move '{0}' into itself")]
+    MoveIntoChild(String),
+    #[error("The patched value is invalid: {0}")]
+    Json(#[from] serde_json::Error),
+}
+
+/// A failed test is a conflict with the current state, anything else is
+/// a patch which can't be applied to it
+impl From<PatchError> for ActixError {
+    fn from(e: PatchError) -> Self {
+        match e {
+            PatchError::TestFailed(_) => ErrorConflict(e),
+            _ => ErrorUnprocessableEntity(e),
+        }
+    }
+}
+
+/// Apply a JSON patch, giving the patched value. Fails if any operation
+/// fails, or the result isn't a `T`.
+pub fn apply_json_patch<T>(target: &T, ops: &[PatchOp]) -> Result<T, PatchError>
+where
+    T: Serialize + DeserializeOwned,
+{
+    let mut doc = serde_json::to_value(target)?;
+    apply_json_patch_to_value(&mut doc, ops)?;
+    Ok(serde_json::from_value(doc)?)
+}
+
+/// Apply a JSON patch to a JSON document, as described by RFC 6902.
+/// Either every operation is applied, or (on error) none are.
+pub fn apply_json_patch_to_value(target: &mut Value, ops: &[PatchOp]) -> Result<(), PatchError> {
+    let mut doc = target.clone();
+    for op in ops {
+        match op {
+            PatchOp::Add { path, value } => add(&mut doc, path, value.clone())?,
+            PatchOp::Remove { path } => {
+                remove(&mut doc, path)?;
+            }
+            PatchOp::Replace { path, value } => {
+                *doc.pointer_mut(path)
+                    .ok_or_else(|| PatchError::NoSuchPath(path.clone()))? = value.clone()
+            }
+            PatchOp::Move { from, path } => {
+                if path.starts_with(&format!("{}/", from)) {
+                    return Err(PatchError::MoveIntoChild(from.clone()));
+                }
+                let value = remove(&mut doc, from)?;
+                add(&mut doc, path, value)?
+            }
+            PatchOp::Copy { from, path } => {
+                let value = doc
+                    .pointer(from)
+                    .cloned()
+                    .ok_or_else(|| PatchError::NoSuchPath(from.clone()))?;
+                add(&mut doc, path, value)?
+            }
+            PatchOp::Test { path, value } => {
+                if doc.pointer(path) != Some(value) {
+                    return Err(PatchError::TestFailed(path.clone()));
+                }
+            }
+        }
+    }
+    *target = doc;
+    Ok(())
+}
+
+/// Split a JSON pointer into the pointer to the parent, and the (unescaped) last token
+fn split_pointer(path: &str) -> Result<(&str, String), PatchError> {
+    match path.rfind('/') {
+        Some(ix) => Ok((
+            &path[..ix],
+            path[ix + 1..].replace("~1", "/").replace("~0", "~"),
+        )),
+        None => Err(PatchError::NoSuchPath(path.to_string())),
+    }
+}
+
+fn add(doc: &mut Value, path: &str, value: Value) -> Result<(), PatchError> {
+    if path.is_empty() {
+        *doc = value;
+        return Ok(());
+    }
+    let no_such_path = || PatchError::NoSuchPath(path.to_string());
+    let (parent, token) = split_pointer(path)?;
+    match doc.pointer_mut(parent).ok_or_else(no_such_path)? {
+        Value::Object(obj) => {
+            obj.insert(token, value);
+        }
+        Value::Array(items) if token == "-" => items.push(value),
+        Value::Array(items) => match token.parse::<usize>() {
+            Ok(ix) if ix <= items.len() => items.insert(ix, value),
+            _ => return Err(no_such_path()),
+        },
+        _ => return Err(no_such_path()),
+    }
+    Ok(())
+}
+
+fn remove(doc: &mut Value, path: &str) -> Result<Value, PatchError> {
+    let no_such_path = || PatchError::NoSuchPath(path.to_string());
+    let (parent, token) = split_pointer(path)?;
+    match doc.pointer_mut(parent).ok_or_else(no_such_path)? {
+        Value::Object(obj) => obj.remove(&token).ok_or_else(no_such_path),
+        Value::Array(items) => match token.parse::<usize>() {
+            Ok(ix) if ix < items.len() => Ok(items.remove(ix)),
+            _ => Err(no_such_path()),
+        },
+        _ => Err(no_such_path()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Pet {
+        name: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        tag: Option<String>,
+        toys: Vec<String>,
+    }
+
+    fn rex() -> Pet {
+        Pet {
+            name: "Rex".into(),
+            tag: Some("dog".into()),
+            toys: vec!["ball".into()],
+        }
+    }
+
+    #[test]
+    fn merge_patches() {
+        let patch: Patch<Pet> = serde_json::from_str(r#"{"name":"Max","tag":null}"#).unwrap();
+        let pet = patch.apply(&rex()).unwrap();
+        assert_eq!(pet.name, "Max");
+        assert_eq!(pet.tag, None);
+        assert_eq!(pet.toys, vec!["ball"]);
+
+        // RFC 7386, appendix A
+        let mut doc = json!({"a": {"b": "c"}, "d": [1]});
+        merge(
+            &mut doc,
+            &json!({"a": {"b": null, "e": "f"}, "d": null, "g": [2]}),
+        );
+        assert_eq!(doc, json!({"a": {"e": "f"}, "g": [2]}));
+
+        let patch: Patch<Pet> = Patch::new(json!({"name": 3}));
+        assert!(patch.apply(&rex()).is_err());
+        assert_eq!(patch.to_body().unwrap(), br#"{"name":3}"#);
+        assert!(Patch::<Pet>::from_body("application/json", b"{}").is_err());
+    }
+
+    #[test]
+    fn json_patches() {
+        let ops: Vec<PatchOp> = serde_json::from_value(json!([
+            {"op": "test", "path": "/name", "value": "Rex"},
+            {"op": "replace", "path": "/name", "value": "Max"},
+            {"op": "add", "path": "/toys/-", "value": "stick"},
+            {"op": "copy", "from": "/toys/0", "path": "/toys/0"},
+            {"op": "move", "from": "/tag", "path": "/toys/1"},
+            {"op": "remove", "path": "/toys/0"},
+        ]))
+        .unwrap();
+        let pet = apply_json_patch(&rex(), &ops).unwrap();
+        assert_eq!(
+            pet,
+            Pet {
+                name: "Max".into(),
+                tag: None,
+                toys: vec!["dog".into(), "ball".into(), "stick".into()],
+            }
+        );
+
+        // failures leave the document as it was
+        let mut doc = json!({"a/b": [1]});
+        let ops = vec![
+            PatchOp::Add {
+                path: "/a~1b/0".into(),
+                value: json!(0),
+            },
+            PatchOp::Test {
+                path: "/a~1b".into(),
+                value: json!([1]),
+            },
+        ];
+        assert!(matches!(
+            apply_json_patch_to_value(&mut doc, &ops),
+            Err(PatchError::TestFailed(_))
+        ));
+        assert_eq!(doc, json!({"a/b": [1]}));
+        let ops = vec![PatchOp::Remove {
+            path: "/missing".into(),
+        }];
+        assert!(matches!(
+            apply_json_patch_to_value(&mut doc, &ops),
+            Err(PatchError::NoSuchPath(_))
+        ));
+        let ops = vec![PatchOp::Move {
+            from: "/a~1b".into(),
+            path: "/a~1b/0".into(),
+        }];
+        assert!(matches!(
+            apply_json_patch_to_value(&mut doc, &ops),
+            Err(PatchError::MoveIntoChild(_))
+        ));
+    }
+}
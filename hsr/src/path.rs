@@ -0,0 +1,159 @@
+//! Percent-encoding of path parameters.
+//!
+//! The generated client encodes every path parameter with `encode_segment`, so that
+//! values containing `/`, `\`, `?`, `#`, spaces etc arrive intact, as a single segment.
+//! `.` and `..` would be resolved as dot segments however they were escaped, so they
+//! are refused.
+//! `actix-web` leaves some escapes (e.g. `%2F`) in the matched path, so the generated
+//! server extracts parameters with `Path`, which decodes them fully.
+
+use std::borrow::Cow;
+use std::ops::Deref;
+
+use actix_http::Payload;
+use actix_web::{error::ErrorNotFound, Error as ActixError, FromRequest, HttpRequest};
+use futures::future::{ready, Ready};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use serde::de::DeserializeOwned;
+
+/// Characters which must be escaped within a path segment
+const SEGMENT: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'+')
+    .add(b'/')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'\\')
+    .add(b'`')
+    .add(b'{')
+    .add(b'}');
+
+/// A path parameter which can't be sent as a single segment
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("Path parameter '{0}' is a dot segment")]
+pub struct DotSegment(pub String);
+
+/// Percent-encode a value for use as a single path segment. `.` and `..` (which
+/// URLs resolve, even as `%2E`) are refused.
+pub fn encode_segment(value: &str) -> Result<Cow<str>, DotSegment> {
+    if value == "." || value == ".." {
+        return Err(DotSegment(value.into()));
+    }
+    Ok(utf8_percent_encode(value, SEGMENT).into())
+}
+
+/// Extract path parameters into `T`, percent-decoding their values.
+///
+/// Like `actix_web::web::Path`, a request whose parameters can't be decoded
+/// or deserialized is rejected with `404 Not Found`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Path<T>(pub T);
+
+impl<T> Path<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for Path<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: DeserializeOwned> FromRequest for Path<T> {
+    type Error = ActixError;
+    type Future = Ready<Result<Self, ActixError>>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        ready(extract(req).map(Path))
+    }
+}
+
+fn extract<T: DeserializeOwned>(req: &HttpRequest) -> Result<T, ActixError> {
+    from_params(req.match_info().iter())
+}
+
+/// Decode the (raw) values of path parameters, and deserialize them into `T`
+pub(crate) fn from_params<'a, T: DeserializeOwned>(
+    raw_params: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> Result<T, ActixError> {
+    let mut params = Vec::new();
+    for (name, value) in raw_params {
+        let value = percent_decode_str(value)
+            .decode_utf8()
+            .map_err(ErrorNotFound)?;
+        params.push((name, value));
+    }
+    // Going via the urlencoded representation gives us the same
+    // string -> number etc conversions as query strings
+    let encoded = serde_urlencoded::to_string(&params).map_err(ErrorNotFound)?;
+    serde_urlencoded::from_str(&encoded).map_err(ErrorNotFound)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::test::TestRequest;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Params {
+        name: String,
+        id: i64,
+    }
+
+    #[test]
+    fn encode_path_segment() {
+        let encode = |value| encode_segment(value).unwrap();
+        assert_eq!(encode("plain-value_1.2~"), "plain-value_1.2~");
+        assert_eq!(encode("a/b?c#d e"), "a%2Fb%3Fc%23d%20e");
+        assert_eq!(encode("100%"), "100%25");
+        assert_eq!(encode("café"), "caf%C3%A9");
+        assert_eq!(encode("a\\b"), "a%5Cb");
+        assert_eq!(encode("%2e"), "%252e");
+        assert_eq!(encode("..."), "...");
+        assert_eq!(encode_segment("."), Err(DotSegment(".".into())));
+        assert_eq!(encode_segment(".."), Err(DotSegment("..".into())));
+    }
+
+    #[test]
+    fn single_segments() {
+        let base = url::Url::parse("http://localhost/api/").unwrap();
+        let url = |id| {
+            base.join(&format!("/pets/{}", encode_segment(id).unwrap()))
+                .unwrap()
+        };
+        assert_eq!(url("a\\b").path_segments().unwrap().count(), 2);
+        assert_eq!(url("a\\b").path(), "/pets/a%5Cb");
+        assert_eq!(url("%2e%2e").path(), "/pets/%252e%252e");
+    }
+
+    #[test]
+    fn decode_path_params() {
+        let req = TestRequest::default()
+            .param("name", "a%2Fb%25 c")
+            .param("id", "12")
+            .to_http_request();
+        let params: Params = extract(&req).unwrap();
+        assert_eq!(
+            params,
+            Params {
+                name: "a/b% c".into(),
+                id: 12
+            }
+        );
+
+        let req = TestRequest::default()
+            .param("name", "x")
+            .param("id", "not-a-number")
+            .to_http_request();
+        assert!(extract::<Params>(&req).is_err());
+    }
+}
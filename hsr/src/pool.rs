@@ -0,0 +1,135 @@
+use std::time::Duration;
+
+use openssl::ssl::{SslConnector, SslMethod};
+
+/// Settings for the connection pool of the generated client.
+///
+/// The defaults of `awc` suit occasional calls; callers making many calls to the
+/// same upstream will want more connections, kept alive for longer. Give a client
+/// its own pool with `Client::with_connection_options`:
+///
+/// ```ignore
+/// let client = Client::new(url).with_connection_options(
+///     &ConnectionOptions::new()
+///         .max_connections(500)
+///         .keep_alive(Duration::from_secs(60))
+///         .connect_timeout(Duration::from_millis(200)),
+/// );
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ConnectionOptions {
+    max_connections: Option<usize>,
+    keep_alive: Option<Duration>,
+    lifetime: Option<Duration>,
+    connect_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    http2: Option<bool>,
+}
+
+impl ConnectionOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Open at most `limit` connections at once, for each of HTTP and HTTPS
+    /// (100 by default)
+    pub fn max_connections(mut self, limit: usize) -> Self {
+        self.max_connections = Some(limit);
+        self
+    }
+
+    /// Close connections which have been idle for `keep_alive` (15 seconds by default)
+    pub fn keep_alive(mut self, keep_alive: Duration) -> Self {
+        self.keep_alive = Some(keep_alive);
+        self
+    }
+
+    /// Close connections once they have been open for `lifetime`, even if busy
+    /// (75 seconds by default), so that new hosts behind a load balancer are used
+    pub fn lifetime(mut self, lifetime: Duration) -> Self {
+        self.lifetime = Some(lifetime);
+        self
+    }
+
+    /// Give up connecting after `timeout` (1 second by default)
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Give up on calls after `timeout` (5 seconds by default). `CallOptions::timeout`
+    /// overrides this for particular calls.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Whether to offer HTTP/2 to HTTPS servers, which use it if they support it
+    /// (the default), or to stick to HTTP/1.1
+    pub fn http2(mut self, http2: bool) -> Self {
+        self.http2 = Some(http2);
+        self
+    }
+
+    /// A client with a pool of connections with these settings
+    pub fn build(&self) -> awc::Client {
+        let mut connector = awc::Connector::new();
+        if let Some(limit) = self.max_connections {
+            connector = connector.limit(limit);
+        }
+        if let Some(keep_alive) = self.keep_alive {
+            connector = connector.conn_keep_alive(keep_alive);
+        }
+        if let Some(lifetime) = self.lifetime {
+            connector = connector.conn_lifetime(lifetime);
+        }
+        if let Some(timeout) = self.connect_timeout {
+            connector = connector.timeout(timeout);
+        }
+        if let Some(http2) = self.http2 {
+            let mut ssl = SslConnector::builder(SslMethod::tls()).expect("failed to set up TLS");
+            ssl.set_alpn_protos(alpn_protocols(http2))
+                .expect("failed to set ALPN protocols");
+            connector = connector.ssl(ssl.build());
+        }
+        let mut client = awc::Client::build().connector(connector.finish());
+        if let Some(timeout) = self.timeout {
+            client = client.timeout(timeout);
+        }
+        client.finish()
+    }
+}
+
+/// The protocols offered to HTTPS servers, in the wire format of ALPN
+fn alpn_protocols(http2: bool) -> &'static [u8] {
+    if http2 {
+        b"\x02h2\x08http/1.1"
+    } else {
+        b"\x08http/1.1"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alpn() {
+        assert_eq!(alpn_protocols(false), b"\x08http/1.1");
+        assert_eq!(&alpn_protocols(true)[..3], b"\x02h2");
+    }
+
+    #[actix_rt::test]
+    async fn build_client() {
+        let opts = ConnectionOptions::new()
+            .max_connections(2)
+            .keep_alive(Duration::from_secs(1))
+            .connect_timeout(Duration::from_millis(100))
+            .http2(false);
+        assert_eq!(opts.max_connections, Some(2));
+        assert_eq!(opts.http2, Some(false));
+        // nothing listens on port 1
+        let err = opts.build().get("http://127.0.0.1:1/").send().await;
+        assert!(err.is_err());
+    }
+}
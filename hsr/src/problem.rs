@@ -0,0 +1,167 @@
+//! Problem details for HTTP APIs ([RFC 7807](https://tools.ietf.org/html/rfc7807))
+
+use std::fmt;
+
+use actix_http::http::{header::CONTENT_TYPE, StatusCode};
+use actix_web::HttpResponse;
+use serde::Serialize;
+use serde_json::{Map, Value};
+
+/// The media type of a problem document
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// A machine-readable description of an error, sent as `application/problem+json`.
+///
+/// Members not defined by the RFC are kept in `extensions`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Problem {
+    /// A URI identifying the type of problem
+    #[serde(rename = "type", default = "about_blank")]
+    pub type_: String,
+    /// A short summary of the type of problem
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// The status code of the response
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    /// An explanation specific to this occurrence of the problem
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// A URI identifying this occurrence of the problem
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    /// Any other members
+    #[serde(flatten)]
+    pub extensions: Map<String, Value>,
+}
+
+fn about_blank() -> String {
+    "about:blank".into()
+}
+
+impl Problem {
+    /// A problem of type `about:blank`, titled with the reason phrase of the status code
+    pub fn new(status: StatusCode) -> Self {
+        Self {
+            type_: about_blank(),
+            title: status.canonical_reason().map(String::from),
+            status: Some(status.as_u16()),
+            detail: None,
+            instance: None,
+            extensions: Map::new(),
+        }
+    }
+
+    /// Build a problem from a response body. The members of a JSON object are
+    /// kept (so a body may set its own `type`, `title` etc), a string becomes
+    /// the `detail` and anything else is stored under `value`.
+    pub fn from_body<T: Serialize>(status: StatusCode, body: &T) -> Self {
+        let mut problem = Self::new(status);
+        match serde_json::to_value(body) {
+            Ok(Value::Object(members)) => {
+                if let Ok(parsed) = serde_json::from_value::<Problem>(Value::Object(members)) {
+                    problem.type_ = parsed.type_;
+                    problem.title = parsed.title.or(problem.title);
+                    problem.status = parsed.status.or(problem.status);
+                    problem.detail = parsed.detail;
+                    problem.instance = parsed.instance;
+                    problem.extensions = parsed.extensions;
+                }
+            }
+            Ok(Value::String(detail)) => problem.detail = Some(detail),
+            Ok(Value::Null) | Err(_) => {}
+            Ok(other) => {
+                problem.extensions.insert("value".into(), other);
+            }
+        }
+        problem
+    }
+
+    pub fn with_type(mut self, type_: impl Into<String>) -> Self {
+        self.type_ = type_.into();
+        self
+    }
+
+    pub fn with_title(mut self, title: impl Into<String>) -> Self {
+        self.title = Some(title.into());
+        self
+    }
+
+    pub fn with_detail(mut self, detail: impl Into<String>) -> Self {
+        self.detail = Some(detail.into());
+        self
+    }
+
+    pub fn with_instance(mut self, instance: impl Into<String>) -> Self {
+        self.instance = Some(instance.into());
+        self
+    }
+
+    /// Render as an `application/problem+json` response
+    pub fn to_response(&self) -> HttpResponse {
+        let status = self
+            .status
+            .and_then(|s| StatusCode::from_u16(s).ok())
+            .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        HttpResponse::build(status)
+            .header(CONTENT_TYPE, PROBLEM_JSON)
+            .body(serde_json::to_string(self).unwrap())
+    }
+
+    /// Whether a `Content-Type` header value denotes a problem document
+    pub fn is_problem_content_type(content_type: &str) -> bool {
+        content_type
+            .split(';')
+            .next()
+            .map(|mime| mime.trim().eq_ignore_ascii_case(PROBLEM_JSON))
+            .unwrap_or(false)
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.title.as_deref().unwrap_or(&self.type_))?;
+        if let Some(status) = self.status {
+            write!(f, " ({})", status)?;
+        }
+        if let Some(detail) = &self.detail {
+            write!(f, ": {}", detail)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Problem {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn problem_from_body() {
+        let body = json!({"title": "Out of credit", "detail": "Balance is 30", "balance": 30});
+        let problem = Problem::from_body(StatusCode::FORBIDDEN, &body);
+        assert_eq!(problem.type_, "about:blank");
+        assert_eq!(problem.title.as_deref(), Some("Out of credit"));
+        assert_eq!(problem.status, Some(403));
+        assert_eq!(problem.extensions["balance"], json!(30));
+
+        let problem = Problem::from_body(StatusCode::NOT_FOUND, &"no such pet");
+        assert_eq!(problem.title.as_deref(), Some("Not Found"));
+        assert_eq!(problem.detail.as_deref(), Some("no such pet"));
+
+        let round_trip: Problem =
+            serde_json::from_str(&serde_json::to_string(&problem).unwrap()).unwrap();
+        assert_eq!(round_trip, problem);
+    }
+
+    #[test]
+    fn problem_content_type() {
+        assert!(Problem::is_problem_content_type("application/problem+json"));
+        assert!(Problem::is_problem_content_type(
+            "application/problem+json; charset=utf-8"
+        ));
+        assert!(!Problem::is_problem_content_type("application/json"));
+    }
+}
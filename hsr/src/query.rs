@@ -0,0 +1,216 @@
+//! Query parameters which need more than `serde_urlencoded` gives us.
+//!
+//! A parameter allowing empty values (`?flag` or `?flag=`) is deserialized with
+//! `allow_empty`, which gives the value the generated code says an empty value
+//! stands for: `true` for booleans, otherwise the parameter is taken to be missing.
+//! String parameters need no help, an empty value is just an empty string.
+//!
+//! The generated client passes the query string through `allow_reserved`, so that the
+//! reserved characters of `allowReserved` parameters are sent without percent-encoding.
+//!
+//! Array parameters with `style: spaceDelimited` or `style: pipeDelimited` are
+//! (de)serialized with `deserialize_delimited` and `serialize_delimited`.
+
+use serde::de::{self, DeserializeOwned, Deserializer};
+use serde::ser::{self, Serialize, Serializer};
+use serde::Deserialize;
+
+/// Reserved characters which `allow_reserved` leaves as they are. `&`, `=`, `+` and `#`
+/// stay encoded, as they would change the meaning of the query string.
+const RESERVED: &[(&str, char)] = &[
+    ("%21", '!'),
+    ("%24", '$'),
+    ("%28", '('),
+    ("%29", ')'),
+    ("%2A", '*'),
+    ("%2C", ','),
+    ("%2F", '/'),
+    ("%3A", ':'),
+    ("%3B", ';'),
+    ("%3F", '?'),
+    ("%40", '@'),
+    ("%5B", '['),
+    ("%5D", ']'),
+];
+
+/// Deserialize a query parameter which may have an empty value, in which case
+/// `empty` gives the value it stands for (if it stands for anything)
+pub fn allow_empty<'de, D, T>(de: D, empty: impl FnOnce() -> Option<T>) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    let value = String::deserialize(de)?;
+    if value.is_empty() {
+        return empty().ok_or_else(|| de::Error::custom("empty value"));
+    }
+    from_part(&value).map_err(de::Error::custom)
+}
+
+/// Deserialize the items of a delimited array parameter, e.g. `?ids=1|2|3`
+pub fn deserialize_delimited<'de, D, T>(de: D, delimiter: char) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned,
+{
+    let value = String::deserialize(de)?;
+    if value.is_empty() {
+        return Ok(Vec::new());
+    }
+    value
+        .split(delimiter)
+        .map(|item| from_part(item).map_err(de::Error::custom))
+        .collect()
+}
+
+/// Serialize the items of a delimited array parameter
+pub fn serialize_delimited<S, T>(items: &[T], delimiter: char, ser: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    T: Serialize,
+{
+    let mut joined = String::new();
+    for (ix, item) in items.iter().enumerate() {
+        if ix > 0 {
+            joined.push(delimiter);
+        }
+        joined.push_str(&to_part(item).map_err(ser::Error::custom)?);
+    }
+    ser.serialize_str(&joined)
+}
+
+/// Deserialize a single value. Going via the urlencoded representation gives us
+/// the same string -> number etc conversions as the rest of the query string.
+fn from_part<T: DeserializeOwned>(value: &str) -> Result<T, serde_urlencoded::de::Error> {
+    let encoded = serde_urlencoded::to_string(&[("value", value)])
+        .map_err(|e| de::Error::custom(e.to_string()))?;
+    let mut decoded: Vec<(String, T)> = serde_urlencoded::from_str(&encoded)?;
+    decoded
+        .pop()
+        .map(|(_, value)| value)
+        .ok_or_else(|| de::Error::custom("missing value"))
+}
+
+/// Serialize a single value, as it is before percent-encoding
+fn to_part<T: Serialize>(value: &T) -> Result<String, serde_urlencoded::ser::Error> {
+    let encoded = serde_urlencoded::to_string(&[("value", value)])?;
+    let mut decoded: Vec<(String, String)> =
+        serde_urlencoded::from_str(&encoded).map_err(|e| ser::Error::custom(e.to_string()))?;
+    decoded
+        .pop()
+        .map(|(_, value)| value)
+        .ok_or_else(|| ser::Error::custom("missing value"))
+}
+
+/// Undo the percent-encoding of reserved characters in the values of the named parameters
+pub fn allow_reserved(query: &str, names: &[&str]) -> String {
+    query
+        .split('&')
+        .map(|pair| {
+            let mut parts = pair.splitn(2, '=');
+            let name = parts.next().unwrap_or_default();
+            match parts.next() {
+                Some(value) if names.contains(&name) => {
+                    format!("{}={}", name, unescape_reserved(value))
+                }
+                _ => pair.to_string(),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+fn unescape_reserved(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(ix) = rest.find('%') {
+        unescaped.push_str(&rest[..ix]);
+        let escape = rest.get(ix..ix + 3).unwrap_or(&rest[ix..]);
+        match RESERVED
+            .iter()
+            .find(|(code, _)| code.eq_ignore_ascii_case(escape))
+        {
+            Some((_, ch)) => unescaped.push(*ch),
+            None => unescaped.push_str(escape),
+        }
+        rest = &rest[ix + escape.len()..];
+    }
+    unescaped.push_str(rest);
+    unescaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flag<'de, D: Deserializer<'de>>(de: D) -> Result<Option<bool>, D::Error> {
+        allow_empty(de, || Some(Some(true)))
+    }
+
+    fn limit<'de, D: Deserializer<'de>>(de: D) -> Result<Option<i64>, D::Error> {
+        allow_empty(de, || Some(None))
+    }
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Params {
+        #[serde(default, deserialize_with = "flag")]
+        verbose: Option<bool>,
+        #[serde(default, deserialize_with = "limit")]
+        limit: Option<i64>,
+    }
+
+    fn params(verbose: Option<bool>, limit: Option<i64>) -> Params {
+        Params { verbose, limit }
+    }
+
+    #[test]
+    fn empty_values() {
+        let parse = |qs: &str| serde_urlencoded::from_str::<Params>(qs).unwrap();
+        assert_eq!(parse(""), params(None, None));
+        assert_eq!(parse("verbose&limit="), params(Some(true), None));
+        assert_eq!(parse("verbose=false&limit=3"), params(Some(false), Some(3)));
+        assert!(serde_urlencoded::from_str::<Params>("limit=lots").is_err());
+    }
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Delimited {
+        #[serde(
+            deserialize_with = "deserialize_pipes",
+            serialize_with = "serialize_pipes"
+        )]
+        ids: Vec<i64>,
+    }
+
+    fn deserialize_pipes<'de, D: Deserializer<'de>>(de: D) -> Result<Vec<i64>, D::Error> {
+        deserialize_delimited(de, '|')
+    }
+
+    #[allow(clippy::ptr_arg)]
+    fn serialize_pipes<S: Serializer>(value: &Vec<i64>, ser: S) -> Result<S::Ok, S::Error> {
+        serialize_delimited(value, '|', ser)
+    }
+
+    #[test]
+    fn delimited_values() {
+        let ids = Delimited { ids: vec![1, 2, 3] };
+        let qs = serde_urlencoded::to_string(&ids).unwrap();
+        assert_eq!(qs, "ids=1%7C2%7C3");
+        assert_eq!(serde_urlencoded::from_str::<Delimited>(&qs).unwrap(), ids);
+        assert_eq!(
+            serde_urlencoded::from_str::<Delimited>("ids=").unwrap(),
+            Delimited { ids: vec![] }
+        );
+        assert!(serde_urlencoded::from_str::<Delimited>("ids=1|two").is_err());
+        assert_eq!(to_part(&"a b|c").unwrap(), "a b|c");
+    }
+
+    #[test]
+    fn reserved_values() {
+        let qs = serde_urlencoded::to_string(&[("path", "a/b:c?"), ("other", "a/b")]).unwrap();
+        assert_eq!(qs, "path=a%2Fb%3Ac%3F&other=a%2Fb");
+        assert_eq!(allow_reserved(&qs, &["path"]), "path=a/b:c?&other=a%2Fb");
+        // these would change the meaning of the query
+        let qs = serde_urlencoded::to_string(&[("path", "a&b=c d#%")]).unwrap();
+        assert_eq!(allow_reserved(&qs, &["path"]), qs);
+    }
+}
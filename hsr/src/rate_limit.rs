@@ -0,0 +1,327 @@
+//! Per-operation rate limiting, declared in the spec with `x-hsr-rate-limit`.
+//!
+//! Each client gets a token bucket per operation, holding up to `requests` tokens
+//! and refilled at `requests` per `per`. Requests which find the bucket empty are
+//! rejected with `429 Too Many Requests` and a `Retry-After` header.
+//!
+//! Buckets live in a `RateLimitStore`. The default `InMemoryStore` is local to the
+//! process; implement the trait on top of e.g. Redis to share limits between replicas.
+//!
+//! Limits keyed on a header trust its value: the limiter runs before the request
+//! is authorized, so a client may dodge the limit by sending a new value with each
+//! request. Only key limits on a header which is authenticated before it reaches
+//! the server (e.g. an API key checked by a gateway).
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use actix_http::http::{header::RETRY_AFTER, Method};
+use actix_service::{Service, Transform};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::{Error as ActixError, HttpResponse};
+use futures::future::{ok, FutureExt, LocalBoxFuture, Ready};
+
+/// What identifies a client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RateLimitKey {
+    /// The client IP address (taking `Forwarded` headers into account)
+    Ip,
+    /// The value of a header, typically an API key. Requests without the
+    /// header are limited by IP address. The value isn't checked, so the header
+    /// must be authenticated upstream (see the module docs).
+    Header(String),
+}
+
+/// A limit of `requests` per `per`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RateLimit {
+    pub requests: u32,
+    pub per: Duration,
+    pub key: RateLimitKey,
+}
+
+impl RateLimit {
+    /// Panics if `requests` or `per` is zero, which would let no request through.
+    pub fn new(requests: u32, per: Duration, key: RateLimitKey) -> Self {
+        assert!(
+            requests > 0 && per > Duration::from_secs(0),
+            "A rate limit needs a number of requests and a period"
+        );
+        Self { requests, per, key }
+    }
+
+    /// `secs` as a duration. Waits are never longer than `per`, and a limit built
+    /// without `new` may have no rate, making them infinite.
+    fn wait(&self, secs: f64) -> Duration {
+        Duration::from_secs_f64(secs.min(self.per.as_secs_f64()).max(0.0))
+    }
+
+    /// Tokens added per second
+    fn rate(&self) -> f64 {
+        f64::from(self.requests) / self.per.as_secs_f64().max(f64::EPSILON)
+    }
+}
+
+/// Storage for the token buckets
+#[async_trait::async_trait]
+pub trait RateLimitStore: Send + Sync + 'static {
+    /// Take a token from the bucket for `key`. If there are none left,
+    /// return how long until there will be.
+    async fn acquire(&self, key: &str, limit: &RateLimit) -> Result<(), Duration>;
+}
+
+/// How often the `InMemoryStore` forgets the buckets which have refilled
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+
+/// An in-process `RateLimitStore`. Buckets which have refilled are forgotten, as
+/// they are no different from new ones, so idle clients don't take up memory.
+#[derive(Debug, Default)]
+pub struct InMemoryStore {
+    buckets: Mutex<Buckets>,
+}
+
+#[derive(Debug, Default)]
+struct Buckets {
+    buckets: HashMap<String, Bucket>,
+    swept: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+    /// When the bucket will be full again
+    full: Instant,
+}
+
+impl InMemoryStore {
+    fn take(&self, key: &str, limit: &RateLimit, now: Instant) -> Result<(), Duration> {
+        let capacity = f64::from(limit.requests);
+        let mut buckets = self.buckets.lock().unwrap();
+        if buckets.swept.map_or(true, |swept| {
+            now.saturating_duration_since(swept) >= SWEEP_INTERVAL
+        }) {
+            buckets.buckets.retain(|_, bucket| bucket.full > now);
+            buckets.swept = Some(now);
+        }
+        let bucket = buckets.buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+            full: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * limit.rate()).min(capacity);
+        bucket.updated = now;
+        let result = if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(limit.wait((1.0 - bucket.tokens) / limit.rate()))
+        };
+        bucket.full = now + limit.wait((capacity - bucket.tokens) / limit.rate());
+        result
+    }
+
+    /// How many buckets are kept
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.buckets.lock().unwrap().buckets.len()
+    }
+}
+
+#[async_trait::async_trait]
+impl RateLimitStore for InMemoryStore {
+    async fn acquire(&self, key: &str, limit: &RateLimit) -> Result<(), Duration> {
+        self.take(key, limit, Instant::now())
+    }
+}
+
+/// Middleware applying the rate limits of the operations of a resource
+#[derive(Clone)]
+pub struct RateLimiter {
+    store: Arc<dyn RateLimitStore>,
+    limits: Arc<Vec<(Method, String, RateLimit)>>,
+}
+
+impl RateLimiter {
+    pub fn new(store: Arc<dyn RateLimitStore>) -> Self {
+        Self {
+            store,
+            limits: Arc::new(Vec::new()),
+        }
+    }
+
+    /// Limit requests to an operation
+    pub fn limit(mut self, method: Method, operation_id: &str, limit: RateLimit) -> Self {
+        Arc::make_mut(&mut self.limits).push((method, operation_id.to_string(), limit));
+        self
+    }
+}
+
+impl<S> Transform<S> for RateLimiter
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = ActixError> + 'static,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = ActixError;
+    type InitError = ();
+    type Transform = RateLimiterMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RateLimiterMiddleware {
+            service: Rc::new(RefCell::new(service)),
+            store: self.store.clone(),
+            limits: self.limits.clone(),
+        })
+    }
+}
+
+pub struct RateLimiterMiddleware<S> {
+    service: Rc<RefCell<S>>,
+    store: Arc<dyn RateLimitStore>,
+    limits: Arc<Vec<(Method, String, RateLimit)>>,
+}
+
+impl<S> Service for RateLimiterMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = ActixError> + 'static,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = ActixError;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.borrow_mut().poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let (opid, limit) = match self
+            .limits
+            .iter()
+            .find(|(method, _, _)| method == req.method())
+        {
+            Some((_, opid, limit)) => (opid.clone(), limit.clone()),
+            None => return self.service.borrow_mut().call(req).boxed_local(),
+        };
+        let client = match &limit.key {
+            RateLimitKey::Header(name) => req
+                .headers()
+                .get(name.as_str())
+                .and_then(|value| value.to_str().ok())
+                .map(|value| format!("header:{}", value)),
+            RateLimitKey::Ip => None,
+        }
+        .unwrap_or_else(|| {
+            let ip = crate::forwarded::client_ip(&req).map(|ip| ip.to_string());
+            format!("ip:{}", ip.unwrap_or_default())
+        });
+        let key = format!("{}:{}", opid, client);
+        let store = self.store.clone();
+        let service = self.service.clone();
+        async move {
+            match store.acquire(&key, &limit).await {
+                Ok(()) => {
+                    // don't hold the borrow across the await
+                    let fut = service.borrow_mut().call(req);
+                    fut.await
+                }
+                Err(retry_after) => {
+                    // round up, so clients don't come back too early
+                    let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+                    let resp = HttpResponse::TooManyRequests()
+                        .header(RETRY_AFTER, secs.to_string())
+                        .finish();
+                    Ok(req.into_response(resp))
+                }
+            }
+        }
+        .boxed_local()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_bucket() {
+        let store = InMemoryStore::default();
+        let limit = RateLimit::new(2, Duration::from_secs(10), RateLimitKey::Ip);
+        let start = Instant::now();
+        assert!(store.take("a", &limit, start).is_ok());
+        assert!(store.take("a", &limit, start).is_ok());
+        let wait = store.take("a", &limit, start).unwrap_err();
+        assert_eq!(wait, Duration::from_secs(5));
+        // other clients have their own bucket
+        assert!(store.take("b", &limit, start).is_ok());
+        // refilled
+        assert!(store
+            .take("a", &limit, start + Duration::from_secs(5))
+            .is_ok());
+        assert!(store
+            .take("a", &limit, start + Duration::from_secs(5))
+            .is_err());
+    }
+
+    #[test]
+    fn empty_limits() {
+        let store = InMemoryStore::default();
+        let start = Instant::now();
+        let never = RateLimit {
+            requests: 0,
+            per: Duration::from_secs(10),
+            key: RateLimitKey::Ip,
+        };
+        assert_eq!(store.take("a", &never, start), Err(Duration::from_secs(10)));
+        let instant = RateLimit {
+            requests: 0,
+            per: Duration::from_secs(0),
+            key: RateLimitKey::Ip,
+        };
+        assert!(store.take("b", &instant, start).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "A rate limit needs")]
+    fn no_requests() {
+        RateLimit::new(0, Duration::from_secs(1), RateLimitKey::Ip);
+    }
+
+    #[test]
+    #[should_panic(expected = "A rate limit needs")]
+    fn no_period() {
+        RateLimit::new(1, Duration::from_secs(0), RateLimitKey::Ip);
+    }
+
+    #[test]
+    fn refilled_buckets_are_forgotten() {
+        let store = InMemoryStore::default();
+        let limit = RateLimit::new(2, Duration::from_secs(60), RateLimitKey::Ip);
+        let start = Instant::now();
+        assert!(store.take("a", &limit, start).is_ok());
+        assert!(store.take("b", &limit, start).is_ok());
+        assert!(store.take("b", &limit, start).is_ok());
+        assert_eq!(store.len(), 2);
+
+        // "a" has refilled after 30s, "b" not until 60s
+        let later = start + Duration::from_secs(31);
+        assert!(store.take("c", &limit, later).is_ok());
+        assert_eq!(store.len(), 2);
+        // "b" is still limited
+        assert!(store.take("b", &limit, later).is_ok());
+        assert!(store.take("b", &limit, later).is_err());
+
+        let much_later = start + Duration::from_secs(600);
+        assert!(store.take("d", &limit, much_later).is_ok());
+        assert_eq!(store.len(), 1);
+    }
+}
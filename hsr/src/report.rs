@@ -0,0 +1,211 @@
+//! Reporting server errors to an error tracker (Sentry, Rollbar etc).
+//!
+//! Give `Config::error_reporter` an `ErrorReporter`, and it is told about every `5xx`
+//! response of an operation, with the request and, where hsr made the response
+//! itself, what went wrong: a panic in the handler, a timeout, or an error of the
+//! application turned into a response by the default `map_error`.
+//!
+//! ```ignore
+//! struct Sentry;
+//!
+//! impl hsr::report::ErrorReporter for Sentry {
+//!     fn report(&self, report: &hsr::report::ErrorReport) {
+//!         sentry::capture_message(&report.to_string(), sentry::Level::Error);
+//!     }
+//! }
+//! ```
+
+use std::fmt;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use actix_http::http::Method;
+use actix_service::{Service, Transform};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::{Error as ActixError, HttpResponse};
+use futures::future::{ok, FutureExt, LocalBoxFuture, Ready};
+
+use crate::request_id::RequestId;
+
+/// What went wrong in an operation, as recorded on its response by the dispatcher
+#[derive(Debug, Clone, PartialEq)]
+pub enum Failure {
+    /// The handler panicked, with this message
+    Panic(String),
+    /// The handler didn't finish within the timeout of the operation
+    Timeout(Duration),
+    /// The application returned an error (in its `Debug` form)
+    Error(String),
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Failure::Panic(message) => write!(f, "panicked: {}", message),
+            Failure::Timeout(timeout) => write!(f, "timed out after {:?}", timeout),
+            Failure::Error(error) => write!(f, "failed: {}", error),
+        }
+    }
+}
+
+/// Record on a response what went wrong, for the error reporter
+pub fn record(resp: &mut HttpResponse, failure: Failure) {
+    resp.extensions_mut().insert(failure);
+}
+
+/// A `5xx` response of an operation
+#[derive(Debug, Clone)]
+pub struct ErrorReport {
+    pub operation_id: String,
+    pub method: Method,
+    /// The path and query of the request
+    pub uri: String,
+    pub request_id: Option<RequestId>,
+    pub status: u16,
+    /// What went wrong, if hsr made the response rather than the handler
+    pub failure: Option<Failure>,
+}
+
+impl fmt::Display for ErrorReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Operation '{}' ({} {}) answered {}",
+            self.operation_id, self.method, self.uri, self.status
+        )?;
+        if let Some(failure) = &self.failure {
+            write!(f, ", having {}", failure)?;
+        }
+        Ok(())
+    }
+}
+
+/// Told about the server errors of an API
+pub trait ErrorReporter: Send + Sync {
+    fn report(&self, report: &ErrorReport);
+}
+
+/// Middleware which reports the `5xx` responses of a resource, if there is a reporter
+#[derive(Clone, Default)]
+pub struct ErrorReports {
+    reporter: Option<Arc<dyn ErrorReporter>>,
+    operations: Arc<Vec<(Method, String)>>,
+}
+
+impl ErrorReports {
+    /// Report the errors of a resource, with the operation id of each method
+    pub fn new(reporter: Option<Arc<dyn ErrorReporter>>, operations: &[(Method, &str)]) -> Self {
+        Self {
+            reporter,
+            operations: Arc::new(
+                operations
+                    .iter()
+                    .map(|(method, opid)| (method.clone(), opid.to_string()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl<S, B> Transform<S> for ErrorReports
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = ActixError>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = ActixError;
+    type InitError = ();
+    type Transform = ErrorReportsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(ErrorReportsMiddleware {
+            service,
+            config: self.clone(),
+        })
+    }
+}
+
+pub struct ErrorReportsMiddleware<S> {
+    service: S,
+    config: ErrorReports,
+}
+
+impl<S, B> Service for ErrorReportsMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = ActixError>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = ActixError;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let reporter = match &self.config.reporter {
+            Some(reporter) => reporter.clone(),
+            None => return self.service.call(req).boxed_local(),
+        };
+        let operation_id = self
+            .config
+            .operations
+            .iter()
+            .find(|(method, _)| method == req.method())
+            .map(|(_, opid)| opid.clone())
+            .unwrap_or_default();
+        let method = req.method().clone();
+        let uri = req.uri().to_string();
+        let fut = self.service.call(req);
+        async move {
+            let res = fut.await?;
+            let status = res.status();
+            if status.is_server_error() {
+                let report = ErrorReport {
+                    operation_id,
+                    method,
+                    uri,
+                    request_id: res.request().extensions().get::<RequestId>().cloned(),
+                    status: status.as_u16(),
+                    failure: res.response().extensions().get::<Failure>().cloned(),
+                };
+                reporter.report(&report);
+            }
+            Ok(res)
+        }
+        .boxed_local()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports() {
+        let mut resp = HttpResponse::InternalServerError().finish();
+        record(&mut resp, Failure::Panic("oops".into()));
+        let failure = resp.extensions().get::<Failure>().cloned();
+        assert_eq!(failure, Some(Failure::Panic("oops".into())));
+
+        let report = ErrorReport {
+            operation_id: "get_pet".into(),
+            method: Method::GET,
+            uri: "/pets/1".into(),
+            request_id: None,
+            status: 500,
+            failure,
+        };
+        assert_eq!(
+            report.to_string(),
+            "Operation 'get_pet' (GET /pets/1) answered 500, having panicked: oops"
+        );
+    }
+}
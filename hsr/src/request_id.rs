@@ -0,0 +1,129 @@
+//! Request ids, for tracing requests across services.
+//!
+//! The `RequestIds` middleware (installed by the generated server) takes the id of
+//! each request from its `X-Request-Id` header, or assigns a new one, and echoes it
+//! back in the response. Handlers can find it with `RequestContext::request_id`, and
+//! pass it on to other services with the generated `Client::with_request_id`.
+
+use std::fmt;
+use std::task::{Context, Poll};
+
+use actix_http::http::{HeaderName, HeaderValue};
+use actix_service::{Service, Transform};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::{Error as ActixError, HttpMessage};
+use futures::future::{ok, FutureExt, LocalBoxFuture, Ready};
+
+/// The header holding the id of a request, if any
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Incoming ids longer than this are replaced
+const MAX_LEN: usize = 128;
+
+/// The id of a request
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RequestId(String);
+
+impl RequestId {
+    /// A new, random id
+    pub fn generate() -> Self {
+        RequestId(uuid::Uuid::new_v4().to_string())
+    }
+
+    /// Accept an id supplied by a client, if it is reasonable
+    pub fn parse(id: &str) -> Option<Self> {
+        let valid =
+            !id.is_empty() && id.len() <= MAX_LEN && id.bytes().all(|b| b.is_ascii_graphic());
+        if valid {
+            Some(RequestId(id.to_string()))
+        } else {
+            None
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Middleware which assigns each request an id, stored in the request extensions
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RequestIds;
+
+impl<S, B> Transform<S> for RequestIds
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = ActixError>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = ActixError;
+    type InitError = ();
+    type Transform = RequestIdsMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(RequestIdsMiddleware { service })
+    }
+}
+
+pub struct RequestIdsMiddleware<S> {
+    service: S,
+}
+
+impl<S, B> Service for RequestIdsMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = ActixError>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = ActixError;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let id = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|id| id.to_str().ok())
+            .and_then(RequestId::parse)
+            .unwrap_or_else(RequestId::generate);
+        req.extensions_mut().insert(id.clone());
+        let fut = self.service.call(req);
+        async move {
+            let mut res = fut.await?;
+            if let Ok(value) = HeaderValue::from_str(id.as_str()) {
+                res.headers_mut()
+                    .insert(HeaderName::from_static(REQUEST_ID_HEADER), value);
+            }
+            Ok(res)
+        }
+        .boxed_local()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_request_id() {
+        assert_eq!(RequestId::parse("abc-123").unwrap().as_str(), "abc-123");
+        assert!(RequestId::parse("").is_none());
+        assert!(RequestId::parse("has space").is_none());
+        assert!(RequestId::parse(&"x".repeat(MAX_LEN + 1)).is_none());
+        assert_ne!(RequestId::generate(), RequestId::generate());
+    }
+}
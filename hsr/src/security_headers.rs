@@ -0,0 +1,210 @@
+//! Response headers asking browsers for safer behaviour.
+//!
+//! With `Config::security_headers`, the `AddSecurityHeaders` middleware (installed by
+//! the generated server) sets `X-Content-Type-Options`, `X-Frame-Options` and
+//! `Referrer-Policy` on every response, and a `Content-Security-Policy` on the
+//! swagger UI page (`/ui.html`). A header the handler has set itself is left alone.
+
+use std::task::{Context, Poll};
+
+use actix_http::http::header::{
+    HeaderName, HeaderValue, CONTENT_SECURITY_POLICY, REFERRER_POLICY, X_CONTENT_TYPE_OPTIONS,
+    X_FRAME_OPTIONS,
+};
+use actix_service::{Service, Transform};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::Error as ActixError;
+use futures::future::{ok, FutureExt, LocalBoxFuture, Ready};
+
+/// The `Content-Security-Policy` which the generated swagger UI page works with: its
+/// script and styles come from cdnjs, and it fetches the spec from the server
+pub const UI_CSP: &str = "default-src 'none'; \
+    script-src https://cdnjs.cloudflare.com 'unsafe-inline'; \
+    style-src https://cdnjs.cloudflare.com 'unsafe-inline'; \
+    img-src 'self' data:; \
+    connect-src 'self'";
+
+/// The security headers of the responses. The default sets them all.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SecurityHeaders {
+    /// Set `X-Content-Type-Options: nosniff`, so browsers don't second-guess the
+    /// content type of a response
+    pub nosniff: bool,
+    /// The `X-Frame-Options`, `DENY` by default
+    pub frame_options: Option<String>,
+    /// The `Referrer-Policy`, `no-referrer` by default
+    pub referrer_policy: Option<String>,
+    /// The `Content-Security-Policy` of the swagger UI page, `UI_CSP` by default
+    pub ui_csp: Option<String>,
+}
+
+impl Default for SecurityHeaders {
+    fn default() -> Self {
+        Self {
+            nosniff: true,
+            frame_options: Some("DENY".into()),
+            referrer_policy: Some("no-referrer".into()),
+            ui_csp: Some(UI_CSP.into()),
+        }
+    }
+}
+
+impl SecurityHeaders {
+    /// The headers of every response, failing on a value which isn't a valid header
+    pub fn headers(&self) -> Result<Vec<(HeaderName, HeaderValue)>, String> {
+        let mut headers = Vec::new();
+        if self.nosniff {
+            headers.push((X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff")));
+        }
+        if let Some(value) = &self.frame_options {
+            headers.push((X_FRAME_OPTIONS, header_value(value)?));
+        }
+        if let Some(value) = &self.referrer_policy {
+            headers.push((REFERRER_POLICY, header_value(value)?));
+        }
+        Ok(headers)
+    }
+}
+
+fn header_value(value: &str) -> Result<HeaderValue, String> {
+    HeaderValue::from_str(value).map_err(|_| format!("Bad header value '{}'", value))
+}
+
+/// Middleware which sets the security headers on the responses, if there is a policy
+#[derive(Debug, Clone, Default)]
+pub struct AddSecurityHeaders {
+    policy: Option<SecurityHeaders>,
+}
+
+impl AddSecurityHeaders {
+    pub fn new(policy: Option<SecurityHeaders>) -> Self {
+        Self { policy }
+    }
+}
+
+impl<S, B> Transform<S> for AddSecurityHeaders
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = ActixError>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = ActixError;
+    type InitError = ();
+    type Transform = AddSecurityHeadersMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        let (headers, ui_csp) = match &self.policy {
+            Some(policy) => {
+                let headers = policy.headers().unwrap_or_else(|e| {
+                    log::error!("Not setting security headers: {}", e);
+                    Vec::new()
+                });
+                let ui_csp = policy
+                    .ui_csp
+                    .as_ref()
+                    .and_then(|csp| header_value(csp).ok());
+                (headers, ui_csp)
+            }
+            None => (Vec::new(), None),
+        };
+        ok(AddSecurityHeadersMiddleware {
+            service,
+            headers,
+            ui_csp,
+        })
+    }
+}
+
+pub struct AddSecurityHeadersMiddleware<S> {
+    service: S,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    ui_csp: Option<HeaderValue>,
+}
+
+impl<S, B> Service for AddSecurityHeadersMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = ActixError>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = ActixError;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let mut headers = self.headers.clone();
+        // the UI may be mounted under a prefix
+        if req.path().ends_with("/ui.html") {
+            if let Some(csp) = &self.ui_csp {
+                headers.push((CONTENT_SECURITY_POLICY, csp.clone()));
+            }
+        }
+        let fut = self.service.call(req);
+        async move {
+            let mut res = fut.await?;
+            for (name, value) in headers {
+                if !res.headers().contains_key(&name) {
+                    res.headers_mut().insert(name, value);
+                }
+            }
+            Ok(res)
+        }
+        .boxed_local()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, web, App, HttpResponse};
+
+    #[actix_rt::test]
+    async fn security_headers() {
+        let policy = SecurityHeaders {
+            referrer_policy: None,
+            ..Default::default()
+        };
+        let mut app = test::init_service(
+            App::new()
+                .wrap(AddSecurityHeaders::new(Some(policy)))
+                .route("/ui.html", web::get().to(HttpResponse::Ok))
+                .route(
+                    "/framed",
+                    web::get().to(|| {
+                        HttpResponse::Ok()
+                            .header(X_FRAME_OPTIONS, "SAMEORIGIN")
+                            .finish()
+                    }),
+                ),
+        )
+        .await;
+
+        let req = test::TestRequest::get().uri("/ui.html").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        let headers = resp.headers();
+        assert_eq!(headers.get(X_CONTENT_TYPE_OPTIONS).unwrap(), "nosniff");
+        assert_eq!(headers.get(X_FRAME_OPTIONS).unwrap(), "DENY");
+        assert!(headers.get(REFERRER_POLICY).is_none());
+        assert_eq!(headers.get(CONTENT_SECURITY_POLICY).unwrap(), UI_CSP);
+
+        // the handler has the last word
+        let req = test::TestRequest::get().uri("/framed").to_request();
+        let resp = test::call_service(&mut app, req).await;
+        assert_eq!(resp.headers().get(X_FRAME_OPTIONS).unwrap(), "SAMEORIGIN");
+        assert!(resp.headers().get(CONTENT_SECURITY_POLICY).is_none());
+
+        let bad = SecurityHeaders {
+            frame_options: Some("DENY\n".into()),
+            ..Default::default()
+        };
+        assert!(bad.headers().is_err());
+    }
+}
@@ -0,0 +1,226 @@
+//! The spec served by the generated server, at `/spec.json`.
+//!
+//! By default the spec is embedded in the binary as a string. For very large specs
+//! it can instead be embedded gzipped, read from a file at runtime, or left out
+//! altogether (in which case neither `/spec.json` nor `/ui.html` are served, and
+//! response validation is unavailable). See `GenerationOptions::spec` of `hsr-codegen`.
+//!
+//! Compressed and external specs are loaded the first time they are needed.
+//!
+//! The spec and the ui are only compiled in with the `ui` feature (on by default).
+//! Without it, the generated code leaves out the ui template, and the spec as with
+//! `SpecSource::Omitted`, for slimmer binaries.
+//!
+//! The generated code also has the title, version and base path of the spec as
+//! constants, and (with `GenerationOptions::version_route`) serves them at `/version`
+//! as an `ApiInfo`, so that a deployment can tell which contract it serves.
+
+use std::io::Read;
+use std::rc::Rc;
+
+use actix_web::HttpResponse;
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::validate::{load_spec, ResponseValidation};
+
+/// Where the spec comes from
+#[derive(Debug, Clone, Copy)]
+pub enum SpecSource {
+    /// The spec itself, as JSON
+    Plain(&'static str),
+    /// The spec as gzipped JSON
+    Gzip(&'static [u8]),
+    /// A file holding the spec as JSON, read at runtime
+    File(&'static str),
+    /// No spec
+    Omitted,
+}
+
+/// The spec of a generated API
+#[derive(Debug)]
+pub struct EmbeddedSpec {
+    source: SpecSource,
+    loaded: OnceCell<Option<&'static str>>,
+}
+
+impl EmbeddedSpec {
+    pub const fn new(source: SpecSource) -> Self {
+        Self {
+            source,
+            loaded: OnceCell::new(),
+        }
+    }
+
+    /// The spec as JSON, if there is one. A spec which fails to load is logged,
+    /// and treated as missing.
+    pub fn get(&self) -> Option<&'static str> {
+        *self.loaded.get_or_init(|| match self.source {
+            SpecSource::Plain(spec) => Some(spec),
+            SpecSource::Omitted => None,
+            source => match load(source) {
+                Ok(spec) => Some(Box::leak(spec.into_boxed_str())),
+                Err(e) => {
+                    log::error!("Failed to load the spec from {:?}: {}", source, e);
+                    None
+                }
+            },
+        })
+    }
+
+    /// Serve the spec at `/spec.json`, and the ui at `/ui.html`, if there is a spec
+    #[cfg(feature = "ui")]
+    pub fn configure(&self, cfg: &mut actix_web::web::ServiceConfig, ui: &'static str) {
+        if let Some(spec) = self.get() {
+            crate::configure_spec(cfg, spec, ui)
+        }
+    }
+
+    /// The spec to check responses against. Without a spec (or with one which isn't
+    /// valid JSON), responses are not checked.
+    pub fn for_validation(&self, mode: ResponseValidation) -> Rc<Value> {
+        let off = || Rc::new(Value::Null);
+        match (self.get(), mode) {
+            (_, ResponseValidation::Off) => off(),
+            (Some(spec), _) => load_spec(spec, mode).unwrap_or_else(|e| {
+                log::error!(
+                    "Response validation is disabled, as the spec is invalid: {}",
+                    e
+                );
+                off()
+            }),
+            (None, _) => {
+                log::warn!("Response validation is disabled, as there is no spec");
+                off()
+            }
+        }
+    }
+}
+
+/// The items (or statements) given, with the `ui` feature, else nothing. Used by the
+/// generated code for the ui.
+#[cfg(feature = "ui")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! if_ui {
+    ($($tt:tt)*) => { $($tt)* };
+}
+
+/// The items (or statements) given, with the `ui` feature, else nothing. Used by the
+/// generated code for the ui.
+#[cfg(not(feature = "ui"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! if_ui {
+    ($($tt:tt)*) => {};
+}
+
+/// The `SpecSource` given, with the `ui` feature, else `SpecSource::Omitted` (so that
+/// the spec isn't compiled in at all). Used by the generated code for `JSON_SPEC`.
+#[cfg(feature = "ui")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! spec_source {
+    ($source:expr) => {
+        $source
+    };
+}
+
+/// The `SpecSource` given, with the `ui` feature, else `SpecSource::Omitted` (so that
+/// the spec isn't compiled in at all). Used by the generated code for `JSON_SPEC`.
+#[cfg(not(feature = "ui"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! spec_source {
+    ($source:expr) => {
+        $crate::spec::SpecSource::Omitted
+    };
+}
+
+/// Which API (and version of it) is served
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ApiInfo {
+    /// `info.title` of the spec
+    pub title: &'static str,
+    /// `info.version` of the spec
+    pub version: &'static str,
+    /// The path of the first server of the spec (empty if there is none)
+    pub base_path: &'static str,
+}
+
+impl ApiInfo {
+    /// Serve the info at `/version`, as JSON
+    pub fn configure(&'static self, cfg: &mut actix_web::web::ServiceConfig) {
+        cfg.route(
+            "/version",
+            actix_web::web::get().to(move || HttpResponse::Ok().json(self)),
+        );
+    }
+}
+
+fn load(source: SpecSource) -> std::io::Result<String> {
+    let mut spec = String::new();
+    match source {
+        SpecSource::Gzip(bytes) => {
+            flate2::read::GzDecoder::new(bytes).read_to_string(&mut spec)?;
+        }
+        SpecSource::File(path) => {
+            spec = std::fs::read_to_string(path)?;
+        }
+        SpecSource::Plain(plain) => spec.push_str(plain),
+        SpecSource::Omitted => {}
+    }
+    Ok(spec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn load_specs() {
+        let json = r#"{"openapi":"3.0.0"}"#;
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        encoder.write_all(json.as_bytes()).unwrap();
+        let gzipped: &'static [u8] = Box::leak(encoder.finish().unwrap().into_boxed_slice());
+
+        assert_eq!(EmbeddedSpec::new(SpecSource::Plain(json)).get(), Some(json));
+        assert_eq!(
+            EmbeddedSpec::new(SpecSource::Gzip(gzipped)).get(),
+            Some(json)
+        );
+        assert_eq!(EmbeddedSpec::new(SpecSource::Omitted).get(), None);
+        assert_eq!(
+            EmbeddedSpec::new(SpecSource::File("/no/such/spec.json")).get(),
+            None
+        );
+        let spec = EmbeddedSpec::new(SpecSource::Omitted);
+        assert_eq!(*spec.for_validation(ResponseValidation::Fail), Value::Null);
+        let spec = EmbeddedSpec::new(SpecSource::Plain("{"));
+        assert_eq!(*spec.for_validation(ResponseValidation::Fail), Value::Null);
+        let spec = EmbeddedSpec::new(SpecSource::Plain(json));
+        assert_eq!(
+            spec.for_validation(ResponseValidation::Fail)["openapi"],
+            "3.0.0"
+        );
+
+        // the spec is left out along with the ui
+        let spec = EmbeddedSpec::new(crate::spec_source!(SpecSource::Plain(json)));
+        assert_eq!(spec.get().is_some(), cfg!(feature = "ui"));
+    }
+
+    #[test]
+    fn api_info() {
+        let info = ApiInfo {
+            title: "Pets",
+            version: "1.2.0",
+            base_path: "/v1",
+        };
+        assert_eq!(
+            serde_json::to_value(info).unwrap(),
+            serde_json::json!({"title": "Pets", "version": "1.2.0", "base_path": "/v1"})
+        );
+    }
+}
@@ -0,0 +1,216 @@
+//! Background tasks, run alongside the server: periodic jobs (flushing metrics,
+//! expiring sessions) and delayed ones (warming a cache once the server is up).
+//!
+//! An API registers its tasks in the `schedule` method of the API trait, and
+//! `server::serve` runs them on the actix system while the server is running. When
+//! the server shuts down, no more runs are started, and `serve` waits for those in
+//! progress to finish before calling `on_shutdown`.
+//!
+//! ```ignore
+//! fn schedule(&self, tasks: &mut hsr::tasks::Tasks) {
+//!     let sessions = self.sessions.clone();
+//!     tasks.every("expire sessions", Duration::from_secs(60), move || {
+//!         let sessions = sessions.clone();
+//!         async move { sessions.expire().await }
+//!     });
+//! }
+//! ```
+
+use std::future::Future;
+use std::time::Duration;
+
+use futures::channel::oneshot;
+use futures::future::{self, Either, FutureExt, LocalBoxFuture, Shared};
+
+enum Job {
+    Every {
+        period: Duration,
+        run: Box<dyn Fn() -> LocalBoxFuture<'static, ()>>,
+    },
+    After {
+        delay: Duration,
+        run: Box<dyn FnOnce() -> LocalBoxFuture<'static, ()>>,
+    },
+}
+
+struct Task {
+    name: String,
+    job: Job,
+}
+
+/// The background tasks of an API, to be started with the server
+#[derive(Default)]
+pub struct Tasks {
+    tasks: Vec<Task>,
+}
+
+impl Tasks {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Run `job` every `period`, counting from the end of the previous run (so runs
+    /// never overlap). The first run is one `period` after the server starts.
+    pub fn every<F, Fut>(&mut self, name: impl Into<String>, period: Duration, job: F) -> &mut Self
+    where
+        F: Fn() -> Fut + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        self.tasks.push(Task {
+            name: name.into(),
+            job: Job::Every {
+                period,
+                run: Box::new(move || job().boxed_local()),
+            },
+        });
+        self
+    }
+
+    /// Run `job` once, `delay` after the server starts
+    pub fn after<F, Fut>(&mut self, name: impl Into<String>, delay: Duration, job: F) -> &mut Self
+    where
+        F: FnOnce() -> Fut + 'static,
+        Fut: Future<Output = ()> + 'static,
+    {
+        self.tasks.push(Task {
+            name: name.into(),
+            job: Job::After {
+                delay,
+                run: Box::new(move || job().boxed_local()),
+            },
+        });
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.tasks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tasks.is_empty()
+    }
+
+    /// Start running the tasks on the current actix system
+    pub fn start(self) -> Running {
+        let (stop, stopped) = oneshot::channel::<()>();
+        let stopped = stopped.shared();
+        let done = self
+            .tasks
+            .into_iter()
+            .map(|task| {
+                let (done, finished) = oneshot::channel();
+                let stopped = stopped.clone();
+                actix_rt::spawn(async move {
+                    run_task(task, stopped).await;
+                    let _ = done.send(());
+                });
+                finished
+            })
+            .collect();
+        Running { stop, done }
+    }
+}
+
+impl std::fmt::Debug for Tasks {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let names: Vec<_> = self.tasks.iter().map(|task| &task.name).collect();
+        f.debug_struct("Tasks").field("tasks", &names).finish()
+    }
+}
+
+/// Wait for `delay`, unless the tasks are stopped first. Gives whether to go on.
+async fn wait(delay: Duration, stopped: Shared<oneshot::Receiver<()>>) -> bool {
+    let delay = actix_rt::time::delay_for(delay);
+    match future::select(delay, stopped).await {
+        Either::Left(_) => true,
+        Either::Right(_) => false,
+    }
+}
+
+async fn run_task(task: Task, stopped: Shared<oneshot::Receiver<()>>) {
+    let Task { name, job } = task;
+    match job {
+        Job::Every { period, run } => {
+            while wait(period, stopped.clone()).await {
+                log::debug!("Running task '{}'", name);
+                run().await;
+            }
+        }
+        Job::After { delay, run } => {
+            if wait(delay, stopped).await {
+                log::debug!("Running task '{}'", name);
+                run().await;
+            }
+        }
+    }
+}
+
+/// Tasks which have been started
+pub struct Running {
+    stop: oneshot::Sender<()>,
+    done: Vec<oneshot::Receiver<()>>,
+}
+
+impl Running {
+    /// Start no more runs, and wait for those in progress to finish
+    pub async fn stop(self) {
+        let _ = self.stop.send(());
+        // a task which panicked drops its sender, which also ends the wait
+        future::join_all(self.done).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[actix_rt::test]
+    async fn run_and_stop() {
+        let runs = Rc::new(Cell::new(0));
+        let delayed = Rc::new(Cell::new(false));
+        let never = Rc::new(Cell::new(false));
+        let mut tasks = Tasks::new();
+        let counter = runs.clone();
+        tasks.every("count", Duration::from_millis(10), move || {
+            counter.set(counter.get() + 1);
+            future::ready(())
+        });
+        let flag = delayed.clone();
+        tasks.after("delayed", Duration::from_millis(5), move || async move {
+            flag.set(true)
+        });
+        let flag = never.clone();
+        tasks.after("never", Duration::from_secs(60), move || async move {
+            flag.set(true)
+        });
+        assert_eq!(tasks.len(), 3);
+
+        let running = tasks.start();
+        actix_rt::time::delay_for(Duration::from_millis(55)).await;
+        running.stop().await;
+        let counted = runs.get();
+        assert!(counted >= 2, "{}", counted);
+        assert!(delayed.get());
+        assert!(!never.get());
+
+        actix_rt::time::delay_for(Duration::from_millis(30)).await;
+        assert_eq!(runs.get(), counted);
+    }
+
+    #[actix_rt::test]
+    async fn graceful_stop() {
+        let finished = Rc::new(Cell::new(false));
+        let mut tasks = Tasks::new();
+        let flag = finished.clone();
+        tasks.after("slow", Duration::from_millis(1), move || async move {
+            actix_rt::time::delay_for(Duration::from_millis(30)).await;
+            flag.set(true)
+        });
+        let running = tasks.start();
+        actix_rt::time::delay_for(Duration::from_millis(10)).await;
+        running.stop().await;
+        assert!(finished.get());
+    }
+}
@@ -0,0 +1,470 @@
+//! Check JSON values against the schemas of a spec.
+//!
+//! This only covers the parts of JSON schema which `hsr` itself understands:
+//! types, nullability, required properties, enums and the `allOf`/`oneOf`/`anyOf`
+//! combinators. It is intended for catching drift between an implementation and
+//! its spec, not for full JSON schema validation.
+
+use std::fmt;
+use std::rc::Rc;
+use std::task::{Context, Poll};
+
+use actix_service::{Service, Transform};
+use actix_web::dev::{Body, ResponseBody, ServiceRequest, ServiceResponse};
+use actix_web::{Error as ActixError, HttpResponse};
+use futures::future::{ok, FutureExt, LocalBoxFuture, Ready};
+use serde_json::Value;
+
+use crate::mock::resolve;
+use crate::negotiate::is_media_type;
+
+/// A place where a value does not conform to its schema
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    /// JSON pointer to the offending part of the value
+    pub pointer: String,
+    pub message: String,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let pointer = if self.pointer.is_empty() {
+            "/"
+        } else {
+            &self.pointer
+        };
+        write!(f, "{}: {}", pointer, self.message)
+    }
+}
+
+/// Validate `value` against `schema`. References are looked up in `spec`.
+pub fn validate(value: &Value, schema: &Value, spec: &Value) -> Result<(), Vec<Mismatch>> {
+    let mut mismatches = Vec::new();
+    validate_at(value, schema, spec, "", &mut mismatches);
+    if mismatches.is_empty() {
+        Ok(())
+    } else {
+        Err(mismatches)
+    }
+}
+
+fn validate_at(
+    value: &Value,
+    schema: &Value,
+    spec: &Value,
+    pointer: &str,
+    mismatches: &mut Vec<Mismatch>,
+) {
+    let schema = resolve(schema, spec);
+    let mut mismatch = |message: String| {
+        mismatches.push(Mismatch {
+            pointer: pointer.to_string(),
+            message,
+        })
+    };
+
+    if value.is_null() {
+        let nullable = schema
+            .get("nullable")
+            .and_then(Value::as_bool)
+            .unwrap_or(false);
+        if !nullable && schema.get("type").is_some() {
+            mismatch("unexpected null".into())
+        }
+        return;
+    }
+
+    if let Some(variants) = schema.get("enum").and_then(Value::as_array) {
+        if !variants.contains(value) {
+            mismatch(format!("{} is not one of {:?}", value, variants))
+        }
+    }
+
+    if let Some(parts) = schema.get("allOf").and_then(Value::as_array) {
+        for part in parts {
+            validate_at(value, part, spec, pointer, mismatches)
+        }
+        return;
+    }
+
+    for key in &["oneOf", "anyOf"] {
+        if let Some(variants) = schema.get(*key).and_then(Value::as_array) {
+            let matched = variants
+                .iter()
+                .any(|variant| validate(value, variant, spec).is_ok());
+            if !matched {
+                mismatch(format!("does not match any '{}' variant", key))
+            }
+            return;
+        }
+    }
+
+    let typ = schema.get("type").and_then(Value::as_str).or_else(|| {
+        // untyped, but has properties - assume an object
+        schema.get("properties").map(|_| "object")
+    });
+    let ok = match typ {
+        Some("string") => value.is_string(),
+        Some("integer") => value.is_i64() || value.is_u64(),
+        Some("number") => value.is_number(),
+        Some("boolean") => value.is_boolean(),
+        Some("array") => value.is_array(),
+        Some("object") => value.is_object(),
+        _ => true,
+    };
+    if !ok {
+        mismatch(format!("expected {}, found {}", typ.unwrap(), value));
+        return;
+    }
+
+    match value {
+        Value::Array(items) => {
+            if let Some(item_schema) = schema.get("items") {
+                for (ix, item) in items.iter().enumerate() {
+                    let pointer = format!("{}/{}", pointer, ix);
+                    validate_at(item, item_schema, spec, &pointer, mismatches)
+                }
+            }
+        }
+        Value::Object(obj) => {
+            if let Some(required) = schema.get("required").and_then(Value::as_array) {
+                for field in required.iter().filter_map(Value::as_str) {
+                    if !obj.contains_key(field) {
+                        mismatches.push(Mismatch {
+                            pointer: pointer.to_string(),
+                            message: format!("missing required field '{}'", field),
+                        })
+                    }
+                }
+            }
+            if let Some(props) = schema.get("properties").and_then(Value::as_object) {
+                for (field, val) in obj {
+                    if let Some(prop_schema) = props.get(field) {
+                        let pointer = format!("{}/{}", pointer, escape_token(field));
+                        validate_at(val, prop_schema, spec, &pointer, mismatches)
+                    }
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Escape a property name for use in a JSON pointer (RFC 6901)
+fn escape_token(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+/// Check a response against the responses declared for the operation
+pub fn check_response(
+    status: u16,
+    content_type: Option<&str>,
+    body: &[u8],
+    operation: &Value,
+    spec: &Value,
+) -> Vec<String> {
+    let mut problems = Vec::new();
+    let responses = operation.get("responses");
+    let declared = responses
+        .and_then(|r| r.get(status.to_string()))
+        .or_else(|| responses.and_then(|r| r.get("default")));
+    let declared = match declared {
+        Some(declared) => resolve(declared, spec),
+        None => {
+            problems.push(format!("undeclared status code {}", status));
+            return problems;
+        }
+    };
+    let content = match declared.get("content").and_then(Value::as_object) {
+        Some(content) if !content.is_empty() => content,
+        // No body expected, nothing else to check
+        _ => return problems,
+    };
+    // Problem documents stand in for JSON bodies
+    let content_type = content_type.unwrap_or_default();
+    let is_problem = crate::Problem::is_problem_content_type(content_type);
+    let declared_media = content.iter().find(|(media_type, _)| {
+        is_media_type(content_type, media_type) || (is_problem && *media_type == "application/json")
+    });
+    let (media_type, media) = match declared_media {
+        Some(media) => media,
+        None => {
+            let expected: Vec<_> = content.keys().map(|mt| format!("'{}'", mt)).collect();
+            problems.push(format!(
+                "expected content type {}, found {:?}",
+                expected.join(" or "),
+                content_type
+            ));
+            return problems;
+        }
+    };
+    // Only JSON bodies have a schema we can check
+    if media_type != "application/json" && !media_type.ends_with("+json") {
+        return problems;
+    }
+    let value: Value = match serde_json::from_slice(body) {
+        Ok(value) => value,
+        Err(e) => {
+            problems.push(format!("bad json body: {}", e));
+            return problems;
+        }
+    };
+    if let Some(schema) = media.get("schema") {
+        if let Err(mismatches) = validate(&value, schema, spec) {
+            problems.extend(mismatches.iter().map(ToString::to_string))
+        }
+    }
+    problems
+}
+
+/// How the generated server should check its own responses against the spec
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseValidation {
+    /// Don't check responses (the default)
+    Off,
+    /// Log responses which do not conform to the spec
+    Log,
+    /// As `Log`, and also replace the response with a `500 Internal Server Error`
+    Fail,
+}
+
+impl Default for ResponseValidation {
+    fn default() -> Self {
+        ResponseValidation::Off
+    }
+}
+
+impl std::str::FromStr for ResponseValidation {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "off" => Ok(ResponseValidation::Off),
+            "log" => Ok(ResponseValidation::Log),
+            "fail" => Ok(ResponseValidation::Fail),
+            other => Err(format!(
+                "unknown response validation mode '{}' (expected off, log or fail)",
+                other
+            )),
+        }
+    }
+}
+
+/// Parse the spec, if it will be needed for validating responses
+pub fn load_spec(spec: &str, mode: ResponseValidation) -> serde_json::Result<Rc<Value>> {
+    match mode {
+        ResponseValidation::Off => Ok(Rc::new(Value::Null)),
+        _ => Ok(Rc::new(serde_json::from_str(spec)?)),
+    }
+}
+
+/// Middleware which checks the responses of a resource against the spec.
+///
+/// This is intended to catch drift between handlers and the spec during
+/// development. It has a runtime cost, so is best left off in production.
+pub struct ValidateResponses {
+    spec: Rc<Value>,
+    path: Rc<String>,
+    mode: ResponseValidation,
+}
+
+impl ValidateResponses {
+    /// Check responses of the resource found at `path` in the spec
+    pub fn new(spec: &Rc<Value>, path: &str, mode: ResponseValidation) -> Self {
+        Self {
+            spec: spec.clone(),
+            path: Rc::new(path.to_string()),
+            mode,
+        }
+    }
+}
+
+impl<S> Transform<S> for ValidateResponses
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = ActixError>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = ActixError;
+    type InitError = ();
+    type Transform = ValidateResponsesMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(ValidateResponsesMiddleware {
+            service,
+            spec: self.spec.clone(),
+            path: self.path.clone(),
+            mode: self.mode,
+        })
+    }
+}
+
+pub struct ValidateResponsesMiddleware<S> {
+    service: S,
+    spec: Rc<Value>,
+    path: Rc<String>,
+    mode: ResponseValidation,
+}
+
+impl<S> Service for ValidateResponsesMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = ActixError>,
+    S::Future: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse;
+    type Error = ActixError;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let method = req.method().as_str().to_lowercase();
+        let spec = self.spec.clone();
+        let path = self.path.clone();
+        let mode = self.mode;
+        let fut = self.service.call(req);
+        async move {
+            let res = fut.await?;
+            let operation = match spec
+                .get("paths")
+                .and_then(|paths| paths.get(path.as_str()))
+                .and_then(|item| item.get(&method))
+            {
+                Some(operation) => operation,
+                None => return Ok(res),
+            };
+            let body: &[u8] = match res.response().body() {
+                ResponseBody::Body(Body::Bytes(bytes))
+                | ResponseBody::Other(Body::Bytes(bytes)) => bytes,
+                ResponseBody::Body(Body::None)
+                | ResponseBody::Body(Body::Empty)
+                | ResponseBody::Other(Body::None)
+                | ResponseBody::Other(Body::Empty) => b"",
+                // Streaming bodies can't be checked
+                _ => return Ok(res),
+            };
+            let content_type = res
+                .headers()
+                .get(actix_http::http::header::CONTENT_TYPE)
+                .and_then(|ct| ct.to_str().ok());
+            let problems =
+                check_response(res.status().as_u16(), content_type, body, operation, &spec);
+            if problems.is_empty() {
+                return Ok(res);
+            }
+            for problem in &problems {
+                log::error!(
+                    "Response to {} {} does not conform to spec: {}",
+                    method.to_uppercase(),
+                    path,
+                    problem
+                );
+            }
+            match mode {
+                ResponseValidation::Fail => {
+                    let body = format!(
+                        "Response does not conform to spec:\n{}",
+                        problems.join("\n")
+                    );
+                    Ok(res.into_response(HttpResponse::InternalServerError().body(body)))
+                }
+                _ => Ok(res),
+            }
+        }
+        .boxed_local()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_validate() {
+        let spec = json!({
+            "components": {
+                "schemas": {
+                    "Pet": {
+                        "required": ["id", "name"],
+                        "properties": {
+                            "id": { "type": "integer" },
+                            "name": { "type": "string" },
+                            "tag": { "type": "string", "nullable": true },
+                            "kind": { "type": "string", "enum": ["dog", "cat"] }
+                        }
+                    }
+                }
+            }
+        });
+        let schema = json!({
+            "type": "array",
+            "items": { "$ref": "#/components/schemas/Pet" }
+        });
+
+        let good = json!([{ "id": 1, "name": "Rex", "tag": null, "kind": "dog" }]);
+        assert!(validate(&good, &schema, &spec).is_ok());
+
+        let bad = json!([{ "id": "1", "kind": "fish" }]);
+        let mismatches = validate(&bad, &schema, &spec).unwrap_err();
+        let pointers: Vec<_> = mismatches.iter().map(|m| m.pointer.as_str()).collect();
+        assert_eq!(pointers, vec!["/0", "/0/id", "/0/kind"]);
+
+        // property names are escaped
+        let schema = json!({
+            "properties": {
+                "a/b": { "type": "string" },
+                "m~n": { "type": "string" }
+            }
+        });
+        let bad = json!({ "a/b": 1, "m~n": 2 });
+        let mismatches = validate(&bad, &schema, &spec).unwrap_err();
+        let pointers: Vec<_> = mismatches.iter().map(|m| m.pointer.as_str()).collect();
+        assert_eq!(pointers, vec!["/a~1b", "/m~0n"]);
+    }
+
+    #[test]
+    fn test_load_spec() {
+        assert_eq!(
+            *load_spec("not json", ResponseValidation::Off).unwrap(),
+            Value::Null
+        );
+        assert!(load_spec("not json", ResponseValidation::Log).is_err());
+        assert_eq!(
+            *load_spec("{}", ResponseValidation::Fail).unwrap(),
+            json!({})
+        );
+    }
+
+    #[test]
+    fn test_check_response() {
+        let op = json!({
+            "responses": {
+                "200": {
+                    "description": "Ok",
+                    "content": {
+                        "application/json": { "schema": { "type": "string" } },
+                        "text/csv": {}
+                    }
+                }
+            }
+        });
+        let spec = json!({});
+        assert!(check_response(200, Some("application/json"), b"\"hi\"", &op, &spec).is_empty());
+        assert_eq!(
+            check_response(200, Some("application/json"), b"1", &op, &spec).len(),
+            1
+        );
+        assert_eq!(check_response(404, None, b"", &op, &spec).len(), 1);
+        // other representations are not checked, but must be one of those declared
+        assert!(check_response(200, Some("text/csv"), b"1,2", &op, &spec).is_empty());
+        assert_eq!(
+            check_response(200, Some("text/html"), b"<p>", &op, &spec).len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,279 @@
+//! Contract testing. Exercise every operation of a spec against a live server,
+//! and check that the responses conform to the spec.
+//!
+//! Requests are built from the `example`s in the spec where possible, otherwise
+//! the inputs are synthesized from the schemas (see [`mock`](../mock/index.html)).
+
+use std::fmt;
+
+use actix_http::http::Method;
+use serde_json::Value;
+
+use crate::mock::{example_for_media_type, example_for_schema, resolve};
+use crate::validate::check_response;
+use crate::Url;
+
+/// The operations of a path item, by their field in it
+const METHODS: &[(&str, Method)] = &[
+    ("get", Method::GET),
+    ("put", Method::PUT),
+    ("post", Method::POST),
+    ("delete", Method::DELETE),
+    ("options", Method::OPTIONS),
+    ("head", Method::HEAD),
+    ("patch", Method::PATCH),
+    ("trace", Method::TRACE),
+];
+
+/// Responses bigger than this are rejected
+const BODY_LIMIT: usize = 10 * 1024 * 1024;
+
+/// The outcome of exercising a single operation
+#[derive(Debug, Clone)]
+pub struct OperationReport {
+    pub operation: String,
+    pub method: Method,
+    pub path: String,
+    /// Everything that did not conform to the spec. Empty if all is well.
+    pub problems: Vec<String>,
+}
+
+impl OperationReport {
+    pub fn passed(&self) -> bool {
+        self.problems.is_empty()
+    }
+}
+
+impl fmt::Display for OperationReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let verdict = if self.passed() { "ok" } else { "FAILED" };
+        writeln!(
+            f,
+            "{} {} ({}) ... {}",
+            self.method, self.path, self.operation, verdict
+        )?;
+        for problem in &self.problems {
+            writeln!(f, "    {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+/// Render an example value as it would appear in a url
+fn to_param(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn param_example(param: &Value, spec: &Value) -> Value {
+    if let Some(example) = param.get("example") {
+        return example.clone();
+    }
+    match param.get("schema") {
+        Some(schema) => example_for_schema(schema, spec),
+        None => Value::Null,
+    }
+}
+
+/// The parameters of an operation: its own, and those of its path item which it
+/// doesn't override (with the same name and location)
+fn operation_params<'a>(item: &'a Value, operation: &'a Value, spec: &'a Value) -> Vec<&'a Value> {
+    let params = |node: &'a Value| -> Vec<&'a Value> {
+        node.get("parameters")
+            .and_then(Value::as_array)
+            .map(|params| params.iter().map(|p| resolve(p, spec)).collect())
+            .unwrap_or_default()
+    };
+    let key = |param: &Value| (param.get("in").cloned(), param.get("name").cloned());
+    let mut merged = params(operation);
+    for param in params(item) {
+        if !merged.iter().any(|p| key(p) == key(param)) {
+            merged.push(param);
+        }
+    }
+    merged
+}
+
+/// Build the url for an operation, filling in path and (required) query parameters.
+/// Fails if `base` can't have a path (e.g. `mailto:`).
+fn build_url(base: &Url, path: &str, params: &[&Value], spec: &Value) -> Result<Url, String> {
+    let find_param = |location: &str, name: &str| {
+        params.iter().find(|p| {
+            p.get("in").and_then(Value::as_str) == Some(location)
+                && p.get("name").and_then(Value::as_str) == Some(name)
+        })
+    };
+
+    let mut url = base.clone();
+    {
+        let mut segments = url
+            .path_segments_mut()
+            .map_err(|()| format!("'{}' can't be the base of a url", base))?;
+        segments.pop_if_empty();
+        for segment in path.split('/').skip(1) {
+            if segment.starts_with('{') && segment.ends_with('}') {
+                let name = &segment[1..segment.len() - 1];
+                let value = find_param("path", name)
+                    .map(|p| to_param(&param_example(p, spec)))
+                    .unwrap_or_default();
+                segments.push(&value);
+            } else {
+                segments.push(segment);
+            }
+        }
+    }
+    for param in params {
+        let required = param
+            .get("required")
+            .and_then(Value::as_bool)
+            .unwrap_or(false);
+        if param.get("in").and_then(Value::as_str) == Some("query") && required {
+            let name = param.get("name").and_then(Value::as_str).unwrap_or("");
+            url.query_pairs_mut()
+                .append_pair(name, &to_param(&param_example(param, spec)));
+        }
+    }
+    Ok(url)
+}
+
+/// Exercise a single operation
+async fn verify_operation(
+    client: &awc::Client,
+    base: &Url,
+    path: &str,
+    method: Method,
+    item: &Value,
+    operation: &Value,
+    spec: &Value,
+) -> OperationReport {
+    let params = operation_params(item, operation, spec);
+    let body = operation
+        .get("requestBody")
+        .map(|body| resolve(body, spec))
+        .and_then(|body| body.get("content"))
+        .and_then(|content| content.get("application/json"))
+        .map(|media| example_for_media_type(media, spec));
+
+    let problems = match build_url(base, path, &params, spec) {
+        Ok(url) => send(client, method.clone(), &url, body, operation, spec).await,
+        Err(e) => vec![e],
+    };
+
+    OperationReport {
+        operation: operation
+            .get("operationId")
+            .and_then(Value::as_str)
+            .unwrap_or("<no operationId>")
+            .to_string(),
+        method,
+        path: path.to_string(),
+        problems,
+    }
+}
+
+/// Make a request of an operation, giving everything about the response which
+/// doesn't conform to the spec
+async fn send(
+    client: &awc::Client,
+    method: Method,
+    url: &Url,
+    body: Option<Value>,
+    operation: &Value,
+    spec: &Value,
+) -> Vec<String> {
+    let req = client.request(method, url.as_str());
+    let resp = match body {
+        Some(body) => req.send_json(&body).await,
+        None => req.send().await,
+    };
+    match resp {
+        Ok(mut resp) => {
+            let status = resp.status().as_u16();
+            let content_type = resp
+                .headers()
+                .get(actix_http::http::header::CONTENT_TYPE)
+                .and_then(|ct| ct.to_str().ok())
+                .map(String::from);
+            match resp.body().limit(BODY_LIMIT).await {
+                Ok(bytes) => {
+                    check_response(status, content_type.as_deref(), &bytes, operation, spec)
+                }
+                Err(e) => vec![format!("failed to read body: {}", e)],
+            }
+        }
+        Err(e) => vec![format!("request failed: {}", e)],
+    }
+}
+
+/// Exercise every operation in the spec against the server at `base`
+pub async fn verify(spec: &Value, base: &Url) -> Vec<OperationReport> {
+    let client = awc::Client::new();
+    let mut reports = Vec::new();
+    let paths = match spec.get("paths").and_then(Value::as_object) {
+        Some(paths) => paths,
+        None => return reports,
+    };
+    for (path, item) in paths {
+        let item = resolve(item, spec);
+        for (field, method) in METHODS {
+            if let Some(operation) = item.get(*field) {
+                let report =
+                    verify_operation(&client, base, path, method.clone(), item, operation, spec)
+                        .await;
+                reports.push(report);
+            }
+        }
+    }
+    reports
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_build_url() {
+        let base: Url = "http://localhost:8000/api/".parse().unwrap();
+        let op = json!({
+            "parameters": [
+                { "in": "path", "name": "petId", "required": true, "example": "a b/c" },
+                { "in": "query", "name": "limit", "required": true, "schema": { "type": "integer" } },
+                { "in": "query", "name": "filter", "schema": { "type": "string" } }
+            ]
+        });
+        let (item, spec) = (json!({}), json!({}));
+        let params = operation_params(&item, &op, &spec);
+        let url = build_url(&base, "/pets/{petId}", &params, &spec).unwrap();
+        assert_eq!(
+            url.as_str(),
+            "http://localhost:8000/api/pets/a%20b%2Fc?limit=0"
+        );
+
+        let mailto: Url = "mailto:pets@example.com".parse().unwrap();
+        assert!(build_url(&mailto, "/pets/{petId}", &params, &spec).is_err());
+    }
+
+    #[test]
+    fn test_path_item_params() {
+        let base: Url = "http://localhost:8000/".parse().unwrap();
+        let item = json!({
+            "parameters": [
+                { "in": "path", "name": "petId", "required": true, "example": 1 },
+                { "in": "query", "name": "limit", "required": true, "example": 10 }
+            ]
+        });
+        // the operation's own parameters take precedence
+        let op = json!({
+            "parameters": [
+                { "in": "query", "name": "limit", "required": true, "example": 5 }
+            ]
+        });
+        let spec = json!({});
+        let params = operation_params(&item, &op, &spec);
+        let url = build_url(&base, "/pets/{petId}", &params, &spec).unwrap();
+        assert_eq!(url.as_str(), "http://localhost:8000/pets/1?limit=5");
+    }
+}
@@ -0,0 +1,123 @@
+//! Typed websockets, for routes marked with `x-hsr-websocket`
+
+use std::marker::PhantomData;
+
+use actix_codec::{Decoder, Encoder};
+use actix_http::ws::{self, Codec, Frame, Message, ProtocolError};
+use actix_web::{web, Error as ActixError, HttpRequest, HttpResponse};
+use bytes::BytesMut;
+use futures::channel::mpsc;
+use futures::StreamExt;
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Errors that may occur while talking over a websocket
+#[derive(Debug, thiserror::Error)]
+pub enum WsError {
+    #[error("Websocket protocol error: {}", _0)]
+    Protocol(#[from] ProtocolError),
+    #[error("Bad message: {}", _0)]
+    Json(#[from] serde_json::Error),
+    #[error("Websocket closed")]
+    Closed,
+}
+
+/// A websocket connection which sends and receives JSON-encoded messages of type `T`
+pub struct WebSocket<T> {
+    incoming: mpsc::UnboundedReceiver<Result<Frame, ProtocolError>>,
+    outgoing: mpsc::UnboundedSender<Message>,
+    _message: PhantomData<T>,
+}
+
+impl<T: Serialize + DeserializeOwned> WebSocket<T> {
+    /// Receive the next message. Returns `None` once the connection is closed.
+    ///
+    /// Pings are answered automatically.
+    pub async fn recv(&mut self) -> Option<Result<T, WsError>> {
+        loop {
+            let frame = match self.incoming.next().await? {
+                Ok(frame) => frame,
+                Err(e) => return Some(Err(e.into())),
+            };
+            match frame {
+                Frame::Text(bytes) | Frame::Binary(bytes) => {
+                    return Some(serde_json::from_slice(&bytes).map_err(WsError::from))
+                }
+                Frame::Ping(bytes) => {
+                    let _ = self.outgoing.unbounded_send(Message::Pong(bytes));
+                }
+                Frame::Close(reason) => {
+                    let _ = self.outgoing.unbounded_send(Message::Close(reason));
+                    return None;
+                }
+                Frame::Pong(_) | Frame::Continuation(_) => {}
+            }
+        }
+    }
+
+    /// Send a message
+    pub async fn send(&mut self, msg: &T) -> Result<(), WsError> {
+        let text = serde_json::to_string(msg)?;
+        self.outgoing
+            .unbounded_send(Message::Text(text))
+            .map_err(|_| WsError::Closed)
+    }
+
+    /// Close the connection
+    pub async fn close(self) {
+        let _ = self.outgoing.unbounded_send(Message::Close(None));
+    }
+}
+
+/// Perform the websocket handshake.
+///
+/// Returns the response which should be sent back to the client, and the
+/// socket to pass on to the handler. The connection stays open until the
+/// socket is dropped.
+pub fn upgrade<T>(
+    req: &HttpRequest,
+    mut payload: web::Payload,
+) -> Result<(HttpResponse, WebSocket<T>), ActixError> {
+    let mut resp = ws::handshake(req.head())?;
+    let (in_tx, in_rx) = mpsc::unbounded();
+    let (out_tx, out_rx) = mpsc::unbounded();
+
+    // Decode the incoming bytes into frames in the background
+    actix_rt::spawn(async move {
+        let mut codec = Codec::new();
+        let mut buf = BytesMut::new();
+        while let Some(Ok(chunk)) = payload.next().await {
+            buf.extend_from_slice(&chunk);
+            loop {
+                match codec.decode(&mut buf) {
+                    Ok(Some(frame)) => {
+                        if in_tx.unbounded_send(Ok(frame)).is_err() {
+                            return;
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(e) => {
+                        let _ = in_tx.unbounded_send(Err(e));
+                        return;
+                    }
+                }
+            }
+        }
+    });
+
+    // Encode outgoing messages into the response body
+    let mut codec = Codec::new();
+    let body = out_rx.map(move |msg| {
+        let mut buf = BytesMut::new();
+        codec
+            .encode(msg, &mut buf)
+            .map(|()| buf.freeze())
+            .map_err(ActixError::from)
+    });
+
+    let socket = WebSocket {
+        incoming: in_rx,
+        outgoing: out_tx,
+        _message: PhantomData,
+    };
+    Ok((resp.streaming(body), socket))
+}
@@ -1,13 +1,79 @@
 use hsr_codegen;
 use std::io::Write;
 
-fn main() {
-    let code = hsr_codegen::generate_from_yaml_file("test-spec.yaml").expect("Generation failure");
+/// The specs of the examples, generated here too so that any warnings in the code
+/// made from them fail the build (see `src/lib.rs`)
+const EXAMPLES: &[(&str, &str)] = &[
+    ("bench", "../examples/bench/bench.yaml"),
+    ("petstore", "../examples/petstore/petstore.yaml"),
+    (
+        "petstore_expanded",
+        "../examples/petstore-expanded/petstore-expanded.yaml",
+    ),
+    ("quickstart", "../examples/quickstart/quickstart.yaml"),
+    ("tutorial", "../examples/tutorial/spec.yaml"),
+];
+
+fn generate(spec: &str, name: &str, opts: &hsr_codegen::GenerationOptions) {
+    let code =
+        hsr_codegen::generate_from_yaml_file_with_options(spec, opts).expect("Generation failure");
+    write(name, &code);
+    println!("cargo:rerun-if-changed={}", spec);
+}
 
+fn write(name: &str, code: &str) {
     let out_dir = std::env::var("OUT_DIR").unwrap();
-    let dest_path = std::path::Path::new(&out_dir).join("api.rs");
+    let dest_path = std::path::Path::new(&out_dir).join(format!("{}.rs", name));
     let mut f = std::fs::File::create(&dest_path).unwrap();
 
     write!(f, "{}", code).unwrap();
-    println!("cargo:rerun-if-changed=test-spec.yaml");
+}
+
+fn main() {
+    let opts = hsr_codegen::GenerationOptions {
+        auto_head: true,
+        ..Default::default()
+    };
+    generate("test-spec.yaml", "api", &opts);
+    for (name, spec) in EXAMPLES {
+        generate(spec, name, &Default::default());
+    }
+    // the server of the bench, holding its API as a trait object
+    let opts = hsr_codegen::GenerationOptions {
+        dyn_dispatch: true,
+        ..Default::default()
+    };
+    generate(EXAMPLES[0].1, "bench_dyn", &opts);
+    // an API which authorizes every call
+    let opts = hsr_codegen::GenerationOptions {
+        authorization: true,
+        ..Default::default()
+    };
+    generate("guarded-spec.yaml", "guarded", &opts);
+    // the same API, given the context of each request
+    let opts = hsr_codegen::GenerationOptions {
+        request_context: true,
+        ..Default::default()
+    };
+    generate("guarded-spec.yaml", "contextual", &opts);
+    // the petstore, sending its errors as problem documents
+    let opts = hsr_codegen::GenerationOptions {
+        problem_json: true,
+        ..Default::default()
+    };
+    generate(EXAMPLES[1].1, "problems", &opts);
+    // the guarded spec again, with a placeholder implementation
+    let opts = hsr_codegen::GenerationOptions {
+        stub: true,
+        ..Default::default()
+    };
+    generate("guarded-spec.yaml", "stubbed", &opts);
+    // two versions of an API, served together
+    let versions = [("v1", "versions/v1.yaml"), ("v2", "versions/v2.yaml")];
+    let code = hsr_codegen::generate_versioned_from_yaml_files(&versions, &Default::default())
+        .expect("Generation failure");
+    write("versioned", &code);
+    for (_, spec) in &versions {
+        println!("cargo:rerun-if-changed={}", spec);
+    }
 }
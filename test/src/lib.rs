@@ -1,4 +1,50 @@
-#[allow(non_snake_case)]
+// The generated code must build without warnings (and without `unsafe`) in the
+// user's crate, whatever lints it has turned on
+#![deny(warnings)]
+#![forbid(unsafe_code)]
+
 pub mod api {
     include!(concat!(env!("OUT_DIR"), "/api.rs"));
 }
+
+pub mod guarded {
+    include!(concat!(env!("OUT_DIR"), "/guarded.rs"));
+}
+
+/// The code generated from the specs of the examples
+pub mod contextual {
+    include!(concat!(env!("OUT_DIR"), "/contextual.rs"));
+}
+
+pub mod problems {
+    include!(concat!(env!("OUT_DIR"), "/problems.rs"));
+}
+
+pub mod stubbed {
+    include!(concat!(env!("OUT_DIR"), "/stubbed.rs"));
+}
+
+pub mod versioned {
+    include!(concat!(env!("OUT_DIR"), "/versioned.rs"));
+}
+
+pub mod examples {
+    pub mod bench {
+        include!(concat!(env!("OUT_DIR"), "/bench.rs"));
+    }
+    pub mod bench_dyn {
+        include!(concat!(env!("OUT_DIR"), "/bench_dyn.rs"));
+    }
+    pub mod petstore {
+        include!(concat!(env!("OUT_DIR"), "/petstore.rs"));
+    }
+    pub mod petstore_expanded {
+        include!(concat!(env!("OUT_DIR"), "/petstore_expanded.rs"));
+    }
+    pub mod quickstart {
+        include!(concat!(env!("OUT_DIR"), "/quickstart.rs"));
+    }
+    pub mod tutorial {
+        include!(concat!(env!("OUT_DIR"), "/tutorial.rs"));
+    }
+}
@@ -1,7 +1,13 @@
 use test::api::{self, client, server, TestApi};
 
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+
 struct Api;
 
+static COUNTER: AtomicI64 = AtomicI64::new(0);
+static AUDITED: AtomicI64 = AtomicI64::new(0);
+
 #[hsr::async_trait::async_trait(?Send)]
 impl TestApi for Api {
     async fn get_status(&self) -> api::GetStatus {
@@ -54,6 +60,51 @@ impl TestApi for Api {
     async fn anything_goes(&self, one_of: api::OneOfTest) -> api::AnythingGoes {
         api::AnythingGoes::Ok(one_of)
     }
+
+    async fn rate_limited(&self) -> api::RateLimited {
+        api::RateLimited::Ok
+    }
+
+    async fn increment(&self) -> api::Increment {
+        api::Increment::Ok(COUNTER.fetch_add(1, Ordering::SeqCst) + 1)
+    }
+
+    async fn cached_counter(&self) -> api::CachedCounter {
+        api::CachedCounter::Ok(COUNTER.fetch_add(1, Ordering::SeqCst) + 1)
+    }
+
+    async fn slow(&self, millis: i64) -> api::Slow {
+        hsr::actix_rt::time::delay_for(std::time::Duration::from_millis(millis as u64)).await;
+        api::Slow::Ok
+    }
+
+    async fn buggy(&self) -> api::Buggy {
+        panic!("a bug")
+    }
+
+    async fn paged_numbers(&self, cursor: Option<String>) -> api::PagedNumbers {
+        // pages of two numbers, up to 5
+        let start: i64 = cursor.map(|c| c.parse().unwrap()).unwrap_or(0);
+        let end = (start + 2).min(5);
+        api::PagedNumbers::Ok(api::PagedNumbers200 {
+            numbers: (start..end).collect(),
+            next_cursor: if end < 5 { Some(end.to_string()) } else { None },
+        })
+    }
+
+    async fn echo_socket(&self, mut socket: hsr::ws::WebSocket<api::Hello>) {
+        while let Some(Ok(msg)) = socket.recv().await {
+            if socket.send(&msg).await.is_err() {
+                break;
+            }
+        }
+    }
+
+    async fn audit(&self, event: hsr::audit::AuditEvent) {
+        assert_eq!(event.operation_id, "set_status");
+        assert_eq!(event.status, 200);
+        AUDITED.fetch_add(1, Ordering::SeqCst);
+    }
 }
 
 // Quickly generate some data
@@ -64,6 +115,121 @@ fn hello() -> api::Hello {
     }
 }
 
+/// Replaces the generated handler of `just_default`, answering as the API would
+async fn just_default() -> hsr::actix_web::HttpResponse {
+    hsr::actix_web::HttpResponse::Ok()
+        .header("x-overridden", "true")
+        .json(hello())
+}
+
+/// Authorizes the calls with an `x-allow` header
+struct Guarded;
+
+#[hsr::async_trait::async_trait(?Send)]
+impl test::guarded::GuardedApi for Guarded {
+    async fn authorize(&self, input: hsr::authz::AuthzInput) -> Result<(), hsr::authz::Denied> {
+        assert_eq!(input.params["id"], 7);
+        match input.context.header("x-allow") {
+            Some(_) => Ok(()),
+            None => Err(hsr::authz::Denied::Forbidden("no x-allow header".into())),
+        }
+    }
+
+    async fn get_secret(&self, _id: i64) -> test::guarded::GetSecret {
+        test::guarded::GetSecret::Ok("generated".into())
+    }
+}
+
+/// Replaces the generated handler of `get_secret`
+async fn get_secret() -> hsr::actix_web::HttpResponse {
+    hsr::actix_web::HttpResponse::Ok().json("overridden")
+}
+
+/// Describes the request it is given the context of
+struct Contextual;
+
+#[hsr::async_trait::async_trait(?Send)]
+impl test::contextual::GuardedApi for Contextual {
+    async fn get_secret(&self, ctx: hsr::RequestContext, id: i64) -> test::contextual::GetSecret {
+        test::contextual::GetSecret::Ok(format!(
+            "{} {} {} {}",
+            ctx.method(),
+            ctx.uri().path(),
+            ctx.header("x-tenant").unwrap_or("-"),
+            ctx.request_id().is_some() && id == 7,
+        ))
+    }
+}
+
+/// A petstore which fails
+struct Problems;
+
+#[hsr::async_trait::async_trait(?Send)]
+impl test::problems::PetstoreApi for Problems {
+    async fn get_all_pets(
+        &self,
+        _limit: i64,
+        _filter: Option<String>,
+    ) -> test::problems::GetAllPets {
+        test::problems::GetAllPets::Ok(vec![])
+    }
+
+    async fn create_pet(&self, _new_pet: test::problems::NewPet) -> test::problems::CreatePet {
+        test::problems::CreatePet::Forbidden
+    }
+
+    async fn get_pet(&self, pet_id: i64) -> test::problems::GetPet {
+        match pet_id {
+            0 => test::problems::GetPet::NotFound,
+            _ => test::problems::GetPet::Default {
+                status_code: 503,
+                body: test::problems::Error {
+                    code: 7,
+                    message: "down".into(),
+                },
+            },
+        }
+    }
+
+    async fn delete_pet(&self, _pet_id: i64) -> test::problems::DeletePet {
+        test::problems::DeletePet::NoContent
+    }
+}
+
+/// The versions of the pets API, which have the same pets
+struct PetsV1;
+struct PetsV2;
+
+fn rex() -> test::versioned::shared::Pet {
+    test::versioned::shared::Pet { name: "Rex".into() }
+}
+
+#[hsr::async_trait::async_trait(?Send)]
+impl test::versioned::v1::PetsApi for PetsV1 {
+    async fn list_pets(&self) -> test::versioned::v1::ListPets {
+        test::versioned::v1::ListPets::Ok(vec![rex()])
+    }
+
+    async fn get_owner(&self) -> test::versioned::v1::GetOwner {
+        test::versioned::v1::GetOwner::Ok(test::versioned::v1::Owner {
+            name: "Alex".into(),
+        })
+    }
+}
+
+#[hsr::async_trait::async_trait(?Send)]
+impl test::versioned::v2::PetsApi for PetsV2 {
+    async fn list_pets(&self) -> test::versioned::v2::ListPets {
+        test::versioned::v2::ListPets::Ok(vec![rex()])
+    }
+
+    async fn get_owner(&self) -> test::versioned::v2::GetOwner {
+        test::versioned::v2::GetOwner::Ok(test::versioned::v2::Owner {
+            full_name: "Alex Whitney".into(),
+        })
+    }
+}
+
 #[allow(dead_code)]
 fn nullable_struct() -> api::NullableStruct {
     Some(api::NullableStructOpt {
@@ -74,6 +240,16 @@ fn nullable_struct() -> api::NullableStruct {
     })
 }
 
+/// Keeps the server errors it is told about
+#[derive(Default)]
+struct Reports(Mutex<Vec<hsr::report::ErrorReport>>);
+
+impl hsr::report::ErrorReporter for Reports {
+    fn report(&self, report: &hsr::report::ErrorReport) {
+        self.0.lock().unwrap().push(report.clone());
+    }
+}
+
 fn all_of_test() -> api::AllOfTest {
     let blob = serde_json::json!({
         "myName": "Alex",
@@ -95,34 +271,90 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     let uri: hsr::Url = "http://127.0.0.1:8000".parse().unwrap();
     let uri2 = uri.clone();
+    let reports = Arc::new(Reports::default());
+    let reporter = reports.clone();
 
     std::thread::spawn(move || {
         println!("Serving at '{}'", uri);
         let mut system = hsr::actix_rt::System::new("main");
-        let server = server::serve(Api, hsr::Config::with_host(uri));
+        let mut cfg = hsr::Config::with_host(uri).also_bind("127.0.0.1:8001".parse().unwrap());
+        // Make sure we are returning what the spec says we return
+        cfg.response_validation = hsr::validate::ResponseValidation::Fail;
+        cfg = cfg
+            .error_reporter(reporter)
+            .security_headers(hsr::security_headers::SecurityHeaders::default())
+            .override_handler("just_default", |route| route.to(just_default));
+        let server = server::serve(Api, cfg);
         system.block_on(server).unwrap();
     });
 
     std::thread::sleep(std::time::Duration::from_millis(100));
 
-    let client = client::Client::new(uri2);
+    let client = client::Client::new(uri2.clone());
     println!("Testing endpoints");
 
     let _ = all_of_test();
 
     assert_eq!(client.get_status().await?, api::GetStatus::Ok);
 
+    {
+        // the server also listens on a second address
+        let other = client::Client::new("http://127.0.0.1:8001".parse()?);
+        assert_eq!(other.get_status().await?, api::GetStatus::Ok);
+    }
+
+    {
+        // the client may be swapped for another implementation at runtime
+        let dyn_client: Box<dyn client::DynClient> = Box::new(client.clone());
+        assert_eq!(dyn_client.get_status().await?, api::GetStatus::Ok);
+
+        // ...such as one calling the implementation directly
+        let dyn_client: Box<dyn client::DynClient> = Box::new(client::InProcess::new(Api));
+        assert_eq!(dyn_client.get_status().await?, api::GetStatus::Ok);
+        let echo = dyn_client
+            .two_query_params("Alex".to_string(), Some(33))
+            .await?;
+        assert_eq!(echo, api::TwoQueryParams::Ok(hello()));
+    }
+
     {
         assert_eq!(
             client.set_status(Some("some-status".into())).await?,
             api::SetStatus::Ok(Some("some-status".into()))
         );
         assert_eq!(client.set_status(None).await?, api::SetStatus::Ok(None));
+        // set_status is audited
+        assert_eq!(AUDITED.load(Ordering::SeqCst), 2);
     }
 
     {
         let echo = client.two_path_params("Alex".to_string(), 33).await?;
         assert_eq!(echo, api::TwoPathParams::Ok(hello()));
+
+        // awkward characters survive the trip
+        let name = "A/l?e#x %2F".to_string();
+        let echo = client.two_path_params(name.clone(), 33).await?;
+        match echo {
+            api::TwoPathParams::Ok(hello) => assert_eq!(hello.myName, name),
+        }
+
+        let name = "A\\l".to_string();
+        let echo = client.two_path_params(name.clone(), 33).await?;
+        match echo {
+            api::TwoPathParams::Ok(hello) => assert_eq!(hello.myName, name),
+        }
+        // dot segments would be resolved away, so aren't sent
+        for name in &[".", ".."] {
+            assert!(matches!(
+                client.two_path_params(name.to_string(), 33).await,
+                Err(hsr::ClientError::Path(_))
+            ));
+        }
+
+        let base: hsr::Url = "http://example.com/ignored".parse().unwrap();
+        let url = api::paths::two_path_params::url(&base, "A/l".into(), 33).unwrap();
+        assert_eq!(url.as_str(), "http://example.com/twoPathParams/A%2Fl/33");
+        assert!(api::paths::two_path_params::url(&base, "..".into(), 33).is_err());
     }
 
     {
@@ -131,6 +363,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             .await?;
         assert_eq!(echo, api::TwoQueryParams::Ok(hello()));
 
+        let base: hsr::Url = "http://example.com".parse().unwrap();
+        let link = api::links::two_query_params::path_params(&base, "Alex".into(), 33).unwrap();
+        assert_eq!(link.as_str(), "http://example.com/twoPathParams/Alex/33");
+
         let echo = client.two_query_params("Alex".to_string(), None).await?;
         assert_eq!(
             echo,
@@ -139,6 +375,24 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 my_age: None
             })
         );
+
+        // missing optional fields are left out, rather than sent as null
+        let json = serde_json::to_value(api::Hello {
+            myName: "Alex".into(),
+            my_age: None,
+        })?;
+        assert_eq!(json, serde_json::json!({"myName": "Alex"}));
+
+        // the required parameter is missing
+        let resp = hsr::awc::Client::default()
+            .get(uri2.join("/twoQueryParams?my_age=33")?.as_str())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            resp.status(),
+            hsr::actix_http::http::StatusCode::BAD_REQUEST
+        );
     }
 
     {
@@ -149,7 +403,15 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 status_code: 200,
                 body: hello()
             }
-        )
+        );
+
+        // ...by the handler overriding the generated one
+        let resp = hsr::awc::Client::default()
+            .get(uri2.join("/justDefault")?.as_str())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.headers().get("x-overridden").unwrap(), "true");
     }
 
     {
@@ -158,6 +420,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
         let rtn = client.ok_error_default(400).await?;
         assert_eq!(rtn, api::OkErrorDefault::BadRequest);
+        assert_eq!(rtn.to_string(), "400 Bad Request: Not Ok");
 
         let rtn = client.ok_error_default(201).await?;
         assert_eq!(rtn, api::OkErrorDefault::Default { status_code: 201 });
@@ -185,6 +448,330 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         assert_eq!(body, api::AnythingGoes::Ok(payload));
     }
 
+    {
+        assert_eq!(client.rate_limited().await?, api::RateLimited::Ok);
+        assert_eq!(client.rate_limited().await?, api::RateLimited::Ok);
+        assert_eq!(
+            client.rate_limited().await?,
+            api::RateLimited::TooManyRequests
+        );
+    }
+
+    {
+        // each call gets a fresh key
+        let first = client.increment().await?;
+        assert_ne!(client.increment().await?, first);
+
+        // repeating a key replays the response
+        let keyed = client.with(hsr::CallOptions::new().idempotency_key("abc"));
+        let first = keyed.increment().await?;
+        assert_eq!(keyed.increment().await?, first);
+    }
+
+    {
+        assert_eq!(client.slow(0).await?, api::Slow::Ok);
+        // the client gives up as soon as the server would
+        assert!(matches!(
+            client.slow(500).await,
+            Err(hsr::ClientError::Actix(_))
+        ));
+        // given longer, the client gets the server's response
+        let patient =
+            client.with(hsr::CallOptions::new().timeout(std::time::Duration::from_secs(1)));
+        match patient.slow(500).await {
+            Err(hsr::ClientError::Problem(problem)) => assert_eq!(problem.status, Some(504)),
+            other => panic!("Expected a timeout, got {:?}", other),
+        }
+    }
+
+    {
+        // a panic is a 500, and the server carries on
+        match client.buggy().await {
+            Err(hsr::ClientError::Problem(problem)) => assert_eq!(problem.status, Some(500)),
+            other => panic!("Expected a server error, got {:?}", other),
+        }
+        assert_eq!(client.slow(0).await?, api::Slow::Ok);
+
+        // and the error reporter is told about it
+        let reports = reports.0.lock().unwrap();
+        let report = reports
+            .iter()
+            .find(|report| report.operation_id == "buggy")
+            .unwrap();
+        assert_eq!(report.status, 500);
+        assert!(matches!(
+            report.failure,
+            Some(hsr::report::Failure::Panic(_))
+        ));
+    }
+
+    {
+        // requests the API has no operation for get problem documents
+        let resp = hsr::awc::Client::default()
+            .get(uri2.join("/noSuchPath")?.as_str())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), hsr::actix_http::http::StatusCode::NOT_FOUND);
+        assert_eq!(
+            hsr::negotiate::content_type(resp.headers()),
+            hsr::problem::PROBLEM_JSON
+        );
+
+        let resp = hsr::awc::Client::default()
+            .delete(uri2.join("/status")?.as_str())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            resp.status(),
+            hsr::actix_http::http::StatusCode::METHOD_NOT_ALLOWED
+        );
+        assert_eq!(resp.headers().get("allow").unwrap(), "GET, POST, HEAD");
+
+        // HEAD is answered as GET is, without the body (see `auto_head` in build.rs)
+        let url = uri2.join("/twoPathParams/Alex/33")?;
+        let get = hsr::awc::Client::default()
+            .get(url.as_str())
+            .send()
+            .await
+            .unwrap();
+        let head = hsr::awc::Client::default()
+            .head(url.as_str())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(head.status(), hsr::actix_http::http::StatusCode::OK);
+        assert_eq!(
+            head.headers().get("content-length"),
+            get.headers().get("content-length")
+        );
+    }
+
+    {
+        // every response has the security headers, and the UI a content security policy
+        let resp = hsr::awc::Client::default()
+            .get(uri2.join("/ui.html")?.as_str())
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(
+            resp.headers().get("x-content-type-options").unwrap(),
+            "nosniff"
+        );
+        assert_eq!(resp.headers().get("x-frame-options").unwrap(), "DENY");
+        assert_eq!(
+            resp.headers().get("content-security-policy").unwrap(),
+            hsr::security_headers::UI_CSP
+        );
+    }
+
+    {
+        let first = client.cached_counter().await?;
+        assert_ne!(client.cached_counter().await?, first);
+
+        // the second call is answered from the cache
+        let cached = client.with_cache(hsr::cache::ResponseCache::new());
+        let first = cached.cached_counter().await?;
+        assert_eq!(cached.cached_counter().await?, first);
+
+        // ...but not for a clone sharing the cache with other credentials
+        let other = cached.with(hsr::CallOptions::new().header("authorization", "Bearer other"));
+        let second = other.cached_counter().await?;
+        assert_ne!(second, first);
+        assert_eq!(other.cached_counter().await?, second);
+        assert_eq!(cached.cached_counter().await?, first);
+    }
+
+    {
+        use hsr::futures::StreamExt;
+        let numbers: Vec<i64> = client
+            .paged_numbers_items()
+            .map(|n| n.unwrap())
+            .collect()
+            .await;
+        assert_eq!(numbers, vec![0, 1, 2, 3, 4]);
+        assert_eq!(client.paged_numbers_pages().count().await, 3);
+    }
+
+    {
+        let op = api::operation("set_status").unwrap();
+        assert_eq!((op.method, op.path), ("POST", "/status"));
+        assert!(op.has_tag("status"));
+        assert!(op.security.is_empty());
+        assert!(api::operation("no_such_operation").is_none());
+    }
+
+    {
+        // an overriding handler is only called once the call is authorized
+        std::thread::spawn(move || {
+            let mut system = hsr::actix_rt::System::new("guarded");
+            let cfg = hsr::Config::with_host("http://127.0.0.1:8002".parse().unwrap())
+                .override_handler("get_secret", |route| route.to(get_secret));
+            let server = test::guarded::server::serve(Guarded, cfg);
+            system.block_on(server).unwrap();
+        });
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        let url = "http://127.0.0.1:8002/secrets/7";
+        let resp = hsr::awc::Client::default().get(url).send().await.unwrap();
+        assert_eq!(resp.status(), 403);
+        let mut resp = hsr::awc::Client::default()
+            .get(url)
+            .header("x-allow", "yes")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.json::<String>().await?, "overridden");
+    }
+
+    {
+        // handlers are given the context of the request
+        std::thread::spawn(move || {
+            let mut system = hsr::actix_rt::System::new("contextual");
+            let cfg = hsr::Config::with_host("http://127.0.0.1:8003".parse().unwrap());
+            let server = test::contextual::server::serve(Contextual, cfg);
+            system.block_on(server).unwrap();
+        });
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        let client = test::contextual::client::Client::new("http://127.0.0.1:8003".parse()?)
+            .with(hsr::CallOptions::new().header("x-tenant", "acme"));
+        assert_eq!(
+            client.get_secret(7).await?,
+            test::contextual::GetSecret::Ok("GET /secrets/7 acme true".into())
+        );
+        // further options are added to those the client already has
+        let client =
+            client.with(hsr::CallOptions::new().timeout(std::time::Duration::from_secs(1)));
+        assert_eq!(
+            client.get_secret(7).await?,
+            test::contextual::GetSecret::Ok("GET /secrets/7 acme true".into())
+        );
+
+        // ...as they are when called in process, without the headers of a request
+        use test::contextual::client::DynClient;
+        let in_process = test::contextual::client::InProcess::new(Contextual);
+        assert_eq!(
+            in_process.get_secret(7).await?,
+            test::contextual::GetSecret::Ok("GET /secrets/7 - false".into())
+        );
+    }
+
+    {
+        // error responses are problem documents
+        std::thread::spawn(move || {
+            let mut system = hsr::actix_rt::System::new("problems");
+            let cfg = hsr::Config::with_host("http://127.0.0.1:8004".parse().unwrap());
+            let server = test::problems::server::serve(Problems, cfg);
+            system.block_on(server).unwrap();
+        });
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        let get = |path: &'static str| {
+            hsr::awc::Client::default()
+                .get(format!("http://127.0.0.1:8004{}", path))
+                .send()
+        };
+        let mut resp = get("/pets/0").await.unwrap();
+        assert_eq!(resp.status(), 404);
+        assert_eq!(
+            hsr::negotiate::content_type(resp.headers()),
+            hsr::problem::PROBLEM_JSON
+        );
+        let problem: hsr::Problem = resp.json().await?;
+        assert_eq!(
+            problem,
+            hsr::Problem::new(hsr::actix_http::http::StatusCode::NOT_FOUND)
+        );
+
+        // the members of a body are kept
+        let mut resp = get("/pets/1").await.unwrap();
+        assert_eq!(resp.status(), 503);
+        let problem: hsr::Problem = resp.json().await?;
+        assert_eq!(problem.status, Some(503));
+        assert_eq!(problem.extensions["code"], 7);
+        assert_eq!(problem.extensions["message"], "down");
+
+        // successes are not problems
+        let resp = get("/pets?limit=1").await.unwrap();
+        assert_eq!(resp.status(), 200);
+        assert_eq!(
+            hsr::negotiate::content_type(resp.headers()),
+            "application/json"
+        );
+
+        // and the client still decodes the responses of the spec
+        let client = test::problems::client::Client::new("http://127.0.0.1:8004".parse()?);
+        assert_eq!(client.get_pet(0).await?, test::problems::GetPet::NotFound);
+        assert_eq!(
+            client.get_pet(1).await?,
+            test::problems::GetPet::Default {
+                status_code: 503,
+                body: test::problems::Error {
+                    code: 7,
+                    message: "down".into(),
+                },
+            }
+        );
+    }
+
+    {
+        // the stub serves the spec, and answers every operation with a 500
+        std::thread::spawn(move || {
+            let mut system = hsr::actix_rt::System::new("stubbed");
+            let cfg = hsr::Config::with_host("http://127.0.0.1:8005".parse().unwrap());
+            let server = test::stubbed::server::serve(test::stubbed::server::Stub, cfg);
+            system.block_on(server).unwrap();
+        });
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        let client = test::stubbed::client::Client::new("http://127.0.0.1:8005".parse()?);
+        for _ in 0..2 {
+            match client.get_secret(7).await {
+                Err(hsr::ClientError::Problem(problem)) => assert_eq!(problem.status, Some(500)),
+                other => panic!("Expected a server error, got {:?}", other),
+            }
+        }
+        let resp = hsr::awc::Client::default()
+            .get("http://127.0.0.1:8005/spec.json")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+    }
+
+    {
+        // each version is served under its own prefix
+        std::thread::spawn(move || {
+            let mut system = hsr::actix_rt::System::new("versioned");
+            let cfg = hsr::Config::with_host("http://127.0.0.1:8006".parse().unwrap());
+            let server = test::versioned::serve(PetsV1, PetsV2, cfg);
+            system.block_on(server).unwrap();
+        });
+        std::thread::sleep(std::time::Duration::from_millis(100));
+
+        let get = |path: &'static str| {
+            hsr::awc::Client::default()
+                .get(format!("http://127.0.0.1:8006{}", path))
+                .send()
+        };
+        for path in &["/v1/pets", "/v2/pets"] {
+            let mut resp = get(*path).await.unwrap();
+            assert_eq!(resp.status(), 200, "{}", path);
+            let pets: Vec<test::versioned::shared::Pet> = resp.json().await?;
+            assert_eq!(pets, vec![rex()]);
+        }
+        let mut resp = get("/v1/owner").await.unwrap();
+        let owner: test::versioned::v1::Owner = resp.json().await?;
+        assert_eq!(owner.name, "Alex");
+        let mut resp = get("/v2/owner").await.unwrap();
+        let owner: test::versioned::v2::Owner = resp.json().await?;
+        assert_eq!(owner.full_name, "Alex Whitney");
+        assert_eq!(get("/pets").await.unwrap().status(), 404);
+    }
+
     println!("Success");
 
     Ok(())